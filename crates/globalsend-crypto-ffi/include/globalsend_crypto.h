@@ -0,0 +1,268 @@
+#ifndef GLOBALSEND_CRYPTO_H
+#define GLOBALSEND_CRYPTO_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// X25519 public key and handshake hash length
+#define GS_KEY_LEN 32
+
+// Nonce prefix written before the first STREAM chunk
+#define GS_STREAM_PREFIX_LEN 19
+
+// Plaintext bytes per non-final STREAM chunk
+#define GS_STREAM_CHUNK_LEN 65536
+
+typedef enum GsStatus {
+  GS_STATUS_OK = 0,
+  // A required pointer argument was null
+  GS_STATUS_NULL_POINTER = 1,
+  // Malformed input, e.g. a key of the wrong length
+  GS_STATUS_INVALID_ARGUMENT = 2,
+  // Handshake message rejected or sent out of turn
+  GS_STATUS_HANDSHAKE = 3,
+  // Encryption failed or the nonce space is used up
+  GS_STATUS_ENCRYPT = 4,
+  // Authentication failed: tampered data, wrong key or reordered chunk
+  GS_STATUS_DECRYPT = 5,
+  // Object already consumed (finished handshake or stream)
+  GS_STATUS_INVALID_STATE = 6,
+  // Rust code panicked; the object involved should be freed
+  GS_STATUS_PANIC = 7,
+} GsStatus;
+
+// Long-term X25519 device key
+typedef struct GsDeviceKey GsDeviceKey;
+
+// Noise XX handshake in progress
+//
+// Borrows the device key it was created with, which must stay alive until
+// the handshake is freed.
+typedef struct GsHandshake GsHandshake;
+
+// Decrypting half of a STREAM
+typedef struct GsOpenStream GsOpenStream;
+
+// Encrypting half of a STREAM
+typedef struct GsSealStream GsSealStream;
+
+// Directional transport keys from a finished handshake
+typedef struct GsSession GsSession;
+
+// Owned byte buffer allocated by this library
+typedef struct GsBuffer {
+  uint8_t *data;
+  size_t len;
+} GsBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Static description of `status`; never free the returned string
+const char *gs_status_message(enum GsStatus status);
+
+// Zero and release a buffer returned by this library; null data is a no-op
+//
+// # Safety
+//
+// `buf` must come from this library and must not be freed twice.
+void gs_buffer_free(struct GsBuffer buf);
+
+// Generate a new device key; free it with [`gs_device_key_free`]
+//
+// # Safety
+//
+// `out` must be a valid pointer.
+enum GsStatus gs_device_key_generate(struct GsDeviceKey **out);
+
+// Load a key from its versioned encoding (see [`gs_device_key_to_bytes`])
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+enum GsStatus gs_device_key_from_bytes(const uint8_t *data, size_t len, struct GsDeviceKey **out);
+
+// Versioned secret key encoding, for storage in the platform keystore
+//
+// # Safety
+//
+// `key` must be a live device key and `out` a valid pointer.
+enum GsStatus gs_device_key_to_bytes(const struct GsDeviceKey *key, struct GsBuffer *out);
+
+// Write the 32-byte public key to `out`
+//
+// # Safety
+//
+// `key` must be a live device key and `out` must have room for [`GS_KEY_LEN`] bytes.
+enum GsStatus gs_device_key_public(const struct GsDeviceKey *key, uint8_t *out);
+
+// # Safety
+//
+// `key` must come from this library and must not be used afterwards; null is a no-op.
+void gs_device_key_free(struct GsDeviceKey *key);
+
+// Start a handshake as initiator (`initiator = true`) or responder
+//
+// # Safety
+//
+// `key` must outlive the handshake, `prologue` must point to `prologue_len`
+// readable bytes and `out` must be a valid pointer.
+enum GsStatus gs_handshake_new(const struct GsDeviceKey *key,
+                               bool initiator,
+                               const uint8_t *prologue,
+                               size_t prologue_len,
+                               struct GsHandshake **out);
+
+// Produce the next handshake message carrying `payload`
+//
+// # Safety
+//
+// `hs` must be a live handshake, `payload` must point to `payload_len`
+// readable bytes and `out` must be a valid pointer.
+enum GsStatus gs_handshake_write_message(struct GsHandshake *hs,
+                                         const uint8_t *payload,
+                                         size_t payload_len,
+                                         struct GsBuffer *out);
+
+// Consume the peer's next handshake message, returning its payload in `out`
+//
+// # Safety
+//
+// `hs` must be a live handshake, `message` must point to `message_len`
+// readable bytes and `out` must be a valid pointer.
+enum GsStatus gs_handshake_read_message(struct GsHandshake *hs,
+                                        const uint8_t *message,
+                                        size_t message_len,
+                                        struct GsBuffer *out);
+
+// Whether all three messages have been exchanged; false for null or consumed handshakes
+//
+// # Safety
+//
+// `hs` must be null or a live handshake.
+bool gs_handshake_is_finished(const struct GsHandshake *hs);
+
+// Derive the session keys; the handshake is consumed but must still be freed
+//
+// # Safety
+//
+// `hs` must be a live handshake and `out` a valid pointer.
+enum GsStatus gs_handshake_into_session(struct GsHandshake *hs, struct GsSession **out);
+
+// # Safety
+//
+// `hs` must come from this library and must not be used afterwards; null is a no-op.
+void gs_handshake_free(struct GsHandshake *hs);
+
+// Encrypt one message; send `*counter_out` alongside the ciphertext
+//
+// # Safety
+//
+// `session` must be live, `aad`/`plaintext` must point to the given number of
+// readable bytes and `counter_out`/`out` must be valid pointers.
+enum GsStatus gs_session_encrypt(struct GsSession *session,
+                                 const uint8_t *aad,
+                                 size_t aad_len,
+                                 const uint8_t *plaintext,
+                                 size_t plaintext_len,
+                                 uint64_t *counter_out,
+                                 struct GsBuffer *out);
+
+// Decrypt message number `counter` from the peer
+//
+// # Safety
+//
+// `session` must be live, `aad`/`ciphertext` must point to the given number
+// of readable bytes and `out` must be a valid pointer.
+enum GsStatus gs_session_decrypt(const struct GsSession *session,
+                                 uint64_t counter,
+                                 const uint8_t *aad,
+                                 size_t aad_len,
+                                 const uint8_t *ciphertext,
+                                 size_t ciphertext_len,
+                                 struct GsBuffer *out);
+
+// Write the 32-byte transcript hash (for SAS display) to `out`
+//
+// # Safety
+//
+// `session` must be live and `out` must have room for [`GS_KEY_LEN`] bytes.
+enum GsStatus gs_session_handshake_hash(const struct GsSession *session, uint8_t *out);
+
+// Write the peer's authenticated 32-byte static key to `out`
+//
+// # Safety
+//
+// `session` must be live and `out` must have room for [`GS_KEY_LEN`] bytes.
+enum GsStatus gs_session_remote_static(const struct GsSession *session, uint8_t *out);
+
+// # Safety
+//
+// `session` must come from this library and must not be used afterwards; null is a no-op.
+void gs_session_free(struct GsSession *session);
+
+// Start a stream with a fresh random prefix, which is written to `prefix_out`
+//
+// # Safety
+//
+// `key` must point to [`GS_KEY_LEN`] bytes, `prefix_out` must have room for
+// [`GS_STREAM_PREFIX_LEN`] bytes and `out` must be a valid pointer.
+enum GsStatus gs_seal_stream_new(const uint8_t *key,
+                                 uint8_t *prefix_out,
+                                 struct GsSealStream **out);
+
+// Seal the next chunk; `last` seals the final chunk and consumes the stream
+//
+// # Safety
+//
+// `stream` must be live, `chunk` must point to `chunk_len` readable bytes and
+// `out` must be a valid pointer.
+enum GsStatus gs_seal_stream_push(struct GsSealStream *stream,
+                                  const uint8_t *chunk,
+                                  size_t chunk_len,
+                                  bool last,
+                                  struct GsBuffer *out);
+
+// # Safety
+//
+// `stream` must come from this library and must not be used afterwards; null is a no-op.
+void gs_seal_stream_free(struct GsSealStream *stream);
+
+// Start decrypting a stream whose prefix has been read from the wire
+//
+// # Safety
+//
+// `key` must point to [`GS_KEY_LEN`] bytes, `prefix` to
+// [`GS_STREAM_PREFIX_LEN`] bytes and `out` must be a valid pointer.
+enum GsStatus gs_open_stream_new(const uint8_t *key,
+                                 const uint8_t *prefix,
+                                 struct GsOpenStream **out);
+
+// Open the next sealed chunk; `last` must be set for the final chunk and consumes the stream
+//
+// A stream that ends without a successful `last` chunk was truncated.
+//
+// # Safety
+//
+// `stream` must be live, `chunk` must point to `chunk_len` readable bytes and
+// `out` must be a valid pointer.
+enum GsStatus gs_open_stream_push(struct GsOpenStream *stream,
+                                  const uint8_t *chunk,
+                                  size_t chunk_len,
+                                  bool last,
+                                  struct GsBuffer *out);
+
+// # Safety
+//
+// `stream` must come from this library and must not be used afterwards; null is a no-op.
+void gs_open_stream_free(struct GsOpenStream *stream);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* GLOBALSEND_CRYPTO_H */
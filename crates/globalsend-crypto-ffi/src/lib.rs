@@ -0,0 +1,619 @@
+//! C ABI over `globalsend-crypto` for the Kotlin and Swift clients
+//!
+//! Every object crosses the boundary as an opaque pointer created by a
+//! `gs_*_new`/`gs_*_generate` function and released by the matching
+//! `gs_*_free`. Byte output is returned in a [`GsBuffer`] that the caller
+//! frees with [`gs_buffer_free`]. Functions report failure through
+//! [`GsStatus`]; out-parameters are only written on `GS_STATUS_OK`.
+//!
+//! The header in `include/globalsend_crypto.h` is generated by cbindgen from
+//! this file (see `cbindgen.toml`). The `uniffi` feature adds the [`mobile`]
+//! module, a higher-level API for generated Kotlin and Swift bindings.
+
+use std::ffi::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use globalsend_crypto::handshake::{Handshake, HandshakeError, TransportKeys};
+use globalsend_crypto::secret::SecretKey;
+use globalsend_crypto::stream::{random_prefix, OpenStream, SealStream, StreamError, STREAM_CHUNK_LEN, STREAM_PREFIX_LEN};
+use globalsend_crypto::{aead_decrypt, aead_encrypt, DeviceKey, AEAD_KEY_LEN};
+use zeroize::Zeroize;
+
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
+/// X25519 public key and handshake hash length
+pub const GS_KEY_LEN: usize = 32;
+/// Nonce prefix written before the first STREAM chunk
+pub const GS_STREAM_PREFIX_LEN: usize = 19;
+/// Plaintext bytes per non-final STREAM chunk
+pub const GS_STREAM_CHUNK_LEN: usize = 65536;
+
+const _: () = assert!(GS_KEY_LEN == AEAD_KEY_LEN);
+const _: () = assert!(GS_STREAM_PREFIX_LEN == STREAM_PREFIX_LEN);
+const _: () = assert!(GS_STREAM_CHUNK_LEN == STREAM_CHUNK_LEN);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GsStatus {
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = 1,
+    /// Malformed input, e.g. a key of the wrong length
+    InvalidArgument = 2,
+    /// Handshake message rejected or sent out of turn
+    Handshake = 3,
+    /// Encryption failed or the nonce space is used up
+    Encrypt = 4,
+    /// Authentication failed: tampered data, wrong key or reordered chunk
+    Decrypt = 5,
+    /// Object already consumed (finished handshake or stream)
+    InvalidState = 6,
+    /// Rust code panicked; the object involved should be freed
+    Panic = 7,
+}
+
+/// Static description of `status`; never free the returned string
+#[no_mangle]
+pub extern "C" fn gs_status_message(status: GsStatus) -> *const c_char {
+    let msg: &'static [u8] = match status {
+        GsStatus::Ok => b"ok\0",
+        GsStatus::NullPointer => b"null pointer argument\0",
+        GsStatus::InvalidArgument => b"invalid argument\0",
+        GsStatus::Handshake => b"handshake failed\0",
+        GsStatus::Encrypt => b"encryption failed\0",
+        GsStatus::Decrypt => b"decryption failed\0",
+        GsStatus::InvalidState => b"object already consumed\0",
+        GsStatus::Panic => b"internal error\0",
+    };
+    msg.as_ptr().cast()
+}
+
+impl From<HandshakeError> for GsStatus {
+    fn from(e: HandshakeError) -> Self {
+        match e {
+            HandshakeError::Finished | HandshakeError::NotFinished => GsStatus::InvalidState,
+            _ => GsStatus::Handshake,
+        }
+    }
+}
+
+impl From<StreamError> for GsStatus {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::Decrypt | StreamError::Truncated => GsStatus::Decrypt,
+            _ => GsStatus::Encrypt,
+        }
+    }
+}
+
+/// Owned byte buffer allocated by this library
+#[repr(C)]
+pub struct GsBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl GsBuffer {
+    fn from_vec(v: Vec<u8>) -> Self {
+        let len = v.len();
+        let data = Box::into_raw(v.into_boxed_slice()).cast::<u8>();
+        Self { data, len }
+    }
+}
+
+/// Zero and release a buffer returned by this library; null data is a no-op
+///
+/// # Safety
+///
+/// `buf` must come from this library and must not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn gs_buffer_free(buf: GsBuffer) {
+    if buf.data.is_null() {
+        return;
+    }
+    let mut bytes = Box::from_raw(ptr::slice_from_raw_parts_mut(buf.data, buf.len));
+    bytes.zeroize();
+}
+
+/// Run `f`, turning errors and panics into a status
+fn guard(f: impl FnOnce() -> Result<(), GsStatus>) -> GsStatus {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => GsStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => GsStatus::Panic,
+    }
+}
+
+/// Borrow `len` bytes at `data`; null is accepted for an empty slice
+unsafe fn input<'a>(data: *const u8, len: usize) -> Result<&'a [u8], GsStatus> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(GsStatus::NullPointer),
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn output<'a, T>(out: *mut T) -> Result<&'a mut T, GsStatus> {
+    out.as_mut().ok_or(GsStatus::NullPointer)
+}
+
+unsafe fn handle<'a, T>(h: *mut T) -> Result<&'a mut T, GsStatus> {
+    h.as_mut().ok_or(GsStatus::NullPointer)
+}
+
+unsafe fn fixed<const N: usize>(data: *const u8) -> Result<[u8; N], GsStatus> {
+    Ok(input(data, N)?.try_into().expect("N bytes"))
+}
+
+// ---- device keys ----
+
+/// Long-term X25519 device key
+pub struct GsDeviceKey(DeviceKey);
+
+/// Generate a new device key; free it with [`gs_device_key_free`]
+///
+/// # Safety
+///
+/// `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_device_key_generate(out: *mut *mut GsDeviceKey) -> GsStatus {
+    guard(|| {
+        *output(out)? = Box::into_raw(Box::new(GsDeviceKey(DeviceKey::generate())));
+        Ok(())
+    })
+}
+
+/// Load a key from its versioned encoding (see [`gs_device_key_to_bytes`])
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_device_key_from_bytes(data: *const u8, len: usize, out: *mut *mut GsDeviceKey) -> GsStatus {
+    guard(|| {
+        let key = DeviceKey::from_versioned_bytes(input(data, len)?).map_err(|_| GsStatus::InvalidArgument)?;
+        *output(out)? = Box::into_raw(Box::new(GsDeviceKey(key)));
+        Ok(())
+    })
+}
+
+/// Versioned secret key encoding, for storage in the platform keystore
+///
+/// # Safety
+///
+/// `key` must be a live device key and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_device_key_to_bytes(key: *const GsDeviceKey, out: *mut GsBuffer) -> GsStatus {
+    guard(|| {
+        let key = key.as_ref().ok_or(GsStatus::NullPointer)?;
+        *output(out)? = GsBuffer::from_vec(key.0.to_versioned_bytes().to_vec());
+        Ok(())
+    })
+}
+
+/// Write the 32-byte public key to `out`
+///
+/// # Safety
+///
+/// `key` must be a live device key and `out` must have room for [`GS_KEY_LEN`] bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gs_device_key_public(key: *const GsDeviceKey, out: *mut u8) -> GsStatus {
+    guard(|| {
+        let key = key.as_ref().ok_or(GsStatus::NullPointer)?;
+        if out.is_null() {
+            return Err(GsStatus::NullPointer);
+        }
+        ptr::copy_nonoverlapping(key.0.public().as_bytes().as_ptr(), out, GS_KEY_LEN);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `key` must come from this library and must not be used afterwards; null is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn gs_device_key_free(key: *mut GsDeviceKey) {
+    if !key.is_null() {
+        drop(Box::from_raw(key));
+    }
+}
+
+// ---- handshake ----
+
+/// Noise XX handshake in progress
+///
+/// Borrows the device key it was created with, which must stay alive until
+/// the handshake is freed.
+pub struct GsHandshake(Option<Handshake<'static>>);
+
+/// Start a handshake as initiator (`initiator = true`) or responder
+///
+/// # Safety
+///
+/// `key` must outlive the handshake, `prologue` must point to `prologue_len`
+/// readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_new(
+    key: *const GsDeviceKey,
+    initiator: bool,
+    prologue: *const u8,
+    prologue_len: usize,
+    out: *mut *mut GsHandshake,
+) -> GsStatus {
+    guard(|| {
+        let key: &'static GsDeviceKey = key.as_ref().ok_or(GsStatus::NullPointer)?;
+        let prologue = input(prologue, prologue_len)?;
+        let hs = if initiator { Handshake::initiator(&key.0, prologue) } else { Handshake::responder(&key.0, prologue) };
+        *output(out)? = Box::into_raw(Box::new(GsHandshake(Some(hs))));
+        Ok(())
+    })
+}
+
+/// Produce the next handshake message carrying `payload`
+///
+/// # Safety
+///
+/// `hs` must be a live handshake, `payload` must point to `payload_len`
+/// readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_write_message(hs: *mut GsHandshake, payload: *const u8, payload_len: usize, out: *mut GsBuffer) -> GsStatus {
+    guard(|| {
+        let hs = handle(hs)?.0.as_mut().ok_or(GsStatus::InvalidState)?;
+        let msg = hs.write_message(input(payload, payload_len)?)?;
+        *output(out)? = GsBuffer::from_vec(msg);
+        Ok(())
+    })
+}
+
+/// Consume the peer's next handshake message, returning its payload in `out`
+///
+/// # Safety
+///
+/// `hs` must be a live handshake, `message` must point to `message_len`
+/// readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_read_message(hs: *mut GsHandshake, message: *const u8, message_len: usize, out: *mut GsBuffer) -> GsStatus {
+    guard(|| {
+        let hs = handle(hs)?.0.as_mut().ok_or(GsStatus::InvalidState)?;
+        let payload = hs.read_message(input(message, message_len)?)?;
+        *output(out)? = GsBuffer::from_vec(payload);
+        Ok(())
+    })
+}
+
+/// Whether all three messages have been exchanged; false for null or consumed handshakes
+///
+/// # Safety
+///
+/// `hs` must be null or a live handshake.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_is_finished(hs: *const GsHandshake) -> bool {
+    hs.as_ref().and_then(|h| h.0.as_ref()).is_some_and(Handshake::is_finished)
+}
+
+/// Derive the session keys; the handshake is consumed but must still be freed
+///
+/// # Safety
+///
+/// `hs` must be a live handshake and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_into_session(hs: *mut GsHandshake, out: *mut *mut GsSession) -> GsStatus {
+    guard(|| {
+        let slot = &mut handle(hs)?.0;
+        if !slot.as_ref().ok_or(GsStatus::InvalidState)?.is_finished() {
+            return Err(GsStatus::InvalidState);
+        }
+        let out = output(out)?;
+        let keys = slot.take().expect("checked above").into_transport()?;
+        *out = Box::into_raw(Box::new(GsSession(keys)));
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `hs` must come from this library and must not be used afterwards; null is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn gs_handshake_free(hs: *mut GsHandshake) {
+    if !hs.is_null() {
+        drop(Box::from_raw(hs));
+    }
+}
+
+// ---- session ----
+
+/// Directional transport keys from a finished handshake
+pub struct GsSession(TransportKeys);
+
+/// Encrypt one message; send `*counter_out` alongside the ciphertext
+///
+/// # Safety
+///
+/// `session` must be live, `aad`/`plaintext` must point to the given number of
+/// readable bytes and `counter_out`/`out` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn gs_session_encrypt(
+    session: *mut GsSession,
+    aad: *const u8,
+    aad_len: usize,
+    plaintext: *const u8,
+    plaintext_len: usize,
+    counter_out: *mut u64,
+    out: *mut GsBuffer,
+) -> GsStatus {
+    guard(|| {
+        let keys = &mut handle(session)?.0;
+        let (aad, plaintext) = (input(aad, aad_len)?, input(plaintext, plaintext_len)?);
+        let (counter_out, out) = (output(counter_out)?, output(out)?);
+        let nonce = keys.send_nonce.next().map_err(|_| GsStatus::Encrypt)?;
+        let counter = nonce.counter();
+        let ct = aead_encrypt(keys.send_key.as_key(), nonce, aad, plaintext).map_err(|_| GsStatus::Encrypt)?;
+        *counter_out = counter;
+        *out = GsBuffer::from_vec(ct);
+        Ok(())
+    })
+}
+
+/// Decrypt message number `counter` from the peer
+///
+/// # Safety
+///
+/// `session` must be live, `aad`/`ciphertext` must point to the given number
+/// of readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_session_decrypt(
+    session: *const GsSession,
+    counter: u64,
+    aad: *const u8,
+    aad_len: usize,
+    ciphertext: *const u8,
+    ciphertext_len: usize,
+    out: *mut GsBuffer,
+) -> GsStatus {
+    guard(|| {
+        let keys = &session.as_ref().ok_or(GsStatus::NullPointer)?.0;
+        let (aad, ciphertext) = (input(aad, aad_len)?, input(ciphertext, ciphertext_len)?);
+        let pt = aead_decrypt(keys.recv_key.as_key(), &keys.recv_nonce, counter, aad, ciphertext).map_err(|_| GsStatus::Decrypt)?;
+        *output(out)? = GsBuffer::from_vec(pt);
+        Ok(())
+    })
+}
+
+/// Write the 32-byte transcript hash (for SAS display) to `out`
+///
+/// # Safety
+///
+/// `session` must be live and `out` must have room for [`GS_KEY_LEN`] bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gs_session_handshake_hash(session: *const GsSession, out: *mut u8) -> GsStatus {
+    guard(|| {
+        let keys = &session.as_ref().ok_or(GsStatus::NullPointer)?.0;
+        if out.is_null() {
+            return Err(GsStatus::NullPointer);
+        }
+        ptr::copy_nonoverlapping(keys.handshake_hash.as_ptr(), out, GS_KEY_LEN);
+        Ok(())
+    })
+}
+
+/// Write the peer's authenticated 32-byte static key to `out`
+///
+/// # Safety
+///
+/// `session` must be live and `out` must have room for [`GS_KEY_LEN`] bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gs_session_remote_static(session: *const GsSession, out: *mut u8) -> GsStatus {
+    guard(|| {
+        let keys = &session.as_ref().ok_or(GsStatus::NullPointer)?.0;
+        if out.is_null() {
+            return Err(GsStatus::NullPointer);
+        }
+        ptr::copy_nonoverlapping(keys.remote_static.as_bytes().as_ptr(), out, GS_KEY_LEN);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `session` must come from this library and must not be used afterwards; null is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn gs_session_free(session: *mut GsSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+// ---- streaming ----
+//
+// Same wire format as `encrypt_stream`: the prefix, then chunks of
+// GS_STREAM_CHUNK_LEN plaintext bytes sealed in order, the last one shorter
+// or equal. Each sealed chunk is 16 bytes longer than its plaintext.
+
+/// Encrypting half of a STREAM
+pub struct GsSealStream(Option<SealStream>);
+
+/// Decrypting half of a STREAM
+pub struct GsOpenStream(Option<OpenStream>);
+
+/// Start a stream with a fresh random prefix, which is written to `prefix_out`
+///
+/// # Safety
+///
+/// `key` must point to [`GS_KEY_LEN`] bytes, `prefix_out` must have room for
+/// [`GS_STREAM_PREFIX_LEN`] bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_seal_stream_new(key: *const u8, prefix_out: *mut u8, out: *mut *mut GsSealStream) -> GsStatus {
+    guard(|| {
+        let key = SecretKey::new(fixed(key)?);
+        if prefix_out.is_null() {
+            return Err(GsStatus::NullPointer);
+        }
+        let out = output(out)?;
+        let prefix = random_prefix();
+        ptr::copy_nonoverlapping(prefix.as_ptr(), prefix_out, STREAM_PREFIX_LEN);
+        *out = Box::into_raw(Box::new(GsSealStream(Some(SealStream::new(key.as_key(), &prefix)))));
+        Ok(())
+    })
+}
+
+/// Seal the next chunk; `last` seals the final chunk and consumes the stream
+///
+/// # Safety
+///
+/// `stream` must be live, `chunk` must point to `chunk_len` readable bytes and
+/// `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_seal_stream_push(stream: *mut GsSealStream, chunk: *const u8, chunk_len: usize, last: bool, out: *mut GsBuffer) -> GsStatus {
+    guard(|| {
+        let slot = &mut handle(stream)?.0;
+        let (chunk, out) = (input(chunk, chunk_len)?, output(out)?);
+        let ct = if last {
+            slot.take().ok_or(GsStatus::InvalidState)?.seal_last(&[], chunk)?
+        } else {
+            slot.as_mut().ok_or(GsStatus::InvalidState)?.seal_chunk(&[], chunk)?
+        };
+        *out = GsBuffer::from_vec(ct);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `stream` must come from this library and must not be used afterwards; null is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn gs_seal_stream_free(stream: *mut GsSealStream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+/// Start decrypting a stream whose prefix has been read from the wire
+///
+/// # Safety
+///
+/// `key` must point to [`GS_KEY_LEN`] bytes, `prefix` to
+/// [`GS_STREAM_PREFIX_LEN`] bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_open_stream_new(key: *const u8, prefix: *const u8, out: *mut *mut GsOpenStream) -> GsStatus {
+    guard(|| {
+        let key = SecretKey::new(fixed(key)?);
+        let prefix = fixed::<STREAM_PREFIX_LEN>(prefix)?;
+        *output(out)? = Box::into_raw(Box::new(GsOpenStream(Some(OpenStream::new(key.as_key(), &prefix)))));
+        Ok(())
+    })
+}
+
+/// Open the next sealed chunk; `last` must be set for the final chunk and consumes the stream
+///
+/// A stream that ends without a successful `last` chunk was truncated.
+///
+/// # Safety
+///
+/// `stream` must be live, `chunk` must point to `chunk_len` readable bytes and
+/// `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gs_open_stream_push(stream: *mut GsOpenStream, chunk: *const u8, chunk_len: usize, last: bool, out: *mut GsBuffer) -> GsStatus {
+    guard(|| {
+        let slot = &mut handle(stream)?.0;
+        let (chunk, out) = (input(chunk, chunk_len)?, output(out)?);
+        let pt = if last {
+            slot.take().ok_or(GsStatus::InvalidState)?.open_last(&[], chunk)?
+        } else {
+            slot.as_mut().ok_or(GsStatus::InvalidState)?.open_chunk(&[], chunk)?
+        };
+        *out = GsBuffer::from_vec(pt);
+        Ok(())
+    })
+}
+
+/// # Safety
+///
+/// `stream` must come from this library and must not be used afterwards; null is a no-op.
+#[no_mangle]
+pub unsafe extern "C" fn gs_open_stream_free(stream: *mut GsOpenStream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> GsBuffer {
+        GsBuffer { data: ptr::null_mut(), len: 0 }
+    }
+
+    unsafe fn bytes(buf: &GsBuffer) -> &[u8] {
+        slice::from_raw_parts(buf.data, buf.len)
+    }
+
+    #[test]
+    fn handshake_session_and_stream_through_c_api() {
+        unsafe {
+            let (mut ka, mut kb) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(gs_device_key_generate(&mut ka), GsStatus::Ok);
+            assert_eq!(gs_device_key_generate(&mut kb), GsStatus::Ok);
+            let (mut a, mut b) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(gs_handshake_new(ka, true, ptr::null(), 0, &mut a), GsStatus::Ok);
+            assert_eq!(gs_handshake_new(kb, false, ptr::null(), 0, &mut b), GsStatus::Ok);
+
+            // -> e, <- e ee s es, -> s se
+            for (from, to) in [(a, b), (b, a), (a, b)] {
+                let (mut msg, mut payload) = (empty(), empty());
+                assert_eq!(gs_handshake_write_message(from, ptr::null(), 0, &mut msg), GsStatus::Ok);
+                assert_eq!(gs_handshake_read_message(to, msg.data, msg.len, &mut payload), GsStatus::Ok);
+                gs_buffer_free(msg);
+                gs_buffer_free(payload);
+            }
+            assert!(gs_handshake_is_finished(a) && gs_handshake_is_finished(b));
+            let (mut sa, mut sb) = (ptr::null_mut(), ptr::null_mut());
+            assert_eq!(gs_handshake_into_session(a, &mut sa), GsStatus::Ok);
+            assert_eq!(gs_handshake_into_session(b, &mut sb), GsStatus::Ok);
+            assert_eq!(gs_handshake_into_session(a, &mut sa), GsStatus::InvalidState);
+
+            let (mut ha, mut hb) = ([0u8; GS_KEY_LEN], [1u8; GS_KEY_LEN]);
+            assert_eq!(gs_session_handshake_hash(sa, ha.as_mut_ptr()), GsStatus::Ok);
+            assert_eq!(gs_session_handshake_hash(sb, hb.as_mut_ptr()), GsStatus::Ok);
+            assert_eq!(ha, hb);
+
+            let (mut counter, mut ct, mut pt) = (0u64, empty(), empty());
+            assert_eq!(gs_session_encrypt(sa, ptr::null(), 0, b"hi".as_ptr(), 2, &mut counter, &mut ct), GsStatus::Ok);
+            assert_eq!(gs_session_decrypt(sb, counter, ptr::null(), 0, ct.data, ct.len, &mut pt), GsStatus::Ok);
+            assert_eq!(bytes(&pt), b"hi");
+            assert_eq!(gs_session_decrypt(sb, counter + 1, ptr::null(), 0, ct.data, ct.len, &mut pt), GsStatus::Decrypt);
+            gs_buffer_free(ct);
+            gs_buffer_free(pt);
+
+            let key = [9u8; GS_KEY_LEN];
+            let (mut prefix, mut seal, mut open) = ([0u8; GS_STREAM_PREFIX_LEN], ptr::null_mut(), ptr::null_mut());
+            assert_eq!(gs_seal_stream_new(key.as_ptr(), prefix.as_mut_ptr(), &mut seal), GsStatus::Ok);
+            assert_eq!(gs_open_stream_new(key.as_ptr(), prefix.as_ptr(), &mut open), GsStatus::Ok);
+            let (mut c1, mut c2, mut p1, mut p2) = (empty(), empty(), empty(), empty());
+            assert_eq!(gs_seal_stream_push(seal, b"one".as_ptr(), 3, false, &mut c1), GsStatus::Ok);
+            assert_eq!(gs_seal_stream_push(seal, b"two".as_ptr(), 3, true, &mut c2), GsStatus::Ok);
+            assert_eq!(gs_seal_stream_push(seal, b"x".as_ptr(), 1, false, &mut c1), GsStatus::InvalidState);
+            // the final chunk cannot stand in for a non-final one
+            assert_eq!(gs_open_stream_push(open, c2.data, c2.len, false, &mut p1), GsStatus::Decrypt);
+            assert_eq!(gs_open_stream_push(open, c1.data, c1.len, false, &mut p1), GsStatus::Ok);
+            assert_eq!(gs_open_stream_push(open, c2.data, c2.len, true, &mut p2), GsStatus::Ok);
+            assert_eq!((bytes(&p1), bytes(&p2)), (&b"one"[..], &b"two"[..]));
+            for buf in [c1, c2, p1, p2] {
+                gs_buffer_free(buf);
+            }
+
+            gs_seal_stream_free(seal);
+            gs_open_stream_free(open);
+            gs_session_free(sa);
+            gs_session_free(sb);
+            gs_handshake_free(a);
+            gs_handshake_free(b);
+            gs_device_key_free(ka);
+            gs_device_key_free(kb);
+        }
+    }
+}
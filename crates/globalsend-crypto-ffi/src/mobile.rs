@@ -0,0 +1,346 @@
+//! UniFFI bindings for the Android and iOS apps
+//!
+//! Built with the `uniffi` feature. Generate the Kotlin and Swift sources from
+//! the compiled library:
+//!
+//! ```text
+//! cargo build --release --features uniffi
+//! cargo run --features uniffi --bin uniffi-bindgen -- generate \
+//!     --library target/release/libglobalsend_crypto_ffi.so --language kotlin --out-dir out
+//! ```
+//!
+//! UniFFI objects are shared (`Arc`) and only take `&self`, so the one-shot
+//! types of the core crate (ephemeral keys, pairing steps) sit behind a
+//! `Mutex<Option<_>>` and report [`GlobalsendError::AlreadyUsed`] when a
+//! consumed step is called again.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use globalsend_crypto::aad::Aad;
+use globalsend_crypto::pairing::{self, PairingError, PendingConfirmation, Pin, Role};
+use globalsend_crypto::secret::SecretKey;
+use globalsend_crypto::session::{SessionError, SessionKeys};
+use globalsend_crypto::stream::{self, StreamError};
+use globalsend_crypto::CryptoError;
+use x25519_dalek::PublicKey as XPublicKey;
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum GlobalsendError {
+    /// Key bytes have the wrong length or version
+    InvalidKey,
+    InvalidPin,
+    /// One-shot object called again after it was consumed
+    AlreadyUsed,
+    Pairing(PairingError),
+    Session(SessionError),
+    Crypto(CryptoError),
+    Stream(StreamError),
+    Io(io::Error),
+}
+
+impl fmt::Display for GlobalsendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GlobalsendError::InvalidKey => write!(f, "invalid key"),
+            GlobalsendError::InvalidPin => write!(f, "invalid pin"),
+            GlobalsendError::AlreadyUsed => write!(f, "object already used"),
+            GlobalsendError::Pairing(e) => write!(f, "{e}"),
+            GlobalsendError::Session(e) => write!(f, "{e}"),
+            GlobalsendError::Crypto(e) => write!(f, "{e}"),
+            GlobalsendError::Stream(e) => write!(f, "{e}"),
+            GlobalsendError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for GlobalsendError {}
+
+impl From<PairingError> for GlobalsendError {
+    fn from(e: PairingError) -> Self {
+        match e {
+            PairingError::InvalidPin => GlobalsendError::InvalidPin,
+            e => GlobalsendError::Pairing(e),
+        }
+    }
+}
+
+impl From<SessionError> for GlobalsendError {
+    fn from(e: SessionError) -> Self {
+        GlobalsendError::Session(e)
+    }
+}
+
+impl From<CryptoError> for GlobalsendError {
+    fn from(e: CryptoError) -> Self {
+        GlobalsendError::Crypto(e)
+    }
+}
+
+impl From<StreamError> for GlobalsendError {
+    fn from(e: StreamError) -> Self {
+        GlobalsendError::Stream(e)
+    }
+}
+
+impl From<io::Error> for GlobalsendError {
+    fn from(e: io::Error) -> Self {
+        GlobalsendError::Io(e)
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    // a panic in another call cannot leave these states half-updated
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn public_key(bytes: &[u8]) -> Result<XPublicKey, GlobalsendError> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| GlobalsendError::InvalidKey)?;
+    Ok(XPublicKey::from(bytes))
+}
+
+fn secret_key(bytes: &[u8]) -> Result<SecretKey, GlobalsendError> {
+    SecretKey::try_from(bytes).map_err(|_| GlobalsendError::InvalidKey)
+}
+
+/// Long-term X25519 device key
+#[derive(uniffi::Object)]
+pub struct DeviceKey(globalsend_crypto::DeviceKey);
+
+#[uniffi::export]
+impl DeviceKey {
+    #[uniffi::constructor]
+    pub fn generate() -> Arc<Self> {
+        Arc::new(Self(globalsend_crypto::DeviceKey::generate()))
+    }
+
+    /// Load the versioned encoding produced by [`DeviceKey::to_bytes`]
+    #[uniffi::constructor]
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Arc<Self>, GlobalsendError> {
+        let key = globalsend_crypto::DeviceKey::from_versioned_bytes(&bytes).map_err(|_| GlobalsendError::InvalidKey)?;
+        Ok(Arc::new(Self(key)))
+    }
+
+    /// Secret key encoding for the platform keystore
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_versioned_bytes().to_vec()
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.0.public().to_bytes().to_vec()
+    }
+}
+
+/// Single-use X25519 key for one [`Session`]
+#[derive(uniffi::Object)]
+pub struct EphemeralKey {
+    public: Vec<u8>,
+    key: Mutex<Option<globalsend_crypto::EphemeralKey>>,
+}
+
+#[uniffi::export]
+impl EphemeralKey {
+    #[uniffi::constructor]
+    pub fn generate() -> Arc<Self> {
+        let key = globalsend_crypto::EphemeralKey::generate();
+        Arc::new(Self { public: key.public().to_bytes().to_vec(), key: Mutex::new(Some(key)) })
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public.clone()
+    }
+}
+
+/// Random PIN for the displaying side of a pairing
+#[uniffi::export]
+pub fn generate_pin() -> String {
+    Pin::generate().as_str().to_string()
+}
+
+enum PairingState {
+    Started(pairing::Pairing),
+    Confirming(PendingConfirmation),
+    Done,
+}
+
+/// PIN pairing: exchange [`Pairing::message`], then confirmations, then use the key
+#[derive(uniffi::Object)]
+pub struct Pairing {
+    message: Vec<u8>,
+    state: Mutex<PairingState>,
+}
+
+#[uniffi::export]
+impl Pairing {
+    /// `initiator` is the device that displays the PIN
+    #[uniffi::constructor]
+    pub fn new(initiator: bool, pin: String) -> Result<Arc<Self>, GlobalsendError> {
+        let role = if initiator { Role::Initiator } else { Role::Responder };
+        let p = pairing::Pairing::start(role, &Pin::parse(&pin)?);
+        Ok(Arc::new(Self { message: p.message().to_vec(), state: Mutex::new(PairingState::Started(p)) }))
+    }
+
+    /// SPAKE2 message to send to the peer
+    pub fn message(&self) -> Vec<u8> {
+        self.message.clone()
+    }
+
+    /// Process the peer's message, returning our confirmation to send back
+    pub fn finish(&self, peer_message: Vec<u8>) -> Result<Vec<u8>, GlobalsendError> {
+        let mut state = lock(&self.state);
+        let PairingState::Started(p) = std::mem::replace(&mut *state, PairingState::Done) else {
+            return Err(GlobalsendError::AlreadyUsed);
+        };
+        let pending = p.finish(&peer_message)?;
+        let confirmation = pending.confirmation().to_vec();
+        *state = PairingState::Confirming(pending);
+        Ok(confirmation)
+    }
+
+    /// Check the peer's confirmation and return the 32-byte pairing key
+    pub fn verify(&self, peer_confirmation: Vec<u8>) -> Result<Vec<u8>, GlobalsendError> {
+        let PairingState::Confirming(pending) = std::mem::replace(&mut *lock(&self.state), PairingState::Done) else {
+            return Err(GlobalsendError::AlreadyUsed);
+        };
+        Ok(pending.verify(&peer_confirmation)?.as_bytes().to_vec())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SealedMessage {
+    /// Message number; send it with the ciphertext
+    pub counter: u64,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Forward-secret transfer session, see `globalsend_crypto::session`
+#[derive(uniffi::Object)]
+pub struct Session(Mutex<SessionKeys>);
+
+#[uniffi::export]
+impl Session {
+    /// Derive keys from our keys and the peer's public halves; consumes `ephemeral`
+    #[uniffi::constructor]
+    pub fn derive(
+        static_key: Arc<DeviceKey>,
+        ephemeral: Arc<EphemeralKey>,
+        peer_static: Vec<u8>,
+        peer_ephemeral: Vec<u8>,
+    ) -> Result<Arc<Self>, GlobalsendError> {
+        let (peer_static, peer_ephemeral) = (public_key(&peer_static)?, public_key(&peer_ephemeral)?);
+        let eph = lock(&ephemeral.key).take().ok_or(GlobalsendError::AlreadyUsed)?;
+        let keys = SessionKeys::derive(&static_key.0, eph, &peer_static, &peer_ephemeral)?;
+        Ok(Arc::new(Self(Mutex::new(keys))))
+    }
+
+    pub fn seal(&self, aad: Vec<u8>, plaintext: Vec<u8>) -> Result<SealedMessage, GlobalsendError> {
+        let (counter, ciphertext) = lock(&self.0).send.seal(&Aad::builder().context(&aad).build(), &plaintext)?;
+        Ok(SealedMessage { counter, ciphertext })
+    }
+
+    pub fn open(&self, counter: u64, aad: Vec<u8>, ciphertext: Vec<u8>) -> Result<Vec<u8>, GlobalsendError> {
+        Ok(lock(&self.0).recv.open(counter, &Aad::builder().context(&aad).build(), &ciphertext)?)
+    }
+
+    pub fn send_needs_rekey(&self) -> bool {
+        lock(&self.0).send.needs_rekey()
+    }
+
+    pub fn recv_needs_rekey(&self) -> bool {
+        lock(&self.0).recv.needs_rekey()
+    }
+
+    pub fn rekey_send(&self) -> Result<(), GlobalsendError> {
+        Ok(lock(&self.0).send.rekey()?)
+    }
+
+    pub fn rekey_recv(&self) -> Result<(), GlobalsendError> {
+        Ok(lock(&self.0).recv.rekey()?)
+    }
+}
+
+/// Implemented by the app to follow long-running file operations
+#[uniffi::export(with_foreign)]
+pub trait ProgressListener: Send + Sync {
+    /// Input bytes processed so far out of `total`
+    fn on_progress(&self, done: u64, total: u64);
+}
+
+/// Reports how much of the input has been read
+struct ProgressReader<R> {
+    inner: R,
+    done: u64,
+    total: u64,
+    listener: Option<Arc<dyn ProgressListener>>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.done += n as u64;
+        if let Some(l) = &self.listener {
+            l.on_progress(self.done, self.total);
+        }
+        Ok(n)
+    }
+}
+
+fn open_input(path: &str, listener: Option<Arc<dyn ProgressListener>>) -> Result<ProgressReader<File>, GlobalsendError> {
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    Ok(ProgressReader { inner: file, done: 0, total, listener })
+}
+
+/// Encrypt `input_path` into `output_path` with a 32-byte stream key; returns plaintext bytes
+#[uniffi::export]
+pub fn encrypt_file(
+    key: Vec<u8>,
+    input_path: String,
+    output_path: String,
+    listener: Option<Arc<dyn ProgressListener>>,
+) -> Result<u64, GlobalsendError> {
+    let key = secret_key(&key)?;
+    let reader = open_input(&input_path, listener)?;
+    Ok(stream::encrypt_stream(key.as_key(), reader, BufWriter::new(File::create(output_path)?))?)
+}
+
+/// Decrypt a file produced by [`encrypt_file`]; delete the output if this fails
+#[uniffi::export]
+pub fn decrypt_file(
+    key: Vec<u8>,
+    input_path: String,
+    output_path: String,
+    listener: Option<Arc<dyn ProgressListener>>,
+) -> Result<u64, GlobalsendError> {
+    let key = secret_key(&key)?;
+    let reader = open_input(&input_path, listener)?;
+    Ok(stream::decrypt_stream(key.as_key(), reader, BufWriter::new(File::create(output_path)?))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_then_session_roundtrip() {
+        let pin = generate_pin();
+        let (a, b) = (Pairing::new(true, pin.clone()).unwrap(), Pairing::new(false, pin).unwrap());
+        let conf_a = a.finish(b.message()).unwrap();
+        let conf_b = b.finish(a.message()).unwrap();
+        assert_eq!(a.verify(conf_b).unwrap(), b.verify(conf_a.clone()).unwrap());
+        assert!(matches!(a.verify(conf_a), Err(GlobalsendError::AlreadyUsed)));
+
+        let (ka, kb) = (DeviceKey::generate(), DeviceKey::from_bytes(DeviceKey::generate().to_bytes()).unwrap());
+        let (ea, eb) = (EphemeralKey::generate(), EphemeralKey::generate());
+        let sa = Session::derive(ka.clone(), ea.clone(), kb.public_key(), eb.public_key()).unwrap();
+        let sb = Session::derive(kb.clone(), eb.clone(), ka.public_key(), ea.public_key()).unwrap();
+        assert!(matches!(Session::derive(ka, ea, vec![0; 32], vec![0; 32]), Err(GlobalsendError::AlreadyUsed)));
+
+        let m = sa.seal(b"hdr".to_vec(), b"hello".to_vec()).unwrap();
+        assert_eq!(sb.open(m.counter, b"hdr".to_vec(), m.ciphertext.clone()).unwrap(), b"hello");
+        assert!(matches!(sb.open(m.counter, b"hdr".to_vec(), m.ciphertext), Err(GlobalsendError::Session(_))));
+    }
+}
@@ -0,0 +1,50 @@
+//! AEAD throughput per chunk size
+//!
+//! cargo bench --features simd
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use globalsend_crypto::stream::{SealStream, STREAM_PREFIX_LEN};
+use globalsend_crypto::suite::CipherSuite;
+use globalsend_crypto::{aead_encrypt, derive_aead};
+
+const CHUNK_SIZES: [usize; 4] = [1 << 10, 16 << 10, 64 << 10, 1 << 20];
+
+fn aead(c: &mut Criterion) {
+    let mut group = c.benchmark_group("aead");
+    for len in CHUNK_SIZES {
+        group.throughput(Throughput::Bytes(len as u64));
+        let data = vec![0x42u8; len];
+
+        // re-keys and allocates on every call
+        group.bench_with_input(BenchmarkId::new("aead_encrypt", len), &data, |b, data| {
+            let (key, mut nonces) = derive_aead(&[1; 32]).unwrap();
+            b.iter(|| aead_encrypt(key.as_key(), nonces.next().unwrap(), b"", data).unwrap());
+        });
+
+        for suite in CipherSuite::ALL {
+            group.bench_with_input(BenchmarkId::new(suite.name(), len), &data, |b, data| {
+                let (key, mut nonces) = derive_aead(&[2; 32]).unwrap();
+                let cipher = suite.cipher(key.as_key());
+                b.iter(|| cipher.seal(nonces.next().unwrap(), b"", data).unwrap());
+            });
+
+            #[cfg(feature = "simd")]
+            group.bench_with_input(BenchmarkId::new(format!("{}/in-place", suite.name()), len), &data, |b, data| {
+                let (key, mut nonces) = derive_aead(&[3; 32]).unwrap();
+                let cipher = globalsend_crypto::bulk::BulkCipher::new(suite, key.as_key());
+                let mut buf = data.clone();
+                b.iter(|| cipher.seal_in_place(nonces.next().unwrap(), b"", &mut buf).unwrap());
+            });
+        }
+
+        group.bench_with_input(BenchmarkId::new("stream_chunk", len), &data, |b, data| {
+            let (key, _) = derive_aead(&[4; 32]).unwrap();
+            let mut seal = SealStream::new(key.as_key(), &[0; STREAM_PREFIX_LEN]);
+            b.iter(|| seal.seal_chunk(b"", data).unwrap());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aead);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+//! Print the known-answer suite for a seed (default: the published one)
+//!
+//! cargo run --example kat --features test-vectors > vectors/kat-v1.json
+
+use globalsend_crypto::vectors::TestVectors;
+
+/// Seed of `vectors/kat-v1.json`
+const PUBLISHED_SEED: [u8; 32] = *b"globalsend known-answer tests v1";
+
+fn main() {
+    let seed = match std::env::args().nth(1) {
+        Some(s) => hex::decode(&s).ok().and_then(|v| v.try_into().ok()).expect("seed must be 64 hex digits"),
+        None => PUBLISHED_SEED,
+    };
+    println!("{}", TestVectors::generate(seed).to_json());
+}
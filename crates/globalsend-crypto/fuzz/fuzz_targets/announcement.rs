@@ -0,0 +1,11 @@
+#![no_main]
+
+use globalsend_crypto::announce::{Announcement, AnnouncementTracker};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(announcement) = Announcement::from_bytes(data) {
+        assert_eq!(announcement.to_bytes(), data);
+    }
+    let _ = AnnouncementTracker::default().accept(data, 1_700_000_000);
+});
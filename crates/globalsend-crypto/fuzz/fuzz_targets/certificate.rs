@@ -0,0 +1,11 @@
+#![no_main]
+
+use globalsend_crypto::certificate::DeviceCertificate;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cert) = DeviceCertificate::from_bytes(data) {
+        assert_eq!(cert.to_bytes(), data);
+        let _ = cert.verify(0);
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use globalsend_crypto::handshake::Handshake;
+use globalsend_crypto::DeviceKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let a = DeviceKey::from_bytes(&[1; 32]).unwrap();
+    let b = DeviceKey::from_bytes(&[2; 32]).unwrap();
+
+    // as the first message
+    let mut responder = Handshake::responder(&b, b"");
+    let _ = responder.read_message(data);
+
+    // as the responder's reply to a genuine first message
+    let mut initiator = Handshake::initiator(&a, b"");
+    if initiator.write_message(b"").is_ok() {
+        let _ = initiator.read_message(data);
+    }
+});
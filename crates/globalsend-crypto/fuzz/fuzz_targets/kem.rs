@@ -0,0 +1,9 @@
+#![no_main]
+
+use globalsend_crypto::hybrid::{respond, KemOffer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = respond(data);
+    let _ = KemOffer::new().accept(data);
+});
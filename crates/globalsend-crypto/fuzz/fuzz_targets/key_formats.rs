@@ -0,0 +1,21 @@
+#![no_main]
+
+use globalsend_crypto::hashing::ContentHash;
+use globalsend_crypto::keyfile::EncryptedKeyFile;
+use globalsend_crypto::merkle::MerkleProof;
+use globalsend_crypto::DeviceKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DeviceKey::from_versioned_bytes(data);
+    let _ = DeviceKey::from_pkcs8_der(data);
+    // key files are not opened: Argon2 cost comes from the header
+    let _ = EncryptedKeyFile::from_bytes(data);
+    if let Ok(proof) = MerkleProof::from_bytes(data) {
+        assert_eq!(proof.to_bytes(), data);
+    }
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = DeviceKey::from_pkcs8_pem(s);
+        let _ = ContentHash::parse(s);
+    }
+});
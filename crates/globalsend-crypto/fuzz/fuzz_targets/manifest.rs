@@ -0,0 +1,11 @@
+#![no_main]
+
+use globalsend_crypto::manifest::TransferManifest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(manifest) = TransferManifest::from_bytes(data) {
+        // only canonical encodings parse, so re-encoding is the identity
+        assert_eq!(manifest.to_bytes().unwrap(), data);
+    }
+});
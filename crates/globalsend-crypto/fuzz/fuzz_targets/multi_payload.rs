@@ -0,0 +1,14 @@
+#![no_main]
+
+use globalsend_crypto::multi::SealedPayload;
+use globalsend_crypto::DeviceKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(payload) = SealedPayload::from_bytes(data) {
+        assert_eq!(SealedPayload::from_bytes(&payload.to_bytes()).unwrap(), payload);
+        // passphrase headers are skipped: opening them runs Argon2 with attacker-chosen cost
+        let device = DeviceKey::from_bytes(&[7; 32]).unwrap();
+        let _ = payload.open(&device, b"");
+    }
+});
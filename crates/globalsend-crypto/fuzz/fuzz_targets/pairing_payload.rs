@@ -0,0 +1,14 @@
+#![no_main]
+
+use globalsend_crypto::pairing::{PairingPayload, Pin};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(payload) = PairingPayload::from_cbor(data) {
+        assert_eq!(PairingPayload::from_cbor(&payload.to_cbor()).unwrap(), payload);
+    }
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = PairingPayload::from_qr_string(s);
+        let _ = Pin::parse(s);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use globalsend_crypto::aad::Aad;
+use globalsend_crypto::session::{Frame, SessionKeys};
+use globalsend_crypto::{DeviceKey, EphemeralKey};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(frame) = Frame::parse(data) {
+        assert_eq!(frame.to_bytes(), data);
+    }
+    let ours = DeviceKey::from_bytes(&[1; 32]).unwrap();
+    let theirs = DeviceKey::from_bytes(&[2; 32]).unwrap();
+    let eph = EphemeralKey::generate();
+    let peer_eph = DeviceKey::from_bytes(&[4; 32]).unwrap().public();
+    let mut keys = SessionKeys::derive(&ours, eph, &theirs.public(), &peer_eph).unwrap();
+    let _ = keys.recv.open_frame(data, &Aad::default());
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use globalsend_crypto::stream::decrypt_stream;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let key = [9u8; 32].into();
+    let _ = decrypt_stream(&key, data, std::io::sink());
+});
@@ -0,0 +1,172 @@
+//! Canonical associated data for session and chunk encryption
+//!
+//! Free-form AAD lets two implementations disagree on layout and lets a
+//! careless caller bind nothing at all, so a chunk of one file decrypts fine
+//! when spliced into another. [`Aad`] fixes the layout instead:
+//!
+//! ```text
+//! "globalsend aad v1" || present (u8) || session id (32)
+//!     || file (u64 BE) || seq (u64 BE) || direction (u8) || context
+//! ```
+//!
+//! Every field sits at a fixed offset and `present` records which ones were
+//! set, so no two different [`Aad`]s encode to the same bytes. `context` is
+//! free-form application data and comes last, which keeps it unambiguous
+//! without a length prefix.
+//!
+//! [`SealKey::seal`](crate::session::SealKey::seal) and
+//! [`OpenKey::open`](crate::session::OpenKey::open) fill in the session id,
+//! direction and message number themselves; callers only say which file the
+//! message belongs to.
+
+use alloc::vec::Vec;
+
+const LABEL: &[u8] = b"globalsend aad v1";
+
+const HAS_SESSION: u8 = 1;
+const HAS_FILE: u8 = 2;
+const HAS_SEQ: u8 = 4;
+const HAS_DIRECTION: u8 = 8;
+
+/// Length of the encoding without `context`
+pub const AAD_HEADER_LEN: usize = LABEL.len() + 1 + 32 + 8 + 8 + 1;
+
+/// Which way a message travels, named like the session's directional keys:
+/// the side whose (static, ephemeral) public keys sort lower is `Low`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    LowToHigh,
+    HighToLow,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Direction::LowToHigh => 1,
+            Direction::HighToLow => 2,
+        }
+    }
+}
+
+/// Associated data with a fixed, canonical encoding; see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Aad {
+    session: Option<[u8; 32]>,
+    file: Option<u64>,
+    seq: Option<u64>,
+    direction: Option<Direction>,
+    context: Vec<u8>,
+}
+
+impl Aad {
+    pub fn builder() -> AadBuilder {
+        AadBuilder::default()
+    }
+
+    pub fn file(&self) -> Option<u64> {
+        self.file
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut present = 0;
+        for (set, bit) in [
+            (self.session.is_some(), HAS_SESSION),
+            (self.file.is_some(), HAS_FILE),
+            (self.seq.is_some(), HAS_SEQ),
+            (self.direction.is_some(), HAS_DIRECTION),
+        ] {
+            if set {
+                present |= bit;
+            }
+        }
+        let mut out = Vec::with_capacity(AAD_HEADER_LEN + self.context.len());
+        out.extend_from_slice(LABEL);
+        out.push(present);
+        out.extend_from_slice(&self.session.unwrap_or([0; 32]));
+        out.extend_from_slice(&self.file.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&self.seq.unwrap_or(0).to_be_bytes());
+        out.push(self.direction.map_or(0, Direction::to_byte));
+        out.extend_from_slice(&self.context);
+        out
+    }
+
+    /// Copy with the message number set to `seq`, for sealing chunk after chunk
+    pub fn with_seq(&self, seq: u64) -> Aad {
+        Aad { seq: Some(seq), ..self.clone() }
+    }
+
+    /// Encoding bound to one message of a session direction; overrides whatever the caller set
+    pub(crate) fn bind(&self, session: &[u8; 32], direction: Direction, seq: u64) -> Vec<u8> {
+        Aad { session: Some(*session), direction: Some(direction), seq: Some(seq), ..self.clone() }.to_bytes()
+    }
+}
+
+/// Builder for [`Aad`]; unset fields are recorded as absent
+#[derive(Debug, Clone, Default)]
+pub struct AadBuilder(Aad);
+
+impl AadBuilder {
+    /// Bind a [`SessionKeys::session_id`](crate::session::SessionKeys::session_id)
+    pub fn session(mut self, id: [u8; 32]) -> Self {
+        self.0.session = Some(id);
+        self
+    }
+
+    /// Index of the file within the transfer
+    pub fn file(mut self, index: u64) -> Self {
+        self.0.file = Some(index);
+        self
+    }
+
+    /// Message or chunk number
+    pub fn seq(mut self, n: u64) -> Self {
+        self.0.seq = Some(n);
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.0.direction = Some(direction);
+        self
+    }
+
+    /// Application data bound after the fixed fields
+    pub fn context(mut self, context: &[u8]) -> Self {
+        self.0.context = context.to_vec();
+        self
+    }
+
+    pub fn build(self) -> Aad {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_is_fixed_layout_and_unambiguous() {
+        let empty = Aad::default().to_bytes();
+        assert_eq!(empty.len(), AAD_HEADER_LEN);
+        assert_eq!(&empty[..LABEL.len()], LABEL);
+
+        let full = Aad::builder().session([7; 32]).file(3).seq(9).direction(Direction::HighToLow).context(b"name").build();
+        let bytes = full.to_bytes();
+        assert_eq!(bytes.len(), AAD_HEADER_LEN + 4);
+        assert_eq!(bytes[LABEL.len()], HAS_SESSION | HAS_FILE | HAS_SEQ | HAS_DIRECTION);
+        assert_eq!(&bytes[LABEL.len() + 33..LABEL.len() + 41], &3u64.to_be_bytes());
+
+        // an explicit zero is not the same as an absent field
+        assert_ne!(Aad::builder().file(0).build().to_bytes(), empty);
+        assert_ne!(Aad::builder().file(1).build().to_bytes(), Aad::builder().file(2).build().to_bytes());
+        // context cannot masquerade as a fixed field
+        assert_ne!(Aad::builder().context(&[0; 8]).build().to_bytes(), Aad::builder().file(0).build().to_bytes());
+
+        assert_eq!(full.bind(&[7; 32], Direction::HighToLow, 9), bytes);
+        assert_eq!(Aad::builder().file(3).build().with_seq(4), Aad::builder().file(3).seq(4).build());
+    }
+}
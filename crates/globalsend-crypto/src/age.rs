@@ -0,0 +1,406 @@
+//! age v1 files to and from device keys
+//!
+//! [`export_age`] writes a standard [age](https://age-encryption.org/v1) file
+//! with one X25519 recipient, so a transfer can sit on disk or go by email
+//! and still be opened later by [`import_age`], `age` or `rage`. Device
+//! exchange keys are plain X25519 keys, so [`recipient`] and [`identity`]
+//! spell them the way those tools expect: `age1…` and `AGE-SECRET-KEY-1…`.
+//!
+//! Only X25519 stanzas are understood; files with other recipient types
+//! open as long as one of their stanzas is for us. Plaintext is read and
+//! written in 64 KiB chunks, never held whole.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32, Hrp};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{CryptoRngCore, OsRng};
+use sha2::Sha256;
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::keyprovider::KeyProvider;
+use crate::{CryptoError, DeviceKey, EphemeralKey};
+
+const VERSION_LINE: &str = "age-encryption.org/v1";
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+const FILE_KEY_LEN: usize = 16;
+const PAYLOAD_NONCE_LEN: usize = 16;
+/// Plaintext bytes per payload chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+/// Stanza bodies are wrapped at this many base64 columns
+const COLUMNS: usize = 64;
+/// Longest header line read; far above anything a real file has
+const MAX_LINE: usize = 8 * 1024;
+const MAX_STANZAS: usize = 1024;
+
+#[derive(Debug)]
+pub enum AgeError {
+    Io(io::Error),
+    /// Not an age v1 file, or a malformed header
+    Header(&'static str),
+    /// No stanza in the file is for this key
+    NoIdentityMatched,
+    /// The header was altered, or the file key is wrong
+    HeaderMac,
+    /// A payload chunk failed authentication or the file was cut short
+    Payload,
+    /// Not an `age1…` recipient or `AGE-SECRET-KEY-1…` identity
+    InvalidKey,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for AgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeError::Io(e) => write!(f, "age: {e}"),
+            AgeError::Header(what) => write!(f, "age header: {what}"),
+            AgeError::NoIdentityMatched => write!(f, "age file is not encrypted to this key"),
+            AgeError::HeaderMac => write!(f, "age header authentication failed"),
+            AgeError::Payload => write!(f, "age payload is corrupted or truncated"),
+            AgeError::InvalidKey => write!(f, "invalid age key"),
+            AgeError::Crypto(e) => write!(f, "age: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AgeError {}
+
+impl From<io::Error> for AgeError {
+    fn from(e: io::Error) -> Self {
+        AgeError::Io(e)
+    }
+}
+
+impl From<CryptoError> for AgeError {
+    fn from(e: CryptoError) -> Self {
+        AgeError::Crypto(e)
+    }
+}
+
+/// `age1…` for `key`, what `age -r` takes
+pub fn recipient(key: &XPublicKey) -> String {
+    bech32::encode::<Bech32>(Hrp::parse_unchecked(RECIPIENT_HRP), key.as_bytes()).expect("32 bytes fit a bech32 string")
+}
+
+pub fn parse_recipient(s: &str) -> Result<XPublicKey, AgeError> {
+    let bytes = decode_bech32(s, RECIPIENT_HRP)?;
+    let bytes: [u8; 32] = bytes.as_slice().try_into().map_err(|_| AgeError::InvalidKey)?;
+    Ok(XPublicKey::from(bytes))
+}
+
+/// `AGE-SECRET-KEY-1…` for `key`, what `age -i` reads from a file
+pub fn identity(key: &DeviceKey) -> Zeroizing<String> {
+    Zeroizing::new(bech32::encode_upper::<Bech32>(Hrp::parse_unchecked(IDENTITY_HRP), key.to_bytes().as_ref()).expect("32 bytes fit a bech32 string"))
+}
+
+/// One identity, bare or as the first key in an identity file like `age-keygen` writes
+pub fn parse_identity(s: &str) -> Result<DeviceKey, AgeError> {
+    let key = s.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')).ok_or(AgeError::InvalidKey)?;
+    let bytes = Zeroizing::new(decode_bech32(key, IDENTITY_HRP)?);
+    DeviceKey::from_bytes(&bytes).map_err(|_| AgeError::InvalidKey)
+}
+
+fn decode_bech32(s: &str, hrp: &str) -> Result<Vec<u8>, AgeError> {
+    let parsed = CheckedHrpstring::new::<Bech32>(s.trim()).map_err(|_| AgeError::InvalidKey)?;
+    if parsed.hrp().to_lowercase() != hrp {
+        return Err(AgeError::InvalidKey);
+    }
+    Ok(parsed.byte_iter().collect())
+}
+
+/// Encrypt everything `reader` yields to `recipient`, writing the age file to `writer`
+pub fn export_age(recipient: &XPublicKey, reader: impl Read, writer: impl Write) -> Result<(), AgeError> {
+    export_age_with_rng(&mut OsRng, recipient, reader, writer)
+}
+
+/// [`export_age`] drawing the file key, ephemeral key and nonce from `rng`
+pub fn export_age_with_rng<R: CryptoRngCore>(rng: &mut R, recipient: &XPublicKey, mut reader: impl Read, mut writer: impl Write) -> Result<(), AgeError> {
+    let mut file_key = Zeroizing::new([0u8; FILE_KEY_LEN]);
+    rng.fill_bytes(file_key.as_mut());
+
+    let ephemeral = EphemeralKey::generate_with_rng(rng);
+    let share = ephemeral.public();
+    let shared = ephemeral.ecdh(recipient);
+    if shared.as_bytes().iter().all(|&b| b == 0) {
+        return Err(AgeError::InvalidKey);
+    }
+    let wrap_key = wrap_key(shared.as_bytes(), &share, recipient)?;
+    let body = ChaCha20Poly1305::new(&wrap_key).encrypt(&Nonce::default(), file_key.as_ref()).map_err(|_| CryptoError::Encrypt)?;
+
+    let mut header = format!("{VERSION_LINE}\n-> X25519 {}\n", STANDARD_NO_PAD.encode(share.as_bytes()));
+    let body = STANDARD_NO_PAD.encode(body);
+    // every line full but the last, which may be empty
+    for line in body.as_bytes().chunks(COLUMNS) {
+        header.push_str(std::str::from_utf8(line).expect("base64 is ascii"));
+        header.push('\n');
+    }
+    if body.len() % COLUMNS == 0 {
+        header.push('\n');
+    }
+    header.push_str("---");
+    let mac = header_mac(&file_key, header.as_bytes())?;
+    header.push_str(&format!(" {}\n", STANDARD_NO_PAD.encode(mac)));
+    writer.write_all(header.as_bytes())?;
+
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    writer.write_all(&nonce)?;
+    let aead = ChaCha20Poly1305::new(&payload_key(&file_key, &nonce)?);
+    let mut chunk = Zeroizing::new(vec![0u8; CHUNK_SIZE + 1]);
+    let mut len = fill(&mut reader, &mut chunk)?;
+    for counter in 0u64.. {
+        // one byte past a full chunk means another chunk follows
+        let last = len <= CHUNK_SIZE;
+        let end = len.min(CHUNK_SIZE);
+        let sealed = aead.encrypt(&chunk_nonce(counter, last), &chunk[..end]).map_err(|_| CryptoError::Encrypt)?;
+        writer.write_all(&sealed)?;
+        if last {
+            break;
+        }
+        chunk[0] = chunk[CHUNK_SIZE];
+        len = 1 + fill(&mut reader, &mut chunk[1..])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decrypt an age file from `reader` with `key`, writing the plaintext to `writer`
+///
+/// Plaintext is written as each chunk authenticates, so on an error
+/// `writer` may hold a prefix of it and should be thrown away.
+pub fn import_age(key: &dyn KeyProvider, mut reader: impl Read, mut writer: impl Write) -> Result<(), AgeError> {
+    let header = read_header(&mut reader)?;
+    let ours = key.public();
+    let mut file_key = None;
+    for stanza in header.stanzas.iter().filter(|s| s.kind == "X25519") {
+        let [share] = stanza.args.as_slice() else { return Err(AgeError::Header("X25519 stanza needs one argument")) };
+        let share: [u8; 32] = decode(share)?.try_into().map_err(|_| AgeError::Header("X25519 share is not 32 bytes"))?;
+        if stanza.body.len() != FILE_KEY_LEN + TAG_LEN {
+            return Err(AgeError::Header("X25519 body is not 32 bytes"));
+        }
+        let share = XPublicKey::from(share);
+        let shared = key.ecdh(&share)?;
+        if shared.as_bytes().iter().all(|&b| b == 0) {
+            return Err(AgeError::Header("X25519 share is a low-order point"));
+        }
+        let wrap_key = wrap_key(shared.as_bytes(), &share, &ours)?;
+        // a stanza for someone else just fails to open
+        if let Ok(unwrapped) = ChaCha20Poly1305::new(&wrap_key).decrypt(&Nonce::default(), stanza.body.as_slice()) {
+            let unwrapped = Zeroizing::new(unwrapped);
+            let mut bytes = Zeroizing::new([0u8; FILE_KEY_LEN]);
+            bytes.copy_from_slice(&unwrapped);
+            file_key = Some(bytes);
+            break;
+        }
+    }
+    let file_key = file_key.ok_or(AgeError::NoIdentityMatched)?;
+    let expected = header_mac(&file_key, &header.authenticated)?;
+    if !bool::from(subtle::ConstantTimeEq::ct_eq(&expected[..], &header.mac[..])) {
+        return Err(AgeError::HeaderMac);
+    }
+
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    reader.read_exact(&mut nonce).map_err(|_| AgeError::Payload)?;
+    let aead = ChaCha20Poly1305::new(&payload_key(&file_key, &nonce)?);
+    let sealed_len = CHUNK_SIZE + TAG_LEN;
+    let mut chunk = vec![0u8; sealed_len + 1];
+    let mut len = fill(&mut reader, &mut chunk)?;
+    for counter in 0u64.. {
+        let last = len <= sealed_len;
+        let end = len.min(sealed_len);
+        // only an empty file may end in an empty chunk
+        if end < TAG_LEN || (last && end == TAG_LEN && counter > 0) {
+            return Err(AgeError::Payload);
+        }
+        let plain = Zeroizing::new(aead.decrypt(&chunk_nonce(counter, last), &chunk[..end]).map_err(|_| AgeError::Payload)?);
+        writer.write_all(&plain)?;
+        if last {
+            break;
+        }
+        chunk[0] = chunk[sealed_len];
+        len = 1 + fill(&mut reader, &mut chunk[1..])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+struct Stanza {
+    kind: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+struct Header {
+    stanzas: Vec<Stanza>,
+    /// Everything the MAC covers: up to and including `---`
+    authenticated: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+fn read_header(reader: &mut impl Read) -> Result<Header, AgeError> {
+    let mut authenticated = Vec::new();
+    let mut line = read_line(reader)?;
+    if line != VERSION_LINE {
+        return Err(AgeError::Header("not an age v1 file"));
+    }
+    authenticated.extend_from_slice(line.as_bytes());
+    authenticated.push(b'\n');
+    let mut stanzas = Vec::new();
+    line = read_line(reader)?;
+    loop {
+        if let Some(mac) = line.strip_prefix("--- ") {
+            authenticated.extend_from_slice(b"---");
+            return Ok(Header { stanzas, authenticated, mac: decode(mac)? });
+        }
+        let Some(rest) = line.strip_prefix("-> ") else { return Err(AgeError::Header("expected a stanza or the mac")) };
+        let mut words = rest.split(' ');
+        let kind = words.next().filter(|k| !k.is_empty()).ok_or(AgeError::Header("stanza without a type"))?.to_owned();
+        let args: Vec<String> = words.map(str::to_owned).collect();
+        if args.iter().any(String::is_empty) {
+            return Err(AgeError::Header("empty stanza argument"));
+        }
+        if stanzas.len() == MAX_STANZAS {
+            return Err(AgeError::Header("too many stanzas"));
+        }
+        authenticated.extend_from_slice(line.as_bytes());
+        authenticated.push(b'\n');
+        let mut body = String::new();
+        loop {
+            let body_line = read_line(reader)?;
+            authenticated.extend_from_slice(body_line.as_bytes());
+            authenticated.push(b'\n');
+            let full = body_line.len() == COLUMNS;
+            if body_line.len() > COLUMNS {
+                return Err(AgeError::Header("stanza body line too long"));
+            }
+            body.push_str(&body_line);
+            if !full {
+                break;
+            }
+        }
+        stanzas.push(Stanza { kind, args, body: decode(&body)? });
+        line = read_line(reader)?;
+    }
+}
+
+/// One `\n`-terminated ASCII line, without the newline
+fn read_line(reader: &mut impl Read) -> Result<String, AgeError> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Err(AgeError::Header("unexpected end of header"));
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() == MAX_LINE || !(0x20..0x7f).contains(&byte[0]) {
+            return Err(AgeError::Header("invalid header line"));
+        }
+        line.push(byte[0]);
+    }
+    Ok(String::from_utf8(line).expect("checked ascii"))
+}
+
+fn decode(s: &str) -> Result<Vec<u8>, AgeError> {
+    STANDARD_NO_PAD.decode(s).map_err(|_| AgeError::Header("invalid base64"))
+}
+
+fn wrap_key(shared: &[u8], share: &XPublicKey, recipient: &XPublicKey) -> Result<Key, CryptoError> {
+    let salt = [share.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared).expand(X25519_LABEL, &mut key)?;
+    Ok(key)
+}
+
+fn header_mac(file_key: &[u8; FILE_KEY_LEN], header: &[u8]) -> Result<[u8; 32], CryptoError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(&[]), file_key).expand(b"header", key.as_mut())?;
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_ref()).map_err(|_| CryptoError::KeyDerivation)?;
+    mac.update(header);
+    Ok(mac.finalize().into_bytes().into())
+}
+
+fn payload_key(file_key: &[u8; FILE_KEY_LEN], nonce: &[u8; PAYLOAD_NONCE_LEN]) -> Result<Key, CryptoError> {
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(nonce), file_key).expand(b"payload", &mut key)?;
+    Ok(key)
+}
+
+/// 11-byte big-endian chunk counter, then 1 on the last chunk
+fn chunk_nonce(counter: u64, last: bool) -> Nonce {
+    // the counter is 88 bits in the format; 64 of them outlast any file
+    let mut nonce = Nonce::default();
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+/// Read until `buf` is full or the reader ends; returns the bytes read
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_at_chunk_boundaries_and_rejects_tampering() {
+        let key = DeviceKey::generate();
+        let other = DeviceKey::generate();
+        assert_eq!(parse_recipient(&recipient(&key.public())).unwrap(), key.public());
+        assert_eq!(parse_identity(&identity(&key)).unwrap().public(), key.public());
+        let file = format!("# created: 2026-01-01T00:00:00Z\n# public key: {}\n{}\n", recipient(&key.public()), identity(&key).as_str());
+        assert_eq!(parse_identity(&file).unwrap().public(), key.public());
+        assert!(identity(&key).starts_with("AGE-SECRET-KEY-1") && recipient(&key.public()).starts_with("age1"));
+        assert!(matches!(parse_recipient("age1qqqq"), Err(AgeError::InvalidKey)));
+
+        for len in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 2 * CHUNK_SIZE] {
+            let plain: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let mut file = Vec::new();
+            export_age(&key.public(), plain.as_slice(), &mut file).unwrap();
+            assert!(file.starts_with(b"age-encryption.org/v1\n-> X25519 "));
+            let mut out = Vec::new();
+            import_age(&key, file.as_slice(), &mut out).unwrap();
+            assert_eq!(out, plain, "{len}");
+            assert!(matches!(import_age(&other, file.as_slice(), io::sink()), Err(AgeError::NoIdentityMatched)));
+        }
+
+        let mut file = Vec::new();
+        export_age(&key.public(), &b"attack at dawn"[..], &mut file).unwrap();
+        let mut cut = file.clone();
+        cut.truncate(file.len() - 1);
+        assert!(matches!(import_age(&key, cut.as_slice(), io::sink()), Err(AgeError::Payload)));
+        let mut flipped = file.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(matches!(import_age(&key, flipped.as_slice(), io::sink()), Err(AgeError::Payload)));
+        // an extra stanza changes what the mac covers
+        let text = String::from_utf8_lossy(&file).into_owned();
+        let at = text.find("---").unwrap();
+        let mut spliced = file[..at].to_vec();
+        spliced.extend_from_slice(b"-> other\n\n");
+        spliced.extend_from_slice(&file[at..]);
+        assert!(matches!(import_age(&key, spliced.as_slice(), io::sink()), Err(AgeError::HeaderMac)));
+    }
+}
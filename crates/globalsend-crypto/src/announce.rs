@@ -0,0 +1,236 @@
+//! Signed discovery announcements
+//!
+//! Devices periodically broadcast an [`Announcement`] (mDNS TXT record,
+//! multicast, BLE) saying who they are and where to connect. On a hostile
+//! network anyone can send such packets, so each one is signed with the
+//! Ed25519 identity key and carries a timestamp:
+//!
+//! - a spoofed announcement fails [`Announcement::verify`] unless it names
+//!   the attacker's own identity, which then does not match a pinned
+//!   fingerprint;
+//! - a replayed one is rejected once it falls outside the freshness window,
+//!   and [`AnnouncementTracker`] rejects one older than the latest seen from
+//!   the same device even inside the window, so a stale port cannot be
+//!   re-injected.
+//!
+//! Encoding is canonical CBOR: `{0: version, 1: identity, 2: alias,
+//! 3: port, 4: capabilities, 5: timestamp, 6: signature}`; the signature
+//! covers the domain separator followed by the encoding of fields 0..=5.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::identity::{self, DeviceIdentity, Fingerprint};
+
+const ANNOUNCEMENT_CONTEXT: &[u8] = b"globalsend announcement v1";
+pub const ANNOUNCEMENT_VERSION: u64 = 1;
+pub const MAX_ALIAS_LEN: usize = 64;
+/// Accepted clock difference between sender and receiver, in seconds
+pub const DEFAULT_FRESHNESS_SECS: u64 = 120;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnouncementError {
+    AliasTooLong,
+    BadSignature,
+    /// Timestamp is further in the past than the freshness window
+    Stale,
+    /// Timestamp is further in the future than the freshness window
+    FromFuture,
+    /// Older than an announcement already accepted from this device
+    Replayed,
+    UnsupportedVersion(u64),
+    Cbor(CborError),
+}
+
+impl fmt::Display for AnnouncementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnouncementError::AliasTooLong => write!(f, "device alias longer than {MAX_ALIAS_LEN} bytes"),
+            AnnouncementError::BadSignature => write!(f, "invalid announcement signature"),
+            AnnouncementError::Stale => write!(f, "announcement too old"),
+            AnnouncementError::FromFuture => write!(f, "announcement timestamp in the future"),
+            AnnouncementError::Replayed => write!(f, "replayed announcement"),
+            AnnouncementError::UnsupportedVersion(v) => write!(f, "unsupported announcement version {v}"),
+            AnnouncementError::Cbor(e) => write!(f, "invalid announcement encoding: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for AnnouncementError {}
+
+impl From<CborError> for AnnouncementError {
+    fn from(e: CborError) -> Self {
+        AnnouncementError::Cbor(e)
+    }
+}
+
+/// Signed "this device is here" statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub identity: VerifyingKey,
+    /// Human-readable device name, at most [`MAX_ALIAS_LEN`] bytes
+    pub alias: String,
+    pub port: u16,
+    /// Feature bits; unknown bits are ignored
+    pub capabilities: u64,
+    /// Unix seconds
+    pub timestamp: u64,
+    pub signature: Signature,
+}
+
+fn encode_tbs(e: &mut Encoder, identity: &VerifyingKey, alias: &str, port: u16, capabilities: u64, timestamp: u64) {
+    e.uint(0).uint(ANNOUNCEMENT_VERSION);
+    e.uint(1).bytes(identity.as_bytes());
+    e.uint(2).text(alias);
+    e.uint(3).uint(port.into());
+    e.uint(4).uint(capabilities);
+    e.uint(5).uint(timestamp);
+}
+
+fn signed_bytes(identity: &VerifyingKey, alias: &str, port: u16, capabilities: u64, timestamp: u64) -> Vec<u8> {
+    let mut e = Encoder::new();
+    e.map(6);
+    encode_tbs(&mut e, identity, alias, port, capabilities, timestamp);
+    [ANNOUNCEMENT_CONTEXT, &e.finish()].concat()
+}
+
+impl Announcement {
+    pub fn sign(identity: &DeviceIdentity, alias: &str, port: u16, capabilities: u64, timestamp: u64) -> Result<Self, AnnouncementError> {
+        if alias.len() > MAX_ALIAS_LEN {
+            return Err(AnnouncementError::AliasTooLong);
+        }
+        let key = identity.verifying_key();
+        let signature = identity.sign(&signed_bytes(&key, alias, port, capabilities, timestamp));
+        Ok(Self { identity: key, alias: alias.to_string(), port, capabilities, timestamp, signature })
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.identity)
+    }
+
+    /// Check the signature and that the timestamp is within `freshness` seconds of `now`
+    pub fn verify(&self, now: u64, freshness: u64) -> Result<(), AnnouncementError> {
+        let msg = signed_bytes(&self.identity, &self.alias, self.port, self.capabilities, self.timestamp);
+        identity::verify(&self.identity, &msg, &self.signature).map_err(|_| AnnouncementError::BadSignature)?;
+        if self.timestamp.saturating_add(freshness) < now {
+            return Err(AnnouncementError::Stale);
+        }
+        if self.timestamp > now.saturating_add(freshness) {
+            return Err(AnnouncementError::FromFuture);
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(7);
+        encode_tbs(&mut e, &self.identity, &self.alias, self.port, self.capabilities, self.timestamp);
+        e.uint(6).bytes(&self.signature.to_bytes());
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AnnouncementError> {
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 7 {
+            return Err(CborError::Schema("announcement must have 7 fields").into());
+        }
+        d.key(0)?;
+        let version = d.uint()?;
+        if version != ANNOUNCEMENT_VERSION {
+            return Err(AnnouncementError::UnsupportedVersion(version));
+        }
+        d.key(1)?;
+        let identity = VerifyingKey::from_bytes(&d.byte_array::<32>()?).map_err(|_| CborError::Schema("invalid identity key"))?;
+        d.key(2)?;
+        let alias = d.text()?;
+        if alias.len() > MAX_ALIAS_LEN {
+            return Err(AnnouncementError::AliasTooLong);
+        }
+        d.key(3)?;
+        let port = u16::try_from(d.uint()?).map_err(|_| CborError::Schema("port out of range"))?;
+        d.key(4)?;
+        let capabilities = d.uint()?;
+        d.key(5)?;
+        let timestamp = d.uint()?;
+        d.key(6)?;
+        let signature = Signature::from_bytes(&d.byte_array::<64>()?);
+        d.finish()?;
+        Ok(Self { identity, alias: alias.to_string(), port, capabilities, timestamp, signature })
+    }
+}
+
+/// Latest accepted announcement time per device, for rejecting replays inside the freshness window
+#[derive(Debug)]
+pub struct AnnouncementTracker {
+    freshness: u64,
+    latest: BTreeMap<Fingerprint, u64>,
+}
+
+impl Default for AnnouncementTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRESHNESS_SECS)
+    }
+}
+
+impl AnnouncementTracker {
+    pub fn new(freshness: u64) -> Self {
+        Self { freshness, latest: BTreeMap::new() }
+    }
+
+    /// Parse, verify and record an announcement.
+    ///
+    /// A rebroadcast with the same timestamp is accepted; an older one is not.
+    pub fn accept(&mut self, bytes: &[u8], now: u64) -> Result<Announcement, AnnouncementError> {
+        let announcement = Announcement::from_bytes(bytes)?;
+        announcement.verify(now, self.freshness)?;
+        let fingerprint = announcement.fingerprint();
+        if self.latest.get(&fingerprint).is_some_and(|&t| announcement.timestamp < t) {
+            return Err(AnnouncementError::Replayed);
+        }
+        // entries this old can only be matched by announcements that fail `verify` anyway
+        let horizon = now.saturating_sub(self.freshness);
+        self.latest.retain(|_, t| *t >= horizon);
+        self.latest.insert(fingerprint, announcement.timestamp);
+        Ok(announcement)
+    }
+
+    /// Number of devices currently tracked
+    pub fn len(&self) -> usize {
+        self.latest.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spoofed_and_replayed_announcements_are_rejected() {
+        let id = DeviceIdentity::generate();
+        let old = Announcement::sign(&id, "Laptop", 53317, 0b101, 1000).unwrap();
+        let new = Announcement::sign(&id, "Laptop", 53318, 0b101, 1060).unwrap();
+        assert_eq!(Announcement::from_bytes(&new.to_bytes()).unwrap(), new);
+
+        let mut tracker = AnnouncementTracker::new(120);
+        assert_eq!(tracker.accept(&new.to_bytes(), 1070).unwrap().port, 53318);
+        assert_eq!(tracker.accept(&new.to_bytes(), 1075).unwrap().port, 53318);
+        assert_eq!(tracker.accept(&old.to_bytes(), 1070), Err(AnnouncementError::Replayed));
+        assert_eq!(tracker.accept(&new.to_bytes(), 1181), Err(AnnouncementError::Stale));
+        assert_eq!(new.verify(900, 120), Err(AnnouncementError::FromFuture));
+
+        let mut spoofed = new.clone();
+        spoofed.port = 4444;
+        assert_eq!(tracker.accept(&spoofed.to_bytes(), 1070), Err(AnnouncementError::BadSignature));
+        assert_eq!(Announcement::sign(&id, &"x".repeat(MAX_ALIAS_LEN + 1), 1, 0, 0), Err(AnnouncementError::AliasTooLong));
+        assert_eq!(tracker.len(), 1);
+    }
+}
@@ -0,0 +1,140 @@
+//! In-place AEAD for the bulk data path
+//!
+//! [`aead_encrypt`](crate::aead_encrypt) and [`AeadCipher`](crate::suite::AeadCipher)
+//! allocate a fresh output buffer for every message, and the free functions
+//! also re-key the cipher on every call. At gigabit rates that per-call
+//! overhead, not the cipher, is what limits a sender. [`BulkCipher`] is keyed
+//! once and encrypts the caller's buffer in place with a detached tag, so a
+//! transfer can reuse one chunk buffer for the whole file and the SIMD
+//! backends run over long contiguous slices.
+//!
+//! Output is wire-compatible with the allocating API: `ciphertext || tag`.
+//!
+//! Backends (see [`simd_backend`]): on x86 the cipher crates pick AVX2 or
+//! SSE2 for ChaCha20 and AES-NI/CLMUL for AES-GCM at runtime. On aarch64 the
+//! NEON and ARMv8 crypto backends are behind the `chacha20_force_neon`,
+//! `aes_armv8` and `polyval_armv8` cfg flags, which the repository
+//! `.cargo/config.toml` sets; building with `simd` without them is an error.
+
+use alloc::boxed::Box;
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+
+use aes_gcm::Aes256Gcm;
+
+use crate::events;
+use crate::suite::{gcm_nonce, CipherSuite};
+use crate::{CryptoError, MessageNonce, NonceSequence};
+
+#[cfg(all(target_arch = "aarch64", not(all(chacha20_force_neon, aes_armv8, polyval_armv8))))]
+compile_error!("the `simd` feature on aarch64 needs `--cfg chacha20_force_neon --cfg aes_armv8 --cfg polyval_armv8` (see .cargo/config.toml)");
+
+/// Poly1305 and GHASH tags are both 16 bytes
+pub const TAG_LEN: usize = 16;
+
+/// Vector unit the ChaCha20 backend runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimdBackend {
+    Avx2,
+    Sse2,
+    Neon,
+    Portable,
+}
+
+#[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn simd_backend() -> SimdBackend {
+    if std::arch::is_x86_feature_detected!("avx2") {
+        SimdBackend::Avx2
+    } else if std::arch::is_x86_feature_detected!("sse2") {
+        SimdBackend::Sse2
+    } else {
+        SimdBackend::Portable
+    }
+}
+
+/// Without runtime detection, report what the target features guarantee
+#[cfg(not(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64"))))]
+pub fn simd_backend() -> SimdBackend {
+    if cfg!(target_feature = "avx2") {
+        SimdBackend::Avx2
+    } else if cfg!(target_feature = "sse2") {
+        SimdBackend::Sse2
+    } else if cfg!(all(target_arch = "aarch64", target_feature = "neon")) {
+        SimdBackend::Neon
+    } else {
+        SimdBackend::Portable
+    }
+}
+
+enum Inner {
+    XChaCha(XChaCha20Poly1305),
+    // expanded AES key schedule is ~1 KiB
+    Aes(Box<Aes256Gcm>),
+}
+
+/// Cipher keyed once for many in-place messages
+pub struct BulkCipher {
+    suite: CipherSuite,
+    inner: Inner,
+}
+
+impl BulkCipher {
+    pub fn new(suite: CipherSuite, key: &Key) -> Self {
+        let inner = match suite {
+            CipherSuite::XChaCha20Poly1305 => Inner::XChaCha(XChaCha20Poly1305::new(key)),
+            CipherSuite::Aes256Gcm => Inner::Aes(Box::new(Aes256Gcm::new(key))),
+        };
+        Self { suite, inner }
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    /// Encrypt `buf` in place and return its tag
+    pub fn seal_in_place(&self, nonce: MessageNonce, aad: &[u8], buf: &mut [u8]) -> Result<[u8; TAG_LEN], CryptoError> {
+        let nonce = nonce.bytes.as_bytes();
+        let tag = match &self.inner {
+            Inner::XChaCha(c) => c.encrypt_in_place_detached(XNonce::from_slice(nonce), aad, buf),
+            Inner::Aes(c) => c.encrypt_in_place_detached(gcm_nonce(nonce), aad, buf),
+        };
+        tag.map(Into::into).map_err(|_| CryptoError::Encrypt)
+    }
+
+    /// Decrypt message number `counter` in place; `buf` is unspecified on error
+    pub fn open_in_place(&self, nonces: &NonceSequence, counter: u64, aad: &[u8], buf: &mut [u8], tag: &[u8; TAG_LEN]) -> Result<(), CryptoError> {
+        let nonce = nonces.at(counter);
+        let tag = Tag::from_slice(tag);
+        match &self.inner {
+            Inner::XChaCha(c) => c.decrypt_in_place_detached(XNonce::from_slice(nonce.as_bytes()), aad, buf, tag),
+            Inner::Aes(c) => c.decrypt_in_place_detached(gcm_nonce(nonce.as_bytes()), aad, buf, tag),
+        }
+        .map_err(|_| events::decrypt_failed(CryptoError::Decrypt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derive_aead;
+
+    #[test]
+    fn in_place_matches_allocating_api() {
+        for suite in CipherSuite::ALL {
+            let (key, mut nonces) = derive_aead(&[suite.id(); 32]).unwrap();
+            let bulk = BulkCipher::new(suite, key.as_key());
+            let mut buf = *b"sixteen byte msg";
+            let tag = bulk.seal_in_place(nonces.next().unwrap(), b"aad", &mut buf).unwrap();
+
+            let wire = [&buf[..], &tag].concat();
+            assert_eq!(suite.cipher(key.as_key()).open(&nonces, 0, b"aad", &wire).unwrap(), b"sixteen byte msg");
+
+            bulk.open_in_place(&nonces, 0, b"aad", &mut buf, &tag).unwrap();
+            assert_eq!(&buf, b"sixteen byte msg");
+            assert_eq!(bulk.open_in_place(&nonces, 1, b"aad", &mut buf, &tag), Err(CryptoError::Decrypt));
+        }
+        #[cfg(target_arch = "x86_64")]
+        assert_ne!(simd_backend(), SimdBackend::Portable);
+    }
+}
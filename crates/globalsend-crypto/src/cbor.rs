@@ -0,0 +1,222 @@
+//! Minimal deterministic CBOR (RFC 8949 section 4.2) for small wire structures
+//!
+//! Only what our payloads need: unsigned ints, byte/text strings, arrays and
+//! maps with definite lengths. The decoder is strict: non-shortest integer
+//! encodings, indefinite lengths and trailing bytes are rejected, so every
+//! value has exactly one accepted encoding.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+const MAJOR_UINT: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CborError {
+    Truncated,
+    /// Unexpected major type at this position
+    UnexpectedType,
+    /// Integer or length not in shortest form, or indefinite length
+    NonCanonical,
+    InvalidUtf8,
+    TrailingBytes,
+    /// Well-formed CBOR that does not match the expected schema
+    Schema(&'static str),
+}
+
+impl fmt::Display for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CborError::Truncated => write!(f, "cbor input truncated"),
+            CborError::UnexpectedType => write!(f, "unexpected cbor type"),
+            CborError::NonCanonical => write!(f, "non-canonical cbor encoding"),
+            CborError::InvalidUtf8 => write!(f, "invalid utf-8 in cbor text"),
+            CborError::TrailingBytes => write!(f, "trailing bytes after cbor value"),
+            CborError::Schema(what) => write!(f, "cbor schema mismatch: {what}"),
+        }
+    }
+}
+
+impl core::error::Error for CborError {}
+
+#[derive(Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn head(&mut self, major: u8, v: u64) {
+        let m = major << 5;
+        match v {
+            0..=23 => self.buf.push(m | v as u8),
+            24..=0xff => self.buf.extend_from_slice(&[m | 24, v as u8]),
+            0x100..=0xffff => {
+                self.buf.push(m | 25);
+                self.buf.extend_from_slice(&(v as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.buf.push(m | 26);
+                self.buf.extend_from_slice(&(v as u32).to_be_bytes());
+            }
+            _ => {
+                self.buf.push(m | 27);
+                self.buf.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn uint(&mut self, v: u64) -> &mut Self {
+        self.head(MAJOR_UINT, v);
+        self
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        self.head(MAJOR_BYTES, b.len() as u64);
+        self.buf.extend_from_slice(b);
+        self
+    }
+
+    pub fn text(&mut self, s: &str) -> &mut Self {
+        self.head(MAJOR_TEXT, s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    pub fn array(&mut self, len: usize) -> &mut Self {
+        self.head(MAJOR_ARRAY, len as u64);
+        self
+    }
+
+    /// Map header; callers must write keys in ascending order
+    pub fn map(&mut self, len: usize) -> &mut Self {
+        self.head(MAJOR_MAP, len as u64);
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CborError> {
+        let end = self.pos.checked_add(n).ok_or(CborError::Truncated)?;
+        let s = self.data.get(self.pos..end).ok_or(CborError::Truncated)?;
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn head(&mut self, major: u8) -> Result<u64, CborError> {
+        let b = self.take(1)?[0];
+        if b >> 5 != major {
+            return Err(CborError::UnexpectedType);
+        }
+        let (v, min) = match b & 0x1f {
+            n @ 0..=23 => return Ok(n as u64),
+            24 => (self.take(1)?[0] as u64, 24),
+            25 => (u16::from_be_bytes(self.take(2)?.try_into().expect("2 bytes")) as u64, 0x100),
+            26 => (u32::from_be_bytes(self.take(4)?.try_into().expect("4 bytes")) as u64, 0x1_0000),
+            27 => (u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")), 0x1_0000_0000),
+            _ => return Err(CborError::NonCanonical),
+        };
+        if v < min {
+            return Err(CborError::NonCanonical);
+        }
+        Ok(v)
+    }
+
+    fn len(&mut self, major: u8) -> Result<usize, CborError> {
+        let v = self.head(major)?;
+        // a length can never exceed the remaining input (every item is >= 1 byte)
+        if v > (self.data.len() - self.pos) as u64 {
+            return Err(CborError::Truncated);
+        }
+        Ok(v as usize)
+    }
+
+    pub fn uint(&mut self) -> Result<u64, CborError> {
+        self.head(MAJOR_UINT)
+    }
+
+    /// Expect a specific unsigned int, e.g. a map key
+    pub fn key(&mut self, expected: u64) -> Result<(), CborError> {
+        if self.uint()? != expected {
+            return Err(CborError::Schema("unexpected map key"));
+        }
+        Ok(())
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], CborError> {
+        let n = self.len(MAJOR_BYTES)?;
+        self.take(n)
+    }
+
+    /// Byte string of exactly `N` bytes
+    pub fn byte_array<const N: usize>(&mut self) -> Result<[u8; N], CborError> {
+        self.bytes()?.try_into().map_err(|_| CborError::Schema("wrong byte string length"))
+    }
+
+    pub fn text(&mut self) -> Result<&'a str, CborError> {
+        let n = self.len(MAJOR_TEXT)?;
+        core::str::from_utf8(self.take(n)?).map_err(|_| CborError::InvalidUtf8)
+    }
+
+    pub fn array(&mut self) -> Result<usize, CborError> {
+        self.len(MAJOR_ARRAY)
+    }
+
+    pub fn map(&mut self) -> Result<usize, CborError> {
+        self.len(MAJOR_MAP)
+    }
+
+    pub fn finish(self) -> Result<(), CborError> {
+        if self.pos != self.data.len() {
+            return Err(CborError::TrailingBytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_strictness() {
+        let mut e = Encoder::new();
+        e.map(2).uint(0).uint(500).uint(1).array(2).bytes(b"ab").text("hi");
+        let buf = e.finish();
+        assert_eq!(buf, [0xa2, 0x00, 0x19, 0x01, 0xf4, 0x01, 0x82, 0x42, b'a', b'b', 0x62, b'h', b'i']);
+
+        let mut d = Decoder::new(&buf);
+        assert_eq!(d.map().unwrap(), 2);
+        d.key(0).unwrap();
+        assert_eq!(d.uint().unwrap(), 500);
+        d.key(1).unwrap();
+        assert_eq!(d.array().unwrap(), 2);
+        assert_eq!(d.bytes().unwrap(), b"ab");
+        assert_eq!(d.text().unwrap(), "hi");
+        d.finish().unwrap();
+
+        // 5 encoded with a one-byte argument is valid CBOR but not canonical
+        assert_eq!(Decoder::new(&[0x18, 0x05]).uint(), Err(CborError::NonCanonical));
+        assert_eq!(Decoder::new(&[0x5f]).bytes(), Err(CborError::NonCanonical));
+        assert_eq!(Decoder::new(&[0x45, 1, 2]).bytes(), Err(CborError::Truncated));
+    }
+}
@@ -0,0 +1,262 @@
+//! Device certificates: the Ed25519 identity vouches for the X25519 exchange key
+//!
+//! Peers pin the identity [`Fingerprint`], not the exchange key. A device can
+//! then rotate its exchange key at will: it signs a new certificate and sends
+//! it along in the handshake payload. Verifiers check that
+//!
+//! 1. the certificate's identity key hashes to the pinned fingerprint,
+//! 2. the signature is valid,
+//! 3. the certified exchange key is the one the handshake authenticated, and
+//! 4. the current time is inside the validity window.
+//!
+//! Encoding is canonical CBOR: `{0: version, 1: identity, 2: exchange,
+//! 3: valid_from, 4: valid_until, 5: signature}`; the signature covers the
+//! domain separator followed by the encoding of fields 0..=4.
+//!
+//! A certificate can be replayed by anyone who saw it. To prove a fingerprint
+//! to a peer inside one session, the identity instead signs its exchange key
+//! and that session's handshake hash ([`DeviceIdentity::prove_session`]); the
+//! peer checks it with [`verify_session_proof`] against the key and hash its
+//! own handshake produced.
+
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use x25519_dalek::PublicKey as XPublicKey;
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::ct::ct_eq_array;
+use crate::events;
+use crate::identity::{self, DeviceIdentity, Fingerprint};
+#[cfg(feature = "std")]
+use crate::DeviceKey;
+
+const CERT_CONTEXT: &[u8] = b"globalsend device certificate v1";
+const SESSION_CONTEXT: &[u8] = b"globalsend session proof v1";
+pub const CERT_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    /// Identity key does not match the pinned fingerprint
+    WrongIdentity,
+    /// Certified exchange key differs from the key used in the handshake
+    WrongExchangeKey,
+    BadSignature,
+    NotYetValid,
+    Expired,
+    UnsupportedVersion(u64),
+    Cbor(CborError),
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertificateError::WrongIdentity => write!(f, "certificate issued by unexpected identity"),
+            CertificateError::WrongExchangeKey => write!(f, "certificate does not cover the session key"),
+            CertificateError::BadSignature => write!(f, "invalid certificate signature"),
+            CertificateError::NotYetValid => write!(f, "certificate not yet valid"),
+            CertificateError::Expired => write!(f, "certificate expired"),
+            CertificateError::UnsupportedVersion(v) => write!(f, "unsupported certificate version {v}"),
+            CertificateError::Cbor(e) => write!(f, "invalid certificate encoding: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for CertificateError {}
+
+impl From<CborError> for CertificateError {
+    fn from(e: CborError) -> Self {
+        CertificateError::Cbor(e)
+    }
+}
+
+/// Seconds since the Unix epoch
+#[cfg(feature = "std")]
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Identity-signed statement that `exchange` belongs to `identity` for a time window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCertificate {
+    pub identity: VerifyingKey,
+    pub exchange: XPublicKey,
+    /// Unix seconds, inclusive
+    pub valid_from: u64,
+    /// Unix seconds, exclusive
+    pub valid_until: u64,
+    pub signature: Signature,
+}
+
+fn encode_tbs(e: &mut Encoder, identity: &VerifyingKey, exchange: &XPublicKey, valid_from: u64, valid_until: u64) {
+    e.uint(0).uint(CERT_VERSION);
+    e.uint(1).bytes(identity.as_bytes());
+    e.uint(2).bytes(exchange.as_bytes());
+    e.uint(3).uint(valid_from);
+    e.uint(4).uint(valid_until);
+}
+
+fn signed_bytes(identity: &VerifyingKey, exchange: &XPublicKey, valid_from: u64, valid_until: u64) -> Vec<u8> {
+    let mut e = Encoder::new();
+    e.map(5);
+    encode_tbs(&mut e, identity, exchange, valid_from, valid_until);
+    [CERT_CONTEXT, &e.finish()].concat()
+}
+
+fn session_bytes(exchange: &XPublicKey, handshake_hash: &[u8; 32]) -> Vec<u8> {
+    [SESSION_CONTEXT, exchange.as_bytes(), handshake_hash].concat()
+}
+
+/// Check that `identity` signed `exchange` into the session with
+/// `handshake_hash`; the identity's fingerprint if it did
+///
+/// `exchange` and `handshake_hash` must come from the verifier's own
+/// handshake, never from the peer's messages.
+pub fn verify_session_proof(identity: &VerifyingKey, exchange: &XPublicKey, handshake_hash: &[u8; 32], signature: &Signature) -> Result<Fingerprint, CertificateError> {
+    identity::verify(identity, &session_bytes(exchange, handshake_hash), signature).map_err(|_| CertificateError::BadSignature)?;
+    let fingerprint = Fingerprint::of(identity);
+    events::emit(|s| s.peer_verified(&fingerprint));
+    Ok(fingerprint)
+}
+
+impl DeviceCertificate {
+    /// Fingerprint of the issuing identity
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.identity)
+    }
+
+    /// Check signature and validity window only
+    pub fn verify(&self, now: u64) -> Result<(), CertificateError> {
+        let msg = signed_bytes(&self.identity, &self.exchange, self.valid_from, self.valid_until);
+        identity::verify(&self.identity, &msg, &self.signature).map_err(|_| CertificateError::BadSignature)?;
+        if now < self.valid_from {
+            return Err(CertificateError::NotYetValid);
+        }
+        if now >= self.valid_until {
+            return Err(CertificateError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Full chain check against a pinned fingerprint and the handshake's remote static key
+    pub fn verify_chain(&self, pinned: &Fingerprint, remote_static: &XPublicKey, now: u64) -> Result<(), CertificateError> {
+        if self.fingerprint() != *pinned {
+            return Err(CertificateError::WrongIdentity);
+        }
+        if !ct_eq_array(self.exchange.as_bytes(), remote_static.as_bytes()) {
+            return Err(CertificateError::WrongExchangeKey);
+        }
+        self.verify(now)?;
+        events::emit(|s| s.peer_verified(pinned));
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(6);
+        encode_tbs(&mut e, &self.identity, &self.exchange, self.valid_from, self.valid_until);
+        e.uint(5).bytes(&self.signature.to_bytes());
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CertificateError> {
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 6 {
+            return Err(CborError::Schema("certificate must have 6 fields").into());
+        }
+        d.key(0)?;
+        let version = d.uint()?;
+        if version != CERT_VERSION {
+            return Err(CertificateError::UnsupportedVersion(version));
+        }
+        d.key(1)?;
+        let identity = VerifyingKey::from_bytes(&d.byte_array::<32>()?).map_err(|_| CborError::Schema("invalid identity key"))?;
+        d.key(2)?;
+        let exchange = XPublicKey::from(d.byte_array::<32>()?);
+        d.key(3)?;
+        let valid_from = d.uint()?;
+        d.key(4)?;
+        let valid_until = d.uint()?;
+        d.key(5)?;
+        let signature = Signature::from_bytes(&d.byte_array::<64>()?);
+        d.finish()?;
+        Ok(Self { identity, exchange, valid_from, valid_until, signature })
+    }
+}
+
+impl DeviceIdentity {
+    /// Certify `exchange` for `[valid_from, valid_until)`
+    pub fn certify_exchange_key(&self, exchange: &XPublicKey, valid_from: u64, valid_until: u64) -> DeviceCertificate {
+        let identity = self.verifying_key();
+        let signature = self.sign(&signed_bytes(&identity, exchange, valid_from, valid_until));
+        DeviceCertificate { identity, exchange: *exchange, valid_from, valid_until, signature }
+    }
+
+    /// Proof for the peer of one session that this identity holds the
+    /// current exchange key; see [`verify_session_proof`]
+    pub fn prove_session(&self, handshake_hash: &[u8; 32]) -> Signature {
+        self.sign(&session_bytes(&self.exchange().public(), handshake_hash))
+    }
+
+    /// Certificate for the current exchange key, valid from now for `validity`
+    #[cfg(feature = "std")]
+    pub fn certificate(&self, validity: Duration) -> DeviceCertificate {
+        let now = unix_now();
+        self.certify_exchange_key(&self.exchange().public(), now, now.saturating_add(validity.as_secs()))
+    }
+
+    /// Replace the exchange key with a fresh one and certify it.
+    ///
+    /// Returns the old key so in-flight sessions can finish with it.
+    #[cfg(feature = "std")]
+    pub fn rotate_exchange_key(&mut self, validity: Duration) -> (DeviceKey, DeviceCertificate) {
+        let old = self.replace_exchange(DeviceKey::generate());
+        (old, self.certificate(validity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_key_verifies_against_pinned_identity() {
+        let mut id = DeviceIdentity::generate();
+        let pinned = id.fingerprint();
+        let (old, cert) = id.rotate_exchange_key(Duration::from_secs(3600));
+        assert_ne!(old.public(), id.exchange().public());
+
+        let parsed = DeviceCertificate::from_bytes(&cert.to_bytes()).unwrap();
+        assert_eq!(parsed, cert);
+        let now = unix_now();
+        assert_eq!(parsed.verify_chain(&pinned, &id.exchange().public(), now), Ok(()));
+        assert_eq!(parsed.verify_chain(&pinned, &old.public(), now), Err(CertificateError::WrongExchangeKey));
+        assert_eq!(parsed.verify(now + 3600), Err(CertificateError::Expired));
+
+        let other = DeviceIdentity::generate().fingerprint();
+        assert_eq!(parsed.verify_chain(&other, &id.exchange().public(), now), Err(CertificateError::WrongIdentity));
+
+        let mut forged = cert.clone();
+        forged.valid_until += 1;
+        assert_eq!(forged.verify(now), Err(CertificateError::BadSignature));
+    }
+
+    #[test]
+    fn session_proof_is_bound_to_key_and_session() {
+        let id = DeviceIdentity::generate();
+        let (hash, other_hash) = ([1; 32], [2; 32]);
+        let proof = id.prove_session(&hash);
+        let exchange = id.exchange().public();
+        assert_eq!(verify_session_proof(&id.verifying_key(), &exchange, &hash, &proof), Ok(id.fingerprint()));
+        assert_eq!(verify_session_proof(&id.verifying_key(), &exchange, &other_hash, &proof), Err(CertificateError::BadSignature));
+        // someone else's key with this identity's proof, or this key claimed by another identity
+        let other = DeviceIdentity::generate();
+        assert_eq!(verify_session_proof(&id.verifying_key(), &other.exchange().public(), &hash, &proof), Err(CertificateError::BadSignature));
+        assert_eq!(verify_session_proof(&other.verifying_key(), &exchange, &hash, &proof), Err(CertificateError::BadSignature));
+    }
+}
@@ -0,0 +1,37 @@
+//! Constant-time comparison helpers
+//!
+//! Fingerprints, SAS codes, PINs and MAC tags must not be compared with
+//! `==` on bytes or strings: the early exit of a byte-wise compare tells a
+//! remote attacker how many leading bytes of a guess were right. The types
+//! in this crate implement `PartialEq` through [`ct_eq`], and
+//! [`ConstantTimeEq`] is re-exported for callers that need a
+//! [`Choice`] to combine with other checks.
+//!
+//! Lengths are not treated as secret.
+
+pub use subtle::{Choice, ConstantTimeEq};
+
+/// `a == b` without data-dependent branches on the contents
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// [`ct_eq`] for values with a byte representation of the same fixed length
+pub fn ct_eq_array<const N: usize>(a: &[u8; N], b: &[u8; N]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ct_eq_matches_eq() {
+        assert!(ct_eq(b"abc", b"abc"));
+        assert!(!ct_eq(b"abc", b"abd"));
+        assert!(!ct_eq(b"abc", b"ab"));
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq_array(&[7u8; 32], &[7u8; 32]));
+        assert!(!ct_eq_array(&[7u8; 32], &[8u8; 32]));
+    }
+}
@@ -0,0 +1,202 @@
+//! Device IDs people can read out loud
+//!
+//! The device [`Fingerprint`] written the way Syncthing writes its device
+//! IDs: unpadded base32 (`A`-`Z`, `2`-`7`), cut into four runs of 13
+//! characters that each get a Luhn mod 32 check character, then grouped by
+//! seven:
+//!
+//! ```text
+//! P56IOI7-MZJNU2Y-IQGDREY-DM2MGTI-MGL3BXN-PQ6W5BM-TBBZ4TJ-XZWICQ2
+//! ```
+//!
+//! Parsing is strict. Case, dashes and spaces are free, but `0`, `1`, `8`
+//! and anything else outside the alphabet are refused instead of guessed at,
+//! and every check character must match, so a mistyped or misheard ID is an
+//! error rather than some other device.
+
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+
+use ed25519_dalek::VerifyingKey;
+
+use crate::identity::{Fingerprint, FINGERPRINT_LEN};
+
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Base32 characters for a fingerprint
+const DATA_LEN: usize = (FINGERPRINT_LEN * 8).div_ceil(5);
+/// Characters covered by each check character
+const RUN: usize = 13;
+const GROUP: usize = 7;
+/// Base32 and check characters
+pub const DEVICE_ID_CHARS: usize = DATA_LEN + DATA_LEN / RUN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceIdError {
+    /// Number of ID characters, separators aside
+    Length(usize),
+    InvalidCharacter(char),
+    /// Check character of run `n` (0-3) does not match
+    Check(usize),
+    /// Bits past the end of the fingerprint are set
+    NonCanonical,
+}
+
+impl fmt::Display for DeviceIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceIdError::Length(n) => write!(f, "device ID has {n} characters, expected {DEVICE_ID_CHARS}"),
+            DeviceIdError::InvalidCharacter(c) => write!(f, "{c:?} cannot appear in a device ID (only A-Z and 2-7)"),
+            DeviceIdError::Check(run) => write!(f, "device ID check character {} does not match; a character is wrong", run + 1),
+            DeviceIdError::NonCanonical => write!(f, "device ID does not encode a fingerprint"),
+        }
+    }
+}
+
+impl core::error::Error for DeviceIdError {}
+
+/// A [`Fingerprint`] in device ID form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId(Fingerprint);
+
+impl DeviceId {
+    pub fn of(verifying_key: &VerifyingKey) -> Self {
+        Self(Fingerprint::of(verifying_key))
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.0
+    }
+
+    /// Strict parse; see the module docs for what is accepted
+    pub fn parse(s: &str) -> Result<Self, DeviceIdError> {
+        let mut chars = [0u8; DEVICE_ID_CHARS];
+        let mut len = 0;
+        for c in s.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+            let c = c.to_ascii_uppercase();
+            if !c.is_ascii() || !ALPHABET.contains(&(c as u8)) {
+                return Err(DeviceIdError::InvalidCharacter(c));
+            }
+            if len < DEVICE_ID_CHARS {
+                chars[len] = c as u8;
+            }
+            len += 1;
+        }
+        if len != DEVICE_ID_CHARS {
+            return Err(DeviceIdError::Length(len));
+        }
+        let mut data = [0u8; DATA_LEN];
+        for (run, chunk) in chars.chunks(RUN + 1).enumerate() {
+            if luhn32(&chunk[..RUN]) != chunk[RUN] {
+                return Err(DeviceIdError::Check(run));
+            }
+            data[run * RUN..(run + 1) * RUN].copy_from_slice(&chunk[..RUN]);
+        }
+        Ok(Self(Fingerprint::from_bytes(base32_decode(&data)?)))
+    }
+}
+
+impl From<Fingerprint> for DeviceId {
+    fn from(fingerprint: Fingerprint) -> Self {
+        Self(fingerprint)
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = DeviceIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let data = base32_encode(self.0.as_bytes());
+        let mut chars = String::with_capacity(DEVICE_ID_CHARS);
+        for run in data.chunks(RUN) {
+            chars.extend(run.iter().map(|&b| b as char));
+            chars.push(luhn32(run) as char);
+        }
+        for (i, group) in chars.as_bytes().chunks(GROUP).enumerate() {
+            if i > 0 {
+                f.write_str("-")?;
+            }
+            f.write_str(core::str::from_utf8(group).expect("base32 is ascii"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Luhn mod 32 check character over base32 characters, as Syncthing computes it
+/// (weights start at 1 from the left)
+fn luhn32(run: &[u8]) -> u8 {
+    let mut sum = 0;
+    for (i, c) in run.iter().enumerate() {
+        let value = ALPHABET.iter().position(|a| a == c).expect("checked against the alphabet");
+        let addend = value * if i % 2 == 0 { 1 } else { 2 };
+        sum += addend / 32 + addend % 32;
+    }
+    ALPHABET[(32 - sum % 32) % 32]
+}
+
+fn base32_encode(bytes: &[u8; FINGERPRINT_LEN]) -> [u8; DATA_LEN] {
+    let mut out = [0u8; DATA_LEN];
+    let (mut buffer, mut bits, mut n) = (0u16, 0, 0);
+    for &b in bytes {
+        buffer = (buffer << 8) | u16::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out[n] = ALPHABET[usize::from((buffer >> bits) & 31)];
+            n += 1;
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    out[n] = ALPHABET[usize::from((buffer << (5 - bits)) & 31)];
+    out
+}
+
+fn base32_decode(chars: &[u8; DATA_LEN]) -> Result<[u8; FINGERPRINT_LEN], DeviceIdError> {
+    let mut out = [0u8; FINGERPRINT_LEN];
+    let (mut buffer, mut bits, mut n) = (0u16, 0, 0);
+    for c in chars {
+        let value = ALPHABET.iter().position(|a| a == c).expect("checked against the alphabet") as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out[n] = (buffer >> bits) as u8;
+            n += 1;
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if buffer != 0 {
+        return Err(DeviceIdError::NonCanonical);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_strictly() {
+        // from Syncthing's own tests, so the check characters agree with it
+        let syncthing = "P56IOI7-MZJNU2Y-IQGDREY-DM2MGTI-MGL3BXN-PQ6W5BM-TBBZ4TJ-XZWICQ2";
+        let id = DeviceId::parse(syncthing).unwrap();
+        assert_eq!(id.to_string(), syncthing);
+        assert_eq!(DeviceId::parse(&syncthing.to_lowercase().replace('-', " ")), Ok(id));
+        assert_eq!(DeviceId::parse("P56IOI7MZJNU2YIQGDREYDM2MGTIMGL3BXNPQ6W5BMTBBZ4TJXZWICQ2"), Ok(id));
+
+        let key = crate::identity::DeviceIdentity::generate().verifying_key();
+        let ours = DeviceId::of(&key);
+        assert_eq!(ours.to_string().parse::<DeviceId>().unwrap().fingerprint(), &Fingerprint::of(&key));
+
+        assert_eq!(DeviceId::parse(&syncthing.replace("MZJNU2Y", "MZJNU2Z")), Err(DeviceIdError::Check(0)));
+        assert_eq!(DeviceId::parse(&syncthing.replace("MZJNU2Y", "ZMJNU2Y")), Err(DeviceIdError::Check(0)));
+        assert_eq!(DeviceId::parse(&syncthing.replace('O', "0")), Err(DeviceIdError::InvalidCharacter('0')));
+        assert_eq!(DeviceId::parse(&syncthing[..62]), Err(DeviceIdError::Length(55)));
+    }
+}
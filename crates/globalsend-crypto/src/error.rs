@@ -0,0 +1,34 @@
+//! Error type shared by the core primitives
+//!
+//! The AEAD crates report every failure as the opaque `aead::Error`, and HKDF
+//! length errors used to be `expect`ed away. [`CryptoError`] says which step
+//! failed; protocol modules wrap it in their own error enums.
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CryptoError {
+    /// HKDF rejected the requested output length
+    #[error("key derivation failed")]
+    KeyDerivation,
+    /// Plaintext too long for the AEAD
+    #[error("encryption failed")]
+    Encrypt,
+    /// Authentication tag mismatch: tampered data, wrong key, nonce or AAD
+    #[error("decryption failed: authentication tag mismatch")]
+    Decrypt,
+    #[error("invalid key length: expected {expected} bytes, got {actual}")]
+    InvalidKeyLength { expected: usize, actual: usize },
+    /// Message counter space of a key is used up; derive a new key
+    #[error("nonce sequence exhausted")]
+    NonceExhausted,
+    /// Hardware key backend failed or is not present
+    #[error("device key unavailable")]
+    KeyUnavailable,
+}
+
+impl From<hkdf::InvalidLength> for CryptoError {
+    fn from(_: hkdf::InvalidLength) -> Self {
+        CryptoError::KeyDerivation
+    }
+}
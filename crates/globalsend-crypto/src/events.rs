@@ -0,0 +1,134 @@
+//! Hooks for a security audit log
+//!
+//! The crypto layer reports key lifecycle and failure events to a
+//! [`CryptoEvents`] sink installed once at startup with [`set_sink`]. The
+//! daemon turns them into its tamper-evident log; this crate does not depend
+//! on any logging framework and emits nothing when no sink is installed.
+//!
+//! Events never carry secret material: only public keys, fingerprints and
+//! counters. Sinks run inline on the thread doing the crypto, so they should
+//! queue and return quickly.
+//!
+//! Installing a sink needs `std`; in `no_std` builds the hooks compile to
+//! nothing.
+
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use core::fmt;
+
+use x25519_dalek::PublicKey as XPublicKey;
+
+use crate::identity::Fingerprint;
+
+/// Which long-term key was generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// X25519 device exchange key
+    Exchange,
+    /// Ed25519 identity key
+    Identity,
+}
+
+/// Which half of a session ratcheted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// Receiver of audit events; every method defaults to doing nothing
+pub trait CryptoEvents: Send + Sync {
+    /// A long-term key was generated; `public` is its public half
+    fn key_generated(&self, _kind: KeyKind, _public: &[u8; 32]) {}
+
+    /// A Noise handshake finished with the peer's static key
+    fn handshake_completed(&self, _remote_static: &XPublicKey, _handshake_hash: &[u8; 32]) {}
+
+    /// A peer's certificate chain checked out against its pinned fingerprint
+    fn peer_verified(&self, _fingerprint: &Fingerprint) {}
+
+    /// An AEAD tag or handshake MAC did not verify
+    fn decryption_failed(&self) {}
+
+    /// A session direction moved to a new key epoch
+    fn rekeyed(&self, _direction: Direction, _epoch: u64) {}
+}
+
+/// [`set_sink`] was already called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkAlreadySet;
+
+impl fmt::Display for SinkAlreadySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "crypto event sink already installed")
+    }
+}
+
+impl core::error::Error for SinkAlreadySet {}
+
+#[cfg(feature = "std")]
+static SINK: std::sync::OnceLock<Box<dyn CryptoEvents>> = std::sync::OnceLock::new();
+
+/// Install the process-wide sink; can be done once
+#[cfg(feature = "std")]
+pub fn set_sink(sink: Box<dyn CryptoEvents>) -> Result<(), SinkAlreadySet> {
+    SINK.set(sink).map_err(|_| SinkAlreadySet)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn emit(f: impl FnOnce(&dyn CryptoEvents)) {
+    if let Some(sink) = SINK.get() {
+        f(sink.as_ref());
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn emit(_f: impl FnOnce(&dyn CryptoEvents)) {}
+
+/// Report a decryption failure and return the error to propagate
+pub(crate) fn decrypt_failed<E>(error: E) -> E {
+    emit(|s| s.decryption_failed());
+    error
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::{aead_decrypt, derive_aead, DeviceKey};
+
+    #[derive(Default)]
+    struct Counts {
+        keys: AtomicUsize,
+        failures: AtomicUsize,
+    }
+
+    struct Recorder(Arc<Counts>);
+
+    impl CryptoEvents for Recorder {
+        fn key_generated(&self, _kind: KeyKind, _public: &[u8; 32]) {
+            self.0.keys.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn decryption_failed(&self) {
+            self.0.failures.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn sink_sees_key_generation_and_failures() {
+        let counts = Arc::new(Counts::default());
+        set_sink(Box::new(Recorder(counts.clone()))).unwrap();
+        assert_eq!(set_sink(Box::new(Recorder(counts.clone()))), Err(SinkAlreadySet));
+
+        // other tests run concurrently and report into the same sink, so only check growth
+        let (keys, failures) = (counts.keys.load(Ordering::SeqCst), counts.failures.load(Ordering::SeqCst));
+        DeviceKey::generate();
+        let (key, nonces) = derive_aead(&[1; 32]).unwrap();
+        assert!(aead_decrypt(key.as_key(), &nonces, 0, b"", &[0; 32]).is_err());
+        assert!(counts.keys.load(Ordering::SeqCst) > keys);
+        assert!(counts.failures.load(Ordering::SeqCst) > failures);
+    }
+}
@@ -0,0 +1,135 @@
+//! Tear down connections that keep failing to decrypt
+//!
+//! A tag failure on an established session means tampering, a confused
+//! peer or someone guessing keys. One is worth logging; a burst is an
+//! attack. [`DecryptGuard`] counts failures per peer (a fingerprint, an
+//! address, a session id: any `Ord` key) in a sliding window and answers
+//! [`GuardDecision::TearDown`] once a peer reaches the limit. The caller
+//! closes the connection and can refuse new ones from that peer while
+//! [`DecryptGuard::is_blocked`] holds.
+//!
+//! The same guard also throttles online guessing of PIN-derived keys:
+//! record a failed [`pairing`](crate::pairing) confirmation as a failure.
+//!
+//! Time is passed in as Unix seconds, like elsewhere in this crate. At most
+//! [`MAX_TRACKED_PEERS`] peers are tracked; beyond that the peer whose
+//! latest failure is oldest is forgotten first.
+
+use alloc::collections::{BTreeMap, VecDeque};
+
+pub const DEFAULT_FAILURE_LIMIT: u32 = 5;
+pub const DEFAULT_WINDOW_SECS: u64 = 60;
+pub const MAX_TRACKED_PEERS: usize = 4096;
+
+/// What to do with the connection after a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardDecision {
+    Continue,
+    /// The peer reached the failure limit within the window
+    TearDown,
+}
+
+/// Sliding-window counter of decryption failures per peer
+#[derive(Debug)]
+pub struct DecryptGuard<K: Ord> {
+    limit: u32,
+    window: u64,
+    /// Failure times per peer, oldest first, at most `limit` entries
+    failures: BTreeMap<K, VecDeque<u64>>,
+}
+
+impl<K: Ord + Clone> Default for DecryptGuard<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_LIMIT, DEFAULT_WINDOW_SECS)
+    }
+}
+
+impl<K: Ord + Clone> DecryptGuard<K> {
+    /// Tear down after `limit` failures within `window_secs`
+    pub fn new(limit: u32, window_secs: u64) -> Self {
+        Self { limit: limit.max(1), window: window_secs, failures: BTreeMap::new() }
+    }
+
+    /// Count a failed tag (or handshake MAC, or pairing confirmation) from `peer`
+    pub fn record_failure(&mut self, peer: &K, now: u64) -> GuardDecision {
+        if !self.failures.contains_key(peer) && self.failures.len() >= MAX_TRACKED_PEERS {
+            self.evict(now);
+        }
+        let times = self.failures.entry(peer.clone()).or_default();
+        times.push_back(now);
+        if times.len() > self.limit as usize {
+            times.pop_front();
+        }
+        if self.is_blocked(peer, now) {
+            GuardDecision::TearDown
+        } else {
+            GuardDecision::Continue
+        }
+    }
+
+    /// Has `peer` reached the limit within the window ending at `now`?
+    pub fn is_blocked(&self, peer: &K, now: u64) -> bool {
+        self.failures.get(peer).is_some_and(|times| {
+            times.len() >= self.limit as usize && times.front().is_some_and(|&first| now.saturating_sub(first) < self.window)
+        })
+    }
+
+    /// Failures from `peer` still inside the window
+    pub fn failures(&self, peer: &K, now: u64) -> u32 {
+        self.failures
+            .get(peer)
+            .map_or(0, |times| times.iter().filter(|&&t| now.saturating_sub(t) < self.window).count() as u32)
+    }
+
+    /// Clear the record for `peer`, e.g. after it re-paired successfully
+    pub fn forget(&mut self, peer: &K) {
+        self.failures.remove(peer);
+    }
+
+    /// Number of peers with failures on record
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn evict(&mut self, now: u64) {
+        let window = self.window;
+        self.failures.retain(|_, times| times.back().is_some_and(|&last| now.saturating_sub(last) < window));
+        if self.failures.len() >= MAX_TRACKED_PEERS {
+            let stalest = self.failures.iter().min_by_key(|(_, times)| times.back().copied()).map(|(k, _)| k.clone());
+            if let Some(k) = stalest {
+                self.failures.remove(&k);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tears_down_after_burst_and_recovers() {
+        let mut guard = DecryptGuard::new(3, 60);
+        assert_eq!(guard.record_failure(&"mallory", 100), GuardDecision::Continue);
+        assert_eq!(guard.record_failure(&"mallory", 110), GuardDecision::Continue);
+        assert_eq!(guard.record_failure(&"alice", 110), GuardDecision::Continue);
+        assert_eq!(guard.record_failure(&"mallory", 120), GuardDecision::TearDown);
+        assert!(guard.is_blocked(&"mallory", 150));
+        assert!(!guard.is_blocked(&"alice", 150));
+
+        // the window slides: the first failure expires at 160
+        assert!(!guard.is_blocked(&"mallory", 160));
+        assert_eq!(guard.failures(&"mallory", 160), 2);
+        // spread-out failures never trip it
+        assert_eq!(guard.record_failure(&"alice", 200), GuardDecision::Continue);
+        assert_eq!(guard.record_failure(&"alice", 300), GuardDecision::Continue);
+
+        guard.forget(&"mallory");
+        assert_eq!(guard.failures(&"mallory", 160), 0);
+        assert_eq!(guard.len(), 1);
+    }
+}
@@ -0,0 +1,546 @@
+//! Noise XX handshake for authenticated session establishment
+//!
+//! Implements `Noise_XX_25519_ChaChaPoly_SHA256` over the device X25519 keys:
+//!
+//! ```text
+//! -> e
+//! <- e, ee, s, es
+//! -> s, se
+//! ```
+//!
+//! Both sides end up with the peer's static public key, a handshake hash that
+//! binds the whole transcript, and a pair of directional transport keys that
+//! plug into [`aead_encrypt`](crate::aead_encrypt) / [`aead_decrypt`](crate::aead_decrypt).
+//!
+//! Whatever the two sides negotiate in the payloads, [`Handshake::bind_parameters`]
+//! mixes the outcome (protocol version and cipher suite) into the transcript
+//! before any key is derived, so sides that came to different conclusions,
+//! for instance because one was shown a stripped-down offer, end up with
+//! different keys instead of a weaker session.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::events;
+use crate::kdf::KdfContext;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::session::SessionKeys;
+use crate::suite::CipherSuite;
+use crate::keyprovider::KeyProvider;
+use crate::{CryptoError, EphemeralKey, NonceSequence, PROTOCOL_VERSION};
+
+/// Full Noise protocol name; exactly 32 bytes so it is used as the initial hash directly
+const PROTOCOL_NAME: &[u8; 32] = b"Noise_XX_25519_ChaChaPoly_SHA256";
+/// Maximum Noise message size
+pub const MAX_MESSAGE_LEN: usize = 65535;
+const DH_LEN: usize = 32;
+const HASH_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+/// HKDF info for [`Handshake::into_session`]
+const SESSION_INFO: &[u8] = b"globalsend noise session v1";
+/// Prefix of what [`Handshake::bind_parameters`] mixes into the transcript
+const PARAMETERS_LABEL: &[u8] = b"globalsend negotiated parameters v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// `write_message`/`read_message` called when it is the other side's turn
+    OutOfOrder,
+    /// Handshake already completed
+    Finished,
+    /// `into_transport` called before all three messages were exchanged
+    NotFinished,
+    /// Message is shorter than the pattern requires or exceeds `MAX_MESSAGE_LEN`
+    BadLength,
+    /// AEAD tag check failed on an encrypted handshake field
+    Decrypt,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::OutOfOrder => write!(f, "handshake message out of order"),
+            HandshakeError::Finished => write!(f, "handshake already finished"),
+            HandshakeError::NotFinished => write!(f, "handshake not finished"),
+            HandshakeError::BadLength => write!(f, "invalid handshake message length"),
+            HandshakeError::Decrypt => write!(f, "handshake decryption failed"),
+            HandshakeError::Crypto(e) => write!(f, "handshake: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for HandshakeError {}
+
+impl From<CryptoError> for HandshakeError {
+    fn from(e: CryptoError) -> Self {
+        HandshakeError::Crypto(e)
+    }
+}
+
+/// Per-session keys produced by a completed handshake
+pub struct TransportKeys {
+    pub send_key: SecretKey,
+    pub send_nonce: NonceSequence,
+    pub recv_key: SecretKey,
+    pub recv_nonce: NonceSequence,
+    /// Transcript hash; identical on both sides, suitable for SAS display
+    pub handshake_hash: [u8; HASH_LEN],
+    /// Authenticated static key of the peer
+    pub remote_static: XPublicKey,
+}
+
+/// Noise CipherState (ChaChaPoly with 64-bit little-endian counter nonce)
+struct CipherState {
+    key: Option<SecretKey>,
+    n: u64,
+}
+
+impl CipherState {
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        match &self.key {
+            None => Ok(plaintext.to_vec()),
+            Some(k) => {
+                let cipher = ChaCha20Poly1305::new(k.as_key());
+                let ct = cipher
+                    .encrypt(&noise_nonce(self.n), Payload { msg: plaintext, aad: ad })
+                    .map_err(|_| CryptoError::Encrypt)?;
+                self.n += 1;
+                Ok(ct)
+            }
+        }
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        match &self.key {
+            None => Ok(ciphertext.to_vec()),
+            Some(k) => {
+                let cipher = ChaCha20Poly1305::new(k.as_key());
+                let pt = cipher
+                    .decrypt(&noise_nonce(self.n), Payload { msg: ciphertext, aad: ad })
+                    .map_err(|_| events::decrypt_failed(HandshakeError::Decrypt))?;
+                self.n += 1;
+                Ok(pt)
+            }
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        if self.key.is_some() { TAG_LEN } else { 0 }
+    }
+}
+
+fn noise_nonce(n: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&n.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+/// Noise HKDF: HKDF-SHA256 with the chaining key as salt and empty info
+fn noise_hkdf(ck: &SecretBytes<HASH_LEN>, ikm: &[u8]) -> Result<(SecretBytes<HASH_LEN>, SecretBytes<HASH_LEN>), CryptoError> {
+    let hk = Hkdf::<Sha256>::new(Some(ck.as_bytes()), ikm);
+    let mut okm = SecretBytes::<{ 2 * HASH_LEN }>::zeroed();
+    hk.expand(&[], okm.as_mut_bytes())?;
+    let (a, b) = okm.as_bytes().split_at(HASH_LEN);
+    Ok((SecretBytes::from_slice(a), SecretBytes::from_slice(b)))
+}
+
+/// Noise SymmetricState
+struct SymmetricState {
+    ck: SecretBytes<HASH_LEN>,
+    h: [u8; HASH_LEN],
+    cipher: CipherState,
+}
+
+impl SymmetricState {
+    fn new(prologue: &[u8]) -> Self {
+        let mut s = Self {
+            ck: SecretBytes::new(*PROTOCOL_NAME),
+            h: *PROTOCOL_NAME,
+            cipher: CipherState { key: None, n: 0 },
+        };
+        s.mix_hash(prologue);
+        s
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(self.h);
+        hasher.update(data);
+        self.h = hasher.finalize().into();
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) -> Result<(), CryptoError> {
+        let (ck, k) = noise_hkdf(&self.ck, ikm)?;
+        self.ck = ck;
+        self.cipher = CipherState { key: Some(k), n: 0 };
+        Ok(())
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let ct = self.cipher.encrypt(&self.h, plaintext)?;
+        self.mix_hash(&ct);
+        Ok(ct)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        let pt = self.cipher.decrypt(&self.h, ciphertext)?;
+        self.mix_hash(ciphertext);
+        Ok(pt)
+    }
+
+    fn split(&self) -> Result<(SecretKey, SecretKey), CryptoError> {
+        noise_hkdf(&self.ck, &[])
+    }
+}
+
+/// In-progress XX handshake for one side of a session
+pub struct Handshake<'a> {
+    role: Role,
+    s: &'a dyn KeyProvider,
+    e: Option<EphemeralKey>,
+    re: Option<XPublicKey>,
+    rs: Option<XPublicKey>,
+    state: SymmetricState,
+    /// Number of handshake messages processed so far (0..=3)
+    step: usize,
+}
+
+impl<'a> Handshake<'a> {
+    /// Start a handshake as the connecting side
+    pub fn initiator(s: &'a dyn KeyProvider, prologue: &[u8]) -> Self {
+        Self::new(Role::Initiator, s, prologue)
+    }
+
+    /// Start a handshake as the accepting side
+    pub fn responder(s: &'a dyn KeyProvider, prologue: &[u8]) -> Self {
+        Self::new(Role::Responder, s, prologue)
+    }
+
+    fn new(role: Role, s: &'a dyn KeyProvider, prologue: &[u8]) -> Self {
+        Self {
+            role,
+            s,
+            e: None,
+            re: None,
+            rs: None,
+            state: SymmetricState::new(prologue),
+            step: 0,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// True once all three messages have been exchanged
+    pub fn is_finished(&self) -> bool {
+        self.step == 3
+    }
+
+    /// Peer static key, available after it has been received (and authenticated)
+    pub fn remote_static(&self) -> Option<XPublicKey> {
+        self.rs
+    }
+
+    /// Current transcript hash
+    pub fn handshake_hash(&self) -> [u8; HASH_LEN] {
+        self.state.h
+    }
+
+    /// Bind the negotiated protocol `version` and `suite` into the transcript,
+    /// and so into [`handshake_hash`](Self::handshake_hash) and the keys
+    ///
+    /// Both sides call it after the last message and before deriving keys.
+    pub fn bind_parameters(&mut self, version: u16, suite: CipherSuite) -> Result<(), HandshakeError> {
+        if !self.is_finished() {
+            return Err(HandshakeError::NotFinished);
+        }
+        self.state.mix_hash(&[PARAMETERS_LABEL, &version.to_be_bytes(), &[suite.id()]].concat());
+        Ok(())
+    }
+
+    fn is_my_turn(&self) -> bool {
+        // initiator writes messages 0 and 2, responder writes message 1
+        self.step.is_multiple_of(2) == (self.role == Role::Initiator)
+    }
+
+    fn ephemeral(&self) -> &EphemeralKey {
+        self.e.as_ref().expect("ephemeral key generated")
+    }
+
+    /// Produce the next handshake message carrying `payload`
+    #[cfg(feature = "std")]
+    pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        self.write_message_with_rng(&mut OsRng, payload)
+    }
+
+    /// [`write_message`](Self::write_message) drawing the ephemeral key from `rng`
+    pub fn write_message_with_rng<R: CryptoRngCore>(&mut self, rng: &mut R, payload: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        if self.is_finished() {
+            return Err(HandshakeError::Finished);
+        }
+        if !self.is_my_turn() {
+            return Err(HandshakeError::OutOfOrder);
+        }
+        let mut out = Vec::new();
+        match self.step {
+            // -> e
+            0 => {
+                self.write_e(rng, &mut out);
+            }
+            // <- e, ee, s, es
+            1 => {
+                self.write_e(rng, &mut out);
+                let re = self.re.expect("remote ephemeral");
+                let ee = self.ephemeral().ecdh(&re);
+                self.state.mix_key(ee.as_bytes())?;
+                self.write_s(&mut out)?;
+                let es = self.s.ecdh(&re)?;
+                self.state.mix_key(es.as_bytes())?;
+            }
+            // -> s, se
+            _ => {
+                self.write_s(&mut out)?;
+                let re = self.re.expect("remote ephemeral");
+                let se = self.s.ecdh(&re)?;
+                self.state.mix_key(se.as_bytes())?;
+            }
+        }
+        out.extend_from_slice(&self.state.encrypt_and_hash(payload)?);
+        if out.len() > MAX_MESSAGE_LEN {
+            return Err(HandshakeError::BadLength);
+        }
+        self.step += 1;
+        Ok(out)
+    }
+
+    /// Consume the peer's next handshake message, returning its payload
+    pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        if self.is_finished() {
+            return Err(HandshakeError::Finished);
+        }
+        if self.is_my_turn() {
+            return Err(HandshakeError::OutOfOrder);
+        }
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(HandshakeError::BadLength);
+        }
+        let mut rest = message;
+        match self.step {
+            // -> e
+            0 => {
+                rest = self.read_e(rest)?;
+            }
+            // <- e, ee, s, es
+            1 => {
+                rest = self.read_e(rest)?;
+                let re = self.re.expect("remote ephemeral");
+                let ee = self.ephemeral().ecdh(&re);
+                self.state.mix_key(ee.as_bytes())?;
+                rest = self.read_s(rest)?;
+                let rs = self.rs.expect("remote static");
+                let es = self.ephemeral().ecdh(&rs);
+                self.state.mix_key(es.as_bytes())?;
+            }
+            // -> s, se
+            _ => {
+                rest = self.read_s(rest)?;
+                let rs = self.rs.expect("remote static");
+                let se = self.ephemeral().ecdh(&rs);
+                self.state.mix_key(se.as_bytes())?;
+            }
+        }
+        if rest.len() < self.state.cipher.overhead() {
+            return Err(HandshakeError::BadLength);
+        }
+        let payload = self.state.decrypt_and_hash(rest)?;
+        self.step += 1;
+        Ok(payload)
+    }
+
+    fn write_e<R: CryptoRngCore>(&mut self, rng: &mut R, out: &mut Vec<u8>) {
+        let e = EphemeralKey::generate_with_rng(rng);
+        let e_pub = e.public();
+        out.extend_from_slice(e_pub.as_bytes());
+        self.state.mix_hash(e_pub.as_bytes());
+        self.e = Some(e);
+    }
+
+    fn write_s(&mut self, out: &mut Vec<u8>) -> Result<(), CryptoError> {
+        let s_pub = self.s.public();
+        let ct = self.state.encrypt_and_hash(s_pub.as_bytes())?;
+        out.extend_from_slice(&ct);
+        Ok(())
+    }
+
+    fn read_e<'m>(&mut self, message: &'m [u8]) -> Result<&'m [u8], HandshakeError> {
+        if message.len() < DH_LEN {
+            return Err(HandshakeError::BadLength);
+        }
+        let (e, rest) = message.split_at(DH_LEN);
+        let mut bytes = [0u8; DH_LEN];
+        bytes.copy_from_slice(e);
+        self.state.mix_hash(&bytes);
+        self.re = Some(XPublicKey::from(bytes));
+        Ok(rest)
+    }
+
+    fn read_s<'m>(&mut self, message: &'m [u8]) -> Result<&'m [u8], HandshakeError> {
+        let len = DH_LEN + self.state.cipher.overhead();
+        if message.len() < len {
+            return Err(HandshakeError::BadLength);
+        }
+        let (s, rest) = message.split_at(len);
+        let pt = self.state.decrypt_and_hash(s)?;
+        let mut bytes = [0u8; DH_LEN];
+        bytes.copy_from_slice(&pt);
+        self.rs = Some(XPublicKey::from(bytes));
+        Ok(rest)
+    }
+
+    /// Finish the handshake and derive directional transport keys
+    pub fn into_transport(self) -> Result<TransportKeys, HandshakeError> {
+        if !self.is_finished() {
+            return Err(HandshakeError::NotFinished);
+        }
+        let (k1, k2) = self.state.split()?;
+        let remote_static = self.rs.expect("remote static");
+        let handshake_hash = self.state.h;
+        events::emit(|s| s.handshake_completed(&remote_static, &handshake_hash));
+        let ctx = KdfContext::new(PROTOCOL_VERSION)
+            .cipher_suite(core::str::from_utf8(PROTOCOL_NAME).expect("ascii protocol name"))
+            .public_keys(&self.s.public(), &remote_static)
+            .transcript(&handshake_hash);
+        let (i2r_key, i2r_nonce) = ctx.derive_aead(k1.as_bytes())?;
+        let (r2i_key, r2i_nonce) = ctx.derive_aead(k2.as_bytes())?;
+        Ok(match self.role {
+            Role::Initiator => TransportKeys {
+                send_key: i2r_key,
+                send_nonce: i2r_nonce,
+                recv_key: r2i_key,
+                recv_nonce: r2i_nonce,
+                handshake_hash,
+                remote_static,
+            },
+            Role::Responder => TransportKeys {
+                send_key: r2i_key,
+                send_nonce: r2i_nonce,
+                recv_key: i2r_key,
+                recv_nonce: i2r_nonce,
+                handshake_hash,
+                remote_static,
+            },
+        })
+    }
+
+    /// Finish the handshake and derive [`SessionKeys`] (rekeying, file keys, exporter)
+    /// instead of bare [`TransportKeys`].
+    ///
+    /// Read [`remote_static`](Self::remote_static) and
+    /// [`handshake_hash`](Self::handshake_hash) first.
+    pub fn into_session(self) -> Result<SessionKeys, HandshakeError> {
+        if !self.is_finished() {
+            return Err(HandshakeError::NotFinished);
+        }
+        let (k1, k2) = self.state.split()?;
+        let remote_static = self.rs.expect("remote static");
+        let handshake_hash = self.state.h;
+        events::emit(|s| s.handshake_completed(&remote_static, &handshake_hash));
+        let mut ikm = Zeroizing::new([0u8; 2 * HASH_LEN]);
+        ikm[..HASH_LEN].copy_from_slice(k1.as_bytes());
+        ikm[HASH_LEN..].copy_from_slice(k2.as_bytes());
+        let hk = Hkdf::<Sha256>::new(Some(&handshake_hash), ikm.as_ref());
+        Ok(SessionKeys::expand(&hk, SESSION_INFO, self.role == Role::Initiator)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aead_decrypt, aead_encrypt, DeviceKey};
+
+    #[test]
+    fn xx_handshake_roundtrip() {
+        let a = DeviceKey::generate();
+        let b = DeviceKey::generate();
+        let mut init = Handshake::initiator(&a, b"globalsend");
+        let mut resp = Handshake::responder(&b, b"globalsend");
+
+        let m1 = init.write_message(b"").unwrap();
+        resp.read_message(&m1).unwrap();
+        let m2 = resp.write_message(b"hello from b").unwrap();
+        assert_eq!(init.read_message(&m2).unwrap(), b"hello from b");
+        let m3 = init.write_message(b"hello from a").unwrap();
+        assert_eq!(resp.read_message(&m3).unwrap(), b"hello from a");
+
+        let mut ti = init.into_transport().unwrap();
+        let tr = resp.into_transport().unwrap();
+        assert_eq!(ti.handshake_hash, tr.handshake_hash);
+        assert_eq!(ti.remote_static.as_bytes(), b.public().as_bytes());
+        assert_eq!(tr.remote_static.as_bytes(), a.public().as_bytes());
+
+        let ct = aead_encrypt(ti.send_key.as_key(), ti.send_nonce.next().unwrap(), b"", b"data").unwrap();
+        let pt = aead_decrypt(tr.recv_key.as_key(), &tr.recv_nonce, 0, b"", &ct).unwrap();
+        assert_eq!(pt, b"data");
+        assert_ne!(ti.send_key.as_bytes(), ti.recv_key.as_bytes());
+    }
+
+    #[test]
+    fn negotiated_parameters_bind_the_keys() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let finished = || {
+            let mut init = Handshake::initiator(&a, b"");
+            let mut resp = Handshake::responder(&b, b"");
+            assert_eq!(init.bind_parameters(9, CipherSuite::Aes256Gcm), Err(HandshakeError::NotFinished));
+            resp.read_message(&init.write_message(b"").unwrap()).unwrap();
+            init.read_message(&resp.write_message(b"").unwrap()).unwrap();
+            resp.read_message(&init.write_message(b"").unwrap()).unwrap();
+            (init, resp)
+        };
+        let (mut init, mut resp) = finished();
+        init.bind_parameters(9, CipherSuite::Aes256Gcm).unwrap();
+        resp.bind_parameters(9, CipherSuite::Aes256Gcm).unwrap();
+        assert_eq!(init.handshake_hash(), resp.handshake_hash());
+        assert_eq!(init.into_session().unwrap().session_id(), resp.into_session().unwrap().session_id());
+
+        for (version, suite) in [(8, CipherSuite::Aes256Gcm), (9, CipherSuite::XChaCha20Poly1305)] {
+            let (mut init, mut resp) = finished();
+            init.bind_parameters(9, CipherSuite::Aes256Gcm).unwrap();
+            resp.bind_parameters(version, suite).unwrap();
+            assert_ne!(init.handshake_hash(), resp.handshake_hash());
+            assert_ne!(init.into_session().unwrap().session_id(), resp.into_session().unwrap().session_id());
+        }
+    }
+
+    #[test]
+    fn tampered_message_rejected() {
+        let a = DeviceKey::generate();
+        let b = DeviceKey::generate();
+        let mut init = Handshake::initiator(&a, b"");
+        let mut resp = Handshake::responder(&b, b"");
+
+        let m1 = init.write_message(b"").unwrap();
+        resp.read_message(&m1).unwrap();
+        let mut m2 = resp.write_message(b"").unwrap();
+        let last = m2.len() - 1;
+        m2[last] ^= 1;
+        assert_eq!(init.read_message(&m2), Err(HandshakeError::Decrypt));
+        assert_eq!(resp.write_message(b""), Err(HandshakeError::OutOfOrder));
+    }
+}
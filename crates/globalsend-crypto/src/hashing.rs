@@ -0,0 +1,297 @@
+//! Content hashing for integrity checks independent of the AEAD layer
+//!
+//! The AEAD proves each chunk came from the peer, but not that the file on
+//! disk ended up intact: a resumed transfer stitches together bytes written in
+//! several sessions, and storage can corrupt data after the fact. Senders
+//! publish a BLAKE3 digest (SHA-256 where a peer needs it for interop) and
+//! receivers re-hash the file with [`verify_file`].
+//!
+//! Digests print as `<algorithm>:<hex>`, e.g. `blake3:af13...`.
+
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use sha2::{Digest as _, Sha256};
+
+use crate::ct::ct_eq_array;
+#[cfg(feature = "std")]
+use crate::stream::read_full;
+
+pub const HASH_LEN: usize = 32;
+/// Chunk size for [`hash_chunks`]; matches the STREAM chunk so chunk hashes line up with frames
+pub const HASH_CHUNK_LEN: usize = 64 * 1024;
+#[cfg(feature = "std")]
+const _: () = assert!(HASH_CHUNK_LEN == crate::stream::STREAM_CHUNK_LEN);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// Digest tagged with the algorithm that produced it
+#[derive(Clone, Copy, Eq)]
+pub struct ContentHash {
+    algorithm: HashAlgorithm,
+    bytes: [u8; HASH_LEN],
+}
+
+impl ContentHash {
+    pub fn new(algorithm: HashAlgorithm, bytes: [u8; HASH_LEN]) -> Self {
+        Self { algorithm, bytes }
+    }
+
+    /// Hash an in-memory buffer
+    pub fn of(algorithm: HashAlgorithm, data: &[u8]) -> Self {
+        let mut h = Hasher::new(algorithm);
+        h.update(data);
+        h.finalize()
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_LEN] {
+        &self.bytes
+    }
+
+    /// Parse the `<algorithm>:<hex>` form produced by `Display`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (name, hex) = s.split_once(':')?;
+        let algorithm = HashAlgorithm::from_name(name)?;
+        if hex.len() != 2 * HASH_LEN || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut bytes = [0u8; HASH_LEN];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self { algorithm, bytes })
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm.name())?;
+        self.bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl PartialEq for ContentHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.algorithm == other.algorithm && ct_eq_array(&self.bytes, &other.bytes)
+    }
+}
+
+impl hash::Hash for ContentHash {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        hash::Hash::hash(&self.algorithm, state);
+        hash::Hash::hash(&self.bytes, state);
+    }
+}
+
+impl fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContentHash({self})")
+    }
+}
+
+/// Incremental hasher for either algorithm
+#[derive(Clone)]
+pub enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Hasher::Blake3(_) => HashAlgorithm::Blake3,
+            Hasher::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+            Hasher::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> ContentHash {
+        let algorithm = self.algorithm();
+        let bytes = match self {
+            Hasher::Blake3(h) => *h.finalize().as_bytes(),
+            Hasher::Sha256(h) => h.finalize().into(),
+        };
+        ContentHash { algorithm, bytes }
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum HashError {
+    Mismatch { expected: ContentHash, actual: ContentHash },
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::Mismatch { expected, actual } => write!(f, "content hash mismatch: expected {expected}, got {actual}"),
+            #[cfg(feature = "std")]
+            HashError::Io(e) => write!(f, "hashing io error: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for HashError {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for HashError {
+    fn from(e: io::Error) -> Self {
+        HashError::Io(e)
+    }
+}
+
+/// Hash everything `reader` yields
+#[cfg(feature = "std")]
+pub fn hash_reader<R: Read>(algorithm: HashAlgorithm, mut reader: R) -> io::Result<ContentHash> {
+    let mut h = Hasher::new(algorithm);
+    io::copy(&mut reader, &mut h)?;
+    Ok(h.finalize())
+}
+
+#[cfg(feature = "std")]
+pub fn hash_file(algorithm: HashAlgorithm, path: impl AsRef<Path>) -> io::Result<ContentHash> {
+    hash_reader(algorithm, File::open(path)?)
+}
+
+/// Hash `reader` in `chunk_len` pieces; only the last chunk may be shorter.
+///
+/// An empty input yields no chunks.
+#[cfg(feature = "std")]
+pub fn hash_chunks<R: Read>(algorithm: HashAlgorithm, mut reader: R, chunk_len: usize) -> io::Result<Vec<ContentHash>> {
+    assert!(chunk_len > 0, "chunk_len must be non-zero");
+    let mut buf = vec![0u8; chunk_len];
+    let mut out = Vec::new();
+    loop {
+        let n = read_full(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        out.push(ContentHash::of(algorithm, &buf[..n]));
+        if n < chunk_len {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Check a received chunk against its published hash
+pub fn verify_chunk(data: &[u8], expected: &ContentHash) -> Result<(), HashError> {
+    let actual = ContentHash::of(expected.algorithm, data);
+    if actual != *expected {
+        return Err(HashError::Mismatch { expected: *expected, actual });
+    }
+    Ok(())
+}
+
+/// Re-hash the file at `path` with `expected`'s algorithm and compare
+#[cfg(feature = "std")]
+pub fn verify_file(path: impl AsRef<Path>, expected: &ContentHash) -> Result<(), HashError> {
+    let actual = hash_file(expected.algorithm, path)?;
+    if actual != *expected {
+        return Err(HashError::Mismatch { expected: *expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_digests_and_parse() {
+        let b3 = ContentHash::of(HashAlgorithm::Blake3, b"");
+        assert_eq!(b3.to_string(), "blake3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        let sha = ContentHash::of(HashAlgorithm::Sha256, b"abc");
+        assert_eq!(sha.to_string(), "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(ContentHash::parse(&sha.to_string()), Some(sha));
+        assert_eq!(ContentHash::parse("md5:00"), None);
+    }
+
+    #[test]
+    fn file_and_chunk_verification() {
+        let data: Vec<u8> = (0..HASH_CHUNK_LEN * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("globalsend-hash-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        for alg in [HashAlgorithm::Blake3, HashAlgorithm::Sha256] {
+            let expected = ContentHash::of(alg, &data);
+            assert_eq!(hash_reader(alg, &data[..]).unwrap(), expected);
+            verify_file(&path, &expected).unwrap();
+
+            let chunks = hash_chunks(alg, &data[..], HASH_CHUNK_LEN).unwrap();
+            assert_eq!(chunks.len(), 3);
+            verify_chunk(&data[2 * HASH_CHUNK_LEN..], &chunks[2]).unwrap();
+            assert!(verify_chunk(&data[..HASH_CHUNK_LEN], &chunks[1]).is_err());
+        }
+        assert!(hash_chunks(HashAlgorithm::Blake3, &b""[..], 16).unwrap().is_empty());
+
+        let mut corrupted = data.clone();
+        corrupted[HASH_CHUNK_LEN] ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        let expected = ContentHash::of(HashAlgorithm::Blake3, &data);
+        assert!(matches!(verify_file(&path, &expected), Err(HashError::Mismatch { .. })));
+        let _ = std::fs::remove_file(path);
+    }
+}
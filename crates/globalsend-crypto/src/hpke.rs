@@ -0,0 +1,207 @@
+//! HPKE (RFC 9180) base mode for one-shot sealed payloads
+//!
+//! Used where there is no live session to run a handshake over: offline drop
+//! boxes and messages queued on a relay. The sender needs only the recipient's
+//! X25519 [`DeviceKey`](crate::DeviceKey) public key; the recipient opens with the device key.
+//!
+//! Suite: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20-Poly1305
+//! (`kem_id = 0x0020`, `kdf_id = 0x0001`, `aead_id = 0x0003`). Each call to
+//! [`seal`] uses a fresh ephemeral key and encrypts one message at sequence
+//! number 0, so the output is interoperable with any RFC 9180 implementation.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+use sha2::Sha256;
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::secret::{SecretBytes, SecretKey, SharedSecret};
+use crate::keyprovider::KeyProvider;
+use crate::{CryptoError, EphemeralKey};
+
+/// Length of the encapsulated key (the sender's ephemeral public key)
+pub const ENC_LEN: usize = 32;
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+const AEAD_ID: u16 = 0x0003;
+const MODE_BASE: u8 = 0x00;
+const N_SECRET: usize = 32;
+const N_K: usize = 32;
+const N_N: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HpkeError {
+    /// Recipient key is a low-order point (all-zero DH output)
+    InvalidKey,
+    Seal,
+    Open,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for HpkeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HpkeError::InvalidKey => write!(f, "invalid hpke public key"),
+            HpkeError::Seal => write!(f, "hpke encryption failed"),
+            HpkeError::Open => write!(f, "hpke decryption failed"),
+            HpkeError::Crypto(e) => write!(f, "hpke: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for HpkeError {}
+
+impl From<CryptoError> for HpkeError {
+    fn from(e: CryptoError) -> Self {
+        HpkeError::Crypto(e)
+    }
+}
+
+fn kem_suite_id() -> Vec<u8> {
+    [&b"KEM"[..], &KEM_ID.to_be_bytes()].concat()
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    [&b"HPKE"[..], &KEM_ID.to_be_bytes(), &KDF_ID.to_be_bytes(), &AEAD_ID.to_be_bytes()].concat()
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Hkdf<Sha256> {
+    let labeled_ikm = Zeroizing::new([b"HPKE-v1", suite_id, label, ikm].concat());
+    Hkdf::<Sha256>::new(Some(salt), &labeled_ikm)
+}
+
+fn labeled_expand(prk: &Hkdf<Sha256>, suite_id: &[u8], label: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), CryptoError> {
+    let len = u16::try_from(out.len()).map_err(|_| CryptoError::KeyDerivation)?.to_be_bytes();
+    let labeled_info = [&len[..], b"HPKE-v1", suite_id, label, info].concat();
+    Ok(prk.expand(&labeled_info, out)?)
+}
+
+/// PRK bytes of `labeled_extract`, needed where RFC 9180 hashes into the context
+fn labeled_extract_bytes(suite_id: &[u8], label: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let labeled_ikm = [b"HPKE-v1", suite_id, label, ikm].concat();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(&[]), &labeled_ikm);
+    prk.into()
+}
+
+/// DHKEM `ExtractAndExpand`
+fn kem_shared_secret(dh: &SharedSecret, enc: &[u8; ENC_LEN], pk_r: &XPublicKey) -> Result<SecretBytes<N_SECRET>, HpkeError> {
+    if dh.as_bytes().iter().all(|&b| b == 0) {
+        return Err(HpkeError::InvalidKey);
+    }
+    let suite = kem_suite_id();
+    let kem_context = [&enc[..], pk_r.as_bytes()].concat();
+    let prk = labeled_extract(&suite, &[], b"eae_prk", dh.as_bytes());
+    let mut shared = SecretBytes::zeroed();
+    labeled_expand(&prk, &suite, b"shared_secret", &kem_context, shared.as_mut_bytes())?;
+    Ok(shared)
+}
+
+/// Base-mode key schedule: AEAD key and base nonce
+fn key_schedule(shared_secret: &SecretBytes<N_SECRET>, info: &[u8]) -> Result<(SecretBytes<N_K>, SecretBytes<N_N>), CryptoError> {
+    let suite = hpke_suite_id();
+    let psk_id_hash = labeled_extract_bytes(&suite, b"psk_id_hash", b"");
+    let info_hash = labeled_extract_bytes(&suite, b"info_hash", info);
+    let context = [&[MODE_BASE][..], &psk_id_hash, &info_hash].concat();
+
+    let secret = labeled_extract(&suite, shared_secret.as_bytes(), b"secret", b"");
+    let mut key = SecretKey::zeroed();
+    let mut base_nonce = SecretBytes::zeroed();
+    labeled_expand(&secret, &suite, b"key", &context, key.as_mut_bytes())?;
+    labeled_expand(&secret, &suite, b"base_nonce", &context, base_nonce.as_mut_bytes())?;
+    Ok((key, base_nonce))
+}
+
+fn seal_with(
+    enc: [u8; ENC_LEN],
+    dh: SharedSecret,
+    recipient: &XPublicKey,
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<([u8; ENC_LEN], Vec<u8>), HpkeError> {
+    let shared = kem_shared_secret(&dh, &enc, recipient)?;
+    let (key, nonce) = key_schedule(&shared, info)?;
+    // single message: sequence number 0, so the nonce is the base nonce
+    let ct = ChaCha20Poly1305::new(key.as_key())
+        .encrypt(Nonce::from_slice(nonce.as_bytes()), Payload { msg: plaintext, aad })
+        .map_err(|_| HpkeError::Seal)?;
+    Ok((enc, ct))
+}
+
+/// Encrypt `plaintext` to `recipient`, returning the encapsulated key and ciphertext
+#[cfg(feature = "std")]
+pub fn seal(recipient: &XPublicKey, info: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<([u8; ENC_LEN], Vec<u8>), HpkeError> {
+    seal_with_rng(&mut OsRng, recipient, info, aad, plaintext)
+}
+
+/// [`seal`] drawing the ephemeral key from `rng`
+pub fn seal_with_rng<R: CryptoRngCore>(
+    rng: &mut R,
+    recipient: &XPublicKey,
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<([u8; ENC_LEN], Vec<u8>), HpkeError> {
+    let ephemeral = EphemeralKey::generate_with_rng(rng);
+    let dh = ephemeral.ecdh(recipient);
+    seal_with(ephemeral.public().to_bytes(), dh, recipient, info, aad, plaintext)
+}
+
+/// Decrypt a message produced by [`seal`] for `recipient`
+pub fn open(recipient: &dyn KeyProvider, enc: &[u8; ENC_LEN], info: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, HpkeError> {
+    let pk_r = recipient.public();
+    let dh = recipient.ecdh(&XPublicKey::from(*enc))?;
+    let shared = kem_shared_secret(&dh, enc, &pk_r)?;
+    let (key, nonce) = key_schedule(&shared, info)?;
+    ChaCha20Poly1305::new(key.as_key())
+        .decrypt(Nonce::from_slice(nonce.as_bytes()), Payload { msg: ciphertext, aad })
+        .map_err(|_| HpkeError::Open)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceKey;
+
+    fn unhex<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    // RFC 9180 A.2.1, first encryption
+    #[test]
+    fn rfc9180_base_x25519_chacha_vector() {
+        let sk_e = DeviceKey::from_bytes(&unhex::<32>("f4ec9b33b792c372c1d2c2063507b684ef925b8c75a42dbcbf57d63ccd381600")).unwrap();
+        let sk_r = DeviceKey::from_bytes(&unhex::<32>("8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb")).unwrap();
+        let pk_r = sk_r.public();
+        assert_eq!(hex::encode(pk_r.as_bytes()), "4310ee97d88cc1f088a5576c77ab0cf5c3ac797f3d95139c6c84b5429c59662a");
+        let info = hex::decode("4f6465206f6e2061204772656369616e2055726e").unwrap();
+        let aad = hex::decode("436f756e742d30").unwrap();
+        let pt = hex::decode("4265617574792069732074727574682c20747275746820626561757479").unwrap();
+
+        let (enc, ct) = seal_with(sk_e.public().to_bytes(), sk_e.ecdh(&pk_r), &pk_r, &info, &aad, &pt).unwrap();
+        assert_eq!(hex::encode(enc), "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a");
+        assert_eq!(
+            hex::encode(&ct),
+            "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db21993c62ce81883d2dd1b51a28"
+        );
+        assert_eq!(open(&sk_r, &enc, &info, &aad, &ct).unwrap(), pt);
+    }
+
+    #[test]
+    fn seal_open_roundtrip_and_binding() {
+        let recipient = DeviceKey::generate();
+        let (enc, ct) = seal(&recipient.public(), b"drop box", b"hdr", b"offline payload").unwrap();
+        assert_eq!(open(&recipient, &enc, b"drop box", b"hdr", &ct).unwrap(), b"offline payload");
+        assert_eq!(open(&recipient, &enc, b"other info", b"hdr", &ct), Err(HpkeError::Open));
+        assert_eq!(open(&DeviceKey::generate(), &enc, b"drop box", b"hdr", &ct), Err(HpkeError::Open));
+        assert_eq!(seal(&XPublicKey::from([0u8; 32]), b"", b"", b"x").err(), Some(HpkeError::InvalidKey));
+    }
+}
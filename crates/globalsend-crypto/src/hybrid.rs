@@ -0,0 +1,207 @@
+//! Hybrid post-quantum key agreement (X25519 + ML-KEM-768)
+//!
+//! With the `pq` feature the initiator attaches a fresh ML-KEM-768
+//! encapsulation key to its first message; the responder encapsulates to it
+//! and sends the ciphertext back. Both sides then pass the KEM secret to
+//! [`SessionKeys::derive_hybrid`](crate::session::SessionKeys::derive_hybrid),
+//! which feeds it into HKDF next to the X25519 terms. The session stays secure
+//! as long as either primitive holds.
+//!
+//! Wire format, one byte of KEM id followed by the KEM-specific body:
+//!
+//! ```text
+//! offer: 0x00                    (no KEM)
+//!        0x01 || ek (1184 bytes) (ML-KEM-768)
+//! reply: 0x00                    (peer does not support the offered KEM)
+//!        0x01 || ct (1088 bytes) (ML-KEM-768)
+//! ```
+//!
+//! Builds without `pq` still parse offers and answer `0x00`, so mixed peers
+//! fall back to plain X25519 instead of failing.
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+
+use crate::secret::SecretBytes;
+
+pub const KEM_NONE: u8 = 0;
+pub const KEM_ML_KEM_768: u8 = 1;
+pub const KEM_SECRET_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KemError {
+    /// Offer or reply has the wrong length or an unknown id
+    Malformed,
+    /// Reply uses a KEM we did not offer
+    Unexpected,
+}
+
+impl fmt::Display for KemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KemError::Malformed => write!(f, "malformed kem message"),
+            KemError::Unexpected => write!(f, "kem reply does not match offer"),
+        }
+    }
+}
+
+impl core::error::Error for KemError {}
+
+/// Shared secret established by the KEM
+pub struct KemSecret(SecretBytes<KEM_SECRET_LEN>);
+
+impl KemSecret {
+    pub fn as_bytes(&self) -> &[u8; KEM_SECRET_LEN] {
+        self.0.as_bytes()
+    }
+}
+
+/// Initiator side: the offer we sent and the key to open the reply
+pub struct KemOffer {
+    #[cfg(feature = "pq")]
+    dk: Option<mlkem::DecapsulationKey>,
+    bytes: Vec<u8>,
+}
+
+impl KemOffer {
+    /// Offer the best KEM this build supports
+    #[cfg(feature = "std")]
+    pub fn new() -> Self {
+        Self::new_with_rng(&mut OsRng)
+    }
+
+    /// [`new`](Self::new) drawing the KEM key from `rng`
+    #[cfg_attr(not(feature = "pq"), allow(unused_variables))]
+    pub fn new_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        #[cfg(feature = "pq")]
+        {
+            let (dk, ek) = mlkem::generate(rng);
+            let mut bytes = vec![KEM_ML_KEM_768];
+            bytes.extend_from_slice(&ek);
+            Self { dk: Some(dk), bytes }
+        }
+        #[cfg(not(feature = "pq"))]
+        Self::none()
+    }
+
+    /// Offer no KEM
+    pub fn none() -> Self {
+        Self {
+            #[cfg(feature = "pq")]
+            dk: None,
+            bytes: vec![KEM_NONE],
+        }
+    }
+
+    /// Encoded offer to send to the peer
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Process the peer's reply; `None` means fall back to X25519 only
+    pub fn accept(self, reply: &[u8]) -> Result<Option<KemSecret>, KemError> {
+        match reply.split_first() {
+            Some((&KEM_NONE, [])) => Ok(None),
+            #[cfg(feature = "pq")]
+            Some((&KEM_ML_KEM_768, ct)) => {
+                let dk = self.dk.as_ref().ok_or(KemError::Unexpected)?;
+                mlkem::decapsulate(dk, ct).map(Some)
+            }
+            #[cfg(not(feature = "pq"))]
+            Some((&KEM_ML_KEM_768, _)) => Err(KemError::Unexpected),
+            _ => Err(KemError::Malformed),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for KemOffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Responder side: answer `offer`, returning the reply and the KEM secret if one was agreed
+#[cfg(feature = "std")]
+pub fn respond(offer: &[u8]) -> Result<(Vec<u8>, Option<KemSecret>), KemError> {
+    respond_with_rng(&mut OsRng, offer)
+}
+
+/// [`respond`] drawing the encapsulation randomness from `rng`
+#[cfg_attr(not(feature = "pq"), allow(unused_variables))]
+pub fn respond_with_rng<R: CryptoRngCore>(rng: &mut R, offer: &[u8]) -> Result<(Vec<u8>, Option<KemSecret>), KemError> {
+    match offer.split_first() {
+        Some((&KEM_NONE, [])) => Ok((vec![KEM_NONE], None)),
+        #[cfg(feature = "pq")]
+        Some((&KEM_ML_KEM_768, ek)) => {
+            let (ct, secret) = mlkem::encapsulate(rng, ek)?;
+            let mut reply = vec![KEM_ML_KEM_768];
+            reply.extend_from_slice(&ct);
+            Ok((reply, Some(secret)))
+        }
+        // a KEM we cannot do: decline and let the peer fall back
+        Some(_) => Ok((vec![KEM_NONE], None)),
+        None => Err(KemError::Malformed),
+    }
+}
+
+#[cfg(feature = "pq")]
+mod mlkem {
+    use kem::{Decapsulate, Encapsulate};
+    use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem768};
+    use alloc::vec::Vec;
+    use rand_core::CryptoRngCore;
+
+    use super::{KemError, KemSecret};
+    use crate::secret::SecretBytes;
+
+    pub type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+    type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+
+    pub fn generate<R: CryptoRngCore>(rng: &mut R) -> (DecapsulationKey, Vec<u8>) {
+        let (dk, ek) = MlKem768::generate(rng);
+        (dk, ek.as_bytes().to_vec())
+    }
+
+    pub fn encapsulate<R: CryptoRngCore>(rng: &mut R, ek: &[u8]) -> Result<(Vec<u8>, KemSecret), KemError> {
+        let encoded = ek.try_into().map_err(|_| KemError::Malformed)?;
+        let ek = EncapsulationKey::from_bytes(encoded);
+        let (ct, shared) = ek.encapsulate(rng).map_err(|_| KemError::Malformed)?;
+        Ok((ct.to_vec(), KemSecret(SecretBytes::new(shared.into()))))
+    }
+
+    pub fn decapsulate(dk: &DecapsulationKey, ct: &[u8]) -> Result<KemSecret, KemError> {
+        let ct = Ciphertext::<MlKem768>::try_from(ct).map_err(|_| KemError::Malformed)?;
+        // ML-KEM decapsulation never fails: a bad ciphertext yields an unrelated key
+        let shared = dk.decapsulate(&ct).map_err(|_| KemError::Malformed)?;
+        Ok(KemSecret(SecretBytes::new(shared.into())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offer_reply_agree_or_fall_back() {
+        let offer = KemOffer::new();
+        let (reply, responder) = respond(offer.to_bytes()).unwrap();
+        let initiator = offer.accept(&reply).unwrap();
+        assert_eq!(initiator.as_ref().map(|s| *s.as_bytes()), responder.as_ref().map(|s| *s.as_bytes()));
+        assert_eq!(initiator.is_some(), cfg!(feature = "pq"));
+
+        // unknown KEM ids are declined rather than rejected
+        let (reply, secret) = respond(&[0x7f, 1, 2, 3]).unwrap();
+        assert_eq!(reply, [KEM_NONE]);
+        assert!(secret.is_none());
+        assert!(KemOffer::new().accept(&reply).unwrap().is_none());
+
+        assert_eq!(KemOffer::none().accept(&[KEM_ML_KEM_768, 0]).err(), Some(KemError::Unexpected));
+        assert_eq!(KemOffer::none().accept(&[]).err(), Some(KemError::Malformed));
+        assert!(respond(&[]).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Device identity: Ed25519 signing key next to the X25519 exchange key
+//!
+//! The signing key is the long-term identity of a device. It signs discovery
+//! announcements and transfer manifests, and its verifying key is hashed into
+//! the stable device [`Fingerprint`] shown to users.
+//!
+//! Fingerprints compare in constant time. Code that decides whether a peer
+//! is trusted should hold a [`VerifiedFingerprint`], which only comes out of
+//! [`Fingerprint::verify`] / [`Fingerprint::verify_hex`] and cannot be
+//! compared with `==` at all.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+use ed25519_dalek::{Signer, SigningKey};
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+pub use ed25519_dalek::{Signature, SignatureError, VerifyingKey};
+
+use crate::ct::{ct_eq_array, Choice, ConstantTimeEq};
+use crate::{events, DeviceKey};
+
+pub const FINGERPRINT_LEN: usize = 32;
+
+/// SHA-256 of a device's Ed25519 verifying key
+#[derive(Clone, Copy, Eq, PartialOrd, Ord)]
+pub struct Fingerprint([u8; FINGERPRINT_LEN]);
+
+impl Fingerprint {
+    pub fn of(verifying_key: &VerifyingKey) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"globalsend fingerprint v1");
+        hasher.update(verifying_key.as_bytes());
+        Self(hasher.finalize().into())
+    }
+
+    pub fn from_bytes(bytes: [u8; FINGERPRINT_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; FINGERPRINT_LEN] {
+        &self.0
+    }
+
+    /// Lowercase hex encoding
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Parse the output of [`Fingerprint::to_hex`] (either case)
+    pub fn from_hex(s: &str) -> Option<Self> {
+        if s.len() != 2 * FINGERPRINT_LEN || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let mut out = [0u8; FINGERPRINT_LEN];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Self(out))
+    }
+
+    /// Compare against the expected (pinned or out-of-band) fingerprint
+    pub fn verify(&self, expected: &Fingerprint) -> Option<VerifiedFingerprint> {
+        (self == expected).then_some(VerifiedFingerprint(*self))
+    }
+
+    /// Compare against a hex fingerprint typed or scanned by the user
+    pub fn verify_hex(&self, expected: &str) -> Option<VerifiedFingerprint> {
+        self.verify(&Fingerprint::from_hex(expected)?)
+    }
+}
+
+impl ConstantTimeEq for Fingerprint {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+impl PartialEq for Fingerprint {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq_array(&self.0, &other.0)
+    }
+}
+
+impl Hash for Fingerprint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self.to_hex())
+    }
+}
+
+/// Fingerprint that matched what the user or the trust store expected
+///
+/// Deliberately not `PartialEq`: compare a fresh [`Fingerprint`] through
+/// [`VerifiedFingerprint::matches`] instead.
+#[derive(Clone, Copy)]
+pub struct VerifiedFingerprint(Fingerprint);
+
+impl VerifiedFingerprint {
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.0
+    }
+
+    /// Constant-time check that `other` is the same fingerprint
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for VerifiedFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for VerifiedFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VerifiedFingerprint({})", self.0.to_hex())
+    }
+}
+
+/// Long-term signing identity plus the X25519 key used for key exchange
+pub struct DeviceIdentity {
+    signing: SigningKey,
+    exchange: DeviceKey,
+}
+
+impl DeviceIdentity {
+    /// Generate a new identity with fresh signing and exchange keys
+    #[cfg(feature = "std")]
+    pub fn generate() -> Self {
+        Self::generate_with_rng(&mut OsRng)
+    }
+
+    /// Generate an identity from a caller-provided CSPRNG
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        let signing = SigningKey::generate(rng);
+        events::emit(|s| s.key_generated(events::KeyKind::Identity, signing.verifying_key().as_bytes()));
+        Self { signing, exchange: DeviceKey::generate_with_rng(rng) }
+    }
+
+    /// Assemble an identity from existing keys
+    pub fn from_parts(signing: SigningKey, exchange: DeviceKey) -> Self {
+        Self { signing, exchange }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing.verifying_key()
+    }
+
+    /// Stable fingerprint derived from the verifying key
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.verifying_key())
+    }
+
+    /// X25519 key used for ECDH and handshakes
+    pub fn exchange(&self) -> &DeviceKey {
+        &self.exchange
+    }
+
+    /// Swap in a new exchange key, returning the previous one
+    pub fn replace_exchange(&mut self, exchange: DeviceKey) -> DeviceKey {
+        core::mem::replace(&mut self.exchange, exchange)
+    }
+
+    /// Ed25519 secret seed, for [`to_versioned_bytes`](Self::to_versioned_bytes)
+    pub(crate) fn signing_seed(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.signing.to_bytes())
+    }
+
+    /// Sign `msg` with the identity key
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        self.signing.sign(msg)
+    }
+}
+
+impl fmt::Debug for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceIdentity")
+            .field("fingerprint", &self.fingerprint())
+            .field("exchange", &self.exchange)
+            .finish()
+    }
+}
+
+/// Verify a signature made by [`DeviceIdentity::sign`]
+pub fn verify(verifying_key: &VerifyingKey, msg: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+    verifying_key.verify_strict(msg, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_verify_and_fingerprint() {
+        let id = DeviceIdentity::generate();
+        let sig = id.sign(b"announcement");
+        assert!(verify(&id.verifying_key(), b"announcement", &sig).is_ok());
+        assert!(verify(&id.verifying_key(), b"announcement!", &sig).is_err());
+
+        let other = DeviceIdentity::generate();
+        assert!(verify(&other.verifying_key(), b"announcement", &sig).is_err());
+
+        assert_eq!(id.fingerprint(), Fingerprint::of(&id.verifying_key()));
+        assert_ne!(id.fingerprint(), other.fingerprint());
+        assert_eq!(id.fingerprint().to_hex().len(), 64);
+        assert_eq!(Fingerprint::from_hex(&id.fingerprint().to_hex()), Some(id.fingerprint()));
+        assert_eq!(Fingerprint::from_hex("zz"), None);
+
+        let verified = id.fingerprint().verify_hex(&id.fingerprint().to_hex().to_uppercase()).unwrap();
+        assert!(verified.matches(&id.fingerprint()));
+        assert!(!verified.matches(&other.fingerprint()));
+        assert!(id.fingerprint().verify(&other.fingerprint()).is_none());
+        assert!(id.fingerprint().verify_hex("not hex").is_none());
+    }
+}
@@ -0,0 +1,123 @@
+//! Context-bound key derivation
+//!
+//! [`derive_aead`](crate::derive_aead) expands a shared secret under one fixed
+//! label, so nothing ties the output to how the secret was negotiated.
+//! [`KdfContext`] collects the negotiated parameters and feeds them to HKDF:
+//!
+//! - salt: the handshake transcript hash, if any
+//! - info: `"globalsend kdf v1" || version (u16 BE) || suite || key_lo || key_hi || label`,
+//!   with every variable field prefixed by its u16 BE length
+//!
+//! The two public keys are sorted, so both peers build the same context
+//! regardless of which side they are on. Keys derived under contexts that
+//! differ in any field are unrelated.
+
+use alloc::vec::Vec;
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::PublicKey as XPublicKey;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::{split_okm, CryptoError, NonceSequence, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+
+const KDF_INFO_PREFIX: &[u8] = b"globalsend kdf v1";
+const AEAD_LABEL: &[u8] = b"aead";
+
+/// Parameters a derived key is bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfContext {
+    version: u16,
+    suite: Vec<u8>,
+    public_keys: Option<([u8; 32], [u8; 32])>,
+    transcript: Option<[u8; 32]>,
+}
+
+impl KdfContext {
+    /// Start a context for protocol `version`
+    pub fn new(version: u16) -> Self {
+        Self { version, suite: Vec::new(), public_keys: None, transcript: None }
+    }
+
+    /// Name of the negotiated cipher suite
+    pub fn cipher_suite(mut self, name: &str) -> Self {
+        self.suite = name.as_bytes().to_vec();
+        self
+    }
+
+    /// Both peers' public keys, in either order
+    pub fn public_keys(mut self, a: &XPublicKey, b: &XPublicKey) -> Self {
+        let (a, b) = (a.to_bytes(), b.to_bytes());
+        self.public_keys = Some(if a <= b { (a, b) } else { (b, a) });
+        self
+    }
+
+    /// Hash of the handshake transcript, used as HKDF salt
+    pub fn transcript(mut self, hash: &[u8; 32]) -> Self {
+        self.transcript = Some(*hash);
+        self
+    }
+
+    /// HKDF info string for `label`
+    pub fn info(&self, label: &[u8]) -> Vec<u8> {
+        fn field(out: &mut Vec<u8>, bytes: &[u8]) {
+            let len = u16::try_from(bytes.len()).expect("kdf context field too long");
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(bytes);
+        }
+        let mut info = KDF_INFO_PREFIX.to_vec();
+        info.extend_from_slice(&self.version.to_be_bytes());
+        field(&mut info, &self.suite);
+        let (lo, hi) = self.public_keys.as_ref().map_or((&[][..], &[][..]), |(lo, hi)| (&lo[..], &hi[..]));
+        field(&mut info, lo);
+        field(&mut info, hi);
+        field(&mut info, label);
+        info
+    }
+
+    /// Fill `out` with key material for `label`
+    pub fn expand(&self, shared_secret: &[u8], label: &[u8], out: &mut [u8]) -> Result<(), CryptoError> {
+        let hk = Hkdf::<Sha256>::new(self.transcript.as_ref().map(|h| &h[..]), shared_secret);
+        Ok(hk.expand(&self.info(label), out)?)
+    }
+
+    /// Context-bound counterpart of [`derive_aead`](crate::derive_aead)
+    pub fn derive_aead(&self, shared_secret: &[u8]) -> Result<(SecretKey, NonceSequence), CryptoError> {
+        let mut okm = SecretBytes::<{ AEAD_KEY_LEN + AEAD_NONCE_LEN }>::zeroed();
+        self.expand(shared_secret, AEAD_LABEL, okm.as_mut_bytes())?;
+        Ok(split_okm(okm.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aead_decrypt, aead_encrypt, DeviceKey, PROTOCOL_VERSION};
+
+    #[test]
+    fn keys_bound_to_every_parameter() {
+        let (a, b) = (DeviceKey::generate().public(), DeviceKey::generate().public());
+        let base = KdfContext::new(PROTOCOL_VERSION).cipher_suite("XChaCha20Poly1305").public_keys(&a, &b).transcript(&[7; 32]);
+        let secret = [42u8; 32];
+
+        // the peer lists the keys the other way round and still agrees
+        let mirrored = KdfContext::new(PROTOCOL_VERSION).cipher_suite("XChaCha20Poly1305").public_keys(&b, &a).transcript(&[7; 32]);
+        assert_eq!(base, mirrored);
+        let (k1, mut n1) = base.derive_aead(&secret).unwrap();
+        let (k2, n2) = mirrored.derive_aead(&secret).unwrap();
+        let ct = aead_encrypt(k1.as_key(), n1.next().unwrap(), b"", b"bound").unwrap();
+        assert_eq!(aead_decrypt(k2.as_key(), &n2, 0, b"", &ct).unwrap(), b"bound");
+
+        let variants = [
+            KdfContext::new(PROTOCOL_VERSION + 1).cipher_suite("XChaCha20Poly1305").public_keys(&a, &b).transcript(&[7; 32]),
+            base.clone().cipher_suite("Aes256Gcm"),
+            base.clone().public_keys(&a, &DeviceKey::generate().public()),
+            base.clone().transcript(&[8; 32]),
+            KdfContext::new(PROTOCOL_VERSION).cipher_suite("XChaCha20Poly1305").public_keys(&a, &b),
+        ];
+        for ctx in variants {
+            assert_ne!(ctx.derive_aead(&secret).unwrap().0.as_bytes(), k1.as_bytes(), "{ctx:?}");
+        }
+        assert_ne!(base.derive_aead(&secret).unwrap().0.as_bytes(), crate::derive_aead(&secret).unwrap().0.as_bytes());
+        assert_eq!(base.expand(&secret, b"too long", &mut [0; 255 * 32 + 1]), Err(CryptoError::KeyDerivation));
+    }
+}
@@ -0,0 +1,245 @@
+//! Passphrase-protected device key storage
+//!
+//! The device secret is sealed with XChaCha20-Poly1305 under a key derived
+//! from the passphrase with Argon2id. The file is self-describing:
+//!
+//! ```text
+//! "GSKF" | version u8 | kdf u8 | m_cost u32 | t_cost u32 | p_cost u32 | salt [16] | nonce [24] | ciphertext
+//! ```
+//!
+//! Integers are big-endian. Everything before the ciphertext is authenticated
+//! as AAD, so tampering with the KDF parameters is detected.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::encoding::KeyFormatError;
+use crate::secret::SecretKey;
+use crate::{CryptoError, DeviceKey, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+
+const MAGIC: &[u8; 4] = b"GSKF";
+const VERSION: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+pub const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 12 + SALT_LEN + AEAD_NONCE_LEN;
+/// Refuse files demanding more than 1 GiB of KDF memory
+const MAX_M_COST_KIB: u32 = 1 << 20;
+const MAX_T_COST: u32 = 64;
+const MAX_P_COST: u32 = 16;
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory in KiB
+    pub m_cost: u32,
+    /// Iterations
+    pub t_cost: u32,
+    /// Parallelism
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// RFC 9106 second recommended option: 64 MiB, 3 passes, 1 lane
+    fn default() -> Self {
+        Self { m_cost: 64 * 1024, t_cost: 3, p_cost: 1 }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyFileError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnsupportedKdf(u8),
+    /// KDF parameters are invalid or exceed local limits
+    BadParams,
+    Truncated,
+    /// Passphrase is wrong or the file was modified
+    Decrypt,
+    Key(KeyFormatError),
+    Crypto(CryptoError),
+    Io(io::Error),
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFileError::BadMagic => write!(f, "not a globalsend key file"),
+            KeyFileError::UnsupportedVersion(v) => write!(f, "unsupported key file version {v}"),
+            KeyFileError::UnsupportedKdf(k) => write!(f, "unsupported key file kdf {k}"),
+            KeyFileError::BadParams => write!(f, "invalid key file kdf parameters"),
+            KeyFileError::Truncated => write!(f, "key file truncated"),
+            KeyFileError::Decrypt => write!(f, "wrong passphrase or corrupted key file"),
+            KeyFileError::Key(e) => write!(f, "key file contents: {e}"),
+            KeyFileError::Crypto(e) => write!(f, "key file: {e}"),
+            KeyFileError::Io(e) => write!(f, "key file io error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for KeyFileError {}
+
+impl From<io::Error> for KeyFileError {
+    fn from(e: io::Error) -> Self {
+        KeyFileError::Io(e)
+    }
+}
+
+/// Device secret sealed under a passphrase
+#[derive(Debug, Clone)]
+pub struct EncryptedKeyFile {
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; AEAD_NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+pub(crate) fn derive_kek(passphrase: &[u8], salt: &[u8; SALT_LEN], params: &KdfParams) -> Result<SecretKey, KeyFileError> {
+    if params.m_cost > MAX_M_COST_KIB || params.t_cost > MAX_T_COST || params.p_cost > MAX_P_COST {
+        return Err(KeyFileError::BadParams);
+    }
+    let p = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(AEAD_KEY_LEN)).map_err(|_| KeyFileError::BadParams)?;
+    let mut kek = SecretKey::zeroed();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, p)
+        .hash_password_into(passphrase, salt, kek.as_mut_bytes())
+        .map_err(|_| KeyFileError::BadParams)?;
+    Ok(kek)
+}
+
+impl EncryptedKeyFile {
+    /// Seal `key` with default Argon2id parameters
+    pub fn seal(key: &DeviceKey, passphrase: &[u8]) -> Result<Self, KeyFileError> {
+        Self::seal_with_params(key, passphrase, KdfParams::default())
+    }
+
+    pub fn seal_with_params(key: &DeviceKey, passphrase: &[u8], params: KdfParams) -> Result<Self, KeyFileError> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; AEAD_NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let mut file = Self { params, salt, nonce, ciphertext: Vec::new() };
+
+        let kek = derive_kek(passphrase, &salt, &params)?;
+        let cipher = XChaCha20Poly1305::new(kek.as_key());
+        let header = file.header();
+        let plaintext = key.to_versioned_bytes();
+        file.ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &header })
+            .map_err(|_| KeyFileError::Crypto(CryptoError::Encrypt))?;
+        Ok(file)
+    }
+
+    /// Recover the device key; fails with `Decrypt` on a wrong passphrase
+    pub fn open(&self, passphrase: &[u8]) -> Result<DeviceKey, KeyFileError> {
+        let kek = derive_kek(passphrase, &self.salt, &self.params)?;
+        let cipher = XChaCha20Poly1305::new(kek.as_key());
+        let header = self.header();
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(XNonce::from_slice(&self.nonce), Payload { msg: &self.ciphertext, aad: &header })
+                .map_err(|_| KeyFileError::Decrypt)?,
+        );
+        DeviceKey::from_versioned_bytes(&plaintext).map_err(KeyFileError::Key)
+    }
+
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut h = Vec::with_capacity(HEADER_LEN);
+        h.extend_from_slice(MAGIC);
+        h.push(VERSION);
+        h.push(KDF_ARGON2ID);
+        h.extend_from_slice(&self.params.m_cost.to_be_bytes());
+        h.extend_from_slice(&self.params.t_cost.to_be_bytes());
+        h.extend_from_slice(&self.params.p_cost.to_be_bytes());
+        h.extend_from_slice(&self.salt);
+        h.extend_from_slice(&self.nonce);
+        h
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.header();
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyFileError> {
+        if bytes.len() < MAGIC.len() + 2 {
+            return Err(KeyFileError::Truncated);
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(KeyFileError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(KeyFileError::UnsupportedVersion(bytes[4]));
+        }
+        if bytes[5] != KDF_ARGON2ID {
+            return Err(KeyFileError::UnsupportedKdf(bytes[5]));
+        }
+        if bytes.len() <= HEADER_LEN {
+            return Err(KeyFileError::Truncated);
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().expect("4 bytes"));
+        let params = KdfParams { m_cost: u32_at(6), t_cost: u32_at(10), p_cost: u32_at(14) };
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&bytes[18..18 + SALT_LEN]);
+        let mut nonce = [0u8; AEAD_NONCE_LEN];
+        nonce.copy_from_slice(&bytes[18 + SALT_LEN..HEADER_LEN]);
+        Ok(Self { params, salt, nonce, ciphertext: bytes[HEADER_LEN..].to_vec() })
+    }
+
+    /// Write the key file, replacing any existing file atomically
+    pub fn save(&self, path: &Path) -> Result<(), KeyFileError> {
+        let tmp = path.with_extension("tmp");
+        {
+            let mut opts = fs::OpenOptions::new();
+            opts.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                opts.mode(0o600);
+            }
+            let mut f = opts.open(&tmp)?;
+            f.write_all(&self.to_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, KeyFileError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: KdfParams = KdfParams { m_cost: 256, t_cost: 1, p_cost: 1 };
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let key = DeviceKey::generate();
+        let file = EncryptedKeyFile::seal_with_params(&key, b"correct horse", FAST).unwrap();
+        let parsed = EncryptedKeyFile::from_bytes(&file.to_bytes()).unwrap();
+        assert_eq!(parsed.params(), FAST);
+        assert_eq!(parsed.open(b"correct horse").unwrap().public(), key.public());
+        assert!(matches!(parsed.open(b"wrong"), Err(KeyFileError::Decrypt)));
+
+        // bumping the cost parameters invalidates the AAD
+        let mut bytes = file.to_bytes();
+        bytes[13] += 1;
+        let tampered = EncryptedKeyFile::from_bytes(&bytes).unwrap();
+        assert!(matches!(tampered.open(b"correct horse"), Err(KeyFileError::Decrypt)));
+    }
+}
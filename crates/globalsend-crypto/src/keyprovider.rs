@@ -0,0 +1,70 @@
+//! Abstraction over where the static X25519 secret lives
+//!
+//! The handshake, session derivation and HPKE only need two operations on the
+//! device's static key: its public half and X25519 with a peer key.
+//! [`KeyProvider`] captures exactly that, so the secret can stay inside a TPM
+//! 2.0, the Secure Enclave or a PKCS#11 token and never be loaded into process
+//! memory. The software [`DeviceKey`] is the default implementation.
+//!
+//! Hardware backends live in the platform crates. Their `ecdh` can fail at
+//! runtime (token removed, user cancelled a PIN prompt) and reports
+//! [`CryptoError::KeyUnavailable`].
+
+use x25519_dalek::PublicKey as XPublicKey;
+
+use crate::secret::SharedSecret;
+use crate::{CryptoError, DeviceKey};
+
+/// Holder of a static X25519 key pair; shared across threads and async tasks
+pub trait KeyProvider: Send + Sync {
+    fn public(&self) -> XPublicKey;
+
+    /// X25519 between our secret and `peer`
+    fn ecdh(&self, peer: &XPublicKey) -> Result<SharedSecret, CryptoError>;
+}
+
+impl KeyProvider for DeviceKey {
+    fn public(&self) -> XPublicKey {
+        DeviceKey::public(self)
+    }
+
+    fn ecdh(&self, peer: &XPublicKey) -> Result<SharedSecret, CryptoError> {
+        Ok(DeviceKey::ecdh(self, peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::Handshake;
+
+    /// Stand-in for a token that has been unplugged
+    struct Unplugged(XPublicKey);
+
+    impl KeyProvider for Unplugged {
+        fn public(&self) -> XPublicKey {
+            self.0
+        }
+
+        fn ecdh(&self, _: &XPublicKey) -> Result<SharedSecret, CryptoError> {
+            Err(CryptoError::KeyUnavailable)
+        }
+    }
+
+    #[test]
+    fn handshake_runs_on_any_provider() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let mut i = Handshake::initiator(&a, b"");
+        let mut r = Handshake::responder(&b, b"");
+        r.read_message(&i.write_message(b"").unwrap()).unwrap();
+        i.read_message(&r.write_message(b"").unwrap()).unwrap();
+        r.read_message(&i.write_message(b"").unwrap()).unwrap();
+        assert_eq!(i.into_transport().unwrap().remote_static, KeyProvider::public(&b));
+
+        let token = Unplugged(b.public());
+        let mut i = Handshake::initiator(&a, b"");
+        let mut r = Handshake::responder(&token, b"");
+        r.read_message(&i.write_message(b"").unwrap()).unwrap();
+        assert!(r.write_message(b"").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Where the device secret lives between runs
+//!
+//! [`KeyStore`] abstracts persistence of the [`DeviceKey`]. The default is a
+//! passphrase-protected [`EncryptedKeyFile`]; with the `keystore-os` feature
+//! the secret can instead live in the platform credential store (macOS
+//! Keychain, Windows Credential Manager, Linux Secret Service).
+//! [`FallbackKeyStore`] prefers one store and falls back to another when the
+//! first is unavailable, e.g. no Secret Service daemon on a headless box.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use zeroize::Zeroizing;
+
+use crate::keyfile::{EncryptedKeyFile, KdfParams, KeyFileError};
+use crate::DeviceKey;
+
+#[derive(Debug)]
+pub enum KeyStoreError {
+    /// Backend cannot be used on this machine/session
+    Unavailable(String),
+    KeyFile(KeyFileError),
+    /// Stored data is not a valid device key
+    Corrupt(String),
+}
+
+impl fmt::Display for KeyStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStoreError::Unavailable(why) => write!(f, "key store unavailable: {why}"),
+            KeyStoreError::KeyFile(e) => write!(f, "{e}"),
+            KeyStoreError::Corrupt(why) => write!(f, "stored device key is corrupt: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for KeyStoreError {}
+
+impl From<KeyFileError> for KeyStoreError {
+    fn from(e: KeyFileError) -> Self {
+        KeyStoreError::KeyFile(e)
+    }
+}
+
+/// Persistent storage for the device secret
+pub trait KeyStore {
+    /// Load the stored key, `Ok(None)` if nothing has been stored yet
+    fn load(&self) -> Result<Option<DeviceKey>, KeyStoreError>;
+    fn store(&self, key: &DeviceKey) -> Result<(), KeyStoreError>;
+    /// Remove the stored key; succeeds if there was none
+    fn delete(&self) -> Result<(), KeyStoreError>;
+
+    /// Load the stored key or generate and store a new one
+    fn load_or_generate(&self) -> Result<DeviceKey, KeyStoreError> {
+        if let Some(key) = self.load()? {
+            return Ok(key);
+        }
+        let key = DeviceKey::generate();
+        self.store(&key)?;
+        Ok(key)
+    }
+}
+
+/// Key store backed by an Argon2id-encrypted file
+pub struct FileKeyStore {
+    path: PathBuf,
+    passphrase: Zeroizing<Vec<u8>>,
+    params: KdfParams,
+}
+
+impl FileKeyStore {
+    pub fn new(path: impl Into<PathBuf>, passphrase: &[u8]) -> Self {
+        Self {
+            path: path.into(),
+            passphrase: Zeroizing::new(passphrase.to_vec()),
+            params: KdfParams::default(),
+        }
+    }
+
+    /// Override the Argon2id parameters used when storing
+    pub fn with_params(mut self, params: KdfParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn load(&self) -> Result<Option<DeviceKey>, KeyStoreError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let file = EncryptedKeyFile::load(&self.path)?;
+        Ok(Some(file.open(&self.passphrase)?))
+    }
+
+    fn store(&self, key: &DeviceKey) -> Result<(), KeyStoreError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(KeyFileError::Io)?;
+        }
+        EncryptedKeyFile::seal_with_params(key, &self.passphrase, self.params)?.save(&self.path)?;
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(KeyFileError::Io(e).into()),
+        }
+    }
+}
+
+/// Key store in the platform credential manager
+#[cfg(feature = "keystore-os")]
+pub struct OsKeyStore {
+    service: String,
+    account: String,
+}
+
+#[cfg(feature = "keystore-os")]
+impl OsKeyStore {
+    pub const DEFAULT_SERVICE: &'static str = "globalsend";
+
+    /// `account` distinguishes profiles stored under the same service
+    pub fn new(service: &str, account: &str) -> Self {
+        Self { service: service.to_string(), account: account.to_string() }
+    }
+
+    fn entry(&self) -> Result<keyring::Entry, KeyStoreError> {
+        keyring::Entry::new(&self.service, &self.account).map_err(os_error)
+    }
+}
+
+#[cfg(feature = "keystore-os")]
+fn os_error(e: keyring::Error) -> KeyStoreError {
+    match e {
+        keyring::Error::PlatformFailure(e) | keyring::Error::NoStorageAccess(e) => KeyStoreError::Unavailable(e.to_string()),
+        e => KeyStoreError::Corrupt(e.to_string()),
+    }
+}
+
+#[cfg(feature = "keystore-os")]
+impl KeyStore for OsKeyStore {
+    fn load(&self) -> Result<Option<DeviceKey>, KeyStoreError> {
+        match self.entry()?.get_secret() {
+            Ok(secret) => {
+                let secret = Zeroizing::new(secret);
+                DeviceKey::from_versioned_bytes(&secret)
+                    .map(Some)
+                    .map_err(|e| KeyStoreError::Corrupt(e.to_string()))
+            }
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(os_error(e)),
+        }
+    }
+
+    fn store(&self, key: &DeviceKey) -> Result<(), KeyStoreError> {
+        self.entry()?.set_secret(&key.to_versioned_bytes()).map_err(os_error)
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        match self.entry()?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(os_error(e)),
+        }
+    }
+}
+
+/// Uses `primary` unless it reports [`KeyStoreError::Unavailable`], then `fallback`
+pub struct FallbackKeyStore<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P: KeyStore, F: KeyStore> FallbackKeyStore<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<P: KeyStore, F: KeyStore> KeyStore for FallbackKeyStore<P, F> {
+    fn load(&self) -> Result<Option<DeviceKey>, KeyStoreError> {
+        match self.primary.load() {
+            Err(KeyStoreError::Unavailable(_)) => self.fallback.load(),
+            // a key left in the fallback from an earlier run is still ours
+            Ok(None) => self.fallback.load(),
+            r => r,
+        }
+    }
+
+    fn store(&self, key: &DeviceKey) -> Result<(), KeyStoreError> {
+        match self.primary.store(key) {
+            Err(KeyStoreError::Unavailable(_)) => self.fallback.store(key),
+            r => r,
+        }
+    }
+
+    fn delete(&self) -> Result<(), KeyStoreError> {
+        match self.primary.delete() {
+            Ok(()) | Err(KeyStoreError::Unavailable(_)) => self.fallback.delete(),
+            r => r,
+        }
+    }
+}
+
+/// Default store for this build: OS credential store with a file fallback
+/// when `keystore-os` is enabled, otherwise just the encrypted file.
+pub fn default_store(path: impl Into<PathBuf>, passphrase: &[u8]) -> Box<dyn KeyStore> {
+    let file = FileKeyStore::new(path, passphrase);
+    #[cfg(feature = "keystore-os")]
+    {
+        let os = OsKeyStore::new(OsKeyStore::DEFAULT_SERVICE, "device-key");
+        Box::new(FallbackKeyStore::new(os, file))
+    }
+    #[cfg(not(feature = "keystore-os"))]
+    {
+        Box::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Unavailable;
+
+    impl KeyStore for Unavailable {
+        fn load(&self) -> Result<Option<DeviceKey>, KeyStoreError> {
+            Err(KeyStoreError::Unavailable("test".into()))
+        }
+        fn store(&self, _: &DeviceKey) -> Result<(), KeyStoreError> {
+            Err(KeyStoreError::Unavailable("test".into()))
+        }
+        fn delete(&self) -> Result<(), KeyStoreError> {
+            Err(KeyStoreError::Unavailable("test".into()))
+        }
+    }
+
+    #[test]
+    fn file_store_with_fallback() {
+        let dir = std::env::temp_dir().join(format!("globalsend-keystore-{}", std::process::id()));
+        let file = FileKeyStore::new(dir.join("device.key"), b"pw").with_params(KdfParams { m_cost: 256, t_cost: 1, p_cost: 1 });
+        let store = FallbackKeyStore::new(Unavailable, file);
+
+        assert!(store.load().unwrap().is_none());
+        let key = store.load_or_generate().unwrap();
+        assert_eq!(store.load().unwrap().unwrap().public(), key.public());
+        assert_eq!(store.load_or_generate().unwrap().public(), key.public());
+        store.delete().unwrap();
+        assert!(store.load().unwrap().is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
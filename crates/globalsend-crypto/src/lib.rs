@@ -3,27 +3,116 @@
 //! Implements device key generation, X25519 ECDH, HKDF key derivation and
 //! ChaCha20-Poly1305 AEAD wrappers. This is intentionally small and meant as a
 //! starting point for the real `globalsend-crypto` crate.
+//!
+//! Builds as `no_std` + `alloc` with `--no-default-features`, e.g. for an
+//! embedded sender. That drops the `std` feature's OS RNG (use the
+//! `*_with_rng` constructors) and the file and io based modules.
+//!
+//! On `wasm32-unknown-unknown` the default build works unchanged: the OS RNG
+//! is backed by the browser's `crypto.getRandomValues`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
 
-use chacha20poly1305::{aead, XChaCha20Poly1305, Key, XNonce};
+use chacha20poly1305::aead::{self, Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, Key, XNonce};
 use hkdf::Hkdf;
+#[cfg(feature = "std")]
 use rand_core::OsRng;
-use x25519_dalek::{StaticSecret, PublicKey as XPublicKey};
-use zeroize::Zeroize;
+use rand_core::CryptoRngCore;
+use x25519_dalek::{ReusableSecret, StaticSecret, PublicKey as XPublicKey};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+pub use crate::error::CryptoError;
+use crate::secret::{SecretBytes, SecretKey, SharedSecret};
+
+pub mod aad;
+#[cfg(feature = "std")]
+pub mod age;
+pub mod cbor;
+pub mod announce;
+#[cfg(feature = "simd")]
+pub mod bulk;
+pub mod certificate;
+pub mod ct;
+pub mod device_id;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod fingerprint;
+pub mod guard;
+pub mod handshake;
+pub mod hashing;
+pub mod hpke;
+pub mod hybrid;
+pub mod identity;
+pub mod kdf;
+#[cfg(feature = "std")]
+pub mod keyfile;
+pub mod keyprovider;
+#[cfg(feature = "std")]
+pub mod keystore;
+pub mod manifest;
+#[cfg(feature = "memlock")]
+pub mod memlock;
+pub mod merkle;
+pub mod meta;
+pub mod minisign;
+#[cfg(feature = "rayon")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod mnemonic;
+#[cfg(feature = "std")]
+pub mod multi;
+#[cfg(feature = "std")]
+pub mod pairing;
+pub mod prekey;
+pub mod ratchet;
+pub mod replay;
+pub mod secret;
+pub mod selftest;
+pub mod session;
+#[cfg(feature = "std")]
+pub mod ssh;
+#[cfg(feature = "std")]
+pub mod stream;
+pub mod suite;
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod test_rng;
+#[cfg(feature = "std")]
+pub mod ticket;
+#[cfg(feature = "std")]
+pub mod trust;
+#[cfg(feature = "test-vectors")]
+pub mod vectors;
+pub mod wrap;
+
+/// Wire protocol version, bound into derived keys via [`kdf::KdfContext`]
+pub const PROTOCOL_VERSION: u16 = 1;
 
 pub const AEAD_KEY_LEN: usize = 32;
 pub const AEAD_NONCE_LEN: usize = 24; // XChaCha20 nonce
 
-#[derive(Debug)]
 pub struct DeviceKey {
-    /// X25519 static secret used for ECDH (kept encrypted at rest)
+    /// X25519 static secret used for ECDH (kept encrypted at rest, see `keyfile`)
     secret: StaticSecret,
 }
 
 impl DeviceKey {
     /// Generate a new device X25519 keypair
+    #[cfg(feature = "std")]
     pub fn generate() -> Self {
-        let secret = StaticSecret::new(OsRng);
-        Self { secret }
+        Self::generate_with_rng(&mut OsRng)
+    }
+
+    /// Generate a device keypair from a caller-provided CSPRNG
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        let key = Self { secret: StaticSecret::random_from_rng(rng) };
+        events::emit(|s| s.key_generated(events::KeyKind::Exchange, key.public().as_bytes()));
+        key
     }
 
     /// Public key corresponding to this device key
@@ -32,64 +121,209 @@ impl DeviceKey {
     }
 
     /// Compute an ECDH shared secret with peer public key
-    pub fn ecdh(&self, peer: &XPublicKey) -> [u8; 32] {
-        let shared = self.secret.diffie_hellman(peer);
-        shared.to_bytes()
+    pub fn ecdh(&self, peer: &XPublicKey) -> SharedSecret {
+        SecretBytes::new(self.secret.diffie_hellman(peer).to_bytes())
+    }
+}
+
+impl core::fmt::Debug for DeviceKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Never print the secret; the public key is enough to identify the device
+        f.debug_struct("DeviceKey").field("public", self.public().as_bytes()).finish()
     }
 }
 
 impl Drop for DeviceKey {
     fn drop(&mut self) {
-        // StaticSecret implements zeroize on drop through inner representation
+        self.secret.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for DeviceKey {}
+
+/// Per-transfer X25519 key; never persisted, dropped once session keys are derived
+pub struct EphemeralKey {
+    secret: ReusableSecret,
+}
+
+impl EphemeralKey {
+    /// Generate a fresh ephemeral X25519 keypair
+    #[cfg(feature = "std")]
+    pub fn generate() -> Self {
+        Self::generate_with_rng(&mut OsRng)
+    }
+
+    /// Generate an ephemeral keypair from a caller-provided CSPRNG
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        Self { secret: ReusableSecret::random_from_rng(rng) }
+    }
+
+    /// Public key to send to the peer
+    pub fn public(&self) -> XPublicKey {
+        XPublicKey::from(&self.secret)
+    }
+
+    /// Compute an ECDH shared secret with peer public key
+    pub fn ecdh(&self, peer: &XPublicKey) -> SharedSecret {
+        SecretBytes::new(self.secret.diffie_hellman(peer).to_bytes())
+    }
+}
+
+impl core::fmt::Debug for EphemeralKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EphemeralKey").field("public", self.public().as_bytes()).finish()
+    }
+}
+
+impl Drop for EphemeralKey {
+    fn drop(&mut self) {
+        self.secret.zeroize();
     }
 }
 
-/// Derive AEAD key and base nonce using HKDF-SHA256 from a shared secret
-pub fn derive_aead(shared_secret: &[u8]) -> (Key, [u8; AEAD_NONCE_LEN]) {
+impl ZeroizeOnDrop for EphemeralKey {}
+
+/// Derive AEAD key and nonce sequence using HKDF-SHA256 from a shared secret
+///
+/// Uses a fixed label only; use [`kdf::KdfContext`] to bind the output to the
+/// negotiated version, suite, peer keys and transcript.
+pub fn derive_aead(shared_secret: &[u8]) -> Result<(SecretKey, NonceSequence), CryptoError> {
     // info labels
     let hk = Hkdf::<sha2::Sha256>::new(None, shared_secret);
-    let mut okm = [0u8; AEAD_KEY_LEN + AEAD_NONCE_LEN];
-    hk.expand(b"globalsend v1", &mut okm).expect("hkdf expand");
-    let key = Key::from_slice(&okm[..AEAD_KEY_LEN]);
-    let mut nonce = [0u8; AEAD_NONCE_LEN];
-    nonce.copy_from_slice(&okm[AEAD_KEY_LEN..]);
-    (key.clone(), nonce)
+    let mut okm = SecretBytes::<{ AEAD_KEY_LEN + AEAD_NONCE_LEN }>::zeroed();
+    hk.expand(b"globalsend v1", okm.as_mut_bytes())?;
+    Ok(split_okm(okm.as_bytes()))
 }
 
-/// AEAD encrypt helper using XChaCha20-Poly1305
-pub fn aead_encrypt(key: &Key, base_nonce: &[u8; AEAD_NONCE_LEN], counter: u64, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, aead::Error> {
-    let cipher = XChaCha20Poly1305::new(key);
-    // Derive per-message nonce by xoring the base nonce with counter (simple construction)
-    let mut nonce_bytes = [0u8; AEAD_NONCE_LEN];
-    nonce_bytes.copy_from_slice(base_nonce);
+/// Split `key || base nonce` HKDF output without copying it out of secret storage
+pub(crate) fn split_okm(okm: &[u8]) -> (SecretKey, NonceSequence) {
+    let key = SecretKey::from_slice(&okm[..AEAD_KEY_LEN]);
+    let base = SecretBytes::from_slice(&okm[AEAD_KEY_LEN..AEAD_KEY_LEN + AEAD_NONCE_LEN]);
+    (key, NonceSequence::new(base))
+}
+
+/// Derive per-message nonce by xoring the base nonce with counter (simple construction)
+fn message_nonce(base_nonce: &[u8; AEAD_NONCE_LEN], counter: u64) -> SecretBytes<AEAD_NONCE_LEN> {
+    let mut nonce = SecretBytes::new(*base_nonce);
     // XOR counter into the last 8 bytes
-    let ctr_bytes = counter.to_be_bytes();
-    for i in 0..8 {
-        let idx = AEAD_NONCE_LEN - 8 + i;
-        nonce_bytes[idx] ^= ctr_bytes[i];
+    for (b, c) in nonce.as_mut_bytes()[AEAD_NONCE_LEN - 8..].iter_mut().zip(counter.to_be_bytes()) {
+        *b ^= c;
+    }
+    nonce
+}
+
+/// Base nonce plus a monotone message counter.
+///
+/// [`NonceSequence::next`] is the only way to get a nonce for
+/// [`aead_encrypt`], and each nonce is consumed by the call, so a key never
+/// encrypts twice under the same nonce. Decryption only needs the base and
+/// takes the message number explicitly.
+pub struct NonceSequence {
+    base: SecretBytes<AEAD_NONCE_LEN>,
+    next: u64,
+}
+
+impl NonceSequence {
+    pub(crate) fn new(base: SecretBytes<AEAD_NONCE_LEN>) -> Self {
+        Self { base, next: 0 }
+    }
+
+    /// Start a sequence from an externally managed base nonce
+    #[cfg(feature = "raw-nonce")]
+    pub fn from_base(base: [u8; AEAD_NONCE_LEN]) -> Self {
+        Self::new(SecretBytes::new(base))
+    }
+
+    /// Counter the next call to [`NonceSequence::next`] will use
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    /// Reserve the next message nonce; fails once the counter space is spent
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<MessageNonce, CryptoError> {
+        let counter = self.next;
+        self.next = counter.checked_add(1).ok_or(CryptoError::NonceExhausted)?;
+        Ok(MessageNonce { counter, bytes: message_nonce(self.base.as_bytes(), counter) })
+    }
+
+    pub(crate) fn at(&self, counter: u64) -> SecretBytes<AEAD_NONCE_LEN> {
+        message_nonce(self.base.as_bytes(), counter)
+    }
+
+    /// Reserve `n` consecutive nonces at once, e.g. to hand them to worker threads
+    #[cfg(feature = "rayon")]
+    pub(crate) fn reserve(&mut self, n: u64) -> Result<core::ops::Range<u64>, CryptoError> {
+        let first = self.next;
+        let end = first.checked_add(n).ok_or(CryptoError::NonceExhausted)?;
+        self.next = end;
+        Ok(first..end)
+    }
+
+    /// Nonce for a counter previously returned by [`NonceSequence::reserve`]
+    #[cfg(feature = "rayon")]
+    pub(crate) fn reserved(&self, counter: u64) -> MessageNonce {
+        MessageNonce { counter, bytes: self.at(counter) }
+    }
+}
+
+impl core::fmt::Debug for NonceSequence {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NonceSequence").field("position", &self.next).finish_non_exhaustive()
+    }
+}
+
+/// Single-use nonce handed out by [`NonceSequence::next`]
+#[derive(Debug)]
+pub struct MessageNonce {
+    counter: u64,
+    pub(crate) bytes: SecretBytes<AEAD_NONCE_LEN>,
+}
+
+impl MessageNonce {
+    /// Message number to send alongside the ciphertext
+    pub fn counter(&self) -> u64 {
+        self.counter
     }
-    let nonce = XNonce::from_slice(&nonce_bytes);
-    cipher.encrypt(nonce, aead::Payload { msg: plaintext, aad })
+}
+
+/// AEAD encrypt helper using XChaCha20-Poly1305
+pub fn aead_encrypt(key: &Key, nonce: MessageNonce, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let cipher = XChaCha20Poly1305::new(key);
+    cipher
+        .encrypt(XNonce::from_slice(nonce.bytes.as_bytes()), aead::Payload { msg: plaintext, aad })
+        .map_err(|_| CryptoError::Encrypt)
 }
 
 /// AEAD decrypt helper using XChaCha20-Poly1305
-pub fn aead_decrypt(key: &Key, base_nonce: &[u8; AEAD_NONCE_LEN], counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, aead::Error> {
+pub fn aead_decrypt(key: &Key, nonces: &NonceSequence, counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
     let cipher = XChaCha20Poly1305::new(key);
-    let mut nonce_bytes = [0u8; AEAD_NONCE_LEN];
-    nonce_bytes.copy_from_slice(base_nonce);
-    let ctr_bytes = counter.to_be_bytes();
-    for i in 0..8 {
-        let idx = AEAD_NONCE_LEN - 8 + i;
-        nonce_bytes[idx] ^= ctr_bytes[i];
-    }
-    let nonce = XNonce::from_slice(&nonce_bytes);
-    cipher.decrypt(nonce, aead::Payload { msg: ciphertext, aad })
+    cipher
+        .decrypt(XNonce::from_slice(nonces.at(counter).as_bytes()), aead::Payload { msg: ciphertext, aad })
+        .map_err(|_| events::decrypt_failed(CryptoError::Decrypt))
+}
+
+/// Encrypt with a caller-chosen counter; the caller must never repeat one
+#[cfg(feature = "raw-nonce")]
+pub fn aead_encrypt_raw(key: &Key, base_nonce: &[u8; AEAD_NONCE_LEN], counter: u64, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let cipher = XChaCha20Poly1305::new(key);
+    cipher
+        .encrypt(XNonce::from_slice(message_nonce(base_nonce, counter).as_bytes()), aead::Payload { msg: plaintext, aad })
+        .map_err(|_| CryptoError::Encrypt)
+}
+
+/// Counterpart of [`aead_encrypt_raw`]
+#[cfg(feature = "raw-nonce")]
+pub fn aead_decrypt_raw(key: &Key, base_nonce: &[u8; AEAD_NONCE_LEN], counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let cipher = XChaCha20Poly1305::new(key);
+    cipher
+        .decrypt(XNonce::from_slice(message_nonce(base_nonce, counter).as_bytes()), aead::Payload { msg: ciphertext, aad })
+        .map_err(|_| events::decrypt_failed(CryptoError::Decrypt))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hex::ToHex;
 
     #[test]
     fn ecdh_derive_encrypt_roundtrip() {
@@ -100,13 +334,22 @@ mod tests {
 
         let shared_a = a.ecdh(&b.public());
         let shared_b = b.ecdh(&a.public());
-        assert_eq!(shared_a, shared_b);
+        assert_eq!(shared_a.as_bytes(), shared_b.as_bytes());
 
-        let (key, base_nonce) = derive_aead(&shared_a);
+        let (key, mut nonces) = derive_aead(shared_a.as_bytes()).unwrap();
         let aad = b"meta";
         let msg = b"hello world from globalsend";
-        let ct = aead_encrypt(&key, &base_nonce, 1, aad, msg).expect("encrypt");
-        let pt = aead_decrypt(&key, &base_nonce, 1, aad, &ct).expect("decrypt");
+        let first = nonces.next().unwrap();
+        assert_eq!(first.counter(), 0);
+        let _ = aead_encrypt(key.as_key(), first, aad, b"first").expect("encrypt");
+        let nonce = nonces.next().unwrap();
+        assert_eq!(nonce.counter(), 1);
+        let ct = aead_encrypt(key.as_key(), nonce, aad, msg).expect("encrypt");
+        let pt = aead_decrypt(key.as_key(), &nonces, 1, aad, &ct).expect("decrypt");
         assert_eq!(pt, msg);
+        assert_eq!(aead_decrypt(key.as_key(), &nonces, 0, aad, &ct), Err(CryptoError::Decrypt));
+
+        let mut last = NonceSequence { base: SecretBytes::zeroed(), next: u64::MAX };
+        assert_eq!(last.next().unwrap_err(), CryptoError::NonceExhausted);
     }
 }
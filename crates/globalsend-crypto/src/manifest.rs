@@ -0,0 +1,275 @@
+//! Signed transfer manifests
+//!
+//! A manifest lists what a transfer contains (file names, sizes, content
+//! hashes) and who sent it. The sender signs it with the identity key, and the
+//! detached signature stays valid after the session ends, so a receiver can
+//! later prove which device sent which files.
+//!
+//! Encoding is canonical CBOR with entries sorted by name:
+//!
+//! ```text
+//! {0: version, 1: sender identity key, 2: created (unix secs),
+//!  3: [[name, size, hash algorithm, hash, mode, mtime], ...]}
+//! ```
+//!
+//! Version 2 added `mode` and `mtime` for folder transfers. A manifest
+//! without any file metadata is still encoded as version 1, whose entries
+//! stop after the hash, so signatures over older manifests keep verifying.
+//!
+//! The signature covers `"globalsend manifest v1"` followed by that encoding.
+//! [`minisign_manifest`] signs the bare encoding once more in the
+//! [`minisign`](crate::minisign) format, so the manifest saved as a file can
+//! be checked with `minisign -V` by someone without globalsend.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::hashing::{ContentHash, HashAlgorithm, HASH_LEN};
+use crate::identity::{self, DeviceIdentity, Fingerprint};
+use crate::minisign::{self, MinisignError, PublicKey};
+
+const MANIFEST_CONTEXT: &[u8] = b"globalsend manifest v1";
+pub const MANIFEST_VERSION: u64 = 2;
+/// Entries without `mode` and `mtime`
+const MANIFEST_VERSION_V1: u64 = 1;
+/// Upper bound on entries accepted when parsing
+pub const MAX_MANIFEST_ENTRIES: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestError {
+    /// Two entries share a name
+    DuplicateName,
+    BadSignature,
+    UnsupportedVersion(u64),
+    Cbor(CborError),
+    Minisign(MinisignError),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::DuplicateName => write!(f, "duplicate file name in manifest"),
+            ManifestError::BadSignature => write!(f, "invalid manifest signature"),
+            ManifestError::UnsupportedVersion(v) => write!(f, "unsupported manifest version {v}"),
+            ManifestError::Cbor(e) => write!(f, "invalid manifest encoding: {e}"),
+            ManifestError::Minisign(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl core::error::Error for ManifestError {}
+
+impl From<CborError> for ManifestError {
+    fn from(e: CborError) -> Self {
+        ManifestError::Cbor(e)
+    }
+}
+
+impl From<MinisignError> for ManifestError {
+    fn from(e: MinisignError) -> Self {
+        match e {
+            MinisignError::BadSignature => ManifestError::BadSignature,
+            e => ManifestError::Minisign(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Relative path using `/` separators
+    pub name: String,
+    pub size: u64,
+    pub hash: ContentHash,
+    /// Unix permission bits; 0 when not recorded
+    pub mode: u32,
+    /// Modification time in unix seconds; 0 when not recorded
+    pub mtime: u64,
+}
+
+impl ManifestEntry {
+    fn has_metadata(&self) -> bool {
+        self.mode != 0 || self.mtime != 0
+    }
+}
+
+/// What a transfer contains and who sent it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferManifest {
+    pub sender: VerifyingKey,
+    /// Unix seconds
+    pub created: u64,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl TransferManifest {
+    pub fn sender_fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.sender)
+    }
+
+    /// Canonical encoding; identical for manifests that differ only in entry order
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ManifestError> {
+        let mut entries: Vec<&ManifestEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        if entries.windows(2).any(|w| w[0].name == w[1].name) {
+            return Err(ManifestError::DuplicateName);
+        }
+        let version = if entries.iter().any(|e| e.has_metadata()) { MANIFEST_VERSION } else { MANIFEST_VERSION_V1 };
+        let mut e = Encoder::new();
+        e.map(4);
+        e.uint(0).uint(version);
+        e.uint(1).bytes(self.sender.as_bytes());
+        e.uint(2).uint(self.created);
+        e.uint(3).array(entries.len());
+        for entry in entries {
+            e.array(if version == MANIFEST_VERSION { 6 } else { 4 }).text(&entry.name).uint(entry.size);
+            e.text(entry.hash.algorithm().name()).bytes(entry.hash.as_bytes());
+            if version == MANIFEST_VERSION {
+                e.uint(entry.mode.into()).uint(entry.mtime);
+            }
+        }
+        Ok(e.finish())
+    }
+
+    /// Parse a canonical encoding; unsorted or duplicate entries are rejected
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ManifestError> {
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 4 {
+            return Err(CborError::Schema("manifest must have 4 fields").into());
+        }
+        d.key(0)?;
+        let version = d.uint()?;
+        let fields = match version {
+            MANIFEST_VERSION_V1 => 4,
+            MANIFEST_VERSION => 6,
+            _ => return Err(ManifestError::UnsupportedVersion(version)),
+        };
+        d.key(1)?;
+        let sender = VerifyingKey::from_bytes(&d.byte_array::<32>()?).map_err(|_| CborError::Schema("invalid sender key"))?;
+        d.key(2)?;
+        let created = d.uint()?;
+        d.key(3)?;
+        let count = d.array()?;
+        if count > MAX_MANIFEST_ENTRIES {
+            return Err(CborError::Schema("too many manifest entries").into());
+        }
+        let mut entries: Vec<ManifestEntry> = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            if d.array()? != fields {
+                return Err(CborError::Schema("wrong number of manifest entry fields").into());
+            }
+            let name = d.text()?.to_string();
+            let size = d.uint()?;
+            let algorithm = HashAlgorithm::from_name(d.text()?).ok_or(CborError::Schema("unknown hash algorithm"))?;
+            let hash = ContentHash::new(algorithm, d.byte_array::<HASH_LEN>()?);
+            let (mode, mtime) = match version {
+                MANIFEST_VERSION => (u32::try_from(d.uint()?).map_err(|_| CborError::Schema("mode out of range"))?, d.uint()?),
+                _ => (0, 0),
+            };
+            match entries.last() {
+                Some(prev) if prev.name == name => return Err(ManifestError::DuplicateName),
+                Some(prev) if prev.name > name => return Err(CborError::NonCanonical.into()),
+                _ => {}
+            }
+            entries.push(ManifestEntry { name, size, hash, mode, mtime });
+        }
+        d.finish()?;
+        if version == MANIFEST_VERSION && !entries.iter().any(|e| e.has_metadata()) {
+            // would have been encoded as version 1
+            return Err(CborError::NonCanonical.into());
+        }
+        Ok(Self { sender, created, entries })
+    }
+}
+
+fn signed_bytes(manifest: &TransferManifest) -> Result<Vec<u8>, ManifestError> {
+    Ok([MANIFEST_CONTEXT, &manifest.to_bytes()?].concat())
+}
+
+/// Detached signature over `manifest`; `manifest.sender` must be `identity`'s key
+pub fn sign_manifest(identity: &DeviceIdentity, manifest: &TransferManifest) -> Result<Signature, ManifestError> {
+    if manifest.sender != identity.verifying_key() {
+        return Err(ManifestError::BadSignature);
+    }
+    Ok(identity.sign(&signed_bytes(manifest)?))
+}
+
+/// Check that `manifest.sender` signed exactly this manifest
+pub fn verify_manifest(manifest: &TransferManifest, signature: &Signature) -> Result<(), ManifestError> {
+    identity::verify(&manifest.sender, &signed_bytes(manifest)?, signature).map_err(|_| ManifestError::BadSignature)
+}
+
+/// `.minisig` file contents for the encoding of `manifest`
+pub fn minisign_manifest(identity: &DeviceIdentity, manifest: &TransferManifest) -> Result<String, ManifestError> {
+    if manifest.sender != identity.verifying_key() {
+        return Err(ManifestError::BadSignature);
+    }
+    let comment = format!("timestamp:{}\tglobalsend manifest from {}", manifest.created, manifest.sender_fingerprint().to_hex());
+    Ok(minisign::sign(identity, &manifest.to_bytes()?, &comment)?)
+}
+
+/// Parse an encoded manifest and check its sender made `minisig` over exactly these bytes
+pub fn verify_manifest_minisign(bytes: &[u8], minisig: &str) -> Result<TransferManifest, ManifestError> {
+    let manifest = TransferManifest::from_bytes(bytes)?;
+    minisign::verify(&PublicKey::of(&manifest.sender), bytes, minisig)?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, data: &[u8]) -> ManifestEntry {
+        ManifestEntry { name: name.into(), size: data.len() as u64, hash: ContentHash::of(HashAlgorithm::Blake3, data), mode: 0, mtime: 0 }
+    }
+
+    #[test]
+    fn signature_survives_reencoding_and_detects_edits() {
+        let id = DeviceIdentity::generate();
+        let manifest = TransferManifest {
+            sender: id.verifying_key(),
+            created: 1_700_000_000,
+            entries: vec![entry("photos/b.jpg", b"bbb"), entry("a.txt", b"a")],
+        };
+        let sig = sign_manifest(&id, &manifest).unwrap();
+
+        let parsed = TransferManifest::from_bytes(&manifest.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.entries[0].name, "a.txt");
+        assert_eq!(verify_manifest(&parsed, &sig), Ok(()));
+        assert_eq!(parsed.sender_fingerprint(), id.fingerprint());
+
+        let mut edited = parsed.clone();
+        edited.entries[1].size += 1;
+        assert_eq!(verify_manifest(&edited, &sig), Err(ManifestError::BadSignature));
+
+        let mut dup = manifest.clone();
+        dup.entries.push(entry("a.txt", b"other"));
+        assert_eq!(dup.to_bytes(), Err(ManifestError::DuplicateName));
+        assert_eq!(sign_manifest(&DeviceIdentity::generate(), &manifest), Err(ManifestError::BadSignature));
+
+        let minisig = minisign_manifest(&id, &manifest).unwrap();
+        let bytes = manifest.to_bytes().unwrap();
+        assert_eq!(verify_manifest_minisign(&bytes, &minisig), Ok(parsed));
+        assert_eq!(verify_manifest_minisign(&edited.to_bytes().unwrap(), &minisig), Err(ManifestError::BadSignature));
+    }
+
+    #[test]
+    fn file_metadata_moves_to_version_2() {
+        let id = DeviceIdentity::generate();
+        let mut manifest = TransferManifest { sender: id.verifying_key(), created: 1, entries: vec![entry("a", b"a"), entry("dir/b", b"b")] };
+        assert_eq!(manifest.to_bytes().unwrap()[2], MANIFEST_VERSION_V1 as u8);
+
+        manifest.entries[1].mode = 0o755;
+        manifest.entries[1].mtime = 1_700_000_000;
+        let bytes = manifest.to_bytes().unwrap();
+        assert_eq!(bytes[2], MANIFEST_VERSION as u8);
+        let parsed = TransferManifest::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, manifest);
+        let sig = sign_manifest(&id, &manifest).unwrap();
+        assert_eq!(verify_manifest(&parsed, &sig), Ok(()));
+    }
+}
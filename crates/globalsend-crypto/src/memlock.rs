@@ -0,0 +1,201 @@
+//! Keep secrets out of swap and core dumps
+//!
+//! With the `memlock` feature every [`SecretBytes`](crate::secret::SecretBytes)
+//! lives in its own heap allocation whose pages are locked into RAM
+//! (`mlock` / `VirtualLock`) and, on Linux, excluded from core dumps
+//! (`MADV_DONTDUMP`). Several secrets can share a page, so pages are
+//! reference counted and only unlocked when the last secret on them is
+//! dropped.
+//!
+//! Locking is best effort: an exhausted `RLIMIT_MEMLOCK` or working-set
+//! quota leaves the page unlocked rather than failing key derivation.
+//! [`failed_locks`] reports how often that happened, so a receiver can warn
+//! at startup. [`disable_core_dumps`] additionally turns off core dumps for
+//! the whole process.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Lock count per page start address
+static PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+fn page_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(sys::page_size)
+}
+
+fn pages(ptr: *const u8, len: usize) -> impl Iterator<Item = usize> {
+    let size = page_size();
+    let start = ptr as usize & !(size - 1);
+    let end = ptr as usize + len.max(1);
+    (start..end).step_by(size)
+}
+
+/// Lock the pages under `ptr..ptr + len`
+pub(crate) fn lock(ptr: *const u8, len: usize) {
+    let mut locked = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages(ptr, len) {
+        let count = locked.entry(page).or_insert(0);
+        *count += 1;
+        if *count == 1 && !sys::lock(page, page_size()) {
+            FAILED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Release one lock on the pages under `ptr..ptr + len`; the memory must already be wiped
+pub(crate) fn unlock(ptr: *const u8, len: usize) {
+    let mut locked = PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for page in pages(ptr, len) {
+        if let Some(count) = locked.get_mut(&page) {
+            *count -= 1;
+            if *count == 0 {
+                locked.remove(&page);
+                sys::unlock(page, page_size());
+            }
+        }
+    }
+}
+
+/// Pages currently holding at least one secret
+pub fn locked_pages() -> usize {
+    PAGES.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+/// Pages the OS refused to lock since startup
+pub fn failed_locks() -> usize {
+    FAILED.load(Ordering::Relaxed)
+}
+
+/// Turn off core dumps for this process (Unix: `RLIMIT_CORE = 0`, Linux also `PR_SET_DUMPABLE = 0`)
+pub fn disable_core_dumps() -> io::Result<()> {
+    sys::disable_core_dumps()
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io;
+
+    pub fn page_size() -> usize {
+        // SAFETY: sysconf has no preconditions
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            n if n > 0 => n as usize,
+            _ => 4096,
+        }
+    }
+
+    pub fn lock(page: usize, len: usize) -> bool {
+        #[cfg(target_os = "linux")]
+        // SAFETY: `page` is the start of a mapped page that holds a live allocation; madvise does not touch its contents
+        unsafe {
+            libc::madvise(page as *mut libc::c_void, len, libc::MADV_DONTDUMP);
+        }
+        // SAFETY: as above; mlock only changes residency
+        unsafe { libc::mlock(page as *const libc::c_void, len) == 0 }
+    }
+
+    pub fn unlock(page: usize, len: usize) {
+        // SAFETY: `page` is still mapped (the allocation on it is being dropped, not yet freed)
+        unsafe {
+            libc::munlock(page as *const libc::c_void, len);
+        }
+    }
+
+    pub fn disable_core_dumps() -> io::Result<()> {
+        let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        // SAFETY: `limit` is a valid rlimit
+        if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE takes a single integer argument
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+
+    use windows_sys::Win32::System::Memory::{VirtualLock, VirtualUnlock};
+    use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+    pub fn page_size() -> usize {
+        // SAFETY: GetSystemInfo fills the struct it is given
+        let info = unsafe {
+            let mut info: SYSTEM_INFO = std::mem::zeroed();
+            GetSystemInfo(&mut info);
+            info
+        };
+        info.dwPageSize as usize
+    }
+
+    pub fn lock(page: usize, len: usize) -> bool {
+        // SAFETY: `page` is the start of a committed page that holds a live allocation
+        unsafe { VirtualLock(page as *const _, len) != 0 }
+    }
+
+    pub fn unlock(page: usize, len: usize) {
+        // SAFETY: as above
+        unsafe {
+            VirtualUnlock(page as *const _, len);
+        }
+    }
+
+    /// Windows has no per-process switch; crash dumps are configured through WER
+    pub fn disable_core_dumps() -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::io;
+
+    pub fn page_size() -> usize {
+        4096
+    }
+
+    pub fn lock(_page: usize, _len: usize) -> bool {
+        false
+    }
+
+    pub fn unlock(_page: usize, _len: usize) {}
+
+    pub fn disable_core_dumps() -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "no core dump control on this platform"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretBytes;
+
+    #[test]
+    fn pages_are_refcounted() {
+        let size = page_size();
+        let buf = vec![0u8; 3 * size];
+        // a whole page inside `buf`, so no other test's secrets can land on it
+        let page = (buf.as_ptr() as usize + size) & !(size - 1);
+        let count = || PAGES.lock().unwrap().get(&page).copied();
+
+        lock(page as *const u8, 16);
+        lock((page + 100) as *const u8, 16);
+        assert_eq!(count(), Some(2));
+        unlock(page as *const u8, 16);
+        assert_eq!(count(), Some(1));
+        unlock((page + 100) as *const u8, 16);
+        assert_eq!(count(), None);
+
+        let secret = SecretBytes::<32>::new([1; 32]);
+        let page = secret.as_bytes().as_ptr() as usize & !(size - 1);
+        assert!(PAGES.lock().unwrap().contains_key(&page));
+    }
+}
@@ -0,0 +1,254 @@
+//! Merkle trees over fixed-size chunks
+//!
+//! A sender hashes each chunk of a file into a leaf and publishes (and signs)
+//! only the root. A receiver resuming a transfer can check any single chunk
+//! against that root with a short inclusion proof, instead of re-hashing the
+//! whole file.
+//!
+//! The tree shape and proofs follow RFC 9162 (Certificate Transparency v2)
+//! with BLAKE3 as the hash:
+//!
+//! ```text
+//! leaf = BLAKE3(0x00 || chunk)
+//! node = BLAKE3(0x01 || left || right)
+//! ```
+//!
+//! where the left subtree always holds the largest power of two of leaves.
+//! Proofs encode as `index u64 BE || leaf_count u64 BE || 32-byte hashes...`.
+
+#[cfg(feature = "std")]
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+use crate::ct::ct_eq_array;
+use crate::hashing::HASH_LEN;
+#[cfg(feature = "std")]
+use crate::stream::read_full;
+
+pub type NodeHash = [u8; HASH_LEN];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+/// Deepest tree a proof may describe; 2^64 leaves
+const MAX_PROOF_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MerkleError {
+    /// Proof bytes are truncated or too long
+    Malformed,
+    /// Proof does not lead from the chunk to the root
+    Mismatch,
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MerkleError::Malformed => write!(f, "malformed merkle proof"),
+            MerkleError::Mismatch => write!(f, "chunk does not match merkle root"),
+        }
+    }
+}
+
+impl core::error::Error for MerkleError {}
+
+pub fn leaf_hash(chunk: &[u8]) -> NodeHash {
+    let mut h = blake3::Hasher::new();
+    h.update(&[LEAF_PREFIX]);
+    h.update(chunk);
+    *h.finalize().as_bytes()
+}
+
+fn node_hash(left: &NodeHash, right: &NodeHash) -> NodeHash {
+    let mut h = blake3::Hasher::new();
+    h.update(&[NODE_PREFIX]);
+    h.update(left);
+    h.update(right);
+    *h.finalize().as_bytes()
+}
+
+/// Largest power of two strictly less than `n` (n >= 2)
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+fn subtree_root(leaves: &[NodeHash]) -> NodeHash {
+    match leaves.len() {
+        0 => *blake3::hash(b"").as_bytes(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&subtree_root(&leaves[..k]), &subtree_root(&leaves[k..]))
+        }
+    }
+}
+
+fn audit_path(index: usize, leaves: &[NodeHash], out: &mut Vec<NodeHash>) {
+    let n = leaves.len();
+    if n <= 1 {
+        return;
+    }
+    let k = split_point(n);
+    if index < k {
+        audit_path(index, &leaves[..k], out);
+        out.push(subtree_root(&leaves[k..]));
+    } else {
+        audit_path(index - k, &leaves[k..], out);
+        out.push(subtree_root(&leaves[..k]));
+    }
+}
+
+/// Leaf hashes of a chunked file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    chunk_len: usize,
+    total_len: u64,
+    leaves: Vec<NodeHash>,
+}
+
+impl MerkleTree {
+    /// Hash `reader` in `chunk_len` pieces; only the last chunk may be shorter
+    #[cfg(feature = "std")]
+    pub fn build_from_reader<R: Read>(mut reader: R, chunk_len: usize) -> io::Result<Self> {
+        assert!(chunk_len > 0, "chunk_len must be non-zero");
+        let mut buf = vec![0u8; chunk_len];
+        let mut leaves = Vec::new();
+        let mut total_len = 0u64;
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            leaves.push(leaf_hash(&buf[..n]));
+            total_len += n as u64;
+            if n < chunk_len {
+                break;
+            }
+        }
+        Ok(Self { chunk_len, total_len, leaves })
+    }
+
+    pub fn root(&self) -> NodeHash {
+        subtree_root(&self.leaves)
+    }
+
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Bytes hashed
+    pub fn total_len(&self) -> u64 {
+        self.total_len
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    /// Inclusion proof for chunk `index`
+    pub fn proof(&self, index: u64) -> Option<MerkleProof> {
+        let i = usize::try_from(index).ok().filter(|&i| i < self.leaves.len())?;
+        let mut path = Vec::new();
+        audit_path(i, &self.leaves, &mut path);
+        Some(MerkleProof { index, leaf_count: self.leaf_count(), path })
+    }
+}
+
+/// Audit path from one leaf to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaf_count: u64,
+    pub path: Vec<NodeHash>,
+}
+
+impl MerkleProof {
+    /// Check that `chunk` is leaf `index` of the tree with `root` (RFC 9162 §2.1.3.2)
+    pub fn verify(&self, root: &NodeHash, chunk: &[u8]) -> Result<(), MerkleError> {
+        if self.index >= self.leaf_count {
+            return Err(MerkleError::Mismatch);
+        }
+        let (mut f, mut s) = (self.index, self.leaf_count - 1);
+        let mut r = leaf_hash(chunk);
+        for p in &self.path {
+            if s == 0 {
+                return Err(MerkleError::Mismatch);
+            }
+            if f & 1 == 1 || f == s {
+                r = node_hash(p, &r);
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            } else {
+                r = node_hash(&r, p);
+            }
+            f >>= 1;
+            s >>= 1;
+        }
+        if s != 0 || !ct_eq_array(&r, root) {
+            return Err(MerkleError::Mismatch);
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.path.len() * HASH_LEN);
+        out.extend_from_slice(&self.index.to_be_bytes());
+        out.extend_from_slice(&self.leaf_count.to_be_bytes());
+        for h in &self.path {
+            out.extend_from_slice(h);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        if bytes.len() < 16 || !(bytes.len() - 16).is_multiple_of(HASH_LEN) || (bytes.len() - 16) / HASH_LEN > MAX_PROOF_LEN {
+            return Err(MerkleError::Malformed);
+        }
+        let index = u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes"));
+        let leaf_count = u64::from_be_bytes(bytes[8..16].try_into().expect("8 bytes"));
+        let path = bytes[16..].chunks_exact(HASH_LEN).map(|c| c.try_into().expect("hash length")).collect();
+        Ok(Self { index, leaf_count, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_chunk_proves_against_root() {
+        let chunk_len = 16;
+        for chunks in [1usize, 2, 3, 5, 8, 13] {
+            let data: Vec<u8> = (0..chunks * chunk_len - 3).map(|i| (i % 251) as u8).collect();
+            let tree = MerkleTree::build_from_reader(&data[..], chunk_len).unwrap();
+            assert_eq!(tree.leaf_count(), chunks as u64);
+            assert_eq!(tree.total_len(), data.len() as u64);
+            let root = tree.root();
+
+            for (i, chunk) in data.chunks(chunk_len).enumerate() {
+                let proof = MerkleProof::from_bytes(&tree.proof(i as u64).unwrap().to_bytes()).unwrap();
+                assert_eq!(proof.verify(&root, chunk), Ok(()), "{chunks} chunks, leaf {i}");
+                let mut bad = chunk.to_vec();
+                bad[0] ^= 1;
+                assert_eq!(proof.verify(&root, &bad), Err(MerkleError::Mismatch));
+            }
+            assert!(tree.proof(chunks as u64).is_none());
+        }
+    }
+
+    #[test]
+    fn proof_bound_to_position() {
+        let data = [[1u8; 4], [2; 4], [3; 4], [4; 4]].concat();
+        let tree = MerkleTree::build_from_reader(&data[..], 4).unwrap();
+        let mut proof = tree.proof(1).unwrap();
+        proof.index = 2;
+        assert_eq!(proof.verify(&tree.root(), &[2; 4]), Err(MerkleError::Mismatch));
+        assert_eq!(MerkleProof::from_bytes(&[0; 17]), Err(MerkleError::Malformed));
+        // leaves and nodes are domain separated
+        assert_ne!(leaf_hash(&[[0u8; 32], [0; 32]].concat()), node_hash(&[0; 32], &[0; 32]));
+    }
+}
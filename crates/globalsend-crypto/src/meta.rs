@@ -0,0 +1,298 @@
+//! Encrypted transfer metadata
+//!
+//! File names, MIME types and thumbnails often say more than the file
+//! contents, so they travel in a [`MetaEnvelope`] rather than in whatever
+//! framing the bulk data channel uses. Envelopes are sealed under their own
+//! per-direction key, `HKDF(file_root, "globalsend metadata key v1")`, with
+//! the [`SessionKeys::session_id`] in the AAD: an envelope only opens in the
+//! session it was made for, whatever carries it (QUIC stream, HTTP body,
+//! relay message).
+//!
+//! The plaintext is canonical CBOR padded to a multiple of [`META_PAD_LEN`]
+//! (ISO/IEC 7816-4: `0x80` then zeros), so ciphertext length leaks only a
+//! coarse size class rather than the length of each name.
+//!
+//! ```text
+//! envelope: message number (u64 BE) || ciphertext
+//! entry:    {0: name, 1: mime, 2: size, ?3: thumbnail, ?4: text}
+//! ```
+//!
+//! Previews are capped at [`MAX_THUMBNAIL_LEN`] and [`MAX_TEXT_PREVIEW_LEN`]
+//! bytes. Passing those caps says nothing about what is inside; receivers
+//! re-validate previews before showing them.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::replay::{ReplayError, ReplayFilter};
+use crate::secret::SecretKey;
+use crate::session::SessionKeys;
+use crate::{aead_decrypt, aead_encrypt, CryptoError, NonceSequence};
+
+const META_AAD_CONTEXT: &[u8] = b"globalsend metadata v1";
+pub const META_HEADER_LEN: usize = 8;
+/// Plaintext is padded to a multiple of this many bytes
+pub const META_PAD_LEN: usize = 256;
+pub const MAX_META_LEN: usize = 4 << 20;
+pub const MAX_NAME_LEN: usize = 4096;
+pub const MAX_MIME_LEN: usize = 255;
+pub const MAX_THUMBNAIL_LEN: usize = 64 << 10;
+pub const MAX_TEXT_PREVIEW_LEN: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaError {
+    TooLarge,
+    Malformed,
+    Replay(ReplayError),
+    Crypto(CryptoError),
+    Cbor(CborError),
+}
+
+impl fmt::Display for MetaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetaError::TooLarge => write!(f, "metadata larger than {MAX_META_LEN} bytes"),
+            MetaError::Malformed => write!(f, "malformed metadata envelope"),
+            MetaError::Replay(e) => write!(f, "metadata envelope rejected: {e}"),
+            MetaError::Crypto(e) => write!(f, "metadata envelope: {e}"),
+            MetaError::Cbor(e) => write!(f, "invalid metadata encoding: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for MetaError {}
+
+impl From<ReplayError> for MetaError {
+    fn from(e: ReplayError) -> Self {
+        MetaError::Replay(e)
+    }
+}
+
+impl From<CryptoError> for MetaError {
+    fn from(e: CryptoError) -> Self {
+        MetaError::Crypto(e)
+    }
+}
+
+impl From<CborError> for MetaError {
+    fn from(e: CborError) -> Self {
+        MetaError::Cbor(e)
+    }
+}
+
+/// What the receiver learns about one file before accepting it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMeta {
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    /// Small preview image, if the sender made one
+    pub thumbnail: Option<Vec<u8>>,
+    /// Start of a text file, if the sender included it
+    pub text: Option<String>,
+}
+
+/// Metadata for every file in a batch, in transfer order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferMetadata {
+    pub files: Vec<FileMeta>,
+}
+
+impl TransferMetadata {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.array(self.files.len());
+        for file in &self.files {
+            e.map(3 + usize::from(file.thumbnail.is_some()) + usize::from(file.text.is_some()));
+            e.uint(0).text(&file.name);
+            e.uint(1).text(&file.mime);
+            e.uint(2).uint(file.size);
+            if let Some(thumbnail) = &file.thumbnail {
+                e.uint(3).bytes(thumbnail);
+            }
+            if let Some(text) = &file.text {
+                e.uint(4).text(text);
+            }
+        }
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MetaError> {
+        let mut d = Decoder::new(bytes);
+        let count = d.array()?;
+        // every entry takes at least a few bytes, so this bounds the allocation
+        let mut files = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let fields = d.map()?;
+            if !(3..=5).contains(&fields) {
+                return Err(CborError::Schema("file metadata must have 3 to 5 fields").into());
+            }
+            d.key(0)?;
+            let name = d.text()?;
+            d.key(1)?;
+            let mime = d.text()?;
+            if name.len() > MAX_NAME_LEN || mime.len() > MAX_MIME_LEN {
+                return Err(CborError::Schema("file name or MIME type too long").into());
+            }
+            d.key(2)?;
+            let size = d.uint()?;
+            let (mut thumbnail, mut text) = (None, None);
+            let mut last = 2;
+            for _ in 3..fields {
+                let key = d.uint()?;
+                if key <= last {
+                    return Err(CborError::Schema("file metadata keys out of order").into());
+                }
+                last = key;
+                match key {
+                    3 => thumbnail = Some(d.bytes()?.to_vec()),
+                    4 => text = Some(d.text()?.to_string()),
+                    _ => return Err(CborError::Schema("unknown file metadata key").into()),
+                }
+            }
+            if thumbnail.as_ref().is_some_and(|t| t.len() > MAX_THUMBNAIL_LEN) || text.as_ref().is_some_and(|t| t.len() > MAX_TEXT_PREVIEW_LEN) {
+                return Err(CborError::Schema("preview too long").into());
+            }
+            files.push(FileMeta { name: name.to_string(), mime: mime.to_string(), size, thumbnail, text });
+        }
+        d.finish()?;
+        Ok(Self { files })
+    }
+}
+
+/// Sealed [`TransferMetadata`] as sent on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaEnvelope {
+    pub counter: u64,
+    pub ciphertext: Vec<u8>,
+}
+
+impl MetaEnvelope {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.counter.to_be_bytes()[..], &self.ciphertext].concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MetaError> {
+        if bytes.len() < META_HEADER_LEN + 16 {
+            return Err(MetaError::Malformed);
+        }
+        if bytes.len() > META_HEADER_LEN + MAX_META_LEN + 16 {
+            return Err(MetaError::TooLarge);
+        }
+        let (counter, ciphertext) = bytes.split_at(META_HEADER_LEN);
+        Ok(Self { counter: u64::from_be_bytes(counter.try_into().expect("8 bytes")), ciphertext: ciphertext.to_vec() })
+    }
+}
+
+fn aad(session_id: &[u8; 32]) -> Vec<u8> {
+    [META_AAD_CONTEXT, session_id].concat()
+}
+
+fn pad(mut plaintext: Vec<u8>) -> Vec<u8> {
+    plaintext.push(0x80);
+    plaintext.resize(plaintext.len().div_ceil(META_PAD_LEN) * META_PAD_LEN, 0);
+    plaintext
+}
+
+fn unpad(plaintext: &[u8]) -> Result<&[u8], MetaError> {
+    let end = plaintext.iter().rposition(|&b| b != 0).ok_or(MetaError::Malformed)?;
+    if plaintext[end] != 0x80 {
+        return Err(MetaError::Malformed);
+    }
+    Ok(&plaintext[..end])
+}
+
+/// Sending half: seals metadata for the peer
+pub struct MetaSealer {
+    key: SecretKey,
+    nonces: NonceSequence,
+    aad: Vec<u8>,
+}
+
+impl MetaSealer {
+    pub fn new(session: &SessionKeys) -> Result<Self, CryptoError> {
+        let (key, nonces) = session.send.derive_meta_key()?;
+        Ok(Self { key, nonces, aad: aad(&session.session_id()) })
+    }
+
+    pub fn seal(&mut self, metadata: &TransferMetadata) -> Result<MetaEnvelope, MetaError> {
+        let encoded = metadata.to_bytes();
+        if encoded.len() >= MAX_META_LEN {
+            return Err(MetaError::TooLarge);
+        }
+        let nonce = self.nonces.next()?;
+        let counter = nonce.counter();
+        let ciphertext = aead_encrypt(self.key.as_key(), nonce, &self.aad, &pad(encoded))?;
+        Ok(MetaEnvelope { counter, ciphertext })
+    }
+}
+
+/// Receiving half: opens the peer's envelopes, each at most once
+pub struct MetaOpener {
+    key: SecretKey,
+    nonces: NonceSequence,
+    aad: Vec<u8>,
+    replay: ReplayFilter,
+}
+
+impl MetaOpener {
+    pub fn new(session: &SessionKeys) -> Result<Self, CryptoError> {
+        let (key, nonces) = session.recv.derive_meta_key()?;
+        Ok(Self { key, nonces, aad: aad(&session.session_id()), replay: ReplayFilter::new() })
+    }
+
+    pub fn open(&mut self, envelope: &MetaEnvelope) -> Result<TransferMetadata, MetaError> {
+        self.replay.check(envelope.counter)?;
+        let plaintext = aead_decrypt(self.key.as_key(), &self.nonces, envelope.counter, &self.aad, &envelope.ciphertext)?;
+        let metadata = TransferMetadata::from_bytes(unpad(&plaintext)?)?;
+        self.replay.update(envelope.counter)?;
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceKey, EphemeralKey};
+
+    fn pair() -> (SessionKeys, SessionKeys) {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let (ea, eb) = (EphemeralKey::generate(), EphemeralKey::generate());
+        let (pa, pb) = (ea.public(), eb.public());
+        let ka = SessionKeys::derive(&a, ea, &b.public(), &pb).unwrap();
+        let kb = SessionKeys::derive(&b, eb, &a.public(), &pa).unwrap();
+        (ka, kb)
+    }
+
+    #[test]
+    fn envelope_roundtrip_is_bound_to_session() {
+        let (alice, bob) = pair();
+        let metadata = TransferMetadata {
+            files: vec![
+                FileMeta { name: "holiday.jpg".into(), mime: "image/jpeg".into(), size: 3_000_000, thumbnail: Some(vec![0xff; 900]), text: None },
+                FileMeta { name: "notes.txt".into(), mime: "text/plain".into(), size: 12, thumbnail: None, text: Some("milk, eggs\n".into()) },
+            ],
+        };
+
+        let mut sealer = MetaSealer::new(&alice).unwrap();
+        let envelope = sealer.seal(&metadata).unwrap();
+        assert_eq!(envelope.ciphertext.len() % META_PAD_LEN, 16);
+        let wire = MetaEnvelope::from_bytes(&envelope.to_bytes()).unwrap();
+
+        let mut opener = MetaOpener::new(&bob).unwrap();
+        assert_eq!(opener.open(&wire).unwrap(), metadata);
+        assert_eq!(opener.open(&wire), Err(MetaError::Replay(ReplayError::Duplicate)));
+
+        // the same envelope does not open in another session, nor in the sender's own direction
+        let (_, carol) = pair();
+        assert_eq!(MetaOpener::new(&carol).unwrap().open(&wire), Err(MetaError::Crypto(CryptoError::Decrypt)));
+        assert_eq!(MetaOpener::new(&alice).unwrap().open(&wire), Err(MetaError::Crypto(CryptoError::Decrypt)));
+        assert_eq!(MetaEnvelope::from_bytes(&[0; 20]), Err(MetaError::Malformed));
+
+        let mut oversized = metadata.clone();
+        oversized.files[0].thumbnail = Some(vec![0; MAX_THUMBNAIL_LEN + 1]);
+        assert!(TransferMetadata::from_bytes(&oversized.to_bytes()).is_err());
+    }
+}
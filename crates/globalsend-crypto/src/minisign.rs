@@ -0,0 +1,173 @@
+//! Signatures in the minisign format
+//!
+//! Lets someone check what a device signed with the stock
+//! [`minisign`](https://jedisct1.github.io/minisign/) or `rsign` tools, no
+//! globalsend needed: [`public_key`] is the `.pub` file for a device key and
+//! [`sign`] writes a `.minisig` file.
+//!
+//! ```text
+//! untrusted comment: <anything>
+//! base64("ED" || key id || Ed25519(BLAKE2b-512(data)))
+//! trusted comment: <one line>
+//! base64(Ed25519(signature || trusted comment))
+//! ```
+//!
+//! Only the prehashed `ED` algorithm is signed with: the identity key never
+//! signs raw file bytes, only a 64-byte hash, which no other globalsend
+//! context can be mistaken for. Legacy `Ed` signatures still verify. The
+//! key id, which minisign makes up at random, is the first 8 bytes of the
+//! device [`Fingerprint`], so it is the same wherever the key is exported.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::identity::{self, DeviceIdentity, Fingerprint};
+
+pub const KEY_ID_LEN: usize = 8;
+const PUBLIC_KEY_ALGORITHM: &[u8; 2] = b"Ed";
+const PREHASHED: &[u8; 2] = b"ED";
+const LEGACY: &[u8; 2] = b"Ed";
+const UNTRUSTED: &str = "untrusted comment: ";
+const TRUSTED: &str = "trusted comment: ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MinisignError {
+    Malformed(&'static str),
+    /// Signature algorithm other than `Ed` or `ED`
+    UnsupportedAlgorithm,
+    /// Signed by a key with another id
+    WrongKey,
+    BadSignature,
+    /// Trusted comments are one line
+    MultilineComment,
+}
+
+impl fmt::Display for MinisignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinisignError::Malformed(what) => write!(f, "malformed minisign file: {what}"),
+            MinisignError::UnsupportedAlgorithm => write!(f, "unsupported minisign signature algorithm"),
+            MinisignError::WrongKey => write!(f, "minisign signature is from a different key"),
+            MinisignError::BadSignature => write!(f, "invalid minisign signature"),
+            MinisignError::MultilineComment => write!(f, "trusted comment must be a single line"),
+        }
+    }
+}
+
+impl core::error::Error for MinisignError {}
+
+/// A minisign public key: the Ed25519 key and the id signatures name it by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey {
+    pub key_id: [u8; KEY_ID_LEN],
+    pub key: VerifyingKey,
+}
+
+impl PublicKey {
+    /// The key as exported by [`public_key`]
+    pub fn of(key: &VerifyingKey) -> Self {
+        let mut key_id = [0; KEY_ID_LEN];
+        key_id.copy_from_slice(&Fingerprint::of(key).as_bytes()[..KEY_ID_LEN]);
+        Self { key_id, key: *key }
+    }
+
+    /// The id as minisign prints it: a little-endian integer in upper-case hex
+    pub fn key_id_hex(&self) -> String {
+        format!("{:016X}", u64::from_le_bytes(self.key_id))
+    }
+
+    /// The base64 line, as `minisign -P` takes it
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode([&PUBLIC_KEY_ALGORITHM[..], &self.key_id, self.key.as_bytes()].concat())
+    }
+}
+
+/// Contents of a `.pub` file for `key`
+pub fn public_key(key: &VerifyingKey) -> String {
+    let public = PublicKey::of(key);
+    format!("{UNTRUSTED}minisign public key {}\n{}\n", public.key_id_hex(), public.to_base64())
+}
+
+/// Read a `.pub` file, or just its base64 line
+pub fn parse_public_key(text: &str) -> Result<PublicKey, MinisignError> {
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with(UNTRUSTED)).ok_or(MinisignError::Malformed("no public key"))?;
+    let bytes = STANDARD.decode(line).map_err(|_| MinisignError::Malformed("invalid base64"))?;
+    if bytes.len() != 2 + KEY_ID_LEN + 32 {
+        return Err(MinisignError::Malformed("wrong public key length"));
+    }
+    if bytes[..2] != PUBLIC_KEY_ALGORITHM[..] {
+        return Err(MinisignError::UnsupportedAlgorithm);
+    }
+    let key = VerifyingKey::from_bytes(bytes[2 + KEY_ID_LEN..].try_into().expect("length checked")).map_err(|_| MinisignError::Malformed("invalid Ed25519 key"))?;
+    Ok(PublicKey { key_id: bytes[2..2 + KEY_ID_LEN].try_into().expect("length checked"), key })
+}
+
+/// Contents of a `.minisig` file for `data`, carrying `trusted_comment`
+pub fn sign(identity: &DeviceIdentity, data: &[u8], trusted_comment: &str) -> Result<String, MinisignError> {
+    if trusted_comment.contains(['\r', '\n']) {
+        return Err(MinisignError::MultilineComment);
+    }
+    let public = PublicKey::of(&identity.verifying_key());
+    let signature = identity.sign(&Blake2b512::digest(data)).to_bytes();
+    let global = identity.sign(&[&signature[..], trusted_comment.as_bytes()].concat());
+    Ok(format!(
+        "{UNTRUSTED}signature from globalsend key {}\n{}\n{TRUSTED}{trusted_comment}\n{}\n",
+        public.key_id_hex(),
+        STANDARD.encode([&PREHASHED[..], &public.key_id, &signature].concat()),
+        STANDARD.encode(global.to_bytes()),
+    ))
+}
+
+/// Check a `.minisig` file over `data` against `public`; gives back the trusted comment
+pub fn verify(public: &PublicKey, data: &[u8], minisig: &str) -> Result<String, MinisignError> {
+    let mut lines = minisig.lines();
+    lines.next().filter(|l| l.starts_with(UNTRUSTED)).ok_or(MinisignError::Malformed("no untrusted comment"))?;
+    let signature = lines.next().map(|l| STANDARD.decode(l.trim())).ok_or(MinisignError::Malformed("no signature"))?.map_err(|_| MinisignError::Malformed("invalid base64"))?;
+    let comment = lines.next().and_then(|l| l.strip_prefix(TRUSTED)).ok_or(MinisignError::Malformed("no trusted comment"))?;
+    let global = lines.next().map(|l| STANDARD.decode(l.trim())).ok_or(MinisignError::Malformed("no global signature"))?.map_err(|_| MinisignError::Malformed("invalid base64"))?;
+    if signature.len() != 2 + KEY_ID_LEN + 64 {
+        return Err(MinisignError::Malformed("wrong signature length"));
+    }
+    let (algorithm, rest) = signature.split_at(2);
+    let (key_id, signature) = rest.split_at(KEY_ID_LEN);
+    if key_id != public.key_id {
+        return Err(MinisignError::WrongKey);
+    }
+    let signature_bytes = Signature::from_slice(signature).map_err(|_| MinisignError::BadSignature)?;
+    let verified = match <&[u8; 2]>::try_from(algorithm).expect("split at 2") {
+        PREHASHED => identity::verify(&public.key, &Blake2b512::digest(data), &signature_bytes),
+        LEGACY => identity::verify(&public.key, data, &signature_bytes),
+        _ => return Err(MinisignError::UnsupportedAlgorithm),
+    };
+    verified.map_err(|_| MinisignError::BadSignature)?;
+    let global = Signature::from_slice(&global).map_err(|_| MinisignError::Malformed("wrong global signature length"))?;
+    identity::verify(&public.key, &[signature, comment.as_bytes()].concat(), &global).map_err(|_| MinisignError::BadSignature)?;
+    Ok(comment.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_and_verifies_minisign_files() {
+        let id = DeviceIdentity::generate();
+        let public = parse_public_key(&public_key(&id.verifying_key())).unwrap();
+        assert_eq!(public, PublicKey::of(&id.verifying_key()));
+        assert_eq!(parse_public_key(&public.to_base64()), Ok(public));
+
+        let minisig = sign(&id, b"artifact", "timestamp:1700000000\tfile:a.manifest").unwrap();
+        assert_eq!(verify(&public, b"artifact", &minisig).unwrap(), "timestamp:1700000000\tfile:a.manifest");
+        assert_eq!(verify(&public, b"artifacT", &minisig), Err(MinisignError::BadSignature));
+        let edited = minisig.replace("a.manifest", "b.manifest");
+        assert_eq!(verify(&public, b"artifact", &edited), Err(MinisignError::BadSignature));
+        assert_eq!(verify(&PublicKey::of(&DeviceIdentity::generate().verifying_key()), b"artifact", &minisig), Err(MinisignError::WrongKey));
+        assert_eq!(sign(&id, b"artifact", "two\nlines"), Err(MinisignError::MultilineComment));
+    }
+}
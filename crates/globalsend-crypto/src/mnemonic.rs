@@ -0,0 +1,126 @@
+//! Recovery phrases for [`DeviceKey`]
+//!
+//! The 32-byte device secret is written as a 24-word BIP39 mnemonic (English
+//! word list, 8-bit checksum), so a user can restore the same identity, and
+//! with it every existing pairing, after reinstalling.
+//!
+//! With a passphrase the phrase encodes `secret XOR Argon2id(passphrase)`
+//! instead of the secret itself. As in BIP39, every passphrase restores
+//! *some* key: a typo is not detected here, so compare the restored public key
+//! or fingerprint with the expected one before trusting it.
+
+use std::fmt;
+
+use bip39::{Language, Mnemonic};
+use zeroize::Zeroizing;
+
+use crate::encoding::SECRET_KEY_LEN;
+use crate::keyfile::{derive_kek, KdfParams, SALT_LEN};
+use crate::DeviceKey;
+
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// Fixed salt: the phrase has no room to carry one
+const MNEMONIC_SALT: &[u8; SALT_LEN] = b"gs mnemonic v1\0\0";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MnemonicError {
+    WordCount(usize),
+    /// Word at this position is not in the word list
+    UnknownWord(usize),
+    /// Checksum mismatch; a word was mistyped or the order is wrong
+    Checksum,
+    /// Passphrase key derivation failed
+    Passphrase,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MnemonicError::WordCount(n) => write!(f, "recovery phrase must have {MNEMONIC_WORDS} words, got {n}"),
+            MnemonicError::UnknownWord(i) => write!(f, "word {} of the recovery phrase is not in the word list", i + 1),
+            MnemonicError::Checksum => write!(f, "recovery phrase checksum mismatch"),
+            MnemonicError::Passphrase => write!(f, "recovery passphrase key derivation failed"),
+        }
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+impl From<bip39::Error> for MnemonicError {
+    fn from(e: bip39::Error) -> Self {
+        match e {
+            bip39::Error::BadWordCount(n) => MnemonicError::WordCount(n),
+            bip39::Error::UnknownWord(i) => MnemonicError::UnknownWord(i),
+            _ => MnemonicError::Checksum,
+        }
+    }
+}
+
+/// XOR the secret with the passphrase mask; an empty passphrase leaves it unchanged
+fn apply_passphrase(secret: &mut [u8; SECRET_KEY_LEN], passphrase: &str, params: &KdfParams) -> Result<(), MnemonicError> {
+    if passphrase.is_empty() {
+        return Ok(());
+    }
+    let mask = derive_kek(passphrase.as_bytes(), MNEMONIC_SALT, params).map_err(|_| MnemonicError::Passphrase)?;
+    secret.iter_mut().zip(mask.as_bytes()).for_each(|(s, m)| *s ^= m);
+    Ok(())
+}
+
+impl DeviceKey {
+    /// 24-word recovery phrase; `passphrase` may be empty
+    pub fn to_mnemonic(&self, passphrase: &str) -> Result<Zeroizing<String>, MnemonicError> {
+        self.to_mnemonic_with_params(passphrase, &KdfParams::default())
+    }
+
+    pub fn to_mnemonic_with_params(&self, passphrase: &str, params: &KdfParams) -> Result<Zeroizing<String>, MnemonicError> {
+        let mut entropy = self.to_bytes();
+        apply_passphrase(&mut entropy, passphrase, params)?;
+        let mnemonic = Mnemonic::from_entropy_in(Language::English, entropy.as_ref())?;
+        Ok(Zeroizing::new(mnemonic.to_string()))
+    }
+
+    /// Restore a key from [`DeviceKey::to_mnemonic`] output; case and extra whitespace are ignored
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, MnemonicError> {
+        Self::from_mnemonic_with_params(phrase, passphrase, &KdfParams::default())
+    }
+
+    pub fn from_mnemonic_with_params(phrase: &str, passphrase: &str, params: &KdfParams) -> Result<Self, MnemonicError> {
+        let normalized = Zeroizing::new(phrase.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join(" "));
+        let mnemonic = Mnemonic::parse_in_normalized(Language::English, &normalized)?;
+        if mnemonic.word_count() != MNEMONIC_WORDS {
+            return Err(MnemonicError::WordCount(mnemonic.word_count()));
+        }
+        let (entropy, len) = mnemonic.to_entropy_array();
+        let mut secret = Zeroizing::new([0u8; SECRET_KEY_LEN]);
+        secret.copy_from_slice(&entropy[..len]);
+        apply_passphrase(&mut secret, passphrase, params)?;
+        Ok(Self::from_bytes(secret.as_ref()).expect("32-byte secret"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phrase_roundtrip_and_checksum() {
+        let fast = KdfParams { m_cost: 64, t_cost: 1, p_cost: 1 };
+        let key = DeviceKey::from_bytes(&[0x5a; SECRET_KEY_LEN]).unwrap();
+
+        let phrase = key.to_mnemonic_with_params("", &fast).unwrap();
+        assert_eq!(phrase.split(' ').count(), MNEMONIC_WORDS);
+        let restored = DeviceKey::from_mnemonic_with_params(&format!("  {}\n", phrase.to_uppercase()), "", &fast).unwrap();
+        assert_eq!(restored.public(), key.public());
+
+        let protected = key.to_mnemonic_with_params("hunter2", &fast).unwrap();
+        assert_ne!(protected, phrase);
+        assert_eq!(DeviceKey::from_mnemonic_with_params(&protected, "hunter2", &fast).unwrap().public(), key.public());
+        assert_ne!(DeviceKey::from_mnemonic_with_params(&protected, "hunter3", &fast).unwrap().public(), key.public());
+
+        let mut words: Vec<&str> = phrase.split(' ').collect();
+        words.swap(0, 1);
+        assert_eq!(DeviceKey::from_mnemonic_with_params(&words.join(" "), "", &fast).unwrap_err(), MnemonicError::Checksum);
+        assert_eq!(DeviceKey::from_mnemonic_with_params(&words[..23].join(" "), "", &fast).unwrap_err(), MnemonicError::WordCount(23));
+    }
+}
@@ -0,0 +1,384 @@
+//! Encrypt once, deliver to many devices
+//!
+//! The payload is sealed a single time under a random content key. That key
+//! is then wrapped for each recipient, giving one ciphertext plus a ~110 byte
+//! header per recipient instead of one full ciphertext per device:
+//!
+//! - device recipients: [`hpke`](crate::hpke) to the device's X25519 key
+//! - passphrase recipients: XChaCha20-Poly1305 under an Argon2id key, so
+//!   anyone who knows the phrase can receive (compare age's scrypt recipient)
+//!
+//! Encoding is canonical CBOR:
+//!
+//! ```text
+//! {0: version, 1: [header, ...], 2: ciphertext}
+//! header = [0, recipient, enc, wrapped_key]
+//!        | [1, m_cost, t_cost, p_cost, salt, wrapped_key]
+//! ```
+//!
+//! The caller's AAD authenticates both the wrapped keys and the payload.
+
+use std::fmt;
+
+use rand_core::{OsRng, RngCore};
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::hpke::{self, HpkeError, ENC_LEN};
+use crate::keyfile::{derive_kek, KdfParams, SALT_LEN};
+use crate::keyprovider::KeyProvider;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::{aead_decrypt, aead_encrypt, derive_aead, CryptoError};
+
+pub const MULTI_VERSION: u64 = 1;
+/// Upper bound on headers accepted when parsing
+pub const MAX_RECIPIENTS: usize = 256;
+const CONTENT_KEY_LEN: usize = 32;
+const WRAP_INFO: &[u8] = b"globalsend multi-recipient v1";
+const HEADER_DEVICE: u64 = 0;
+const HEADER_PASSPHRASE: u64 = 1;
+/// Words in a generated passphrase; 11 bits each
+pub const PASSPHRASE_WORDS: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiError {
+    NoRecipients,
+    TooManyRecipients,
+    /// None of the headers is addressed to this device
+    NotARecipient,
+    WrongPassphrase,
+    /// Passphrase header asks for KDF parameters outside local limits
+    BadKdfParams,
+    Wrap(HpkeError),
+    Encrypt,
+    Decrypt,
+    UnsupportedVersion(u64),
+    Cbor(CborError),
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for MultiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiError::NoRecipients => write!(f, "no recipients"),
+            MultiError::TooManyRecipients => write!(f, "more than {MAX_RECIPIENTS} recipients"),
+            MultiError::NotARecipient => write!(f, "payload is not addressed to this device"),
+            MultiError::WrongPassphrase => write!(f, "wrong passphrase"),
+            MultiError::BadKdfParams => write!(f, "invalid passphrase kdf parameters"),
+            MultiError::Wrap(e) => write!(f, "content key wrap failed: {e}"),
+            MultiError::Encrypt => write!(f, "payload encryption failed"),
+            MultiError::Decrypt => write!(f, "payload decryption failed"),
+            MultiError::UnsupportedVersion(v) => write!(f, "unsupported multi-recipient version {v}"),
+            MultiError::Cbor(e) => write!(f, "invalid multi-recipient encoding: {e}"),
+            MultiError::Crypto(e) => write!(f, "multi-recipient: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MultiError {}
+
+impl From<CborError> for MultiError {
+    fn from(e: CborError) -> Self {
+        MultiError::Cbor(e)
+    }
+}
+
+impl From<CryptoError> for MultiError {
+    fn from(e: CryptoError) -> Self {
+        MultiError::Crypto(e)
+    }
+}
+
+/// Receive with a passphrase instead of a device key
+#[derive(Clone)]
+pub struct PassphraseRecipient {
+    passphrase: Zeroizing<String>,
+    params: KdfParams,
+}
+
+impl PassphraseRecipient {
+    pub fn new(passphrase: &str) -> Self {
+        Self { passphrase: Zeroizing::new(passphrase.to_string()), params: KdfParams::default() }
+    }
+
+    /// Random passphrase from [`generate_passphrase`]
+    pub fn generate() -> Self {
+        Self { passphrase: generate_passphrase(PASSPHRASE_WORDS), params: KdfParams::default() }
+    }
+
+    /// Override the Argon2id cost
+    pub fn with_params(mut self, params: KdfParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// The phrase to hand to the receiving person
+    pub fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+}
+
+impl fmt::Debug for PassphraseRecipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PassphraseRecipient").field("params", &self.params).finish_non_exhaustive()
+    }
+}
+
+/// `words` random BIP39 English words joined by `-`, e.g. `orbit-velvet-claw-...`
+pub fn generate_passphrase(words: usize) -> Zeroizing<String> {
+    let list = bip39::Language::English.word_list();
+    let mut out = Zeroizing::new(String::new());
+    for i in 0..words {
+        if i > 0 {
+            out.push('-');
+        }
+        // 2048 words: the low 11 bits of a u32 are uniform
+        out.push_str(list[(OsRng.next_u32() & 0x7ff) as usize]);
+    }
+    out
+}
+
+/// Whom a payload is sealed for
+#[derive(Debug, Clone)]
+pub enum Recipient {
+    Device(XPublicKey),
+    Passphrase(PassphraseRecipient),
+}
+
+/// Content key wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipientHeader {
+    Device { recipient: XPublicKey, enc: [u8; ENC_LEN], wrapped_key: Vec<u8> },
+    Passphrase { params: KdfParams, salt: [u8; SALT_LEN], wrapped_key: Vec<u8> },
+}
+
+/// One ciphertext plus a header per recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedPayload {
+    pub headers: Vec<RecipientHeader>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Collects recipients, then seals payloads for all of them at once
+#[derive(Debug, Default, Clone)]
+pub struct MultiRecipientSealer {
+    recipients: Vec<Recipient>,
+}
+
+fn passphrase_kek(passphrase: &str, salt: &[u8; SALT_LEN], params: &KdfParams) -> Result<SecretKey, MultiError> {
+    derive_kek(passphrase.as_bytes(), salt, params).map_err(|_| MultiError::BadKdfParams)
+}
+
+impl MultiRecipientSealer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a device; duplicates are ignored
+    pub fn add_recipient(&mut self, recipient: XPublicKey) -> &mut Self {
+        if !self.recipients.iter().any(|r| matches!(r, Recipient::Device(pk) if *pk == recipient)) {
+            self.recipients.push(Recipient::Device(recipient));
+        }
+        self
+    }
+
+    /// Let anyone who knows the passphrase open the payload
+    pub fn add_passphrase(&mut self, recipient: PassphraseRecipient) -> &mut Self {
+        self.recipients.push(Recipient::Passphrase(recipient));
+        self
+    }
+
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    /// Encrypt `plaintext` once and wrap the content key for every recipient
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<SealedPayload, MultiError> {
+        if self.recipients.is_empty() {
+            return Err(MultiError::NoRecipients);
+        }
+        if self.recipients.len() > MAX_RECIPIENTS {
+            return Err(MultiError::TooManyRecipients);
+        }
+        let mut content_key = SecretBytes::<CONTENT_KEY_LEN>::zeroed();
+        OsRng.fill_bytes(content_key.as_mut_bytes());
+
+        let headers = self
+            .recipients
+            .iter()
+            .map(|r| match r {
+                Recipient::Device(pk) => {
+                    let (enc, wrapped_key) = hpke::seal(pk, WRAP_INFO, aad, content_key.as_bytes()).map_err(MultiError::Wrap)?;
+                    Ok(RecipientHeader::Device { recipient: *pk, enc, wrapped_key })
+                }
+                Recipient::Passphrase(p) => {
+                    let mut salt = [0u8; SALT_LEN];
+                    OsRng.fill_bytes(&mut salt);
+                    // fresh salt, so the KEK and its nonces are used for this header only
+                    let kek = passphrase_kek(&p.passphrase, &salt, &p.params)?;
+                    let (key, mut nonces) = derive_aead(kek.as_bytes())?;
+                    let nonce = nonces.next().map_err(|_| MultiError::Encrypt)?;
+                    let wrapped_key = aead_encrypt(key.as_key(), nonce, aad, content_key.as_bytes()).map_err(|_| MultiError::Encrypt)?;
+                    Ok(RecipientHeader::Passphrase { params: p.params, salt, wrapped_key })
+                }
+            })
+            .collect::<Result<Vec<_>, MultiError>>()?;
+
+        // the content key is used for exactly one message
+        let (key, mut nonces) = derive_aead(content_key.as_bytes())?;
+        let nonce = nonces.next().map_err(|_| MultiError::Encrypt)?;
+        let ciphertext = aead_encrypt(key.as_key(), nonce, aad, plaintext).map_err(|_| MultiError::Encrypt)?;
+        Ok(SealedPayload { headers, ciphertext })
+    }
+}
+
+impl SealedPayload {
+    /// Find our header, unwrap the content key and decrypt
+    pub fn open(&self, device: &dyn KeyProvider, aad: &[u8]) -> Result<Vec<u8>, MultiError> {
+        let me = device.public();
+        let (enc, wrapped_key) = self
+            .headers
+            .iter()
+            .find_map(|h| match h {
+                RecipientHeader::Device { recipient, enc, wrapped_key } if *recipient == me => Some((enc, wrapped_key)),
+                _ => None,
+            })
+            .ok_or(MultiError::NotARecipient)?;
+        let content_key = Zeroizing::new(hpke::open(device, enc, WRAP_INFO, aad, wrapped_key).map_err(MultiError::Wrap)?);
+        self.open_content(&content_key, aad)
+    }
+
+    /// Try `passphrase` against every passphrase header
+    pub fn open_with_passphrase(&self, passphrase: &str, aad: &[u8]) -> Result<Vec<u8>, MultiError> {
+        let mut result = Err(MultiError::NotARecipient);
+        for h in &self.headers {
+            let RecipientHeader::Passphrase { params, salt, wrapped_key } = h else {
+                continue;
+            };
+            let kek = passphrase_kek(passphrase, salt, params)?;
+            let (key, nonces) = derive_aead(kek.as_bytes())?;
+            match aead_decrypt(key.as_key(), &nonces, 0, aad, wrapped_key) {
+                Ok(content_key) => return self.open_content(&Zeroizing::new(content_key), aad),
+                Err(_) => result = Err(MultiError::WrongPassphrase),
+            }
+        }
+        result
+    }
+
+    fn open_content(&self, content_key: &[u8], aad: &[u8]) -> Result<Vec<u8>, MultiError> {
+        let (key, nonces) = derive_aead(content_key)?;
+        aead_decrypt(key.as_key(), &nonces, 0, aad, &self.ciphertext).map_err(|_| MultiError::Decrypt)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(3);
+        e.uint(0).uint(MULTI_VERSION);
+        e.uint(1).array(self.headers.len());
+        for h in &self.headers {
+            match h {
+                RecipientHeader::Device { recipient, enc, wrapped_key } => {
+                    e.array(4).uint(HEADER_DEVICE).bytes(recipient.as_bytes()).bytes(enc).bytes(wrapped_key);
+                }
+                RecipientHeader::Passphrase { params, salt, wrapped_key } => {
+                    e.array(6).uint(HEADER_PASSPHRASE);
+                    e.uint(params.m_cost.into()).uint(params.t_cost.into()).uint(params.p_cost.into());
+                    e.bytes(salt).bytes(wrapped_key);
+                }
+            }
+        }
+        e.uint(2).bytes(&self.ciphertext);
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MultiError> {
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 3 {
+            return Err(CborError::Schema("multi-recipient payload must have 3 fields").into());
+        }
+        d.key(0)?;
+        let version = d.uint()?;
+        if version != MULTI_VERSION {
+            return Err(MultiError::UnsupportedVersion(version));
+        }
+        d.key(1)?;
+        let count = d.array()?;
+        if count == 0 || count > MAX_RECIPIENTS {
+            return Err(CborError::Schema("bad recipient count").into());
+        }
+        let mut headers = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = d.array()?;
+            let header = match (d.uint()?, len) {
+                (HEADER_DEVICE, 4) => {
+                    let recipient = XPublicKey::from(d.byte_array::<32>()?);
+                    let enc = d.byte_array::<ENC_LEN>()?;
+                    RecipientHeader::Device { recipient, enc, wrapped_key: d.bytes()?.to_vec() }
+                }
+                (HEADER_PASSPHRASE, 6) => {
+                    let mut cost = || -> Result<u32, MultiError> {
+                        u32::try_from(d.uint()?).map_err(|_| MultiError::BadKdfParams)
+                    };
+                    let params = KdfParams { m_cost: cost()?, t_cost: cost()?, p_cost: cost()? };
+                    let salt = d.byte_array::<SALT_LEN>()?;
+                    RecipientHeader::Passphrase { params, salt, wrapped_key: d.bytes()?.to_vec() }
+                }
+                _ => return Err(CborError::Schema("unknown recipient header").into()),
+            };
+            headers.push(header);
+        }
+        d.key(2)?;
+        let ciphertext = d.bytes()?.to_vec();
+        d.finish()?;
+        Ok(Self { headers, ciphertext })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceKey;
+
+    #[test]
+    fn one_ciphertext_opens_on_every_recipient() {
+        let devices: Vec<_> = (0..5).map(|_| DeviceKey::generate()).collect();
+        let mut sealer = MultiRecipientSealer::new();
+        for d in &devices {
+            sealer.add_recipient(d.public());
+        }
+        sealer.add_recipient(devices[0].public());
+        assert_eq!(sealer.recipients().len(), 5);
+
+        let sealed = sealer.seal(b"file.txt", b"shared contents").unwrap();
+        let parsed = SealedPayload::from_bytes(&sealed.to_bytes()).unwrap();
+        assert_eq!(parsed, sealed);
+        for d in &devices {
+            assert_eq!(parsed.open(d, b"file.txt").unwrap(), b"shared contents");
+        }
+        assert_eq!(parsed.open(&DeviceKey::generate(), b"file.txt"), Err(MultiError::NotARecipient));
+        assert!(parsed.open(&devices[1], b"other.txt").is_err());
+        assert_eq!(MultiRecipientSealer::new().seal(b"", b"x"), Err(MultiError::NoRecipients));
+    }
+
+    #[test]
+    fn passphrase_recipient_opens_alongside_devices() {
+        let fast = KdfParams { m_cost: 1024, t_cost: 1, p_cost: 1 };
+        let phrase = PassphraseRecipient::generate().with_params(fast);
+        assert_eq!(phrase.passphrase().split('-').count(), PASSPHRASE_WORDS);
+        let device = DeviceKey::generate();
+
+        let mut sealer = MultiRecipientSealer::new();
+        sealer.add_recipient(device.public()).add_passphrase(phrase.clone());
+        let sealed = SealedPayload::from_bytes(&sealer.seal(b"", b"for anyone with the words").unwrap().to_bytes()).unwrap();
+
+        assert_eq!(sealed.open_with_passphrase(phrase.passphrase(), b"").unwrap(), b"for anyone with the words");
+        assert_eq!(sealed.open(&device, b"").unwrap(), b"for anyone with the words");
+        assert_eq!(sealed.open_with_passphrase("wrong-words", b""), Err(MultiError::WrongPassphrase));
+
+        let mut device_only = MultiRecipientSealer::new();
+        device_only.add_recipient(device.public());
+        let sealed = device_only.seal(b"", b"x").unwrap();
+        assert_eq!(sealed.open_with_passphrase(phrase.passphrase(), b""), Err(MultiError::NotARecipient));
+    }
+}
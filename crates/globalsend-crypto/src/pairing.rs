@@ -0,0 +1,266 @@
+//! PIN pairing with SPAKE2
+//!
+//! Two devices that both know a short numeric PIN run SPAKE2 (Ed25519 group)
+//! and end up with a strong shared key. An eavesdropper learns nothing usable
+//! for an offline dictionary attack; an active attacker gets exactly one PIN
+//! guess per pairing attempt.
+//!
+//! Flow (the initiator is the device that displays the PIN):
+//!
+//! ```text
+//! I -> R : spake2 message A
+//! R -> I : spake2 message B
+//! I -> R : confirmation_I
+//! R -> I : confirmation_R
+//! ```
+//!
+//! The confirmations are HMACs over both SPAKE2 messages, so a wrong PIN is
+//! detected before the key is used for anything. Over the internet the same
+//! flow runs with a [`WormholeCode`] in place of the PIN.
+
+use std::fmt;
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{CryptoRngCore, OsRng};
+use sha2::Sha256;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use zeroize::Zeroizing;
+
+pub use crate::handshake::Role;
+use crate::ct::ct_eq;
+use crate::CryptoError;
+
+mod code;
+mod qr;
+pub use code::{WormholeCode, CODE_WORDS, MAX_NAMEPLATE, RENDEZVOUS_ID_LEN};
+pub use qr::{ConnectionHint, PairingPayload, QrError};
+
+pub const PIN_DIGITS: usize = 6;
+pub const CONFIRMATION_LEN: usize = 32;
+pub const PAIRING_KEY_LEN: usize = 32;
+
+const ID_INITIATOR: &[u8] = b"globalsend pairing initiator";
+const ID_RESPONDER: &[u8] = b"globalsend pairing responder";
+const PAIRING_INFO: &[u8] = b"globalsend pairing v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingError {
+    /// PIN is not exactly `PIN_DIGITS` ASCII digits
+    InvalidPin,
+    /// Not a `<nameplate>-<word>-<word>` wormhole code
+    InvalidCode,
+    /// Peer SPAKE2 message is malformed
+    BadMessage,
+    /// Key confirmation failed: wrong PIN or active attacker
+    ConfirmationFailed,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for PairingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairingError::InvalidPin => write!(f, "pin must be {PIN_DIGITS} digits"),
+            PairingError::InvalidCode => write!(f, "code must look like 7-guitar-sunset"),
+            PairingError::BadMessage => write!(f, "malformed pairing message"),
+            PairingError::ConfirmationFailed => write!(f, "pairing confirmation failed (wrong pin?)"),
+            PairingError::Crypto(e) => write!(f, "pairing: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PairingError {}
+
+/// Short numeric pairing code
+#[derive(Clone, Eq)]
+pub struct Pin(Zeroizing<String>);
+
+impl Pin {
+    pub fn parse(s: &str) -> Result<Self, PairingError> {
+        let s = s.trim();
+        if s.len() != PIN_DIGITS || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(PairingError::InvalidPin);
+        }
+        Ok(Self(Zeroizing::new(s.to_string())))
+    }
+
+    /// Uniformly random PIN
+    pub fn generate() -> Self {
+        Self::generate_with_rng(&mut OsRng)
+    }
+
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        const RANGE: u32 = 10u32.pow(PIN_DIGITS as u32);
+        // reject the top of the u32 range to avoid modulo bias
+        let limit = u32::MAX - u32::MAX % RANGE;
+        let n = loop {
+            let n = rng.next_u32();
+            if n < limit {
+                break n % RANGE;
+            }
+        };
+        Self(Zeroizing::new(format!("{n:0width$}", width = PIN_DIGITS)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Pin {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl fmt::Debug for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Pin(******)")
+    }
+}
+
+/// First half of a pairing: our SPAKE2 message is ready to send
+pub struct Pairing {
+    role: Role,
+    spake: Spake2<Ed25519Group>,
+    outbound: Vec<u8>,
+}
+
+impl Pairing {
+    pub fn start(role: Role, pin: &Pin) -> Self {
+        Self::start_with_rng(&mut OsRng, role, pin)
+    }
+
+    pub fn start_with_rng<R: CryptoRngCore>(rng: &mut R, role: Role, pin: &Pin) -> Self {
+        Self::start_password(rng, role, pin.as_str().as_bytes())
+    }
+
+    /// Same exchange keyed by a wormhole code; the sender is the initiator
+    pub fn start_with_code(role: Role, code: &WormholeCode) -> Self {
+        Self::start_with_code_with_rng(&mut OsRng, role, code)
+    }
+
+    pub fn start_with_code_with_rng<R: CryptoRngCore>(rng: &mut R, role: Role, code: &WormholeCode) -> Self {
+        Self::start_password(rng, role, code.as_str().as_bytes())
+    }
+
+    fn start_password<R: CryptoRngCore>(rng: &mut R, role: Role, password: &[u8]) -> Self {
+        let password = Password::new(password);
+        let (id_a, id_b) = (Identity::new(ID_INITIATOR), Identity::new(ID_RESPONDER));
+        let (spake, outbound) = match role {
+            Role::Initiator => Spake2::<Ed25519Group>::start_a_with_rng(&password, &id_a, &id_b, &mut *rng),
+            Role::Responder => Spake2::<Ed25519Group>::start_b_with_rng(&password, &id_a, &id_b, &mut *rng),
+        };
+        Self { role, spake, outbound }
+    }
+
+    /// SPAKE2 message to send to the peer
+    pub fn message(&self) -> &[u8] {
+        &self.outbound
+    }
+
+    /// Process the peer's SPAKE2 message
+    pub fn finish(self, peer_message: &[u8]) -> Result<PendingConfirmation, PairingError> {
+        let shared = Zeroizing::new(self.spake.finish(peer_message).map_err(|_| PairingError::BadMessage)?);
+
+        let hk = Hkdf::<Sha256>::new(None, &shared);
+        let mut okm = Zeroizing::new([0u8; 2 * CONFIRMATION_LEN + PAIRING_KEY_LEN]);
+        hk.expand(PAIRING_INFO, okm.as_mut()).map_err(|e| PairingError::Crypto(e.into()))?;
+        let (conf_i, rest) = okm.split_at(CONFIRMATION_LEN);
+        let (conf_r, key) = rest.split_at(CONFIRMATION_LEN);
+
+        let (msg_a, msg_b) = match self.role {
+            Role::Initiator => (self.outbound.clone(), peer_message.to_vec()),
+            Role::Responder => (peer_message.to_vec(), self.outbound.clone()),
+        };
+        let (mine, theirs) = match self.role {
+            Role::Initiator => (conf_i, conf_r),
+            Role::Responder => (conf_r, conf_i),
+        };
+        let confirmation = confirmation_mac(mine, &msg_a, &msg_b).finalize().into_bytes().into();
+        let mut their_conf_key = Zeroizing::new([0u8; CONFIRMATION_LEN]);
+        their_conf_key.copy_from_slice(theirs);
+        let mut pairing_key = Zeroizing::new([0u8; PAIRING_KEY_LEN]);
+        pairing_key.copy_from_slice(key);
+        Ok(PendingConfirmation { confirmation, their_conf_key, msg_a, msg_b, key: pairing_key })
+    }
+}
+
+fn confirmation_mac(key: &[u8], msg_a: &[u8], msg_b: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(&(msg_a.len() as u32).to_be_bytes());
+    mac.update(msg_a);
+    mac.update(msg_b);
+    mac
+}
+
+/// SPAKE2 done; waiting for the peer to prove it derived the same key
+pub struct PendingConfirmation {
+    confirmation: [u8; CONFIRMATION_LEN],
+    their_conf_key: Zeroizing<[u8; CONFIRMATION_LEN]>,
+    msg_a: Vec<u8>,
+    msg_b: Vec<u8>,
+    key: Zeroizing<[u8; PAIRING_KEY_LEN]>,
+}
+
+impl PendingConfirmation {
+    /// Confirmation message to send to the peer
+    pub fn confirmation(&self) -> &[u8; CONFIRMATION_LEN] {
+        &self.confirmation
+    }
+
+    /// Check the peer's confirmation (constant time) and release the pairing key
+    pub fn verify(self, peer_confirmation: &[u8]) -> Result<PairingKey, PairingError> {
+        confirmation_mac(self.their_conf_key.as_ref(), &self.msg_a, &self.msg_b)
+            .verify_slice(peer_confirmation)
+            .map_err(|_| PairingError::ConfirmationFailed)?;
+        Ok(PairingKey(self.key))
+    }
+}
+
+/// Shared secret established by a successful pairing
+pub struct PairingKey(Zeroizing<[u8; PAIRING_KEY_LEN]>);
+
+impl PairingKey {
+    pub fn as_bytes(&self) -> &[u8; PAIRING_KEY_LEN] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(pin_i: &Pin, pin_r: &Pin) -> (Result<PairingKey, PairingError>, Result<PairingKey, PairingError>) {
+        let i = Pairing::start(Role::Initiator, pin_i);
+        let r = Pairing::start(Role::Responder, pin_r);
+        let (mi, mr) = (i.message().to_vec(), r.message().to_vec());
+        let ci = i.finish(&mr).unwrap();
+        let cr = r.finish(&mi).unwrap();
+        let (conf_i, conf_r) = (*ci.confirmation(), *cr.confirmation());
+        (ci.verify(&conf_r), cr.verify(&conf_i))
+    }
+
+    #[test]
+    fn matching_pin_pairs_and_wrong_pin_fails() {
+        let pin = Pin::generate();
+        assert_eq!(pin.as_str().len(), PIN_DIGITS);
+        let (ki, kr) = run(&pin, &pin);
+        assert_eq!(ki.unwrap().as_bytes(), kr.unwrap().as_bytes());
+
+        let (ki, kr) = run(&Pin::parse("123456").unwrap(), &Pin::parse("123457").unwrap());
+        assert_eq!(ki.err(), Some(PairingError::ConfirmationFailed));
+        assert_eq!(kr.err(), Some(PairingError::ConfirmationFailed));
+
+        assert_eq!(Pin::parse("12a456"), Err(PairingError::InvalidPin));
+
+        // a seeded RNG makes the PIN, the code and our SPAKE2 message repeatable
+        let draw = || {
+            let mut rng = crate::test_rng::TestRng::new([7; 32]);
+            let (pin, code) = (Pin::generate_with_rng(&mut rng), WormholeCode::generate_with_rng(&mut rng));
+            let message = Pairing::start_with_rng(&mut rng, Role::Initiator, &pin).message().to_vec();
+            (pin, code, message)
+        };
+        assert_eq!(draw(), draw());
+    }
+}
@@ -0,0 +1,132 @@
+//! Wormhole codes
+//!
+//! A short phrase like `7-guitar-sunset` that one side reads out and the
+//! other types in, for pairing over the internet where there is no QR camera
+//! or shared LAN. The number is the nameplate: it is sent in the clear so
+//! both sides find each other at the rendezvous server. The words are the
+//! secret part and only ever go into SPAKE2 (see [`Pairing::start_with_code`]),
+//! so the server, or anyone who claims the nameplate first, gets one guess
+//! at 22 bits before the pairing fails.
+//!
+//! [`Pairing::start_with_code`]: super::Pairing::start_with_code
+
+use std::fmt;
+
+use bip39::Language;
+use rand_core::{CryptoRngCore, OsRng};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use super::PairingError;
+use crate::ct::ct_eq;
+
+/// Nameplates are `1..=MAX_NAMEPLATE`; short to type, and collisions only cost a retry
+pub const MAX_NAMEPLATE: u16 = 999;
+/// BIP39 words after the nameplate, 11 bits each
+pub const CODE_WORDS: usize = 2;
+pub const RENDEZVOUS_ID_LEN: usize = 32;
+
+const RENDEZVOUS_CONTEXT: &[u8] = b"globalsend wormhole nameplate v1";
+
+#[derive(Clone, Eq)]
+pub struct WormholeCode {
+    nameplate: u16,
+    code: Zeroizing<String>,
+}
+
+impl WormholeCode {
+    /// Random nameplate and words
+    pub fn generate() -> Self {
+        Self::generate_with_rng(&mut OsRng)
+    }
+
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R) -> Self {
+        let range = u32::from(MAX_NAMEPLATE);
+        // reject the top of the u32 range to avoid modulo bias
+        let limit = u32::MAX - u32::MAX % range;
+        let nameplate = loop {
+            let n = rng.next_u32();
+            if n < limit {
+                break (n % range) as u16 + 1;
+            }
+        };
+        let list = Language::English.word_list();
+        // 2048 words: the low 11 bits of a u32 are uniform
+        let words: Vec<&str> = (0..CODE_WORDS).map(|_| list[(rng.next_u32() & 0x7ff) as usize]).collect();
+        Self::from_parts(nameplate, &words)
+    }
+
+    /// Accepts what a user types: surrounding whitespace, any case, spaces or dashes between parts
+    pub fn parse(s: &str) -> Result<Self, PairingError> {
+        let lower = Zeroizing::new(s.trim().to_ascii_lowercase());
+        let mut parts = lower.split(|c: char| c == '-' || c.is_whitespace()).filter(|p| !p.is_empty());
+        let nameplate = parts
+            .next()
+            .and_then(|n| n.parse::<u16>().ok())
+            .filter(|n| (1..=MAX_NAMEPLATE).contains(n))
+            .ok_or(PairingError::InvalidCode)?;
+        let words: Vec<&str> = parts.collect();
+        if words.len() != CODE_WORDS || words.iter().any(|w| Language::English.find_word(w).is_none()) {
+            return Err(PairingError::InvalidCode);
+        }
+        Ok(Self::from_parts(nameplate, &words))
+    }
+
+    fn from_parts(nameplate: u16, words: &[&str]) -> Self {
+        let mut code = Zeroizing::new(nameplate.to_string());
+        for word in words {
+            code.push('-');
+            code.push_str(word);
+        }
+        Self { nameplate, code }
+    }
+
+    pub fn nameplate(&self) -> u16 {
+        self.nameplate
+    }
+
+    /// Where both sides meet: derived from the nameplate only, never the words
+    pub fn rendezvous_id(&self) -> [u8; RENDEZVOUS_ID_LEN] {
+        let mut hash = Sha256::new();
+        hash.update(RENDEZVOUS_CONTEXT);
+        hash.update(self.nameplate.to_be_bytes());
+        hash.finalize().into()
+    }
+
+    /// Canonical form, `7-guitar-sunset`
+    pub fn as_str(&self) -> &str {
+        &self.code
+    }
+}
+
+impl PartialEq for WormholeCode {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(self.code.as_bytes(), other.code.as_bytes())
+    }
+}
+
+impl fmt::Debug for WormholeCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WormholeCode({}-…)", self.nameplate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_normalises_and_rejects_junk() {
+        let code = WormholeCode::parse("  7 Guitar-SUNSET ").unwrap();
+        assert_eq!(code.as_str(), "7-guitar-sunset");
+        assert_eq!(code.nameplate(), 7);
+        assert_eq!(code.rendezvous_id(), WormholeCode::parse("7-abandon-zoo").unwrap().rendezvous_id());
+
+        let generated = WormholeCode::generate();
+        assert_eq!(WormholeCode::parse(generated.as_str()).unwrap(), generated);
+
+        for bad in ["guitar-sunset", "0-guitar-sunset", "1000-guitar-sunset", "7-guitar", "7-guitar-sunsett", "7-guitar-sunset-zoo"] {
+            assert_eq!(WormholeCode::parse(bad), Err(PairingError::InvalidCode), "{bad}");
+        }
+    }
+}
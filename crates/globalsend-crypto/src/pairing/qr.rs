@@ -0,0 +1,181 @@
+//! QR pairing payload
+//!
+//! A desktop shows a QR code carrying its name, X25519 public key, identity
+//! fingerprint and where to reach it. The scanning phone connects, runs the
+//! handshake and checks that the peer's static key matches the payload, so no
+//! PIN is needed: the camera is the authenticated channel.
+//!
+//! The payload is canonical CBOR (integer-keyed map, keys 0..=4) and the QR
+//! string is `GS1:` followed by unpadded base32, which stays within the QR
+//! alphanumeric character set.
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use data_encoding::BASE32_NOPAD;
+use x25519_dalek::PublicKey as XPublicKey;
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::ct::ct_eq_array;
+use crate::identity::{Fingerprint, FINGERPRINT_LEN};
+
+const QR_PREFIX: &str = "GS1:";
+pub const PAYLOAD_VERSION: u64 = 1;
+/// Keep device names short enough for a comfortably scannable QR
+pub const MAX_DEVICE_NAME_LEN: usize = 64;
+pub const MAX_HINTS: usize = 8;
+
+const HINT_SOCKET: u64 = 0;
+const HINT_URL: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QrError {
+    /// Missing `GS1:` prefix or invalid base32
+    BadEncoding,
+    UnsupportedVersion(u64),
+    Cbor(CborError),
+}
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QrError::BadEncoding => write!(f, "not a globalsend pairing code"),
+            QrError::UnsupportedVersion(v) => write!(f, "unsupported pairing payload version {v}"),
+            QrError::Cbor(e) => write!(f, "invalid pairing payload: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for QrError {}
+
+impl From<CborError> for QrError {
+    fn from(e: CborError) -> Self {
+        QrError::Cbor(e)
+    }
+}
+
+/// Where the displaying device can be reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionHint {
+    /// Direct LAN address
+    Socket(SocketAddr),
+    /// Rendezvous or relay URL
+    Url(String),
+}
+
+/// Contents of a pairing QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingPayload {
+    pub device_name: String,
+    pub public_key: XPublicKey,
+    pub fingerprint: Fingerprint,
+    pub hints: Vec<ConnectionHint>,
+}
+
+impl PairingPayload {
+    /// True if `remote_static` from a completed handshake is the key in this payload
+    pub fn matches(&self, remote_static: &XPublicKey) -> bool {
+        ct_eq_array(self.public_key.as_bytes(), remote_static.as_bytes())
+    }
+
+    /// Canonical CBOR encoding
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(5);
+        e.uint(0).uint(PAYLOAD_VERSION);
+        e.uint(1).text(&self.device_name);
+        e.uint(2).bytes(self.public_key.as_bytes());
+        e.uint(3).bytes(self.fingerprint.as_bytes());
+        e.uint(4).array(self.hints.len());
+        for hint in &self.hints {
+            e.array(2);
+            match hint {
+                ConnectionHint::Socket(addr) => e.uint(HINT_SOCKET).text(&addr.to_string()),
+                ConnectionHint::Url(url) => e.uint(HINT_URL).text(url),
+            };
+        }
+        e.finish()
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<Self, QrError> {
+        let mut d = Decoder::new(data);
+        if d.map()? != 5 {
+            return Err(CborError::Schema("pairing payload must have 5 fields").into());
+        }
+        d.key(0)?;
+        let version = d.uint()?;
+        if version != PAYLOAD_VERSION {
+            return Err(QrError::UnsupportedVersion(version));
+        }
+        d.key(1)?;
+        let device_name = d.text()?;
+        if device_name.len() > MAX_DEVICE_NAME_LEN {
+            return Err(CborError::Schema("device name too long").into());
+        }
+        d.key(2)?;
+        let public_key = XPublicKey::from(d.byte_array::<32>()?);
+        d.key(3)?;
+        let fingerprint = Fingerprint::from_bytes(d.byte_array::<FINGERPRINT_LEN>()?);
+        d.key(4)?;
+        let n = d.array()?;
+        if n > MAX_HINTS {
+            return Err(CborError::Schema("too many connection hints").into());
+        }
+        let mut hints = Vec::with_capacity(n);
+        for _ in 0..n {
+            if d.array()? != 2 {
+                return Err(CborError::Schema("connection hint must be a pair").into());
+            }
+            let hint = match d.uint()? {
+                HINT_SOCKET => ConnectionHint::Socket(d.text()?.parse().map_err(|_| CborError::Schema("bad socket address"))?),
+                HINT_URL => ConnectionHint::Url(d.text()?.to_string()),
+                _ => return Err(CborError::Schema("unknown connection hint type").into()),
+            };
+            hints.push(hint);
+        }
+        d.finish()?;
+        Ok(Self { device_name: device_name.to_string(), public_key, fingerprint, hints })
+    }
+
+    /// String to render as a QR code
+    pub fn to_qr_string(&self) -> String {
+        format!("{QR_PREFIX}{}", BASE32_NOPAD.encode(&self.to_cbor()))
+    }
+
+    pub fn from_qr_string(s: &str) -> Result<Self, QrError> {
+        let body = s.trim().strip_prefix(QR_PREFIX).ok_or(QrError::BadEncoding)?;
+        let cbor = BASE32_NOPAD.decode(body.as_bytes()).map_err(|_| QrError::BadEncoding)?;
+        Self::from_cbor(&cbor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DeviceIdentity;
+
+    #[test]
+    fn qr_roundtrip() {
+        let id = DeviceIdentity::generate();
+        let payload = PairingPayload {
+            device_name: "Laptop".into(),
+            public_key: id.exchange().public(),
+            fingerprint: id.fingerprint(),
+            hints: vec![
+                ConnectionHint::Socket("192.168.1.20:53317".parse().unwrap()),
+                ConnectionHint::Url("wss://relay.example/r/abc".into()),
+            ],
+        };
+        let qr = payload.to_qr_string();
+        assert!(qr.starts_with("GS1:"));
+        assert!(qr.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == ':'));
+        let parsed = PairingPayload::from_qr_string(&qr).unwrap();
+        assert_eq!(parsed, payload);
+        assert!(parsed.matches(&id.exchange().public()));
+
+        let mut cbor = payload.to_cbor();
+        cbor.push(0);
+        assert_eq!(PairingPayload::from_cbor(&cbor), Err(QrError::Cbor(CborError::TrailingBytes)));
+        assert_eq!(PairingPayload::from_qr_string("GS1:!!"), Err(QrError::BadEncoding));
+    }
+}
@@ -0,0 +1,523 @@
+//! X3DH-style prekeys for delivery to offline devices
+//!
+//! A device that expects mail while it is offline uploads a [`PrekeyUpload`]
+//! to the relay: its identity [`DeviceCertificate`], a signed prekey (an
+//! X25519 key certified by the same identity, rotated every few weeks) and a
+//! batch of one-time prekeys. The relay hands each sender a [`PrekeyBundle`]
+//! carrying at most one of the one-time keys. The sender runs the X3DH
+//! computation against the bundle and deposits an [`OfflineMessage`]:
+//!
+//! ```text
+//! DH1 = DH(IK_A, SPK_B)   DH2 = DH(EK_A, IK_B)
+//! DH3 = DH(EK_A, SPK_B)   DH4 = DH(EK_A, OPK_B)   (if a one-time key was served)
+//! SK  = HKDF(0xff * 32 || DH1 || DH2 || DH3 [|| DH4], "globalsend x3dh v1")
+//! ```
+//!
+//! `IK` are the device exchange keys, each vouched for by a certificate,
+//! so the recipient learns the sender's [`Fingerprint`] and the sender only
+//! encrypts to the pinned recipient. When the one-time keys run out the
+//! relay serves bundles without one; such messages can be replayed to the
+//! recipient until the signed prekey rotates, so callers should dedupe them.
+//!
+//! Encoding is canonical CBOR. Bundle: `{0: version, 1: identity cert,
+//! 2: signed prekey cert, ?3: one-time key}`. Message: `{0: version,
+//! 1: sender cert, 2: ephemeral key, 3: signed prekey, 4: ciphertext,
+//! ?5: one-time key}`.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use hkdf::Hkdf;
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+use sha2::Sha256;
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::cbor::{CborError, Decoder, Encoder};
+use crate::certificate::{CertificateError, DeviceCertificate};
+use crate::identity::{DeviceIdentity, Fingerprint};
+use crate::keyprovider::KeyProvider;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::{aead_decrypt, aead_encrypt, split_okm, CryptoError, DeviceKey, EphemeralKey, NonceSequence, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+
+const X3DH_INFO: &[u8] = b"globalsend x3dh v1";
+const X3DH_AAD_CONTEXT: &[u8] = b"globalsend offline message v1";
+pub const PREKEY_VERSION: u64 = 1;
+/// Signed prekeys kept after rotation, so messages sent to the previous one still open
+pub const SIGNED_PREKEYS_KEPT: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrekeyError {
+    /// Message names a prekey this device no longer (or never) had
+    UnknownPrekey,
+    /// Identity and signed prekey certificates are from different identities
+    MismatchedIdentity,
+    /// Sender's certificate does not cover the key it used
+    WrongSenderKey,
+    UnsupportedVersion(u64),
+    Certificate(CertificateError),
+    Crypto(CryptoError),
+    Cbor(CborError),
+}
+
+impl fmt::Display for PrekeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrekeyError::UnknownPrekey => write!(f, "unknown or already used prekey"),
+            PrekeyError::MismatchedIdentity => write!(f, "signed prekey certified by another identity"),
+            PrekeyError::WrongSenderKey => write!(f, "sender certificate does not cover the sender key"),
+            PrekeyError::UnsupportedVersion(v) => write!(f, "unsupported prekey version {v}"),
+            PrekeyError::Certificate(e) => write!(f, "prekey certificate: {e}"),
+            PrekeyError::Crypto(e) => write!(f, "offline message: {e}"),
+            PrekeyError::Cbor(e) => write!(f, "invalid prekey encoding: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for PrekeyError {}
+
+impl From<CertificateError> for PrekeyError {
+    fn from(e: CertificateError) -> Self {
+        PrekeyError::Certificate(e)
+    }
+}
+
+impl From<CryptoError> for PrekeyError {
+    fn from(e: CryptoError) -> Self {
+        PrekeyError::Crypto(e)
+    }
+}
+
+impl From<CborError> for PrekeyError {
+    fn from(e: CborError) -> Self {
+        PrekeyError::Cbor(e)
+    }
+}
+
+fn certificate(d: &mut Decoder<'_>) -> Result<DeviceCertificate, PrekeyError> {
+    Ok(DeviceCertificate::from_bytes(d.bytes()?)?)
+}
+
+fn version(d: &mut Decoder<'_>) -> Result<(), PrekeyError> {
+    d.key(0)?;
+    match d.uint()? {
+        PREKEY_VERSION => Ok(()),
+        v => Err(PrekeyError::UnsupportedVersion(v)),
+    }
+}
+
+/// Everything the relay stores for one device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrekeyUpload {
+    pub identity: DeviceCertificate,
+    pub signed_prekey: DeviceCertificate,
+    pub one_time: Vec<XPublicKey>,
+}
+
+impl PrekeyUpload {
+    /// Bundle for the next sender, using up one one-time key if any are left
+    pub fn take_bundle(&mut self) -> PrekeyBundle {
+        PrekeyBundle { identity: self.identity.clone(), signed_prekey: self.signed_prekey.clone(), one_time: self.one_time.pop() }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(4);
+        e.uint(0).uint(PREKEY_VERSION);
+        e.uint(1).bytes(&self.identity.to_bytes());
+        e.uint(2).bytes(&self.signed_prekey.to_bytes());
+        e.uint(3).array(self.one_time.len());
+        for key in &self.one_time {
+            e.bytes(key.as_bytes());
+        }
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PrekeyError> {
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 4 {
+            return Err(CborError::Schema("prekey upload must have 4 fields").into());
+        }
+        version(&mut d)?;
+        d.key(1)?;
+        let identity = certificate(&mut d)?;
+        d.key(2)?;
+        let signed_prekey = certificate(&mut d)?;
+        d.key(3)?;
+        let count = d.array()?;
+        let mut one_time = Vec::with_capacity(count.min(bytes.len() / 32));
+        for _ in 0..count {
+            one_time.push(XPublicKey::from(d.byte_array::<32>()?));
+        }
+        d.finish()?;
+        Ok(Self { identity, signed_prekey, one_time })
+    }
+}
+
+/// What a sender fetches from the relay to write to an offline device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrekeyBundle {
+    pub identity: DeviceCertificate,
+    pub signed_prekey: DeviceCertificate,
+    pub one_time: Option<XPublicKey>,
+}
+
+impl PrekeyBundle {
+    /// Check both certificates against the recipient's pinned fingerprint
+    pub fn verify(&self, pinned: &Fingerprint, now: u64) -> Result<(), PrekeyError> {
+        self.identity.verify_chain(pinned, &self.identity.exchange, now)?;
+        if self.signed_prekey.identity != self.identity.identity {
+            return Err(PrekeyError::MismatchedIdentity);
+        }
+        Ok(self.signed_prekey.verify(now)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(if self.one_time.is_some() { 4 } else { 3 });
+        e.uint(0).uint(PREKEY_VERSION);
+        e.uint(1).bytes(&self.identity.to_bytes());
+        e.uint(2).bytes(&self.signed_prekey.to_bytes());
+        if let Some(key) = &self.one_time {
+            e.uint(3).bytes(key.as_bytes());
+        }
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PrekeyError> {
+        let mut d = Decoder::new(bytes);
+        let fields = d.map()?;
+        if !(3..=4).contains(&fields) {
+            return Err(CborError::Schema("prekey bundle must have 3 or 4 fields").into());
+        }
+        version(&mut d)?;
+        d.key(1)?;
+        let identity = certificate(&mut d)?;
+        d.key(2)?;
+        let signed_prekey = certificate(&mut d)?;
+        let one_time = if fields == 4 {
+            d.key(3)?;
+            Some(XPublicKey::from(d.byte_array::<32>()?))
+        } else {
+            None
+        };
+        d.finish()?;
+        Ok(Self { identity, signed_prekey, one_time })
+    }
+}
+
+/// Ciphertext deposited on the relay for an offline device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineMessage {
+    pub sender: DeviceCertificate,
+    pub ephemeral: XPublicKey,
+    pub signed_prekey: XPublicKey,
+    pub one_time: Option<XPublicKey>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl OfflineMessage {
+    /// Fingerprint of the sending device; authenticated once [`PrekeyStore::open`] succeeds
+    pub fn sender_fingerprint(&self) -> Fingerprint {
+        self.sender.fingerprint()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        e.map(if self.one_time.is_some() { 6 } else { 5 });
+        e.uint(0).uint(PREKEY_VERSION);
+        e.uint(1).bytes(&self.sender.to_bytes());
+        e.uint(2).bytes(self.ephemeral.as_bytes());
+        e.uint(3).bytes(self.signed_prekey.as_bytes());
+        e.uint(4).bytes(&self.ciphertext);
+        if let Some(key) = &self.one_time {
+            e.uint(5).bytes(key.as_bytes());
+        }
+        e.finish()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PrekeyError> {
+        let mut d = Decoder::new(bytes);
+        let fields = d.map()?;
+        if !(5..=6).contains(&fields) {
+            return Err(CborError::Schema("offline message must have 5 or 6 fields").into());
+        }
+        version(&mut d)?;
+        d.key(1)?;
+        let sender = certificate(&mut d)?;
+        d.key(2)?;
+        let ephemeral = XPublicKey::from(d.byte_array::<32>()?);
+        d.key(3)?;
+        let signed_prekey = XPublicKey::from(d.byte_array::<32>()?);
+        d.key(4)?;
+        let ciphertext = d.bytes()?.to_vec();
+        let one_time = if fields == 6 {
+            d.key(5)?;
+            Some(XPublicKey::from(d.byte_array::<32>()?))
+        } else {
+            None
+        };
+        d.finish()?;
+        Ok(Self { sender, ephemeral, signed_prekey, one_time, ciphertext })
+    }
+
+    fn aad(&self, recipient: &XPublicKey, aad: &[u8]) -> Vec<u8> {
+        let one_time = self.one_time.as_ref().map_or(&[][..], |k| &k.as_bytes()[..]);
+        [
+            X3DH_AAD_CONTEXT,
+            self.sender.exchange.as_bytes(),
+            recipient.as_bytes(),
+            self.ephemeral.as_bytes(),
+            self.signed_prekey.as_bytes(),
+            one_time,
+            aad,
+        ]
+        .concat()
+    }
+}
+
+fn agree(dh: &[SecretBytes<32>], aad: &[u8]) -> Result<(SecretKey, NonceSequence), CryptoError> {
+    let mut ikm = Zeroizing::new(Vec::with_capacity(32 * (dh.len() + 1)));
+    ikm.extend_from_slice(&[0xff; 32]);
+    for secret in dh {
+        ikm.extend_from_slice(secret.as_bytes());
+    }
+    let hk = Hkdf::<Sha256>::new(Some(&[0; 32]), &ikm);
+    let mut okm = SecretBytes::<{ AEAD_KEY_LEN + AEAD_NONCE_LEN }>::zeroed();
+    hk.expand_multi_info(&[X3DH_INFO, aad], okm.as_mut_bytes())?;
+    Ok(split_okm(okm.as_bytes()))
+}
+
+/// Encrypt `plaintext` for the device behind `bundle`.
+///
+/// `sender` is our exchange key and `certificate` the identity certificate for it.
+#[cfg(feature = "std")]
+pub fn seal(
+    sender: &dyn KeyProvider,
+    certificate: &DeviceCertificate,
+    bundle: &PrekeyBundle,
+    pinned: &Fingerprint,
+    now: u64,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<OfflineMessage, PrekeyError> {
+    seal_with_rng(&mut OsRng, sender, certificate, bundle, pinned, now, aad, plaintext)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn seal_with_rng<R: CryptoRngCore>(
+    rng: &mut R,
+    sender: &dyn KeyProvider,
+    certificate: &DeviceCertificate,
+    bundle: &PrekeyBundle,
+    pinned: &Fingerprint,
+    now: u64,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<OfflineMessage, PrekeyError> {
+    if certificate.exchange != sender.public() {
+        return Err(PrekeyError::WrongSenderKey);
+    }
+    bundle.verify(pinned, now)?;
+    let ephemeral = EphemeralKey::generate_with_rng(rng);
+    let spk = bundle.signed_prekey.exchange;
+    let mut dh = Vec::with_capacity(4);
+    dh.push(sender.ecdh(&spk)?);
+    dh.push(ephemeral.ecdh(&bundle.identity.exchange));
+    dh.push(ephemeral.ecdh(&spk));
+    if let Some(opk) = &bundle.one_time {
+        dh.push(ephemeral.ecdh(opk));
+    }
+    let mut message = OfflineMessage {
+        sender: certificate.clone(),
+        ephemeral: ephemeral.public(),
+        signed_prekey: spk,
+        one_time: bundle.one_time,
+        ciphertext: Vec::new(),
+    };
+    let full_aad = message.aad(&bundle.identity.exchange, aad);
+    let (key, mut nonces) = agree(&dh, &full_aad)?;
+    message.ciphertext = aead_encrypt(key.as_key(), nonces.next()?, &full_aad, plaintext)?;
+    Ok(message)
+}
+
+struct SignedPrekey {
+    key: DeviceKey,
+    certificate: DeviceCertificate,
+}
+
+/// The recipient's private prekeys
+pub struct PrekeyStore {
+    /// Newest first
+    signed: Vec<SignedPrekey>,
+    one_time: Vec<DeviceKey>,
+}
+
+impl PrekeyStore {
+    /// Fresh store with a signed prekey valid for `[valid_from, valid_until)`
+    pub fn generate_with_rng<R: CryptoRngCore>(rng: &mut R, identity: &DeviceIdentity, valid_from: u64, valid_until: u64) -> Self {
+        let mut store = Self { signed: Vec::new(), one_time: Vec::new() };
+        store.rotate_signed_prekey_with_rng(rng, identity, valid_from, valid_until);
+        store
+    }
+
+    /// Replace the signed prekey, keeping the previous one for late messages
+    pub fn rotate_signed_prekey_with_rng<R: CryptoRngCore>(&mut self, rng: &mut R, identity: &DeviceIdentity, valid_from: u64, valid_until: u64) {
+        let key = DeviceKey::generate_with_rng(rng);
+        let certificate = identity.certify_exchange_key(&key.public(), valid_from, valid_until);
+        self.signed.insert(0, SignedPrekey { key, certificate });
+        self.signed.truncate(SIGNED_PREKEYS_KEPT);
+    }
+
+    /// Create `count` one-time prekeys, returning their public halves for upload
+    pub fn add_one_time_with_rng<R: CryptoRngCore>(&mut self, rng: &mut R, count: usize) -> Vec<XPublicKey> {
+        let keys: Vec<DeviceKey> = (0..count).map(|_| DeviceKey::generate_with_rng(rng)).collect();
+        let public = keys.iter().map(DeviceKey::public).collect();
+        self.one_time.extend(keys);
+        public
+    }
+
+    /// One-time prekeys not yet used by a message
+    pub fn one_time_remaining(&self) -> usize {
+        self.one_time.len()
+    }
+
+    /// What to send to the relay; `identity` certifies our current exchange key
+    pub fn upload(&self, identity: &DeviceCertificate) -> PrekeyUpload {
+        PrekeyUpload {
+            identity: identity.clone(),
+            signed_prekey: self.signed[0].certificate.clone(),
+            one_time: self.one_time.iter().map(DeviceKey::public).collect(),
+        }
+    }
+
+    /// Decrypt a message picked up from the relay; a one-time prekey it used is deleted.
+    ///
+    /// `exchange` is our device exchange key. Check the fingerprint of
+    /// `message.sender` against the trust store before acting on the plaintext.
+    pub fn open(&mut self, exchange: &dyn KeyProvider, message: &OfflineMessage, now: u64, aad: &[u8]) -> Result<Vec<u8>, PrekeyError> {
+        message.sender.verify(now)?;
+        let spk = self
+            .signed
+            .iter()
+            .find(|s| s.certificate.exchange == message.signed_prekey)
+            .ok_or(PrekeyError::UnknownPrekey)?;
+        let opk = match &message.one_time {
+            Some(public) => Some(self.one_time.iter().position(|k| k.public() == *public).ok_or(PrekeyError::UnknownPrekey)?),
+            None => None,
+        };
+        let mut dh = Vec::with_capacity(4);
+        dh.push(spk.key.ecdh(&message.sender.exchange));
+        dh.push(exchange.ecdh(&message.ephemeral)?);
+        dh.push(spk.key.ecdh(&message.ephemeral));
+        if let Some(i) = opk {
+            dh.push(self.one_time[i].ecdh(&message.ephemeral));
+        }
+        let full_aad = message.aad(&exchange.public(), aad);
+        let (key, nonces) = agree(&dh, &full_aad)?;
+        let plaintext = aead_decrypt(key.as_key(), &nonces, 0, &full_aad, &message.ciphertext)?;
+        if let Some(i) = opk {
+            self.one_time.swap_remove(i);
+        }
+        Ok(plaintext)
+    }
+
+    /// Serialize the private prekeys for storage; the output is secret
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut e = Encoder::new();
+        e.map(3);
+        e.uint(0).uint(PREKEY_VERSION);
+        e.uint(1).array(self.signed.len());
+        for s in &self.signed {
+            e.array(2).bytes(s.key.to_bytes().as_ref()).bytes(&s.certificate.to_bytes());
+        }
+        e.uint(2).array(self.one_time.len());
+        for k in &self.one_time {
+            e.bytes(k.to_bytes().as_ref());
+        }
+        Zeroizing::new(e.finish())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PrekeyError> {
+        fn key(d: &mut Decoder<'_>) -> Result<DeviceKey, PrekeyError> {
+            DeviceKey::from_bytes(d.bytes()?).map_err(|_| CborError::Schema("invalid prekey").into())
+        }
+
+        let mut d = Decoder::new(bytes);
+        if d.map()? != 3 {
+            return Err(CborError::Schema("prekey store must have 3 fields").into());
+        }
+        version(&mut d)?;
+        d.key(1)?;
+        let count = d.array()?;
+        if count == 0 || count > SIGNED_PREKEYS_KEPT {
+            return Err(CborError::Schema("wrong number of signed prekeys").into());
+        }
+        let mut signed = Vec::with_capacity(count);
+        for _ in 0..count {
+            if d.array()? != 2 {
+                return Err(CborError::Schema("signed prekey must have 2 fields").into());
+            }
+            signed.push(SignedPrekey { key: key(&mut d)?, certificate: certificate(&mut d)? });
+        }
+        d.key(2)?;
+        let count = d.array()?;
+        let mut one_time = Vec::with_capacity(count.min(bytes.len() / 32));
+        for _ in 0..count {
+            one_time.push(key(&mut d)?);
+        }
+        d.finish()?;
+        Ok(Self { signed, one_time })
+    }
+}
+
+impl fmt::Debug for PrekeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrekeyStore")
+            .field("signed_prekey", &self.signed[0].certificate.exchange)
+            .field("one_time_remaining", &self.one_time.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::OsRng;
+
+    #[test]
+    fn offline_message_via_relay() {
+        let alice = DeviceIdentity::generate();
+        let bob = DeviceIdentity::generate();
+        let (alice_cert, bob_cert) = (alice.certify_exchange_key(&alice.exchange().public(), 0, 2000), bob.certify_exchange_key(&bob.exchange().public(), 0, 2000));
+
+        let mut store = PrekeyStore::generate_with_rng(&mut OsRng, &bob, 0, 2000);
+        store.add_one_time_with_rng(&mut OsRng, 1);
+        let mut relay = PrekeyUpload::from_bytes(&store.upload(&bob_cert).to_bytes()).unwrap();
+
+        let bundle = PrekeyBundle::from_bytes(&relay.take_bundle().to_bytes()).unwrap();
+        assert!(bundle.one_time.is_some());
+        assert_eq!(
+            seal(alice.exchange(), &alice_cert, &bundle, &alice.fingerprint(), 1000, b"", b"x"),
+            Err(PrekeyError::Certificate(CertificateError::WrongIdentity))
+        );
+        let message = seal(alice.exchange(), &alice_cert, &bundle, &bob.fingerprint(), 1000, b"drop", b"hello").unwrap();
+        let wire = OfflineMessage::from_bytes(&message.to_bytes()).unwrap();
+
+        let mut store = PrekeyStore::from_bytes(&store.to_bytes()).unwrap();
+        assert_eq!(store.open(bob.exchange(), &wire, 1500, b"other"), Err(PrekeyError::Crypto(CryptoError::Decrypt)));
+        assert_eq!(store.open(bob.exchange(), &wire, 1500, b"drop").unwrap(), b"hello");
+        assert_eq!(wire.sender_fingerprint(), alice.fingerprint());
+        // the one-time key is gone, so the same message cannot be delivered twice
+        assert_eq!(store.open(bob.exchange(), &wire, 1500, b"drop"), Err(PrekeyError::UnknownPrekey));
+
+        // out of one-time keys: the signed prekey alone still works, also after one rotation
+        let fallback = relay.take_bundle();
+        assert!(fallback.one_time.is_none());
+        let message = seal(alice.exchange(), &alice_cert, &fallback, &bob.fingerprint(), 1000, b"", b"again").unwrap();
+        store.rotate_signed_prekey_with_rng(&mut OsRng, &bob, 0, 2000);
+        assert_eq!(store.open(bob.exchange(), &message, 1500, b"").unwrap(), b"again");
+    }
+}
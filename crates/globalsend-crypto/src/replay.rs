@@ -0,0 +1,138 @@
+//! Sliding-window replay protection for received messages
+//!
+//! Datagram transports (UDP, QUIC datagrams) may reorder and duplicate frames,
+//! so a receiver cannot insist on strictly increasing message counters.
+//! [`ReplayFilter`] remembers the highest counter accepted so far plus a bitmap
+//! of the [`REPLAY_WINDOW`] counters below it. Anything already seen, or too
+//! far behind to be tracked, is rejected.
+//!
+//! The bitmap is a ring of 64-bit words (RFC 6479), so advancing the window
+//! only clears the words that scroll into view instead of shifting bits.
+//!
+//! Check a counter before decrypting and record it only once the ciphertext
+//! has authenticated, otherwise a forged frame could burn a counter:
+//!
+//! ```text
+//! filter.check(n)?;
+//! let pt = aead_decrypt(key, nonces, n, aad, ct)?;
+//! filter.update(n)?;
+//! ```
+
+use core::fmt;
+
+const WORD_BITS: u64 = u64::BITS as u64;
+const WORDS: usize = 16;
+/// Number of most recent counters, up to the highest accepted one, that are tracked
+pub const REPLAY_WINDOW: u64 = (WORDS as u64 - 1) * WORD_BITS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// Counter was already accepted
+    Duplicate,
+    /// Counter is older than the window (or can never be valid)
+    OutsideWindow,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Duplicate => write!(f, "replayed message"),
+            ReplayError::OutsideWindow => write!(f, "message counter outside replay window"),
+        }
+    }
+}
+
+impl core::error::Error for ReplayError {}
+
+/// Tracks which message counters have been accepted
+#[derive(Debug, Clone, Default)]
+pub struct ReplayFilter {
+    /// One past the highest accepted counter; 0 while nothing was accepted
+    next: u64,
+    bitmap: [u64; WORDS],
+}
+
+impl ReplayFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// One past the highest counter accepted so far
+    pub fn next_expected(&self) -> u64 {
+        self.next
+    }
+
+    /// Would `counter` be accepted? Does not modify the filter.
+    pub fn check(&self, counter: u64) -> Result<(), ReplayError> {
+        // NonceSequence never hands out u64::MAX, and `next` could not represent it
+        if counter == u64::MAX {
+            return Err(ReplayError::OutsideWindow);
+        }
+        if counter >= self.next {
+            return Ok(());
+        }
+        if self.next - counter > REPLAY_WINDOW {
+            return Err(ReplayError::OutsideWindow);
+        }
+        let (word, bit) = Self::position(counter);
+        if self.bitmap[word] & bit != 0 {
+            return Err(ReplayError::Duplicate);
+        }
+        Ok(())
+    }
+
+    /// Record `counter` as accepted, sliding the window forward if needed
+    pub fn update(&mut self, counter: u64) -> Result<(), ReplayError> {
+        self.check(counter)?;
+        if counter >= self.next {
+            // clear every word that scrolls into the window, at most the whole ring
+            let first = if self.next == 0 { 0 } else { (self.next - 1) / WORD_BITS + 1 };
+            let last = counter / WORD_BITS;
+            let count = (last + 1).saturating_sub(first).min(WORDS as u64);
+            for w in first..first + count {
+                self.bitmap[(w % WORDS as u64) as usize] = 0;
+            }
+            self.next = counter + 1;
+        }
+        let (word, bit) = Self::position(counter);
+        self.bitmap[word] |= bit;
+        Ok(())
+    }
+
+    fn position(counter: u64) -> (usize, u64) {
+        let word = ((counter / WORD_BITS) % WORDS as u64) as usize;
+        (word, 1 << (counter % WORD_BITS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_reordered_and_rejects_replays() {
+        let mut f = ReplayFilter::new();
+        for n in [0, 2, 1, 5, 3] {
+            assert_eq!(f.update(n), Ok(()));
+        }
+        assert_eq!(f.update(2), Err(ReplayError::Duplicate));
+        assert_eq!(f.check(4), Ok(()));
+        assert_eq!(f.next_expected(), 6);
+
+        // jump far ahead: everything behind the window is refused, the rest is fresh
+        let top = 10 * REPLAY_WINDOW + 7;
+        f.update(top).unwrap();
+        assert_eq!(f.check(5), Err(ReplayError::OutsideWindow));
+        assert_eq!(f.check(top - REPLAY_WINDOW), Err(ReplayError::OutsideWindow));
+        assert_eq!(f.update(top - REPLAY_WINDOW + 1), Ok(()));
+        assert_eq!(f.update(top - 1), Ok(()));
+        assert_eq!(f.update(top), Err(ReplayError::Duplicate));
+        assert_eq!(f.check(u64::MAX), Err(ReplayError::OutsideWindow));
+
+        // sliding by one word must not leave stale bits from the previous lap
+        let mut f = ReplayFilter::new();
+        for n in 0..4 * REPLAY_WINDOW {
+            assert_eq!(f.update(n), Ok(()), "counter {n}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Zeroizing containers for key material
+//!
+//! `chacha20poly1305::Key` and plain byte arrays are `Copy`, so every
+//! assignment or return leaves another copy of the secret behind. Keys, HKDF
+//! output, shared secrets and nonce bases derived in this crate are held in
+//! [`SecretBytes`] instead, which is not `Clone`, redacts itself in `Debug`
+//! and wipes its buffer on drop. Borrow the contents with
+//! [`SecretBytes::as_bytes`] or [`SecretKey::as_key`]; never copy them out.
+//!
+//! With the `memlock` feature the bytes move to a heap allocation that is
+//! locked in RAM for the lifetime of the value; see [`memlock`](crate::memlock).
+
+#[cfg(feature = "memlock")]
+use alloc::boxed::Box;
+use core::borrow::{Borrow, BorrowMut};
+use core::fmt;
+
+use chacha20poly1305::Key;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{CryptoError, AEAD_KEY_LEN};
+
+#[cfg(not(feature = "memlock"))]
+type Storage<const N: usize> = [u8; N];
+#[cfg(feature = "memlock")]
+type Storage<const N: usize> = Box<[u8; N]>;
+
+/// Fixed-size secret wiped on drop
+pub struct SecretBytes<const N: usize>(Storage<N>);
+
+/// 256-bit AEAD key
+pub type SecretKey = SecretBytes<AEAD_KEY_LEN>;
+
+/// X25519 (or KEM) shared secret
+pub type SharedSecret = SecretBytes<32>;
+
+impl<const N: usize> SecretBytes<N> {
+    /// All-zero buffer to be filled in place, e.g. by HKDF expand
+    pub fn zeroed() -> Self {
+        Self::new([0u8; N])
+    }
+
+    /// Take ownership of `bytes`; the caller should not keep its own copy
+    #[cfg(not(feature = "memlock"))]
+    pub fn new(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+
+    /// Take ownership of `bytes`; the caller should not keep its own copy
+    #[cfg(feature = "memlock")]
+    pub fn new(mut bytes: [u8; N]) -> Self {
+        let boxed = Box::new(bytes);
+        bytes.zeroize();
+        crate::memlock::lock(boxed.as_ptr(), N);
+        Self(boxed)
+    }
+
+    /// Copy from a slice the caller has already cut to `N` bytes
+    pub(crate) fn from_slice(bytes: &[u8]) -> Self {
+        let mut out = Self::zeroed();
+        out.as_mut_bytes().copy_from_slice(bytes);
+        out
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N] {
+        self.0.borrow()
+    }
+
+    pub fn as_mut_bytes(&mut self) -> &mut [u8; N] {
+        self.0.borrow_mut()
+    }
+}
+
+impl<const N: usize> TryFrom<&[u8]> for SecretBytes<N> {
+    type Error = CryptoError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, CryptoError> {
+        if bytes.len() != N {
+            return Err(CryptoError::InvalidKeyLength { expected: N, actual: bytes.len() });
+        }
+        Ok(Self::from_slice(bytes))
+    }
+}
+
+impl SecretBytes<AEAD_KEY_LEN> {
+    /// Borrow as the key type the AEAD crates expect
+    pub fn as_key(&self) -> &Key {
+        Key::from_slice(self.as_bytes())
+    }
+}
+
+impl<const N: usize> Drop for SecretBytes<N> {
+    fn drop(&mut self) {
+        self.as_mut_bytes().zeroize();
+        #[cfg(feature = "memlock")]
+        crate::memlock::unlock(self.0.as_ptr(), N);
+    }
+}
+
+impl<const N: usize> ZeroizeOnDrop for SecretBytes<N> {}
+
+impl<const N: usize> fmt::Debug for SecretBytes<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes<{N}>(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn redacted_and_zeroize_on_drop() {
+        let key = SecretKey::from_slice(&[0x42; AEAD_KEY_LEN]);
+        assert_eq!(key.as_key().as_slice(), &[0x42; AEAD_KEY_LEN]);
+        assert_eq!(format!("{key:?}"), "SecretBytes<32>(..)");
+        assert_eq!(SecretKey::try_from(&[0u8; 16][..]).unwrap_err(), CryptoError::InvalidKeyLength { expected: 32, actual: 16 });
+        assert_zeroize_on_drop::<SecretKey>();
+        assert_zeroize_on_drop::<crate::DeviceKey>();
+        assert_zeroize_on_drop::<crate::EphemeralKey>();
+    }
+}
@@ -0,0 +1,202 @@
+//! Known-answer self test
+//!
+//! [`known_answers`] runs every primitive the protocol rests on against a
+//! published vector: SHA-256 (FIPS 180-2), BLAKE3, HKDF-SHA256 (RFC 5869),
+//! X25519 (RFC 7748), Ed25519 (RFC 8032), XChaCha20-Poly1305
+//! (draft-irtf-cfrg-xchacha) and AES-256-GCM (the GCM spec's test case 14).
+//! A build whose backend is broken, say a miscompiled SIMD path or a bad
+//! dependency bump, fails here instead of on the first transfer.
+//!
+//! [`rng_health`] draws from an RNG and checks it is not stuck or biased:
+//! two draws differ, and 20 000 bits pass the FIPS 140-2 monobit bounds.
+//! It can only catch an RNG that is badly broken, not prove one is good.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use ed25519_dalek::{Signer, SigningKey, Verifier};
+use hkdf::Hkdf;
+#[cfg(feature = "std")]
+use rand_core::OsRng;
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey as XPublicKey, StaticSecret};
+
+/// Bits drawn for the monobit test
+const MONOBIT_BITS: usize = 20_000;
+/// Ones allowed in [`MONOBIT_BITS`], exclusive
+const MONOBIT_BOUNDS: (usize, usize) = (9_725, 10_275);
+
+/// A check's name and whether it gave the published answer
+type KnownAnswer = (&'static str, fn() -> bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestError {
+    /// Output differs from the published vector
+    Mismatch,
+    /// The RNG failed the named check
+    Rng(&'static str),
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::Mismatch => write!(f, "output differs from the known answer"),
+            SelfTestError::Rng(check) => write!(f, "random number generator failed the {check} check"),
+        }
+    }
+}
+
+impl core::error::Error for SelfTestError {}
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<(), SelfTestError>,
+}
+
+/// Every known-answer test, then the OS RNG's health
+#[cfg(feature = "std")]
+pub fn self_test() -> Vec<Check> {
+    let mut checks = known_answers();
+    checks.push(Check { name: "os rng", result: rng_health(&mut OsRng) });
+    checks
+}
+
+/// Every known-answer test, in a fixed order
+pub fn known_answers() -> Vec<Check> {
+    let tests: [KnownAnswer; 7] = [
+        ("sha-256", sha256),
+        ("blake3", blake3),
+        ("hkdf-sha256", hkdf),
+        ("x25519", x25519),
+        ("ed25519", ed25519),
+        ("xchacha20-poly1305", xchacha20poly1305),
+        ("aes-256-gcm", aes256gcm),
+    ];
+    tests.into_iter().map(|(name, test)| Check { name, result: if test() { Ok(()) } else { Err(SelfTestError::Mismatch) } }).collect()
+}
+
+/// Check `rng` is neither stuck nor grossly biased
+pub fn rng_health<R: CryptoRngCore>(rng: &mut R) -> Result<(), SelfTestError> {
+    let (mut a, mut b) = ([0u8; 32], [0u8; 32]);
+    rng.try_fill_bytes(&mut a).map_err(|_| SelfTestError::Rng("availability"))?;
+    rng.try_fill_bytes(&mut b).map_err(|_| SelfTestError::Rng("availability"))?;
+    if a == b || a.iter().all(|&x| x == a[0]) {
+        return Err(SelfTestError::Rng("repetition"));
+    }
+    let mut bits = vec![0u8; MONOBIT_BITS / 8];
+    rng.try_fill_bytes(&mut bits).map_err(|_| SelfTestError::Rng("availability"))?;
+    let ones: usize = bits.iter().map(|b| b.count_ones() as usize).sum();
+    if ones <= MONOBIT_BOUNDS.0 || ones >= MONOBIT_BOUNDS.1 {
+        return Err(SelfTestError::Rng("monobit"));
+    }
+    Ok(())
+}
+
+/// Hex without separators; the vectors below are all well formed
+fn unhex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("vector is hex")).collect()
+}
+
+fn array<const N: usize>(s: &str) -> [u8; N] {
+    unhex(s).try_into().expect("vector has the right length")
+}
+
+fn sha256() -> bool {
+    Sha256::digest(b"abc")[..] == unhex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+}
+
+fn blake3() -> bool {
+    blake3::hash(b"").as_bytes()[..] == unhex("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262")
+}
+
+/// RFC 5869 A.1
+fn hkdf() -> bool {
+    let hk = Hkdf::<Sha256>::new(Some(&unhex("000102030405060708090a0b0c")), &[0x0b; 22]);
+    let mut okm = [0u8; 42];
+    hk.expand(&unhex("f0f1f2f3f4f5f6f7f8f9"), &mut okm).is_ok() && okm[..] == unhex("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865")
+}
+
+/// RFC 7748 6.1
+fn x25519() -> bool {
+    let alice = StaticSecret::from(array::<32>("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a"));
+    let bob = StaticSecret::from(array::<32>("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb"));
+    let bob_public = XPublicKey::from(&bob);
+    bob_public.as_bytes()[..] == unhex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        && alice.diffie_hellman(&bob_public).as_bytes()[..] == unhex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")
+}
+
+/// RFC 8032 7.1, test 1
+fn ed25519() -> bool {
+    let key = SigningKey::from_bytes(&array::<32>("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60"));
+    let signature = key.sign(b"");
+    key.verifying_key().as_bytes()[..] == unhex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+        && signature.to_bytes()[..] == unhex("e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b")
+        && key.verifying_key().verify(b"", &signature).is_ok()
+}
+
+/// draft-irtf-cfrg-xchacha-03 A.3.1
+fn xchacha20poly1305() -> bool {
+    let cipher = XChaCha20Poly1305::new(&array::<32>("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f").into());
+    let nonce = array::<24>("404142434445464748494a4b4c4d4e4f5051525354555657");
+    let aad = unhex("50515253c0c1c2c3c4c5c6c7");
+    let msg = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let expected = unhex(
+        "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b4522f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff921f9664c97637da9768812f615c68b13b52ec0875924c1c7987947deafd8780acf49",
+    );
+    let sealed = cipher.encrypt(XNonce::from_slice(&nonce), Payload { msg, aad: &aad });
+    sealed.as_deref() == Ok(&expected[..]) && cipher.decrypt(XNonce::from_slice(&nonce), Payload { msg: &expected, aad: &aad }).as_deref() == Ok(&msg[..])
+}
+
+/// McGrew and Viega, "The Galois/Counter Mode of Operation", test case 14
+fn aes256gcm() -> bool {
+    let cipher = Aes256Gcm::new(&[0u8; 32].into());
+    let nonce = aes_gcm::Nonce::from_slice(&[0u8; 12]);
+    let expected = unhex("cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919");
+    cipher.encrypt(nonce, &[0u8; 16][..]).as_deref() == Ok(&expected[..]) && cipher.decrypt(nonce, &expected[..]).as_deref() == Ok(&[0u8; 16][..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the same byte forever
+    struct Stuck;
+
+    impl rand_core::RngCore for Stuck {
+        fn next_u32(&mut self) -> u32 {
+            0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0x5a);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rand_core::CryptoRng for Stuck {}
+
+    #[test]
+    fn every_known_answer_matches() {
+        let checks = self_test();
+        assert_eq!(checks.len(), 8);
+        for check in &checks {
+            assert_eq!(check.result, Ok(()), "{}", check.name);
+        }
+        assert_eq!(rng_health(&mut Stuck), Err(SelfTestError::Rng("repetition")));
+    }
+}
@@ -0,0 +1,733 @@
+//! Forward-secret session key derivation
+//!
+//! Each transfer mixes fresh ephemeral X25519 keys with the static device
+//! identities (triple DH), so compromising a device key later does not expose
+//! past sessions: the ephemeral secrets are gone once the keys are derived.
+//!
+//! Long sessions ratchet: after [`REKEY_AFTER_MESSAGES`] messages in an epoch
+//! the sender and receiver of that direction replace the key with `HKDF(key)`
+//! via [`SealKey::rekey`] / [`OpenKey::rekey`]. The ratchet is one-way, so a
+//! key captured late in a transfer does not decrypt earlier epochs, and no
+//! epoch ever gets close to nonce or volume limits.
+//!
+//! Each file in a batch gets its own key and nonce sequence from
+//! [`SealKey::derive_file_key`] / [`OpenKey::derive_file_key`]:
+//! `HKDF(file_root, "globalsend file key v1" || file_id)`, where `file_root`
+//! is a per-direction secret expanded next to the session keys. File keys do
+//! not depend on the ratchet, so resuming or re-sending one file never shares
+//! nonce space with another file or with the control messages.
+//! [`SessionKeys::for_file`] puts both directions' file keys together for a
+//! channel that carries one file.
+//!
+//! [`SessionKeys::export_keying_material`] gives other layers (e.g. an HTTP
+//! control channel next to the data channel) secrets bound to this session,
+//! in the spirit of the TLS exporter (RFC 8446 §7.5).
+//! [`SessionKeys::session_id`] is one such export, a public handle that both
+//! sides agree on.
+//!
+//! Messages are sealed with XChaCha20-Poly1305 unless both sides switch to
+//! the [`CipherSuite`] their handshake negotiated with
+//! [`SessionKeys::with_suite`]. The suite changes only the AEAD; keys,
+//! nonces, ratchet and file keys are the same for every suite.
+//!
+//! On the wire a sealed message is a [`Frame`]:
+//!
+//! ```text
+//! message number (u64 BE) || ciphertext (>= 16 bytes tag)
+//! ```
+//!
+//! The associated data is an [`Aad`]: the caller names the file, and the
+//! session id, direction and message number are bound in automatically.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt;
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::PublicKey as XPublicKey;
+use zeroize::Zeroizing;
+
+use crate::aad::{Aad, Direction as AadDirection};
+use crate::events::{self, Direction};
+use crate::hybrid::{KemSecret, KEM_SECRET_LEN};
+use crate::keyprovider::KeyProvider;
+use crate::replay::{ReplayError, ReplayFilter};
+use crate::secret::{SecretBytes, SecretKey};
+use crate::suite::{AeadCipher, CipherSuite};
+use crate::{split_okm, CryptoError, EphemeralKey, NonceSequence, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+
+const SESSION_INFO: &[u8] = b"globalsend session v2";
+const SESSION_INFO_HYBRID: &[u8] = b"globalsend session x25519+mlkem768 v2";
+const REKEY_INFO: &[u8] = b"globalsend rekey v1";
+const FILE_ROOT_INFO: &[u8] = b"globalsend file root v1";
+const FILE_KEY_INFO: &[u8] = b"globalsend file key v1";
+const EXPORTER_INFO: &[u8] = b"globalsend exporter v1";
+const META_KEY_INFO: &[u8] = b"globalsend metadata key v1";
+const SESSION_ID_LABEL: &[u8] = b"globalsend session id v1";
+/// Messages per epoch; at 64 KiB per message this is 1 TiB between ratchets
+pub const REKEY_AFTER_MESSAGES: u64 = 1 << 24;
+/// Message number prefix of a [`Frame`]
+pub const FRAME_HEADER_LEN: usize = 8;
+/// Shortest valid frame: header plus the Poly1305 tag of an empty message
+pub const MIN_FRAME_LEN: usize = FRAME_HEADER_LEN + 16;
+/// Key and base nonce for one direction
+const DIR_LEN: usize = AEAD_KEY_LEN + AEAD_NONCE_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// Epoch message budget used up; both sides must call `rekey` first
+    RekeyRequired,
+    /// Message number outside the current epoch
+    BadCounter,
+    /// Message already received or too old to tell
+    Replayed(ReplayError),
+    /// Frame shorter than a message number plus tag
+    Malformed,
+    Encrypt,
+    Decrypt,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::RekeyRequired => write!(f, "session must be rekeyed"),
+            SessionError::BadCounter => write!(f, "message number outside current epoch"),
+            SessionError::Replayed(e) => write!(f, "{e}"),
+            SessionError::Malformed => write!(f, "malformed session frame"),
+            SessionError::Encrypt => write!(f, "session encryption failed"),
+            SessionError::Decrypt => write!(f, "session decryption failed"),
+        }
+    }
+}
+
+impl core::error::Error for SessionError {}
+
+/// Sealed session message as sent on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub counter: u64,
+    pub ciphertext: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Split a received frame; only checks the length, authentication happens in [`OpenKey::open_frame`]
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, SessionError> {
+        if bytes.len() < MIN_FRAME_LEN {
+            return Err(SessionError::Malformed);
+        }
+        let (header, ciphertext) = bytes.split_at(FRAME_HEADER_LEN);
+        let counter = u64::from_be_bytes(header.try_into().expect("8 bytes"));
+        Ok(Self { counter, ciphertext })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FRAME_HEADER_LEN + self.ciphertext.len());
+        out.extend_from_slice(&self.counter.to_be_bytes());
+        out.extend_from_slice(self.ciphertext);
+        out
+    }
+}
+
+/// One direction's key, nonce sequence and ratchet position
+struct EpochKey {
+    key: SecretKey,
+    nonces: NonceSequence,
+    /// `key` set up for the session's suite
+    cipher: Box<dyn AeadCipher>,
+    epoch: u64,
+    rekey_after: u64,
+}
+
+impl EpochKey {
+    fn from_okm(okm: &[u8]) -> Self {
+        let (key, nonces) = split_okm(okm);
+        Self::new(key, nonces, CipherSuite::XChaCha20Poly1305, REKEY_AFTER_MESSAGES)
+    }
+
+    fn new(key: SecretKey, nonces: NonceSequence, suite: CipherSuite, rekey_after: u64) -> Self {
+        let cipher = suite.cipher(key.as_key());
+        Self { key, nonces, cipher, epoch: 0, rekey_after }
+    }
+
+    fn set_suite(&mut self, suite: CipherSuite) {
+        self.cipher = suite.cipher(self.key.as_key());
+    }
+
+    fn needs_rekey(&self) -> bool {
+        self.nonces.position() >= self.rekey_after
+    }
+
+    /// Ratchet forward: `key, nonce = HKDF(key, epoch)`; the old key is erased
+    fn rekey(&mut self) -> Result<(), CryptoError> {
+        let epoch = self.epoch + 1;
+        let hk = Hkdf::<Sha256>::new(Some(&epoch.to_be_bytes()), self.key.as_bytes());
+        let mut okm = SecretBytes::<DIR_LEN>::zeroed();
+        hk.expand(REKEY_INFO, okm.as_mut_bytes())?;
+        // the old key is wiped as it is dropped here
+        (self.key, self.nonces) = split_okm(okm.as_bytes());
+        self.set_suite(self.cipher.suite());
+        self.epoch = epoch;
+        Ok(())
+    }
+}
+
+/// Per-direction secret that file keys are expanded from
+type FileRoot = SecretBytes<AEAD_KEY_LEN>;
+
+fn derive_file_key(root: &FileRoot, file_id: u64) -> Result<(SecretKey, NonceSequence), CryptoError> {
+    expand_root(root, &[FILE_KEY_INFO, &file_id.to_be_bytes()])
+}
+
+/// Root for the file keys of file `file_id`'s own channel, so they never repeat the file's key
+fn derive_file_root(root: &FileRoot, file_id: u64) -> Result<FileRoot, CryptoError> {
+    let hk = Hkdf::<Sha256>::from_prk(root.as_bytes()).map_err(|_| CryptoError::KeyDerivation)?;
+    let mut child = FileRoot::zeroed();
+    hk.expand_multi_info(&[FILE_ROOT_INFO, &file_id.to_be_bytes()], child.as_mut_bytes())?;
+    Ok(child)
+}
+
+fn expand_root(root: &FileRoot, info: &[&[u8]]) -> Result<(SecretKey, NonceSequence), CryptoError> {
+    let hk = Hkdf::<Sha256>::from_prk(root.as_bytes()).map_err(|_| CryptoError::KeyDerivation)?;
+    let mut okm = SecretBytes::<DIR_LEN>::zeroed();
+    hk.expand_multi_info(info, okm.as_mut_bytes())?;
+    Ok(split_okm(okm.as_bytes()))
+}
+
+/// Key for the messages we send
+pub struct SealKey {
+    key: EpochKey,
+    file_root: FileRoot,
+    session: [u8; 32],
+    direction: AadDirection,
+}
+
+impl SealKey {
+    /// Number of ratchet steps taken so far
+    pub fn epoch(&self) -> u64 {
+        self.key.epoch
+    }
+
+    /// True once the epoch's message budget is spent
+    pub fn needs_rekey(&self) -> bool {
+        self.key.needs_rekey()
+    }
+
+    /// Independent key and nonce sequence for file `file_id` of this direction.
+    ///
+    /// The receiver gets the same pair from [`OpenKey::derive_file_key`].
+    /// Never derive the same `file_id` twice for new content.
+    pub fn derive_file_key(&self, file_id: u64) -> Result<(SecretKey, NonceSequence), CryptoError> {
+        derive_file_key(&self.file_root, file_id)
+    }
+
+    /// Key for [`meta`](crate::meta) envelopes sent in this direction
+    pub(crate) fn derive_meta_key(&self) -> Result<(SecretKey, NonceSequence), CryptoError> {
+        expand_root(&self.file_root, &[META_KEY_INFO])
+    }
+
+    /// Ratchet the send direction; the peer's [`OpenKey::rekey`] must follow
+    pub fn rekey(&mut self) -> Result<(), CryptoError> {
+        self.key.rekey()?;
+        events::emit(|s| s.rekeyed(Direction::Send, self.key.epoch));
+        Ok(())
+    }
+
+    /// Encrypt the next message, returning its message number and ciphertext
+    ///
+    /// `aad` names the file the message belongs to; the session id, direction
+    /// and message number are bound into it here.
+    pub fn seal(&mut self, aad: &Aad, plaintext: &[u8]) -> Result<(u64, Vec<u8>), SessionError> {
+        if self.needs_rekey() {
+            return Err(SessionError::RekeyRequired);
+        }
+        let nonce = self.key.nonces.next().map_err(|_| SessionError::Encrypt)?;
+        let n = nonce.counter();
+        let aad = aad.bind(&self.session, self.direction, n);
+        let ct = self.key.cipher.seal(nonce, &aad, plaintext).map_err(|_| SessionError::Encrypt)?;
+        Ok((n, ct))
+    }
+
+    /// [`SealKey::seal`] encoded as a [`Frame`]
+    pub fn seal_frame(&mut self, aad: &Aad, plaintext: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let (counter, ciphertext) = self.seal(aad, plaintext)?;
+        Ok(Frame { counter, ciphertext: &ciphertext }.to_bytes())
+    }
+}
+
+/// Key for the messages the peer sends
+pub struct OpenKey {
+    key: EpochKey,
+    replay: ReplayFilter,
+    file_root: FileRoot,
+    session: [u8; 32],
+    direction: AadDirection,
+}
+
+impl OpenKey {
+    /// Number of ratchet steps taken so far
+    pub fn epoch(&self) -> u64 {
+        self.key.epoch
+    }
+
+    /// True once the last message of the peer's epoch budget has been opened
+    pub fn needs_rekey(&self) -> bool {
+        self.replay.next_expected() >= self.key.rekey_after
+    }
+
+    /// Counterpart of the peer's [`SealKey::derive_file_key`]
+    pub fn derive_file_key(&self, file_id: u64) -> Result<(SecretKey, NonceSequence), CryptoError> {
+        derive_file_key(&self.file_root, file_id)
+    }
+
+    pub(crate) fn derive_meta_key(&self) -> Result<(SecretKey, NonceSequence), CryptoError> {
+        expand_root(&self.file_root, &[META_KEY_INFO])
+    }
+
+    /// Follow the peer's [`SealKey::rekey`]; stragglers from the old epoch are dropped
+    pub fn rekey(&mut self) -> Result<(), CryptoError> {
+        self.key.rekey()?;
+        self.replay = ReplayFilter::new();
+        events::emit(|s| s.rekeyed(Direction::Receive, self.key.epoch));
+        Ok(())
+    }
+
+    /// Decrypt message number `n` of the current epoch.
+    ///
+    /// Messages may arrive out of order within the [`REPLAY_WINDOW`](crate::replay::REPLAY_WINDOW),
+    /// but each number is accepted only once. Opening the last message of an
+    /// epoch makes `needs_rekey` true on the receiving side as well. `aad`
+    /// must name the same file the sender used.
+    pub fn open(&mut self, n: u64, aad: &Aad, ciphertext: &[u8]) -> Result<Vec<u8>, SessionError> {
+        if self.needs_rekey() {
+            return Err(SessionError::RekeyRequired);
+        }
+        if n >= self.key.rekey_after {
+            return Err(SessionError::BadCounter);
+        }
+        self.replay.check(n).map_err(SessionError::Replayed)?;
+        let aad = aad.bind(&self.session, self.direction, n);
+        let pt = self.key.cipher.open(&self.key.nonces, n, &aad, ciphertext).map_err(|_| SessionError::Decrypt)?;
+        self.replay.update(n).map_err(SessionError::Replayed)?;
+        Ok(pt)
+    }
+
+    /// Parse and open a frame from [`SealKey::seal_frame`]
+    pub fn open_frame(&mut self, frame: &[u8], aad: &Aad) -> Result<Vec<u8>, SessionError> {
+        let frame = Frame::parse(frame)?;
+        self.open(frame.counter, aad, frame.ciphertext)
+    }
+}
+
+/// AEAD key material for one transfer session.
+///
+/// Each direction has its own key and nonce sequence, so both peers can send
+/// at the same time without coordinating counters, and each direction
+/// ratchets on its own schedule.
+pub struct SessionKeys {
+    pub send: SealKey,
+    pub recv: OpenKey,
+    /// Same on both sides, unlike the directional keys
+    exporter: SecretBytes<32>,
+}
+
+impl SessionKeys {
+    /// Derive session keys from our static + ephemeral keys and the peer's public halves.
+    ///
+    /// Both sides call this with their own keys and obtain mirrored output: our
+    /// `send` is the peer's `recv` and vice versa. The ephemeral key is consumed
+    /// so it cannot be reused for another session.
+    pub fn derive(
+        static_key: &dyn KeyProvider,
+        ephemeral: EphemeralKey,
+        peer_static: &XPublicKey,
+        peer_ephemeral: &XPublicKey,
+    ) -> Result<Self, CryptoError> {
+        Self::derive_hybrid(static_key, ephemeral, peer_static, peer_ephemeral, None)
+    }
+
+    /// Like [`SessionKeys::derive`], additionally mixing in a post-quantum KEM
+    /// secret from [`hybrid`](crate::hybrid). `None` gives exactly the output of `derive`.
+    pub fn derive_hybrid(
+        static_key: &dyn KeyProvider,
+        ephemeral: EphemeralKey,
+        peer_static: &XPublicKey,
+        peer_ephemeral: &XPublicKey,
+        kem: Option<&KemSecret>,
+    ) -> Result<Self, CryptoError> {
+        let ours = (static_key.public().to_bytes(), ephemeral.public().to_bytes());
+        let theirs = (peer_static.to_bytes(), peer_ephemeral.to_bytes());
+
+        let ee = ephemeral.ecdh(peer_ephemeral);
+        let se = static_key.ecdh(peer_ephemeral)?;
+        let es = ephemeral.ecdh(peer_static);
+
+        // Order the cross terms and public keys canonically by (static, ephemeral)
+        // so both peers feed HKDF the same bytes. The same order names the
+        // directions: the lower side sends with the first key.
+        let we_are_low = ours <= theirs;
+        let (lo, hi, first, second) = if we_are_low {
+            (ours, theirs, se, es)
+        } else {
+            (theirs, ours, es, se)
+        };
+
+        let mut ikm = Zeroizing::new(Vec::with_capacity(96 + KEM_SECRET_LEN));
+        ikm.extend_from_slice(ee.as_bytes());
+        ikm.extend_from_slice(first.as_bytes());
+        ikm.extend_from_slice(second.as_bytes());
+        let info = match kem {
+            Some(secret) => {
+                ikm.extend_from_slice(secret.as_bytes());
+                SESSION_INFO_HYBRID
+            }
+            None => SESSION_INFO,
+        };
+
+        let mut salt = [0u8; 128];
+        salt[..32].copy_from_slice(&lo.0);
+        salt[32..64].copy_from_slice(&lo.1);
+        salt[64..96].copy_from_slice(&hi.0);
+        salt[96..].copy_from_slice(&hi.1);
+
+        let hk = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+        drop(ikm);
+        Self::expand(&hk, info, we_are_low)
+    }
+
+    /// Split HKDF output into directional keys, file roots and the exporter secret.
+    /// The `low` side sends with the first key.
+    pub(crate) fn expand(hk: &Hkdf<Sha256>, info: &[u8], we_are_low: bool) -> Result<Self, CryptoError> {
+        let mut okm = SecretBytes::<{ 2 * DIR_LEN }>::zeroed();
+        hk.expand(info, okm.as_mut_bytes())?;
+        let mut roots = SecretBytes::<{ 2 * AEAD_KEY_LEN }>::zeroed();
+        hk.expand_multi_info(&[info, FILE_ROOT_INFO], roots.as_mut_bytes())?;
+        let mut exporter = SecretBytes::zeroed();
+        hk.expand_multi_info(&[info, EXPORTER_INFO], exporter.as_mut_bytes())?;
+        let (lo_to_hi, hi_to_lo) = okm.as_bytes().split_at(DIR_LEN);
+        let (root_lo, root_hi) = roots.as_bytes().split_at(AEAD_KEY_LEN);
+        let ((send, send_root), (recv, recv_root)) =
+            if we_are_low { ((lo_to_hi, root_lo), (hi_to_lo, root_hi)) } else { ((hi_to_lo, root_hi), (lo_to_hi, root_lo)) };
+        let (send_dir, recv_dir) =
+            if we_are_low { (AadDirection::LowToHigh, AadDirection::HighToLow) } else { (AadDirection::HighToLow, AadDirection::LowToHigh) };
+        let mut keys = Self {
+            send: SealKey { key: EpochKey::from_okm(send), file_root: FileRoot::from_slice(send_root), session: [0; 32], direction: send_dir },
+            recv: OpenKey {
+                key: EpochKey::from_okm(recv),
+                replay: ReplayFilter::new(),
+                file_root: FileRoot::from_slice(recv_root),
+                session: [0; 32],
+                direction: recv_dir,
+            },
+            exporter,
+        };
+        let id = keys.session_id();
+        keys.send.session = id;
+        keys.recv.session = id;
+        Ok(keys)
+    }
+
+    /// `len` bytes bound to this session, `label` and `context`; both peers get the same output.
+    ///
+    /// Distinct labels give unrelated outputs. `len` is at most 8160 bytes.
+    pub fn export_keying_material(&self, label: &[u8], context: &[u8], len: usize) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        let label_len = u16::try_from(label.len()).map_err(|_| CryptoError::KeyDerivation)?.to_be_bytes();
+        let context_len = u16::try_from(context.len()).map_err(|_| CryptoError::KeyDerivation)?.to_be_bytes();
+        let hk = Hkdf::<Sha256>::from_prk(self.exporter.as_bytes()).map_err(|_| CryptoError::KeyDerivation)?;
+        let mut out = Zeroizing::new(vec![0u8; len]);
+        hk.expand_multi_info(&[EXPORTER_INFO, &label_len, label, &context_len, context], &mut out)?;
+        Ok(out)
+    }
+
+    /// Public identifier of this session, the same on both sides; safe to log or bind into AAD
+    pub fn session_id(&self) -> [u8; 32] {
+        let id = self.export_keying_material(SESSION_ID_LABEL, b"", 32).expect("32-byte export");
+        id.as_slice().try_into().expect("32 bytes")
+    }
+
+    /// Override the per-epoch message budget of both directions; must match on both sides
+    pub fn with_rekey_threshold(mut self, messages: u64) -> Self {
+        self.send.key.rekey_after = messages.max(1);
+        self.recv.key.rekey_after = messages.max(1);
+        self
+    }
+
+    /// Seal and open both directions with `suite`; must match on both sides
+    pub fn with_suite(mut self, suite: CipherSuite) -> Self {
+        self.send.key.set_suite(suite);
+        self.recv.key.set_suite(suite);
+        self
+    }
+
+    /// Suite the session's messages are sealed with
+    pub fn suite(&self) -> CipherSuite {
+        self.send.key.cipher.suite()
+    }
+
+    /// Keys for a channel that carries only file `file_id`: each direction
+    /// seals with its [`SealKey::derive_file_key`], in the session's suite
+    /// and with its session id and rekey threshold. The peer's `for_file`
+    /// with the same id mirrors it. Never derive the same `file_id` twice
+    /// for new content.
+    pub fn for_file(&self, file_id: u64) -> Result<Self, CryptoError> {
+        let (suite, rekey_after) = (self.suite(), self.send.key.rekey_after);
+        let (send, send_nonces) = self.send.derive_file_key(file_id)?;
+        let (recv, recv_nonces) = self.recv.derive_file_key(file_id)?;
+        Ok(Self {
+            send: SealKey {
+                key: EpochKey::new(send, send_nonces, suite, rekey_after),
+                file_root: derive_file_root(&self.send.file_root, file_id)?,
+                session: self.send.session,
+                direction: self.send.direction,
+            },
+            recv: OpenKey {
+                key: EpochKey::new(recv, recv_nonces, suite, rekey_after),
+                replay: ReplayFilter::new(),
+                file_root: derive_file_root(&self.recv.file_root, file_id)?,
+                session: self.recv.session,
+                direction: self.recv.direction,
+            },
+            exporter: SecretBytes::new(*self.exporter.as_bytes()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aead_decrypt, aead_encrypt, DeviceKey};
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    fn pair(threshold: u64) -> (SessionKeys, SessionKeys) {
+        let a = DeviceKey::generate();
+        let b = DeviceKey::generate();
+        let (a_eph, b_eph) = (EphemeralKey::generate(), EphemeralKey::generate());
+        let (a_eph_pub, b_eph_pub) = (a_eph.public(), b_eph.public());
+        let ka = SessionKeys::derive(&a, a_eph, &b.public(), &b_eph_pub).unwrap().with_rekey_threshold(threshold);
+        let kb = SessionKeys::derive(&b, b_eph, &a.public(), &a_eph_pub).unwrap().with_rekey_threshold(threshold);
+        (ka, kb)
+    }
+
+    #[test]
+    fn both_sides_derive_mirrored_keys() {
+        let (mut ka, mut kb) = pair(REKEY_AFTER_MESSAGES);
+        assert_eq!(ka.send.key.key.as_bytes(), kb.recv.key.key.as_bytes());
+        assert_eq!(ka.recv.key.key.as_bytes(), kb.send.key.key.as_bytes());
+        assert_ne!(ka.send.key.key.as_bytes(), ka.recv.key.key.as_bytes());
+
+        // both directions in flight at once, each with its own counter
+        let (na, ca) = ka.send.seal(&Aad::default(), b"from a").unwrap();
+        let (nb, cb) = kb.send.seal(&Aad::default(), b"from b").unwrap();
+        assert_eq!((na, nb), (0, 0));
+        assert_eq!(kb.recv.open(na, &Aad::default(), &ca).unwrap(), b"from a");
+        assert_eq!(ka.recv.open(nb, &Aad::default(), &cb).unwrap(), b"from b");
+        // a message cannot be reflected back to its sender
+        assert_eq!(ka.recv.open(0, &Aad::default(), &ca), Err(SessionError::Replayed(ReplayError::Duplicate)));
+        let (n, ca) = ka.send.seal(&Aad::default(), b"again").unwrap();
+        assert_eq!(ka.recv.open(n, &Aad::default(), &ca), Err(SessionError::Decrypt));
+
+        // a chunk of one file does not open as part of another
+        let (file1, file2) = (Aad::builder().file(1).build(), Aad::builder().file(2).build());
+        let (n, ct) = ka.send.seal(&file1, b"chunk of file 1").unwrap();
+        assert_eq!(kb.recv.open(n, &file2, &ct), Err(SessionError::Decrypt));
+        assert_eq!(kb.recv.open(n, &file1, &ct).unwrap(), b"chunk of file 1");
+
+        // a new ephemeral on one side yields unrelated keys
+        let (kc, _) = pair(REKEY_AFTER_MESSAGES);
+        assert_ne!(ka.send.key.key.as_bytes(), kc.send.key.key.as_bytes());
+    }
+
+    #[test]
+    fn each_direction_ratchets_after_threshold() {
+        let (mut a, mut b) = pair(2);
+        let (tx, rx) = (&mut a.send, &mut b.recv);
+
+        for _ in 0..2 {
+            let (n, ct) = tx.seal(&Aad::default(), b"chunk").unwrap();
+            assert_eq!(rx.open(n, &Aad::default(), &ct).unwrap(), b"chunk");
+        }
+        assert!(tx.needs_rekey() && rx.needs_rekey());
+        assert_eq!(tx.seal(&Aad::default(), b"x"), Err(SessionError::RekeyRequired));
+        // the other direction is unaffected
+        assert!(!b.send.needs_rekey());
+
+        let old_key = *tx.key.key.as_bytes();
+        tx.rekey().unwrap();
+        rx.rekey().unwrap();
+        assert_eq!(tx.epoch(), 1);
+        assert_eq!(b.send.epoch(), 0);
+        assert_ne!(tx.key.key.as_bytes(), &old_key);
+        let (n, ct) = tx.seal(&Aad::default(), b"next epoch").unwrap();
+        assert_eq!(n, 0);
+        assert_eq!(rx.open(n, &Aad::default(), &ct).unwrap(), b"next epoch");
+    }
+
+    #[test]
+    fn suites_open_only_their_own_frames() {
+        for suite in CipherSuite::ALL {
+            let (a, b) = pair(2);
+            let (mut a, mut b) = (a.with_suite(suite), b.with_suite(suite));
+            assert_eq!(a.suite(), suite);
+            let frame = a.send.seal_frame(&Aad::default(), b"hello").unwrap();
+            assert_eq!(b.recv.open_frame(&frame, &Aad::default()).unwrap(), b"hello");
+            // the suite survives the ratchet
+            a.send.seal_frame(&Aad::default(), b"").unwrap();
+            a.send.rekey().unwrap();
+            b.recv.rekey().unwrap();
+            assert_eq!(a.suite(), suite);
+            let frame = a.send.seal_frame(&Aad::default(), b"epoch 1").unwrap();
+            assert_eq!(b.recv.open_frame(&frame, &Aad::default()).unwrap(), b"epoch 1");
+        }
+        let (mut a, b) = pair(REKEY_AFTER_MESSAGES);
+        let mut b = b.with_suite(CipherSuite::Aes256Gcm);
+        let frame = a.send.seal_frame(&Aad::default(), b"mismatch").unwrap();
+        assert_eq!(b.recv.open_frame(&frame, &Aad::default()), Err(SessionError::Decrypt));
+    }
+
+    #[test]
+    fn frames_roundtrip_and_reject_short_input() {
+        let (mut a, mut b) = pair(REKEY_AFTER_MESSAGES);
+        a.send.seal(&Aad::default(), b"skipped").unwrap();
+        let hdr = Aad::builder().file(0).context(b"hdr").build();
+        let frame = a.send.seal_frame(&hdr, b"framed").unwrap();
+        assert_eq!(Frame::parse(&frame).unwrap().counter, 1);
+        assert_eq!(b.recv.open_frame(&frame, &hdr).unwrap(), b"framed");
+
+        for len in 0..MIN_FRAME_LEN {
+            assert_eq!(Frame::parse(&frame[..len]), Err(SessionError::Malformed));
+        }
+        let mut bad = frame.clone();
+        bad[7] ^= 1;
+        assert_eq!(b.recv.open_frame(&bad, &hdr), Err(SessionError::Decrypt));
+    }
+
+    #[test]
+    fn file_keys_match_across_sides_and_survive_rekey() {
+        let (mut a, b) = pair(1);
+        let (key, mut nonces) = a.send.derive_file_key(7).unwrap();
+        let nonce = nonces.next().unwrap();
+        let ct = aead_encrypt(key.as_key(), nonce, b"", b"file 7").unwrap();
+
+        a.send.seal(&Aad::default(), b"control").unwrap();
+        a.send.rekey().unwrap();
+        let (rkey, rnonces) = b.recv.derive_file_key(7).unwrap();
+        assert_eq!(aead_decrypt(rkey.as_key(), &rnonces, 0, b"", &ct).unwrap(), b"file 7");
+
+        let (other, _) = a.send.derive_file_key(8).unwrap();
+        assert_ne!(other.as_bytes(), key.as_bytes());
+        let (reverse, _) = b.send.derive_file_key(7).unwrap();
+        assert_ne!(reverse.as_bytes(), key.as_bytes());
+        assert_ne!(key.as_bytes(), a.send.key.key.as_bytes());
+
+        // a channel for file 7 seals with these keys, in both directions
+        let (mut fa, mut fb) = (a.for_file(7).unwrap(), b.for_file(7).unwrap());
+        assert_eq!((fa.send.key.key.as_bytes(), fb.send.key.key.as_bytes()), (key.as_bytes(), reverse.as_bytes()));
+        assert_eq!((fa.session_id(), fa.send.key.rekey_after), (a.session_id(), 1));
+        let (n, ct) = fa.send.seal(&Aad::default(), b"chunk of 7").unwrap();
+        assert_eq!(fb.recv.open(n, &Aad::default(), &ct).unwrap(), b"chunk of 7");
+        assert_eq!(b.for_file(8).unwrap().recv.open(n, &Aad::default(), &ct), Err(SessionError::Decrypt));
+        assert_ne!(fa.for_file(7).unwrap().send.key.key.as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn exporter_agrees_and_separates_labels() {
+        let (a, b) = pair(REKEY_AFTER_MESSAGES);
+        let token = a.export_keying_material(b"http control", b"", 32).unwrap();
+        assert_eq!(token, b.export_keying_material(b"http control", b"", 32).unwrap());
+        assert_ne!(token, a.export_keying_material(b"http control", b"ctx", 32).unwrap());
+        // length-prefixed fields: moving bytes between label and context changes the output
+        assert_ne!(a.export_keying_material(b"ab", b"c", 16).unwrap(), a.export_keying_material(b"a", b"bc", 16).unwrap());
+        assert_eq!(a.export_keying_material(b"x", b"", 255 * 32 + 1), Err(CryptoError::KeyDerivation));
+        let (c, _) = pair(REKEY_AFTER_MESSAGES);
+        assert_ne!(token, c.export_keying_material(b"http control", b"", 32).unwrap());
+    }
+
+    #[test]
+    fn out_of_order_accepted_once() {
+        let (mut a, mut b) = pair(REKEY_AFTER_MESSAGES);
+        let sealed: Vec<_> = (0..4).map(|i| a.send.seal(&Aad::default(), &[i]).unwrap()).collect();
+        for i in [2, 0, 3, 1] {
+            let (n, ct) = &sealed[i];
+            assert_eq!(b.recv.open(*n, &Aad::default(), ct).unwrap(), [i as u8]);
+        }
+        let (n, ct) = &sealed[3];
+        assert_eq!(b.recv.open(*n, &Aad::default(), ct), Err(SessionError::Replayed(ReplayError::Duplicate)));
+        // a forged frame does not burn the counter it claims
+        let (n, ct) = a.send.seal(&Aad::default(), b"real").unwrap();
+        assert_eq!(b.recv.open(n, &Aad::default(), b"forged ciphertext!"), Err(SessionError::Decrypt));
+        assert_eq!(b.recv.open(n, &Aad::default(), &ct).unwrap(), b"real");
+    }
+
+    #[test]
+    fn hybrid_secret_changes_keys_and_falls_back() {
+        use crate::hybrid::{respond, KemOffer};
+
+        let a = DeviceKey::generate();
+        let b = DeviceKey::generate();
+        let derive = |kem_a: Option<&KemSecret>, kem_b: Option<&KemSecret>| {
+            let (a_eph, b_eph) = (EphemeralKey::generate(), EphemeralKey::generate());
+            let (a_eph_pub, b_eph_pub) = (a_eph.public(), b_eph.public());
+            let ka = SessionKeys::derive_hybrid(&a, a_eph, &b.public(), &b_eph_pub, kem_a).unwrap();
+            let kb = SessionKeys::derive_hybrid(&b, b_eph, &a.public(), &a_eph_pub, kem_b).unwrap();
+            (ka, kb)
+        };
+
+        let offer = KemOffer::new();
+        let (reply, kem_b) = respond(offer.to_bytes()).unwrap();
+        let kem_a = offer.accept(&reply).unwrap();
+        let (mut ka, mut kb) = derive(kem_a.as_ref(), kem_b.as_ref());
+        let (n, ct) = ka.send.seal(&Aad::default(), b"hybrid").unwrap();
+        assert_eq!(kb.recv.open(n, &Aad::default(), &ct).unwrap(), b"hybrid");
+
+        // a secret known to only one side yields mismatched keys
+        let (_, lone) = respond(KemOffer::new().to_bytes()).unwrap();
+        if let Some(lone) = lone {
+            let (ka, kb) = derive(Some(&lone), None);
+            assert_ne!(ka.send.key.key.as_bytes(), kb.recv.key.key.as_bytes());
+        }
+    }
+
+    /// Key and nonce message `counter` goes out under: ours or the peer's
+    /// direction, the session stream or one file's key
+    fn material(keys: &SessionKeys, (send, file, counter): (bool, Option<u64>, u64)) -> ([u8; AEAD_KEY_LEN], [u8; AEAD_NONCE_LEN]) {
+        let (key, nonces) = match (send, file) {
+            (true, None) => return (*keys.send.key.key.as_bytes(), *keys.send.key.nonces.at(counter).as_bytes()),
+            (false, None) => return (*keys.recv.key.key.as_bytes(), *keys.recv.key.nonces.at(counter).as_bytes()),
+            (true, Some(file)) => keys.send.derive_file_key(file).unwrap(),
+            (false, Some(file)) => keys.recv.derive_file_key(file).unwrap(),
+        };
+        (*key.as_bytes(), *nonces.at(counter).as_bytes())
+    }
+
+    /// Small numbers often, so neighbours and equal parts come up, and the odd large one
+    fn number() -> impl Strategy<Value = u64> {
+        prop_oneof![0..4u64, any::<u64>()]
+    }
+
+    proptest! {
+        #[test]
+        fn no_two_messages_share_key_and_nonce(a in (any::<bool>(), option::of(number()), number()), b in (any::<bool>(), option::of(number()), number())) {
+            prop_assume!(a != b);
+            let (keys, _) = pair(REKEY_AFTER_MESSAGES);
+            prop_assert_ne!(material(&keys, a), material(&keys, b));
+        }
+
+        #[test]
+        fn mutated_frames_never_open(plaintext in vec(any::<u8>(), 0..512), at in any::<Index>(), flip in 1u8.., cut in any::<Index>()) {
+            let (mut ka, mut kb) = pair(REKEY_AFTER_MESSAGES);
+            let mut frame = ka.send.seal_frame(&Aad::default(), &plaintext).unwrap();
+            let i = at.index(frame.len());
+            frame[i] ^= flip;
+            prop_assert!(kb.recv.open_frame(&frame, &Aad::default()).is_err());
+            frame[i] ^= flip;
+
+            prop_assert!(kb.recv.open_frame(&frame[..cut.index(frame.len())], &Aad::default()).is_err());
+            let longer = [&frame[..], &[flip]].concat();
+            prop_assert!(kb.recv.open_frame(&longer, &Aad::default()).is_err());
+            // none of the rejects used up the message number
+            prop_assert_eq!(kb.recv.open_frame(&frame, &Aad::default()).unwrap(), plaintext);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Streaming AEAD for large payloads (STREAM construction)
+//!
+//! Plaintext is cut into fixed-size chunks which are sealed independently with
+//! XChaCha20-Poly1305. The 24-byte nonce is laid out as
+//!
+//! ```text
+//! prefix (19 bytes) || chunk counter (u32 BE) || last-chunk flag (1 byte)
+//! ```
+//!
+//! which matches `aead::stream::StreamBE32`. Reordering chunks breaks the
+//! counter and dropping the tail leaves a stream without a final chunk, so both
+//! are detected on open.
+//!
+//! Everything here runs on the caller's thread, so the same code works in a
+//! single-threaded browser (`wasm32-unknown-unknown`) receiver.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand_core::{CryptoRngCore, OsRng};
+
+use crate::{events, AEAD_NONCE_LEN};
+
+#[cfg(feature = "tokio")]
+mod async_io;
+#[cfg(feature = "tokio")]
+pub use async_io::{EncryptedReader, EncryptedWriter};
+
+/// Random per-stream nonce prefix length
+pub const STREAM_PREFIX_LEN: usize = AEAD_NONCE_LEN - 5;
+/// Plaintext bytes per chunk used by [`encrypt_stream`]/[`decrypt_stream`]
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum StreamError {
+    /// Chunk could not be sealed (exceeds AEAD length limits)
+    Encrypt,
+    /// Chunk failed authentication (tampered, reordered or wrong key)
+    Decrypt,
+    /// Stream ended without an authenticated final chunk
+    Truncated,
+    /// More than 2^32 chunks sealed under one prefix
+    CounterOverflow,
+    Io(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Encrypt => write!(f, "stream chunk encryption failed"),
+            StreamError::Decrypt => write!(f, "stream chunk decryption failed"),
+            StreamError::Truncated => write!(f, "stream truncated before final chunk"),
+            StreamError::CounterOverflow => write!(f, "stream chunk counter exhausted"),
+            StreamError::Io(e) => write!(f, "stream io error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+/// Generate a random nonce prefix for a new stream
+pub fn random_prefix() -> [u8; STREAM_PREFIX_LEN] {
+    random_prefix_with_rng(&mut OsRng)
+}
+
+pub fn random_prefix_with_rng<R: CryptoRngCore>(rng: &mut R) -> [u8; STREAM_PREFIX_LEN] {
+    let mut prefix = [0u8; STREAM_PREFIX_LEN];
+    rng.fill_bytes(&mut prefix);
+    prefix
+}
+
+fn stream_nonce(prefix: &[u8; STREAM_PREFIX_LEN], counter: u32, last: bool) -> XNonce {
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    nonce[..STREAM_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[STREAM_PREFIX_LEN..AEAD_NONCE_LEN - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[AEAD_NONCE_LEN - 1] = last as u8;
+    XNonce::from(nonce)
+}
+
+/// Encrypting half of a STREAM
+pub struct SealStream {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; STREAM_PREFIX_LEN],
+    counter: u32,
+    exhausted: bool,
+}
+
+impl SealStream {
+    pub fn new(key: &Key, prefix: &[u8; STREAM_PREFIX_LEN]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key),
+            prefix: *prefix,
+            counter: 0,
+            exhausted: false,
+        }
+    }
+
+    /// Seal a non-final chunk
+    pub fn seal_chunk(&mut self, aad: &[u8], chunk: &[u8]) -> Result<Vec<u8>, StreamError> {
+        let ct = self.seal(aad, chunk, false)?;
+        match self.counter.checked_add(1) {
+            Some(c) => self.counter = c,
+            None => self.exhausted = true,
+        }
+        Ok(ct)
+    }
+
+    /// Seal the final chunk, consuming the stream
+    pub fn seal_last(self, aad: &[u8], chunk: &[u8]) -> Result<Vec<u8>, StreamError> {
+        self.seal(aad, chunk, true)
+    }
+
+    fn seal(&self, aad: &[u8], chunk: &[u8], last: bool) -> Result<Vec<u8>, StreamError> {
+        if self.exhausted {
+            return Err(StreamError::CounterOverflow);
+        }
+        let nonce = stream_nonce(&self.prefix, self.counter, last);
+        self.cipher
+            .encrypt(&nonce, Payload { msg: chunk, aad })
+            .map_err(|_| StreamError::Encrypt)
+    }
+}
+
+/// Decrypting half of a STREAM
+pub struct OpenStream {
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; STREAM_PREFIX_LEN],
+    counter: u32,
+    exhausted: bool,
+}
+
+impl OpenStream {
+    pub fn new(key: &Key, prefix: &[u8; STREAM_PREFIX_LEN]) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key),
+            prefix: *prefix,
+            counter: 0,
+            exhausted: false,
+        }
+    }
+
+    /// Open a non-final chunk
+    pub fn open_chunk(&mut self, aad: &[u8], chunk: &[u8]) -> Result<Vec<u8>, StreamError> {
+        let pt = self.open(aad, chunk, false)?;
+        match self.counter.checked_add(1) {
+            Some(c) => self.counter = c,
+            None => self.exhausted = true,
+        }
+        Ok(pt)
+    }
+
+    /// Open the final chunk, consuming the stream
+    pub fn open_last(self, aad: &[u8], chunk: &[u8]) -> Result<Vec<u8>, StreamError> {
+        self.open(aad, chunk, true)
+    }
+
+    fn open(&self, aad: &[u8], chunk: &[u8], last: bool) -> Result<Vec<u8>, StreamError> {
+        if self.exhausted {
+            return Err(StreamError::CounterOverflow);
+        }
+        let nonce = stream_nonce(&self.prefix, self.counter, last);
+        self.cipher
+            .decrypt(&nonce, Payload { msg: chunk, aad })
+            .map_err(|_| events::decrypt_failed(StreamError::Decrypt))
+    }
+}
+
+/// Fill `buf` from `r` until full or EOF, returning the number of bytes read
+pub(crate) fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Encrypt everything from `reader` into `writer` as a prefixed STREAM.
+///
+/// Output is the nonce prefix followed by sealed chunks of
+/// `STREAM_CHUNK_LEN + 16` bytes; only the final chunk may be shorter.
+/// Returns the number of plaintext bytes consumed.
+pub fn encrypt_stream<R: Read, W: Write>(key: &Key, reader: R, writer: W) -> Result<u64, StreamError> {
+    encrypt_stream_with_rng(&mut OsRng, key, reader, writer)
+}
+
+/// [`encrypt_stream`] drawing the nonce prefix from `rng`
+pub fn encrypt_stream_with_rng<G: CryptoRngCore, R: Read, W: Write>(rng: &mut G, key: &Key, mut reader: R, mut writer: W) -> Result<u64, StreamError> {
+    let prefix = random_prefix_with_rng(rng);
+    writer.write_all(&prefix)?;
+    let mut seal = SealStream::new(key, &prefix);
+    // one byte of lookahead tells us whether the current chunk is the last one
+    let mut buf = vec![0u8; STREAM_CHUNK_LEN + 1];
+    let mut filled = read_full(&mut reader, &mut buf)?;
+    let mut total = 0u64;
+    while filled > STREAM_CHUNK_LEN {
+        writer.write_all(&seal.seal_chunk(&[], &buf[..STREAM_CHUNK_LEN])?)?;
+        total += STREAM_CHUNK_LEN as u64;
+        buf[0] = buf[STREAM_CHUNK_LEN];
+        filled = 1 + read_full(&mut reader, &mut buf[1..])?;
+    }
+    writer.write_all(&seal.seal_last(&[], &buf[..filled])?)?;
+    total += filled as u64;
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Decrypt a stream produced by [`encrypt_stream`], returning plaintext bytes written.
+///
+/// Plaintext of each chunk is written as soon as it authenticates; callers
+/// must discard the output if this returns an error.
+pub fn decrypt_stream<R: Read, W: Write>(key: &Key, mut reader: R, mut writer: W) -> Result<u64, StreamError> {
+    let mut prefix = [0u8; STREAM_PREFIX_LEN];
+    if read_full(&mut reader, &mut prefix)? != STREAM_PREFIX_LEN {
+        return Err(StreamError::Truncated);
+    }
+    let mut open = OpenStream::new(key, &prefix);
+    let ct_len = STREAM_CHUNK_LEN + TAG_LEN;
+    let mut buf = vec![0u8; ct_len + 1];
+    let mut filled = read_full(&mut reader, &mut buf)?;
+    let mut total = 0u64;
+    while filled > ct_len {
+        let pt = open.open_chunk(&[], &buf[..ct_len])?;
+        writer.write_all(&pt)?;
+        total += pt.len() as u64;
+        buf[0] = buf[ct_len];
+        filled = 1 + read_full(&mut reader, &mut buf[1..])?;
+    }
+    if filled < TAG_LEN {
+        return Err(StreamError::Truncated);
+    }
+    // a full-size chunk at EOF may be a final chunk, or a stream cut at a chunk boundary
+    let pt = open.open_last(&[], &buf[..filled]).map_err(|e| match e {
+        StreamError::Decrypt if filled == ct_len => StreamError::Truncated,
+        e => e,
+    })?;
+    writer.write_all(&pt)?;
+    total += pt.len() as u64;
+    writer.flush()?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> Key {
+        *Key::from_slice(&[7u8; 32])
+    }
+
+    #[test]
+    fn stream_roundtrip_various_sizes() {
+        for len in [0, 1, STREAM_CHUNK_LEN - 1, STREAM_CHUNK_LEN, STREAM_CHUNK_LEN + 1, 3 * STREAM_CHUNK_LEN] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut ct = Vec::new();
+            assert_eq!(encrypt_stream(&key(), &data[..], &mut ct).unwrap(), len as u64);
+            let mut pt = Vec::new();
+            assert_eq!(decrypt_stream(&key(), &ct[..], &mut pt).unwrap(), len as u64);
+            assert_eq!(pt, data);
+        }
+    }
+
+    #[test]
+    fn truncation_and_reordering_detected() {
+        let data = vec![1u8; 2 * STREAM_CHUNK_LEN + 10];
+        let mut ct = Vec::new();
+        encrypt_stream(&key(), &data[..], &mut ct).unwrap();
+
+        // drop the final chunk: stream now ends on a chunk boundary
+        let cut = STREAM_PREFIX_LEN + 2 * (STREAM_CHUNK_LEN + TAG_LEN);
+        let err = decrypt_stream(&key(), &ct[..cut], io::sink()).unwrap_err();
+        assert!(matches!(err, StreamError::Truncated));
+
+        // swap the first two chunks
+        let c = STREAM_CHUNK_LEN + TAG_LEN;
+        let mut swapped = ct.clone();
+        swapped[STREAM_PREFIX_LEN..STREAM_PREFIX_LEN + c].copy_from_slice(&ct[STREAM_PREFIX_LEN + c..STREAM_PREFIX_LEN + 2 * c]);
+        swapped[STREAM_PREFIX_LEN + c..STREAM_PREFIX_LEN + 2 * c].copy_from_slice(&ct[STREAM_PREFIX_LEN..STREAM_PREFIX_LEN + c]);
+        let err = decrypt_stream(&key(), &swapped[..], io::sink()).unwrap_err();
+        assert!(matches!(err, StreamError::Decrypt));
+    }
+}
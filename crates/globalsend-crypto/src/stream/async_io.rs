@@ -0,0 +1,239 @@
+//! Tokio adapters producing/consuming the [`encrypt_stream`](super::encrypt_stream) wire format
+//!
+//! ```ignore
+//! let mut enc = EncryptedWriter::new(socket, &key);
+//! tokio::io::copy(&mut file, &mut enc).await?;
+//! enc.shutdown().await?; // seals the final chunk
+//! ```
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use chacha20poly1305::Key;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{random_prefix, OpenStream, SealStream, StreamError, STREAM_CHUNK_LEN, STREAM_PREFIX_LEN, TAG_LEN};
+use crate::secret::SecretKey;
+
+const CT_CHUNK_LEN: usize = STREAM_CHUNK_LEN + TAG_LEN;
+
+fn invalid_data(e: StreamError) -> io::Error {
+    match e {
+        StreamError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Seals everything written to it onto the inner writer.
+///
+/// Chunks are fixed size, so `flush` only pushes out complete chunks; the
+/// final (possibly short) chunk is sealed by `shutdown`, which must be called.
+pub struct EncryptedWriter<W> {
+    inner: W,
+    seal: Option<SealStream>,
+    /// Plaintext waiting to fill a chunk
+    plain: Vec<u8>,
+    /// Ciphertext not yet accepted by `inner`
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptedWriter<W> {
+    pub fn new(inner: W, key: &Key) -> Self {
+        let prefix = random_prefix();
+        Self {
+            inner,
+            seal: Some(SealStream::new(key, &prefix)),
+            plain: Vec::with_capacity(STREAM_CHUNK_LEN),
+            out: prefix.to_vec(),
+            out_pos: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.out_pos < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.out_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.out_pos += n;
+        }
+        self.out.clear();
+        self.out_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptedWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        let Some(seal) = this.seal.as_mut() else {
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::BrokenPipe, "encrypted stream already finished")));
+        };
+        if this.plain.len() == STREAM_CHUNK_LEN && !buf.is_empty() {
+            // more data is coming, so the buffered chunk is not the last one
+            this.out = seal.seal_chunk(&[], &this.plain).map_err(invalid_data)?;
+            this.plain.clear();
+            ready!(this.poll_drain(cx))?;
+        }
+        let n = buf.len().min(STREAM_CHUNK_LEN - this.plain.len());
+        this.plain.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if let Some(seal) = this.seal.take() {
+            this.out = seal.seal_last(&[], &this.plain).map_err(invalid_data)?;
+            this.plain.clear();
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Opens a sealed stream from the inner reader, yielding plaintext.
+///
+/// EOF is only reported after the final chunk authenticated; a truncated or
+/// tampered stream surfaces as an `InvalidData` error.
+pub struct EncryptedReader<R> {
+    inner: R,
+    key: SecretKey,
+    open: Option<OpenStream>,
+    /// Ciphertext being collected; holds one byte of lookahead past a chunk
+    buf: Vec<u8>,
+    filled: usize,
+    plain: Vec<u8>,
+    plain_pos: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> EncryptedReader<R> {
+    pub fn new(inner: R, key: &Key) -> Self {
+        Self {
+            inner,
+            key: SecretKey::from_slice(key),
+            open: None,
+            buf: vec![0u8; CT_CHUNK_LEN + 1],
+            filled: 0,
+            plain: Vec::new(),
+            plain_pos: 0,
+            done: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read into `buf[filled..want]`; returns false on EOF before `want` bytes
+    fn poll_fill(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<io::Result<bool>> {
+        while self.filled < want {
+            let mut rb = ReadBuf::new(&mut self.buf[self.filled..want]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut rb))?;
+            let n = rb.filled().len();
+            if n == 0 {
+                return Poll::Ready(Ok(false));
+            }
+            self.filled += n;
+        }
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for EncryptedReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, out: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = out.remaining().min(this.plain.len() - this.plain_pos);
+                out.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.done {
+                return Poll::Ready(Ok(()));
+            }
+            if this.open.is_none() {
+                if !ready!(this.poll_fill(cx, STREAM_PREFIX_LEN))? {
+                    return Poll::Ready(Err(invalid_data(StreamError::Truncated)));
+                }
+                let mut prefix = [0u8; STREAM_PREFIX_LEN];
+                prefix.copy_from_slice(&this.buf[..STREAM_PREFIX_LEN]);
+                this.open = Some(OpenStream::new(this.key.as_key(), &prefix));
+                this.filled = 0;
+            }
+            let more = ready!(this.poll_fill(cx, CT_CHUNK_LEN + 1))?;
+            this.plain_pos = 0;
+            if more {
+                let open = this.open.as_mut().expect("stream opened");
+                this.plain = open.open_chunk(&[], &this.buf[..CT_CHUNK_LEN]).map_err(invalid_data)?;
+                this.buf[0] = this.buf[CT_CHUNK_LEN];
+                this.filled = 1;
+            } else {
+                if this.filled < TAG_LEN {
+                    return Poll::Ready(Err(invalid_data(StreamError::Truncated)));
+                }
+                let open = this.open.take().expect("stream opened");
+                let filled = this.filled;
+                this.plain = open.open_last(&[], &this.buf[..filled]).map_err(|e| match e {
+                    StreamError::Decrypt if filled == CT_CHUNK_LEN => invalid_data(StreamError::Truncated),
+                    e => invalid_data(e),
+                })?;
+                this.done = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::decrypt_stream;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn async_roundtrip_matches_sync_format() {
+        let key = *Key::from_slice(&[9u8; 32]);
+        let data: Vec<u8> = (0..2 * STREAM_CHUNK_LEN + 123).map(|i| (i % 251) as u8).collect();
+
+        let mut enc = EncryptedWriter::new(Vec::new(), &key);
+        tokio::io::copy(&mut &data[..], &mut enc).await.unwrap();
+        enc.shutdown().await.unwrap();
+        let ct = enc.into_inner();
+
+        let mut sync_pt = Vec::new();
+        decrypt_stream(&key, &ct[..], &mut sync_pt).unwrap();
+        assert_eq!(sync_pt, data);
+
+        let mut dec = EncryptedReader::new(&ct[..], &key);
+        let mut pt = Vec::new();
+        tokio::io::copy(&mut dec, &mut pt).await.unwrap();
+        assert_eq!(pt, data);
+
+        let mut dec = EncryptedReader::new(&ct[..ct.len() - 1], &key);
+        let err = tokio::io::copy(&mut dec, &mut tokio::io::sink()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,181 @@
+//! Cipher suite selection
+//!
+//! XChaCha20-Poly1305 is the default; AES-256-GCM is offered for devices with
+//! AES instructions, where it is considerably faster. Each side advertises its
+//! suites in preference order ([`CipherSuite::preferred`]) and the initiator's
+//! order wins ([`negotiate`]).
+//!
+//! Both suites use the same key size and the same [`NonceSequence`]. AES-GCM
+//! takes the trailing 12 bytes of each 24-byte message nonce, which still
+//! contain the whole counter, so nonces stay unique per key.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt;
+
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::events;
+use crate::{CryptoError, MessageNonce, NonceSequence, AEAD_NONCE_LEN};
+
+const GCM_NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    XChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl CipherSuite {
+    pub const ALL: [CipherSuite; 2] = [CipherSuite::XChaCha20Poly1305, CipherSuite::Aes256Gcm];
+
+    /// Wire identifier
+    pub fn id(self) -> u8 {
+        match self {
+            CipherSuite::XChaCha20Poly1305 => 1,
+            CipherSuite::Aes256Gcm => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.id() == id)
+    }
+
+    /// Stable name, e.g. for [`KdfContext::cipher_suite`](crate::kdf::KdfContext::cipher_suite)
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherSuite::XChaCha20Poly1305 => "XChaCha20Poly1305",
+            CipherSuite::Aes256Gcm => "AES256GCM",
+        }
+    }
+
+    /// Suites supported by this build, fastest first for the running CPU
+    pub fn preferred() -> Vec<CipherSuite> {
+        if aes_hardware() {
+            vec![CipherSuite::Aes256Gcm, CipherSuite::XChaCha20Poly1305]
+        } else {
+            vec![CipherSuite::XChaCha20Poly1305, CipherSuite::Aes256Gcm]
+        }
+    }
+
+    /// Cipher instance keyed with `key`
+    pub fn cipher(self, key: &Key) -> Box<dyn AeadCipher> {
+        match self {
+            CipherSuite::XChaCha20Poly1305 => Box::new(XChaChaCipher(XChaCha20Poly1305::new(key))),
+            CipherSuite::Aes256Gcm => Box::new(AesGcmCipher(Aes256Gcm::new(key))),
+        }
+    }
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+fn aes_hardware() -> bool {
+    std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(all(feature = "std", target_arch = "aarch64"))]
+fn aes_hardware() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes")
+}
+
+/// Without std there is no runtime detection; trust the compile-time target features
+#[cfg(not(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))))]
+fn aes_hardware() -> bool {
+    cfg!(all(target_feature = "aes", any(target_feature = "pclmulqdq", target_arch = "aarch64")))
+}
+
+/// First suite in the initiator's list that the responder also supports
+pub fn negotiate(initiator: &[CipherSuite], responder: &[CipherSuite]) -> Option<CipherSuite> {
+    initiator.iter().copied().find(|s| responder.contains(s))
+}
+
+/// Keyed AEAD, independent of the underlying algorithm
+pub trait AeadCipher: Send + Sync {
+    fn suite(&self) -> CipherSuite;
+
+    /// Encrypt with a nonce from [`NonceSequence::next`]
+    fn seal(&self, nonce: MessageNonce, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError>;
+
+    /// Decrypt message number `counter` of `nonces`
+    fn open(&self, nonces: &NonceSequence, counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError>;
+}
+
+struct XChaChaCipher(XChaCha20Poly1305);
+
+impl AeadCipher for XChaChaCipher {
+    fn suite(&self) -> CipherSuite {
+        CipherSuite::XChaCha20Poly1305
+    }
+
+    fn seal(&self, nonce: MessageNonce, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.0
+            .encrypt(XNonce::from_slice(nonce.bytes.as_bytes()), Payload { msg: plaintext, aad })
+            .map_err(|_| CryptoError::Encrypt)
+    }
+
+    fn open(&self, nonces: &NonceSequence, counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.0
+            .decrypt(XNonce::from_slice(nonces.at(counter).as_bytes()), Payload { msg: ciphertext, aad })
+            .map_err(|_| events::decrypt_failed(CryptoError::Decrypt))
+    }
+}
+
+struct AesGcmCipher(Aes256Gcm);
+
+pub(crate) fn gcm_nonce(nonce: &[u8; AEAD_NONCE_LEN]) -> &aes_gcm::Nonce<aes_gcm::aead::consts::U12> {
+    aes_gcm::Nonce::from_slice(&nonce[AEAD_NONCE_LEN - GCM_NONCE_LEN..])
+}
+
+impl AeadCipher for AesGcmCipher {
+    fn suite(&self) -> CipherSuite {
+        CipherSuite::Aes256Gcm
+    }
+
+    fn seal(&self, nonce: MessageNonce, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.0
+            .encrypt(gcm_nonce(nonce.bytes.as_bytes()), Payload { msg: plaintext, aad })
+            .map_err(|_| CryptoError::Encrypt)
+    }
+
+    fn open(&self, nonces: &NonceSequence, counter: u64, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.0
+            .decrypt(gcm_nonce(nonces.at(counter).as_bytes()), Payload { msg: ciphertext, aad })
+            .map_err(|_| events::decrypt_failed(CryptoError::Decrypt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derive_aead;
+
+    #[test]
+    fn negotiation_and_roundtrip_per_suite() {
+        use CipherSuite::*;
+        assert_eq!(negotiate(&[Aes256Gcm, XChaCha20Poly1305], &[XChaCha20Poly1305, Aes256Gcm]), Some(Aes256Gcm));
+        assert_eq!(negotiate(&[Aes256Gcm], &[XChaCha20Poly1305]), None);
+        assert_eq!(CipherSuite::preferred().len(), CipherSuite::ALL.len());
+
+        for suite in CipherSuite::ALL {
+            assert_eq!(CipherSuite::from_id(suite.id()), Some(suite));
+            let (key, mut nonces) = derive_aead(&[suite.id(); 32]).unwrap();
+            let cipher = suite.cipher(key.as_key());
+            assert_eq!(cipher.suite(), suite);
+            let _ = cipher.seal(nonces.next().unwrap(), b"aad", b"first").unwrap();
+            let ct = cipher.seal(nonces.next().unwrap(), b"aad", b"second").unwrap();
+            assert_eq!(cipher.open(&nonces, 1, b"aad", &ct).unwrap(), b"second");
+            assert!(cipher.open(&nonces, 0, b"aad", &ct).is_err());
+            assert!(cipher.open(&nonces, 1, b"other", &ct).is_err());
+        }
+        // the xchacha suite is wire-compatible with the free functions
+        let (key, mut nonces) = derive_aead(&[0; 32]).unwrap();
+        let ct = crate::aead_encrypt(key.as_key(), nonces.next().unwrap(), b"", b"x").unwrap();
+        assert_eq!(XChaCha20Poly1305.cipher(key.as_key()).open(&nonces, 0, b"", &ct).unwrap(), b"x");
+    }
+}
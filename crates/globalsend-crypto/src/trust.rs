@@ -0,0 +1,216 @@
+//! Trust-on-first-use pinning of peer fingerprints
+//!
+//! The first time we complete a handshake with a peer its fingerprint is
+//! pinned under the peer's device id. Later handshakes are checked against the
+//! pin so the application can warn loudly when a known device suddenly shows
+//! up with a different key.
+//!
+//! The store is persisted as a text file in the spirit of `known_hosts`:
+//!
+//! ```text
+//! <fingerprint hex> <first seen unix secs> <last seen unix secs> <peer id>
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::identity::Fingerprint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustDecision {
+    /// Never seen this peer before
+    New,
+    /// Fingerprint matches the pinned one
+    Known,
+    /// Peer presented a different key than the one pinned
+    Changed { previous: Fingerprint },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedPeer {
+    pub fingerprint: Fingerprint,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug)]
+pub enum TrustStoreError {
+    /// Peer ids may not be empty or contain line breaks
+    InvalidPeerId,
+    Parse { line: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for TrustStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustStoreError::InvalidPeerId => write!(f, "invalid peer id"),
+            TrustStoreError::Parse { line } => write!(f, "malformed trust store entry on line {line}"),
+            TrustStoreError::Io(e) => write!(f, "trust store io error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TrustStoreError {}
+
+impl From<io::Error> for TrustStoreError {
+    fn from(e: io::Error) -> Self {
+        TrustStoreError::Io(e)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn valid_peer_id(id: &str) -> bool {
+    !id.trim().is_empty() && !id.contains(['\n', '\r'])
+}
+
+/// Pinned peer fingerprints, optionally backed by a file
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    path: Option<PathBuf>,
+    peers: BTreeMap<String, TrustedPeer>,
+}
+
+impl TrustStore {
+    /// Store that is never persisted
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Load the store at `path`; a missing file yields an empty store
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, TrustStoreError> {
+        let path = path.into();
+        let peers = match fs::read_to_string(&path) {
+            Ok(text) => parse(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path: Some(path), peers })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Compare `fingerprint` against the pin for `peer_id` without modifying the store
+    pub fn check(&self, peer_id: &str, fingerprint: &Fingerprint) -> TrustDecision {
+        match self.peers.get(peer_id) {
+            None => TrustDecision::New,
+            Some(p) if p.fingerprint == *fingerprint => TrustDecision::Known,
+            Some(p) => TrustDecision::Changed { previous: p.fingerprint },
+        }
+    }
+
+    /// Check and record a handshake: new peers are pinned, known peers get
+    /// their last-seen time bumped. A changed key is reported but never
+    /// overwrites the pin; call [`TrustStore::pin`] once the user accepts it.
+    pub fn observe(&mut self, peer_id: &str, fingerprint: &Fingerprint) -> Result<TrustDecision, TrustStoreError> {
+        let decision = self.check(peer_id, fingerprint);
+        match decision {
+            TrustDecision::New => self.pin(peer_id, fingerprint)?,
+            TrustDecision::Known => {
+                if let Some(p) = self.peers.get_mut(peer_id) {
+                    p.last_seen = now();
+                }
+            }
+            TrustDecision::Changed { .. } => {}
+        }
+        Ok(decision)
+    }
+
+    /// Pin (or re-pin) `peer_id` to `fingerprint`
+    pub fn pin(&mut self, peer_id: &str, fingerprint: &Fingerprint) -> Result<(), TrustStoreError> {
+        if !valid_peer_id(peer_id) {
+            return Err(TrustStoreError::InvalidPeerId);
+        }
+        let t = now();
+        self.peers.insert(
+            peer_id.to_string(),
+            TrustedPeer { fingerprint: *fingerprint, first_seen: t, last_seen: t },
+        );
+        Ok(())
+    }
+
+    /// Remove the pin for `peer_id`, returning it if present
+    pub fn forget(&mut self, peer_id: &str) -> Option<TrustedPeer> {
+        self.peers.remove(peer_id)
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<&TrustedPeer> {
+        self.peers.get(peer_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TrustedPeer)> {
+        self.peers.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Persist to the backing file (no-op for in-memory stores)
+    pub fn save(&self) -> Result<(), TrustStoreError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = fs::File::create(&tmp)?;
+            for (id, p) in &self.peers {
+                writeln!(f, "{} {} {} {}", p.fingerprint.to_hex(), p.first_seen, p.last_seen, id)?;
+            }
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn parse(text: &str) -> Result<BTreeMap<String, TrustedPeer>, TrustStoreError> {
+    let mut peers = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = || TrustStoreError::Parse { line: i + 1 };
+        let mut parts = line.splitn(4, ' ');
+        let fingerprint = parts.next().and_then(Fingerprint::from_hex).ok_or_else(err)?;
+        let first_seen = parts.next().and_then(|s| s.parse().ok()).ok_or_else(err)?;
+        let last_seen = parts.next().and_then(|s| s.parse().ok()).ok_or_else(err)?;
+        let id = parts.next().filter(|s| valid_peer_id(s)).ok_or_else(err)?;
+        peers.insert(id.to_string(), TrustedPeer { fingerprint, first_seen, last_seen });
+    }
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::DeviceIdentity;
+
+    #[test]
+    fn tofu_decisions_persist() {
+        let path = std::env::temp_dir().join(format!("globalsend-trust-{}", std::process::id()));
+        let phone = DeviceIdentity::generate().fingerprint();
+        let imposter = DeviceIdentity::generate().fingerprint();
+
+        let mut store = TrustStore::open(&path).unwrap();
+        assert_eq!(store.observe("Pixel 8", &phone).unwrap(), TrustDecision::New);
+        assert_eq!(store.observe("Pixel 8", &phone).unwrap(), TrustDecision::Known);
+        store.save().unwrap();
+
+        let mut store = TrustStore::open(&path).unwrap();
+        assert_eq!(store.check("Pixel 8", &phone), TrustDecision::Known);
+        assert_eq!(store.observe("Pixel 8", &imposter).unwrap(), TrustDecision::Changed { previous: phone });
+        // the pin survives a changed key until explicitly replaced
+        assert_eq!(store.check("Pixel 8", &phone), TrustDecision::Known);
+        assert!(store.pin("bad\nid", &phone).is_err());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,272 @@
+//! Known-answer tests for other implementations
+//!
+//! Built with the `test-vectors` feature. [`TestVectors::generate`] runs the
+//! key derivation, nonce construction and STREAM code with a seeded
+//! [`TestRng`] and records inputs and outputs as JSON; a Kotlin or Swift port
+//! replays the same inputs and must match bit for bit. The published suite is
+//! `vectors/kat-v1.json`, and [`TestVectors::verify`] checks a suite against
+//! this crate.
+//!
+//! All byte strings are lowercase hex. Stream plaintexts are not stored: byte
+//! `i` of a plaintext is `i % 251`. Ciphertexts longer than
+//! [`MAX_INLINE_CIPHERTEXT`] bytes are given only as their SHA-256.
+
+use std::fmt;
+
+use rand_core::{CryptoRng, CryptoRngCore, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::kdf::KdfContext;
+pub use crate::test_rng::TestRng;
+use crate::stream::{encrypt_stream_with_rng, STREAM_CHUNK_LEN, STREAM_PREFIX_LEN};
+use crate::{derive_aead, AEAD_NONCE_LEN};
+
+pub const VECTORS_VERSION: u32 = 1;
+/// Longest stream ciphertext written out in full
+pub const MAX_INLINE_CIPHERTEXT: usize = 512;
+
+#[derive(Debug)]
+pub enum VectorError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    /// Field is not valid hex or has the wrong length
+    BadField(&'static str),
+    /// Output differs from this implementation
+    Mismatch { section: &'static str, index: usize },
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorError::Json(e) => write!(f, "invalid test vector json: {e}"),
+            VectorError::UnsupportedVersion(v) => write!(f, "unsupported test vector version {v}"),
+            VectorError::BadField(name) => write!(f, "invalid test vector field {name}"),
+            VectorError::Mismatch { section, index } => write!(f, "{section} vector {index} does not match"),
+        }
+    }
+}
+
+impl std::error::Error for VectorError {}
+
+impl From<serde_json::Error> for VectorError {
+    fn from(e: serde_json::Error) -> Self {
+        VectorError::Json(e)
+    }
+}
+
+/// [`derive_aead`] on a raw shared secret
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfVector {
+    pub shared_secret: String,
+    pub key: String,
+    pub nonce_base: String,
+}
+
+/// Base nonce XOR big-endian counter in the last 8 bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NonceVector {
+    pub base: String,
+    pub counter: u64,
+    pub nonce: String,
+}
+
+/// [`KdfContext::derive_aead`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextVector {
+    pub version: u16,
+    pub suite: String,
+    pub public_key_a: String,
+    pub public_key_b: String,
+    pub transcript: String,
+    pub shared_secret: String,
+    pub key: String,
+    pub nonce_base: String,
+}
+
+/// [`encrypt_stream`](crate::stream::encrypt_stream) output, prefix included
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamVector {
+    pub key: String,
+    pub prefix: String,
+    pub plaintext_len: usize,
+    pub ciphertext_sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ciphertext: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    pub version: u32,
+    pub seed: String,
+    pub kdf: Vec<KdfVector>,
+    pub nonce: Vec<NonceVector>,
+    pub context: Vec<ContextVector>,
+    pub stream: Vec<StreamVector>,
+}
+
+fn random<const N: usize>(rng: &mut TestRng) -> [u8; N] {
+    let mut out = [0u8; N];
+    rng.fill_bytes(&mut out);
+    out
+}
+
+fn unhex<const N: usize>(s: &str, field: &'static str) -> Result<[u8; N], VectorError> {
+    hex::decode(s).ok().and_then(|v| v.try_into().ok()).ok_or(VectorError::BadField(field))
+}
+
+fn stream_plaintext(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn kdf_vector(shared: &[u8; 32]) -> KdfVector {
+    let (key, nonces) = derive_aead(shared).expect("fixed length");
+    KdfVector { shared_secret: hex::encode(shared), key: hex::encode(key.as_bytes()), nonce_base: hex::encode(nonces.at(0).as_bytes()) }
+}
+
+fn context_vector(version: u16, suite: &str, a: [u8; 32], b: [u8; 32], transcript: [u8; 32], shared: &[u8; 32]) -> ContextVector {
+    let ctx = KdfContext::new(version).cipher_suite(suite).public_keys(&a.into(), &b.into()).transcript(&transcript);
+    let (key, nonces) = ctx.derive_aead(shared).expect("fixed length");
+    ContextVector {
+        version,
+        suite: suite.into(),
+        public_key_a: hex::encode(a),
+        public_key_b: hex::encode(b),
+        transcript: hex::encode(transcript),
+        shared_secret: hex::encode(shared),
+        key: hex::encode(key.as_bytes()),
+        nonce_base: hex::encode(nonces.at(0).as_bytes()),
+    }
+}
+
+/// Encrypt the pattern plaintext, taking the prefix from `rng`
+fn stream_vector(key: [u8; 32], len: usize, rng: &mut impl CryptoRngCore) -> StreamVector {
+    let mut ct = Vec::new();
+    encrypt_stream_with_rng(rng, &key.into(), &stream_plaintext(len)[..], &mut ct).expect("in-memory stream");
+    StreamVector {
+        key: hex::encode(key),
+        prefix: hex::encode(&ct[..STREAM_PREFIX_LEN]),
+        plaintext_len: len,
+        ciphertext_sha256: hex::encode(Sha256::digest(&ct)),
+        ciphertext: (ct.len() <= MAX_INLINE_CIPHERTEXT).then(|| hex::encode(&ct)),
+    }
+}
+
+/// RNG that replays a fixed prefix, for re-running a recorded stream
+struct Replay([u8; STREAM_PREFIX_LEN]);
+
+impl RngCore for Replay {
+    fn next_u32(&mut self) -> u32 {
+        unreachable!("stream only draws the prefix")
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        unreachable!("stream only draws the prefix")
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        dest.copy_from_slice(&self.0);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for Replay {}
+
+impl TestVectors {
+    /// Run every primitive on inputs drawn from `seed`
+    pub fn generate(seed: [u8; 32]) -> Self {
+        let mut rng = TestRng::new(seed);
+        let kdf = (0..3).map(|_| kdf_vector(&random(&mut rng))).collect();
+        let nonce = [0, 1, 0xff, 1 << 32, u64::MAX - 1]
+            .into_iter()
+            .map(|counter| {
+                let base = random::<AEAD_NONCE_LEN>(&mut rng);
+                let nonce = crate::message_nonce(&base, counter);
+                NonceVector { base: hex::encode(base), counter, nonce: hex::encode(nonce.as_bytes()) }
+            })
+            .collect();
+        let context = vec![
+            context_vector(1, "Noise_XX_25519_ChaChaPoly_SHA256", random(&mut rng), random(&mut rng), random(&mut rng), &random(&mut rng)),
+            context_vector(2, "", random(&mut rng), random(&mut rng), random(&mut rng), &random(&mut rng)),
+        ];
+        let stream = [0, 1, 200, STREAM_CHUNK_LEN, STREAM_CHUNK_LEN + 1]
+            .into_iter()
+            .map(|len| {
+                let key = random(&mut rng);
+                stream_vector(key, len, &mut rng)
+            })
+            .collect();
+        Self { version: VECTORS_VERSION, seed: hex::encode(seed), kdf, nonce, context, stream }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("vectors serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, VectorError> {
+        let v: Self = serde_json::from_str(json)?;
+        if v.version != VECTORS_VERSION {
+            return Err(VectorError::UnsupportedVersion(v.version));
+        }
+        Ok(v)
+    }
+
+    /// Recompute every output from its recorded inputs
+    pub fn verify(&self) -> Result<(), VectorError> {
+        for (index, v) in self.kdf.iter().enumerate() {
+            if kdf_vector(&unhex(&v.shared_secret, "kdf.shared_secret")?) != *v {
+                return Err(VectorError::Mismatch { section: "kdf", index });
+            }
+        }
+        for (index, v) in self.nonce.iter().enumerate() {
+            let nonce = crate::message_nonce(&unhex(&v.base, "nonce.base")?, v.counter);
+            if hex::encode(nonce.as_bytes()) != v.nonce {
+                return Err(VectorError::Mismatch { section: "nonce", index });
+            }
+        }
+        for (index, v) in self.context.iter().enumerate() {
+            let got = context_vector(
+                v.version,
+                &v.suite,
+                unhex(&v.public_key_a, "context.public_key_a")?,
+                unhex(&v.public_key_b, "context.public_key_b")?,
+                unhex(&v.transcript, "context.transcript")?,
+                &unhex(&v.shared_secret, "context.shared_secret")?,
+            );
+            if got != *v {
+                return Err(VectorError::Mismatch { section: "context", index });
+            }
+        }
+        for (index, v) in self.stream.iter().enumerate() {
+            let mut replay = Replay(unhex(&v.prefix, "stream.prefix")?);
+            let got = stream_vector(unhex(&v.key, "stream.key")?, v.plaintext_len, &mut replay);
+            if got.ciphertext_sha256 != v.ciphertext_sha256 || (v.ciphertext.is_some() && got.ciphertext != v.ciphertext) {
+                return Err(VectorError::Mismatch { section: "stream", index });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLISHED: &str = include_str!("../vectors/kat-v1.json");
+
+    #[test]
+    fn published_suite_verifies_and_regenerates() {
+        let published = TestVectors::from_json(PUBLISHED).unwrap();
+        published.verify().unwrap();
+        let seed = unhex(&published.seed, "seed").unwrap();
+        assert_eq!(TestVectors::generate(seed), published);
+
+        let mut tampered = published.clone();
+        tampered.kdf[1].key = published.kdf[0].key.clone();
+        assert!(matches!(tampered.verify(), Err(VectorError::Mismatch { section: "kdf", index: 1 })));
+    }
+}
@@ -0,0 +1,175 @@
+//! Wrapping secrets for storage
+//!
+//! Relay auth tokens, ticket keys, prekey private parts and the like are
+//! kept on disk wrapped under a [`WrapKey`] derived from the device
+//! identity, rather than under a scheme of each component's own:
+//!
+//! ```text
+//! version u8 (1) | siv [24] | XChaCha20-Poly1305(secret)
+//! siv = HMAC-SHA256(mac key, len u16 BE || purpose || secret)[..24]
+//! ```
+//!
+//! The nonce is synthetic (SIV): it is computed from the secret instead of
+//! drawn at random, so wrapping needs no RNG and a repeated nonce can only
+//! come from wrapping the same secret twice, which gives the same bytes and
+//! reveals nothing else. The `purpose` (e.g. `"relay-token"`) is bound as
+//! AAD, so a secret wrapped for one component does not unwrap as another's.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::ct::ct_eq_array;
+use crate::identity::DeviceIdentity;
+use crate::secret::SecretKey;
+use crate::{CryptoError, AEAD_NONCE_LEN};
+
+pub const WRAP_VERSION: u8 = 1;
+/// Bytes a wrapped secret adds to the secret itself
+pub const WRAP_OVERHEAD: usize = 1 + AEAD_NONCE_LEN + 16;
+
+const WRAP_INFO: &[u8] = b"globalsend key wrap v1";
+const ENC_LABEL: &[u8] = b" enc";
+const MAC_LABEL: &[u8] = b" mac";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WrapError {
+    Malformed,
+    UnsupportedVersion(u8),
+    /// Wrapped under another key or purpose, or modified
+    InvalidWrap,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for WrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WrapError::Malformed => write!(f, "malformed wrapped key"),
+            WrapError::UnsupportedVersion(v) => write!(f, "unsupported key wrap version {v}"),
+            WrapError::InvalidWrap => write!(f, "wrapped key does not open under this key and purpose"),
+            WrapError::Crypto(e) => write!(f, "key wrap: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for WrapError {}
+
+impl From<CryptoError> for WrapError {
+    fn from(e: CryptoError) -> Self {
+        WrapError::Crypto(e)
+    }
+}
+
+/// Key pair that wraps and unwraps stored secrets
+pub struct WrapKey {
+    enc: SecretKey,
+    mac: SecretKey,
+}
+
+impl WrapKey {
+    /// Derive from the identity's secret key; the same identity always gives the same key
+    pub fn for_identity(identity: &DeviceIdentity) -> Result<Self, CryptoError> {
+        Self::derive(identity.signing_seed().as_ref())
+    }
+
+    /// Derive from any high-entropy secret, e.g. one kept in the platform keystore
+    pub fn derive(secret: &[u8]) -> Result<Self, CryptoError> {
+        let hk = Hkdf::<Sha256>::new(None, secret);
+        let (mut enc, mut mac) = (SecretKey::zeroed(), SecretKey::zeroed());
+        hk.expand(&[WRAP_INFO, ENC_LABEL].concat(), enc.as_mut_bytes())?;
+        hk.expand(&[WRAP_INFO, MAC_LABEL].concat(), mac.as_mut_bytes())?;
+        Ok(Self { enc, mac })
+    }
+
+    fn siv(&self, purpose: &str, secret: &[u8]) -> [u8; AEAD_NONCE_LEN] {
+        let mut m = <Hmac<Sha256> as Mac>::new_from_slice(self.mac.as_bytes()).expect("hmac takes any key length");
+        m.update(&(purpose.len() as u16).to_be_bytes());
+        m.update(purpose.as_bytes());
+        m.update(secret);
+        let mut siv = [0u8; AEAD_NONCE_LEN];
+        siv.copy_from_slice(&m.finalize().into_bytes()[..AEAD_NONCE_LEN]);
+        siv
+    }
+}
+
+impl fmt::Debug for WrapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WrapKey(..)")
+    }
+}
+
+/// AAD for `purpose`: the version byte and the purpose
+fn aad(purpose: &str) -> Vec<u8> {
+    [&[WRAP_VERSION][..], purpose.as_bytes()].concat()
+}
+
+/// Wrap `secret` for storage under `key`, tagged with what it is for
+pub fn wrap_key(key: &WrapKey, purpose: &str, secret: &[u8]) -> Result<Vec<u8>, WrapError> {
+    if purpose.len() > usize::from(u16::MAX) {
+        return Err(WrapError::Malformed);
+    }
+    let siv = key.siv(purpose, secret);
+    let ct = XChaCha20Poly1305::new(key.enc.as_key())
+        .encrypt(XNonce::from_slice(&siv), Payload { msg: secret, aad: &aad(purpose) })
+        .map_err(|_| CryptoError::Encrypt)?;
+    let mut out = Vec::with_capacity(WRAP_OVERHEAD + secret.len());
+    out.push(WRAP_VERSION);
+    out.extend_from_slice(&siv);
+    out.extend_from_slice(&ct);
+    Ok(out)
+}
+
+/// Open what [`wrap_key`] produced with the same key and purpose
+pub fn unwrap_key(key: &WrapKey, purpose: &str, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>, WrapError> {
+    if wrapped.len() < WRAP_OVERHEAD || purpose.len() > usize::from(u16::MAX) {
+        return Err(WrapError::Malformed);
+    }
+    if wrapped[0] != WRAP_VERSION {
+        return Err(WrapError::UnsupportedVersion(wrapped[0]));
+    }
+    let siv: &[u8; AEAD_NONCE_LEN] = wrapped[1..1 + AEAD_NONCE_LEN].try_into().expect("length checked");
+    let secret = XChaCha20Poly1305::new(key.enc.as_key())
+        .decrypt(XNonce::from_slice(siv), Payload { msg: &wrapped[1 + AEAD_NONCE_LEN..], aad: &aad(purpose) })
+        .map(Zeroizing::new)
+        .map_err(|_| WrapError::InvalidWrap)?;
+    if !ct_eq_array(&key.siv(purpose, &secret), siv) {
+        return Err(WrapError::InvalidWrap);
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_roundtrip_bound_to_key_and_purpose() {
+        let identity = DeviceIdentity::generate();
+        let key = WrapKey::for_identity(&identity).unwrap();
+        let wrapped = wrap_key(&key, "relay-token", b"bearer abc123").unwrap();
+        assert_eq!(wrapped.len(), WRAP_OVERHEAD + 13);
+        assert_eq!(unwrap_key(&key, "relay-token", &wrapped).unwrap().as_slice(), b"bearer abc123");
+
+        // deterministic, and the same for a key derived again from the same identity
+        let again = WrapKey::for_identity(&identity).unwrap();
+        assert_eq!(wrap_key(&again, "relay-token", b"bearer abc123").unwrap(), wrapped);
+        assert_ne!(wrap_key(&key, "relay-token", b"bearer abc124").unwrap()[1..25], wrapped[1..25]);
+
+        assert_eq!(unwrap_key(&key, "ticket-key", &wrapped), Err(WrapError::InvalidWrap));
+        let other = WrapKey::for_identity(&DeviceIdentity::generate()).unwrap();
+        assert_eq!(unwrap_key(&other, "relay-token", &wrapped), Err(WrapError::InvalidWrap));
+        let mut tampered = wrapped.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(unwrap_key(&key, "relay-token", &tampered), Err(WrapError::InvalidWrap));
+        tampered[0] = 2;
+        assert_eq!(unwrap_key(&key, "relay-token", &tampered), Err(WrapError::UnsupportedVersion(2)));
+        assert_eq!(unwrap_key(&key, "relay-token", &wrapped[..WRAP_OVERHEAD - 1]), Err(WrapError::Malformed));
+        assert_eq!(unwrap_key(&key, "", &wrap_key(&key, "", b"").unwrap()).unwrap().as_slice(), b"");
+    }
+}
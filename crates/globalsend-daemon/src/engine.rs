@@ -0,0 +1,1089 @@
+//! Driving one transfer over one connection
+//!
+//! Both sides exchange hellos first, in the oldest version so any peer can
+//! read them, then switch the codec to the negotiated version and, from
+//! [`CAPABILITIES_VERSION`], swap [`Capabilities`] too, and from
+//! [`IDENTITY_VERSION`] an [`IdentityProof`] that the fingerprint in each
+//! hello belongs to the key the handshake authenticated. After that
+//! the [`TransferSession`] decides what goes on the wire; this module only
+//! reads and writes files and waits on the peer, the user and the
+//! transfer's [`CancelToken`].
+//!
+//! The sender reads the files it has open side by side and sends whichever
+//! chunk is ready while the window has room. Over QUIC, from
+//! [`FILE_STREAM_VERSION`], each file's chunks go on a stream of its own,
+//! sealed with the file's keys; headers, acks and everything else stay on
+//! the control channel. The receiver reads a file's stream once its header
+//! is in.
+//!
+//! A receive from a proven peer keeps a [`Checkpoint`] while a
+//! [`CheckpointStore`] is configured, saved whenever the sender has checked
+//! our bytes (each [`HashAck`](globalsend_proto::HashAck) and each verified
+//! file). Cut off part way, it keeps its files; when the same transfer is
+//! offered again it is taken without asking, from the end of what is
+//! still intact on disk. The sender skips ahead to that point in its file.
+//!
+//! Each transfer runs in a `transfer` tracing span carrying its id,
+//! direction and peer, and feeds the metrics `globalsend_transfers_active`,
+//! `globalsend_transfers_total` (by `direction` and `outcome`),
+//! `globalsend_bytes_sent_total` and `globalsend_bytes_received_total`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use globalsend_crypto::certificate::{self, CertificateError};
+use globalsend_crypto::identity::{self, DeviceIdentity, Fingerprint, VerifyingKey};
+use globalsend_crypto::meta::{MetaEnvelope, MetaError, MetaOpener, MetaSealer};
+use globalsend_crypto::session::SessionKeys;
+use globalsend_proto::{
+    negotiate, negotiate_capabilities, CancelReason, Capabilities, DeltaOp, Hello, HelloCapabilities, IdentityProof, Message, Metadata, OfferedFile, ProtoError, TransferId, TransferOffer, VersionRange,
+    CAPABILITIES_VERSION, MIN_SUPPORTED_VERSION,
+};
+use globalsend_store::TransferRecord;
+use globalsend_transfer::config::{ACCEPT_FILES_VERSION, CHUNK_ACK_VERSION, FILE_STREAM_VERSION, HASH_ACK_VERSION, IDENTITY_VERSION, METADATA_VERSION, SYNC_VERSION};
+use globalsend_transfer::delta::{self, Signature};
+use globalsend_transfer::folder::Layout;
+use globalsend_transfer::preflight::{self, OnCollision};
+use globalsend_transfer::preview::{self, Preview};
+use globalsend_transfer::session::MAX_OPEN_FILES;
+use globalsend_transfer::{
+    CancelToken, Checkpoint, CheckpointStore, Direction, Failure, FileStatus, KeepPartial, TransferConfig, TransferError, TransferEvents, TransferSession, TransferState,
+};
+use globalsend_transport::connect::{file_channel, Connection, ControlChannel};
+use globalsend_transport::quic::{QuicConnection, QuicStream};
+use globalsend_transport::{CodecError, Peer, RateLimiter};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+
+use crate::hooks::{self, HookEvent};
+use crate::{now, Answer, Entry, Shared};
+
+/// First version whose senders understand a refusal with reasons
+const REFUSAL_VERSION: u16 = 7;
+/// Optional features this engine implements; of delta, only the sending side
+const CAPABILITIES: Capabilities = Capabilities::from_bits(Capabilities::COMPRESSION.bits() | Capabilities::DELTA.bits());
+/// Chunks or delta batches read ahead of the window, across files
+const READ_AHEAD: usize = 8;
+/// How long the side that sent the last message waits for the peer to hang
+/// up, so closing the connection cannot cut that message off
+const LINGER: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub(crate) enum EngineError {
+    Io(io::Error),
+    Codec(CodecError),
+    Proto(ProtoError),
+    Transfer(TransferError),
+    /// The peer's identity proof does not hold up
+    Identity(CertificateError),
+    /// The peer sent something out of place
+    Unexpected(&'static str),
+    /// The peer hung up mid-transfer
+    Closed,
+    Cancelled(CancelReason),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io(e) => write!(f, "i/o error: {e}"),
+            EngineError::Codec(e) => write!(f, "{e}"),
+            EngineError::Proto(e) => write!(f, "{e}"),
+            EngineError::Transfer(e) => write!(f, "{e}"),
+            EngineError::Identity(e) => write!(f, "peer did not prove its fingerprint: {e}"),
+            EngineError::Unexpected(what) => write!(f, "unexpected message: {what}"),
+            EngineError::Closed => write!(f, "peer closed the connection"),
+            EngineError::Cancelled(reason) => write!(f, "cancelled: {reason:?}"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        EngineError::Io(e)
+    }
+}
+
+impl From<CodecError> for EngineError {
+    fn from(e: CodecError) -> Self {
+        EngineError::Codec(e)
+    }
+}
+
+impl From<ProtoError> for EngineError {
+    fn from(e: ProtoError) -> Self {
+        EngineError::Proto(e)
+    }
+}
+
+impl From<TransferError> for EngineError {
+    fn from(e: TransferError) -> Self {
+        EngineError::Transfer(e)
+    }
+}
+
+impl From<CertificateError> for EngineError {
+    fn from(e: CertificateError) -> Self {
+        EngineError::Identity(e)
+    }
+}
+
+pub(crate) async fn next(control: &mut ControlChannel) -> Result<Message, EngineError> {
+    match control.next().await {
+        Some(message) => Ok(message?),
+        None => Err(EngineError::Closed),
+    }
+}
+
+/// Swap hellos, and capabilities if the version has them; returns the
+/// negotiated version, the peer's hello and the capabilities both have
+///
+/// `bound` is the version the handshake settled on, if the peer offered one;
+/// the hellos have to come to the same.
+async fn hello(control: &mut ControlChannel, ours: Hello, offered: Capabilities, bound: Option<u16>) -> Result<(u16, Hello, Capabilities), EngineError> {
+    control.codec_mut().set_version(MIN_SUPPORTED_VERSION);
+    control.send(ours.into()).await?;
+    let Message::Hello(theirs) = next(control).await? else {
+        return Err(EngineError::Unexpected("expected a hello"));
+    };
+    let version = negotiate(VersionRange::CURRENT, theirs.versions)?;
+    if bound.is_some_and(|bound| bound != version) {
+        return Err(EngineError::Unexpected("hello disagrees with the handshake on the version"));
+    }
+    control.codec_mut().set_version(version);
+    let mut capabilities = None;
+    if version >= CAPABILITIES_VERSION {
+        control.send(HelloCapabilities { capabilities: offered }.into()).await?;
+        let Message::HelloCapabilities(hello) = next(control).await? else {
+            return Err(EngineError::Unexpected("expected capabilities"));
+        };
+        capabilities = Some(hello.capabilities);
+    }
+    Ok((version, theirs, negotiate_capabilities(offered, capabilities, version)))
+}
+
+/// Send our [`IdentityProof`] for the session `session`, and check the
+/// peer's against it and the fingerprint in `theirs`
+async fn prove(control: &mut ControlChannel, identity: &DeviceIdentity, session: &Peer, theirs: &Hello) -> Result<Fingerprint, EngineError> {
+    let signature = identity.prove_session(&session.handshake_hash);
+    control.send(IdentityProof { identity_key: identity.verifying_key().to_bytes(), signature: signature.to_bytes().to_vec() }.into()).await?;
+    let Message::IdentityProof(proof) = next(control).await? else {
+        return Err(EngineError::Unexpected("expected an identity proof"));
+    };
+    let key = VerifyingKey::from_bytes(&proof.identity_key).map_err(|_| CertificateError::WrongIdentity)?;
+    let signature = identity::Signature::from_slice(&proof.signature).map_err(|_| CertificateError::BadSignature)?;
+    let fingerprint = certificate::verify_session_proof(&key, &session.static_key, &session.handshake_hash, &signature)?;
+    if fingerprint != Fingerprint::from_bytes(theirs.fingerprint) {
+        return Err(CertificateError::WrongIdentity.into());
+    }
+    Ok(fingerprint)
+}
+
+/// What the hellos settled
+pub(crate) struct Greeted {
+    pub(crate) version: u16,
+    pub(crate) peer: Hello,
+    pub(crate) capabilities: Capabilities,
+    /// The peer's fingerprint, once it proved it; `None` before [`IDENTITY_VERSION`]
+    pub(crate) proven: Option<Fingerprint>,
+}
+
+/// [`hello`] and, from [`IDENTITY_VERSION`], [`prove`] on a fresh
+/// connection, recording the peer on the current span
+pub(crate) async fn greet(shared: &Shared, conn: &mut Connection) -> Result<Greeted, EngineError> {
+    let offered = if shared.config.compress { CAPABILITIES } else { Capabilities::from_bits(CAPABILITIES.bits() & !Capabilities::COMPRESSION.bits()) };
+    let (version, peer, capabilities) = hello(&mut conn.control, shared.hello(), offered, conn.peer.version).await?;
+    tracing::Span::current().record("peer", peer.device_name.as_str());
+    let proven = if version >= IDENTITY_VERSION { Some(prove(&mut conn.control, &shared.identity, &conn.peer, &peer).await?) } else { None };
+    Ok(Greeted { version, peer, capabilities, proven })
+}
+
+/// Connect `conn` to the daemon's entry for `transfer` and send `paths`;
+/// the state the transfer ended in, if it got that far
+#[tracing::instrument(name = "transfer", skip_all, fields(id = %crate::rpc::transfer_id_hex(&transfer), direction = "send", peer = tracing::field::Empty))]
+pub(crate) async fn send(shared: Arc<Shared>, mut conn: Connection, transfer: TransferId, paths: Vec<PathBuf>, files: Vec<OfferedFile>) -> Option<TransferState> {
+    let started_at = now();
+    match greet(&shared, &mut conn).await {
+        Ok(greeted) => offer(&shared, &mut conn, &greeted, started_at, transfer, paths, files).await,
+        Err(e) => {
+            tracing::info!(error = %e, "no hello from the peer");
+            shared.update(&transfer, |entry| entry.error = Some(e.to_string()));
+            None
+        }
+    }
+}
+
+/// Offer and send `paths` once the hellos are done; the state the transfer
+/// ended in, or `None` if its entry went away
+pub(crate) async fn offer(shared: &Shared, conn: &mut Connection, greeted: &Greeted, started_at: u64, transfer: TransferId, paths: Vec<PathBuf>, files: Vec<OfferedFile>) -> Option<TransferState> {
+    let config = TransferConfig::default().for_peer(greeted.version);
+    let (session, offer) = TransferSession::outgoing(transfer, files, config);
+    let metadata = match &offer {
+        Message::TransferOffer(offered) if greeted.version >= METADATA_VERSION => Some(seal_previews(conn.control.codec().keys(), offered, &paths).await),
+        _ => None,
+    };
+    let (cancel, events) = attach(shared, &transfer, &session, greeted)?;
+    let mut run = Run::new(conn, session, events, cancel, greeted);
+    let result = match run.send_offer(offer, metadata).await {
+        Ok(()) => run.send_files(&paths).await,
+        Err(e) => Err(e),
+    };
+    run.finish(result).await;
+    let paths: Vec<Option<PathBuf>> = paths.into_iter().map(Some).collect();
+    record(shared, &run.session, greeted, started_at, &paths);
+    ended(shared, &run.session, &paths);
+    Some(run.session.state())
+}
+
+/// Take an incoming connection: an offer, shown until the user answers it,
+/// or the start of a sync run
+#[tracing::instrument(name = "transfer", skip_all, fields(id = tracing::field::Empty, direction = "receive", peer = tracing::field::Empty))]
+pub(crate) async fn receive(shared: Arc<Shared>, mut conn: Connection) {
+    let started_at = now();
+    let Ok(greeted) = greet(&shared, &mut conn).await else { return };
+    match next(&mut conn.control).await {
+        Ok(Message::TransferOffer(offer)) => {
+            let Some((session, layout)) = take(&shared, &mut conn, &greeted, offer, None).await else { return };
+            let paths = received_paths(&session, layout.as_ref());
+            record(&shared, &session, &greeted, started_at, &paths);
+            ended(&shared, &session, &paths);
+        }
+        Ok(Message::SyncIndex(index)) if greeted.version >= SYNC_VERSION => crate::sync::respond(shared, conn, &greeted, index, started_at).await,
+        _ => {}
+    }
+}
+
+/// Run an incoming `offer` to the end: wait for the user's answer, or take
+/// all of it into `into` without asking. Returns the ended session and
+/// where its files went, if they went anywhere; what did not finish is
+/// gone, unless a checkpoint keeps it for the same offer made again.
+pub(crate) async fn take(shared: &Shared, conn: &mut Connection, greeted: &Greeted, offer: TransferOffer, into: Option<PathBuf>) -> Option<(TransferSession, Option<Layout>)> {
+    tracing::Span::current().record("id", crate::rpc::transfer_id_hex(&offer.transfer));
+    tracing::info!(files = offer.files.len(), bytes = offer.files.iter().map(|f| f.size).sum::<u64>(), "offer received");
+    let previews = if greeted.version >= METADATA_VERSION {
+        let Ok(Message::Metadata(metadata)) = next(&mut conn.control).await else { return None };
+        open_previews(&conn.control, &metadata, &offer)
+    } else {
+        Vec::new()
+    };
+    // sync runs stage their files and start over each time
+    let mut resumable = match (&shared.checkpoints, greeted.proven, &into) {
+        (Some(store), Some(peer), None) => Some(Resumable::load(store, &offer, peer)),
+        _ => None,
+    };
+    let (tx, answer) = oneshot::channel();
+    let mut entry = Entry::new(Direction::Receive, offer.files.clone());
+    entry.previews = previews;
+    let asking = match into {
+        Some(dir) => {
+            let _ = tx.send(Answer::Accept(dir, None));
+            false
+        }
+        // the user took this very offer before
+        None if resumable.as_ref().is_some_and(Resumable::resuming) => {
+            let _ = tx.send(Answer::Resume);
+            false
+        }
+        None => {
+            entry.answer = Some(tx);
+            entry.selective = greeted.version >= ACCEPT_FILES_VERSION;
+            true
+        }
+    };
+    {
+        let mut transfers = shared.transfers.lock().expect("transfers lock");
+        // a second offer under a live id would hijack the first; a finished
+        // one may come again from the same device, to resume
+        if transfers.get(&offer.transfer).is_some_and(|known| !known.finished() || !known.verified || known.peer != greeted.proven) {
+            return None;
+        }
+        transfers.insert(offer.transfer, entry);
+    }
+    let session = TransferSession::incoming(&offer);
+    let (cancel, events) = attach(shared, &offer.transfer, &session, greeted)?;
+    if asking {
+        notify(shared, &offer.transfer, HookEvent::OfferReceived, &[]);
+    }
+    let mut run = Run::new(conn, session, events, cancel, greeted);
+    let mut layout = None;
+    // the sender's compression offer comes right behind its offer; an answer
+    // ready at once must not overtake it
+    let result = if asking || !greeted.capabilities.contains(Capabilities::COMPRESSION) { Ok(()) } else { run.step().await };
+    let result = match result {
+        Ok(()) => run.receive_files(&offer, answer, &mut layout, resumable.as_mut()).await,
+        Err(e) => Err(e),
+    };
+    run.finish(result).await;
+    if let Some(layout) = &layout {
+        match resumable {
+            Some(resumable) => resumable.ended(&run.session, layout, &shared.config.keep_partial),
+            // whatever did not finish cannot be resumed without a checkpoint
+            None => {
+                let _ = shared.config.keep_partial.clean_up(&run.session, |index| layout.path(index));
+            }
+        }
+    }
+    Some((run.session, layout))
+}
+
+/// A receive that keeps a checkpoint, so the same offer made again can pick up where it stopped
+struct Resumable<'s> {
+    store: &'s CheckpointStore,
+    checkpoint: Checkpoint,
+}
+
+impl<'s> Resumable<'s> {
+    /// The checkpoint for `offer` from `peer` if there is one, else a fresh one
+    fn load(store: &'s CheckpointStore, offer: &TransferOffer, peer: Fingerprint) -> Self {
+        let peer = *peer.as_bytes();
+        let checkpoint = match store.load(&offer.transfer) {
+            Ok(Some(checkpoint)) if checkpoint.matches(offer, &peer) => checkpoint,
+            _ => Checkpoint::new(offer, peer),
+        };
+        Self { store, checkpoint }
+    }
+
+    /// Whether an earlier attempt got as far as writing files
+    fn resuming(&self) -> bool {
+        self.checkpoint.layout.is_some()
+    }
+
+    fn save(&self) {
+        // losing a checkpoint only costs a resume
+        if let Err(e) = self.store.save(&self.checkpoint) {
+            tracing::info!(error = %e, "checkpoint not saved");
+        }
+    }
+
+    /// Keep the files and checkpoint of a transfer the link cut off; leave
+    /// the files of any other to `keep`
+    fn ended(self, session: &TransferSession, layout: &Layout, keep: &KeepPartial) {
+        if self.resuming() && matches!(session.state(), TransferState::Failed(Failure::Local | Failure::Peer | Failure::Timeout)) {
+            return self.save();
+        }
+        let _ = self.store.remove(&session.id());
+        let _ = keep.clean_up(session, |index| layout.path(index));
+    }
+}
+
+/// Check what `checkpoint` says is on disk under `layout` still is; the
+/// file and offset to accept from, with that file open at the offset and
+/// hashed up to it. `None` if the offer has no files.
+fn reopen(checkpoint: &mut Checkpoint, layout: &Layout) -> io::Result<Option<(u32, u64, std::fs::File, blake3::Hasher)>> {
+    for (index, file) in (0u32..).zip(&mut checkpoint.files) {
+        if file.done && hash_path(layout.path(index)).ok() != file.hash {
+            file.done = false;
+        }
+    }
+    if checkpoint.resume_point().is_none() {
+        // every file is there, but the end never got settled: take the last again
+        let Some(last) = checkpoint.files.last_mut() else { return Ok(None) };
+        last.done = false;
+    }
+    let Some((index, _)) = checkpoint.resume_point() else { return Ok(None) };
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(layout.path(index))?;
+    let offset = checkpoint.verify_partial(index, io::BufReader::new(&file))?;
+    file.set_len(offset)?;
+    file.rewind()?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader((&file).take(offset))?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(Some((index, offset, file, hasher)))
+}
+
+/// Where each file of a received session is, or `None` if it did not arrive
+pub(crate) fn received_paths(session: &TransferSession, layout: Option<&Layout>) -> Vec<Option<PathBuf>> {
+    (0u32..).zip(session.files()).map(|(index, file)| layout.filter(|_| file.status == FileStatus::Done).map(|layout| layout.path(index).to_owned())).collect()
+}
+
+/// Previews of `offer` sealed for the peer, made off the runtime; an empty
+/// envelope if there are none or they do not seal
+async fn seal_previews(keys: &SessionKeys, offer: &TransferOffer, paths: &[PathBuf]) -> Metadata {
+    let (offered, files) = (offer.clone(), paths.to_vec());
+    let described = tokio::task::spawn_blocking(move || preview::describe(&offered, &files)).await.unwrap_or_default();
+    let mut envelope = Vec::new();
+    if described.files.iter().any(|f| f.thumbnail.is_some() || f.text.is_some()) {
+        match MetaSealer::new(keys).map_err(MetaError::from).and_then(|mut sealer| sealer.seal(&described)) {
+            Ok(sealed) => envelope = sealed.to_bytes(),
+            Err(e) => tracing::info!(error = %e, "offer goes without previews"),
+        }
+    }
+    Metadata { transfer: offer.transfer, envelope }
+}
+
+/// The previews in `metadata` that pass their checks; none if it does not
+/// open or does not describe `offer`
+fn open_previews(control: &ControlChannel, metadata: &Metadata, offer: &TransferOffer) -> Vec<Preview> {
+    if metadata.transfer != offer.transfer || metadata.envelope.is_empty() {
+        return Vec::new();
+    }
+    let opened = MetaEnvelope::from_bytes(&metadata.envelope).and_then(|envelope| MetaOpener::new(control.codec().keys())?.open(&envelope));
+    match opened.map(|metadata| preview::check(metadata, offer)) {
+        Ok(Ok(previews)) => previews,
+        Ok(Err(e)) => {
+            tracing::info!(error = %e, "previews dropped");
+            Vec::new()
+        }
+        Err(e) => {
+            tracing::info!(error = %e, "previews dropped");
+            Vec::new()
+        }
+    }
+}
+
+/// Give the entry its peer and progress; `None` if it is gone
+fn attach(shared: &Shared, transfer: &TransferId, session: &TransferSession, greeted: &Greeted) -> Option<(CancelToken, TransferEvents)> {
+    let events = TransferEvents::new(session);
+    let progress = events.watch();
+    let cancel = shared.update(transfer, |entry| {
+        entry.peer = Some(Fingerprint::from_bytes(greeted.peer.fingerprint));
+        entry.verified = greeted.proven.is_some();
+        entry.peer_name = greeted.peer.device_name.clone();
+        entry.progress = Some(progress);
+        entry.cancel.clone()
+    })?;
+    Some((cancel, events))
+}
+
+/// `paths` holds where each file is, or `None` if it is nowhere useful
+pub(crate) fn record(shared: &Shared, session: &TransferSession, greeted: &Greeted, started_at: u64, paths: &[Option<PathBuf>]) {
+    let Some(history) = &shared.history else { return };
+    let Some(mut record) = TransferRecord::from_session(session, Fingerprint::from_bytes(greeted.peer.fingerprint), &greeted.peer.device_name, started_at, now()) else { return };
+    record.verified = greeted.proven.is_some();
+    for (file, path) in record.files.iter_mut().zip(paths) {
+        file.path = path.clone();
+    }
+    // history is a convenience; a failed write must not fail the transfer
+    let _ = history.lock().expect("history lock").record(&record);
+}
+
+/// Run the hooks for how the session ended, if any want it
+pub(crate) fn ended(shared: &Shared, session: &TransferSession, paths: &[Option<PathBuf>]) {
+    let event = match session.state() {
+        TransferState::Done => HookEvent::TransferComplete,
+        TransferState::Failed(Failure::HashMismatch(_)) => HookEvent::VerificationFailed,
+        _ => return,
+    };
+    notify(shared, &session.id(), event, paths);
+}
+
+fn notify(shared: &Shared, transfer: &TransferId, event: HookEvent, paths: &[Option<PathBuf>]) {
+    if shared.config.hooks.is_empty() {
+        return;
+    }
+    let Some(info) = shared.update(transfer, |entry| entry.info(transfer)) else { return };
+    hooks::fire(&shared.config.hooks, event, hooks::payload(event, &info, paths));
+}
+
+/// One session on its connection
+struct Run<'c> {
+    control: &'c mut ControlChannel,
+    /// Set from [`FILE_STREAM_VERSION`] over QUIC
+    streams: Option<Streams>,
+    session: TransferSession,
+    events: TransferEvents,
+    cancel: CancelToken,
+    version: u16,
+    capabilities: Capabilities,
+    /// Whether the last message on the channel was ours
+    sent_last: bool,
+    _active: Active,
+}
+
+/// Counts a transfer in `globalsend_transfers_active` until dropped
+struct Active;
+
+impl Active {
+    fn new() -> Self {
+        metrics::gauge!("globalsend_transfers_active").increment(1.0);
+        Self
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        metrics::gauge!("globalsend_transfers_active").decrement(1.0);
+    }
+}
+
+impl<'c> Run<'c> {
+    fn new(conn: &'c mut Connection, session: TransferSession, events: TransferEvents, cancel: CancelToken, greeted: &Greeted) -> Self {
+        let streams = conn.quic.clone().filter(|_| greeted.version >= FILE_STREAM_VERSION).map(|quic| Streams::new(quic, conn.limiters.clone()));
+        Self { control: &mut conn.control, streams, session, events, cancel, version: greeted.version, capabilities: greeted.capabilities, sent_last: false, _active: Active::new() }
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), EngineError> {
+        self.control.send(message).await?;
+        self.sent_last = true;
+        self.events.publish(&mut self.session);
+        Ok(())
+    }
+
+    /// The offer, then right behind it its previews if the peer takes them
+    async fn send_offer(&mut self, offer: Message, metadata: Option<Metadata>) -> Result<(), EngineError> {
+        self.send(offer).await?;
+        match metadata {
+            Some(metadata) => self.send(metadata.into()).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Next message from the peer, unless the transfer is cancelled first
+    async fn recv(&mut self) -> Result<Message, EngineError> {
+        let message = tokio::select! {
+            reason = self.cancel.cancelled() => return Err(EngineError::Cancelled(reason)),
+            message = next(self.control) => message?,
+        };
+        self.sent_last = false;
+        Ok(message)
+    }
+
+    /// Next message from the peer on the control channel or a file stream,
+    /// unless the transfer is cancelled first
+    async fn recv_any(&mut self) -> Result<Message, EngineError> {
+        let Some(streams) = self.streams.as_mut() else { return self.recv().await };
+        loop {
+            tokio::select! {
+                reason = self.cancel.cancelled() => return Err(EngineError::Cancelled(reason)),
+                message = next(self.control) => {
+                    self.sent_last = false;
+                    return message;
+                }
+                Some((index, stream)) = streams.accepted.recv() => {
+                    if index as usize >= self.session.files().len() {
+                        return Err(EngineError::Unexpected("stream for a file not offered"));
+                    }
+                    streams.waiting.insert(index, stream);
+                    streams.admit(&self.session, self.control.codec().keys(), self.version)?;
+                }
+                Some((index, message)) = streams.arrived.recv() => {
+                    let message = message?;
+                    let carries = match &message {
+                        Message::ChunkData(chunk) => chunk.index,
+                        Message::CompressedChunk(chunk) => chunk.index,
+                        Message::DeltaChunk(chunk) => chunk.index,
+                        _ => return Err(EngineError::Unexpected("control message on a file stream")),
+                    };
+                    if carries != index {
+                        return Err(EngineError::Unexpected("chunk on another file's stream"));
+                    }
+                    self.sent_last = false;
+                    return Ok(message);
+                }
+            }
+        }
+    }
+
+    /// Feed the session one message that cannot carry file bytes for us
+    async fn step(&mut self) -> Result<(), EngineError> {
+        let message = self.recv().await?;
+        self.session.on_message(&message)?;
+        self.events.publish(&mut self.session);
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.session.state().is_terminal()
+    }
+
+    async fn send_files(&mut self, paths: &[PathBuf]) -> Result<(), EngineError> {
+        if self.capabilities.contains(Capabilities::COMPRESSION) {
+            let message = self.session.offer_compression()?;
+            self.send(message).await?;
+        }
+        while self.session.state() == TransferState::Offered {
+            self.step().await?;
+        }
+        let (feed, mut pieces) = mpsc::channel(READ_AHEAD);
+        // readers stop with the transfer
+        let mut reading = JoinSet::new();
+        loop {
+            while !self.done() && self.session.next_file().is_some() && self.session.open_files().count() < usize::from(self.session.config().streams) {
+                self.start_file(paths, &feed, &mut reading).await?;
+            }
+            if self.done() {
+                return Ok(());
+            }
+            let sending = self.session.files().iter().any(|f| f.status == FileStatus::Open);
+            if !sending && self.session.next_file().is_none() {
+                break;
+            }
+            tokio::select! {
+                step = self.step() => step?,
+                Some((index, piece)) = pieces.recv(), if sending && self.session.window() > 0 => self.send_piece(index, piece?).await?,
+            }
+        }
+        while !self.done() {
+            self.step().await?;
+        }
+        Ok(())
+    }
+
+    /// Open the next file and start reading it into `feed`, on a stream of its own if there are streams
+    async fn start_file(&mut self, paths: &[PathBuf], feed: &mpsc::Sender<(u32, io::Result<Piece>)>, reading: &mut JoinSet<()>) -> Result<(), EngineError> {
+        let path = paths[self.session.next_file().expect("a file left") as usize].clone();
+        let hash = self.cancel.run(hash_file(path.clone(), self.cancel.clone())).await.map_err(EngineError::Cancelled)??;
+        let (index, header) = self.session.start_file(hash)?;
+        self.send(header).await?;
+        let file = &self.session.files()[index as usize];
+        // empty, or all there already
+        if file.status != FileStatus::Open {
+            return Ok(());
+        }
+        let (offset, size, chunk_size) = (file.bytes, file.size, file.chunk_size);
+        match self.session.base(index) {
+            Some(base) if self.capabilities.contains(Capabilities::DELTA) && offset == 0 => {
+                let (feed, cancel) = (feed.clone(), self.cancel.clone());
+                reading.spawn_blocking(move || diff(index, &path, &base, chunk_size as usize, &feed, &cancel));
+            }
+            _ => {
+                reading.spawn(read_chunks(index, path, offset, size, u64::from(chunk_size), feed.clone(), self.cancel.clone()));
+            }
+        }
+        if let Some(streams) = &mut self.streams {
+            let stream = streams.quic.open_file(index).await.map_err(|_| EngineError::Closed)?;
+            let channel = file_channel(stream, index, self.control.codec().keys(), self.version, &streams.limiters).map_err(CodecError::Crypto)?;
+            streams.sending.insert(index, channel);
+        }
+        Ok(())
+    }
+
+    /// Send what a reader made of file `index`, on the file's stream if it has one
+    async fn send_piece(&mut self, index: u32, piece: Piece) -> Result<(), EngineError> {
+        let (message, len) = match piece {
+            Piece::Chunk(data) => {
+                let len = data.len() as u64;
+                (self.session.chunk(index, data)?, len)
+            }
+            Piece::Delta(ops) => {
+                let literal: usize = ops.iter().map(|op| if let DeltaOp::Literal(data) = op { data.len() } else { 0 }).sum();
+                (self.session.delta(index, ops)?, literal as u64)
+            }
+        };
+        match self.streams.as_mut().and_then(|streams| streams.sending.get_mut(&index)) {
+            Some(stream) => {
+                if stream.send(message).await.is_err() {
+                    // the receiver stopped reading, and says why on the control channel
+                    while !self.done() {
+                        self.step().await?;
+                    }
+                    return Ok(());
+                }
+                self.events.publish(&mut self.session);
+            }
+            None => self.send(message).await?,
+        }
+        metrics::counter!("globalsend_bytes_sent_total").increment(len);
+        if self.session.files()[index as usize].status != FileStatus::Open {
+            // the receiver reads a file's stream to its end
+            if let Some(mut stream) = self.streams.as_mut().and_then(|streams| streams.sending.remove(&index)) {
+                let _ = stream.close().await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn receive_files(&mut self, offer: &TransferOffer, mut answer: oneshot::Receiver<Answer>, layout: &mut Option<Layout>, mut resumable: Option<&mut Resumable<'_>>) -> Result<(), EngineError> {
+        let answer = loop {
+            tokio::select! {
+                // the entry went away: nobody can answer any more
+                answer = &mut answer => break answer.unwrap_or(Answer::Decline),
+                step = self.step() => step?,
+            }
+            if self.done() {
+                return Ok(());
+            }
+        };
+        let mut writing: BTreeMap<u32, (File, blake3::Hasher)> = BTreeMap::new();
+        let (dir, wanted) = match (answer, resumable.as_deref_mut()) {
+            (Answer::Accept(dir, wanted), _) => (dir, wanted),
+            (Answer::Resume, Some(resumable)) => {
+                let planned = layout.insert(resumable.checkpoint.layout.clone().expect("resuming"));
+                let (mut checkpoint, paths) = (resumable.checkpoint.clone(), planned.clone());
+                let reopened = tokio::task::spawn_blocking(move || reopen(&mut checkpoint, &paths).map(|reopened| (checkpoint, reopened))).await.map_err(io::Error::other)??;
+                resumable.checkpoint = reopened.0;
+                planned.create_dirs()?;
+                if let Some(message) = self.session.accept_compression()? {
+                    self.send(message).await?;
+                }
+                let message = match reopened.1 {
+                    Some((index, offset, file, hasher)) => {
+                        tracing::info!(index, offset, "resuming");
+                        writing.insert(index, (File::from_std(file), hasher));
+                        self.session.accept_from(index, offset)?
+                    }
+                    None => self.session.accept()?,
+                };
+                self.send(message).await?;
+                return self.write_files(planned, writing, Some(resumable)).await;
+            }
+            (Answer::Resume | Answer::Decline, _) => {
+                let message = self.session.decline()?;
+                return self.send(message).await;
+            }
+        };
+        let checked = match &wanted {
+            Some(wanted) => preflight::check_selected(&dir, offer, wanted, OnCollision::Rename),
+            None => preflight::check(&dir, offer, OnCollision::Rename),
+        };
+        let planned = match checked {
+            Ok(planned) => layout.insert(planned),
+            Err(refusals) => {
+                let message = if self.version >= REFUSAL_VERSION { self.session.refuse(refusals)? } else { self.session.decline()? };
+                return self.send(message).await;
+            }
+        };
+        planned.create_dirs()?;
+        if let Some(message) = self.session.accept_compression()? {
+            self.send(message).await?;
+        }
+        let message = match &wanted {
+            Some(wanted) => self.session.accept_files(wanted)?,
+            None => self.session.accept()?,
+        };
+        self.send(message).await?;
+        // a selection is accepted with a list, which cannot say where to resume
+        let mut resumable = resumable.filter(|_| wanted.is_none());
+        if let Some(resumable) = resumable.as_deref_mut() {
+            resumable.checkpoint.layout = Some(planned.clone());
+        }
+        self.write_files(planned, writing, resumable).await
+    }
+
+    /// Write what arrives into `planned` until the session ends, recording
+    /// it in the checkpoint if there is one; `writing` has the files already open
+    async fn write_files(&mut self, planned: &Layout, mut writing: BTreeMap<u32, (File, blake3::Hasher)>, mut resumable: Option<&mut Resumable<'_>>) -> Result<(), EngineError> {
+        if let Some(streams) = &mut self.streams {
+            streams.listen();
+        }
+        let mut checked = BTreeSet::new();
+        while !self.done() {
+            let message = self.recv_any().await?;
+            let received = self.session.on_message(&message)?;
+            if let Message::FileHeader(header) = &message {
+                if let Some(resumable) = resumable.as_deref_mut() {
+                    resumable.checkpoint.record_header(header.index, header.hash);
+                }
+                if let Some(streams) = &mut self.streams {
+                    streams.admit(&self.session, self.control.codec().keys(), self.version)?;
+                }
+            }
+            if let Some(chunk) = received {
+                let (file, hasher) = match writing.entry(chunk.index) {
+                    std::collections::btree_map::Entry::Occupied(open) => open.into_mut(),
+                    std::collections::btree_map::Entry::Vacant(slot) => slot.insert((File::create(planned.path(chunk.index)).await?, blake3::Hasher::new())),
+                };
+                file.write_all(&chunk.data).await?;
+                hasher.update(&chunk.data);
+                if let Some(resumable) = resumable.as_deref_mut() {
+                    resumable.checkpoint.record_chunk(chunk.index, chunk.offset, &chunk.data);
+                }
+                metrics::counter!("globalsend_bytes_received_total").increment(chunk.data.len() as u64);
+                let index = chunk.index;
+                let ack = if self.version >= HASH_ACK_VERSION {
+                    self.session.written_with_hash(index, || *hasher.finalize().as_bytes())?
+                } else if self.version >= CHUNK_ACK_VERSION {
+                    self.session.written(index)?
+                } else {
+                    None
+                };
+                if let Some(ack) = ack {
+                    // the sender checks everything up to a hash ack against its own
+                    if let (Message::HashAck(_), Some(resumable)) = (&ack, resumable.as_deref()) {
+                        file.flush().await?;
+                        resumable.save();
+                    }
+                    self.send(ack).await?;
+                }
+            }
+            let complete: Vec<u32> = (0u32..).zip(self.session.files()).filter(|(index, f)| f.status == FileStatus::Verifying && !checked.contains(index)).map(|(index, _)| index).collect();
+            for index in complete {
+                checked.insert(index);
+                let hash = match writing.remove(&index) {
+                    Some((mut file, hasher)) => {
+                        file.flush().await?;
+                        hasher.finalize()
+                    }
+                    // empty files get no chunks
+                    None => {
+                        File::create(planned.path(index)).await?;
+                        blake3::Hasher::new().finalize()
+                    }
+                };
+                let reply = self.session.verified(index, *hash.as_bytes())?;
+                if let Some(resumable) = resumable.as_deref_mut().filter(|_| self.session.files()[index as usize].status == FileStatus::Done) {
+                    resumable.checkpoint.record_done(index);
+                    resumable.save();
+                }
+                self.send(reply).await?;
+            }
+            self.events.publish(&mut self.session);
+        }
+        Ok(())
+    }
+
+    /// Settle the session after `result` and tell the peer if it does not know
+    async fn finish(&mut self, result: Result<(), EngineError>) {
+        let message = match result {
+            Ok(()) => None,
+            Err(_) if self.done() => Some(self.session.abort_message()),
+            Err(EngineError::Cancelled(reason)) => self.session.cancel(reason).ok(),
+            // the link is gone; the same offer made again may still get through
+            Err(EngineError::Closed | EngineError::Codec(CodecError::Io(_))) => self.session.cancel(CancelReason::Timeout).ok(),
+            Err(_) => self.session.cancel(CancelReason::Failed).ok(),
+        };
+        if let Some(message) = message {
+            let _ = self.send(message).await;
+        }
+        self.events.publish(&mut self.session);
+        let state = self.session.state();
+        let outcome = match state {
+            TransferState::Done => "done",
+            TransferState::Cancelled(CancelReason::Declined) => "declined",
+            TransferState::Cancelled(_) => "cancelled",
+            _ => "failed",
+        };
+        let direction = match self.session.direction() {
+            Direction::Send => "send",
+            Direction::Receive => "receive",
+        };
+        tracing::info!(%state, "transfer ended");
+        metrics::counter!("globalsend_transfers_total", "direction" => direction, "outcome" => outcome).increment(1);
+        if self.sent_last {
+            let _ = tokio::time::timeout(LINGER, self.control.next()).await;
+        }
+    }
+}
+
+/// A QUIC connection's per-file streams
+struct Streams {
+    quic: QuicConnection,
+    /// The connection's, for every stream
+    limiters: Vec<RateLimiter>,
+    /// Sender: the stream of each file still sending
+    sending: BTreeMap<u32, ControlChannel>,
+    /// Receiver: streams the peer opened, from [`listen`](Self::listen) on
+    accepted: mpsc::Receiver<(u32, QuicStream)>,
+    accept: Option<mpsc::Sender<(u32, QuicStream)>>,
+    /// Receiver: streams whose file's header has not come yet
+    waiting: BTreeMap<u32, QuicStream>,
+    /// Receiver: what came in on the streams being read
+    arrived: mpsc::Receiver<(u32, Result<Message, CodecError>)>,
+    arrive: mpsc::Sender<(u32, Result<Message, CodecError>)>,
+    /// Receiver: taking and reading streams, until the run ends
+    tasks: JoinSet<()>,
+}
+
+impl Streams {
+    fn new(quic: QuicConnection, limiters: Vec<RateLimiter>) -> Self {
+        let (accept, accepted) = mpsc::channel(MAX_OPEN_FILES);
+        let (arrive, arrived) = mpsc::channel(READ_AHEAD);
+        Self { quic, limiters, sending: BTreeMap::new(), accepted, accept: Some(accept), waiting: BTreeMap::new(), arrived, arrive, tasks: JoinSet::new() }
+    }
+
+    /// Receiver: take the file streams the peer opens
+    fn listen(&mut self) {
+        let (Some(accept), quic) = (self.accept.take(), self.quic.clone()) else { return };
+        self.tasks.spawn(async move {
+            while let Ok(stream) = quic.accept_file().await {
+                if accept.send(stream).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Receiver: start reading the waiting streams whose file is open
+    fn admit(&mut self, session: &TransferSession, keys: &SessionKeys, version: u16) -> Result<(), EngineError> {
+        let open: Vec<u32> = self.waiting.keys().copied().filter(|&index| session.files()[index as usize].status == FileStatus::Open).collect();
+        for index in open {
+            let stream = self.waiting.remove(&index).expect("waiting stream");
+            let mut channel = file_channel(stream, index, keys, version, &self.limiters).map_err(CodecError::Crypto)?;
+            let arrive = self.arrive.clone();
+            self.tasks.spawn(async move {
+                while let Some(message) = channel.next().await {
+                    let failed = message.is_err();
+                    if arrive.send((index, message)).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// What a file's reader hands the sending loop
+enum Piece {
+    Chunk(Vec<u8>),
+    Delta(Vec<DeltaOp>),
+}
+
+/// File `index` of `size` bytes in chunks from `offset`, into `feed`, until `cancel`
+async fn read_chunks(index: u32, path: PathBuf, offset: u64, size: u64, chunk_size: u64, feed: mpsc::Sender<(u32, io::Result<Piece>)>, cancel: CancelToken) {
+    let read = async {
+        let mut file = File::open(&path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut read = offset;
+        while read < size {
+            let mut chunk = vec![0; chunk_size.min(size - read) as usize];
+            file.read_exact(&mut chunk).await.map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::other(format!("{} shrank while sending", path.display())),
+                _ => e,
+            })?;
+            read += chunk.len() as u64;
+            if feed.send((index, Ok(Piece::Chunk(chunk)))).await.is_err() {
+                break;
+            }
+        }
+        Ok::<_, io::Error>(())
+    };
+    if let Ok(Err(e)) = cancel.run(read).await {
+        let _ = feed.send((index, Err(e))).await;
+    }
+}
+
+/// File `index` as changes against the receiver's older copy, into `feed`
+/// until `cancel`; blocks
+fn diff(index: u32, path: &Path, base: &Signature, max_literal: usize, feed: &mpsc::Sender<(u32, io::Result<Piece>)>, cancel: &CancelToken) {
+    let diffed = std::fs::File::open(path).and_then(|file| {
+        delta::diff(base, io::BufReader::new(Cancellable { inner: file, cancel }), max_literal, |ops| feed.blocking_send((index, Ok(Piece::Delta(ops)))).map_err(|_| io::Error::other("transfer ended")))
+    });
+    if let Err(e) = diffed {
+        let _ = feed.blocking_send((index, Err(e)));
+    }
+}
+
+/// BLAKE3 of the file at `path`; a blocking read, which stops at the next block once `cancel` is
+async fn hash_file(path: PathBuf, cancel: CancelToken) -> io::Result<[u8; 32]> {
+    tokio::task::spawn_blocking(move || hash_reader(Cancellable { inner: std::fs::File::open(&path)?, cancel: &cancel })).await.map_err(io::Error::other)?
+}
+
+fn hash_path(path: &Path) -> io::Result<[u8; 32]> {
+    hash_reader(std::fs::File::open(path)?)
+}
+
+fn hash_reader(reader: impl Read) -> io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(reader)?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// A blocking reader that fails once its transfer is cancelled, so the
+/// thread reading it does not go on to the end of the file
+struct Cancellable<'a, R> {
+    inner: R,
+    cancel: &'a CancelToken,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.cancel.reason() {
+            Some(reason) => Err(io::Error::other(format!("cancelled: {reason:?}"))),
+            None => self.inner.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{by_file_name, Daemon, DaemonConfig};
+
+    async fn daemon(name: &str, dir: &Path) -> Daemon {
+        let mut config = DaemonConfig::new(name, dir.join(name));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.socket = dir.join(format!("{name}.sock"));
+        config.discovery = false;
+        Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn no_compress_leaves_compression_out_of_the_hello() {
+        let dir = std::env::temp_dir().join(format!("gs-no-compress-{}", std::process::id()));
+        let a = daemon("a", &dir).await;
+        let mut config = DaemonConfig::new("b", dir.join("b"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.compress = false;
+        let b = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+
+        let (shared, addr) = (a.shared.clone(), b.local_addr().unwrap());
+        let dialed = tokio::spawn(async move { greet(&shared, &mut shared.connect(vec![addr]).await.unwrap()).await.unwrap() });
+        let mut conn = b.shared.listener.accept().await.unwrap().handshake(b.shared.identity.exchange()).await.unwrap();
+        let greeted = greet(&b.shared, &mut conn).await.unwrap();
+        // b only takes it out of what it offers; neither side may use it
+        for capabilities in [greeted.capabilities, dialed.await.unwrap().capabilities] {
+            assert!(!capabilities.contains(Capabilities::COMPRESSION) && capabilities.contains(Capabilities::DELTA), "{capabilities}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn files_go_side_by_side_on_streams_of_their_own() {
+        let dir = std::env::temp_dir().join(format!("gs-streams-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (big, small) = (dir.join("big.bin"), dir.join("small.txt"));
+        std::fs::write(&big, (0..40 * 256 * 1024u32).map(|i| (i % 251) as u8).collect::<Vec<u8>>()).unwrap();
+        std::fs::write(&small, b"next to the big one").unwrap();
+
+        let (a, b) = (daemon("a", &dir).await, daemon("b", &dir).await);
+        let (transfer, paths, files) = a.register(by_file_name(vec![big, small])).unwrap();
+        let (shared, addr) = (a.shared.clone(), b.local_addr().unwrap());
+        let sent = tokio::spawn(async move { send(shared.clone(), shared.connect(vec![addr]).await.unwrap(), transfer, paths, files).await });
+
+        // a receiver that reads both streams and never acks
+        let mut conn = b.shared.listener.accept().await.unwrap().handshake(b.shared.identity.exchange()).await.unwrap();
+        let greeted = greet(&b.shared, &mut conn).await.unwrap();
+        assert!(greeted.version >= FILE_STREAM_VERSION && conn.quic.is_some());
+        let Ok(Message::TransferOffer(offer)) = next(&mut conn.control).await else { panic!("no offer") };
+        let mut session = TransferSession::incoming(&offer);
+        for _ in 0..2 {
+            let message = next(&mut conn.control).await.unwrap();
+            session.on_message(&message).unwrap();
+        }
+        let accepted = session.accept_compression().unwrap().into_iter().chain([session.accept().unwrap()]);
+        for message in accepted {
+            conn.control.send(message).await.unwrap();
+        }
+        for _ in 0..2 {
+            assert!(matches!(next(&mut conn.control).await.unwrap(), Message::FileHeader(_)));
+        }
+        let quic = conn.quic.clone().unwrap();
+        let mut lanes = BTreeMap::new();
+        for _ in 0..2 {
+            let (index, stream) = quic.accept_file().await.unwrap();
+            lanes.insert(index, file_channel(stream, index, conn.control.codec().keys(), greeted.version, &[]).unwrap());
+        }
+        let (mut big, mut small) = (lanes.remove(&0).unwrap(), lanes.remove(&1).unwrap());
+        let mut chunks = 0;
+        let reached = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                chunks += 1;
+                tokio::select! {
+                    message = big.next() => assert!(matches!(message, Some(Ok(Message::ChunkData(_) | Message::CompressedChunk(_))))),
+                    message = small.next() => {
+                        assert!(matches!(message, Some(Ok(Message::ChunkData(_) | Message::CompressedChunk(_)))));
+                        break;
+                    }
+                }
+            }
+        });
+        // one file after another would fill the window with the big one
+        reached.await.expect("the small file's chunk never came");
+        assert!(chunks <= 32, "{chunks} chunks before the small file's");
+
+        conn.control.send(session.cancel(CancelReason::User).unwrap()).await.unwrap();
+        assert_eq!(sent.await.unwrap(), Some(TransferState::Cancelled(CancelReason::User)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
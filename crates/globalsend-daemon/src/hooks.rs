@@ -0,0 +1,188 @@
+//! Commands and webhooks run on transfer events
+//!
+//! Each [`Hook`] names the [`HookEvent`]s it wants and either runs a
+//! command or POSTs to a URL. Both get the same JSON object: the event
+//! name, the transfer as [`TransferInfo`] shows it and, once files have
+//! landed, a `path` on each file. A command reads it on stdin and also
+//! gets `GLOBALSEND_EVENT`, `GLOBALSEND_TRANSFER`, `GLOBALSEND_PEER` and
+//! `GLOBALSEND_PEER_NAME` in its environment, enough for a one-line
+//! `notify-send`.
+//!
+//! Hooks run in the background and never hold up or fail a transfer; a
+//! hook that errors or outlives [`HOOK_TIMEOUT`] is logged and forgotten.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::rpc::TransferInfo;
+
+/// How long a command or webhook gets before it is given up on
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// An offer arrived and is waiting for an answer
+    OfferReceived,
+    /// Every file went through, either way
+    TransferComplete,
+    /// A received file did not match the hash the sender gave
+    VerificationFailed,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 3] = [HookEvent::OfferReceived, HookEvent::TransferComplete, HookEvent::VerificationFailed];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::OfferReceived => "offer-received",
+            HookEvent::TransferComplete => "transfer-complete",
+            HookEvent::VerificationFailed => "verification-failed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.name() == name)
+    }
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    /// Program and arguments, run without a shell
+    Command(Vec<String>),
+    /// POST the event as JSON to this URL
+    Webhook(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub events: Vec<HookEvent>,
+    pub action: HookAction,
+}
+
+impl Hook {
+    pub fn new(events: Vec<HookEvent>, action: HookAction) -> Self {
+        Self { events, action }
+    }
+
+    /// Run this hook once for `payload`, made by [`payload`]
+    pub async fn run(&self, event: HookEvent, payload: &Value) -> Result<(), String> {
+        let run = async {
+            match &self.action {
+                HookAction::Command(argv) => command(argv, event, payload).await,
+                HookAction::Webhook(url) => webhook(url, payload).await,
+            }
+        };
+        tokio::time::timeout(HOOK_TIMEOUT, run).await.unwrap_or_else(|_| Err("timed out".into()))
+    }
+}
+
+/// What hooks get for `event` on `info`; `paths` lines up with `info.files`
+pub fn payload(event: HookEvent, info: &TransferInfo, paths: &[Option<PathBuf>]) -> Value {
+    let mut value = json!(info);
+    value["event"] = json!(event.name());
+    if let Some(Value::Array(files)) = value.get_mut("files") {
+        for (file, path) in files.iter_mut().zip(paths) {
+            if let Some(path) = path {
+                file["path"] = json!(path);
+            }
+        }
+    }
+    value
+}
+
+/// Start every hook in `hooks` that wants `event`, without waiting for any
+pub(crate) fn fire(hooks: &[Hook], event: HookEvent, payload: Value) {
+    let wanted: Vec<Hook> = hooks.iter().filter(|hook| hook.events.contains(&event)).cloned().collect();
+    if wanted.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for hook in wanted {
+            if let Err(error) = hook.run(event, &payload).await {
+                tracing::warn!(%event, action = ?hook.action, %error, "hook failed");
+            }
+        }
+    });
+}
+
+async fn command(argv: &[String], event: HookEvent, payload: &Value) -> Result<(), String> {
+    let (program, args) = argv.split_first().ok_or("empty command")?;
+    let field = |key: &str| payload[key].as_str().unwrap_or_default().to_owned();
+    let mut child = Command::new(program)
+        .args(args)
+        .env("GLOBALSEND_EVENT", event.name())
+        .env("GLOBALSEND_TRANSFER", field("transfer"))
+        .env("GLOBALSEND_PEER", field("peer"))
+        .env("GLOBALSEND_PEER_NAME", field("peer_name"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("{program}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // one line, so `read` in a shell script takes all of it
+        let input = format!("{payload}\n");
+        // a command that does not read its input is fine
+        let _ = stdin.write_all(input.as_bytes()).await;
+    }
+    let status = child.wait().await.map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{program} exited with {status}"))
+    }
+}
+
+async fn webhook(url: &str, payload: &Value) -> Result<(), String> {
+    let response = reqwest::Client::new().post(url).json(payload).send().await.map_err(|e| e.to_string())?;
+    response.error_for_status().map(drop).map_err(|e| e.to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::rpc::FileInfo;
+
+    #[tokio::test]
+    async fn command_gets_the_event_on_stdin_and_in_its_environment() {
+        let out = std::env::temp_dir().join(format!("gs-hook-{}", std::process::id()));
+        let info = TransferInfo {
+            transfer: "00".repeat(16),
+            direction: "receive".into(),
+            peer: "ab".repeat(32),
+            peer_verified: true,
+            peer_name: "phone".into(),
+            state: "done".into(),
+            waiting: false,
+            bytes: 3,
+            total: 3,
+            files: vec![FileInfo { name: "a.jpg".into(), size: 3, bytes: 3, text: None, thumbnail: None }],
+        };
+        let payload = payload(HookEvent::TransferComplete, &info, &[Some(PathBuf::from("/in/a.jpg"))]);
+        let script = format!("cat > {0}; echo \"$GLOBALSEND_EVENT $GLOBALSEND_PEER_NAME\" >> {0}", out.display());
+        let hook = Hook::new(vec![HookEvent::TransferComplete], HookAction::Command(vec!["sh".into(), "-c".into(), script]));
+        hook.run(HookEvent::TransferComplete, &payload).await.unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        let (json, env) = written.split_once('\n').unwrap();
+        let sent: Value = serde_json::from_str(json).unwrap();
+        assert_eq!((sent["event"].as_str(), sent["files"][0]["path"].as_str()), (Some("transfer-complete"), Some("/in/a.jpg")));
+        assert_eq!(env.trim(), "transfer-complete phone");
+
+        let failing = Hook::new(vec![HookEvent::TransferComplete], HookAction::Command(vec!["false".into()]));
+        assert!(failing.run(HookEvent::TransferComplete, &payload).await.is_err());
+        std::fs::remove_file(&out).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//! The local control socket
+//!
+//! A Unix domain socket, or a named pipe on Windows, carrying one JSON-RPC
+//! message per line in each direction (see [`rpc`](crate::rpc)). Requests
+//! on one connection are answered in order; connections run concurrently.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+
+use crate::rpc::{Call, Request, Response, RpcError, INVALID_REQUEST, PARSE_ERROR};
+use crate::{Daemon, DaemonError};
+
+#[cfg(unix)]
+type Stream = tokio::net::UnixStream;
+#[cfg(windows)]
+type Stream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// `$XDG_RUNTIME_DIR/globalsend.sock`, falling back to the temporary directory
+#[cfg(unix)]
+pub fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("globalsend.sock"),
+        // shared with other users, so keep out of their way
+        None => std::env::temp_dir().join(format!("globalsend-{}.sock", std::env::var("USER").unwrap_or_default())),
+    }
+}
+
+#[cfg(windows)]
+pub fn default_socket() -> PathBuf {
+    PathBuf::from(r"\\.\pipe\globalsend")
+}
+
+#[cfg(unix)]
+pub(crate) async fn serve(path: &Path, daemon: &Daemon) -> Result<(), DaemonError> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())).into());
+        }
+        if Stream::connect(path).await.is_ok() {
+            return Err(DaemonError::AlreadyRunning(path.to_owned()));
+        }
+        // left behind by a daemon that did not shut down cleanly
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let daemon = daemon.clone();
+        tokio::spawn(async move { connection(&daemon, stream).await });
+    }
+}
+
+#[cfg(windows)]
+pub(crate) async fn serve(path: &Path, daemon: &Daemon) -> Result<(), DaemonError> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new().first_pipe_instance(true).create(path).map_err(|e| match e.kind() {
+        io::ErrorKind::PermissionDenied => DaemonError::AlreadyRunning(path.to_owned()),
+        _ => e.into(),
+    })?;
+    loop {
+        server.connect().await?;
+        let connected = std::mem::replace(&mut server, ServerOptions::new().create(path)?);
+        let daemon = daemon.clone();
+        tokio::spawn(async move { connection(&daemon, connected).await });
+    }
+}
+
+async fn connection<S: AsyncRead + AsyncWrite>(daemon: &Daemon, stream: S) -> io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle(daemon, &line).await {
+            let mut out = serde_json::to_vec(&response).map_err(io::Error::other)?;
+            out.push(b'\n');
+            writer.write_all(&out).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Answer one line; notifications get no response
+async fn handle(daemon: &Daemon, line: &str) -> Option<Response> {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Some(Response::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: Request = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => return Some(Response::new(id, Err(RpcError::new(INVALID_REQUEST, e.to_string())))),
+    };
+    if request.jsonrpc != "2.0" {
+        return Some(Response::new(id, Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))));
+    }
+    let outcome = match Call::parse(&request.method, request.params) {
+        Ok(call) => daemon.call(call).await,
+        Err(e) => Err(e),
+    };
+    request.id.map(|id| Response::new(id, outcome))
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// The daemon answered with an error
+    Rpc(RpcError),
+    /// The daemon's answer is not a response to our request
+    Malformed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "i/o error: {e}"),
+            ClientError::Rpc(e) => write!(f, "{e}"),
+            ClientError::Malformed => write!(f, "malformed response from the daemon"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// A connection to a running daemon's control socket
+pub struct Client {
+    lines: Lines<BufReader<ReadHalf<Stream>>>,
+    writer: WriteHalf<Stream>,
+    next_id: u64,
+}
+
+impl Client {
+    pub async fn connect(path: &Path) -> io::Result<Self> {
+        #[cfg(unix)]
+        let stream = Stream::connect(path).await?;
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(path)?;
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(Self { lines: BufReader::new(reader).lines(), writer, next_id: 1 })
+    }
+
+    /// Make one call and wait for its result
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, ClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request { jsonrpc: "2.0".into(), id: Some(id.into()), method: method.into(), params };
+        let mut out = serde_json::to_vec(&request).map_err(io::Error::other)?;
+        out.push(b'\n');
+        self.writer.write_all(&out).await?;
+        let line = self.lines.next_line().await?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let response: Response = serde_json::from_str(&line).map_err(|_| ClientError::Malformed)?;
+        if response.id != id {
+            return Err(ClientError::Malformed);
+        }
+        match response.error {
+            Some(error) => Err(ClientError::Rpc(error)),
+            // a null result reads back as none
+            None => Ok(response.result.unwrap_or(Value::Null)),
+        }
+    }
+}
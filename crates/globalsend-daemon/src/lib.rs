@@ -0,0 +1,859 @@
+//! `globalsend daemon`: discovery and receiving that keep running
+//!
+//! A [`Daemon`] advertises this device over mDNS, accepts connections on
+//! its transport [`Listener`] and drives every transfer to the end, while
+//! desktop shells and scripts steer it over a local socket ([`ipc`]) with
+//! the JSON-RPC calls in [`rpc`]: list devices and transfers, send files,
+//! accept or decline offers and cancel transfers. Incoming offers always
+//! wait for an explicit `accept`.
+//!
+//! Configured [`hooks`] run a command or call a webhook when an offer
+//! arrives, a transfer completes or a received file fails verification.
+//! A [`watcher`] sends a file or folder again whenever it changes, and
+//! [`sync`] keeps configured folders the same on two paired devices; those
+//! are the only files the daemon takes without asking.
+//!
+//! With a checkpoint directory configured, a receive cut off part way
+//! keeps its files and a checkpoint; the sender offers the same transfer
+//! again when its link drops, and the receiver picks it up where it
+//! stopped without asking a second time. With an outbox directory
+//! ([`DaemonConfig::outbox`]), the sender does the same for transfers it
+//! had going when it stopped.
+//!
+//! The socket is the only access control: it is created readable by this
+//! user only, and anyone who can open it can send and receive as this
+//! device.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_discovery::{Device, DiscoveryError, LocalDevice, MdnsDiscovery};
+use globalsend_proto::{CancelReason, Hello, OfferedFile, TransferId, VersionRange};
+use globalsend_store::{HistoryStore, IndexStore, StoreError};
+use globalsend_transfer::policy::guess_mime;
+use globalsend_transfer::preview::Preview;
+use globalsend_transfer::{CancelToken, CheckpointStore, Direction, Failure, KeepPartial, Progress, TransferState};
+use globalsend_transport::connect::{ConnectError, Connection};
+use globalsend_transport::{Dialer, Listener, RateLimit, RateLimiter, TransportPreference};
+use serde_json::{json, Value};
+use tokio::sync::{oneshot, watch};
+
+mod engine;
+pub mod hooks;
+pub mod ipc;
+mod outbox;
+pub mod rpc;
+pub mod sync;
+pub mod watcher;
+
+use crate::hooks::Hook;
+use crate::outbox::{Outbox, Outgoing, To};
+use crate::sync::SyncProfile;
+use crate::rpc::{Call, DeviceInfo, FileInfo, RpcError, Target, TransferInfo, INVALID_PARAMS, NOT_FOUND, WRONG_STATE};
+
+/// How often a transfer whose link dropped or whose device was out of reach is offered again
+const RESEND_ATTEMPTS: usize = 5;
+/// Wait before the first retry, doubled for each one after
+const RESEND_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    /// Name shown to other devices
+    pub alias: String,
+    /// Where the transport listens; port 0 picks a free one
+    pub listen: SocketAddr,
+    /// Control socket path, or pipe name on Windows
+    pub socket: PathBuf,
+    /// Where accepted files go unless `accept` names a destination
+    pub downloads: PathBuf,
+    /// Advertise and browse over mDNS
+    pub discovery: bool,
+    pub transport: TransportPreference,
+    /// Record finished transfers in this history database
+    pub history: Option<PathBuf>,
+    /// Run on offers, completed transfers and failed verification
+    pub hooks: Vec<Hook>,
+    /// Folders kept in sync with paired devices
+    pub sync: Vec<SyncProfile>,
+    /// Sync index database; without one, every file is new after a restart
+    pub index: Option<PathBuf>,
+    /// Checkpoints of receives cut off part way; without them, what did not finish goes to `keep_partial`
+    pub checkpoints: Option<PathBuf>,
+    /// What becomes of files a receive left half-written and cannot resume
+    pub keep_partial: KeepPartial,
+    /// Sends not yet ended, offered again after a restart; without it, they are forgotten
+    pub outbox: Option<PathBuf>,
+    /// Offer to compress chunks; without, peers send them as they are
+    pub compress: bool,
+    /// Bandwidth for all transfers together
+    pub rate: RateLimit,
+    /// Bandwidth for each transfer on its own
+    pub transfer_rate: RateLimit,
+}
+
+impl DaemonConfig {
+    pub fn new(alias: impl Into<String>, downloads: impl Into<PathBuf>) -> Self {
+        Self {
+            alias: alias.into(),
+            listen: SocketAddr::from(([0, 0, 0, 0], 0)),
+            socket: ipc::default_socket(),
+            downloads: downloads.into(),
+            discovery: true,
+            transport: TransportPreference::Auto,
+            history: None,
+            hooks: Vec::new(),
+            sync: Vec::new(),
+            index: None,
+            checkpoints: None,
+            keep_partial: KeepPartial::Remove,
+            outbox: None,
+            compress: true,
+            rate: RateLimit::UNLIMITED,
+            transfer_rate: RateLimit::UNLIMITED,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DaemonError {
+    Io(io::Error),
+    Connect(ConnectError),
+    Discovery(DiscoveryError),
+    Store(StoreError),
+    /// Another daemon is serving the control socket
+    AlreadyRunning(PathBuf),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DaemonError::Io(e) => write!(f, "i/o error: {e}"),
+            DaemonError::Connect(e) => write!(f, "{e}"),
+            DaemonError::Discovery(e) => write!(f, "discovery: {e}"),
+            DaemonError::Store(e) => write!(f, "{e}"),
+            DaemonError::AlreadyRunning(path) => write!(f, "a daemon is already listening on {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+impl From<io::Error> for DaemonError {
+    fn from(e: io::Error) -> Self {
+        DaemonError::Io(e)
+    }
+}
+
+impl From<ConnectError> for DaemonError {
+    fn from(e: ConnectError) -> Self {
+        DaemonError::Connect(e)
+    }
+}
+
+impl From<DiscoveryError> for DaemonError {
+    fn from(e: DiscoveryError) -> Self {
+        DaemonError::Discovery(e)
+    }
+}
+
+impl From<StoreError> for DaemonError {
+    fn from(e: StoreError) -> Self {
+        DaemonError::Store(e)
+    }
+}
+
+/// The user's answer to an incoming offer
+#[derive(Debug)]
+enum Answer {
+    /// Into the directory, only the files marked wanted if there is a list
+    Accept(PathBuf, Option<Vec<bool>>),
+    Decline,
+    /// Pick up where an earlier attempt at the same offer stopped
+    Resume,
+}
+
+/// What the daemon knows about one transfer
+struct Entry {
+    direction: Direction,
+    peer: Option<Fingerprint>,
+    /// The peer proved `peer`, rather than only claiming it in its hello
+    verified: bool,
+    peer_name: String,
+    files: Vec<OfferedFile>,
+    /// Set once the session exists
+    progress: Option<watch::Receiver<Progress>>,
+    /// Why it ended before a session existed
+    error: Option<String>,
+    cancel: CancelToken,
+    /// Incoming offers waiting for the user
+    answer: Option<oneshot::Sender<Answer>>,
+    /// Checked previews of an incoming offer, by file
+    previews: Vec<Preview>,
+    /// The sender of an incoming offer can skip files the user turns down
+    selective: bool,
+    /// An outgoing one may still be offered again, so an end so far is not the last
+    retrying: bool,
+}
+
+impl Entry {
+    fn new(direction: Direction, files: Vec<OfferedFile>) -> Self {
+        Self { direction, peer: None, verified: false, peer_name: String::new(), files, progress: None, error: None, cancel: CancelToken::new(), answer: None, previews: Vec::new(), selective: false, retrying: false }
+    }
+
+    fn finished(&self) -> bool {
+        !self.retrying && (self.error.is_some() || self.progress.as_ref().is_some_and(|p| p.borrow().state.is_terminal()))
+    }
+
+    fn info(&self, transfer: &TransferId) -> TransferInfo {
+        let progress = self.progress.as_ref().map(|p| p.borrow().clone());
+        let state = match (&self.error, &progress) {
+            (Some(error), _) => format!("failed: {error}"),
+            (None, Some(progress)) => progress.state.to_string(),
+            (None, None) => "connecting".into(),
+        };
+        let mut files: Vec<FileInfo> = match &progress {
+            Some(progress) => progress.files.iter().map(|f| FileInfo { name: f.name.clone(), size: f.size, bytes: f.bytes, text: None, thumbnail: None }).collect(),
+            None => self.files.iter().map(|f| FileInfo { name: f.name.clone(), size: f.size, bytes: 0, text: None, thumbnail: None }).collect(),
+        };
+        for (file, preview) in files.iter_mut().zip(&self.previews) {
+            file.text = preview.text.clone();
+            file.thumbnail = preview.thumbnail.as_ref().map(|png| STANDARD.encode(png));
+        }
+        TransferInfo {
+            transfer: rpc::transfer_id_hex(transfer),
+            direction: match self.direction {
+                Direction::Send => "send".into(),
+                Direction::Receive => "receive".into(),
+            },
+            peer: self.peer.map(|p| p.to_hex()).unwrap_or_default(),
+            peer_verified: self.verified,
+            peer_name: self.peer_name.clone(),
+            state,
+            waiting: self.answer.is_some(),
+            bytes: files.iter().map(|f| f.bytes).sum(),
+            total: files.iter().map(|f| f.size).sum(),
+            files,
+        }
+    }
+}
+
+/// State every task of the daemon shares
+struct Shared {
+    identity: Arc<DeviceIdentity>,
+    config: DaemonConfig,
+    listener: Listener,
+    devices: watch::Receiver<BTreeMap<Fingerprint, Device>>,
+    transfers: Mutex<BTreeMap<TransferId, Entry>>,
+    history: Option<Mutex<HistoryStore>>,
+    index: Mutex<IndexStore>,
+    checkpoints: Option<CheckpointStore>,
+    outbox: Option<Outbox>,
+    /// Shared by every connection, for [`DaemonConfig::rate`]
+    limiter: RateLimiter,
+    /// Sync folders with a run going
+    syncing: Mutex<BTreeSet<String>>,
+}
+
+impl Shared {
+    fn hello(&self) -> Hello {
+        Hello { versions: VersionRange::CURRENT, device_name: self.config.alias.clone(), fingerprint: *self.identity.fingerprint().as_bytes() }
+    }
+
+    fn update<T>(&self, transfer: &TransferId, f: impl FnOnce(&mut Entry) -> T) -> Option<T> {
+        self.transfers.lock().expect("transfers lock").get_mut(transfer).map(f)
+    }
+
+    /// Where to reach `target`; `None` for a device discovery does not see
+    fn addrs(&self, target: &Target) -> Option<Vec<SocketAddr>> {
+        match target {
+            Target::Addr(addr) => Some(vec![*addr]),
+            Target::Device(fingerprint) => self.devices.borrow().get(fingerprint).map(|d| d.addrs.clone()),
+        }
+    }
+
+    /// Dial each of `addrs` in turn until one answers; why the last failed if none does
+    async fn connect(&self, addrs: Vec<SocketAddr>) -> Result<Connection, String> {
+        let dialer = Dialer::new(self.identity.exchange(), self.listener.quic()).preference(self.config.transport);
+        let mut last = None;
+        for addr in addrs {
+            match dialer.connect(addr).await {
+                Ok(conn) => return Ok(self.throttle(conn)),
+                Err(e) => last = Some(e.to_string()),
+            }
+        }
+        Err(last.unwrap_or_else(|| "device has no address".into()))
+    }
+
+    /// Hold `conn` to the daemon's bandwidth and a transfer's own
+    fn throttle(&self, conn: Connection) -> Connection {
+        conn.throttle(&[self.limiter.clone(), RateLimiter::new(self.config.transfer_rate)])
+    }
+}
+
+/// Offer `outgoing` until it ends other than by a dropped link or an
+/// unreachable device, waiting longer before each retry; then it leaves the outbox
+async fn deliver(shared: Arc<Shared>, outgoing: Outgoing) {
+    let Outgoing { transfer, to, paths, files } = &outgoing;
+    let Some(cancel) = shared.update(transfer, |entry| entry.cancel.clone()) else { return };
+    let mut delay = RESEND_DELAY;
+    for attempt in 0..=RESEND_ATTEMPTS {
+        shared.update(transfer, |entry| entry.retrying = attempt < RESEND_ATTEMPTS);
+        if attempt > 0 {
+            tokio::select! {
+                () = tokio::time::sleep(delay) => {}
+                _ = cancel.cancelled() => {
+                    shared.update(transfer, |entry| entry.error = Some("cancelled".into()));
+                    break;
+                }
+            }
+            delay *= 2;
+        }
+        let addrs = to.target().and_then(|target| shared.addrs(&target));
+        let connected = match addrs {
+            Some(addrs) => shared.connect(addrs).await,
+            None => Err("device is out of reach".into()),
+        };
+        match connected {
+            Ok(conn) => {
+                // only a dropped link is worth offering the same transfer again
+                if engine::send(shared.clone(), conn, *transfer, paths.clone(), files.clone()).await != Some(TransferState::Failed(Failure::Timeout)) {
+                    break;
+                }
+            }
+            Err(e) if attempt < RESEND_ATTEMPTS => tracing::info!(error = %e, "cannot reach the device; trying again"),
+            Err(e) => {
+                shared.update(transfer, |entry| entry.error = Some(e));
+            }
+        }
+    }
+    shared.update(transfer, |entry| entry.retrying = false);
+    if let Some(outbox) = &shared.outbox {
+        let _ = outbox.remove(transfer);
+    }
+}
+
+/// Each path with its file name, or an empty name if it has none
+fn by_file_name(paths: Vec<PathBuf>) -> Vec<(PathBuf, String)> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            (path, name)
+        })
+        .collect()
+}
+
+/// Seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[derive(Clone)]
+pub struct Daemon {
+    shared: Arc<Shared>,
+    _mdns: Option<Arc<MdnsDiscovery>>,
+}
+
+impl Daemon {
+    /// Bind the transport and start discovery; nothing is served until [`run`](Self::run)
+    pub async fn start(identity: Arc<DeviceIdentity>, config: DaemonConfig) -> Result<Self, DaemonError> {
+        let listener = Listener::bind(config.listen).await?;
+        let (mdns, devices) = if config.discovery {
+            let local = LocalDevice { fingerprint: identity.fingerprint(), alias: config.alias.clone(), port: listener.local_addr()?.port() };
+            let mdns = MdnsDiscovery::start(&local)?;
+            let devices = mdns.devices();
+            (Some(mdns), devices)
+        } else {
+            (None, watch::Sender::new(BTreeMap::new()).subscribe())
+        };
+        let history = config.history.as_ref().map(HistoryStore::open).transpose()?.map(Mutex::new);
+        let index = Mutex::new(config.index.as_ref().map_or_else(IndexStore::in_memory, IndexStore::open)?);
+        let checkpoints = config.checkpoints.as_ref().map(CheckpointStore::open).transpose()?;
+        let outbox = config.outbox.as_ref().map(Outbox::open).transpose()?;
+        let limiter = RateLimiter::new(config.rate);
+        let shared = Shared { identity, config, listener, devices, transfers: Mutex::new(BTreeMap::new()), history, index, checkpoints, outbox, limiter, syncing: Mutex::new(BTreeSet::new()) };
+        let daemon = Self { shared: Arc::new(shared), _mdns: mdns.map(Arc::new) };
+        daemon.resume_sends()?;
+        Ok(daemon)
+    }
+
+    /// Offer again what the outbox holds from before the last stop, under the same ids
+    fn resume_sends(&self) -> io::Result<()> {
+        let Some(outbox) = &self.shared.outbox else { return Ok(()) };
+        for outgoing in outbox.list()? {
+            // an edited file would not match what the receiver has of it
+            if !outgoing.unchanged() || outgoing.to.target().is_none() {
+                outbox.remove(&outgoing.transfer)?;
+                continue;
+            }
+            tracing::info!(id = rpc::transfer_id_hex(&outgoing.transfer), "offering again");
+            self.shared.transfers.lock().expect("transfers lock").insert(outgoing.transfer, Entry::new(Direction::Send, outgoing.files.clone()));
+            tokio::spawn(deliver(self.shared.clone(), outgoing));
+        }
+        Ok(())
+    }
+
+    /// Where the transport listens
+    pub fn local_addr(&self) -> Result<SocketAddr, DaemonError> {
+        Ok(self.shared.listener.local_addr()?)
+    }
+
+    /// Take connections and control requests, and keep the sync folders in
+    /// sync, until the control socket fails
+    pub async fn run(&self) -> Result<(), DaemonError> {
+        let _syncing = self.keep_in_sync().map_err(|e| DaemonError::Io(io::Error::other(e)))?;
+        let accepting = tokio::spawn({
+            let daemon = self.clone();
+            async move { daemon.listen().await }
+        });
+        let served = ipc::serve(&self.shared.config.socket, self).await;
+        accepting.abort();
+        served
+    }
+
+    /// Take connections from peers, without a control socket; never returns
+    pub async fn listen(&self) {
+        loop {
+            let Ok(incoming) = self.shared.listener.accept().await else { continue };
+            // each handshake in its own task, so a stalled peer holds up nobody else;
+            // a failed one is the peer's problem, not ours
+            let daemon = self.clone();
+            tokio::spawn(async move {
+                if let Ok(conn) = incoming.handshake(daemon.shared.identity.exchange()).await {
+                    daemon.receive_over(conn);
+                }
+            });
+        }
+    }
+
+    /// Answer one request, as if it came over the socket
+    pub async fn call(&self, call: Call) -> Result<Value, RpcError> {
+        match call {
+            Call::Devices => Ok(json!(self.devices())),
+            Call::Transfers => Ok(json!(self.transfers())),
+            Call::Send { target, paths } => self.send(target, paths).map(|transfer| json!({ "transfer": rpc::transfer_id_hex(&transfer) })),
+            Call::Accept { transfer, destination, files } => self.accept_files(&transfer, destination, files.as_deref()).map(|()| Value::Null),
+            Call::Decline { transfer } => self.decline(&transfer).map(|()| Value::Null),
+            Call::Cancel { transfer } => self.cancel(&transfer).map(|()| Value::Null),
+            Call::Sync { folder } => self.sync_now(&folder).map(|()| Value::Null),
+        }
+    }
+
+    /// Devices discovery currently sees
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.shared.devices.borrow().values().map(DeviceInfo::from).collect()
+    }
+
+    pub fn transfers(&self) -> Vec<TransferInfo> {
+        self.shared.transfers.lock().expect("transfers lock").iter().map(|(id, entry)| entry.info(id)).collect()
+    }
+
+    /// Take up an offer, into `destination` or the download directory
+    pub fn accept(&self, transfer: &TransferId, destination: Option<PathBuf>) -> Result<(), RpcError> {
+        self.accept_files(transfer, destination, None)
+    }
+
+    /// Take up only the offered files with the indices in `files`, or all
+    /// of them for `None`; senders before protocol version 12 cannot skip any
+    pub fn accept_files(&self, transfer: &TransferId, destination: Option<PathBuf>, files: Option<&[u32]>) -> Result<(), RpcError> {
+        let (offered, selective) = self.shared.update(transfer, |entry| (entry.files.len(), entry.selective)).ok_or_else(|| RpcError::new(NOT_FOUND, "no such transfer"))?;
+        let wanted = match files {
+            Some(files) => {
+                let mut wanted = vec![false; offered];
+                for &index in files {
+                    *wanted.get_mut(index as usize).ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("no offered file {index}")))? = true;
+                }
+                if !wanted.contains(&true) {
+                    return Err(RpcError::new(INVALID_PARAMS, "no files to accept; decline instead"));
+                }
+                Some(wanted).filter(|wanted| wanted.contains(&false))
+            }
+            None => None,
+        };
+        if wanted.is_some() && !selective {
+            return Err(RpcError::new(WRONG_STATE, "the sender cannot skip files; accept all or decline"));
+        }
+        let destination = destination.unwrap_or_else(|| self.shared.config.downloads.clone());
+        self.answer(transfer, Answer::Accept(destination, wanted))
+    }
+
+    pub fn decline(&self, transfer: &TransferId) -> Result<(), RpcError> {
+        self.answer(transfer, Answer::Decline)
+    }
+
+    pub fn cancel(&self, transfer: &TransferId) -> Result<(), RpcError> {
+        match self.shared.update(transfer, |entry| !entry.finished() && entry.cancel.cancel(CancelReason::User)) {
+            None => Err(RpcError::new(NOT_FOUND, "no such transfer")),
+            Some(false) => Err(RpcError::new(WRONG_STATE, "transfer already ended")),
+            Some(true) => Ok(()),
+        }
+    }
+
+    fn answer(&self, transfer: &TransferId, answer: Answer) -> Result<(), RpcError> {
+        let waiting = self.shared.update(transfer, |entry| entry.answer.take()).ok_or_else(|| RpcError::new(NOT_FOUND, "no such transfer"))?;
+        let waiting = waiting.ok_or_else(|| RpcError::new(WRONG_STATE, "transfer is not waiting for an answer"))?;
+        waiting.send(answer).map_err(|_| RpcError::new(WRONG_STATE, "transfer already ended"))
+    }
+
+    /// Start sending `paths` to `target`; the transfer runs in the background
+    pub fn send(&self, target: Target, paths: Vec<PathBuf>) -> Result<TransferId, RpcError> {
+        self.send_named(target, by_file_name(paths))
+    }
+
+    /// [`send`](Self::send) with the name each file goes under, e.g. `notes/a.txt` to land in a folder
+    pub fn send_named(&self, target: Target, files: Vec<(PathBuf, String)>) -> Result<TransferId, RpcError> {
+        self.shared.addrs(&target).ok_or_else(|| RpcError::new(NOT_FOUND, "no such device"))?;
+        let (transfer, paths, files) = self.register(files)?;
+        let outgoing = Outgoing { transfer, to: To::from(&target), paths, files };
+        if let Some(outbox) = &self.shared.outbox {
+            // without it the send still runs, it only would not survive a restart
+            if let Err(e) = outbox.save(&outgoing) {
+                tracing::warn!(error = %e, "cannot save the transfer to the outbox");
+            }
+        }
+        tokio::spawn(deliver(self.shared.clone(), outgoing));
+        Ok(transfer)
+    }
+
+    /// Send `paths` over a connection made elsewhere, e.g. through a wormhole
+    pub fn send_over(&self, conn: Connection, paths: Vec<PathBuf>) -> Result<TransferId, RpcError> {
+        let (transfer, paths, files) = self.register(by_file_name(paths))?;
+        tokio::spawn(engine::send(self.shared.clone(), self.shared.throttle(conn), transfer, paths, files));
+        Ok(transfer)
+    }
+
+    /// Wait for an offer on a connection made elsewhere; it shows up in
+    /// [`transfers`](Self::transfers) like any other
+    pub fn receive_over(&self, conn: Connection) {
+        tokio::spawn(engine::receive(self.shared.clone(), self.shared.throttle(conn)));
+    }
+
+    /// Check the paths are files and add an outgoing entry offering them under their names
+    fn register(&self, named: Vec<(PathBuf, String)>) -> Result<(TransferId, Vec<PathBuf>, Vec<OfferedFile>), RpcError> {
+        let mut paths = Vec::with_capacity(named.len());
+        let mut files = Vec::with_capacity(named.len());
+        for (path, name) in named {
+            let metadata = std::fs::metadata(&path).map_err(|e| RpcError::new(INVALID_PARAMS, format!("{}: {e}", path.display())))?;
+            if name.is_empty() || !metadata.is_file() {
+                return Err(RpcError::new(INVALID_PARAMS, format!("{} is not a file", path.display())));
+            }
+            files.push(OfferedFile { mime: guess_mime(&name).map(Into::into), name, size: metadata.len() });
+            paths.push(path);
+        }
+        let transfer = TransferId(rand::random());
+        self.shared.transfers.lock().expect("transfers lock").insert(transfer, Entry::new(Direction::Send, files.clone()));
+        Ok((transfer, paths, files))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::ipc::{Client, ClientError};
+    use crate::rpc::METHOD_NOT_FOUND;
+    use std::time::Duration;
+
+    async fn daemon(name: &str, dir: &std::path::Path) -> Daemon {
+        let mut config = DaemonConfig::new(name, dir.join(name));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.socket = dir.join(format!("{name}.sock"));
+        config.discovery = false;
+        config.history = Some(dir.join(format!("{name}.db")));
+        std::fs::create_dir_all(&config.downloads).unwrap();
+        Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap()
+    }
+
+    async fn transfers(client: &mut Client) -> Vec<TransferInfo> {
+        serde_json::from_value(client.call("transfers", Value::Null).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn send_waits_for_accept_over_the_socket() {
+        let dir = std::env::temp_dir().join(format!("gs-daemon-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("notes.txt");
+        let data: Vec<u8> = (0..600_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+
+        let (a, b) = (daemon("a", &dir).await, daemon("b", &dir).await);
+        let b_addr = b.local_addr().unwrap();
+        tokio::spawn({
+            let b = b.clone();
+            async move { b.run().await }
+        });
+        let socket = dir.join("b.sock");
+        let mut client = loop {
+            match Client::connect(&socket).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        assert!(matches!(client.call("frobnicate", Value::Null).await, Err(ClientError::Rpc(e)) if e.code == METHOD_NOT_FOUND));
+
+        let transfer = rpc::transfer_id_hex(&a.send(Target::Addr(b_addr), vec![source]).unwrap());
+        // nothing lands until the user says so
+        let offered = loop {
+            if let Some(info) = transfers(&mut client).await.into_iter().find(|t| t.waiting) {
+                break info;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!((offered.transfer.as_str(), offered.total, offered.peer_name.as_str()), (transfer.as_str(), data.len() as u64, "a"));
+        assert_eq!((offered.peer, offered.peer_verified), (a.shared.identity.fingerprint().to_hex(), true));
+        assert!(!dir.join("b/notes.txt").exists());
+
+        client.call("accept", json!({ "transfer": transfer })).await.unwrap();
+        let done = loop {
+            let info = transfers(&mut client).await.remove(0);
+            if info.state == "done" {
+                break info;
+            }
+            assert!(!info.state.starts_with("failed"), "{}", info.state);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(done.bytes, data.len() as u64);
+        assert_eq!(std::fs::read(dir.join("b/notes.txt")).unwrap(), data);
+        assert!(matches!(client.call("cancel", json!({ "transfer": transfer })).await, Err(ClientError::Rpc(e)) if e.code == WRONG_STATE));
+
+        let history = b.shared.history.as_ref().unwrap().lock().unwrap().query(&globalsend_store::HistoryQuery::recent(10)).unwrap();
+        assert_eq!(history[0].files[0].path.as_deref(), Some(dir.join("b/notes.txt").as_path()));
+        assert!(history[0].verified);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transfers_hold_to_the_upload_limit() {
+        let dir = std::env::temp_dir().join(format!("gs-limit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("notes.txt");
+        // random, so compression does not shrink it under the limit
+        std::fs::write(&source, (0..500_000).map(|_| rand::random::<u8>()).collect::<Vec<u8>>()).unwrap();
+        let mut config = DaemonConfig::new("a", dir.join("a"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.transfer_rate = RateLimit { max_up: Some(250_000), max_down: None };
+        let a = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        let b = daemon("b", &dir).await;
+        tokio::spawn({
+            let b = b.clone();
+            async move { b.listen().await }
+        });
+
+        let started = std::time::Instant::now();
+        let transfer = a.send(Target::Addr(b.local_addr().unwrap()), vec![source]).unwrap();
+        while b.accept(&transfer, None).is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        while !a.transfers().iter().any(|t| t.state == "done") {
+            assert!(!a.transfers()[0].state.starts_with("failed"), "{}", a.transfers()[0].state);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // a burst of 25 KB, then the rest at 250 KB/s
+        assert!(started.elapsed() >= Duration::from_millis(1800), "{:?}", started.elapsed());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_cancelled_receive_leaves_its_partial_files_to_the_policy() {
+        let dir = std::env::temp_dir().join(format!("gs-quarantine-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("notes.txt");
+        std::fs::write(&source, (0..1_000_000).map(|_| rand::random::<u8>()).collect::<Vec<u8>>()).unwrap();
+        let mut config = DaemonConfig::new("a", dir.join("a"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.transfer_rate = RateLimit { max_up: Some(250_000), max_down: None };
+        let a = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        let mut config = DaemonConfig::new("b", dir.join("b"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.keep_partial = KeepPartial::Quarantine(dir.join("quarantine"));
+        std::fs::create_dir_all(&config.downloads).unwrap();
+        let b = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        tokio::spawn({
+            let b = b.clone();
+            async move { b.listen().await }
+        });
+
+        let transfer = a.send(Target::Addr(b.local_addr().unwrap()), vec![source]).unwrap();
+        while b.accept(&transfer, None).is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        while b.transfers()[0].bytes == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        b.cancel(&transfer).unwrap();
+        let quarantined = dir.join("quarantine").join(rpc::transfer_id_hex(&transfer)).join("notes.txt");
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !quarantined.exists() {
+            assert!(std::time::Instant::now() < deadline, "{}", b.transfers()[0].state);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!dir.join("b/notes.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Forward TCP to `to`, cutting the first connection after `cut` bytes
+    /// towards `to`; the bytes each connection forwarded that way
+    async fn flaky_link(to: SocketAddr, cut: usize) -> (SocketAddr, Arc<Mutex<Vec<usize>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (addr, forwarded) = (listener.local_addr().unwrap(), Arc::new(Mutex::new(Vec::new())));
+        let counts = forwarded.clone();
+        tokio::spawn(async move {
+            while let Ok((inbound, _)) = listener.accept().await {
+                let (counts, connection) = (counts.clone(), counts.lock().unwrap().len());
+                counts.lock().unwrap().push(0);
+                let first = connection == 0;
+                let outbound = tokio::net::TcpStream::connect(to).await.unwrap();
+                tokio::spawn(async move {
+                    let ((mut in_read, mut in_write), (mut out_read, mut out_write)) = (inbound.into_split(), outbound.into_split());
+                    let forward = async {
+                        let (mut buf, mut sent) = (vec![0; 16 * 1024], 0);
+                        while !first || sent < cut {
+                            let n = in_read.read(&mut buf).await?;
+                            if n == 0 {
+                                break;
+                            }
+                            out_write.write_all(&buf[..n]).await?;
+                            sent += n;
+                            counts.lock().unwrap()[connection] = sent;
+                        }
+                        std::io::Result::Ok(())
+                    };
+                    // either way ending drops both sides
+                    tokio::select! {
+                        _ = forward => {}
+                        _ = tokio::io::copy(&mut out_read, &mut in_write) => {}
+                    }
+                });
+            }
+        });
+        (addr, forwarded)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_dropped_link_is_offered_again_and_resumed() {
+        let dir = std::env::temp_dir().join(format!("gs-dropped-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("notes.txt");
+        let data: Vec<u8> = (0..3_000_000).map(|_| rand::random::<u8>()).collect();
+        std::fs::write(&source, &data).unwrap();
+        let mut config = DaemonConfig::new("a", dir.join("a"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.transport = TransportPreference::TcpOnly;
+        config.outbox = Some(dir.join("a-outbox"));
+        let a = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        let mut config = DaemonConfig::new("b", dir.join("b"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.checkpoints = Some(dir.join("b-checkpoints"));
+        std::fs::create_dir_all(&config.downloads).unwrap();
+        let b = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        tokio::spawn({
+            let b = b.clone();
+            async move { b.listen().await }
+        });
+        let (link, forwarded) = flaky_link(b.local_addr().unwrap(), 1_200_000).await;
+
+        let transfer = a.send(Target::Addr(link), vec![source]).unwrap();
+        assert_eq!(a.shared.outbox.as_ref().unwrap().list().unwrap()[0].transfer, transfer);
+        while b.accept(&transfer, None).is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // the second offer goes through without another accept
+        let done = tokio::time::timeout(Duration::from_secs(30), async {
+            while a.transfers()[0].state != "done" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        done.await.unwrap_or_else(|_| panic!("{}", a.transfers()[0].state));
+        assert_eq!(std::fs::read(dir.join("b/notes.txt")).unwrap(), data);
+        // the second time, not the chunks the first got in
+        let forwarded = forwarded.lock().unwrap().clone();
+        assert!(forwarded.len() == 2 && forwarded[1] < data.len() - 262_144, "{forwarded:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_same_offer_again_resumes_from_the_checkpoint() {
+        use globalsend_transfer::folder::Layout;
+        use globalsend_transfer::Checkpoint;
+
+        let dir = std::env::temp_dir().join(format!("gs-resume-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("notes.txt");
+        let data: Vec<u8> = (0..600_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+        let a_identity = Arc::new(DeviceIdentity::generate());
+        let mut config = DaemonConfig::new("b", dir.join("b"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.checkpoints = Some(dir.join("b-checkpoints"));
+        std::fs::create_dir_all(&config.downloads).unwrap();
+        let b = Daemon::start(Arc::new(DeviceIdentity::generate()), config).await.unwrap();
+        tokio::spawn({
+            let b = b.clone();
+            async move { b.listen().await }
+        });
+
+        // an earlier attempt got one chunk in and a torn bit of the next before the link dropped
+        let transfer = TransferId([7; 16]);
+        let files = vec![OfferedFile { name: "notes.txt".into(), size: data.len() as u64, mime: None }];
+        let offer = globalsend_proto::TransferOffer { transfer, files: files.clone() };
+        let target = dir.join("b/notes.txt");
+        let mut checkpoint = Checkpoint::new(&offer, *a_identity.fingerprint().as_bytes());
+        checkpoint.layout = Some(Layout::plan(&dir.join("b"), &offer).unwrap());
+        checkpoint.record_header(0, *blake3::hash(&data).as_bytes());
+        checkpoint.record_chunk(0, 0, &data[..262_144]);
+        checkpoint.record_chunk(0, 262_144, &data[262_144..524_288]);
+        b.shared.checkpoints.as_ref().unwrap().save(&checkpoint).unwrap();
+        let mut partial = data[..300_000].to_vec();
+        partial[290_000] ^= 0xff;
+        std::fs::write(&target, &partial).unwrap();
+
+        // the sender stopped part way too, and left the transfer in its outbox
+        let outbox = Outbox::open(dir.join("a-outbox")).unwrap();
+        outbox.save(&Outgoing { transfer, to: To::Addr(b.local_addr().unwrap()), paths: vec![source], files }).unwrap();
+        let mut config = DaemonConfig::new("a", dir.join("a"));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.discovery = false;
+        config.outbox = Some(dir.join("a-outbox"));
+        let a = Daemon::start(a_identity, config).await.unwrap();
+        // offered again at start and taken without anyone accepting it a second time
+        let state = loop {
+            let info = a.transfers().remove(0);
+            if info.state == "done" || info.state.starts_with("failed") {
+                break info.state;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!((state.as_str(), a.transfers()[0].transfer.as_str()), ("done", "07070707070707070707070707070707"));
+        assert_eq!(std::fs::read(&target).unwrap(), data);
+        // the receiver forgets the checkpoint once it has settled too, and the sender its outbox copy
+        let store = b.shared.checkpoints.as_ref().unwrap();
+        for _ in 0..500 {
+            if store.load(&transfer).unwrap().is_none() && outbox.list().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(store.load(&transfer).unwrap().is_none() && outbox.list().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
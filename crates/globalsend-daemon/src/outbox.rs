@@ -0,0 +1,112 @@
+//! Outgoing transfers that have not ended yet
+//!
+//! Each is saved when it starts and removed once it ends, one JSON file per
+//! transfer. A daemon that stopped part way finds them at its next start
+//! and offers them again under the same [`TransferId`], so a receiver with
+//! a checkpoint picks up where it stopped.
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_proto::{OfferedFile, TransferId};
+use serde::{Deserialize, Serialize};
+
+use crate::rpc::{self, Target};
+
+const EXTENSION: &str = "json";
+
+/// One transfer as [`Daemon::send_named`](crate::Daemon::send_named) started it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Outgoing {
+    pub(crate) transfer: TransferId,
+    pub(crate) to: To,
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) files: Vec<OfferedFile>,
+}
+
+/// A [`Target`] as it is stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum To {
+    /// Fingerprint in hex
+    Device(String),
+    Addr(SocketAddr),
+}
+
+impl From<&Target> for To {
+    fn from(target: &Target) -> Self {
+        match target {
+            Target::Device(fingerprint) => To::Device(fingerprint.to_hex()),
+            Target::Addr(addr) => To::Addr(*addr),
+        }
+    }
+}
+
+impl To {
+    pub(crate) fn target(&self) -> Option<Target> {
+        match self {
+            To::Device(hex) => Fingerprint::from_hex(hex).map(Target::Device),
+            To::Addr(addr) => Some(Target::Addr(*addr)),
+        }
+    }
+}
+
+impl Outgoing {
+    /// Every path is still a file of the size offered
+    pub(crate) fn unchanged(&self) -> bool {
+        self.paths.len() == self.files.len() && self.paths.iter().zip(&self.files).all(|(path, file)| fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() == file.size))
+    }
+}
+
+/// A directory of outgoing transfers, one file each
+#[derive(Debug, Clone)]
+pub(crate) struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    pub(crate) fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, transfer: &TransferId) -> PathBuf {
+        self.dir.join(rpc::transfer_id_hex(transfer)).with_extension(EXTENSION)
+    }
+
+    /// Write `outgoing`, replacing any earlier copy atomically
+    pub(crate) fn save(&self, outgoing: &Outgoing) -> io::Result<()> {
+        let path = self.path(&outgoing.transfer);
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(outgoing)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(crate) fn remove(&self, transfer: &TransferId) -> io::Result<()> {
+        match fs::remove_file(self.path(transfer)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every stored transfer; unreadable files are skipped
+    pub(crate) fn list(&self) -> io::Result<Vec<Outgoing>> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(EXTENSION.as_ref()) {
+                continue;
+            }
+            if let Some(outgoing) = fs::read(&path).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok()) {
+                out.push(outgoing);
+            }
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,242 @@
+//! The control API's JSON-RPC 2.0 schema
+//!
+//! One request or response per line. Transfer ids and fingerprints are
+//! lowercase hex; sizes are bytes. `accept` takes every offered file
+//! unless `files` lists the indices of the ones wanted. `sync` starts a
+//! run for a configured sync folder now instead of at its next change.
+//!
+//! | method      | params                                   | result             |
+//! |-------------|------------------------------------------|--------------------|
+//! | `devices`   | none                                     | `[DeviceInfo]`     |
+//! | `transfers` | none                                     | `[TransferInfo]`   |
+//! | `send`      | `{device \| addr, paths}`                | `{transfer}`       |
+//! | `accept`    | `{transfer, destination?, files?}`       | `null`             |
+//! | `decline`   | `{transfer}`                             | `null`             |
+//! | `cancel`    | `{transfer}`                             | `null`             |
+//! | `sync`      | `{folder}`                               | `null`             |
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_discovery::Device;
+use globalsend_proto::TransferId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// No device or transfer by that id
+pub const NOT_FOUND: i64 = -32000;
+/// The call is valid but the transfer is not in a state that allows it
+pub const WRONG_STATE: i64 = -32001;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Absent for notifications, which get no response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self { jsonrpc: "2.0".into(), id, result, error }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Where `send` goes: a discovered device or an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Device(Fingerprint),
+    Addr(SocketAddr),
+}
+
+/// A parsed request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Devices,
+    Transfers,
+    Send { target: Target, paths: Vec<PathBuf> },
+    /// Into `destination`, or the daemon's download directory
+    /// Only the offered files with the indices in `files`, if given
+    Accept { transfer: TransferId, destination: Option<PathBuf>, files: Option<Vec<u32>> },
+    Decline { transfer: TransferId },
+    Cancel { transfer: TransferId },
+    Sync { folder: String },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendParams {
+    device: Option<String>,
+    addr: Option<SocketAddr>,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransferParams {
+    transfer: String,
+    #[serde(default)]
+    destination: Option<PathBuf>,
+    #[serde(default)]
+    files: Option<Vec<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncParams {
+    folder: String,
+}
+
+impl Call {
+    pub fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        fn params_of<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+            serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+        }
+        let transfer = |params: Value, accepting: bool| -> Result<(TransferId, TransferParams), RpcError> {
+            let params: TransferParams = params_of(params)?;
+            if (params.destination.is_some() || params.files.is_some()) && !accepting {
+                return Err(RpcError::new(INVALID_PARAMS, "only accept takes a destination or files"));
+            }
+            let transfer = parse_transfer_id(&params.transfer).ok_or_else(|| RpcError::new(INVALID_PARAMS, "transfer is not a transfer id"))?;
+            Ok((transfer, params))
+        };
+        Ok(match method {
+            "devices" => Call::Devices,
+            "transfers" => Call::Transfers,
+            "send" => {
+                let params: SendParams = params_of(params)?;
+                let target = match (params.device, params.addr) {
+                    (Some(device), None) => Target::Device(Fingerprint::from_hex(&device).ok_or_else(|| RpcError::new(INVALID_PARAMS, "device is not a fingerprint"))?),
+                    (None, Some(addr)) => Target::Addr(addr),
+                    _ => return Err(RpcError::new(INVALID_PARAMS, "give exactly one of device and addr")),
+                };
+                if params.paths.is_empty() {
+                    return Err(RpcError::new(INVALID_PARAMS, "nothing to send"));
+                }
+                Call::Send { target, paths: params.paths }
+            }
+            "accept" => {
+                let (transfer, params) = transfer(params, true)?;
+                Call::Accept { transfer, destination: params.destination, files: params.files }
+            }
+            "decline" => Call::Decline { transfer: transfer(params, false)?.0 },
+            "cancel" => Call::Cancel { transfer: transfer(params, false)?.0 },
+            "sync" => Call::Sync { folder: params_of::<SyncParams>(params)?.folder },
+            _ => return Err(RpcError::new(METHOD_NOT_FOUND, format!("no method {method:?}"))),
+        })
+    }
+}
+
+/// A device discovery currently sees
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub fingerprint: String,
+    pub alias: String,
+    pub version: u16,
+    pub addrs: Vec<SocketAddr>,
+}
+
+impl From<&Device> for DeviceInfo {
+    fn from(device: &Device) -> Self {
+        Self { fingerprint: device.fingerprint.to_hex(), alias: device.alias.clone(), version: device.version, addrs: device.addrs.clone() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+    pub bytes: u64,
+    /// First lines of an offered text file, checked and cleaned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Base64 PNG of an offered image or PDF, decoded and encoded again by the daemon
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+/// A running or finished transfer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferInfo {
+    pub transfer: String,
+    /// `send` or `receive`
+    pub direction: String,
+    /// The fingerprint in the peer's hello
+    pub peer: String,
+    /// The peer proved `peer` in the handshake; if not, it is only a claim
+    #[serde(default)]
+    pub peer_verified: bool,
+    pub peer_name: String,
+    /// As [`TransferState`](globalsend_transfer::TransferState) displays it
+    pub state: String,
+    /// An incoming offer waiting for `accept` or `decline`
+    pub waiting: bool,
+    pub files: Vec<FileInfo>,
+    pub bytes: u64,
+    pub total: u64,
+}
+
+impl TransferInfo {
+    /// Done, declined, cancelled or failed
+    pub fn finished(&self) -> bool {
+        matches!(self.state.as_str(), "done" | "declined" | "cancelled") || self.state.starts_with("failed")
+    }
+}
+
+pub fn transfer_id_hex(transfer: &TransferId) -> String {
+    transfer.0.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn parse_transfer_id(s: &str) -> Option<TransferId> {
+    let mut id = [0u8; 16];
+    if s.len() != 2 * id.len() || !s.is_ascii() {
+        return None;
+    }
+    for (i, b) in id.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(TransferId(id))
+}
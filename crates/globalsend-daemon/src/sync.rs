@@ -0,0 +1,458 @@
+//! Two-way folder sync with a paired device
+//!
+//! Each [`SyncProfile`] in the config pairs a local folder with a folder id
+//! and the device that holds the other copy; both devices configure the
+//! same id. [`Daemon::keep_in_sync`] starts a sync run for a folder when its
+//! files change (after [`SETTLE`]), every `interval` if it has one, and once
+//! at start; the `sync` RPC starts one by hand.
+//!
+//! A run goes one way (see [`globalsend_transfer::sync`]): this side scans
+//! the folder into the index database, swaps [`SyncIndex`]es with the peer
+//! and offers what it changed. The peer takes the offer without asking,
+//! into a hidden staging folder, then moves each file into place, or next
+//! to its own copy under a conflict name when both sides changed it. Runs
+//! of one folder never overlap; a run that finds the other side busy gives
+//! up until the next one.
+//!
+//! The peer has to prove its fingerprint (see
+//! [`IDENTITY_VERSION`](globalsend_transfer::config::IDENTITY_VERSION)): a
+//! connection from a device that only claims the profile's fingerprint in
+//! its hello gets neither side's files.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use futures_util::SinkExt;
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_proto::{Cancel, CancelReason, IndexedFile, Message, SyncIndex, TransferId};
+use globalsend_store::{IndexEntry, StoreError};
+use globalsend_transfer::config::SYNC_VERSION;
+use globalsend_transfer::folder::Layout;
+use globalsend_transfer::sync::{self, Incoming};
+use globalsend_transfer::{FileStatus, TransferSession, TransferState};
+use globalsend_transport::connect::Connection;
+use notify::RecommendedWatcher;
+use tokio::task::JoinHandle;
+
+use crate::engine::{self, EngineError, Greeted};
+use crate::rpc::{RpcError, Target, NOT_FOUND};
+use crate::watcher::{self, name_under, walk, POLL, SETTLE};
+use crate::{now, Daemon, Shared};
+
+/// Hidden folder in a synced folder where incoming files wait until they are whole
+const STAGING: &str = ".globalsend-sync";
+/// Longest wait before a failed run is tried again
+const RETRY: Duration = Duration::from_secs(30);
+
+/// One folder kept in sync with one device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProfile {
+    /// Id both devices give the folder
+    pub folder: String,
+    pub path: PathBuf,
+    pub peer: Fingerprint,
+    /// Dial this instead of looking the peer up through discovery
+    pub addr: Option<SocketAddr>,
+    /// Run this often even without changes, to pick up the peer's
+    pub interval: Option<Duration>,
+}
+
+#[derive(Debug)]
+enum SyncError {
+    Io(io::Error),
+    Store(StoreError),
+    Engine(EngineError),
+    /// The peer could not be reached
+    Unreachable(String),
+    /// The peer did not prove it is the profile's device, or is too old to sync
+    WrongPeer,
+    /// The peer does not sync this folder with us, or is busy with it
+    Refused,
+    /// A run for the folder is already going
+    Busy,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Io(e) => write!(f, "i/o error: {e}"),
+            SyncError::Store(e) => write!(f, "{e}"),
+            SyncError::Engine(e) => write!(f, "{e}"),
+            SyncError::Unreachable(e) => write!(f, "peer unreachable: {e}"),
+            SyncError::WrongPeer => write!(f, "peer is not the device the folder syncs with, or cannot sync"),
+            SyncError::Refused => write!(f, "peer does not sync this folder now"),
+            SyncError::Busy => write!(f, "a run for this folder is already going"),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<io::Error> for SyncError {
+    fn from(e: io::Error) -> Self {
+        SyncError::Io(e)
+    }
+}
+
+impl From<StoreError> for SyncError {
+    fn from(e: StoreError) -> Self {
+        SyncError::Store(e)
+    }
+}
+
+impl From<EngineError> for SyncError {
+    fn from(e: EngineError) -> Self {
+        SyncError::Engine(e)
+    }
+}
+
+/// Running sync profiles; dropping it stops them
+pub struct Syncing {
+    _watchers: Vec<RecommendedWatcher>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Syncing {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// A folder with a run going; it is free again when dropped
+struct Busy {
+    shared: Arc<Shared>,
+    folder: String,
+}
+
+impl Busy {
+    fn take(shared: &Arc<Shared>, folder: &str) -> Option<Self> {
+        shared.syncing.lock().expect("syncing lock").insert(folder.to_owned()).then(|| Self { shared: shared.clone(), folder: folder.to_owned() })
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.shared.syncing.lock().expect("syncing lock").remove(&self.folder);
+    }
+}
+
+impl Daemon {
+    /// Start every configured sync profile: a run now, on changes and on its interval
+    pub fn keep_in_sync(&self) -> notify::Result<Syncing> {
+        let mut syncing = Syncing { _watchers: Vec::new(), tasks: Vec::new() };
+        for profile in &self.shared.config.sync {
+            std::fs::create_dir_all(&profile.path)?;
+            let (watcher, changes) = watcher::changes(&profile.path)?;
+            syncing._watchers.push(watcher);
+            syncing.tasks.push(tokio::spawn(schedule(self.clone(), profile.clone(), changes)));
+        }
+        Ok(syncing)
+    }
+
+    /// Start a run for the configured `folder` now; it goes on in the background
+    pub fn sync_now(&self, folder: &str) -> Result<(), RpcError> {
+        let profile = self.shared.config.sync.iter().find(|p| p.folder == folder).cloned().ok_or_else(|| RpcError::new(NOT_FOUND, "no such sync folder"))?;
+        let daemon = self.clone();
+        tokio::spawn(async move { daemon.sync_logged(&profile).await });
+        Ok(())
+    }
+
+    /// [`sync_once`](Self::sync_once), logging why it failed; false if it did
+    async fn sync_logged(&self, profile: &SyncProfile) -> bool {
+        match self.sync_once(profile).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::info!(error = %e, folder = profile.folder, "sync run failed");
+                false
+            }
+        }
+    }
+
+    /// One run: offer the peer what changed here since it last had it
+    #[tracing::instrument(name = "sync", skip_all, fields(folder = profile.folder, peer = tracing::field::Empty))]
+    async fn sync_once(&self, profile: &SyncProfile) -> Result<(), SyncError> {
+        let shared = &self.shared;
+        let _busy = Busy::take(shared, &profile.folder).ok_or(SyncError::Busy)?;
+        let started_at = now();
+        let ours = scan(shared.clone(), profile.clone()).await?;
+        let addrs = match profile.addr {
+            Some(addr) => vec![addr],
+            None => shared.addrs(&Target::Device(profile.peer)).unwrap_or_default(),
+        };
+        let mut conn = shared.connect(addrs).await.map_err(SyncError::Unreachable)?;
+        let greeted = engine::greet(shared, &mut conn).await?;
+        if greeted.version < SYNC_VERSION || greeted.proven != Some(profile.peer) {
+            return Err(SyncError::WrongPeer);
+        }
+        conn.control.send(SyncIndex { folder: profile.folder.clone(), files: ours.clone() }.into()).await.map_err(EngineError::from)?;
+        let theirs = match engine::next(&mut conn.control).await? {
+            Message::SyncIndex(index) if index.folder == profile.folder => index.files,
+            Message::Cancel(_) => return Err(SyncError::Refused),
+            _ => return Err(EngineError::Unexpected("expected a sync index").into()),
+        };
+        let (names, sending) = {
+            let mut index = shared.index.lock().expect("index lock");
+            for (name, hash) in sync::agreed(&ours, &theirs) {
+                index.mark_synced(&profile.folder, name, &hash)?;
+            }
+            let names = sync::to_send(&ours, &theirs, |name| index.get(&profile.folder, name).ok().flatten().and_then(|entry| entry.synced));
+            let sending: Vec<IndexedFile> = ours.into_iter().filter(|f| names.contains(&f.name)).collect();
+            (names, sending)
+        };
+        if names.is_empty() {
+            return Ok(());
+        }
+        let named = names.into_iter().map(|name| (profile.path.join(&name), name)).collect();
+        let (transfer, paths, files) = self.register(named).map_err(|e| io::Error::other(e.message))?;
+        if engine::offer(shared, &mut conn, &greeted, started_at, transfer, paths, files).await == Some(TransferState::Done) {
+            let mut index = shared.index.lock().expect("index lock");
+            for file in &sending {
+                index.mark_synced(&profile.folder, &file.name, &file.hash)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `profile` at start, once its folder settles after changes, and on
+/// its interval; a failed run is tried again within [`RETRY`]
+async fn schedule(daemon: Daemon, profile: SyncProfile, mut changes: tokio::sync::mpsc::UnboundedReceiver<PathBuf>) {
+    let mut due = true;
+    let mut last_change = Instant::now();
+    let mut last_run = Instant::now();
+    loop {
+        if (due && last_change.elapsed() >= SETTLE) || profile.interval.is_some_and(|interval| last_run.elapsed() >= interval) {
+            due = !daemon.sync_logged(&profile).await;
+            last_run = Instant::now();
+            if due {
+                // spread out, so two sides that both gave up do not collide again
+                last_change = Instant::now() + RETRY.mul_f64(rand::random());
+            }
+        }
+        tokio::select! {
+            change = changes.recv() => match change {
+                Some(path) if name_under(&profile.path, &path).is_some() => {
+                    due = true;
+                    last_change = Instant::now();
+                }
+                Some(_) => {}
+                None => return,
+            },
+            _ = tokio::time::sleep(POLL) => {}
+        }
+    }
+}
+
+/// Answer a peer's sync run: our index back, then take its offer, if it has one
+pub(crate) async fn respond(shared: Arc<Shared>, mut conn: Connection, greeted: &Greeted, theirs: SyncIndex, started_at: u64) {
+    let profile = shared.config.sync.iter().find(|p| p.folder == theirs.folder && greeted.proven == Some(p.peer)).cloned();
+    let busy = profile.as_ref().and_then(|profile| Busy::take(&shared, &profile.folder));
+    let ours = match (&profile, &busy) {
+        (Some(profile), Some(_)) => scan(shared.clone(), profile.clone()).await.map_err(|e| tracing::info!(error = %e, folder = profile.folder, "sync scan failed")).ok(),
+        _ => None,
+    };
+    let (Some(profile), Some(ours)) = (profile, ours) else {
+        let _ = conn.control.send(Cancel { transfer: TransferId([0; 16]), reason: CancelReason::Declined }.into()).await;
+        return;
+    };
+    if conn.control.send(SyncIndex { folder: profile.folder.clone(), files: ours.clone() }.into()).await.is_err() {
+        return;
+    }
+    {
+        let mut index = shared.index.lock().expect("index lock");
+        for (name, hash) in sync::agreed(&ours, &theirs.files) {
+            let _ = index.mark_synced(&profile.folder, name, &hash);
+        }
+    }
+    let Ok(Message::TransferOffer(offer)) = engine::next(&mut conn.control).await else { return };
+    let staging_root = profile.path.join(STAGING);
+    let staging = staging_root.join(crate::rpc::transfer_id_hex(&offer.transfer));
+    let Some((session, layout)) = engine::take(&shared, &mut conn, greeted, offer, Some(staging.clone())).await else { return };
+    let peer_name = greeted.peer.device_name.clone();
+    let paths = {
+        let shared = shared.clone();
+        let staging = staging.clone();
+        tokio::task::spawn_blocking(move || commit(&shared, &profile, &peer_name, &session, &staging, layout.as_ref()).map(|paths| (session, paths))).await
+    };
+    let _ = std::fs::remove_dir_all(&staging);
+    let _ = std::fs::remove_dir(&staging_root);
+    match paths {
+        Ok(Ok((session, paths))) => {
+            engine::record(&shared, &session, greeted, started_at, &paths);
+            engine::ended(&shared, &session, &paths);
+        }
+        Ok(Err(e)) => tracing::info!(error = %e, "received files not synced"),
+        Err(e) => tracing::info!(error = %e, "received files not synced"),
+    }
+}
+
+/// Move the files `session` received into `profile`'s folder and index them;
+/// where each one went, or `None` for files that did not arrive
+fn commit(shared: &Shared, profile: &SyncProfile, peer_name: &str, session: &TransferSession, staging: &Path, layout: Option<&Layout>) -> Result<Vec<Option<PathBuf>>, SyncError> {
+    let mut paths = Vec::with_capacity(session.files().len());
+    for (index, file) in (0u32..).zip(session.files()) {
+        let (Some(layout), Some(incoming), FileStatus::Done) = (layout, file.hash, file.status) else {
+            paths.push(None);
+            continue;
+        };
+        let staged = layout.path(index);
+        let Some(name) = name_under(staging, staged) else {
+            // hidden files have no place in a synced folder
+            paths.push(None);
+            continue;
+        };
+        let target = profile.path.join(&name);
+        let local = hash(&target).ok();
+        let synced = shared.index.lock().expect("index lock").get(&profile.folder, &name)?.and_then(|entry| entry.synced);
+        let (name, target, synced) = match sync::resolve(incoming, local, synced) {
+            Incoming::Take => (name, target, Some(incoming)),
+            Incoming::Conflict => {
+                let name = sync::conflict_name(&name, peer_name, |candidate| profile.path.join(candidate).exists());
+                tracing::info!(name, "sync conflict; incoming copy kept alongside");
+                (name.clone(), profile.path.join(name), None)
+            }
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(staged, &target)?;
+        let (size, modified) = stamp(&target)?;
+        shared.index.lock().expect("index lock").put(&profile.folder, &IndexEntry { name, size, modified, hash: incoming, synced })?;
+        paths.push(Some(target));
+    }
+    Ok(paths)
+}
+
+/// The folder's files as they are now, hashing only the ones whose size or
+/// modification time moved since the index last saw them; files gone from
+/// the folder leave the index
+async fn scan(shared: Arc<Shared>, profile: SyncProfile) -> Result<Vec<IndexedFile>, SyncError> {
+    tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        let mut seen = BTreeSet::new();
+        for path in walk(&profile.path) {
+            let Some(name) = name_under(&profile.path, &path) else { continue };
+            let Ok((size, modified)) = stamp(&path) else { continue };
+            let known = shared.index.lock().expect("index lock").get(&profile.folder, &name)?;
+            let hash = match known {
+                Some(entry) if (entry.size, entry.modified) == (size, modified) => entry.hash,
+                known => {
+                    // gone or unreadable since the walk: next scan
+                    let Ok(hash) = hash(&path) else { continue };
+                    let synced = known.and_then(|entry| entry.synced);
+                    shared.index.lock().expect("index lock").put(&profile.folder, &IndexEntry { name: name.clone(), size, modified, hash, synced })?;
+                    hash
+                }
+            };
+            seen.insert(name.clone());
+            files.push(IndexedFile { name, size, modified, hash });
+        }
+        let mut index = shared.index.lock().expect("index lock");
+        for entry in index.entries(&profile.folder)? {
+            if !seen.contains(&entry.name) {
+                index.remove(&profile.folder, &entry.name)?;
+            }
+        }
+        Ok(files)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Size and modification time in seconds of a regular file
+fn stamp(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_file() {
+        return Err(io::Error::other(format!("{} is not a file", path.display())));
+    }
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    Ok((metadata.len(), modified))
+}
+
+fn hash(path: &Path) -> io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(path)?)?;
+    Ok(*hasher.finalize().as_bytes())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::DaemonConfig;
+    use globalsend_crypto::identity::DeviceIdentity;
+
+    async fn daemon(name: &str, dir: &Path, identity: Arc<DeviceIdentity>, peer: Fingerprint) -> (Daemon, SyncProfile) {
+        let mut config = DaemonConfig::new(name, dir.join(format!("{name}-downloads")));
+        config.listen = "127.0.0.1:0".parse().unwrap();
+        config.socket = dir.join(format!("{name}.sock"));
+        config.discovery = false;
+        config.index = Some(dir.join(format!("{name}.db")));
+        let profile = SyncProfile { folder: "docs".into(), path: dir.join(name), peer, addr: None, interval: None };
+        config.sync = vec![profile.clone()];
+        std::fs::create_dir_all(&profile.path).unwrap();
+        let daemon = Daemon::start(identity, config).await.unwrap();
+        tokio::spawn({
+            let daemon = daemon.clone();
+            async move { daemon.listen().await }
+        });
+        (daemon, profile)
+    }
+
+    /// Run `from`'s side of the folder against `to` and wait for `to` to settle it
+    async fn run(from: &Daemon, profile: &SyncProfile, to: &Daemon) {
+        let profile = SyncProfile { addr: Some(to.local_addr().unwrap()), ..profile.clone() };
+        from.sync_once(&profile).await.unwrap();
+        while !to.shared.syncing.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn syncs_both_ways_and_keeps_both_sides_of_a_conflict() {
+        let dir = std::env::temp_dir().join(format!("gs-sync-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (a_id, b_id) = (Arc::new(DeviceIdentity::generate()), Arc::new(DeviceIdentity::generate()));
+        let (a, a_profile) = daemon("a", &dir, a_id.clone(), b_id.fingerprint()).await;
+        let (b, b_profile) = daemon("b", &dir, b_id, a_id.fingerprint()).await;
+        let (a_dir, b_dir) = (a_profile.path.clone(), b_profile.path.clone());
+        std::fs::create_dir_all(a_dir.join("notes")).unwrap();
+        std::fs::write(a_dir.join("notes/a.txt"), b"from a").unwrap();
+        std::fs::write(a_dir.join("same.txt"), b"same").unwrap();
+        std::fs::write(b_dir.join("same.txt"), b"same").unwrap();
+        std::fs::write(b_dir.join("c.txt"), b"from b").unwrap();
+
+        run(&a, &a_profile, &b).await;
+        assert_eq!(std::fs::read(b_dir.join("notes/a.txt")).unwrap(), b"from a");
+        assert!(!a_dir.join("c.txt").exists());
+        run(&b, &b_profile, &a).await;
+        assert_eq!(std::fs::read(a_dir.join("c.txt")).unwrap(), b"from b");
+        assert!(!b_dir.join(STAGING).exists());
+
+        // both change the same file: b keeps its own and a's next to it, then a gets both
+        std::fs::write(a_dir.join("notes/a.txt"), b"a again").unwrap();
+        std::fs::write(b_dir.join("notes/a.txt"), b"b's edit of a").unwrap();
+        run(&a, &a_profile, &b).await;
+        assert_eq!(std::fs::read(b_dir.join("notes/a.txt")).unwrap(), b"b's edit of a");
+        assert_eq!(std::fs::read(b_dir.join("notes/a (conflict from a).txt")).unwrap(), b"a again");
+        run(&b, &b_profile, &a).await;
+        assert_eq!(std::fs::read(a_dir.join("notes/a.txt")).unwrap(), b"b's edit of a");
+        assert_eq!(std::fs::read(a_dir.join("notes/a (conflict from a).txt")).unwrap(), b"a again");
+
+        // settled: neither side has anything left to send
+        let (ours, theirs) = (scan(a.shared.clone(), a_profile.clone()).await.unwrap(), scan(b.shared.clone(), b_profile.clone()).await.unwrap());
+        assert_eq!(ours.len(), 4);
+        fn synced(daemon: &Daemon) -> impl Fn(&str) -> Option<[u8; 32]> + '_ {
+            |name| daemon.shared.index.lock().unwrap().get("docs", name).unwrap().and_then(|e| e.synced)
+        }
+        assert!(sync::to_send(&ours, &theirs, synced(&a)).is_empty());
+        assert!(sync::to_send(&theirs, &ours, synced(&b)).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
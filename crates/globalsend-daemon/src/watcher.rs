@@ -0,0 +1,211 @@
+//! Sending files again when they change
+//!
+//! [`Daemon::watch`] keeps an eye on a file or folder (through `notify`)
+//! and offers whatever changed to one device: a one-way sync, with the
+//! receiver still answering each offer, by hand or with its accept rules.
+//! Changes collect until the path has been quiet for [`SETTLE`], so a save
+//! or a copy in progress goes as one offer once it is whole, and only one
+//! offer is out at a time; what changes meanwhile goes in the next.
+//!
+//! Files go under their path in the watched folder, e.g. `notes/a.txt`,
+//! and only if their size or modification time moved since they last went
+//! through: an offer that fails goes out again, one that is turned down
+//! waits for the next change. Hidden files (a name starting with `.`),
+//! symlinks and deletions are left alone. Receivers that send block signatures for their older copy get
+//! only the changed blocks (see [`delta`](globalsend_transfer::delta)).
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use globalsend_proto::TransferId;
+use globalsend_transfer::{Failure, TransferState};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::rpc::Target;
+use crate::Daemon;
+
+/// How long a path has to stay quiet before its changes go out
+pub const SETTLE: Duration = Duration::from_millis(500);
+pub(crate) const POLL: Duration = Duration::from_millis(100);
+
+/// Size and modification time, what decides whether a file changed
+type Stamp = (u64, Option<SystemTime>);
+
+/// A running watch; dropping it stops watching
+pub struct Watch {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Daemon {
+    /// Offer `path`, a file or everything under a folder, to `target` again
+    /// whenever it changes; what is there now counts as already sent
+    pub fn watch(&self, target: Target, path: PathBuf) -> notify::Result<Watch> {
+        let root = path.canonicalize()?;
+        let (watcher, changes) = changes(&root)?;
+        let offered = walk(&root).into_iter().filter_map(|path| stamp(&path).map(|stamp| (path, stamp))).collect();
+        let task = tokio::spawn(run(self.clone(), target, root, offered, changes));
+        Ok(Watch { _watcher: watcher, task })
+    }
+}
+
+/// Paths created or modified under `root`, for as long as the watcher lives
+pub(crate) fn changes(root: &Path) -> notify::Result<(RecommendedWatcher, mpsc::UnboundedReceiver<PathBuf>)> {
+    let (tx, changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            _ => {}
+        }
+    })?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    Ok((watcher, changes))
+}
+
+async fn run(daemon: Daemon, target: Target, root: PathBuf, mut offered: HashMap<PathBuf, Stamp>, mut changes: mpsc::UnboundedReceiver<PathBuf>) {
+    let mut pending = BTreeSet::new();
+    let mut last_change = Instant::now();
+    // the offer out, with the stamps its files get once it is done
+    let mut current: Option<(TransferId, Vec<(PathBuf, Stamp)>)> = None;
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Some(path) => {
+                    pending.insert(path);
+                    last_change = Instant::now();
+                }
+                None => return,
+            },
+            _ = tokio::time::sleep(POLL) => {}
+        }
+        if let Some((transfer, stamps)) = &current {
+            match outcome(&daemon, transfer) {
+                None => continue,
+                Some(TransferState::Done) => offered.extend(stamps.iter().cloned()),
+                // turned down or stopped by hand: offered again only once they change again
+                Some(TransferState::Cancelled(_)) => {}
+                Some(_) => pending.extend(stamps.iter().map(|(path, _)| path.clone())),
+            }
+            current = None;
+        }
+        if pending.is_empty() || last_change.elapsed() < SETTLE {
+            continue;
+        }
+        let files = changed(&root, &offered, std::mem::take(&mut pending));
+        if files.is_empty() {
+            continue;
+        }
+        let (named, stamps) = files.into_iter().map(|(path, name, stamp)| ((path.clone(), name), (path, stamp))).unzip();
+        match daemon.send_named(target.clone(), named) {
+            Ok(transfer) => current = Some((transfer, stamps)),
+            Err(e) => tracing::info!(error = %e, root = %root.display(), "changes not offered"),
+        }
+    }
+}
+
+/// How `transfer` ended, `None` while it has not; failed if it never got a session
+fn outcome(daemon: &Daemon, transfer: &TransferId) -> Option<TransferState> {
+    let ended = |entry: &mut crate::Entry| entry.finished().then(|| entry.progress.as_ref().map_or(TransferState::Failed(Failure::Timeout), |p| p.borrow().state));
+    daemon.shared.update(transfer, ended).unwrap_or(Some(TransferState::Failed(Failure::Timeout)))
+}
+
+/// The files among `paths` that changed since `offered`, with the names
+/// they go under and their new stamps
+fn changed(root: &Path, offered: &HashMap<PathBuf, Stamp>, paths: BTreeSet<PathBuf>) -> Vec<(PathBuf, String, Stamp)> {
+    let mut files = Vec::new();
+    for path in paths.into_iter().flat_map(|path| if is_dir(&path) { walk(&path) } else { vec![path] }) {
+        let (Some(name), Some(stamp)) = (name_under(root, &path), stamp(&path)) else { continue };
+        if offered.get(&path) != Some(&stamp) {
+            files.push((path, name, stamp));
+        }
+    }
+    files
+}
+
+/// Name a file goes under: its path below `root` with `/` between the parts,
+/// or its own name when `root` is the file; `None` for hidden files
+pub(crate) fn name_under(root: &Path, path: &Path) -> Option<String> {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => Path::new(path.file_name()?),
+        Ok(relative) => relative,
+        Err(_) => return None,
+    };
+    let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+    if parts.iter().any(|part| part.starts_with('.')) {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Regular files only
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = std::fs::symlink_metadata(path).ok().filter(|m| m.is_file())?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// A directory itself, not a symlink to one
+fn is_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+/// Every file under `dir`, or `dir` itself if it is a file; symlinks below
+/// `dir` are skipped rather than followed
+pub(crate) fn walk(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return vec![dir.to_owned()] };
+    entries
+        .flatten()
+        .flat_map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => walk(&entry.path()),
+            Ok(kind) if kind.is_file() => vec![entry.path()],
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_only_what_changed() {
+        let root = std::env::temp_dir().join(format!("gs-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub/.git")).unwrap();
+        std::fs::write(root.join("a.txt"), b"one").unwrap();
+        std::fs::write(root.join("sub/b.txt"), b"two").unwrap();
+        std::fs::write(root.join("sub/.git/HEAD"), b"ref").unwrap();
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+
+        let mut offered = HashMap::new();
+        let mut files = changed(&root, &offered, BTreeSet::from([root.clone()]));
+        files.sort();
+        let names: Vec<_> = files.iter().map(|(path, name, _)| (path.clone(), name.clone())).collect();
+        assert_eq!(names, [(root.join("a.txt"), "a.txt".to_owned()), (root.join("sub/b.txt"), "sub/b.txt".to_owned())]);
+        // nothing counts as offered until its transfer is done
+        assert_eq!(changed(&root, &offered, BTreeSet::from([root.join("a.txt")])).len(), 1);
+        offered.extend(files.into_iter().map(|(path, _, stamp)| (path, stamp)));
+        assert!(changed(&root, &offered, BTreeSet::from([root.join("a.txt"), root.join("sub"), root.join("sub/loop")])).is_empty());
+
+        std::fs::write(root.join("a.txt"), b"one more").unwrap();
+        let files = changed(&root, &offered, BTreeSet::from([root.join("a.txt")]));
+        assert_eq!(files.iter().map(|(path, name, _)| (path.clone(), name.clone())).collect::<Vec<_>>(), [(root.join("a.txt"), "a.txt".to_owned())]);
+        let single = root.join("sub/b.txt");
+        assert_eq!(name_under(&single, &single).as_deref(), Some("b.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+//! Bluetooth Low Energy presence and wake-up
+//!
+//! For phone-to-laptop sends where multicast never gets through, or where the
+//! receiver has put its Wi-Fi listener to sleep. Devices advertise service
+//! data under [`SERVICE_UUID`]:
+//!
+//! ```text
+//! version u8 || flags u8 || fingerprint prefix (8 bytes)
+//! ```
+//!
+//! ten bytes, so it fits a legacy scan response next to the 128-bit UUID.
+//! [`FLAG_LISTENING`] says whether the Wi-Fi listener is up. A sender that
+//! sees a sleeping peer it trusts writes a [`WakeRequest`] to
+//! [`WAKE_CHARACTERISTIC`]; the peer reports it as
+//! [`BleEvent::WakeRequested`] (at most once per [`WAKE_COOLDOWN`] per
+//! requester) and is expected to start listening, after which [`mdns`] or
+//! [`multicast`] find it as usual.
+//!
+//! Eight fingerprint bytes identify a device among those nearby but do not
+//! authenticate it; only [`BleAdvert::matches`] against a pinned fingerprint
+//! should be used to decide whether to wake someone.
+//!
+//! The radio is behind [`BleRadio`] so that CoreBluetooth, BlueZ and the
+//! Android stack (through the FFI layer) can each provide it.
+//!
+//! [`mdns`]: crate::mdns
+//! [`multicast`]: crate::multicast
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use globalsend_crypto::identity::Fingerprint;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// 128-bit service UUID, as advertised (big-endian)
+pub const SERVICE_UUID: [u8; 16] = [
+    0x6a, 0x0e, 0x3c, 0x52, 0x4b, 0x5f, 0x4d, 0x8e, 0x9c, 0x1f, 0x67, 0xa7, 0xb5, 0xe1, 0xd1, 0xa0,
+];
+/// Writable characteristic under [`SERVICE_UUID`] that takes a [`WakeRequest`]
+pub const WAKE_CHARACTERISTIC: [u8; 16] = [
+    0x6a, 0x0e, 0x3c, 0x53, 0x4b, 0x5f, 0x4d, 0x8e, 0x9c, 0x1f, 0x67, 0xa7, 0xb5, 0xe1, 0xd1, 0xa0,
+];
+pub const ADVERT_VERSION: u8 = 1;
+pub const FLAG_LISTENING: u8 = 0x01;
+pub const PREFIX_LEN: usize = 8;
+pub const ADVERT_LEN: usize = 2 + PREFIX_LEN;
+/// Minimum time between two wake-ups reported for the same requester
+pub const WAKE_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum BleError {
+    /// Bluetooth is off, missing or not permitted
+    Unavailable,
+    /// The peer went away or refused the write
+    Unreachable,
+    Radio(String),
+}
+
+impl fmt::Display for BleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BleError::Unavailable => write!(f, "bluetooth unavailable"),
+            BleError::Unreachable => write!(f, "bluetooth peer unreachable"),
+            BleError::Radio(e) => write!(f, "bluetooth: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BleError {}
+
+/// Platform handle a remote radio is known by until its fingerprint is confirmed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerId(pub String);
+
+/// What a [`BleRadio`] reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RadioEvent {
+    /// Service data for [`SERVICE_UUID`] seen in an advertisement
+    Advert { peer: PeerId, data: Vec<u8>, rssi: Option<i16> },
+    /// Someone wrote to [`WAKE_CHARACTERISTIC`]
+    WakeWrite { data: Vec<u8> },
+}
+
+/// Platform Bluetooth: advertising, scanning and the wake characteristic
+pub trait BleRadio: Send + Sync + 'static {
+    /// Advertise `data` as [`SERVICE_UUID`] service data, replacing the previous advert
+    fn advertise(&self, data: &[u8]) -> Result<(), BleError>;
+    /// Start scanning and serving [`WAKE_CHARACTERISTIC`]; events go to the receiver
+    fn start(&self) -> Result<mpsc::Receiver<RadioEvent>, BleError>;
+    /// Connect to `peer` and write `data` to its [`WAKE_CHARACTERISTIC`]
+    fn write_wake(&self, peer: &PeerId, data: &[u8]) -> impl Future<Output = Result<(), BleError>> + Send;
+}
+
+/// Decoded service data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BleAdvert {
+    pub prefix: [u8; PREFIX_LEN],
+    /// Wi-Fi listener is up; no need to wake it
+    pub listening: bool,
+}
+
+impl BleAdvert {
+    pub fn new(fingerprint: &Fingerprint, listening: bool) -> Self {
+        Self { prefix: prefix(fingerprint), listening }
+    }
+
+    pub fn to_bytes(&self) -> [u8; ADVERT_LEN] {
+        let mut out = [0u8; ADVERT_LEN];
+        out[0] = ADVERT_VERSION;
+        out[1] = if self.listening { FLAG_LISTENING } else { 0 };
+        out[2..].copy_from_slice(&self.prefix);
+        out
+    }
+
+    /// `None` for other versions or lengths; unknown flags are ignored
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != ADVERT_LEN || data[0] != ADVERT_VERSION {
+            return None;
+        }
+        Some(Self { prefix: data[2..].try_into().ok()?, listening: data[1] & FLAG_LISTENING != 0 })
+    }
+
+    /// Whether this could be the device with `fingerprint`
+    pub fn matches(&self, fingerprint: &Fingerprint) -> bool {
+        self.prefix == prefix(fingerprint)
+    }
+}
+
+/// Body of a wake-up write: the requester's fingerprint prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakeRequest {
+    pub from: [u8; PREFIX_LEN],
+}
+
+impl WakeRequest {
+    pub fn to_bytes(&self) -> [u8; 1 + PREFIX_LEN] {
+        let mut out = [0u8; 1 + PREFIX_LEN];
+        out[0] = ADVERT_VERSION;
+        out[1..].copy_from_slice(&self.from);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != 1 + PREFIX_LEN || data[0] != ADVERT_VERSION {
+            return None;
+        }
+        Some(Self { from: data[1..].try_into().ok()? })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BleEvent {
+    /// A globalsend device is in radio range
+    Nearby { peer: PeerId, advert: BleAdvert, rssi: Option<i16> },
+    /// A nearby device asked us to bring up the Wi-Fi listener
+    WakeRequested { from: [u8; PREFIX_LEN] },
+}
+
+/// Advertises this device over BLE, reports nearby ones and wakes sleeping peers; stops when dropped
+pub struct BleDiscovery<R: BleRadio> {
+    radio: Arc<R>,
+    fingerprint: Fingerprint,
+    listening: AtomicBool,
+    task: JoinHandle<()>,
+}
+
+impl<R: BleRadio> BleDiscovery<R> {
+    /// Start advertising as `fingerprint` and scanning; must be called inside a tokio runtime
+    pub fn start(radio: R, fingerprint: &Fingerprint, listening: bool) -> Result<(Self, mpsc::Receiver<BleEvent>), BleError> {
+        radio.advertise(&BleAdvert::new(fingerprint, listening).to_bytes())?;
+        let mut incoming = radio.start()?;
+        let (tx, events) = mpsc::channel(crate::EVENT_BUFFER);
+        let ours = prefix(fingerprint);
+        let task = tokio::spawn(async move {
+            let mut last_wake: HashMap<[u8; PREFIX_LEN], Instant> = HashMap::new();
+            while let Some(event) = incoming.recv().await {
+                let event = match event {
+                    RadioEvent::Advert { peer, data, rssi } => match BleAdvert::from_bytes(&data) {
+                        Some(advert) if advert.prefix != ours => BleEvent::Nearby { peer, advert, rssi },
+                        _ => continue,
+                    },
+                    RadioEvent::WakeWrite { data } => {
+                        let Some(request) = WakeRequest::from_bytes(&data) else { continue };
+                        if last_wake.get(&request.from).is_some_and(|t| t.elapsed() < WAKE_COOLDOWN) {
+                            continue;
+                        }
+                        last_wake.retain(|_, t| t.elapsed() < WAKE_COOLDOWN);
+                        last_wake.insert(request.from, Instant::now());
+                        BleEvent::WakeRequested { from: request.from }
+                    }
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let discovery = Self { radio: Arc::new(radio), fingerprint: *fingerprint, listening: AtomicBool::new(listening), task };
+        Ok((discovery, events))
+    }
+
+    /// Update the advertised listener state
+    pub fn set_listening(&self, listening: bool) -> Result<(), BleError> {
+        if self.listening.swap(listening, Ordering::Relaxed) != listening {
+            self.radio.advertise(&BleAdvert::new(&self.fingerprint, listening).to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Ask `peer` to bring up its Wi-Fi listener
+    pub async fn wake(&self, peer: &PeerId) -> Result<(), BleError> {
+        self.radio.write_wake(peer, &WakeRequest { from: prefix(&self.fingerprint) }.to_bytes()).await
+    }
+}
+
+impl<R: BleRadio> Drop for BleDiscovery<R> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn prefix(fingerprint: &Fingerprint) -> [u8; PREFIX_LEN] {
+    let mut out = [0u8; PREFIX_LEN];
+    out.copy_from_slice(&fingerprint.as_bytes()[..PREFIX_LEN]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Two radios in range of each other
+    struct MockRadio {
+        name: &'static str,
+        advert: Arc<Mutex<Vec<u8>>>,
+        inbox: Mutex<Option<mpsc::Receiver<RadioEvent>>>,
+        peer: mpsc::Sender<RadioEvent>,
+    }
+
+    fn pair() -> (MockRadio, MockRadio, Arc<Mutex<Vec<u8>>>) {
+        let ((tx_a, rx_a), (tx_b, rx_b)) = (mpsc::channel(8), mpsc::channel(8));
+        let advert_b = Arc::new(Mutex::new(Vec::new()));
+        let a = MockRadio { name: "a", advert: Arc::default(), inbox: Mutex::new(Some(rx_a)), peer: tx_b };
+        let b = MockRadio { name: "b", advert: advert_b.clone(), inbox: Mutex::new(Some(rx_b)), peer: tx_a };
+        (a, b, advert_b)
+    }
+
+    impl BleRadio for MockRadio {
+        fn advertise(&self, data: &[u8]) -> Result<(), BleError> {
+            *self.advert.lock().unwrap() = data.to_vec();
+            let event = RadioEvent::Advert { peer: PeerId(self.name.into()), data: data.to_vec(), rssi: Some(-50) };
+            self.peer.try_send(event).map_err(|e| BleError::Radio(e.to_string()))
+        }
+
+        fn start(&self) -> Result<mpsc::Receiver<RadioEvent>, BleError> {
+            self.inbox.lock().unwrap().take().ok_or(BleError::Unavailable)
+        }
+
+        async fn write_wake(&self, _peer: &PeerId, data: &[u8]) -> Result<(), BleError> {
+            self.peer.send(RadioEvent::WakeWrite { data: data.to_vec() }).await.map_err(|_| BleError::Unreachable)
+        }
+    }
+
+    #[tokio::test]
+    async fn wakes_sleeping_peer() {
+        let (phone_fp, laptop_fp) = (Fingerprint::from_bytes([1; 32]), Fingerprint::from_bytes([2; 32]));
+        let (phone_radio, laptop_radio, laptop_advert) = pair();
+        let (laptop, mut laptop_events) = BleDiscovery::start(laptop_radio, &laptop_fp, false).unwrap();
+        let (phone, mut phone_events) = BleDiscovery::start(phone_radio, &phone_fp, true).unwrap();
+
+        let Some(BleEvent::Nearby { peer, advert, .. }) = phone_events.recv().await else { panic!("laptop not seen") };
+        assert!(advert.matches(&laptop_fp) && !advert.listening);
+
+        phone.wake(&peer).await.unwrap();
+        phone.wake(&peer).await.unwrap();
+        assert!(matches!(laptop_events.recv().await, Some(BleEvent::Nearby { .. })));
+        assert_eq!(laptop_events.recv().await, Some(BleEvent::WakeRequested { from: [1; PREFIX_LEN] }));
+        laptop.set_listening(true).unwrap();
+        assert!(BleAdvert::from_bytes(&laptop_advert.lock().unwrap()).unwrap().listening);
+        // the second write fell inside the cooldown
+        assert!(laptop_events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Finding globalsend devices on the local network
+//!
+//! [`mdns`] advertises this device as `_globalsend._tcp.local` and browses
+//! for others; [`multicast`] sends signed announcements to a fixed multicast
+//! group for networks that filter mDNS. Either way results arrive the same
+//! way: a [`DiscoveryEvent`] stream for reacting to changes, and a watchable
+//! snapshot of every [`Device`] currently visible.
+//!
+//! [`ble`] is different: it only says a device is in radio range, by
+//! fingerprint prefix, and can wake its Wi-Fi listener so the other two
+//! backends see it.
+//!
+//! Discovery is a hint, not authentication: a TXT record or datagram can
+//! claim any fingerprint, and the handshake is what proves it.
+
+pub mod ble;
+pub mod mdns;
+pub mod multicast;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use globalsend_crypto::identity::Fingerprint;
+use tokio::sync::{broadcast, watch};
+
+pub use crate::ble::{BleDiscovery, BleEvent, BleRadio};
+pub use crate::mdns::MdnsDiscovery;
+pub use crate::multicast::{MulticastConfig, MulticastDiscovery};
+
+/// Capacity of each event subscription before slow readers start lagging
+pub const EVENT_BUFFER: usize = 64;
+
+/// A device seen on the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub fingerprint: Fingerprint,
+    pub alias: String,
+    /// Highest protocol version it speaks
+    pub version: u16,
+    /// Where its transport listens
+    pub addrs: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// New, or known with a changed alias or address
+    DeviceDiscovered(Device),
+    DeviceLost(Fingerprint),
+}
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    Io(io::Error),
+    Mdns(mdns_sd::Error),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Io(e) => write!(f, "i/o error: {e}"),
+            DiscoveryError::Mdns(e) => write!(f, "mdns: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl From<io::Error> for DiscoveryError {
+    fn from(e: io::Error) -> Self {
+        DiscoveryError::Io(e)
+    }
+}
+
+impl From<mdns_sd::Error> for DiscoveryError {
+    fn from(e: mdns_sd::Error) -> Self {
+        DiscoveryError::Mdns(e)
+    }
+}
+
+/// What this device advertises about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalDevice {
+    pub fingerprint: Fingerprint,
+    pub alias: String,
+    /// Port the transport listens on
+    pub port: u16,
+}
+
+/// Longest prefix of `s` within `max` bytes that ends on a character boundary
+pub(crate) fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Visible devices by fingerprint, with change notification
+///
+/// Backends feed it; it drops repeats and keeps the snapshot and the event
+/// stream in step.
+pub(crate) struct Registry {
+    devices: watch::Sender<BTreeMap<Fingerprint, Device>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+}
+
+impl Registry {
+    pub(crate) fn new() -> Self {
+        Self { devices: watch::Sender::new(BTreeMap::new()), events: broadcast::Sender::new(EVENT_BUFFER) }
+    }
+
+    pub(crate) fn found(&self, device: Device) {
+        let changed = self.devices.send_if_modified(|devices| {
+            if devices.get(&device.fingerprint) == Some(&device) {
+                return false;
+            }
+            devices.insert(device.fingerprint, device.clone());
+            true
+        });
+        if changed {
+            let _ = self.events.send(DiscoveryEvent::DeviceDiscovered(device));
+        }
+    }
+
+    pub(crate) fn lost(&self, fingerprint: &Fingerprint) {
+        if self.devices.send_if_modified(|devices| devices.remove(fingerprint).is_some()) {
+            let _ = self.events.send(DiscoveryEvent::DeviceLost(*fingerprint));
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn watch(&self) -> watch::Receiver<BTreeMap<Fingerprint, Device>> {
+        self.devices.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_reports_changes_only() {
+        let registry = Registry::new();
+        let mut events = registry.subscribe();
+        let devices = registry.watch();
+        let device = Device { fingerprint: Fingerprint::from_bytes([1; 32]), alias: "laptop".into(), version: 1, addrs: vec![] };
+
+        registry.found(device.clone());
+        registry.found(device.clone());
+        registry.lost(&device.fingerprint);
+        registry.lost(&device.fingerprint);
+        assert_eq!(events.try_recv().unwrap(), DiscoveryEvent::DeviceDiscovered(device.clone()));
+        assert_eq!(events.try_recv().unwrap(), DiscoveryEvent::DeviceLost(device.fingerprint));
+        assert!(events.try_recv().is_err());
+        assert!(devices.borrow().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+//! mDNS / DNS-SD backend
+//!
+//! Registers `<fingerprint prefix>._globalsend._tcp.local` on every
+//! interface with TXT records:
+//!
+//! - `fp`: full fingerprint, lowercase hex
+//! - `alias`: device name, cut to [`MAX_ALIAS_LEN`] bytes
+//! - `v`: highest protocol version spoken
+//!
+//! and browses the same type. Records without a valid `fp` are ignored, as
+//! is our own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use globalsend_crypto::announce::MAX_ALIAS_LEN;
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_proto::PROTOCOL_VERSION;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+use crate::{truncate, Device, DiscoveryError, DiscoveryEvent, LocalDevice, Registry};
+
+pub const SERVICE_TYPE: &str = "_globalsend._tcp.local.";
+/// Hex digits of the fingerprint used as the instance name
+const INSTANCE_LEN: usize = 16;
+
+/// Advertises this device and tracks others over mDNS; stops when dropped
+pub struct MdnsDiscovery {
+    daemon: ServiceDaemon,
+    registry: Arc<Registry>,
+    task: JoinHandle<()>,
+}
+
+impl MdnsDiscovery {
+    /// Register `local` and start browsing; must be called inside a tokio runtime
+    pub fn start(local: &LocalDevice) -> Result<Self, DiscoveryError> {
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(service_info(local)?)?;
+        Self::browsing(daemon, local.fingerprint)
+    }
+
+    /// Track others without advertising; `ours` is still left out
+    pub fn browse(ours: &Fingerprint) -> Result<Self, DiscoveryError> {
+        Self::browsing(ServiceDaemon::new()?, *ours)
+    }
+
+    fn browsing(daemon: ServiceDaemon, ours: Fingerprint) -> Result<Self, DiscoveryError> {
+        let browse = daemon.browse(SERVICE_TYPE)?;
+        let registry = Arc::new(Registry::new());
+
+        let feed = registry.clone();
+        let task = tokio::spawn(async move {
+            // removals only carry the instance name
+            let mut names = HashMap::new();
+            while let Ok(event) = browse.recv_async().await {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        if let Some(device) = device(&info).filter(|d| d.fingerprint != ours) {
+                            tracing::debug!(alias = %device.alias, fingerprint = %device.fingerprint, addrs = ?device.addrs, "device found");
+                            names.insert(info.get_fullname().to_owned(), device.fingerprint);
+                            feed.found(device);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        if let Some(fingerprint) = names.remove(&fullname) {
+                            tracing::debug!(%fingerprint, "device gone");
+                            feed.lost(&fingerprint);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Ok(Self { daemon, registry, task })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.registry.subscribe()
+    }
+
+    /// Every device currently visible
+    pub fn devices(&self) -> watch::Receiver<BTreeMap<Fingerprint, Device>> {
+        self.registry.watch()
+    }
+}
+
+impl Drop for MdnsDiscovery {
+    fn drop(&mut self) {
+        self.task.abort();
+        // unregisters our record on the way out
+        let _ = self.daemon.shutdown();
+    }
+}
+
+fn service_info(local: &LocalDevice) -> Result<ServiceInfo, DiscoveryError> {
+    let fp = local.fingerprint.to_hex();
+    let instance = &fp[..INSTANCE_LEN];
+    let version = PROTOCOL_VERSION.to_string();
+    let properties = [("fp", fp.as_str()), ("alias", truncate(&local.alias, MAX_ALIAS_LEN)), ("v", version.as_str())];
+    let info = ServiceInfo::new(SERVICE_TYPE, instance, &format!("{instance}.local."), "", local.port, &properties[..])?;
+    Ok(info.enable_addr_auto())
+}
+
+fn device(info: &ServiceInfo) -> Option<Device> {
+    let fingerprint = Fingerprint::from_hex(info.get_property_val_str("fp")?)?;
+    let mut addrs: Vec<_> = info.get_addresses().iter().map(|ip| SocketAddr::new(*ip, info.get_port())).collect();
+    addrs.sort();
+    Some(Device {
+        fingerprint,
+        alias: info.get_property_val_str("alias").map(|a| truncate(a, MAX_ALIAS_LEN).to_owned()).unwrap_or_default(),
+        version: info.get_property_val_str("v").and_then(|v| v.parse().ok()).unwrap_or(1),
+        addrs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn txt_records_roundtrip() {
+        let fingerprint = Fingerprint::from_bytes([0xab; 32]);
+        let local = LocalDevice { fingerprint, alias: "ü".repeat(40), port: 4242 };
+        let info = service_info(&local).unwrap();
+        assert_eq!(info.get_fullname(), format!("abababababababab.{SERVICE_TYPE}"));
+
+        let device = device(&info).unwrap();
+        assert_eq!(device.fingerprint, fingerprint);
+        assert_eq!(device.version, PROTOCOL_VERSION);
+        // cut on a character boundary
+        assert_eq!(device.alias, "ü".repeat(32));
+    }
+}
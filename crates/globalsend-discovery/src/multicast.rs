@@ -0,0 +1,282 @@
+//! UDP multicast fallback for networks that filter mDNS
+//!
+//! Every datagram goes to [`MULTICAST_GROUP`]:[`MULTICAST_PORT`] and is
+//!
+//! ```text
+//! kind u8 || protocol version u16 BE || signed Announcement (CBOR)
+//! ```
+//!
+//! where `kind` is [`KIND_ANNOUNCE`] (asks everyone to answer) or
+//! [`KIND_REPLY`]. Announcements are verified and replay-checked with
+//! [`AnnouncementTracker`]; the unsigned header only decides whether we
+//! answer, so tampering with it buys an attacker at most one extra reply.
+//!
+//! As in LocalSend there are two modes. [`Mode::Active`] announces at
+//! jittered intervals around [`MulticastConfig::interval`]; [`Mode::Passive`]
+//! stays quiet and only answers other devices' announcements. Either way
+//! answers are delayed by a random fraction of [`REPLY_JITTER`] and sent at
+//! most once per jitter window, so a burst of announcers does not set off a
+//! storm. Devices not heard from for three intervals are reported lost.
+//!
+//! [`probe`] checks the group works on this host at all by sending a
+//! [`KIND_PROBE`] datagram, which devices ignore, and waiting for it to
+//! loop back.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use globalsend_crypto::announce::{Announcement, AnnouncementTracker, MAX_ALIAS_LEN};
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_proto::PROTOCOL_VERSION;
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::{truncate, Device, DiscoveryError, DiscoveryEvent, LocalDevice, Registry};
+
+/// Administratively scoped, so routers keep it on the local site
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 71, 83);
+pub const MULTICAST_PORT: u16 = 53318;
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+/// Longest random delay before answering an announcement
+pub const REPLY_JITTER: Duration = Duration::from_millis(500);
+
+pub const KIND_ANNOUNCE: u8 = 1;
+pub const KIND_REPLY: u8 = 2;
+/// Sent by [`probe`]; never answered
+pub const KIND_PROBE: u8 = 3;
+const HEADER_LEN: usize = 3;
+const MAX_DATAGRAM: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Announce periodically and answer others
+    #[default]
+    Active,
+    /// Only answer others' announcements
+    Passive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticastConfig {
+    pub mode: Mode,
+    /// Mean time between announcements in [`Mode::Active`], jittered by ±25%
+    pub interval: Duration,
+    pub group: Ipv4Addr,
+    pub port: u16,
+}
+
+impl Default for MulticastConfig {
+    fn default() -> Self {
+        Self { mode: Mode::Active, interval: DEFAULT_INTERVAL, group: MULTICAST_GROUP, port: MULTICAST_PORT }
+    }
+}
+
+/// Announces this device and tracks others over UDP multicast; stops when dropped
+pub struct MulticastDiscovery {
+    registry: Arc<Registry>,
+    task: JoinHandle<()>,
+}
+
+impl MulticastDiscovery {
+    /// Join the group and start; `local.fingerprint` must be `identity`'s
+    pub fn start(identity: Arc<DeviceIdentity>, local: &LocalDevice, config: MulticastConfig) -> Result<Self, DiscoveryError> {
+        let socket = bind(config.group, config.port)?;
+        let registry = Arc::new(Registry::new());
+        let worker = Worker {
+            socket: Arc::new(socket),
+            group: SocketAddrV4::new(config.group, config.port).into(),
+            identity,
+            alias: truncate(&local.alias, MAX_ALIAS_LEN).to_owned(),
+            port: local.port,
+            config,
+            registry: registry.clone(),
+        };
+        Ok(Self { registry, task: tokio::spawn(worker.run()) })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.registry.subscribe()
+    }
+
+    /// Every device currently visible
+    pub fn devices(&self) -> watch::Receiver<BTreeMap<Fingerprint, Device>> {
+        self.registry.watch()
+    }
+}
+
+impl Drop for MulticastDiscovery {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn bind(group: Ipv4Addr, port: u16) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Join the group on `port`, send it a probe and wait up to `wait` for it
+/// to come back; false if it does not, which usually means a firewall or
+/// an interface without multicast
+pub async fn probe(port: u16, wait: Duration) -> io::Result<bool> {
+    let socket = bind(MULTICAST_GROUP, port)?;
+    let nonce: [u8; 16] = rand::random();
+    let mut datagram = vec![KIND_PROBE];
+    datagram.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    datagram.extend_from_slice(&nonce);
+    socket.send_to(&datagram, SocketAddrV4::new(MULTICAST_GROUP, port)).await?;
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let echoed = async {
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            if buf[..len] == datagram[..] {
+                return io::Result::Ok(());
+            }
+        }
+    };
+    match time::timeout(wait, echoed).await {
+        Ok(echoed) => echoed.map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+struct Worker {
+    socket: Arc<UdpSocket>,
+    group: SocketAddr,
+    identity: Arc<DeviceIdentity>,
+    alias: String,
+    port: u16,
+    config: MulticastConfig,
+    registry: Arc<Registry>,
+}
+
+impl Worker {
+    async fn run(self) {
+        let ours = self.identity.fingerprint();
+        let expiry = self.config.interval * 3;
+        let mut tracker = AnnouncementTracker::default();
+        let mut last_seen: HashMap<Fingerprint, Instant> = HashMap::new();
+        let mut reply_after = Instant::now();
+        let mut next_announce = Instant::now();
+        let mut sweep = time::interval(self.config.interval);
+        let mut buf = vec![0; MAX_DATAGRAM];
+
+        loop {
+            tokio::select! {
+                received = self.socket.recv_from(&mut buf) => {
+                    let Ok((len, from)) = received else { continue };
+                    let Some((kind, version, announcement)) = parse(&buf[..len], &mut tracker) else { continue };
+                    let fingerprint = announcement.fingerprint();
+                    if fingerprint == ours {
+                        continue;
+                    }
+                    last_seen.insert(fingerprint, Instant::now());
+                    self.registry.found(Device {
+                        fingerprint,
+                        alias: announcement.alias,
+                        version,
+                        addrs: vec![SocketAddr::new(from.ip(), announcement.port)],
+                    });
+                    if kind == KIND_ANNOUNCE && Instant::now() >= reply_after {
+                        let delay = jitter(Duration::ZERO, REPLY_JITTER);
+                        reply_after = Instant::now() + REPLY_JITTER;
+                        if let Some(datagram) = self.datagram(KIND_REPLY) {
+                            let (socket, group) = (self.socket.clone(), self.group);
+                            tokio::spawn(async move {
+                                time::sleep(delay).await;
+                                let _ = socket.send_to(&datagram, group).await;
+                            });
+                        }
+                    }
+                }
+                _ = time::sleep_until(next_announce), if self.config.mode == Mode::Active => {
+                    if let Some(datagram) = self.datagram(KIND_ANNOUNCE) {
+                        let _ = self.socket.send_to(&datagram, self.group).await;
+                    }
+                    let quarter = self.config.interval / 4;
+                    next_announce = Instant::now() + jitter(self.config.interval - quarter, self.config.interval + quarter);
+                }
+                _ = sweep.tick() => {
+                    last_seen.retain(|fingerprint, seen| {
+                        let alive = seen.elapsed() < expiry;
+                        if !alive {
+                            self.registry.lost(fingerprint);
+                        }
+                        alive
+                    });
+                }
+            }
+        }
+    }
+
+    fn datagram(&self, kind: u8) -> Option<Vec<u8>> {
+        let announcement = Announcement::sign(&self.identity, &self.alias, self.port, 0, unix_now()).ok()?;
+        let mut datagram = vec![kind];
+        datagram.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        datagram.extend_from_slice(&announcement.to_bytes());
+        Some(datagram)
+    }
+}
+
+fn parse(datagram: &[u8], tracker: &mut AnnouncementTracker) -> Option<(u8, u16, Announcement)> {
+    let (header, body) = datagram.split_at_checked(HEADER_LEN)?;
+    let kind = header[0];
+    if kind != KIND_ANNOUNCE && kind != KIND_REPLY {
+        return None;
+    }
+    let version = u16::from_be_bytes([header[1], header[2]]);
+    Some((kind, version, tracker.accept(body, unix_now()).ok()?))
+}
+
+fn jitter(min: Duration, max: Duration) -> Duration {
+    rand::thread_rng().gen_range(min..=max)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn active_and_passive_find_each_other() {
+        // a port of our own so parallel test runs do not cross
+        let port = 40000 + (std::process::id() % 20000) as u16;
+        let config = |mode| MulticastConfig { mode, interval: Duration::from_secs(1), port, ..Default::default() };
+        let (a, b) = (Arc::new(DeviceIdentity::generate()), Arc::new(DeviceIdentity::generate()));
+        let local = |identity: &DeviceIdentity, alias: &str| LocalDevice { fingerprint: identity.fingerprint(), alias: alias.into(), port: 7000 };
+
+        let passive = MulticastDiscovery::start(b.clone(), &local(&b, "passive"), config(Mode::Passive)).unwrap();
+        let mut seen_by_passive = passive.subscribe();
+        let active = MulticastDiscovery::start(a.clone(), &local(&a, "active"), config(Mode::Active)).unwrap();
+        let mut seen_by_active = active.devices();
+
+        let found = time::timeout(Duration::from_secs(5), seen_by_passive.recv()).await.unwrap().unwrap();
+        let DiscoveryEvent::DeviceDiscovered(device) = found else { panic!("{found:?}") };
+        assert_eq!((device.fingerprint, device.alias.as_str(), device.addrs[0].port()), (a.fingerprint(), "active", 7000));
+
+        // the passive side only speaks when asked
+        let wait = seen_by_active.wait_for(|devices| devices.contains_key(&b.fingerprint()));
+        time::timeout(Duration::from_secs(5), wait).await.unwrap().unwrap();
+
+        // a probe loops back and neither side takes it for a device
+        assert!(probe(port, Duration::from_secs(2)).await.unwrap());
+        assert_eq!(passive.devices().borrow().len(), 1);
+    }
+}
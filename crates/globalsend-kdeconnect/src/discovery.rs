@@ -0,0 +1,68 @@
+//! Finding KDE Connect devices on the LAN
+//!
+//! Devices broadcast their identity packet, with the `tcpPort` they take
+//! links on, to UDP [`UDP_PORT`] when they start and when the network
+//! changes. Whoever hears one dials that port with [`Link::connect`]; a
+//! device that hears ours dials us, so [`link::bind`](crate::link::bind)
+//! must be listening before [`Discovery::announce`].
+//!
+//! [`Link::connect`]: crate::Link::connect
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::identity::Identity;
+use crate::packet::{IdentityBody, Packet, IDENTITY};
+use crate::UDP_PORT;
+
+/// Largest datagram read; identities are well under a kilobyte
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+pub struct Discovery {
+    socket: UdpSocket,
+    identity: Identity,
+    port: u16,
+}
+
+impl Discovery {
+    /// Listen on [`UDP_PORT`]
+    pub fn bind(identity: &Identity) -> io::Result<Self> {
+        Self::bind_port(identity, UDP_PORT)
+    }
+
+    /// Listen on `port`, shared with other listeners on the host
+    pub fn bind_port(identity: &Identity, port: u16) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+        Ok(Self { socket: UdpSocket::from_std(socket.into())?, identity: identity.clone(), port })
+    }
+
+    /// Tell everyone on the LAN we take links on `tcp_port`
+    pub async fn announce(&self, tcp_port: u16) -> io::Result<()> {
+        let packet = self.identity.packet(Some(tcp_port)).to_line();
+        self.socket.send_to(&packet, SocketAddrV4::new(Ipv4Addr::BROADCAST, self.port)).await?;
+        Ok(())
+    }
+
+    /// Next identity broadcast by another device, and where it takes links
+    pub async fn recv(&self) -> io::Result<(IdentityBody, SocketAddr)> {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            let Ok(body) = Packet::from_line(&buf[..len]).and_then(|p| p.body::<IdentityBody>(IDENTITY)) else { continue };
+            match body.tcp_port {
+                Some(port) if body.device_id != self.identity.device_id() => return Ok((body, SocketAddr::new(from.ip(), port))),
+                // our own, or nowhere to reach it
+                _ => continue,
+            }
+        }
+    }
+}
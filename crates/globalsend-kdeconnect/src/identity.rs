@@ -0,0 +1,95 @@
+//! What this device tells KDE Connect peers about itself
+//!
+//! The device id and certificate have to outlive the process, or every
+//! paired device sees a stranger next time: keep [`Identity::certificate`]
+//! and [`Identity::private_key`] and rebuild with [`Identity::from_der`].
+
+use std::sync::Arc;
+
+use rand::RngCore;
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+
+use crate::packet::{IdentityBody, Packet, IDENTITY, SHARE_REQUEST, SHARE_REQUEST_UPDATE};
+use crate::tls;
+use crate::{KdeConnectError, PROTOCOL_VERSION};
+
+/// Packet types the share plugin takes and sends
+const CAPABILITIES: [&str; 2] = [SHARE_REQUEST, SHARE_REQUEST_UPDATE];
+
+#[derive(Clone)]
+pub struct Identity {
+    device_id: String,
+    name: String,
+    certificate: Vec<u8>,
+    private_key: Vec<u8>,
+    server: Arc<rustls::ServerConfig>,
+    client: Arc<rustls::ClientConfig>,
+}
+
+impl Identity {
+    /// A fresh device id and self-signed certificate, with the subject KDE Connect expects
+    pub fn generate(name: &str) -> Result<Self, KdeConnectError> {
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let device_id = hex::encode(id);
+        let mut params = CertificateParams::default();
+        let mut subject = DistinguishedName::new();
+        subject.push(DnType::CommonName, device_id.as_str());
+        subject.push(DnType::OrganizationName, "KDE");
+        subject.push(DnType::OrganizationalUnitName, "Kde connect");
+        params.distinguished_name = subject;
+        // wide, so peers with their clocks off still take it
+        params.not_before = rcgen::date_time_ymd(2024, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2034, 1, 1);
+        let key = KeyPair::generate().map_err(|e| KdeConnectError::Tls(e.to_string()))?;
+        let certificate = params.self_signed(&key).map_err(|e| KdeConnectError::Tls(e.to_string()))?;
+        Self::from_der(&device_id, name, certificate.der().to_vec(), key.serialize_der())
+    }
+
+    /// A saved identity: the certificate in DER and its key in PKCS#8 DER
+    pub fn from_der(device_id: &str, name: &str, certificate: Vec<u8>, private_key: Vec<u8>) -> Result<Self, KdeConnectError> {
+        let (server, client) = tls::configs(&certificate, &private_key)?;
+        Ok(Self { device_id: device_id.into(), name: name.into(), certificate, private_key, server, client })
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// What goes in our identity packets; `tcp_port` only in broadcasts
+    pub fn body(&self, tcp_port: Option<u16>) -> IdentityBody {
+        IdentityBody {
+            device_id: self.device_id.clone(),
+            device_name: self.name.clone(),
+            device_type: "desktop".into(),
+            protocol_version: PROTOCOL_VERSION,
+            incoming_capabilities: CAPABILITIES.map(Into::into).to_vec(),
+            outgoing_capabilities: CAPABILITIES.map(Into::into).to_vec(),
+            tcp_port,
+        }
+    }
+
+    pub fn packet(&self, tcp_port: Option<u16>) -> Packet {
+        Packet::new(IDENTITY, self.body(tcp_port))
+    }
+
+    pub(crate) fn server_config(&self) -> Arc<rustls::ServerConfig> {
+        self.server.clone()
+    }
+
+    pub(crate) fn client_config(&self) -> Arc<rustls::ClientConfig> {
+        self.client.clone()
+    }
+}
@@ -0,0 +1,99 @@
+//! KDE Connect compatibility
+//!
+//! Speaks enough of the [KDE Connect](https://invent.kde.org/network/kdeconnect-kde)
+//! LAN protocol, version 7 and 8, to pair with KDE Connect and GSConnect
+//! devices and swap files with their share plugin:
+//!
+//! - [`discovery`]: identity packets broadcast on UDP [`UDP_PORT`]
+//! - [`link`]: the TCP connection a device opens after hearing one, its
+//!   identity exchange and the TLS on top, with the roles swapped: whoever
+//!   dialled is the TLS server
+//! - [`pair`]: `kdeconnect.pair` requests and the verification key both
+//!   screens show
+//! - [`share`]: `kdeconnect.share.request`, with file bytes on a second
+//!   connection to the port the sender names
+//!
+//! Devices are known by a self-signed certificate whose common name is
+//! their device id, which [`Identity`] generates. Nothing of globalsend's
+//! own session crypto applies: a paired KDE Connect device is trusted by
+//! pinning its certificate, which is the caller's job, as is keeping this
+//! off unless asked for.
+
+pub mod discovery;
+pub mod identity;
+pub mod link;
+pub mod packet;
+pub mod pair;
+pub mod share;
+mod tls;
+
+use std::fmt;
+use std::io;
+
+pub use crate::identity::Identity;
+pub use crate::link::Link;
+pub use crate::packet::{IdentityBody, Packet, ShareRequest};
+pub use crate::share::Shared;
+
+/// Newest protocol version spoken; version 7 peers are still understood
+pub const PROTOCOL_VERSION: u32 = 8;
+/// Where identity packets are broadcast
+pub const UDP_PORT: u16 = 1716;
+/// Ports tried, in order, for the link listener
+pub const LINK_PORTS: std::ops::RangeInclusive<u16> = 1716..=1764;
+/// Ports tried, in order, for payload listeners
+pub const PAYLOAD_PORTS: std::ops::RangeInclusive<u16> = 1739..=1764;
+
+#[derive(Debug)]
+pub enum KdeConnectError {
+    Io(io::Error),
+    Tls(String),
+    /// A packet that is not JSON, or not the shape its type calls for
+    Packet(serde_json::Error),
+    /// The peer sent something out of place
+    Unexpected(String),
+    /// The peer turned the pairing down
+    Declined,
+    /// The peer's payload connection came with a different certificate than its link
+    WrongCertificate,
+    Timeout,
+}
+
+impl fmt::Display for KdeConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KdeConnectError::Io(e) => write!(f, "i/o error: {e}"),
+            KdeConnectError::Tls(e) => write!(f, "tls: {e}"),
+            KdeConnectError::Packet(e) => write!(f, "bad packet: {e}"),
+            KdeConnectError::Unexpected(what) => write!(f, "unexpected {what}"),
+            KdeConnectError::Declined => write!(f, "the device declined to pair"),
+            KdeConnectError::WrongCertificate => write!(f, "payload connection from a different device"),
+            KdeConnectError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for KdeConnectError {}
+
+impl From<io::Error> for KdeConnectError {
+    fn from(e: io::Error) -> Self {
+        KdeConnectError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for KdeConnectError {
+    fn from(e: serde_json::Error) -> Self {
+        KdeConnectError::Packet(e)
+    }
+}
+
+impl From<rustls::Error> for KdeConnectError {
+    fn from(e: rustls::Error) -> Self {
+        KdeConnectError::Tls(e.to_string())
+    }
+}
+
+/// Milliseconds since the Unix epoch, which KDE Connect uses for packet ids
+pub(crate) fn now_millis() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64)
+}
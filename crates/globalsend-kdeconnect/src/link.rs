@@ -0,0 +1,138 @@
+//! A link to one KDE Connect device
+//!
+//! The device that heard the other's broadcast dials its `tcpPort` and sends
+//! its identity packet in the clear. Then TLS starts with the roles the
+//! other way round, the dialler as server, and both present their
+//! certificates. Version 8 devices send their identity once more inside TLS,
+//! which is the one that counts. After that the link carries one packet
+//! per line.
+
+use std::io;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+use crate::identity::Identity;
+use crate::packet::{IdentityBody, Packet, IDENTITY, MAX_PACKET};
+use crate::{tls, KdeConnectError, LINK_PORTS};
+
+/// How long the identity exchange and TLS handshake may take
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Link {
+    stream: BufReader<TlsStream<TcpStream>>,
+    identity: Identity,
+    peer: IdentityBody,
+    peer_certificate: Vec<u8>,
+    peer_addr: SocketAddr,
+}
+
+impl Link {
+    /// Dial the device that broadcast `heard`, at `addr`
+    pub async fn connect(addr: SocketAddr, heard: &IdentityBody, identity: &Identity) -> Result<Self, KdeConnectError> {
+        timeout(async {
+            let mut tcp = TcpStream::connect(addr).await?;
+            tcp.write_all(&identity.packet(None).to_line()).await?;
+            let stream = TlsAcceptor::from(identity.server_config()).accept(tcp).await?;
+            Self::established(TlsStream::Server(stream), identity, heard.clone()).await
+        })
+        .await
+    }
+
+    /// Take a connection from a device that heard our broadcast
+    pub async fn accept(tcp: TcpStream, identity: &Identity) -> Result<Self, KdeConnectError> {
+        timeout(async {
+            let addr = tcp.peer_addr()?;
+            let mut reader = BufReader::new(tcp);
+            let peer: IdentityBody = read_packet(&mut reader).await?.body(IDENTITY)?;
+            if !reader.buffer().is_empty() {
+                return Err(KdeConnectError::Unexpected("bytes before the tls handshake".into()));
+            }
+            let stream = TlsConnector::from(identity.client_config()).connect(ServerName::IpAddress(addr.ip().into()), reader.into_inner()).await?;
+            Self::established(TlsStream::Client(stream), identity, peer).await
+        })
+        .await
+    }
+
+    /// Finish after TLS; `peer` is the identity heard before it
+    async fn established(stream: TlsStream<TcpStream>, identity: &Identity, peer: IdentityBody) -> Result<Self, KdeConnectError> {
+        let (tcp, state) = stream.get_ref();
+        let peer_addr = tcp.peer_addr()?;
+        let peer_certificate = state.peer_certificates().and_then(|c| c.first()).map(|c| c.to_vec()).ok_or_else(|| KdeConnectError::Tls("peer sent no certificate".into()))?;
+        let repeat = peer.protocol_version >= 8;
+        let mut link = Self { stream: BufReader::new(stream), identity: identity.clone(), peer, peer_certificate, peer_addr };
+        // what came in the clear could be anyone's; v8 devices say it again where it counts
+        if repeat {
+            link.send(&identity.packet(None)).await?;
+            link.peer = link.recv().await?.body(IDENTITY)?;
+        }
+        if tls::common_name(&link.peer_certificate).as_deref() != Some(link.peer.device_id.as_str()) {
+            return Err(KdeConnectError::Unexpected(format!("certificate for a device other than {}", link.peer.device_id)));
+        }
+        Ok(link)
+    }
+
+    pub fn peer(&self) -> &IdentityBody {
+        &self.peer
+    }
+
+    /// The certificate the peer presented, in DER; pin it once paired
+    pub fn peer_certificate(&self) -> &[u8] {
+        &self.peer_certificate
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    pub async fn send(&mut self, packet: &Packet) -> Result<(), KdeConnectError> {
+        let stream = self.stream.get_mut();
+        stream.write_all(&packet.to_line()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Next packet; an error once the peer hangs up
+    pub async fn recv(&mut self) -> Result<Packet, KdeConnectError> {
+        read_packet(&mut self.stream).await
+    }
+}
+
+/// Listen for links on the first free port KDE Connect devices try
+pub async fn bind() -> io::Result<TcpListener> {
+    bind_in(LINK_PORTS).await
+}
+
+/// The first port in `ports` that is free, or any port if none is
+pub(crate) async fn bind_in(ports: RangeInclusive<u16>) -> io::Result<TcpListener> {
+    for port in ports {
+        if let Ok(listener) = TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, port)).await {
+            return Ok(listener);
+        }
+    }
+    TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await
+}
+
+async fn read_packet(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Packet, KdeConnectError> {
+    let mut line = Vec::new();
+    reader.take(MAX_PACKET as u64).read_until(b'\n', &mut line).await?;
+    match line.last() {
+        Some(b'\n') => Packet::from_line(&line),
+        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        Some(_) if line.len() >= MAX_PACKET => Err(KdeConnectError::Unexpected("packet too long".into())),
+        Some(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+    }
+}
+
+async fn timeout<T>(future: impl std::future::Future<Output = Result<T, KdeConnectError>>) -> Result<T, KdeConnectError> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, future).await.unwrap_or(Err(KdeConnectError::Timeout))
+}
@@ -0,0 +1,139 @@
+//! KDE Connect network packets
+//!
+//! Every packet is one line of JSON: an `id` (milliseconds since the
+//! epoch), a `type` and a `body`, plus `payloadSize` and
+//! `payloadTransferInfo` when bytes follow on another connection. Field
+//! names are camelCase as KDE Connect writes them; unknown fields are
+//! ignored.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{now_millis, KdeConnectError};
+
+pub const IDENTITY: &str = "kdeconnect.identity";
+pub const PAIR: &str = "kdeconnect.pair";
+pub const SHARE_REQUEST: &str = "kdeconnect.share.request";
+pub const SHARE_REQUEST_UPDATE: &str = "kdeconnect.share.request.update";
+
+/// Longest line read before giving up on the peer
+pub const MAX_PACKET: usize = 512 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Packet {
+    pub id: i64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub body: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_transfer_info: Option<PayloadTransferInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayloadTransferInfo {
+    pub port: u16,
+}
+
+impl Packet {
+    pub fn new(kind: &str, body: impl Serialize) -> Self {
+        Self { id: now_millis(), kind: kind.into(), body: serde_json::to_value(body).unwrap_or_default(), payload_size: None, payload_transfer_info: None }
+    }
+
+    /// The packet as it goes on the wire, newline included
+    pub fn to_line(&self) -> Vec<u8> {
+        let mut line = serde_json::to_vec(self).expect("packets serialize");
+        line.push(b'\n');
+        line
+    }
+
+    pub fn from_line(line: &[u8]) -> Result<Self, KdeConnectError> {
+        Ok(serde_json::from_slice(line.trim_ascii())?)
+    }
+
+    /// The body as `T`, if this is a `kind` packet
+    pub fn body<T: DeserializeOwned>(&self, kind: &str) -> Result<T, KdeConnectError> {
+        if self.kind != kind {
+            return Err(KdeConnectError::Unexpected(format!("{} packet, expected {kind}", self.kind)));
+        }
+        Ok(T::deserialize(&self.body)?)
+    }
+}
+
+/// Body of `kdeconnect.identity`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityBody {
+    pub device_id: String,
+    pub device_name: String,
+    /// `desktop`, `laptop`, `phone`, `tablet` or `tv`
+    #[serde(default)]
+    pub device_type: String,
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub incoming_capabilities: Vec<String>,
+    #[serde(default)]
+    pub outgoing_capabilities: Vec<String>,
+    /// Where the device takes link connections; only in broadcast identities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_port: Option<u16>,
+}
+
+/// Body of `kdeconnect.pair`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairBody {
+    /// `true` to ask or agree, `false` to refuse or unpair
+    pub pair: bool,
+    /// Seconds since the epoch, in version 8 requests; part of the verification key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+/// Body of `kdeconnect.share.request`: a file, a piece of text or a URL
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Milliseconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<i64>,
+    /// Ask the receiver to open the file once it lands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open: Option<bool>,
+    /// Files in the whole batch this one belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub number_of_files: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_payload_size: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_kde_connect_sends() {
+        let line = br#"{"id":1700000000000,"type":"kdeconnect.share.request","body":{"filename":"IMG_0001.jpg","lastModified":1699999999000,"numberOfFiles":2,"totalPayloadSize":2048,"extra":1},"payloadSize":1024,"payloadTransferInfo":{"port":1739}}"#;
+        let packet = Packet::from_line(line).unwrap();
+        assert_eq!((packet.payload_size, packet.payload_transfer_info), (Some(1024), Some(PayloadTransferInfo { port: 1739 })));
+        let share: ShareRequest = packet.body(SHARE_REQUEST).unwrap();
+        assert_eq!((share.filename.as_deref(), share.number_of_files, share.total_payload_size), (Some("IMG_0001.jpg"), Some(2), Some(2048)));
+        assert!(matches!(packet.body::<PairBody>(PAIR), Err(KdeConnectError::Unexpected(_))));
+
+        let pair = Packet::new(PAIR, PairBody { pair: true, timestamp: None });
+        let line = pair.to_line();
+        assert_eq!(line.last(), Some(&b'\n'));
+        let text = std::str::from_utf8(&line).unwrap();
+        assert!(text.contains(r#""type":"kdeconnect.pair","body":{"pair":true}"#) && !text.contains("payload"), "{text}");
+        assert_eq!(Packet::from_line(&line).unwrap(), pair);
+    }
+}
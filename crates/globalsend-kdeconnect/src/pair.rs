@@ -0,0 +1,66 @@
+//! Pairing with a KDE Connect device
+//!
+//! One side sends `kdeconnect.pair` with `pair: true`, the other answers
+//! with `true` to agree or `false` to refuse, within [`PAIR_TIMEOUT`].
+//! Meanwhile both screens show [`verification_key`]: a hash of the two
+//! public keys, larger first, and in version 8 the request's timestamp, so
+//! a user can tell a man in the middle apart from the device in their
+//! hand. Packets other than the answer are dropped until it comes.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::link::Link;
+use crate::packet::{Packet, PairBody, PAIR};
+use crate::{tls, KdeConnectError};
+
+/// How long a request waits for the other side's answer
+pub const PAIR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Ask the device on `link` to pair; returns the timestamp sent, which the key covers
+pub async fn request(link: &mut Link) -> Result<Option<u64>, KdeConnectError> {
+    let timestamp = (link.peer().protocol_version >= 8).then(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+    link.send(&Packet::new(PAIR, PairBody { pair: true, timestamp })).await?;
+    Ok(timestamp)
+}
+
+/// Wait for the answer to [`request`]
+pub async fn wait(link: &mut Link) -> Result<(), KdeConnectError> {
+    let answer = async {
+        loop {
+            let packet = link.recv().await?;
+            if packet.kind == PAIR {
+                let body: PairBody = packet.body(PAIR)?;
+                return if body.pair { Ok(()) } else { Err(KdeConnectError::Declined) };
+            }
+        }
+    };
+    tokio::time::timeout(PAIR_TIMEOUT, answer).await.unwrap_or(Err(KdeConnectError::Timeout))
+}
+
+/// The pairing request in `packet`, if it is one; its timestamp, if it has one
+pub fn incoming(packet: &Packet) -> Option<Option<u64>> {
+    let body: PairBody = packet.body(PAIR).ok()?;
+    body.pair.then_some(body.timestamp)
+}
+
+/// Agree to or refuse a request; refusing a paired device unpairs it
+pub async fn answer(link: &mut Link, accept: bool) -> Result<(), KdeConnectError> {
+    link.send(&Packet::new(PAIR, PairBody { pair: accept, timestamp: None })).await
+}
+
+/// The eight characters both sides show for a request with `timestamp`
+pub fn verification_key(link: &Link, timestamp: Option<u64>) -> Result<String, KdeConnectError> {
+    let bad = || KdeConnectError::Tls("unreadable certificate".into());
+    let ours = tls::public_key(link.identity().certificate()).ok_or_else(bad)?;
+    let theirs = tls::public_key(link.peer_certificate()).ok_or_else(bad)?;
+    let (first, second) = if ours >= theirs { (ours, theirs) } else { (theirs, ours) };
+    let mut hash = Sha256::new();
+    hash.update(first);
+    hash.update(second);
+    if let Some(timestamp) = timestamp {
+        hash.update(timestamp.to_string());
+    }
+    Ok(hex::encode_upper(&hash.finalize()[..4]))
+}
@@ -0,0 +1,220 @@
+//! The share plugin: files, text and URLs
+//!
+//! A file goes as a `kdeconnect.share.request` naming it, with
+//! `payloadSize` and the port of a fresh listener in
+//! `payloadTransferInfo`. The receiver dials that port and TLS starts with
+//! the sender as server; the bytes follow and the connection closes. Both
+//! ends check the payload connection carries the link's certificate. Text
+//! and URLs fit in the packet itself.
+//!
+//! Received files land in a directory under their sent name, never a path
+//! in it, renamed to `name (1).ext` and so on rather than overwriting.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+use crate::link::{bind_in, Link};
+use crate::packet::{Packet, PayloadTransferInfo, ShareRequest, SHARE_REQUEST};
+use crate::{KdeConnectError, PAYLOAD_PORTS};
+
+/// How long a sender waits for the receiver to come for a file
+pub const PAYLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a share request brought
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shared {
+    /// Where the file landed
+    File(PathBuf),
+    Text(String),
+    Url(String),
+}
+
+/// Send `paths` one after another, each once the last has been taken
+pub async fn send_files(link: &mut Link, paths: &[PathBuf]) -> Result<(), KdeConnectError> {
+    let mut sizes = Vec::with_capacity(paths.len());
+    for path in paths {
+        sizes.push(tokio::fs::metadata(path).await?.len());
+    }
+    let total: u64 = sizes.iter().sum();
+    for (path, size) in paths.iter().zip(sizes) {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).ok_or_else(|| KdeConnectError::Unexpected(format!("{} is not a file", path.display())))?;
+        let modified = tokio::fs::metadata(path).await?.modified().ok();
+        let listener = bind_in(PAYLOAD_PORTS).await?;
+        let request = ShareRequest {
+            filename: Some(name),
+            last_modified: modified.and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok()).map(|d| d.as_millis() as i64),
+            number_of_files: Some(paths.len() as u32),
+            total_payload_size: Some(total as i64),
+            ..ShareRequest::default()
+        };
+        let mut packet = Packet::new(SHARE_REQUEST, request);
+        packet.payload_size = Some(size as i64);
+        packet.payload_transfer_info = Some(PayloadTransferInfo { port: listener.local_addr()?.port() });
+        link.send(&packet).await?;
+
+        let upload = async {
+            let (tcp, _) = listener.accept().await?;
+            let mut stream = TlsStream::Server(TlsAcceptor::from(link.identity().server_config()).accept(tcp).await?);
+            check_peer(&stream, link)?;
+            let mut file = File::open(path).await?;
+            tokio::io::copy(&mut (&mut file).take(size), &mut stream).await?;
+            stream.shutdown().await?;
+            Ok::<_, KdeConnectError>(())
+        };
+        tokio::time::timeout(PAYLOAD_TIMEOUT, upload).await.unwrap_or(Err(KdeConnectError::Timeout))?;
+    }
+    Ok(())
+}
+
+pub async fn send_text(link: &mut Link, text: &str) -> Result<(), KdeConnectError> {
+    link.send(&Packet::new(SHARE_REQUEST, ShareRequest { text: Some(text.into()), ..ShareRequest::default() })).await
+}
+
+pub async fn send_url(link: &mut Link, url: &str) -> Result<(), KdeConnectError> {
+    link.send(&Packet::new(SHARE_REQUEST, ShareRequest { url: Some(url.into()), ..ShareRequest::default() })).await
+}
+
+/// Take what `packet`, a share request from `link`, brings; files go into `dir`
+pub async fn receive(link: &Link, packet: &Packet, dir: &Path) -> Result<Shared, KdeConnectError> {
+    let request: ShareRequest = packet.body(SHARE_REQUEST)?;
+    if let Some(text) = request.text {
+        return Ok(Shared::Text(text));
+    }
+    if let Some(url) = request.url {
+        return Ok(Shared::Url(url));
+    }
+    let (Some(name), Some(info)) = (request.filename, packet.payload_transfer_info) else {
+        return Err(KdeConnectError::Unexpected("share request without a file, text or url".into()));
+    };
+    let path = target_path(dir, &name).ok_or_else(|| KdeConnectError::Unexpected(format!("file name {name:?}")))?;
+    let addr = SocketAddr::new(link.peer_addr().ip(), info.port);
+    let download = async {
+        let tcp = TcpStream::connect(addr).await?;
+        let stream = TlsConnector::from(link.identity().client_config()).connect(ServerName::IpAddress(addr.ip().into()), tcp).await?;
+        let stream = TlsStream::Client(stream);
+        check_peer(&stream, link)?;
+        save(stream, packet.payload_size.and_then(|s| u64::try_from(s).ok()), &path).await
+    };
+    tokio::time::timeout(PAYLOAD_TIMEOUT, download).await.unwrap_or(Err(KdeConnectError::Timeout))?;
+    if let Some(modified) = request.last_modified.and_then(|ms| u64::try_from(ms).ok()) {
+        let time = std::time::UNIX_EPOCH + Duration::from_millis(modified);
+        // a wrong modification time is not worth losing the file over
+        let _ = std::fs::File::options().write(true).open(&path).and_then(|f| f.set_modified(time));
+    }
+    Ok(Shared::File(path))
+}
+
+fn check_peer(stream: &TlsStream<TcpStream>, link: &Link) -> Result<(), KdeConnectError> {
+    let certificate = stream.get_ref().1.peer_certificates().and_then(|c| c.first());
+    if certificate.map(|c| c.as_ref()) != Some(link.peer_certificate()) {
+        return Err(KdeConnectError::WrongCertificate);
+    }
+    Ok(())
+}
+
+/// Read `size` bytes, or to the end if unknown, into `path` by way of a `.part` file
+async fn save(mut stream: TlsStream<TcpStream>, size: Option<u64>, path: &Path) -> Result<(), KdeConnectError> {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    let result = async {
+        let mut file = File::create(&part).await?;
+        let copied = match size {
+            Some(size) => tokio::io::copy(&mut (&mut stream).take(size), &mut file).await?,
+            None => tokio::io::copy(&mut stream, &mut file).await?,
+        };
+        if size.is_some_and(|size| copied != size) {
+            return Err(KdeConnectError::Unexpected(format!("payload of {copied} bytes, expected {}", size.unwrap_or_default())));
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&part, path).await?;
+        Ok(())
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
+/// Where a sent name goes under `dir`; `None` for names that are not a plain file name
+fn target_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return None;
+    }
+    let path = dir.join(name);
+    let (stem, ext) = (path.file_stem()?.to_string_lossy().into_owned(), path.extension().map(|e| e.to_string_lossy().into_owned()));
+    let mut candidate = path.clone();
+    for n in 1.. {
+        if !candidate.exists() {
+            break;
+        }
+        let name = match &ext {
+            Some(ext) => format!("{stem} ({n}).{ext}"),
+            None => format!("{stem} ({n})"),
+        };
+        candidate = path.with_file_name(name);
+    }
+    Some(candidate)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pair, Identity};
+
+    #[tokio::test]
+    async fn pairs_and_shares_a_file_over_loopback() {
+        let dir = std::env::temp_dir().join(format!("gs-kdeconnect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("in")).unwrap();
+        let source = dir.join("photo.jpg");
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+        std::fs::write(dir.join("in/photo.jpg"), b"older").unwrap();
+
+        let (phone, laptop) = (Identity::generate("phone").unwrap(), Identity::generate("laptop").unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the laptop heard the phone's broadcast and dials it
+        let heard = phone.body(Some(addr.port()));
+        let accepting = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            Link::accept(tcp, &phone).await.unwrap()
+        });
+        let mut laptop = Link::connect(addr, &heard, &laptop).await.unwrap();
+        let mut phone = accepting.await.unwrap();
+        assert_eq!((laptop.peer().device_name.as_str(), phone.peer().device_name.as_str()), ("phone", "laptop"));
+
+        let timestamp = pair::request(&mut laptop).await.unwrap();
+        let asked = phone.recv().await.unwrap();
+        assert_eq!(pair::incoming(&asked), Some(timestamp));
+        assert_eq!(pair::verification_key(&phone, timestamp).unwrap(), pair::verification_key(&laptop, timestamp).unwrap());
+        pair::answer(&mut phone, true).await.unwrap();
+        pair::wait(&mut laptop).await.unwrap();
+
+        let sending = tokio::spawn(async move {
+            send_files(&mut laptop, &[source]).await.unwrap();
+            send_text(&mut laptop, "hello").await.unwrap();
+            laptop
+        });
+        let offer = phone.recv().await.unwrap();
+        let landed = dir.join("in/photo (1).jpg");
+        assert_eq!(receive(&phone, &offer, &dir.join("in")).await.unwrap(), Shared::File(landed.clone()));
+        assert_eq!(std::fs::read(&landed).unwrap(), data);
+        let text = phone.recv().await.unwrap();
+        assert_eq!(receive(&phone, &text, &dir).await.unwrap(), Shared::Text("hello".into()));
+        sending.await.unwrap();
+
+        assert_eq!(target_path(&dir, "../escape"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Sending side of the LocalSend REST API
+//!
+//! LocalSend certificates are self-signed and not checked by LocalSend
+//! either, so https here only keeps passive listeners out.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use rand::RngCore;
+use reqwest::StatusCode;
+use tokio_util::io::ReaderStream;
+
+use crate::model::{DeviceInfo, FileInfo, InfoResponse, PrepareUploadRequest, PrepareUploadResponse, Protocol};
+use crate::{LocalSendError, API_PREFIX};
+
+/// Where a LocalSend peer listens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub addr: SocketAddr,
+    pub protocol: Protocol,
+}
+
+impl Target {
+    /// The peer that sent `info` from `ip`
+    pub fn from_info(ip: IpAddr, info: &DeviceInfo) -> Self {
+        Self { addr: SocketAddr::new(ip, info.port), protocol: info.protocol }
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}://{}{API_PREFIX}/{endpoint}", self.protocol.scheme(), self.addr)
+    }
+}
+
+/// Talks to LocalSend peers as `info`
+pub struct Client {
+    http: reqwest::Client,
+    info: DeviceInfo,
+}
+
+impl Client {
+    pub fn new(info: DeviceInfo) -> Result<Self, LocalSendError> {
+        let http = reqwest::Client::builder().danger_accept_invalid_certs(true).build()?;
+        Ok(Self { http, info })
+    }
+
+    pub async fn info(&self, target: &Target) -> Result<InfoResponse, LocalSendError> {
+        let response = self.http.get(target.url("info")).query(&[("fingerprint", &self.info.fingerprint)]).send().await?;
+        Ok(check(response)?.json().await?)
+    }
+
+    /// Answer an announcement from `target`, which also tells it about us
+    pub async fn register(&self, target: &Target) -> Result<InfoResponse, LocalSendError> {
+        let response = self.http.post(target.url("register")).json(&self.info).send().await?;
+        Ok(check(response)?.json().await?)
+    }
+
+    /// Offer `files` and upload whichever the receiver accepts
+    pub async fn send(&self, target: &Target, files: &[PathBuf], pin: Option<&str>) -> Result<(), LocalSendError> {
+        let mut offered = HashMap::new();
+        let mut paths = HashMap::new();
+        for path in files {
+            let file = file_info(path).await?;
+            paths.insert(file.id.clone(), path);
+            offered.insert(file.id.clone(), file);
+        }
+        let mut request = self.http.post(target.url("prepare-upload")).json(&PrepareUploadRequest { info: self.info.clone(), files: offered });
+        if let Some(pin) = pin {
+            request = request.query(&[("pin", pin)]);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(());
+        }
+        let prepared: PrepareUploadResponse = check(response)?.json().await?;
+
+        for (id, token) in &prepared.files {
+            let Some(path) = paths.get(id) else { continue };
+            if let Err(e) = self.upload(target, &prepared.session_id, id, token, path).await {
+                let _ = self.http.post(target.url("cancel")).query(&[("sessionId", &prepared.session_id)]).send().await;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn upload(&self, target: &Target, session: &str, id: &str, token: &str, path: &Path) -> Result<(), LocalSendError> {
+        let file = tokio::fs::File::open(path).await?;
+        let response = self
+            .http
+            .post(target.url("upload"))
+            .query(&[("sessionId", session), ("fileId", id), ("token", token)])
+            .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await?;
+        check(response)?;
+        Ok(())
+    }
+}
+
+fn check(response: reqwest::Response) -> Result<reqwest::Response, LocalSendError> {
+    match response.status() {
+        s if s.is_success() => Ok(response),
+        StatusCode::UNAUTHORIZED => Err(LocalSendError::PinRequired),
+        StatusCode::FORBIDDEN => Err(LocalSendError::Declined),
+        StatusCode::CONFLICT => Err(LocalSendError::Busy),
+        s => Err(LocalSendError::Status(s.as_u16())),
+    }
+}
+
+async fn file_info(path: &Path) -> Result<FileInfo, LocalSendError> {
+    let size = tokio::fs::metadata(path).await?.len();
+    let mut id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut id);
+    Ok(FileInfo {
+        id: hex::encode(id),
+        file_name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        size,
+        file_type: mime_type(path).into(),
+        sha256: None,
+        preview: None,
+    })
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("txt") => "text/plain",
+        Some("html" | "htm") => "text/html",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        Some("apk") => "application/vnd.android.package-archive",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use crate::server::{Server, ServerEvent};
+
+    async fn serve(identity: Identity, dir: &Path, pin: Option<&str>) -> (Target, tokio::sync::mpsc::UnboundedReceiver<ServerEvent>) {
+        let mut server = Server::bind("127.0.0.1:0".parse().unwrap(), identity, dir).await.unwrap();
+        if let Some(pin) = pin {
+            server = server.pin(pin);
+        }
+        let events = server.events().unwrap();
+        let target = Target::from_info("127.0.0.1".parse().unwrap(), server.identity().info());
+        tokio::spawn(server.serve());
+        (target, events)
+    }
+
+    #[tokio::test]
+    async fn sends_to_localsend_receiver() {
+        let root = std::env::temp_dir().join(format!("globalsend-localsend-{}", std::process::id()));
+        let (outbox, inbox) = (root.join("out"), root.join("in"));
+        tokio::fs::create_dir_all(&outbox).await.unwrap();
+        let files = [outbox.join("note.txt")];
+        tokio::fs::write(&files[0], b"hello localsend").await.unwrap();
+
+        for identity in [Identity::https("receiver", 0).unwrap(), Identity::http("receiver", 0)] {
+            let (target, mut events) = serve(identity, &inbox, Some("123456")).await;
+            let client = Client::new(Identity::http("sender", 0).info().clone()).unwrap();
+            assert_eq!(client.info(&target).await.unwrap().alias, "receiver");
+
+            assert!(matches!(client.send(&target, &files, None).await, Err(LocalSendError::PinRequired)));
+            client.send(&target, &files, Some("123456")).await.unwrap();
+            assert!(matches!(events.recv().await, Some(ServerEvent::Started { .. })));
+            let Some(ServerEvent::Received { path, .. }) = events.recv().await else { panic!("no file") };
+            assert_eq!(tokio::fs::read(&path).await.unwrap(), b"hello localsend");
+            assert!(matches!(events.recv().await, Some(ServerEvent::Finished { .. })));
+        }
+        // the second copy was renamed, not overwritten
+        assert!(inbox.join("note (1).txt").exists());
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+}
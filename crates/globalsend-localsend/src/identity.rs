@@ -0,0 +1,63 @@
+//! What this device tells LocalSend peers about itself
+
+use std::sync::Arc;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::model::{DeviceInfo, DeviceType, Protocol};
+use crate::{LocalSendError, PROTOCOL_VERSION};
+
+/// Our [`DeviceInfo`], plus the certificate behind it when serving https
+#[derive(Clone)]
+pub struct Identity {
+    info: DeviceInfo,
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Identity {
+    /// Serve https with a fresh self-signed certificate; the fingerprint is its SHA-256
+    pub fn https(alias: &str, port: u16) -> Result<Self, LocalSendError> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localsend".into()]).map_err(|e| LocalSendError::Tls(e.to_string()))?;
+        let fingerprint = hex::encode(Sha256::digest(cert.cert.der()));
+        let key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|b| b.with_no_client_auth().with_single_cert(vec![cert.cert.der().clone()], key.into()))
+            .map_err(|e| LocalSendError::Tls(e.to_string()))?;
+        Ok(Self { info: info(alias, port, Protocol::Https, fingerprint), tls: Some(Arc::new(config)) })
+    }
+
+    /// Serve plain http; the fingerprint is a random id
+    pub fn http(alias: &str, port: u16) -> Self {
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        Self { info: info(alias, port, Protocol::Http, hex::encode(id)), tls: None }
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    pub(crate) fn tls(&self) -> Option<Arc<rustls::ServerConfig>> {
+        self.tls.clone()
+    }
+
+    /// Update the advertised port, e.g. after binding port 0
+    pub(crate) fn set_port(&mut self, port: u16) {
+        self.info.port = port;
+    }
+}
+
+fn info(alias: &str, port: u16, protocol: Protocol, fingerprint: String) -> DeviceInfo {
+    DeviceInfo {
+        alias: alias.into(),
+        version: PROTOCOL_VERSION.into(),
+        device_model: Some("globalsend".into()),
+        device_type: Some(DeviceType::Desktop),
+        fingerprint,
+        port,
+        protocol,
+        download: false,
+    }
+}
@@ -0,0 +1,78 @@
+//! LocalSend v2 compatibility
+//!
+//! Speaks the [LocalSend](https://github.com/localsend/protocol) v2 protocol
+//! so a globalsend device shows up in stock LocalSend apps and can swap
+//! files with them:
+//!
+//! - [`multicast`]: JSON announcements on `224.0.0.167:53317`
+//! - [`server`]: the `/api/localsend/v2` REST endpoints (`info`, `register`,
+//!   `prepare-upload`, `upload`, `cancel`), with an optional PIN
+//! - [`client`]: the sending side of the same endpoints
+//!
+//! LocalSend has no device keys; over https its peers are identified by
+//! the SHA-256 of a self-signed certificate, which [`Identity`] generates.
+//! None of globalsend's own session crypto applies here, so callers should
+//! treat these peers as untrusted and keep this off unless asked for.
+
+pub mod client;
+pub mod identity;
+pub mod model;
+pub mod multicast;
+pub mod server;
+
+use std::fmt;
+use std::io;
+
+pub use crate::client::{Client, Target};
+pub use crate::identity::Identity;
+pub use crate::model::{Announcement, DeviceInfo, DeviceType, FileInfo, Protocol};
+pub use crate::server::{Server, ServerEvent};
+
+/// Version string sent in every [`DeviceInfo`]
+pub const PROTOCOL_VERSION: &str = "2.0";
+/// Default port for both multicast and HTTP
+pub const DEFAULT_PORT: u16 = 53317;
+pub const API_PREFIX: &str = "/api/localsend/v2";
+
+#[derive(Debug)]
+pub enum LocalSendError {
+    Io(io::Error),
+    Tls(String),
+    Http(reqwest::Error),
+    /// The receiver wants a PIN, or rejected the one given
+    PinRequired,
+    /// The receiver turned the transfer down
+    Declined,
+    /// The receiver is busy with another session
+    Busy,
+    /// Any other unexpected HTTP status
+    Status(u16),
+}
+
+impl fmt::Display for LocalSendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalSendError::Io(e) => write!(f, "i/o error: {e}"),
+            LocalSendError::Tls(e) => write!(f, "tls: {e}"),
+            LocalSendError::Http(e) => write!(f, "http: {e}"),
+            LocalSendError::PinRequired => write!(f, "receiver requires a pin"),
+            LocalSendError::Declined => write!(f, "receiver declined the transfer"),
+            LocalSendError::Busy => write!(f, "receiver is busy with another transfer"),
+            LocalSendError::Status(code) => write!(f, "unexpected http status {code}"),
+        }
+    }
+}
+
+impl std::error::Error for LocalSendError {}
+
+impl From<io::Error> for LocalSendError {
+    fn from(e: io::Error) -> Self {
+        LocalSendError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for LocalSendError {
+    fn from(e: reqwest::Error) -> Self {
+        LocalSendError::Http(e)
+    }
+}
@@ -0,0 +1,143 @@
+//! LocalSend v2 JSON bodies
+//!
+//! Field names follow the LocalSend protocol document (camelCase). Unknown
+//! fields are ignored so newer LocalSend releases keep parsing.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Mobile,
+    Desktop,
+    Web,
+    Headless,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http,
+    Https,
+}
+
+impl Protocol {
+    pub fn scheme(self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Https => "https",
+        }
+    }
+}
+
+/// How a device describes itself in announcements, `register` and `prepare-upload`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub alias: String,
+    pub version: String,
+    #[serde(default)]
+    pub device_model: Option<String>,
+    #[serde(default)]
+    pub device_type: Option<DeviceType>,
+    /// SHA-256 of the TLS certificate over https, a random id over http
+    pub fingerprint: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    /// Whether the download API (reverse transfer) is available
+    #[serde(default)]
+    pub download: bool,
+}
+
+/// Multicast datagram body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    #[serde(flatten)]
+    pub info: DeviceInfo,
+    /// `true` asks everyone listening to answer; replies set it to `false`
+    #[serde(default)]
+    pub announce: bool,
+}
+
+/// Body of `info` and `register` responses: [`DeviceInfo`] without the address fields
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InfoResponse {
+    pub alias: String,
+    pub version: String,
+    #[serde(default)]
+    pub device_model: Option<String>,
+    #[serde(default)]
+    pub device_type: Option<DeviceType>,
+    pub fingerprint: String,
+    #[serde(default)]
+    pub download: bool,
+}
+
+impl From<&DeviceInfo> for InfoResponse {
+    fn from(info: &DeviceInfo) -> Self {
+        Self {
+            alias: info.alias.clone(),
+            version: info.version.clone(),
+            device_model: info.device_model.clone(),
+            device_type: info.device_type,
+            fingerprint: info.fingerprint.clone(),
+            download: info.download,
+        }
+    }
+}
+
+/// One file in a `prepare-upload` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    pub id: String,
+    /// May contain `/` when a folder is sent
+    pub file_name: String,
+    pub size: u64,
+    /// MIME type
+    pub file_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrepareUploadRequest {
+    pub info: DeviceInfo,
+    /// Keyed by [`FileInfo::id`]
+    pub files: HashMap<String, FileInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareUploadResponse {
+    pub session_id: String,
+    /// File id to upload token, for the files the receiver wants
+    pub files: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_localsend_announcement() {
+        // as sent by the LocalSend mobile app
+        let json = r#"{"alias":"Nice Orange","version":"2.0","deviceModel":"Samsung","deviceType":"mobile",
+            "fingerprint":"abc","port":53317,"protocol":"https","download":true,"announcement":true,"announce":true}"#;
+        let a: Announcement = serde_json::from_str(json).unwrap();
+        assert!(a.announce);
+        assert_eq!(a.info.device_type, Some(DeviceType::Mobile));
+        assert_eq!(a.info.protocol, Protocol::Https);
+
+        let back = serde_json::to_value(&a).unwrap();
+        assert_eq!(back["deviceModel"], "Samsung");
+        assert_eq!(back["port"], 53317);
+    }
+}
@@ -0,0 +1,84 @@
+//! LocalSend multicast discovery
+//!
+//! Devices send an [`Announcement`] with `announce: true` to
+//! [`MULTICAST_GROUP`] when they start or refresh. Everyone who hears it
+//! answers, preferably by `POST`ing their info to the announcer's `register`
+//! endpoint, or with an `announce: false` datagram when that fails.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use socket2::{Domain, Protocol as SockProtocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::client::{Client, Target};
+use crate::model::{Announcement, DeviceInfo};
+use crate::DEFAULT_PORT;
+
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 167);
+
+/// Largest datagram read; announcements are a few hundred bytes
+const MAX_DATAGRAM: usize = 8 * 1024;
+
+/// Announces `info` and hears other devices' announcements
+pub struct Multicast {
+    socket: UdpSocket,
+    info: DeviceInfo,
+    group: SocketAddr,
+}
+
+impl Multicast {
+    /// Join the group on [`DEFAULT_PORT`]
+    pub fn bind(info: DeviceInfo) -> io::Result<Self> {
+        Self::bind_port(info, DEFAULT_PORT)
+    }
+
+    /// Join the group on `port`; shared with other listeners on the host
+    pub fn bind_port(info: DeviceInfo, port: u16) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(SockProtocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+        socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        Ok(Self { socket, info, group: SocketAddrV4::new(MULTICAST_GROUP, port).into() })
+    }
+
+    /// Ask everyone listening to answer
+    pub async fn announce(&self) -> io::Result<()> {
+        self.send(true).await
+    }
+
+    /// Next announcement from another device, and where it came from
+    pub async fn recv(&self) -> io::Result<(Announcement, IpAddr)> {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            let (len, from) = self.socket.recv_from(&mut buf).await?;
+            match serde_json::from_slice::<Announcement>(&buf[..len]) {
+                Ok(a) if a.info.fingerprint != self.info.fingerprint => return Ok((a, from.ip())),
+                // our own, or not LocalSend
+                _ => continue,
+            }
+        }
+    }
+
+    /// Answer `announcement` over http, falling back to a multicast reply
+    pub async fn respond(&self, client: &Client, announcement: &Announcement, from: IpAddr) -> io::Result<()> {
+        if !announcement.announce {
+            return Ok(());
+        }
+        if client.register(&Target::from_info(from, &announcement.info)).await.is_ok() {
+            return Ok(());
+        }
+        self.send(false).await
+    }
+
+    async fn send(&self, announce: bool) -> io::Result<()> {
+        let body = serde_json::to_vec(&Announcement { info: self.info.clone(), announce }).map_err(io::Error::other)?;
+        self.socket.send_to(&body, self.group).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! Receiving side of the LocalSend REST API
+//!
+//! Like LocalSend itself, one upload session runs at a time: a second
+//! `prepare-upload` gets `409` until the first finishes or is cancelled.
+//! Uploads are only taken from the address that prepared the session, with
+//! the per-file token handed out for it, and only as many bytes as the
+//! offer said. Files land in the download directory under their sent
+//! (relative) names, renamed to `name (1).ext` and so on rather than
+//! overwriting.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use futures_util::TryStreamExt;
+use globalsend_crypto::ct;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use rand::RngCore;
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::io::StreamReader;
+
+use crate::identity::Identity;
+use crate::model::{DeviceInfo, FileInfo, InfoResponse, PrepareUploadRequest, PrepareUploadResponse};
+use crate::API_PREFIX;
+
+/// What the server reports as it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A peer answered our announcement through `register`
+    Registered { info: DeviceInfo, addr: IpAddr },
+    /// A transfer was accepted and is about to start
+    Started { session: String, sender: DeviceInfo, files: Vec<FileInfo> },
+    Received { session: String, path: PathBuf },
+    Finished { session: String },
+    Cancelled { session: String },
+}
+
+type OfferFilter = dyn Fn(&PrepareUploadRequest) -> bool + Send + Sync;
+
+/// LocalSend HTTP(S) endpoint
+pub struct Server {
+    listener: TcpListener,
+    identity: Identity,
+    dir: PathBuf,
+    pin: Option<String>,
+    on_offer: Box<OfferFilter>,
+    tx: mpsc::UnboundedSender<ServerEvent>,
+    events: Option<mpsc::UnboundedReceiver<ServerEvent>>,
+}
+
+struct Shared {
+    info: DeviceInfo,
+    dir: PathBuf,
+    pin: Option<String>,
+    on_offer: Box<OfferFilter>,
+    session: Mutex<Option<Session>>,
+    events: mpsc::UnboundedSender<ServerEvent>,
+}
+
+struct Session {
+    id: String,
+    sender: IpAddr,
+    /// File id to (info, token) for files not yet received
+    pending: HashMap<String, (FileInfo, String)>,
+}
+
+#[derive(Clone, Copy)]
+struct Remote(IpAddr);
+
+impl Server {
+    /// Listen on `addr`, saving into `dir`; every offer is accepted until [`Server::on_offer`] says otherwise
+    pub async fn bind(addr: SocketAddr, mut identity: Identity, dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        identity.set_port(listener.local_addr()?.port());
+        let (tx, rx) = mpsc::unbounded_channel();
+        Ok(Self { listener, identity, dir: dir.into(), pin: None, on_offer: Box::new(|_| true), tx, events: Some(rx) })
+    }
+
+    /// Require this PIN on `prepare-upload`
+    pub fn pin(mut self, pin: impl Into<String>) -> Self {
+        self.pin = Some(pin.into());
+        self
+    }
+
+    /// Decide which offers to take; declined ones get `403`
+    pub fn on_offer(mut self, filter: impl Fn(&PrepareUploadRequest) -> bool + Send + Sync + 'static) -> Self {
+        self.on_offer = Box::new(filter);
+        self
+    }
+
+    /// Our identity, with the port actually bound
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Event stream; `None` after the first call
+    pub fn events(&mut self) -> Option<mpsc::UnboundedReceiver<ServerEvent>> {
+        self.events.take()
+    }
+
+    /// Serve connections until the listener fails
+    pub async fn serve(self) -> io::Result<()> {
+        let acceptor = self.identity.tls().map(TlsAcceptor::from);
+        let router = router(Shared {
+            info: self.identity.info().clone(),
+            dir: self.dir,
+            pin: self.pin,
+            on_offer: self.on_offer,
+            session: Mutex::new(None),
+            events: self.tx,
+        });
+        loop {
+            let (tcp, remote) = self.listener.accept().await?;
+            let service = TowerToHyperService::new(router.clone().layer(Extension(Remote(remote.ip()))));
+            let tls = acceptor.clone();
+            tokio::spawn(async move {
+                let http = hyper::server::conn::http1::Builder::new();
+                match tls {
+                    Some(acceptor) => {
+                        if let Ok(stream) = acceptor.accept(tcp).await {
+                            let _ = http.serve_connection(TokioIo::new(stream), service).await;
+                        }
+                    }
+                    None => {
+                        let _ = http.serve_connection(TokioIo::new(tcp), service).await;
+                    }
+                }
+            });
+        }
+    }
+}
+
+fn router(shared: Shared) -> Router {
+    Router::new()
+        .route(&format!("{API_PREFIX}/info"), get(info))
+        .route(&format!("{API_PREFIX}/register"), post(register))
+        .route(&format!("{API_PREFIX}/prepare-upload"), post(prepare_upload))
+        .route(&format!("{API_PREFIX}/upload"), post(upload))
+        .route(&format!("{API_PREFIX}/cancel"), post(cancel))
+        .with_state(Arc::new(shared))
+}
+
+async fn info(State(shared): State<Arc<Shared>>) -> Json<InfoResponse> {
+    Json(InfoResponse::from(&shared.info))
+}
+
+async fn register(State(shared): State<Arc<Shared>>, Extension(Remote(addr)): Extension<Remote>, Json(info): Json<DeviceInfo>) -> Json<InfoResponse> {
+    let _ = shared.events.send(ServerEvent::Registered { info, addr });
+    Json(InfoResponse::from(&shared.info))
+}
+
+#[derive(Deserialize)]
+struct PinQuery {
+    pin: Option<String>,
+}
+
+async fn prepare_upload(
+    State(shared): State<Arc<Shared>>,
+    Extension(Remote(addr)): Extension<Remote>,
+    Query(query): Query<PinQuery>,
+    Json(request): Json<PrepareUploadRequest>,
+) -> Response {
+    if let Some(pin) = &shared.pin {
+        if !query.pin.as_deref().is_some_and(|given| ct::ct_eq(given.as_bytes(), pin.as_bytes())) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+    if shared.session.lock().unwrap().is_some() {
+        return StatusCode::CONFLICT.into_response();
+    }
+    if !(shared.on_offer)(&request) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if request.files.is_empty() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let mut guard = shared.session.lock().unwrap();
+    if guard.is_some() {
+        // another offer got in while we were asking
+        return StatusCode::CONFLICT.into_response();
+    }
+    let id = random_id();
+    let pending: HashMap<_, _> = request.files.iter().map(|(k, f)| (k.clone(), (f.clone(), random_id()))).collect();
+    let tokens = pending.iter().map(|(k, (_, token))| (k.clone(), token.clone())).collect();
+    *guard = Some(Session { id: id.clone(), sender: addr, pending });
+    let _ = shared.events.send(ServerEvent::Started { session: id.clone(), sender: request.info, files: request.files.into_values().collect() });
+    Json(PrepareUploadResponse { session_id: id, files: tokens }).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadQuery {
+    session_id: Option<String>,
+    file_id: Option<String>,
+    token: Option<String>,
+}
+
+async fn upload(State(shared): State<Arc<Shared>>, Extension(Remote(addr)): Extension<Remote>, Query(query): Query<UploadQuery>, body: Body) -> StatusCode {
+    let (Some(session_id), Some(file_id), Some(token)) = (query.session_id, query.file_id, query.token) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let file = {
+        let guard = shared.session.lock().unwrap();
+        match guard.as_ref() {
+            None => return StatusCode::CONFLICT,
+            Some(s) if s.id != session_id || s.sender != addr => return StatusCode::FORBIDDEN,
+            Some(s) => match s.pending.get(&file_id) {
+                Some((file, expected)) if ct::ct_eq(expected.as_bytes(), token.as_bytes()) => file.clone(),
+                _ => return StatusCode::FORBIDDEN,
+            },
+        }
+    };
+    let Some(path) = target_path(&shared.dir, &file.file_name) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    match save(body, file.size, &path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => return StatusCode::BAD_REQUEST,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    }
+
+    let mut guard = shared.session.lock().unwrap();
+    let Some(session) = guard.as_mut().filter(|s| s.id == session_id) else {
+        // cancelled mid-upload
+        return StatusCode::CONFLICT;
+    };
+    session.pending.remove(&file_id);
+    let _ = shared.events.send(ServerEvent::Received { session: session_id.clone(), path });
+    if session.pending.is_empty() {
+        *guard = None;
+        let _ = shared.events.send(ServerEvent::Finished { session: session_id });
+    }
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CancelQuery {
+    session_id: Option<String>,
+}
+
+async fn cancel(State(shared): State<Arc<Shared>>, Extension(Remote(addr)): Extension<Remote>, Query(query): Query<CancelQuery>) -> StatusCode {
+    let mut guard = shared.session.lock().unwrap();
+    match guard.as_ref() {
+        Some(s) if s.sender == addr && query.session_id.as_ref().is_none_or(|id| *id == s.id) => {
+            let _ = shared.events.send(ServerEvent::Cancelled { session: s.id.clone() });
+            *guard = None;
+            StatusCode::OK
+        }
+        _ => StatusCode::FORBIDDEN,
+    }
+}
+
+/// Write exactly `size` bytes of `body` into `path` by way of a `.part`
+/// file; a body of any other length is `InvalidData` and leaves nothing
+async fn save(body: Body, size: u64, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    let result = async {
+        let mut file = tokio::fs::File::create(&part).await?;
+        let body = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+        // one byte over is enough to tell the body is too long
+        let copied = tokio::io::copy(&mut body.take(size.saturating_add(1)), &mut file).await?;
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("body is not the {size} bytes offered")));
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&part, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&part).await;
+    }
+    result
+}
+
+/// Where a sent name goes under `dir`; `None` for names that would escape it
+fn target_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = Path::new(name);
+    if name.contains('\\') || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = dir.join(relative);
+    let (stem, ext) = (path.file_stem()?.to_string_lossy().into_owned(), path.extension().map(|e| e.to_string_lossy().into_owned()));
+    let mut candidate = path.clone();
+    for n in 1.. {
+        if !candidate.exists() {
+            break;
+        }
+        let name = match &ext {
+            Some(ext) => format!("{stem} ({n}).{ext}"),
+            None => format!("{stem} ({n})"),
+        };
+        candidate = path.with_file_name(name);
+    }
+    Some(candidate)
+}
+
+fn random_id() -> String {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use crate::model::PrepareUploadRequest;
+
+    async fn serve(dir: &Path, pin: Option<&str>) -> String {
+        let mut server = Server::bind("127.0.0.1:0".parse().unwrap(), Identity::http("receiver", 0), dir).await.unwrap();
+        if let Some(pin) = pin {
+            server = server.pin(pin);
+        }
+        let url = format!("http://{}{API_PREFIX}", server.local_addr().unwrap());
+        tokio::spawn(server.serve());
+        url
+    }
+
+    /// Offer one file named `name` of `size` bytes; its session id and token, or the refusal
+    async fn prepare(url: &str, name: &str, size: u64, pin: Option<&str>) -> Result<(String, String), reqwest::StatusCode> {
+        let file = FileInfo { id: "f".into(), file_name: name.into(), size, file_type: "text/plain".into(), sha256: None, preview: None };
+        let request = PrepareUploadRequest { info: Identity::http("sender", 0).info().clone(), files: HashMap::from([("f".into(), file)]) };
+        let response = reqwest::Client::new().post(format!("{url}/prepare-upload")).query(&[("pin", pin)]).json(&request).send().await.unwrap();
+        if !response.status().is_success() {
+            return Err(response.status());
+        }
+        let prepared: PrepareUploadResponse = response.json().await.unwrap();
+        Ok((prepared.session_id, prepared.files["f"].clone()))
+    }
+
+    async fn upload(http: &reqwest::Client, url: &str, session: &str, token: &str, body: &'static [u8]) -> reqwest::StatusCode {
+        http.post(format!("{url}/upload")).query(&[("sessionId", session), ("fileId", "f"), ("token", token)]).body(body).send().await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn uploads_take_only_the_offered_bytes_from_the_preparing_address_with_its_token() {
+        let dir = std::env::temp_dir().join(format!("globalsend-localsend-upload-{}", std::process::id()));
+        let url = serve(&dir, None).await;
+        let (session, token) = prepare(&url, "note.txt", 5, None).await.unwrap();
+        let http = reqwest::Client::new();
+        assert_eq!(upload(&http, &url, &session, "guess", b"hello").await, StatusCode::FORBIDDEN);
+        let elsewhere = reqwest::Client::builder().local_address("127.0.0.2".parse::<IpAddr>().unwrap()).build().unwrap();
+        assert_eq!(upload(&elsewhere, &url, &session, &token, b"hello").await, StatusCode::FORBIDDEN);
+        for body in [&b"hello, and then some"[..], b"hell"] {
+            assert_eq!(upload(&http, &url, &session, &token, body).await, StatusCode::BAD_REQUEST);
+            assert!(!dir.join("note.txt").exists() && !dir.join("note.txt.part").exists());
+        }
+        assert_eq!(upload(&http, &url, &session, &token, b"hello").await, StatusCode::OK);
+        assert_eq!(std::fs::read(dir.join("note.txt")).unwrap(), b"hello");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn names_that_leave_the_download_directory_and_wrong_pins_are_refused() {
+        let root = std::env::temp_dir().join(format!("globalsend-localsend-names-{}", std::process::id()));
+        let dir = root.join("in");
+        for name in ["../escaped.txt", "/tmp/escaped.txt", "a/../../escaped.txt", "..\\escaped.txt"] {
+            assert_eq!(target_path(&dir, name), None, "{name}");
+        }
+        let url = serve(&dir, Some("123456")).await;
+        assert_eq!(prepare(&url, "note.txt", 5, None).await, Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(prepare(&url, "note.txt", 5, Some("123457")).await, Err(StatusCode::UNAUTHORIZED));
+        let (session, token) = prepare(&url, "../escaped.txt", 5, Some("123456")).await.unwrap();
+        assert_eq!(upload(&reqwest::Client::new(), &url, &session, &token, b"hello").await, StatusCode::BAD_REQUEST);
+        assert!(!root.join("escaped.txt").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
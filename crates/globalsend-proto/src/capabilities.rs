@@ -0,0 +1,176 @@
+//! Optional features, negotiated per connection
+//!
+//! The protocol version says which messages a peer can decode; its
+//! [`Capabilities`] say which optional features it is willing to use, so a
+//! feature can ship turned off, or be left out of a build, without a
+//! version of its own. Both sides send theirs in [`HelloCapabilities`] right
+//! after the hellos once they agreed on [`CAPABILITIES_VERSION`] or later,
+//! and use what both have ([`negotiate_capabilities`]).
+//!
+//! The hellos themselves cannot carry them: every release reads hellos in
+//! version 1, whose decoders refuse a field they do not know. A peer too
+//! old to send capabilities is taken to have what its version implies
+//! ([`Capabilities::implied`]).
+//!
+//! Bits are never reused. Unknown bits from a newer peer are kept when
+//! decoding and fall away in the intersection.
+//!
+//! [`HelloCapabilities`]: crate::HelloCapabilities
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr};
+
+use serde::{Deserialize, Serialize};
+
+/// First version that exchanges [`HelloCapabilities`](crate::HelloCapabilities)
+pub const CAPABILITIES_VERSION: u16 = 9;
+
+/// Set of optional features
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// Chunks compressed with a codec from [`Compression`](crate::Compression)
+    pub const COMPRESSION: Self = Self(1 << 0);
+    /// Picking up a partial file where it stopped
+    pub const RESUME: Self = Self(1 << 1);
+    /// Sending only changed blocks against the receiver's copy
+    pub const DELTA: Self = Self(1 << 2);
+    /// Text and links as [`Snippet`](crate::Snippet)s
+    pub const CLIPBOARD: Self = Self(1 << 3);
+    /// Folders with a signed [`Manifest`](crate::Manifest)
+    pub const FOLDER: Self = Self(1 << 4);
+    /// Hybrid X25519 + ML-KEM session keys
+    pub const PQ_KEM: Self = Self(1 << 5);
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Any bits, including ones this build has no name for
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Every capability in [`REGISTRY`]
+    pub fn known() -> Self {
+        REGISTRY.iter().fold(Self::NONE, |all, c| all | c.flag)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// What a peer speaking `version` that sent no capabilities supports
+    pub fn implied(version: u16) -> Self {
+        REGISTRY.iter().filter(|c| c.implied_since.is_some_and(|since| since <= version)).fold(Self::NONE, |all, c| all | c.flag)
+    }
+
+    /// The capability called `name` in [`REGISTRY`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        REGISTRY.iter().find(|c| c.name == name).map(|c| c.flag)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Names joined by commas, e.g. `compression,delta`; unknown bits in hex
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut sep = |f: &mut fmt::Formatter<'_>| {
+            let s = if first { "" } else { "," };
+            first = false;
+            f.write_str(s)
+        };
+        for c in REGISTRY.iter().filter(|c| self.contains(c.flag)) {
+            sep(f)?;
+            f.write_str(c.name)?;
+        }
+        let unknown = self.0 & !Self::known().0;
+        if unknown != 0 {
+            sep(f)?;
+            write!(f, "{unknown:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One entry of [`REGISTRY`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub flag: Capabilities,
+    pub name: &'static str,
+    /// Version whose peers had it before capabilities were exchanged; `None`
+    /// for ones that have to be announced
+    pub implied_since: Option<u16>,
+}
+
+/// Every capability this build knows of
+pub const REGISTRY: [Capability; 6] = [
+    Capability { flag: Capabilities::COMPRESSION, name: "compression", implied_since: Some(3) },
+    Capability { flag: Capabilities::RESUME, name: "resume", implied_since: Some(1) },
+    Capability { flag: Capabilities::DELTA, name: "delta", implied_since: Some(4) },
+    Capability { flag: Capabilities::CLIPBOARD, name: "clipboard", implied_since: Some(6) },
+    Capability { flag: Capabilities::FOLDER, name: "folder", implied_since: Some(2) },
+    Capability { flag: Capabilities::PQ_KEM, name: "pq-kem", implied_since: None },
+];
+
+/// What a connection in `version` may use: ours and theirs, or ours and what
+/// their version implies when they sent none
+pub fn negotiate_capabilities(ours: Capabilities, theirs: Option<Capabilities>, version: u16) -> Capabilities {
+    ours & theirs.unwrap_or_else(|| Capabilities::implied(version)) & Capabilities::known()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asymmetric_capability_matrix() {
+        use Capabilities as C;
+        let everything = C::known();
+        let future = C::from_bits(1 << 40);
+        let sets = [C::NONE, C::COMPRESSION, C::DELTA | C::PQ_KEM, C::CLIPBOARD | C::FOLDER | C::RESUME, everything, everything | future];
+        for version in 1..=CAPABILITIES_VERSION {
+            for ours in sets {
+                // before capabilities, only what the version implies, and never pq-kem
+                let old = negotiate_capabilities(ours, None, version);
+                assert_eq!(old, ours & C::implied(version) & everything);
+                assert!(!old.contains(C::PQ_KEM));
+                for theirs in sets {
+                    let both = negotiate_capabilities(ours, Some(theirs), version);
+                    assert_eq!(both, negotiate_capabilities(theirs, Some(ours), version), "{ours} / {theirs}");
+                    assert!(ours.contains(both) && theirs.contains(both) && everything.contains(both));
+                    for c in REGISTRY {
+                        assert_eq!(both.contains(c.flag), ours.contains(c.flag) && theirs.contains(c.flag));
+                    }
+                }
+            }
+        }
+        assert_eq!(C::implied(1), C::RESUME);
+        assert_eq!(C::implied(3), C::RESUME | C::FOLDER | C::COMPRESSION);
+        assert_eq!(C::implied(CAPABILITIES_VERSION), C::COMPRESSION | C::RESUME | C::DELTA | C::CLIPBOARD | C::FOLDER);
+        assert_eq!((C::COMPRESSION | C::PQ_KEM | future).to_string(), "compression,pq-kem,0x10000000000");
+        assert_eq!(C::from_name("delta"), Some(C::DELTA));
+    }
+}
@@ -0,0 +1,202 @@
+//! Wire messages for globalsend
+//!
+//! Defines every protocol message ([`Message`]) and its binary encoding.
+//! A frame is the protocol version followed by the message in
+//! [postcard](https://postcard.jamesmunns.com/wire-format), a compact,
+//! deterministic serde format:
+//!
+//! ```text
+//! version (u16 BE) || postcard(Message)
+//! ```
+//!
+//! [`decode`] is strict: it rejects versions outside
+//! [`VersionRange::CURRENT`], trailing bytes and any encoding that
+//! [`encode`] would not have produced, so every message has exactly one
+//! valid frame. Framing on the stream (length prefixes) and encryption
+//! belong to the transport and crypto crates.
+
+use std::fmt;
+
+use postcard::Error as PostcardError;
+
+pub mod capabilities;
+pub mod message;
+pub mod version;
+
+pub use crate::message::{
+    Accept, Ack, BlockChecksum, BlockSignatures, Cancel, CancelReason, ChunkData, Codec, CompressedChunk, Compression, Decline, DeltaChunk, DeltaOp, FileHeader,
+    HashAck, Hello, HelloCapabilities, IdentityProof, IndexedFile, Manifest, ManifestMinisign, Message, Metadata, OfferedFile, PairRequest, Payload, Refusal, Snippet, SyncIndex, TransferId, TransferOffer, MAX_SNIPPET_LEN,
+};
+pub use crate::capabilities::{negotiate_capabilities, Capabilities, CAPABILITIES_VERSION};
+pub use crate::version::{negotiate, VersionRange, MIN_SUPPORTED_VERSION, PROTOCOL_VERSION};
+
+pub const VERSION_LEN: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtoError {
+    /// Frame ended before the message did
+    Truncated,
+    /// Frame is encoded in a version this build does not speak
+    UnsupportedVersion(u16),
+    /// The peers' [`VersionRange`]s do not overlap
+    NoCommonVersion { ours: VersionRange, theirs: VersionRange },
+    /// Unknown message type or invalid field value
+    Malformed,
+    /// Bytes left over after the message
+    TrailingBytes,
+    /// Valid message, but not in its one canonical encoding
+    NonCanonical,
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtoError::Truncated => write!(f, "truncated frame"),
+            ProtoError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {v}"),
+            ProtoError::NoCommonVersion { ours, theirs } => write!(
+                f,
+                "no common protocol version: we speak {}..={}, peer speaks {}..={}",
+                ours.min, ours.max, theirs.min, theirs.max
+            ),
+            ProtoError::Malformed => write!(f, "malformed message"),
+            ProtoError::TrailingBytes => write!(f, "trailing bytes after message"),
+            ProtoError::NonCanonical => write!(f, "non-canonical message encoding"),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+impl From<PostcardError> for ProtoError {
+    fn from(e: PostcardError) -> Self {
+        match e {
+            PostcardError::DeserializeUnexpectedEnd => ProtoError::Truncated,
+            _ => ProtoError::Malformed,
+        }
+    }
+}
+
+/// Encode `message` as a frame of protocol `version`
+pub fn encode(version: u16, message: &Message) -> Result<Vec<u8>, ProtoError> {
+    if !VersionRange::CURRENT.contains(version) || message.since() > version {
+        return Err(ProtoError::UnsupportedVersion(version));
+    }
+    Ok(postcard::to_extend(message, version.to_be_bytes().to_vec())?)
+}
+
+/// Decode a frame, returning its version and message
+pub fn decode(frame: &[u8]) -> Result<(u16, Message), ProtoError> {
+    let (version, body) = frame.split_first_chunk::<VERSION_LEN>().ok_or(ProtoError::Truncated)?;
+    let version = u16::from_be_bytes(*version);
+    if !VersionRange::CURRENT.contains(version) {
+        return Err(ProtoError::UnsupportedVersion(version));
+    }
+    let (message, rest) = postcard::take_from_bytes::<Message>(body)?;
+    if !rest.is_empty() {
+        return Err(ProtoError::TrailingBytes);
+    }
+    if message.since() > version {
+        return Err(ProtoError::Malformed);
+    }
+    // postcard accepts overlong varints; only the shortest form is valid here
+    if postcard::to_allocvec(&message)? != body {
+        return Err(ProtoError::NonCanonical);
+    }
+    Ok((version, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Message> {
+        let transfer = TransferId([7; 16]);
+        vec![
+            Hello { versions: VersionRange::CURRENT, device_name: "laptop".into(), fingerprint: [1; 32] }.into(),
+            HelloCapabilities { capabilities: Capabilities::COMPRESSION | Capabilities::from_bits(1 << 63) }.into(),
+            PairRequest { device_name: "phone".into(), fingerprint: [2; 32], exchange_key: [3; 32] }.into(),
+            TransferOffer {
+                transfer,
+                files: vec![
+                    OfferedFile { name: "a.txt".into(), size: 5, mime: Some("text/plain".into()) },
+                    OfferedFile { name: "b".into(), size: 1 << 40, mime: None },
+                ],
+            }
+            .into(),
+            FileHeader { transfer, index: 1, size: 1 << 40, chunk_size: 1 << 20, hash: [4; 32] }.into(),
+            ChunkData { transfer, index: 1, offset: 300, data: vec![0xaa; 1000] }.into(),
+            Ack { transfer, index: 0, offset: u64::MAX }.into(),
+            HashAck { transfer, index: 2, offset: 1 << 24, hash: [8; 32] }.into(),
+            Cancel { transfer, reason: CancelReason::User }.into(),
+            Cancel { transfer, reason: CancelReason::Declined }.into(),
+            Cancel { transfer, reason: CancelReason::Failed }.into(),
+            Cancel { transfer, reason: CancelReason::Timeout }.into(),
+            Compression { transfer, codecs: vec![Codec::Zstd] }.into(),
+            CompressedChunk { transfer, index: 2, offset: 1 << 20, codec: Codec::Zstd, data: vec![0x28; 40] }.into(),
+            BlockSignatures { transfer, index: 0, size: 9000, block_size: 4096, first: 2, blocks: vec![BlockChecksum { weak: 0xdead_beef, strong: [6; 16] }] }
+                .into(),
+            DeltaChunk { transfer, index: 0, offset: 4096, ops: vec![DeltaOp::Copy { block: 1, count: 3 }, DeltaOp::Literal(vec![9; 17])] }.into(),
+            Snippet { transfer, payload: Payload::Text("see you at 8".into()) }.into(),
+            Snippet { transfer, payload: Payload::Url("https://example.com/a?b=c".into()) }.into(),
+            Decline {
+                transfer,
+                refusals: vec![
+                    Refusal::DiskSpace { needed: 1 << 40, available: 1 << 30 },
+                    Refusal::NotWritable,
+                    Refusal::Exists { index: 3 },
+                    Refusal::BadName { index: 4 },
+                ],
+            }
+            .into(),
+            Metadata { transfer, envelope: vec![0x5a; 300] }.into(),
+            Accept { transfer, files: vec![true, false, true] }.into(),
+            IdentityProof { identity_key: [4; 32], signature: vec![6; 64] }.into(),
+            SyncIndex { folder: "photos".into(), files: vec![IndexedFile { name: "2024/a.jpg".into(), size: 1 << 22, modified: 1_700_000_000, hash: [3; 32] }] }.into(),
+            ManifestMinisign { transfer, signature: "untrusted comment: x\nRUQ=\ntrusted comment: y\nAA==\n".into() }.into(),
+            Manifest { transfer, manifest: vec![0xa4; 90], signature: vec![5; 64] }.into(),
+        ]
+    }
+
+    #[test]
+    fn every_message_roundtrips_and_rejects_corruption() {
+        for message in samples() {
+            let frame = encode(PROTOCOL_VERSION, &message).unwrap();
+            assert_eq!(decode(&frame), Ok((PROTOCOL_VERSION, message.clone())));
+
+            // every strict prefix is an error, never a different message
+            for len in 0..frame.len() {
+                assert!(decode(&frame[..len]).is_err(), "{message:?} decoded from {len} bytes");
+            }
+            let mut long = frame.clone();
+            long.push(0);
+            assert_eq!(decode(&long), Err(ProtoError::TrailingBytes));
+
+            let mut future = frame.clone();
+            future[..VERSION_LEN].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+            assert_eq!(decode(&future), Err(ProtoError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+        }
+        assert_eq!(encode(0, &samples()[0]), Err(ProtoError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn rejects_unknown_tags_and_overlong_encodings() {
+        let v = PROTOCOL_VERSION.to_be_bytes();
+        // message tag 21 does not exist
+        assert_eq!(decode(&[v[0], v[1], 21]), Err(ProtoError::Malformed));
+        // nor does a manifest in version 1
+        let manifest = samples().pop().unwrap();
+        assert_eq!(encode(1, &manifest), Err(ProtoError::UnsupportedVersion(1)));
+        let mut frame = encode(PROTOCOL_VERSION, &manifest).unwrap();
+        frame[..VERSION_LEN].copy_from_slice(&1u16.to_be_bytes());
+        assert_eq!(decode(&frame), Err(ProtoError::Malformed));
+        // Cancel with reason tag 4
+        let mut frame = encode(PROTOCOL_VERSION, &samples()[8]).unwrap();
+        *frame.last_mut().unwrap() = 4;
+        assert_eq!(decode(&frame), Err(ProtoError::Malformed));
+        // Ack with offset 5 as a two-byte varint
+        let mut frame = vec![v[0], v[1], 5];
+        frame.extend_from_slice(&[7; 16]);
+        frame.extend_from_slice(&[0, 0x85, 0x00]);
+        assert_eq!(decode(&frame), Err(ProtoError::NonCanonical));
+    }
+}
@@ -0,0 +1,390 @@
+//! Protocol messages
+//!
+//! These travel inside the encrypted session (see `globalsend-crypto`), so
+//! they carry names and sizes in the clear as far as this crate is
+//! concerned. Keys and fingerprints are raw bytes; checking them is the
+//! crypto layer's job.
+//!
+//! Variants and fields are append-only within a protocol version: a new
+//! field or variant needs a new [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION).
+
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::Capabilities;
+use crate::version::VersionRange;
+
+/// Random identifier the sender picks for one transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TransferId(pub [u8; 16]);
+
+/// First message on every connection, in both directions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub versions: VersionRange,
+    /// Human-readable name shown to the other side
+    pub device_name: String,
+    /// Fingerprint of the device identity key
+    pub fingerprint: [u8; 32],
+}
+
+/// Optional features the sender is willing to use (since version 9)
+///
+/// Each side sends one right after the hellos; see [`capabilities`](crate::capabilities).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelloCapabilities {
+    pub capabilities: Capabilities,
+}
+
+/// Ask an unpaired device to trust us; answered out of band by PIN or QR
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairRequest {
+    pub device_name: String,
+    pub fingerprint: [u8; 32],
+    /// X25519 key the receiver should pin for this device
+    pub exchange_key: [u8; 32],
+}
+
+/// One entry of a [`TransferOffer`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfferedFile {
+    pub name: String,
+    pub size: u64,
+    pub mime: Option<String>,
+}
+
+/// Files the sender wants to send; the receiver answers with [`Ack`],
+/// [`Accept`] for some of them, or [`Cancel`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferOffer {
+    pub transfer: TransferId,
+    pub files: Vec<OfferedFile>,
+}
+
+/// Start of file `index` of an accepted offer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHeader {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub size: u64,
+    /// Size of every [`ChunkData`] but the last
+    pub chunk_size: u32,
+    /// BLAKE3 of the whole file
+    pub hash: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkData {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Receiver has everything of file `index` below `offset`.
+///
+/// The first ack after a [`TransferOffer`] accepts it: `(0, 0)` for a
+/// fresh transfer, anything else resumes one from that point. From
+/// version 5 receivers also ack every chunk once it is written, which is
+/// what paces a pipelining sender; `offset` equal to the file size still
+/// only comes after the file's hash checked out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ack {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelReason {
+    /// The user stopped the transfer
+    User,
+    /// The receiver declined the offer
+    Declined,
+    /// Disk full, unreadable file or similar local failure
+    Failed,
+    /// Peer stopped responding
+    Timeout,
+}
+
+/// Abort a transfer from either side
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cancel {
+    pub transfer: TransferId,
+    pub reason: CancelReason,
+}
+
+/// Signed folder manifest for the [`TransferOffer`] it follows (since version 2)
+///
+/// `manifest` is the canonical encoding and `signature` the detached Ed25519
+/// signature from `globalsend_crypto::manifest`; the receiver checks both
+/// before accepting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub transfer: TransferId,
+    pub manifest: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Chunk compression codecs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    Zstd,
+}
+
+/// Compression negotiation for a transfer (since version 3)
+///
+/// The sender lists what it can compress with right after the offer; a
+/// receiver that wants compression answers with the one codec it picked,
+/// before accepting. No answer means chunks go uncompressed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compression {
+    pub transfer: TransferId,
+    pub codecs: Vec<Codec>,
+}
+
+/// [`ChunkData`] compressed with the negotiated codec (since version 3)
+///
+/// `offset` counts uncompressed bytes; the chunk decompresses to at most
+/// the file's chunk size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressedChunk {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub offset: u64,
+    pub codec: Codec,
+    pub data: Vec<u8>,
+}
+
+/// Checksums of one block of a file the receiver already has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockChecksum {
+    /// Rolling checksum, for finding the block at any offset
+    pub weak: u32,
+    /// Truncated BLAKE3, for confirming a weak match
+    pub strong: [u8; 16],
+}
+
+/// The receiver's older copy of offered file `index`, so the sender can
+/// send only what changed (since version 4)
+///
+/// Sent before accepting. Signatures too big for one frame are split over
+/// several messages; `first` is the block number of `blocks[0]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSignatures {
+    pub transfer: TransferId,
+    pub index: u32,
+    /// Size of the receiver's copy; its last block may be short
+    pub size: u64,
+    pub block_size: u32,
+    pub first: u32,
+    pub blocks: Vec<BlockChecksum>,
+}
+
+/// One step in rebuilding a file from the receiver's older copy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeltaOp {
+    /// `count` blocks of the old copy, starting at block `block`
+    Copy { block: u32, count: u32 },
+    /// Bytes the old copy does not have
+    Literal(Vec<u8>),
+}
+
+/// Part of file `index` as changes against [`BlockSignatures`] (since version 4)
+///
+/// Takes the place of [`ChunkData`] for the file; `offset` counts bytes of
+/// the rebuilt file. Literals add up to at most the file's chunk size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaChunk {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub offset: u64,
+    pub ops: Vec<DeltaOp>,
+}
+
+/// Longest [`Payload`], in bytes
+pub const MAX_SNIPPET_LEN: usize = 64 * 1024;
+
+/// What a [`Snippet`] carries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Payload {
+    Text(String),
+    /// Absolute `http` or `https` URL
+    Url(String),
+}
+
+/// A short text or link sent whole instead of as a file (since version 6)
+///
+/// Nothing is written to disk on either side. The receiver answers with
+/// [`Ack`] `(0, 0)` once the user copied or opened it, or [`Cancel`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snippet {
+    pub transfer: TransferId,
+    pub payload: Payload,
+}
+
+/// One reason a receiver cannot take an offer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Refusal {
+    /// Not enough free space in the destination
+    DiskSpace { needed: u64, available: u64 },
+    /// The destination cannot be written to
+    NotWritable,
+    /// File `index` would replace something already there
+    Exists { index: u32 },
+    /// File `index`'s name cannot be created on the receiver
+    BadName { index: u32 },
+}
+
+/// The receiver's checks refused the offer (since version 7)
+///
+/// Takes the place of [`Cancel`] with [`CancelReason::Declined`] so the
+/// sender can tell its user what to fix instead of failing mid-transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decline {
+    pub transfer: TransferId,
+    pub refusals: Vec<Refusal>,
+}
+
+/// The [`Manifest`] signed again in the minisign format (since version 8)
+///
+/// Sent right after the manifest, so the receiver can keep both as files
+/// anyone can check with `minisign -V`; see `globalsend_crypto::minisign`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestMinisign {
+    pub transfer: TransferId,
+    /// `.minisig` file contents
+    pub signature: String,
+}
+
+/// [`Ack`] that also says what the receiver wrote (since version 10)
+///
+/// `hash` is the BLAKE3 of file `index`'s first `offset` bytes as written
+/// out. Receivers send one in place of the ack for every so many chunks;
+/// a sender whose own bytes hash differently stops the transfer there
+/// instead of finding out at the final hash check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashAck {
+    pub transfer: TransferId,
+    pub index: u32,
+    pub offset: u64,
+    pub hash: [u8; 32],
+}
+
+/// Names, types and previews of the offered files, sealed (since version 11)
+///
+/// `envelope` is a `globalsend_crypto::meta::MetaEnvelope` for the session
+/// the message travels in. Sent right after the offer, so the receiver can
+/// show previews while it asks; it has to check them before it does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub transfer: TransferId,
+    pub envelope: Vec<u8>,
+}
+
+/// Acceptance of some of the offered files (since version 12)
+///
+/// `files` has one decision per offered file, in offer order: `true` to
+/// take it. Takes the place of the accepting `Ack(0, 0)`; the sender skips
+/// the files turned down and the transfer is done when the rest are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Accept {
+    pub transfer: TransferId,
+    pub files: Vec<bool>,
+}
+
+/// One file of a [`SyncIndex`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedFile {
+    /// Path in the folder, parts joined with `/`
+    pub name: String,
+    pub size: u64,
+    /// Modification time, seconds since the Unix epoch
+    pub modified: u64,
+    /// BLAKE3 of the contents
+    pub hash: [u8; 32],
+}
+
+/// What a synced folder holds (since version 13)
+///
+/// Sent in place of an offer, right after the hellos: the side starting a
+/// sync run sends its index of `folder` and the other answers with its own,
+/// or with [`Cancel`] if it does not sync that folder with the sender. The
+/// starting side may then send a [`TransferOffer`] for what the other lacks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncIndex {
+    pub folder: String,
+    pub files: Vec<IndexedFile>,
+}
+
+/// The sender's identity key, signed into this session (since version 14)
+///
+/// Each side sends one right after the hellos and capabilities. `signature`
+/// is `globalsend_crypto::certificate::verify_session_proof` material: the
+/// identity key over the exchange key and handshake hash of the connection
+/// it travels on. It proves the fingerprint in the sender's [`Hello`] is
+/// that of the device the handshake authenticated, and not just a claim.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityProof {
+    /// Ed25519 verifying key; hashes to the fingerprint in the hello
+    pub identity_key: [u8; 32],
+    pub signature: Vec<u8>,
+}
+
+/// Every message that can appear in a frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    Hello(Hello),
+    PairRequest(PairRequest),
+    TransferOffer(TransferOffer),
+    FileHeader(FileHeader),
+    ChunkData(ChunkData),
+    Ack(Ack),
+    Cancel(Cancel),
+    Manifest(Manifest),
+    Compression(Compression),
+    CompressedChunk(CompressedChunk),
+    BlockSignatures(BlockSignatures),
+    DeltaChunk(DeltaChunk),
+    Snippet(Snippet),
+    Decline(Decline),
+    ManifestMinisign(ManifestMinisign),
+    HelloCapabilities(HelloCapabilities),
+    HashAck(HashAck),
+    Metadata(Metadata),
+    Accept(Accept),
+    SyncIndex(SyncIndex),
+    IdentityProof(IdentityProof),
+}
+
+impl Message {
+    /// First protocol version that has this message
+    pub fn since(&self) -> u16 {
+        match self {
+            Message::Manifest(_) => 2,
+            Message::Compression(_) | Message::CompressedChunk(_) => 3,
+            Message::BlockSignatures(_) | Message::DeltaChunk(_) => 4,
+            Message::Snippet(_) => 6,
+            Message::Decline(_) => 7,
+            Message::ManifestMinisign(_) => 8,
+            Message::HelloCapabilities(_) => 9,
+            Message::HashAck(_) => 10,
+            Message::Metadata(_) => 11,
+            Message::Accept(_) => 12,
+            Message::SyncIndex(_) => 13,
+            Message::IdentityProof(_) => 14,
+            _ => 1,
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($($variant:ident),*) => {
+        $(impl From<$variant> for Message {
+            fn from(m: $variant) -> Self {
+                Message::$variant(m)
+            }
+        })*
+    };
+}
+
+impl_from!(Hello, PairRequest, TransferOffer, FileHeader, ChunkData, Ack, Cancel, Manifest, Compression, CompressedChunk, BlockSignatures, DeltaChunk, Snippet, Decline, ManifestMinisign, HelloCapabilities, HashAck, Metadata, Accept, SyncIndex, IdentityProof);
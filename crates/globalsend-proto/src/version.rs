@@ -0,0 +1,60 @@
+//! Protocol version negotiation
+//!
+//! Every frame starts with the protocol version it is encoded in. A
+//! connection opens with a [`Hello`](crate::Hello) from each side carrying the
+//! [`VersionRange`] it speaks; both pick the highest version in the
+//! intersection with [`negotiate`] and encode everything after the hellos in
+//! that version. Hellos themselves are always encoded in
+//! [`MIN_SUPPORTED_VERSION`], so any two releases can read each other's.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ProtoError;
+
+/// Newest version this build encodes
+pub const PROTOCOL_VERSION: u16 = 15;
+/// Oldest version this build still decodes
+pub const MIN_SUPPORTED_VERSION: u16 = 1;
+
+/// Inclusive range of protocol versions a peer speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl VersionRange {
+    /// What this build supports
+    pub const CURRENT: VersionRange = VersionRange { min: MIN_SUPPORTED_VERSION, max: PROTOCOL_VERSION };
+
+    pub fn contains(&self, version: u16) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+}
+
+/// Highest version in both ranges
+pub fn negotiate(ours: VersionRange, theirs: VersionRange) -> Result<u16, ProtoError> {
+    let version = ours.max.min(theirs.max);
+    if version < ours.min.max(theirs.min) {
+        return Err(ProtoError::NoCommonVersion { ours, theirs });
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_highest_common_version() {
+        let range = |min, max| VersionRange { min, max };
+        assert_eq!(negotiate(range(1, 3), range(2, 5)), Ok(3));
+        assert_eq!(negotiate(range(2, 5), range(1, 3)), Ok(3));
+        assert_eq!(negotiate(range(1, 1), range(1, 1)), Ok(1));
+        assert_eq!(
+            negotiate(range(1, 2), range(3, 4)),
+            Err(ProtoError::NoCommonVersion { ours: range(1, 2), theirs: range(3, 4) })
+        );
+        assert!(VersionRange::CURRENT.contains(PROTOCOL_VERSION));
+    }
+}
@@ -0,0 +1,250 @@
+//! Relay server for peers without a direct path
+//!
+//! Pairs two TCP connections that name the same
+//! [`RelaySession`](globalsend_transport::relay::RelaySession) and copies
+//! bytes between them, as described in [`globalsend_transport::relay`]. The
+//! relay holds no keys: everything after the join is a Noise handshake and
+//! sealed frames it cannot read or alter undetected. What it does enforce is
+//! how long a peer may wait for its partner, how many sessions run at once,
+//! and a bandwidth cap per session.
+//!
+//! Each connection gets a `relay` tracing span. Metrics:
+//! `globalsend_relay_sessions_active` (gauge), `globalsend_relay_bytes_total`
+//! (both directions), and `globalsend_relay_joins_total` by `result`:
+//! `paired`, `timeout`, `busy` or `bad_request`.
+//!
+//! The same pairing serves as the rendezvous for wormhole codes
+//! ([`globalsend_transport::wormhole`]): both holders of a code join under a
+//! session derived from its nameplate and run the PAKE through the relay.
+
+pub mod limit;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use globalsend_transport::relay::{RelaySession, JOIN_LEN, STATUS_BAD_REQUEST, STATUS_BUSY, STATUS_PAIRED, STATUS_TIMEOUT};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time;
+use tracing::Instrument;
+
+use crate::limit::Bucket;
+
+pub const DEFAULT_PORT: u16 = 7878;
+/// 4 MiB/s per session
+pub const DEFAULT_BANDWIDTH: u64 = 4 << 20;
+pub const DEFAULT_PAIR_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_SESSIONS: usize = 1024;
+/// How long a new connection gets to send its join request
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+const COPY_BUF: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayConfig {
+    /// Bytes per second per session, both directions together
+    pub bandwidth: u64,
+    /// How long the first peer waits for the second
+    pub pair_timeout: Duration,
+    /// Sessions waiting or running at once
+    pub max_sessions: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self { bandwidth: DEFAULT_BANDWIDTH, pair_timeout: DEFAULT_PAIR_TIMEOUT, max_sessions: DEFAULT_MAX_SESSIONS }
+    }
+}
+
+struct State {
+    config: RelayConfig,
+    waiting: Mutex<HashMap<RelaySession, oneshot::Sender<TcpStream>>>,
+    sessions: AtomicUsize,
+}
+
+pub struct Relay {
+    listener: TcpListener,
+    state: Arc<State>,
+}
+
+impl Relay {
+    pub async fn bind(addr: SocketAddr, config: RelayConfig) -> io::Result<Self> {
+        let state = State { config, waiting: Mutex::new(HashMap::new()), sessions: AtomicUsize::new(0) };
+        Ok(Self { listener: TcpListener::bind(addr).await?, state: Arc::new(state) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept and pair connections until the listener fails
+    pub async fn run(self) -> io::Result<()> {
+        loop {
+            let (stream, from) = self.listener.accept().await?;
+            let state = self.state.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = handle(stream, state).await {
+                        tracing::debug!(error = %e, "connection ended");
+                    }
+                }
+                .instrument(tracing::info_span!("relay", %from)),
+            );
+        }
+    }
+}
+
+/// Counts a session against the limit until dropped
+struct SessionSlot(Arc<State>);
+
+impl SessionSlot {
+    fn new(state: Arc<State>) -> Self {
+        metrics::gauge!("globalsend_relay_sessions_active").increment(1.0);
+        Self(state)
+    }
+}
+
+impl Drop for SessionSlot {
+    fn drop(&mut self) {
+        self.0.sessions.fetch_sub(1, Ordering::Relaxed);
+        metrics::gauge!("globalsend_relay_sessions_active").decrement(1.0);
+    }
+}
+
+/// Count a join by how it ended and answer it with `status`
+async fn reply(stream: &mut TcpStream, status: u8, result: &'static str) -> io::Result<()> {
+    tracing::debug!(result, "join refused");
+    metrics::counter!("globalsend_relay_joins_total", "result" => result).increment(1);
+    stream.write_u8(status).await
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<State>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut join = [0u8; JOIN_LEN];
+    let session = match time::timeout(JOIN_TIMEOUT, stream.read_exact(&mut join)).await {
+        Ok(Ok(_)) => RelaySession::from_join_request(&join),
+        _ => None,
+    };
+    let Some(session) = session else {
+        return reply(&mut stream, STATUS_BAD_REQUEST, "bad_request").await;
+    };
+
+    // second to arrive: hand our stream to the waiting peer's task
+    let waiting = state.waiting.lock().unwrap().remove(&session);
+    if let Some(partner) = waiting {
+        if let Err(mut stream) = partner.send(stream) {
+            // the waiter gave up a moment ago
+            return reply(&mut stream, STATUS_TIMEOUT, "timeout").await;
+        }
+        return Ok(());
+    }
+
+    if state.sessions.fetch_add(1, Ordering::Relaxed) >= state.config.max_sessions {
+        state.sessions.fetch_sub(1, Ordering::Relaxed);
+        return reply(&mut stream, STATUS_BUSY, "busy").await;
+    }
+    let _slot = SessionSlot::new(state.clone());
+    let (tx, rx) = oneshot::channel();
+    state.waiting.lock().unwrap().insert(session, tx);
+    let other = match time::timeout(state.config.pair_timeout, rx).await {
+        Ok(Ok(other)) => other,
+        _ => {
+            state.waiting.lock().unwrap().remove(&session);
+            return reply(&mut stream, STATUS_TIMEOUT, "timeout").await;
+        }
+    };
+    metrics::counter!("globalsend_relay_joins_total", "result" => "paired").increment(1);
+    tracing::info!("paired");
+    let pumped = pump(stream, other, Bucket::new(state.config.bandwidth)).await;
+    tracing::info!("session ended");
+    pumped
+}
+
+async fn pump(mut a: TcpStream, mut b: TcpStream, bucket: Bucket) -> io::Result<()> {
+    a.write_u8(STATUS_PAIRED).await?;
+    b.write_u8(STATUS_PAIRED).await?;
+    let (mut ar, mut aw) = a.split();
+    let (mut br, mut bw) = b.split();
+    tokio::try_join!(copy(&mut ar, &mut bw, &bucket), copy(&mut br, &mut aw, &bucket))?;
+    Ok(())
+}
+
+/// Copy until EOF, then pass the EOF on
+async fn copy<R, W>(reader: &mut R, writer: &mut W, bucket: &Bucket) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; COPY_BUF];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        bucket.take(n).await;
+        writer.write_all(&buf[..n]).await?;
+        metrics::counter!("globalsend_relay_bytes_total").increment(n as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use globalsend_crypto::handshake::Role;
+    use globalsend_crypto::pairing::{PairingError, WormholeCode};
+    use globalsend_crypto::DeviceKey;
+    use globalsend_proto::{ChunkData, Message, TransferId};
+    use globalsend_transport::relay::{self, RelayError};
+    use globalsend_transport::wormhole::{self, WormholeError};
+
+    #[tokio::test]
+    async fn pairs_peers_and_forwards_sealed_frames() {
+        let config = RelayConfig { pair_timeout: Duration::from_millis(200), ..Default::default() };
+        let server = Relay::bind("127.0.0.1:0".parse().unwrap(), config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let session = RelaySession::random();
+        let ((mut ca, pa), (mut cb, pb)) = tokio::try_join!(
+            relay::connect(addr, &session, Role::Initiator, &a),
+            relay::connect(addr, &session, Role::Responder, &b),
+        )
+        .unwrap();
+        assert_eq!((pa.static_key, pb.static_key), (b.public(), a.public()));
+
+        let chunk: Message = ChunkData { transfer: TransferId([1; 16]), index: 0, offset: 0, data: vec![9; 50_000] }.into();
+        ca.send(chunk.clone()).await.unwrap();
+        assert_eq!(cb.next().await.unwrap().unwrap(), chunk);
+
+        let alone = relay::connect(addr, &RelaySession::random(), Role::Initiator, &a).await;
+        assert!(matches!(alone, Err(RelayError::Refused(STATUS_TIMEOUT))));
+    }
+
+    #[tokio::test]
+    async fn wormhole_code_meets_at_relay() {
+        let server = Relay::bind("127.0.0.1:0".parse().unwrap(), RelayConfig::default()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let code = WormholeCode::generate();
+        let typed = WormholeCode::parse(&code.as_str().to_uppercase()).unwrap();
+        let ((mut ca, pa), (mut cb, pb)) = tokio::try_join!(wormhole::send(addr, &code, &a), wormhole::receive(addr, &typed, &b)).unwrap();
+        assert_eq!((pa.static_key, pb.static_key), (b.public(), a.public()));
+        let chunk: Message = ChunkData { transfer: TransferId([2; 16]), index: 0, offset: 0, data: vec![7; 1000] }.into();
+        cb.send(chunk.clone()).await.unwrap();
+        assert_eq!(ca.next().await.unwrap().unwrap(), chunk);
+
+        // same nameplate, wrong words
+        let wrong = WormholeCode::parse(&format!("{}-abandon-zoo", code.nameplate())).unwrap();
+        let (sent, received) = tokio::join!(wormhole::send(addr, &code, &a), wormhole::receive(addr, &wrong, &b));
+        assert!(matches!(sent, Err(WormholeError::Pairing(PairingError::ConfirmationFailed))));
+        assert!(matches!(received, Err(WormholeError::Pairing(PairingError::ConfirmationFailed))));
+    }
+}
@@ -0,0 +1,55 @@
+//! Per-session bandwidth cap
+//!
+//! One token bucket per session, shared by both directions: a byte costs a
+//! token, tokens refill at the cap, and the bucket holds at most one
+//! second's worth so an idle session cannot save up a burst.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{self, Instant};
+
+pub struct Bucket {
+    rate: u64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    /// `rate` bytes per second
+    pub fn new(rate: u64) -> Self {
+        Self { rate, state: Mutex::new((rate as f64, Instant::now())) }
+    }
+
+    /// Wait until `bytes` may pass
+    pub async fn take(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate as f64).min(self.rate as f64);
+            *last = now;
+            // going negative reserves future tokens, so concurrent takers queue up fairly
+            *tokens -= bytes as f64;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / self.rate as f64))
+        };
+        if let Some(wait) = wait {
+            time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_throughput() {
+        let bucket = Bucket::new(1000);
+        let start = Instant::now();
+        // the first second's worth is already in the bucket
+        for _ in 0..30 {
+            bucket.take(100).await;
+        }
+        assert_eq!(start.elapsed().as_secs(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+//! `globalsend-relay [--listen ADDR] [--bandwidth BYTES_PER_SEC] [--max-sessions N] [--pair-timeout SECS] [--metrics ADDR]`
+//!
+//! Logs go to stderr, filtered by `GLOBALSEND_LOG` (`info` by default).
+
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use globalsend_relay::{Relay, RelayConfig, DEFAULT_PORT};
+
+const USAGE: &str = "usage: globalsend-relay [--listen ADDR] [--bandwidth BYTES_PER_SEC] [--max-sessions N] [--pair-timeout SECS] [--metrics ADDR]";
+
+struct Args {
+    listen: SocketAddr,
+    config: RelayConfig,
+    /// Where to serve Prometheus metrics
+    metrics: Option<SocketAddr>,
+}
+
+fn parse() -> Result<Args, String> {
+    let mut listen = SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT));
+    let mut metrics = None;
+    let mut config = RelayConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{flag} needs a value"));
+        let bad = |e: &dyn std::fmt::Display| format!("{flag}: {e}");
+        match flag.as_str() {
+            "--listen" => listen = value()?.parse().map_err(|e| bad(&e))?,
+            "--bandwidth" => config.bandwidth = value()?.parse().map_err(|e| bad(&e))?,
+            "--max-sessions" => config.max_sessions = value()?.parse().map_err(|e| bad(&e))?,
+            "--pair-timeout" => config.pair_timeout = Duration::from_secs(value()?.parse().map_err(|e| bad(&e))?),
+            "--metrics" if cfg!(feature = "prometheus") => metrics = Some(value()?.parse().map_err(|e| bad(&e))?),
+            "--metrics" => return Err("--metrics needs a build with the prometheus feature".into()),
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ => return Err(format!("unknown argument {flag}\n{USAGE}")),
+        }
+    }
+    if config.bandwidth == 0 {
+        return Err("--bandwidth must be positive".into());
+    }
+    Ok(Args { listen, config, metrics })
+}
+
+fn init_logging() {
+    let filter = tracing_subscriber::EnvFilter::try_from_env("GLOBALSEND_LOG").unwrap_or_else(|_| "info".into());
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
+}
+
+#[cfg(feature = "prometheus")]
+fn serve_metrics(addr: SocketAddr) -> Result<(), String> {
+    metrics_exporter_prometheus::PrometheusBuilder::new().with_http_listener(addr).install().map_err(|e| format!("cannot serve metrics on {addr}: {e}"))
+}
+
+#[cfg(not(feature = "prometheus"))]
+fn serve_metrics(_: SocketAddr) -> Result<(), String> {
+    unreachable!("rejected while parsing arguments")
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Args { listen, config, metrics } = match parse() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    init_logging();
+    if let Some(addr) = metrics {
+        if let Err(e) = serve_metrics(addr) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    }
+    let relay = match Relay::bind(listen, config).await {
+        Ok(relay) => relay,
+        Err(e) => {
+            eprintln!("cannot listen on {listen}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("relay listening on {}", relay.local_addr().unwrap_or(listen));
+    if let Err(e) = relay.run().await {
+        eprintln!("relay stopped: {e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,411 @@
+//! Transfer history
+//!
+//! One row per finished transfer and one per file in it. A transfer
+//! recorded again (say, after a resume finally completed it) replaces its
+//! earlier row. Timestamps are Unix seconds; names, sizes and hashes are
+//! what the offer and file headers said, and `path` is where the file was
+//! read from or written to, for sending it again. `verified` says whether
+//! the peer proved its fingerprint in the handshake or only claimed it.
+
+use std::path::{Path, PathBuf};
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_proto::{CancelReason, TransferId};
+use globalsend_transfer::{Direction, TransferSession, TransferState};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+
+use crate::StoreError;
+
+/// `PRAGMA user_version` of the current schema
+pub const SCHEMA_VERSION: u32 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE transfers (
+    id INTEGER PRIMARY KEY,
+    transfer BLOB NOT NULL UNIQUE,
+    peer BLOB NOT NULL,
+    verified INTEGER NOT NULL DEFAULT 0,
+    peer_name TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    error TEXT,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL
+);
+CREATE INDEX transfers_finished ON transfers (finished_at);
+CREATE INDEX transfers_peer ON transfers (peer, finished_at);
+CREATE TABLE files (
+    transfer INTEGER NOT NULL REFERENCES transfers (id) ON DELETE CASCADE,
+    idx INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    hash BLOB,
+    path TEXT,
+    PRIMARY KEY (transfer, idx)
+);
+";
+
+/// From schema 1, where every peer was taken at its word
+const MIGRATE_1: &str = "ALTER TABLE transfers ADD COLUMN verified INTEGER NOT NULL DEFAULT 0;";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Completed,
+    Declined,
+    Cancelled,
+    Failed,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Completed => "completed",
+            Outcome::Declined => "declined",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "completed" => Outcome::Completed,
+            "declined" => Outcome::Declined,
+            "cancelled" => Outcome::Cancelled,
+            "failed" => Outcome::Failed,
+            _ => return None,
+        })
+    }
+}
+
+fn direction_str(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Send => "send",
+        Direction::Receive => "receive",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRecord {
+    pub name: String,
+    pub size: u64,
+    /// Sent or received before the transfer ended
+    pub bytes: u64,
+    pub hash: Option<[u8; 32]>,
+    /// Local file it was read from or written to
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    pub transfer: TransferId,
+    pub peer: Fingerprint,
+    /// The peer proved it holds `peer`'s key, rather than only claiming it
+    pub verified: bool,
+    /// Name the peer gave itself at the time
+    pub peer_name: String,
+    pub direction: Direction,
+    pub outcome: Outcome,
+    /// Why it failed, for [`Outcome::Failed`]
+    pub error: Option<String>,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub files: Vec<FileRecord>,
+}
+
+impl TransferRecord {
+    /// What `session` ended with; `None` while it is still running. File
+    /// paths and `verified` are left for the caller to fill in.
+    pub fn from_session(session: &TransferSession, peer: Fingerprint, peer_name: impl Into<String>, started_at: u64, finished_at: u64) -> Option<Self> {
+        let (outcome, error) = match session.state() {
+            TransferState::Done => (Outcome::Completed, None),
+            TransferState::Cancelled(CancelReason::Declined) => (Outcome::Declined, None),
+            TransferState::Cancelled(_) => (Outcome::Cancelled, None),
+            TransferState::Failed(failure) => (Outcome::Failed, Some(failure.to_string())),
+            _ => return None,
+        };
+        let files = session.files().iter().map(|f| FileRecord { name: f.name.clone(), size: f.size, bytes: f.bytes, hash: f.hash, path: None }).collect();
+        Some(Self { transfer: session.id(), peer, verified: false, peer_name: peer_name.into(), direction: session.direction(), outcome, error, started_at, finished_at, files })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+
+    /// Local files that can be sent again, skipping any since deleted
+    pub fn resend_paths(&self) -> Vec<&Path> {
+        self.files.iter().filter_map(|f| f.path.as_deref()).filter(|path| path.is_file()).collect()
+    }
+}
+
+/// Which transfers to list, newest first; unset fields match everything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub peer: Option<Fingerprint>,
+    pub direction: Option<Direction>,
+    pub outcome: Option<Outcome>,
+    /// Finished at or after this time
+    pub since: Option<u64>,
+    /// Case-insensitive substring of any file name
+    pub name: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl HistoryQuery {
+    /// The `limit` latest transfers
+    pub fn recent(limit: usize) -> Self {
+        Self { peer: None, direction: None, outcome: None, since: None, name: None, limit, offset: 0 }
+    }
+}
+
+/// The history database
+#[derive(Debug)]
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+impl HistoryStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// A database that lives as long as the store, for tests and private sessions
+    pub fn in_memory() -> Result<Self, StoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StoreError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match version {
+            0 => {
+                conn.execute_batch(SCHEMA)?;
+                conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            }
+            1 => {
+                conn.execute_batch(MIGRATE_1)?;
+                conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            }
+            SCHEMA_VERSION => {}
+            newer => return Err(StoreError::UnsupportedSchema(newer)),
+        }
+        Ok(Self { conn })
+    }
+
+    /// Add `record`, replacing an earlier record of the same transfer
+    pub fn record(&mut self, record: &TransferRecord) -> Result<(), StoreError> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM transfers WHERE transfer = ?1", [&record.transfer.0[..]])?;
+        tx.execute(
+            "INSERT INTO transfers (transfer, peer, verified, peer_name, direction, outcome, error, started_at, finished_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                &record.transfer.0[..],
+                &record.peer.as_bytes()[..],
+                record.verified,
+                record.peer_name,
+                direction_str(record.direction),
+                record.outcome.as_str(),
+                record.error,
+                record.started_at,
+                record.finished_at,
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare("INSERT INTO files (transfer, idx, name, size, bytes, hash, path) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            for (index, file) in record.files.iter().enumerate() {
+                let path = file.path.as_ref().map(|p| p.to_string_lossy().into_owned());
+                insert.execute(params![id, index, file.name, file.size, file.bytes, file.hash.as_ref().map(|h| &h[..]), path])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, transfer: &TransferId) -> Result<Option<TransferRecord>, StoreError> {
+        let row = self.conn.query_row(&format!("{SELECT} WHERE transfer = ?1"), [&transfer.0[..]], Header::from_row).optional()?;
+        row.map(|header| self.load(header)).transpose()
+    }
+
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<TransferRecord>, StoreError> {
+        let mut sql = format!("{SELECT} WHERE 1");
+        let mut args: Vec<Value> = Vec::new();
+        if let Some(peer) = &query.peer {
+            sql.push_str(" AND peer = ?");
+            args.push(Value::Blob(peer.as_bytes().to_vec()));
+        }
+        if let Some(direction) = query.direction {
+            sql.push_str(" AND direction = ?");
+            args.push(Value::Text(direction_str(direction).into()));
+        }
+        if let Some(outcome) = query.outcome {
+            sql.push_str(" AND outcome = ?");
+            args.push(Value::Text(outcome.as_str().into()));
+        }
+        if let Some(since) = query.since {
+            sql.push_str(" AND finished_at >= ?");
+            args.push(Value::Integer(i64::try_from(since).unwrap_or(i64::MAX)));
+        }
+        if let Some(name) = &query.name {
+            sql.push_str(r" AND EXISTS (SELECT 1 FROM files WHERE files.transfer = transfers.id AND name LIKE ? ESCAPE '\')");
+            let escaped = name.replace('\\', r"\\").replace('%', r"\%").replace('_', r"\_");
+            args.push(Value::Text(format!("%{escaped}%")));
+        }
+        sql.push_str(" ORDER BY finished_at DESC, id DESC LIMIT ? OFFSET ?");
+        args.push(Value::Integer(i64::try_from(query.limit).unwrap_or(i64::MAX)));
+        args.push(Value::Integer(i64::try_from(query.offset).unwrap_or(i64::MAX)));
+
+        let mut statement = self.conn.prepare(&sql)?;
+        let headers = statement.query_map(params_from_iter(args), Header::from_row)?.collect::<Result<Vec<_>, _>>()?;
+        headers.into_iter().map(|header| self.load(header)).collect()
+    }
+
+    /// Drop one transfer; returns whether it was there
+    pub fn forget(&mut self, transfer: &TransferId) -> Result<bool, StoreError> {
+        Ok(self.conn.execute("DELETE FROM transfers WHERE transfer = ?1", [&transfer.0[..]])? > 0)
+    }
+
+    /// Drop everything that finished before `time`; returns how many transfers went
+    pub fn forget_before(&mut self, time: u64) -> Result<usize, StoreError> {
+        Ok(self.conn.execute("DELETE FROM transfers WHERE finished_at < ?1", [time])?)
+    }
+
+    fn load(&self, header: Header) -> Result<TransferRecord, StoreError> {
+        let mut statement = self.conn.prepare_cached("SELECT name, size, bytes, hash, path FROM files WHERE transfer = ?1 ORDER BY idx")?;
+        let files = statement
+            .query_map([header.id], |row| {
+                let hash: Option<Vec<u8>> = row.get(3)?;
+                let path: Option<String> = row.get(4)?;
+                Ok((FileRecord { name: row.get(0)?, size: row.get(1)?, bytes: row.get(2)?, hash: None, path: path.map(PathBuf::from) }, hash))
+            })?
+            .map(|row| {
+                let (mut file, hash) = row?;
+                file.hash = hash.map(|h| h.try_into().map_err(|_| StoreError::Corrupt("file hash"))).transpose()?;
+                Ok(file)
+            })
+            .collect::<Result<_, StoreError>>()?;
+        Ok(TransferRecord {
+            transfer: TransferId(header.transfer.try_into().map_err(|_| StoreError::Corrupt("transfer id"))?),
+            peer: Fingerprint::from_bytes(header.peer.try_into().map_err(|_| StoreError::Corrupt("peer fingerprint"))?),
+            verified: header.verified,
+            peer_name: header.peer_name,
+            direction: match header.direction.as_str() {
+                "send" => Direction::Send,
+                "receive" => Direction::Receive,
+                _ => return Err(StoreError::Corrupt("direction")),
+            },
+            outcome: Outcome::parse(&header.outcome).ok_or(StoreError::Corrupt("outcome"))?,
+            error: header.error,
+            started_at: header.started_at,
+            finished_at: header.finished_at,
+            files,
+        })
+    }
+}
+
+const SELECT: &str = "SELECT id, transfer, peer, verified, peer_name, direction, outcome, error, started_at, finished_at FROM transfers";
+
+/// A `transfers` row as stored
+struct Header {
+    id: i64,
+    transfer: Vec<u8>,
+    peer: Vec<u8>,
+    verified: bool,
+    peer_name: String,
+    direction: String,
+    outcome: String,
+    error: Option<String>,
+    started_at: u64,
+    finished_at: u64,
+}
+
+impl Header {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            transfer: row.get(1)?,
+            peer: row.get(2)?,
+            verified: row.get(3)?,
+            peer_name: row.get(4)?,
+            direction: row.get(5)?,
+            outcome: row.get(6)?,
+            error: row.get(7)?,
+            started_at: row.get(8)?,
+            finished_at: row.get(9)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_proto::{Message, OfferedFile};
+    use globalsend_transfer::TransferConfig;
+
+    #[test]
+    fn records_and_queries_transfers() {
+        let dir = std::env::temp_dir().join(format!("gs-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (phone, laptop) = (Fingerprint::from_bytes([1; 32]), Fingerprint::from_bytes([2; 32]));
+        let files = vec![OfferedFile { name: "holiday_2024.jpg".into(), size: 3, mime: None }];
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(TransferId([1; 16]), files, TransferConfig::default()) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        assert!(TransferRecord::from_session(&tx, phone, "phone", 10, 11).is_none());
+        tx.on_message(&rx.decline().unwrap()).unwrap();
+
+        let mut sent = TransferRecord::from_session(&tx, phone, "phone", 10, 20).unwrap();
+        assert_eq!((sent.outcome, sent.direction, sent.size()), (Outcome::Declined, Direction::Send, 3));
+        let photo = dir.join("holiday_2024.jpg");
+        std::fs::write(&photo, b"jpg").unwrap();
+        sent.files[0].path = Some(photo.clone());
+        let received = TransferRecord {
+            transfer: TransferId([2; 16]),
+            peer: laptop,
+            verified: true,
+            peer_name: "laptop".into(),
+            direction: Direction::Receive,
+            outcome: Outcome::Completed,
+            error: None,
+            started_at: 30,
+            finished_at: 40,
+            files: vec![FileRecord { name: "notes.txt".into(), size: 5, bytes: 5, hash: Some([9; 32]), path: None }],
+        };
+
+        let path = dir.join("history.db");
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.record(&sent).unwrap();
+        store.record(&received).unwrap();
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert_eq!(store.query(&HistoryQuery::recent(10)).unwrap(), [received.clone(), sent.clone()]);
+        assert_eq!(store.query(&HistoryQuery::recent(1)).unwrap(), std::slice::from_ref(&received));
+        assert_eq!(store.query(&HistoryQuery { peer: Some(phone), ..HistoryQuery::recent(10) }).unwrap(), [sent.clone()]);
+        assert_eq!(store.query(&HistoryQuery { since: Some(21), direction: Some(Direction::Receive), ..HistoryQuery::recent(10) }).unwrap(), std::slice::from_ref(&received));
+        // `_` is literal, not a wildcard
+        assert_eq!(store.query(&HistoryQuery { name: Some("DAY_2024".into()), ..HistoryQuery::recent(10) }).unwrap(), [sent.clone()]);
+        assert!(store.query(&HistoryQuery { name: Some("day_202_".into()), ..HistoryQuery::recent(10) }).unwrap().is_empty());
+        assert_eq!(store.get(&sent.transfer).unwrap().unwrap().resend_paths(), [photo.as_path()]);
+
+        // recording a transfer again replaces it
+        let retried = TransferRecord { outcome: Outcome::Completed, finished_at: 50, ..sent.clone() };
+        store.record(&retried).unwrap();
+        assert_eq!(store.query(&HistoryQuery { outcome: Some(Outcome::Completed), ..HistoryQuery::recent(10) }).unwrap(), [retried, received]);
+        assert_eq!(store.forget_before(45).unwrap(), 1);
+        assert!(store.forget(&sent.transfer).unwrap());
+        assert!(store.query(&HistoryQuery::recent(10)).unwrap().is_empty());
+        drop(store);
+
+        // a schema 1 database keeps its rows, none of them verified
+        let old = dir.join("history-1.db");
+        let conn = Connection::open(&old).unwrap();
+        conn.execute_batch(&SCHEMA.replace("    verified INTEGER NOT NULL DEFAULT 0,\n", "")).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute("INSERT INTO transfers (transfer, peer, peer_name, direction, outcome, started_at, finished_at) VALUES (?1, ?2, 'laptop', 'receive', 'completed', 1, 2)", params![&[3u8; 16][..], &laptop.as_bytes()[..]]).unwrap();
+        drop(conn);
+        let store = HistoryStore::open(&old).unwrap();
+        assert_eq!(store.get(&TransferId([3; 16])).unwrap().map(|r| (r.peer, r.verified)), Some((laptop, false)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Sync index
+//!
+//! One row per file in each synced folder: its size, modification time
+//! and hash as last seen, so a scan only hashes files whose size or time
+//! moved, and the hash the two sides last agreed on (`synced`), which is
+//! what tells a local change from the peer's. Folders are the ids both
+//! sides' profiles share; names are paths in the folder joined with `/`.
+
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::StoreError;
+
+/// `PRAGMA user_version` of the current schema
+pub const INDEX_SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE files (
+    folder TEXT NOT NULL,
+    name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    synced BLOB,
+    PRIMARY KEY (folder, name)
+);
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+    pub hash: [u8; 32],
+    /// Contents both sides last had; `None` until they first agree
+    pub synced: Option<[u8; 32]>,
+}
+
+impl IndexEntry {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<(Self, Vec<u8>, Option<Vec<u8>>)> {
+        Ok((Self { name: row.get(0)?, size: row.get(1)?, modified: row.get(2)?, hash: [0; 32], synced: None }, row.get(3)?, row.get(4)?))
+    }
+
+    fn load((mut entry, hash, synced): (Self, Vec<u8>, Option<Vec<u8>>)) -> Result<Self, StoreError> {
+        entry.hash = hash.try_into().map_err(|_| StoreError::Corrupt("file hash"))?;
+        entry.synced = synced.map(|h| h.try_into().map_err(|_| StoreError::Corrupt("synced hash"))).transpose()?;
+        Ok(entry)
+    }
+}
+
+/// The sync index database
+#[derive(Debug)]
+pub struct IndexStore {
+    conn: Connection,
+}
+
+impl IndexStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// A database that lives as long as the store; every file is new to it
+    pub fn in_memory() -> Result<Self, StoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StoreError> {
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match version {
+            0 => {
+                conn.execute_batch(SCHEMA)?;
+                conn.pragma_update(None, "user_version", INDEX_SCHEMA_VERSION)?;
+            }
+            INDEX_SCHEMA_VERSION => {}
+            newer => return Err(StoreError::UnsupportedSchema(newer)),
+        }
+        Ok(Self { conn })
+    }
+
+    /// Every file of `folder`, by name
+    pub fn entries(&self, folder: &str) -> Result<Vec<IndexEntry>, StoreError> {
+        let mut statement = self.conn.prepare_cached("SELECT name, size, modified, hash, synced FROM files WHERE folder = ?1 ORDER BY name")?;
+        let rows = statement.query_map([folder], IndexEntry::from_row)?.collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter().map(IndexEntry::load).collect()
+    }
+
+    pub fn get(&self, folder: &str, name: &str) -> Result<Option<IndexEntry>, StoreError> {
+        let mut statement = self.conn.prepare_cached("SELECT name, size, modified, hash, synced FROM files WHERE folder = ?1 AND name = ?2")?;
+        statement.query_row([folder, name], IndexEntry::from_row).optional()?.map(IndexEntry::load).transpose()
+    }
+
+    /// Add or replace the entry for `entry.name`
+    pub fn put(&mut self, folder: &str, entry: &IndexEntry) -> Result<(), StoreError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO files (folder, name, size, modified, hash, synced) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![folder, entry.name, entry.size, entry.modified, &entry.hash[..], entry.synced.as_ref().map(|h| &h[..])],
+        )?;
+        Ok(())
+    }
+
+    /// Record that both sides have `hash` under `name`; false if the index has no such file
+    pub fn mark_synced(&mut self, folder: &str, name: &str, hash: &[u8; 32]) -> Result<bool, StoreError> {
+        Ok(self.conn.execute("UPDATE files SET synced = ?3 WHERE folder = ?1 AND name = ?2", params![folder, name, &hash[..]])? > 0)
+    }
+
+    /// Drop a file that is gone; returns whether it was there
+    pub fn remove(&mut self, folder: &str, name: &str) -> Result<bool, StoreError> {
+        Ok(self.conn.execute("DELETE FROM files WHERE folder = ?1 AND name = ?2", [folder, name])? > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_entries_and_synced_hashes_per_folder() {
+        let path = std::env::temp_dir().join(format!("gs-index-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut store = IndexStore::open(&path).unwrap();
+        let a = IndexEntry { name: "notes/a.txt".into(), size: 5, modified: 1_700_000_000, hash: [1; 32], synced: None };
+        store.put("docs", &a).unwrap();
+        store.put("photos", &IndexEntry { name: "b.jpg".into(), ..a.clone() }).unwrap();
+        assert_eq!(store.entries("docs").unwrap(), vec![a.clone()]);
+        assert!(store.mark_synced("docs", "notes/a.txt", &[1; 32]).unwrap());
+        assert!(!store.mark_synced("docs", "b.jpg", &[1; 32]).unwrap());
+        drop(store);
+
+        let mut store = IndexStore::open(&path).unwrap();
+        assert_eq!(store.get("docs", "notes/a.txt").unwrap().unwrap().synced, Some([1; 32]));
+        assert!(store.remove("docs", "notes/a.txt").unwrap());
+        assert!(store.entries("docs").unwrap().is_empty());
+        assert_eq!(store.entries("photos").unwrap().len(), 1);
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+//! Local persistence for globalsend front ends
+//!
+//! [`HistoryStore`] records every transfer that finished, successfully or
+//! not, in an SQLite database, and answers [`HistoryQuery`]s so the CLI and
+//! GUIs can list recent transfers and send the same files again.
+//! [`IndexStore`] keeps what each synced folder held when it was last
+//! scanned and synced.
+
+use std::fmt;
+
+pub mod history;
+pub mod index;
+
+pub use crate::history::{FileRecord, HistoryQuery, HistoryStore, Outcome, TransferRecord, SCHEMA_VERSION};
+pub use crate::index::{IndexEntry, IndexStore, INDEX_SCHEMA_VERSION};
+
+#[derive(Debug)]
+pub enum StoreError {
+    Sqlite(rusqlite::Error),
+    /// The database holds something this build did not write
+    Corrupt(&'static str),
+    /// The database was written by a newer build
+    UnsupportedSchema(u32),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Sqlite(e) => write!(f, "database: {e}"),
+            StoreError::Corrupt(what) => write!(f, "corrupt database: {what}"),
+            StoreError::UnsupportedSchema(v) => write!(f, "database schema {v} is newer than this build"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
@@ -0,0 +1,172 @@
+//! Stopping a transfer cleanly
+//!
+//! A [`CancelToken`] is shared by everything working on one transfer: the
+//! UI cancels it, and every task reading files or driving streams wraps its
+//! work in [`CancelToken::run`] and stops at the next await; blocking reads
+//! check [`CancelToken::reason`] before each block instead. Whoever drives
+//! the [`TransferSession`] then sends the peer the Cancel from
+//! [`TransferSession::cancel`]. It travels sealed under the session keys
+//! like every other message, so nobody else on the path can forge or strip
+//! it. What happens to the files that were half-written is the
+//! [`KeepPartial`] policy's call.
+
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use globalsend_proto::{CancelReason, TransferId};
+use tokio::sync::watch;
+
+use crate::session::TransferSession;
+
+/// Shared cancellation flag; clones observe the same transfer
+#[derive(Debug, Clone)]
+pub struct CancelToken(Arc<watch::Sender<Option<CancelReason>>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(None)))
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel everything holding this token; the first reason sticks.
+    /// Returns whether this call was the one that cancelled.
+    pub fn cancel(&self, reason: CancelReason) -> bool {
+        self.0.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        })
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        *self.0.borrow()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) -> CancelReason {
+        let mut rx = self.0.subscribe();
+        let reason = rx.wait_for(Option::is_some).await.expect("sender lives in self");
+        reason.expect("waited for some")
+    }
+
+    /// Run `work` unless the token is cancelled first, in which case it is dropped
+    pub async fn run<F: Future>(&self, work: F) -> Result<F::Output, CancelReason> {
+        tokio::select! {
+            biased;
+            reason = self.cancelled() => Err(reason),
+            out = work => Ok(out),
+        }
+    }
+}
+
+/// What to do with files a cancelled or failed transfer left half-written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeepPartial {
+    /// Delete them
+    #[default]
+    Remove,
+    /// Leave them where they are, so a checkpoint can resume the transfer
+    Keep,
+    /// Move them under this directory, in a folder per transfer, out of the
+    /// way of the download directory but still there to inspect
+    Quarantine(PathBuf),
+}
+
+impl KeepPartial {
+    /// Apply the policy to one partial file; returns where it is now, if
+    /// anywhere. A file that was never created is not an error.
+    pub fn apply(&self, transfer: TransferId, path: &Path) -> io::Result<Option<PathBuf>> {
+        match self {
+            KeepPartial::Keep => Ok(path.exists().then(|| path.to_owned())),
+            KeepPartial::Remove => match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(None),
+            },
+            KeepPartial::Quarantine(dir) => {
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let dir = dir.join(crate::hex_id(&transfer));
+                fs::create_dir_all(&dir)?;
+                let file_name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+                let mut target = dir.join(file_name);
+                let mut n = 1;
+                while target.exists() {
+                    target = dir.join(format!("{} ({n})", file_name.to_string_lossy()));
+                    n += 1;
+                }
+                if fs::rename(path, &target).is_err() {
+                    // quarantine on another filesystem
+                    fs::copy(path, &target)?;
+                    fs::remove_file(path)?;
+                }
+                Ok(Some(target))
+            }
+        }
+    }
+
+    /// Apply the policy to every file `session` left partial; `path` maps a
+    /// file index to where the receiver was writing it
+    pub fn clean_up<'a>(&self, session: &TransferSession, path: impl Fn(u32) -> &'a Path) -> io::Result<Vec<(u32, PathBuf)>> {
+        let mut kept = Vec::new();
+        for index in session.partial_files() {
+            if let Some(now) = self.apply(session.id(), path(index))? {
+                kept.push((index, now));
+            }
+        }
+        Ok(kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TransferConfig;
+    use globalsend_proto::{Message, OfferedFile};
+
+    #[tokio::test]
+    async fn cancel_stops_work_and_quarantines_partials() {
+        let token = CancelToken::new();
+        let worker = token.clone();
+        let stalled = tokio::spawn(async move { worker.run(std::future::pending::<()>()).await });
+        assert!(token.cancel(CancelReason::User));
+        assert!(!token.cancel(CancelReason::Timeout));
+        assert_eq!(stalled.await.unwrap(), Err(CancelReason::User));
+        assert_eq!(token.run(async { 1 }).await, Err(CancelReason::User));
+
+        let files = vec![OfferedFile { name: "a".into(), size: 8, mime: None }, OfferedFile { name: "b".into(), size: 8, mime: None }];
+        let config = TransferConfig { chunk_size: 4, ..TransferConfig::default() };
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(TransferId([2; 16]), files, config) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        tx.on_message(&rx.accept().unwrap()).unwrap();
+        rx.on_message(&tx.start_file([1; 32]).unwrap().1).unwrap();
+        rx.on_message(&tx.chunk(0, vec![0; 4]).unwrap()).unwrap();
+        tx.on_message(&rx.cancel(token.reason().unwrap()).unwrap()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("gs-partial-{}", std::process::id()));
+        let (downloads, quarantine) = (dir.join("downloads"), dir.join("quarantine"));
+        fs::create_dir_all(&downloads).unwrap();
+        let paths = [downloads.join("a"), downloads.join("b")];
+        fs::write(&paths[0], [0; 4]).unwrap();
+        let kept = KeepPartial::Quarantine(quarantine.clone()).clean_up(&rx, |i| &paths[i as usize]).unwrap();
+        assert_eq!(kept, vec![(0, quarantine.join("02".repeat(16)).join("a"))]);
+        assert!(!paths[0].exists() && kept[0].1.exists());
+        assert_eq!(KeepPartial::Remove.apply(rx.id(), &kept[0].1).unwrap(), None);
+        assert!(!kept[0].1.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
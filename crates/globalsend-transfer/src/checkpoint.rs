@@ -0,0 +1,340 @@
+//! Persisted transfer progress, for resuming after a crash or a dropped link
+//!
+//! The receiver records every chunk it writes (offset, length and BLAKE3)
+//! and saves a [`Checkpoint`] now and then. When the sender offers the same
+//! [`TransferId`] again, the receiver loads the checkpoint, re-hashes the
+//! partial file against the recorded chunks with
+//! [`Checkpoint::verify_partial`] (a crash can leave a torn or unsynced
+//! tail), and accepts from the end of the verified prefix with
+//! [`TransferSession::accept_from`], writing on into the files where the
+//! [`Layout`] it recorded put them. The sender keeps the same transfer id
+//! when it retries; a stored session ticket lets it skip the full handshake
+//! on the way back in.
+//!
+//! On disk: `"GSCK" || version u8 || postcard(Checkpoint)`, one file per
+//! transfer, replaced atomically and readable only by the owner.
+//!
+//! [`TransferSession::accept_from`]: crate::TransferSession::accept_from
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use globalsend_proto::{TransferId, TransferOffer};
+use serde::{Deserialize, Serialize};
+
+use crate::folder::Layout;
+
+const MAGIC: &[u8; 4] = b"GSCK";
+pub const CHECKPOINT_VERSION: u8 = 1;
+const EXTENSION: &str = "ckpt";
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// Not a checkpoint, or truncated
+    Corrupt,
+    UnsupportedVersion(u8),
+    /// A recorded path is not UTF-8 and cannot be stored
+    Path,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "checkpoint: {e}"),
+            CheckpointError::Corrupt => write!(f, "corrupt checkpoint"),
+            CheckpointError::UnsupportedVersion(v) => write!(f, "unsupported checkpoint version {v}"),
+            CheckpointError::Path => write!(f, "checkpoint path is not UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+/// One chunk as written to disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRecord {
+    pub offset: u64,
+    pub len: u32,
+    /// BLAKE3 of the chunk's bytes
+    pub hash: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileCheckpoint {
+    pub name: String,
+    pub size: u64,
+    /// Whole-file BLAKE3 from the header; a different hash on retry means the file changed
+    pub hash: Option<[u8; 32]>,
+    /// In write order
+    pub chunks: Vec<ChunkRecord>,
+    /// Received and verified in full
+    pub done: bool,
+}
+
+impl FileCheckpoint {
+    /// Byte ranges on disk, merged
+    pub fn received(&self) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = self.chunks.iter().map(|c| c.offset..c.offset + u64::from(c.len)).collect();
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => merged.push(r),
+            }
+        }
+        merged
+    }
+
+    /// Length of the unbroken run from offset 0
+    pub fn prefix(&self) -> u64 {
+        if self.done {
+            return self.size;
+        }
+        self.received().first().filter(|r| r.start == 0).map_or(0, |r| r.end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub transfer: TransferId,
+    /// Fingerprint of the peer the transfer is with; only resume with the same device
+    pub peer: [u8; 32],
+    pub files: Vec<FileCheckpoint>,
+    /// `globalsend_crypto::ticket::SessionTicket::to_bytes`, when we hold a ticket for the peer
+    pub ticket: Option<Vec<u8>>,
+    /// Where the files are being written, once the offer was accepted
+    pub layout: Option<Layout>,
+}
+
+impl Checkpoint {
+    pub fn new(offer: &TransferOffer, peer: [u8; 32]) -> Self {
+        let files = offer
+            .files
+            .iter()
+            .map(|f| FileCheckpoint { name: f.name.clone(), size: f.size, hash: None, chunks: Vec::new(), done: false })
+            .collect();
+        Self { transfer: offer.transfer, peer, files, ticket: None, layout: None }
+    }
+
+    /// Whether a re-sent `offer` from `peer` is the transfer this checkpoint belongs to
+    pub fn matches(&self, offer: &TransferOffer, peer: &[u8; 32]) -> bool {
+        self.transfer == offer.transfer
+            && &self.peer == peer
+            && self.files.len() == offer.files.len()
+            && self.files.iter().zip(&offer.files).all(|(c, o)| c.name == o.name && c.size == o.size)
+    }
+
+    /// Record a file header; returns false (and forgets the file's chunks)
+    /// when the hash differs from the one recorded, i.e. the file changed
+    pub fn record_header(&mut self, index: u32, hash: [u8; 32]) -> bool {
+        let file = &mut self.files[index as usize];
+        let same = file.hash.is_none_or(|h| h == hash);
+        if !same {
+            file.chunks.clear();
+            file.done = false;
+        }
+        file.hash = Some(hash);
+        same
+    }
+
+    pub fn record_chunk(&mut self, index: u32, offset: u64, data: &[u8]) {
+        let hash = *blake3::hash(data).as_bytes();
+        self.files[index as usize].chunks.push(ChunkRecord { offset, len: data.len() as u32, hash });
+    }
+
+    pub fn record_done(&mut self, index: u32) {
+        let file = &mut self.files[index as usize];
+        file.done = true;
+        // a finished file is checked by its whole hash; the chunk list is dead weight
+        file.chunks.clear();
+    }
+
+    /// Where to accept from: the first unfinished file and its verified prefix
+    ///
+    /// `None` when every file is done.
+    pub fn resume_point(&self) -> Option<(u32, u64)> {
+        let index = self.files.iter().position(|f| !f.done)?;
+        Some((index as u32, self.files[index].prefix()))
+    }
+
+    /// Re-hash the partial file for `index` against the recorded chunks
+    ///
+    /// Drops every record from the first chunk that is missing or differs
+    /// and returns the verified prefix length.
+    pub fn verify_partial<R: Read>(&mut self, index: u32, mut partial: R) -> io::Result<u64> {
+        let file = &mut self.files[index as usize];
+        file.chunks.sort_by_key(|c| c.offset);
+        let mut pos = 0u64;
+        let mut keep = 0;
+        let mut buf = Vec::new();
+        for chunk in &file.chunks {
+            if chunk.offset != pos {
+                break;
+            }
+            buf.resize(chunk.len as usize, 0);
+            match partial.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            if blake3::hash(&buf).as_bytes() != &chunk.hash {
+                break;
+            }
+            pos += u64::from(chunk.len);
+            keep += 1;
+        }
+        file.chunks.truncate(keep);
+        Ok(pos)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CheckpointError> {
+        let mut out = MAGIC.to_vec();
+        out.push(CHECKPOINT_VERSION);
+        // paths are the only thing that can fail to serialize
+        postcard::to_extend(self, out).map_err(|_| CheckpointError::Path)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CheckpointError> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..4] != MAGIC {
+            return Err(CheckpointError::Corrupt);
+        }
+        if bytes[4] != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(bytes[4]));
+        }
+        postcard::from_bytes(&bytes[5..]).map_err(|_| CheckpointError::Corrupt)
+    }
+}
+
+/// A directory of checkpoints, one file per transfer
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    dir: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, transfer: &TransferId) -> PathBuf {
+        self.dir.join(crate::hex_id(transfer)).with_extension(EXTENSION)
+    }
+
+    /// Write the checkpoint, replacing any previous one atomically
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        let path = self.path(&checkpoint.transfer);
+        let tmp = path.with_extension("tmp");
+        let bytes = checkpoint.to_bytes()?;
+        {
+            let mut opts = fs::OpenOptions::new();
+            opts.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                // may hold a session ticket
+                opts.mode(0o600);
+            }
+            let mut f = opts.open(&tmp)?;
+            f.write_all(&bytes)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(&self, transfer: &TransferId) -> Result<Option<Checkpoint>, CheckpointError> {
+        match fs::read(self.path(transfer)) {
+            Ok(bytes) => Checkpoint::from_bytes(&bytes).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Forget a finished or abandoned transfer
+    pub fn remove(&self, transfer: &TransferId) -> io::Result<()> {
+        match fs::remove_file(self.path(transfer)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every stored checkpoint; unreadable files are skipped
+    pub fn list(&self) -> io::Result<Vec<Checkpoint>> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(EXTENSION.as_ref()) {
+                continue;
+            }
+            if let Ok(checkpoint) = fs::read(&path).map_err(CheckpointError::from).and_then(|b| Checkpoint::from_bytes(&b)) {
+                out.push(checkpoint);
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_proto::OfferedFile;
+
+    #[test]
+    fn survives_restart_and_trims_torn_tail() {
+        let dir = std::env::temp_dir().join(format!("gs-ckpt-{}", std::process::id()));
+        let store = CheckpointStore::open(&dir).unwrap();
+        let offer = TransferOffer {
+            transfer: TransferId([5; 16]),
+            files: vec![
+                OfferedFile { name: "a".into(), size: 3, mime: None },
+                OfferedFile { name: "b".into(), size: 12, mime: None },
+            ],
+        };
+        let data: Vec<u8> = (0..12).collect();
+        let mut checkpoint = Checkpoint::new(&offer, [1; 32]);
+        checkpoint.record_header(0, [7; 32]);
+        checkpoint.record_done(0);
+        assert!(checkpoint.record_header(1, [8; 32]));
+        for offset in [0, 4, 8] {
+            checkpoint.record_chunk(1, offset, &data[offset as usize..offset as usize + 4]);
+        }
+        checkpoint.ticket = Some(vec![9; 8]);
+        checkpoint.layout = Some(Layout::plan(&dir, &offer).unwrap());
+        store.save(&checkpoint).unwrap();
+
+        let mut loaded = store.load(&offer.transfer).unwrap().unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert!(loaded.matches(&offer, &[1; 32]) && !loaded.matches(&offer, &[2; 32]));
+        assert_eq!(loaded.files[1].received(), vec![0..12]);
+
+        // the crash tore the second chunk and lost most of the third
+        let mut partial = data[..10].to_vec();
+        partial[5] ^= 0xff;
+        assert_eq!(loaded.verify_partial(1, &partial[..]).unwrap(), 4);
+        assert_eq!(loaded.resume_point(), Some((1, 4)));
+        assert!(!loaded.record_header(1, [9; 32]));
+        assert_eq!(loaded.resume_point(), Some((1, 0)));
+
+        assert_eq!(store.list().unwrap().len(), 1);
+        store.remove(&offer.transfer).unwrap();
+        assert!(store.load(&offer.transfer).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Clipboard sharing between paired devices
+//!
+//! Copy on the phone, paste on the laptop: each paired device gets a
+//! [`ClipChannel`] over the [`DoubleRatchet`] seeded from its session, so
+//! every clip is sealed under its own message key and a device compromised
+//! today reads neither yesterday's clips nor, once the ratchet has turned,
+//! tomorrow's. A [`Clipboard`] only shares with devices the user has
+//! [enabled](Clipboard::enable); clips from anyone else are dropped unread.
+//! Text and images (up to a size cap) are supported, and everything copied
+//! here or received lands in a bounded history, newest first.
+//!
+//! A clip on the wire is `ratchet(postcard(Clip))` with [`CLIP_AAD`].
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_crypto::ratchet::{DoubleRatchet, RatchetError, RATCHET_HEADER_LEN};
+use serde::{Deserialize, Serialize};
+
+use crate::validate::{check_content, ContentMismatch};
+
+/// Binds ratchet messages to the clipboard, so they cannot be replayed into another channel
+pub const CLIP_AAD: &[u8] = b"globalsend clipboard v1";
+/// Longest text clip, in bytes
+pub const MAX_TEXT: usize = 1 << 20;
+pub const DEFAULT_MAX_IMAGE: usize = 8 << 20;
+pub const DEFAULT_HISTORY: usize = 20;
+/// Tag, postcard framing and timestamp on top of a clip's content
+const CLIP_OVERHEAD: usize = RATCHET_HEADER_LEN + 16 + 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClipContent {
+    Text(String),
+    /// Encoded image, e.g. `image/png`
+    Image { mime: String, data: Vec<u8> },
+}
+
+impl ClipContent {
+    pub fn len(&self) -> usize {
+        match self {
+            ClipContent::Text(text) => text.len(),
+            ClipContent::Image { data, .. } => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What travels inside the ratchet
+#[derive(Serialize, Deserialize)]
+struct Clip {
+    content: ClipContent,
+    /// Unix seconds on the copying device
+    copied_at: u64,
+}
+
+/// One clip in the history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipEntry {
+    /// Device it came from; `None` if it was copied here
+    pub source: Option<Fingerprint>,
+    pub content: ClipContent,
+    pub copied_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardError {
+    /// Clipboard sharing is not enabled for this device
+    NotEnabled(Fingerprint),
+    TooLarge { len: usize, max: usize },
+    /// Image whose bytes are not the image type it claims, or not an image at all
+    Content(ContentMismatch),
+    Malformed,
+    Ratchet(RatchetError),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::NotEnabled(device) => write!(f, "clipboard sharing is off for {device}"),
+            ClipboardError::TooLarge { len, max } => write!(f, "clip of {len} bytes is over the {max} byte limit"),
+            ClipboardError::Content(e) => write!(f, "clipboard image: {e}"),
+            ClipboardError::Malformed => write!(f, "malformed clip"),
+            ClipboardError::Ratchet(e) => write!(f, "clipboard channel: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+impl From<RatchetError> for ClipboardError {
+    fn from(e: RatchetError) -> Self {
+        ClipboardError::Ratchet(e)
+    }
+}
+
+impl From<ContentMismatch> for ClipboardError {
+    fn from(e: ContentMismatch) -> Self {
+        ClipboardError::Content(e)
+    }
+}
+
+/// The clipboard link to one paired device
+#[derive(Debug)]
+pub struct ClipChannel {
+    peer: Fingerprint,
+    ratchet: DoubleRatchet,
+}
+
+impl ClipChannel {
+    pub fn new(peer: Fingerprint, ratchet: DoubleRatchet) -> Self {
+        Self { peer, ratchet }
+    }
+
+    pub fn peer(&self) -> &Fingerprint {
+        &self.peer
+    }
+
+    /// For persisting with [`DoubleRatchet::to_bytes`] between runs
+    pub fn ratchet(&self) -> &DoubleRatchet {
+        &self.ratchet
+    }
+}
+
+/// Per-device opt-in, limits and history
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    devices: BTreeSet<Fingerprint>,
+    max_image: usize,
+    history_len: usize,
+    history: VecDeque<ClipEntry>,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IMAGE, DEFAULT_HISTORY)
+    }
+}
+
+impl Clipboard {
+    /// Sharing with no device yet; images up to `max_image` bytes, `history_len` clips remembered
+    pub fn new(max_image: usize, history_len: usize) -> Self {
+        Self { devices: BTreeSet::new(), max_image, history_len, history: VecDeque::new() }
+    }
+
+    /// Share with `device` from now on
+    pub fn enable(&mut self, device: Fingerprint) {
+        self.devices.insert(device);
+    }
+
+    /// Stop sharing with `device`; returns whether it was enabled
+    pub fn disable(&mut self, device: &Fingerprint) -> bool {
+        self.devices.remove(device)
+    }
+
+    pub fn is_enabled(&self, device: &Fingerprint) -> bool {
+        self.devices.contains(device)
+    }
+
+    /// Newest first
+    pub fn history(&self) -> impl Iterator<Item = &ClipEntry> {
+        self.history.iter()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Record a local copy and seal it for every enabled device among
+    /// `channels`; returns the message for each
+    pub fn copy(&mut self, content: ClipContent, copied_at: u64, channels: &mut [ClipChannel]) -> Result<Vec<(Fingerprint, Vec<u8>)>, ClipboardError> {
+        self.check(&content)?;
+        let clip = Clip { content, copied_at };
+        let plaintext = postcard::to_allocvec(&clip).expect("clip serializes");
+        let mut out = Vec::new();
+        for channel in channels.iter_mut().filter(|channel| self.devices.contains(&channel.peer)) {
+            out.push((channel.peer, channel.ratchet.seal(CLIP_AAD, &plaintext)?));
+        }
+        self.remember(ClipEntry { source: None, content: clip.content, copied_at });
+        Ok(out)
+    }
+
+    /// Open a clip that arrived on `channel` and add it to the history
+    pub fn receive(&mut self, channel: &mut ClipChannel, message: &[u8]) -> Result<&ClipEntry, ClipboardError> {
+        if !self.devices.contains(&channel.peer) {
+            return Err(ClipboardError::NotEnabled(channel.peer));
+        }
+        let max = MAX_TEXT.max(self.max_image) + CLIP_OVERHEAD;
+        if message.len() > max {
+            return Err(ClipboardError::TooLarge { len: message.len(), max });
+        }
+        let plaintext = channel.ratchet.open(CLIP_AAD, message)?;
+        let clip: Clip = postcard::from_bytes(&plaintext).map_err(|_| ClipboardError::Malformed)?;
+        self.check(&clip.content)?;
+        self.remember(ClipEntry { source: Some(channel.peer), content: clip.content, copied_at: clip.copied_at });
+        Ok(&self.history[0])
+    }
+
+    fn check(&self, content: &ClipContent) -> Result<(), ClipboardError> {
+        let max = match content {
+            ClipContent::Text(_) => MAX_TEXT,
+            ClipContent::Image { mime, data } => {
+                if !mime.starts_with("image/") {
+                    return Err(ClipboardError::Malformed);
+                }
+                check_content("", Some(mime), data)?;
+                self.max_image
+            }
+        };
+        if content.len() > max {
+            return Err(ClipboardError::TooLarge { len: content.len(), max });
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, entry: ClipEntry) {
+        self.history.push_front(entry);
+        self.history.truncate(self.history_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_crypto::ratchet::RatchetKey;
+    use globalsend_crypto::session::SessionKeys;
+    use globalsend_crypto::{DeviceKey, EphemeralKey};
+
+    #[test]
+    fn clips_reach_enabled_devices_only() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let (ea, eb) = (EphemeralKey::generate(), EphemeralKey::generate());
+        let (pa, pb) = (ea.public(), eb.public());
+        let ka = SessionKeys::derive(&a, ea, &b.public(), &pb).unwrap();
+        let kb = SessionKeys::derive(&b, eb, &a.public(), &pa).unwrap();
+        let laptop_key = RatchetKey::generate();
+        let (phone, laptop) = (Fingerprint::from_bytes([1; 32]), Fingerprint::from_bytes([2; 32]));
+        let mut on_phone = [ClipChannel::new(laptop, DoubleRatchet::initiator(&ka, &laptop_key.public()).unwrap())];
+        let mut on_laptop = ClipChannel::new(phone, DoubleRatchet::responder(&kb, laptop_key).unwrap());
+
+        let (mut phone_clips, mut laptop_clips) = (Clipboard::new(1024, 2), Clipboard::default());
+        // not enabled yet: kept locally, sent nowhere
+        assert!(phone_clips.copy(ClipContent::Text("first".into()), 1, &mut on_phone).unwrap().is_empty());
+        phone_clips.enable(laptop);
+        let sent = phone_clips.copy(ClipContent::Text("hunter2".into()), 2, &mut on_phone).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(laptop_clips.receive(&mut on_laptop, &sent[0].1), Err(ClipboardError::NotEnabled(phone)));
+
+        laptop_clips.enable(phone);
+        let entry = laptop_clips.receive(&mut on_laptop, &sent[0].1).unwrap();
+        assert_eq!(entry, &ClipEntry { source: Some(phone), content: ClipContent::Text("hunter2".into()), copied_at: 2 });
+
+        let png = ClipContent::Image { mime: "image/png".into(), data: b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec() };
+        let sent = phone_clips.copy(png.clone(), 3, &mut on_phone).unwrap();
+        assert_eq!(laptop_clips.receive(&mut on_laptop, &sent[0].1).unwrap().content, png);
+        assert_eq!(phone_clips.history().map(|e| e.copied_at).collect::<Vec<_>>(), [3, 2]);
+        let big = ClipContent::Image { mime: "image/png".into(), data: vec![0; 2048] };
+        assert_eq!(phone_clips.copy(big, 4, &mut on_phone), Err(ClipboardError::TooLarge { len: 2048, max: 1024 }));
+        let disguised = ClipContent::Image { mime: "image/png".into(), data: b"MZ\x90\0".to_vec() };
+        assert!(matches!(phone_clips.copy(disguised, 5, &mut on_phone), Err(ClipboardError::Content(_))));
+    }
+}
@@ -0,0 +1,97 @@
+//! Per-file zstd compression
+//!
+//! Compression is negotiated per transfer (see
+//! [`globalsend_proto::Compression`]) and then decided per file and per
+//! chunk by the sender. Files that are already compressed are skipped by
+//! name and MIME type. For everything else chunks are compressed until
+//! one fails to shrink by at least [`MIN_SAVING`]; that chunk and the rest
+//! of the file go raw, so compression never makes a transfer bigger.
+//!
+//! Chunks are compressed before they are sealed; decompression is capped at
+//! the file's chunk size so a small frame cannot expand into a huge one.
+
+use std::io;
+
+/// Fast level: source trees and logs shrink well, and the link is usually the bottleneck
+pub const LEVEL: i32 = 3;
+/// Fraction of a chunk compression has to save to be worth it
+pub const MIN_SAVING: f64 = 0.1;
+
+/// Extensions of formats that are compressed already
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "deb", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg", "lz", "lz4", "m4a", "mkv",
+    "mov", "mp3", "mp4", "odt", "ogg", "opus", "png", "pptx", "rar", "rpm", "tgz", "webm", "webp", "whl", "xlsx", "xz", "zip", "zst",
+];
+
+/// MIME types and prefixes of compressed formats
+const COMPRESSED_MIME: &[&str] = &[
+    "image/",
+    "audio/",
+    "video/",
+    "application/zip",
+    "application/gzip",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+    "application/vnd.rar",
+    "application/vnd.openxmlformats-officedocument.",
+];
+
+/// Whether a file is worth sampling, judging by its name and MIME type
+pub fn worth_trying(name: &str, mime: Option<&str>) -> bool {
+    if let Some(mime) = mime {
+        // svg is text that happens to live under image/
+        if mime != "image/svg+xml" && COMPRESSED_MIME.iter().any(|m| mime.starts_with(m)) {
+            return false;
+        }
+    }
+    let ext = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    !ext.is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// `data` compressed, if that saves at least [`MIN_SAVING`]
+pub fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    let out = zstd::bulk::compress(data, LEVEL).ok()?;
+    (out.len() as f64 <= data.len() as f64 * (1.0 - MIN_SAVING)).then_some(out)
+}
+
+/// Decompress a chunk that must come to at most `max` bytes
+pub fn decompress(data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+    zstd::bulk::decompress(data, max)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Incompressible bytes (xorshift)
+    pub(crate) fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x9e37_79b9_7f4a_7c15u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_compressed_formats_and_caps_expansion() {
+        assert!(worth_trying("main.rs", Some("text/x-rust")));
+        assert!(worth_trying("logo.svg", Some("image/svg+xml")));
+        assert!(!worth_trying("holiday.JPG", None));
+        assert!(!worth_trying("clip", Some("video/mp4")));
+
+        let log = b"2024-01-01 INFO request ok\n".repeat(500);
+        let packed = compress(&log).unwrap();
+        assert!(packed.len() < log.len() / 10);
+        assert_eq!(decompress(&packed, log.len()).unwrap(), log);
+        assert!(decompress(&packed, log.len() - 1).is_err());
+
+        let noise = noise(4096);
+        assert_eq!(compress(&noise), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Sender tuning
+
+use crate::session::MAX_OPEN_FILES;
+
+/// First protocol version whose receivers ack every chunk
+pub const CHUNK_ACK_VERSION: u16 = 5;
+/// First protocol version whose receivers send [`HashAck`](globalsend_proto::HashAck)s
+pub const HASH_ACK_VERSION: u16 = 10;
+/// First protocol version that carries [`Metadata`](globalsend_proto::Metadata) with previews
+pub const METADATA_VERSION: u16 = 11;
+/// First protocol version whose senders take an [`Accept`](globalsend_proto::Accept) for some of the files
+pub const ACCEPT_FILES_VERSION: u16 = 12;
+/// First protocol version that syncs folders with [`SyncIndex`](globalsend_proto::SyncIndex)es
+pub const SYNC_VERSION: u16 = 13;
+/// First protocol version whose peers prove the fingerprint in their hello with an [`IdentityProof`](globalsend_proto::IdentityProof)
+pub const IDENTITY_VERSION: u16 = 14;
+/// First protocol version that sends each file's chunks on a QUIC stream of its own
+pub const FILE_STREAM_VERSION: u16 = 15;
+/// Chunks between two hash acks for a file
+pub const HASH_ACK_CHUNKS: u64 = 64;
+
+/// How a sender paces a transfer
+///
+/// On a high-latency link, waiting for each chunk's ack before sending the
+/// next caps throughput at one chunk per round trip. The sender instead
+/// keeps up to `inflight_chunks` unacknowledged chunks out, spread over up
+/// to `streams` files sent side by side, each on its own stream where the
+/// transport has them; acks from the receiver open the window again, so a
+/// slow disk on the far end still pushes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferConfig {
+    /// Bytes per chunk; every chunk but a file's last is exactly this long
+    pub chunk_size: u32,
+    /// Chunks sent and not yet acked, across all files
+    pub inflight_chunks: u32,
+    /// Files open at once, each on its own stream where the transport has them
+    pub streams: u16,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self { chunk_size: 256 * 1024, inflight_chunks: 32, streams: 4 }
+    }
+}
+
+impl TransferConfig {
+    /// Receivers before [`CHUNK_ACK_VERSION`] only ack whole files, so a
+    /// window would stall: send to them without one
+    pub fn for_peer(self, version: u16) -> Self {
+        if version < CHUNK_ACK_VERSION {
+            Self { inflight_chunks: u32::MAX, ..self }
+        } else {
+            self
+        }
+    }
+
+    /// Same config with every field in the range the session works with
+    pub(crate) fn clamped(self) -> Self {
+        Self { chunk_size: self.chunk_size.max(1), inflight_chunks: self.inflight_chunks.max(1), streams: self.streams.clamp(1, MAX_OPEN_FILES as u16) }
+    }
+}
@@ -0,0 +1,328 @@
+//! rsync-style delta transfer
+//!
+//! When the receiver already has an older copy of an offered file it sends
+//! its [`Signature`]: a rolling checksum and a truncated BLAKE3 for every
+//! block. The sender slides a window over the new file ([`diff`]) and
+//! sends the blocks the receiver already has as [`DeltaOp::Copy`] and
+//! everything else as literals; the receiver rebuilds the file with
+//! [`apply`]. The result is checked against the full-file hash from the
+//! file header like any other transfer, so a checksum collision can fail a
+//! transfer but never corrupt a file silently.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use globalsend_proto::{BlockChecksum, BlockSignatures, DeltaOp, Message, TransferId};
+
+/// Smallest block size [`block_size_for`] picks
+pub const MIN_BLOCK_SIZE: u32 = 2048;
+/// Largest block size [`block_size_for`] picks
+pub const MAX_BLOCK_SIZE: u32 = 1 << 20;
+/// Blocks per [`BlockSignatures`] message; keeps each well inside a frame
+pub const BLOCKS_PER_MESSAGE: usize = 32 * 1024;
+/// Copies per delta message before it is sent even without literals
+const MAX_OPS: usize = 4096;
+
+/// Block size for a file of `size` bytes: about its square root, as a power of two
+pub fn block_size_for(size: u64) -> u32 {
+    let root = (size as f64).sqrt() as u64;
+    root.next_power_of_two().clamp(u64::from(MIN_BLOCK_SIZE), u64::from(MAX_BLOCK_SIZE)) as u32
+}
+
+/// Checksums of every block of the receiver's copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub size: u64,
+    pub block_size: u32,
+    pub blocks: Vec<BlockChecksum>,
+}
+
+impl Signature {
+    /// Checksum everything `reader` yields in blocks of `block_size`
+    pub fn compute(mut reader: impl Read, block_size: u32) -> io::Result<Self> {
+        assert!(block_size > 0, "block size must not be zero");
+        let mut block = vec![0; block_size as usize];
+        let (mut size, mut blocks) = (0, Vec::new());
+        loop {
+            let len = read_full(&mut reader, &mut block)?;
+            if len == 0 {
+                break;
+            }
+            size += len as u64;
+            blocks.push(BlockChecksum { weak: Rolling::new(&block[..len]).digest(), strong: strong(&block[..len]) });
+            if len < block.len() {
+                break;
+            }
+        }
+        Ok(Self { size, block_size, blocks })
+    }
+
+    /// Number of blocks a copy of `size` bytes has
+    pub fn block_count(size: u64, block_size: u32) -> u64 {
+        size.div_ceil(u64::from(block_size))
+    }
+
+    /// Length of block `block`; only the last one can be short
+    pub fn block_len(&self, block: u32) -> usize {
+        let start = u64::from(block) * u64::from(self.block_size);
+        (self.size - start).min(u64::from(self.block_size)) as usize
+    }
+
+    /// Bytes the ops rebuild and how many of them are literals, or `None`
+    /// if an op refers to blocks this copy does not have
+    pub fn output_len(&self, ops: &[DeltaOp]) -> Option<(u64, u64)> {
+        let (mut total, mut literal) = (0u64, 0u64);
+        for op in ops {
+            match op {
+                DeltaOp::Copy { block, count } => {
+                    let end = block.checked_add(*count).filter(|&end| *count > 0 && end as usize <= self.blocks.len())?;
+                    total += u64::from(*count - 1) * u64::from(self.block_size) + self.block_len(end - 1) as u64;
+                }
+                DeltaOp::Literal(data) => {
+                    total += data.len() as u64;
+                    literal += data.len() as u64;
+                }
+            }
+        }
+        Some((total, literal))
+    }
+
+    /// The signature as messages for file `index`, split to fit frames
+    pub fn messages(&self, transfer: TransferId, index: u32) -> Vec<Message> {
+        let message = |first: usize, blocks: &[BlockChecksum]| {
+            BlockSignatures { transfer, index, size: self.size, block_size: self.block_size, first: first as u32, blocks: blocks.to_vec() }.into()
+        };
+        if self.blocks.is_empty() {
+            return vec![message(0, &[])];
+        }
+        self.blocks.chunks(BLOCKS_PER_MESSAGE).enumerate().map(|(i, blocks)| message(i * BLOCKS_PER_MESSAGE, blocks)).collect()
+    }
+}
+
+/// Walk `new` against `base` and hand the ops to `emit` in batches, each
+/// with at most `max_literal` literal bytes: one batch per delta message
+pub fn diff(base: &Signature, mut new: impl Read, max_literal: usize, mut emit: impl FnMut(Vec<DeltaOp>) -> io::Result<()>) -> io::Result<()> {
+    assert!(max_literal > 0, "literal limit must not be zero");
+    let block_size = base.block_size as usize;
+    let mut index: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (i, block) in base.blocks.iter().enumerate() {
+        index.entry(block.weak).or_default().push(i as u32);
+    }
+    // a short last block can only match at the very end
+    let short_tail = base.blocks.len().checked_sub(1).map(|b| b as u32).filter(|&b| base.block_len(b) < block_size);
+
+    let mut out = Batch { ops: Vec::new(), literal: 0, max_literal };
+    let mut buf = Vec::new();
+    // buf[start..pos] is pending literal, buf[pos..pos + block_size] the window
+    let (mut start, mut pos) = (0, 0);
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+    loop {
+        if !eof && buf.len() < pos + block_size + 1 {
+            // drop what has been sent before growing the buffer
+            buf.drain(..start);
+            (pos, start) = (pos - start, 0);
+            let have = buf.len();
+            buf.resize(have + block_size.max(64 * 1024), 0);
+            let len = read_full(&mut new, &mut buf[have..])?;
+            buf.truncate(have + len);
+            eof = len == 0;
+            continue;
+        }
+        let window = &buf[pos..buf.len().min(pos + block_size)];
+        if window.is_empty() {
+            break;
+        }
+        let weak = rolling.get_or_insert_with(|| Rolling::new(window)).digest();
+        let found = if window.len() == block_size {
+            index.get(&weak).and_then(|blocks| {
+                let hash = strong(window);
+                blocks.iter().copied().find(|&b| base.block_len(b) == block_size && base.blocks[b as usize].strong == hash)
+            })
+        } else {
+            short_tail.filter(|&b| base.block_len(b) == window.len() && base.blocks[b as usize].weak == weak && base.blocks[b as usize].strong == strong(window))
+        };
+        if let Some(block) = found {
+            out.literal(&buf[start..pos], &mut emit)?;
+            out.copy(block, &mut emit)?;
+            pos += window.len();
+            start = pos;
+            rolling = None;
+            continue;
+        }
+        if window.len() < block_size {
+            // nothing left to match in a short tail
+            pos = buf.len();
+            break;
+        }
+        if let (Some(rolling), Some(&next)) = (rolling.as_mut(), buf.get(pos + block_size)) {
+            rolling.roll(buf[pos], next);
+        } else {
+            rolling = None;
+        }
+        pos += 1;
+        if pos - start == max_literal {
+            out.literal(&buf[start..pos], &mut emit)?;
+            start = pos;
+        }
+    }
+    out.literal(&buf[start..pos], &mut emit)?;
+    out.flush(&mut emit)
+}
+
+/// Rebuild a file from `base`, the receiver's old copy, and one delta
+/// message's ops; returns the bytes written
+pub fn apply(base: &Signature, mut old: impl Read + Seek, ops: &[DeltaOp], mut out: impl Write) -> io::Result<u64> {
+    let mut written = 0;
+    for op in ops {
+        match op {
+            DeltaOp::Copy { block, .. } => {
+                let len = base.output_len(std::slice::from_ref(op)).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "copy outside the old file"))?.0;
+                old.seek(SeekFrom::Start(u64::from(*block) * u64::from(base.block_size)))?;
+                let copied = io::copy(&mut (&mut old).take(len), &mut out)?;
+                if copied != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                written += len;
+            }
+            DeltaOp::Literal(data) => {
+                out.write_all(data)?;
+                written += data.len() as u64;
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Ops waiting to go out as one message
+struct Batch {
+    ops: Vec<DeltaOp>,
+    literal: usize,
+    max_literal: usize,
+}
+
+impl Batch {
+    fn literal(&mut self, data: &[u8], emit: &mut impl FnMut(Vec<DeltaOp>) -> io::Result<()>) -> io::Result<()> {
+        for piece in data.chunks(self.max_literal) {
+            if self.literal + piece.len() > self.max_literal {
+                self.flush(emit)?;
+            }
+            self.literal += piece.len();
+            self.ops.push(DeltaOp::Literal(piece.to_vec()));
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, block: u32, emit: &mut impl FnMut(Vec<DeltaOp>) -> io::Result<()>) -> io::Result<()> {
+        if let Some(DeltaOp::Copy { block: first, count }) = self.ops.last_mut() {
+            if *first + *count == block {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        if self.ops.len() == MAX_OPS {
+            self.flush(emit)?;
+        }
+        self.ops.push(DeltaOp::Copy { block, count: 1 });
+        Ok(())
+    }
+
+    fn flush(&mut self, emit: &mut impl FnMut(Vec<DeltaOp>) -> io::Result<()>) -> io::Result<()> {
+        self.literal = 0;
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        emit(std::mem::take(&mut self.ops))
+    }
+}
+
+/// rsync's weak checksum: two 16-bit sums that can slide one byte at a time
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let (mut a, mut b) = (0u32, 0u32);
+        for &byte in data {
+            a = a.wrapping_add(u32::from(byte));
+            b = b.wrapping_add(a);
+        }
+        Self { a, b, len: data.len() as u32 }
+    }
+
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(u32::from(out)).wrapping_add(u32::from(into));
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(u32::from(out))).wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong(data: &[u8]) -> [u8; 16] {
+    let mut out = [0; 16];
+    out.copy_from_slice(&blake3::hash(data).as_bytes()[..16]);
+    out
+}
+
+/// Fill `buf` unless the reader ends first; returns how much was read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sends_only_what_changed() {
+        let old = crate::compress::tests::noise(100_000);
+        let mut new = old.clone();
+        new[50_000..50_010].copy_from_slice(b"0123456789");
+        new.splice(10_000..10_000, b"inserted".iter().copied());
+        new.truncate(99_000);
+
+        let base = Signature::compute(&old[..], 2048).unwrap();
+        assert_eq!(base.blocks.len(), 49);
+        let mut batches = Vec::new();
+        diff(&base, &new[..], 4096, |ops| {
+            batches.push(ops);
+            Ok(())
+        }).unwrap();
+
+        let (total, literal) = batches.iter().map(|ops| base.output_len(ops).unwrap()).fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        assert_eq!(total, new.len() as u64);
+        assert!(literal < 3 * 2048 + 1000, "{literal} literal bytes");
+        assert!(batches.iter().all(|ops| base.output_len(ops).unwrap().1 <= 4096));
+
+        let mut rebuilt = Vec::new();
+        for ops in &batches {
+            apply(&base, Cursor::new(&old), ops, &mut rebuilt).unwrap();
+        }
+        assert_eq!(rebuilt, new);
+
+        // nothing in common still rebuilds
+        let mut batches = Vec::new();
+        diff(&Signature::compute(&b"short"[..], 2048).unwrap(), &new[..], 4096, |ops| {
+            batches.push(ops);
+            Ok(())
+        }).unwrap();
+        assert!(batches.concat().iter().all(|op| matches!(op, DeltaOp::Literal(_))));
+        assert_eq!(base.output_len(&[DeltaOp::Copy { block: 48, count: 2 }]), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Progress for everyone watching a transfer
+//!
+//! The driver of a [`TransferSession`] calls [`TransferEvents::publish`]
+//! after feeding it; GUIs, the CLI progress bar and hooks then observe the
+//! same data without touching the session:
+//!
+//! - [`TransferEvents::watch`]: the latest [`Progress`] snapshot, for
+//!   anything that redraws (bytes per file, throughput, ETA)
+//! - [`TransferEvents::subscribe`]: every [`TransferEvent`] in order, for
+//!   anything that reacts. Subscribers that fall more than [`EVENT_BUFFER`]
+//!   behind lose the oldest events but can always catch up from the
+//!   snapshot.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use globalsend_proto::TransferId;
+use tokio::sync::{broadcast, watch};
+
+use crate::session::{Direction, FileProgress, FileStatus, TransferEvent, TransferSession};
+use crate::state::TransferState;
+
+/// Events kept for subscribers that fall behind
+pub const EVENT_BUFFER: usize = 1024;
+/// Throughput is averaged over this much recent history
+pub const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Where a transfer stands, as of the last [`TransferEvents::publish`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub transfer: TransferId,
+    pub direction: Direction,
+    pub state: TransferState,
+    pub files: Vec<FileProgress>,
+    /// Sent or received so far, over all files
+    pub bytes: u64,
+    pub total: u64,
+    /// Bytes per second over the last [`RATE_WINDOW`]
+    pub throughput: f64,
+    /// Time left at the current throughput; `None` until there is one
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    fn of(session: &TransferSession) -> Self {
+        let files = session.files().to_vec();
+        Self {
+            transfer: session.id(),
+            direction: session.direction(),
+            state: session.state(),
+            bytes: files.iter().map(|f| f.bytes).sum(),
+            total: files.iter().filter(|f| f.status != FileStatus::Skipped).map(|f| f.size).sum(),
+            files,
+            throughput: 0.0,
+            eta: None,
+        }
+    }
+}
+
+/// Fan-out of one session's progress and events
+#[derive(Debug)]
+pub struct TransferEvents {
+    progress: watch::Sender<Progress>,
+    events: broadcast::Sender<TransferEvent>,
+    /// Byte counts at recent publishes, oldest first
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl TransferEvents {
+    pub fn new(session: &TransferSession) -> Self {
+        Self { progress: watch::Sender::new(Progress::of(session)), events: broadcast::Sender::new(EVENT_BUFFER), samples: VecDeque::new() }
+    }
+
+    /// Latest snapshot, updated on every publish
+    pub fn watch(&self) -> watch::Receiver<Progress> {
+        self.progress.subscribe()
+    }
+
+    /// Events from the next publish on
+    pub fn subscribe(&self) -> broadcast::Receiver<TransferEvent> {
+        self.events.subscribe()
+    }
+
+    /// Pass on the session's queued events and refresh the snapshot
+    pub fn publish(&mut self, session: &mut TransferSession) {
+        self.publish_at(session, Instant::now());
+    }
+
+    fn publish_at(&mut self, session: &mut TransferSession, now: Instant) {
+        while let Some(event) = session.poll_event() {
+            // nobody listening is fine
+            let _ = self.events.send(event);
+        }
+        let mut progress = Progress::of(session);
+        self.samples.push_back((now, progress.bytes));
+        while self.samples.front().is_some_and(|&(at, _)| now.duration_since(at) > RATE_WINDOW) {
+            self.samples.pop_front();
+        }
+        let (since, from) = self.samples[0];
+        let elapsed = now.duration_since(since).as_secs_f64();
+        if elapsed > 0.0 {
+            progress.throughput = (progress.bytes - from.min(progress.bytes)) as f64 / elapsed;
+        }
+        if progress.throughput > 0.0 && !progress.state.is_terminal() {
+            progress.eta = Some(Duration::from_secs_f64((progress.total - progress.bytes) as f64 / progress.throughput));
+        }
+        self.progress.send_replace(progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TransferConfig;
+    use globalsend_proto::{Message, OfferedFile};
+
+    #[test]
+    fn snapshot_and_events_follow_the_session() {
+        let files = vec![OfferedFile { name: "video.mkv".into(), size: 4000, mime: None }];
+        let config = TransferConfig { chunk_size: 1000, ..TransferConfig::default() };
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(TransferId([1; 16]), files, config) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        let mut events = TransferEvents::new(&tx);
+        let (progress, mut stream) = (events.watch(), events.subscribe());
+
+        tx.on_message(&rx.accept().unwrap()).unwrap();
+        tx.start_file([1; 32]).unwrap();
+        let start = Instant::now();
+        events.publish_at(&mut tx, start);
+        tx.chunk(0, vec![0; 1000]).unwrap();
+        events.publish_at(&mut tx, start + Duration::from_secs(1));
+
+        let now = progress.borrow().clone();
+        assert_eq!((now.bytes, now.total, now.state), (1000, 4000, TransferState::Transferring));
+        assert_eq!(now.throughput, 1000.0);
+        assert_eq!(now.eta, Some(Duration::from_secs(3)));
+
+        assert_eq!(stream.try_recv(), Ok(TransferEvent::State(TransferState::Accepting)));
+        assert_eq!(stream.try_recv(), Ok(TransferEvent::State(TransferState::Transferring)));
+        assert_eq!(stream.try_recv(), Ok(TransferEvent::FileStarted { index: 0 }));
+        assert_eq!(stream.try_recv(), Ok(TransferEvent::Progress { index: 0, bytes: 1000, size: 4000 }));
+
+        tx.on_message(&rx.cancel(globalsend_proto::CancelReason::User).unwrap()).unwrap();
+        events.publish(&mut tx);
+        assert_eq!(stream.try_recv(), Ok(TransferEvent::PeerCancelled(globalsend_proto::CancelReason::User)));
+        assert_eq!(progress.borrow().eta, None);
+    }
+}
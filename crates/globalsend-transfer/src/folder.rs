@@ -0,0 +1,346 @@
+//! Folder transfers
+//!
+//! The sender walks the folder ([`walk`]), hashes every file into a signed
+//! [`TransferManifest`] that keeps relative paths, permissions and mtimes
+//! ([`build_manifest`]), offers the files under their relative names and
+//! sends the manifest right after the offer. Files can then go one after
+//! another or several at once (see [`TransferSession`]).
+//!
+//! The receiver checks the manifest against the offer and the sender's
+//! identity ([`open_manifest`]), maps every name to a path under its
+//! download directory ([`Layout`]), renaming a top-level folder or file that
+//! already exists to `name (1)`, and restores permissions and mtimes once a
+//! file is complete ([`apply_metadata`]).
+//!
+//! From version 8 the sender follows the manifest with the same manifest
+//! signed in the minisign format ([`minisign_message`]); a receiver that
+//! checked it ([`open_minisign`]) can keep both as files ([`save_manifest`])
+//! for anyone to check with `minisign -V`, using the key from
+//! `globalsend_crypto::minisign::public_key`.
+//!
+//! Only regular files travel; symlinks are skipped rather than followed,
+//! and empty directories are not recreated.
+//!
+//! [`TransferSession`]: crate::TransferSession
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use globalsend_crypto::hashing::{HashAlgorithm, Hasher};
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint, Signature};
+use globalsend_crypto::manifest::{self, ManifestEntry, ManifestError, TransferManifest};
+use globalsend_proto::{Manifest, ManifestMinisign, Message, OfferedFile, TransferId, TransferOffer};
+use serde::{Deserialize, Serialize};
+
+use crate::validate::sanitize_name;
+
+#[derive(Debug)]
+pub enum FolderError {
+    Io(io::Error),
+    Manifest(ManifestError),
+    /// Manifest was signed by someone other than the peer
+    WrongSender,
+    /// Offer and manifest list different files
+    Mismatch,
+    /// A name that would land outside the download directory
+    UnsafePath(String),
+}
+
+impl fmt::Display for FolderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FolderError::Io(e) => write!(f, "{e}"),
+            FolderError::Manifest(e) => write!(f, "{e}"),
+            FolderError::WrongSender => write!(f, "manifest not signed by the sending device"),
+            FolderError::Mismatch => write!(f, "manifest does not match the offer"),
+            FolderError::UnsafePath(name) => write!(f, "unsafe path in offer: {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for FolderError {}
+
+impl From<io::Error> for FolderError {
+    fn from(e: io::Error) -> Self {
+        FolderError::Io(e)
+    }
+}
+
+impl From<ManifestError> for FolderError {
+    fn from(e: ManifestError) -> Self {
+        FolderError::Manifest(e)
+    }
+}
+
+/// A file found by [`walk`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderEntry {
+    pub path: PathBuf,
+    /// Relative name with `/` separators, starting with the folder's own name
+    pub name: String,
+    pub size: u64,
+    /// Unix permission bits; 0 elsewhere
+    pub mode: u32,
+    /// Unix seconds; 0 when the platform does not say
+    pub mtime: u64,
+}
+
+/// Every regular file under `root`, sorted by name
+///
+/// A plain file yields itself; a folder yields its files named
+/// `folder/sub/file`.
+pub fn walk(root: &Path) -> io::Result<Vec<FolderEntry>> {
+    let base = root.file_name().and_then(|n| n.to_str()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no usable name"))?;
+    let mut out = Vec::new();
+    let mut stack = vec![(root.to_path_buf(), base.to_string())];
+    while let Some((path, name)) = stack.pop() {
+        let meta = fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                // names that are not valid UTF-8 cannot go into the manifest
+                if let Some(child) = entry.file_name().to_str() {
+                    stack.push((entry.path(), format!("{name}/{child}")));
+                }
+            }
+        } else if meta.is_file() {
+            out.push(FolderEntry { path, name, size: meta.len(), mode: mode(&meta), mtime: mtime(&meta) });
+        }
+    }
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(out)
+}
+
+#[cfg(unix)]
+fn mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn mode(_: &fs::Metadata) -> u32 {
+    0
+}
+
+fn mtime(meta: &fs::Metadata) -> u64 {
+    meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+}
+
+/// Hash every entry and sign the manifest
+pub fn build_manifest(identity: &DeviceIdentity, entries: &[FolderEntry], created: u64) -> Result<(TransferManifest, Signature), FolderError> {
+    let mut files = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let mut hasher = Hasher::new(HashAlgorithm::Blake3);
+        io::copy(&mut File::open(&entry.path)?, &mut hasher)?;
+        files.push(ManifestEntry { name: entry.name.clone(), size: entry.size, hash: hasher.finalize(), mode: entry.mode, mtime: entry.mtime });
+    }
+    let manifest = TransferManifest { sender: identity.verifying_key(), created, entries: files };
+    let signature = manifest::sign_manifest(identity, &manifest)?;
+    Ok((manifest, signature))
+}
+
+/// The offer for a manifest, files in manifest order
+pub fn offer(transfer: TransferId, manifest: &TransferManifest) -> TransferOffer {
+    let files = manifest.entries.iter().map(|e| OfferedFile { name: e.name.clone(), size: e.size, mime: None }).collect();
+    TransferOffer { transfer, files }
+}
+
+/// The [`Manifest`] message that follows the offer
+pub fn manifest_message(transfer: TransferId, manifest: &TransferManifest, signature: &Signature) -> Result<Message, FolderError> {
+    Ok(Manifest { transfer, manifest: manifest.to_bytes()?, signature: signature.to_bytes().to_vec() }.into())
+}
+
+/// Receiver: parse and verify a manifest from `sender`, and check it lists exactly what `offer` does
+pub fn open_manifest(message: &Manifest, offer: &TransferOffer, sender: &Fingerprint) -> Result<TransferManifest, FolderError> {
+    let manifest = TransferManifest::from_bytes(&message.manifest)?;
+    let signature = Signature::from_slice(&message.signature).map_err(|_| ManifestError::BadSignature)?;
+    manifest::verify_manifest(&manifest, &signature)?;
+    if &manifest.sender_fingerprint() != sender {
+        return Err(FolderError::WrongSender);
+    }
+    let by_name: HashMap<&str, u64> = manifest.entries.iter().map(|e| (e.name.as_str(), e.size)).collect();
+    if message.transfer != offer.transfer
+        || offer.files.len() != manifest.entries.len()
+        || !offer.files.iter().all(|f| by_name.get(f.name.as_str()) == Some(&f.size))
+    {
+        return Err(FolderError::Mismatch);
+    }
+    Ok(manifest)
+}
+
+/// The [`ManifestMinisign`] message that follows the [`Manifest`] from version 8
+pub fn minisign_message(transfer: TransferId, identity: &DeviceIdentity, manifest: &TransferManifest) -> Result<Message, FolderError> {
+    Ok(ManifestMinisign { transfer, signature: manifest::minisign_manifest(identity, manifest)? }.into())
+}
+
+/// Receiver: check the minisign signature covers `manifest` as received, by its sender
+///
+/// Only means something once [`open_manifest`] has vouched for the sender.
+pub fn open_minisign(message: &ManifestMinisign, manifest: &Manifest) -> Result<(), FolderError> {
+    if message.transfer != manifest.transfer {
+        return Err(FolderError::Mismatch);
+    }
+    manifest::verify_manifest_minisign(&manifest.manifest, &message.signature)?;
+    Ok(())
+}
+
+/// Write the encoded manifest to `path` and its signature next to it as `path.minisig`,
+/// where `minisign -V -m path` looks for it
+pub fn save_manifest(path: &Path, manifest: &Manifest, minisign: &ManifestMinisign) -> io::Result<()> {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".minisig");
+    fs::write(path, &manifest.manifest)?;
+    fs::write(signature, &minisign.signature)
+}
+
+/// Where each offered file goes under a download directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layout {
+    paths: Vec<PathBuf>,
+}
+
+impl Layout {
+    /// Map every offered name under `dir`; top-level names that already exist get a ` (n)` suffix
+    ///
+    /// Every component goes through [`sanitize_name`]; names that climb out
+    /// of `dir`, or that only differ in what sanitizing removed, are refused.
+    pub fn plan(dir: &Path, offer: &TransferOffer) -> Result<Self, FolderError> {
+        let mut roots: HashMap<String, String> = HashMap::new();
+        let mut paths = Vec::with_capacity(offer.files.len());
+        let mut seen = HashSet::with_capacity(offer.files.len());
+        for file in &offer.files {
+            let unsafe_path = || FolderError::UnsafePath(file.name.clone());
+            let parts: Vec<String> = relative_parts(&file.name).ok_or_else(unsafe_path)?.into_iter().map(sanitize_name).collect();
+            let root = match roots.get(&parts[0]) {
+                Some(root) => root.clone(),
+                None => {
+                    let root = unique_name(dir, &parts[0], roots.values());
+                    roots.insert(parts[0].clone(), root.clone());
+                    root
+                }
+            };
+            let mut path = dir.join(root);
+            path.extend(&parts[1..]);
+            if !seen.insert(path.clone()) {
+                return Err(unsafe_path());
+            }
+            paths.push(path);
+        }
+        Ok(Self { paths })
+    }
+
+    /// Destination of offered file `index`
+    pub fn path(&self, index: u32) -> &Path {
+        &self.paths[index as usize]
+    }
+
+    /// Create every parent directory
+    pub fn create_dirs(&self) -> io::Result<()> {
+        for path in &self.paths {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `/`-separated components, or `None` for anything that is not a plain relative path
+fn relative_parts(name: &str) -> Option<Vec<&str>> {
+    let parts: Vec<&str> = name.split('/').collect();
+    let plain = |p: &&str| {
+        !p.is_empty()
+            && !p.contains(['\\', '\0', ':'])
+            && matches!(Path::new(p).components().collect::<Vec<_>>().as_slice(), [Component::Normal(_)])
+    };
+    parts.iter().all(plain).then_some(parts)
+}
+
+/// `name`, or `name (n)` / `stem (n).ext` with the smallest `n` free in `dir` and not in `taken`
+pub(crate) fn unique_name<'a>(dir: &Path, name: &str, taken: impl Iterator<Item = &'a String> + Clone) -> String {
+    let free = |candidate: &str| !dir.join(candidate).exists() && !taken.clone().any(|t| t == candidate);
+    if free(name) {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    (1..).map(|n| format!("{stem} ({n}){ext}")).find(|c| free(c)).expect("some suffix is free")
+}
+
+/// Restore the manifest's permissions and mtime on a finished file
+///
+/// Set-id and sticky bits never come across.
+pub fn apply_metadata(path: &Path, entry: &ManifestEntry) -> io::Result<()> {
+    #[cfg(unix)]
+    if entry.mode != 0 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(entry.mode & 0o777))?;
+    }
+    if entry.mtime != 0 {
+        File::options().write(true).open(path)?.set_modified(UNIX_EPOCH + Duration::from_secs(entry.mtime))?;
+    }
+    Ok(())
+}
+
+/// Unix seconds, for [`build_manifest`]'s `created`
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_roundtrips_with_metadata_and_collision_safe_root() {
+        let base = std::env::temp_dir().join(format!("gs-folder-{}", std::process::id()));
+        let src = base.join("src/tree");
+        fs::create_dir_all(src.join("sub/deeper")).unwrap();
+        fs::write(src.join("a.txt"), b"alpha").unwrap();
+        fs::write(src.join("sub/deeper/b.bin"), [7u8; 3000]).unwrap();
+        File::options().write(true).open(src.join("a.txt")).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1_600_000_000)).unwrap();
+
+        let entries = walk(&src).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["tree/a.txt", "tree/sub/deeper/b.bin"]);
+
+        let identity = DeviceIdentity::generate();
+        let (manifest, signature) = build_manifest(&identity, &entries, now()).unwrap();
+        let id = TransferId([1; 16]);
+        let offer = offer(id, &manifest);
+        let Message::Manifest(message) = manifest_message(id, &manifest, &signature).unwrap() else { panic!() };
+
+        let received = open_manifest(&message, &offer, &identity.fingerprint()).unwrap();
+        assert!(matches!(open_manifest(&message, &offer, &DeviceIdentity::generate().fingerprint()), Err(FolderError::WrongSender)));
+        let Message::ManifestMinisign(minisign) = minisign_message(id, &identity, &manifest).unwrap() else { panic!() };
+        open_minisign(&minisign, &message).unwrap();
+        let forged = Manifest { manifest: build_manifest(&identity, &entries[..1], 1).unwrap().0.to_bytes().unwrap(), ..message.clone() };
+        assert!(matches!(open_minisign(&minisign, &forged), Err(FolderError::Manifest(ManifestError::BadSignature))));
+
+        let dest = base.join("dest");
+        fs::create_dir_all(dest.join("tree")).unwrap();
+        let layout = Layout::plan(&dest, &offer).unwrap();
+        assert_eq!(layout.path(1), dest.join("tree (1)/sub/deeper/b.bin"));
+        layout.create_dirs().unwrap();
+        for (i, entry) in entries.iter().enumerate() {
+            fs::copy(&entry.path, layout.path(i as u32)).unwrap();
+            apply_metadata(layout.path(i as u32), &received.entries[i]).unwrap();
+        }
+        assert_eq!(mtime(&fs::metadata(layout.path(0)).unwrap()), 1_600_000_000);
+
+        for bad in ["../x", "/etc/passwd", "a//b", "a/./b", "c:\\x", "a/.."] {
+            let evil = TransferOffer { transfer: id, files: vec![OfferedFile { name: bad.into(), size: 1, mime: None }] };
+            assert!(matches!(Layout::plan(&dest, &evil), Err(FolderError::UnsafePath(_))), "{bad}");
+        }
+        let names = |names: &[&str]| TransferOffer { transfer: id, files: names.iter().map(|&n| OfferedFile { name: n.into(), size: 1, mime: None }).collect() };
+        assert_eq!(Layout::plan(&dest, &names(&["new/\u{202e}gpj.exe"])).unwrap().path(0), dest.join("new/gpj.exe"));
+        assert!(matches!(Layout::plan(&dest, &names(&["new/a?.txt", "new/a*.txt"])), Err(FolderError::UnsafePath(_))));
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+//! Transfer engine shared by every globalsend front end
+//!
+//! [`TransferSession`] walks one transfer through its lifecycle
+//! ([`TransferState`]) from either end and checks each step against the
+//! protocol, so the CLI and GUIs drive the same state machine instead of
+//! each reimplementing offer, accept and verification. It is sans-I/O:
+//! callers move [`globalsend_proto::Message`]s over whatever transport they
+//! hold and do the file reads, writes and hashing themselves.
+//! [`TransferEvents`] fans its progress out to any number of observers, and
+//! a [`CancelToken`] stops every task working on a transfer at once.
+//! [`AcceptPolicy`] settles offers from trusted devices before the user is
+//! asked, and [`preflight`] checks the destination before anything is
+//! accepted. [`validate`] and [`Quarantine`] treat whatever arrives as hostile
+//! until it has been checked, and so does [`preview`] with the thumbnails
+//! and text an offer comes with.
+//!
+//! [`folder`] walks, signs and recreates directory trees, [`compress`]
+//! decides which chunks go through zstd, [`delta`] sends only the changed
+//! blocks of files the receiver has an older copy of, and
+//! [`checkpoint`] persists a receiver's progress so an interrupted transfer
+//! picks up from its last verified chunk. [`clipboard`] shares copied text
+//! and images with paired devices, and [`snippet`] sends a line of text or
+//! a link without making a file of it. [`sync`] decides what a two-way
+//! folder sync sends and how it settles files both sides changed.
+
+use std::fmt;
+
+use globalsend_proto::TransferId;
+
+pub mod cancel;
+pub mod checkpoint;
+pub mod clipboard;
+pub mod compress;
+pub mod config;
+pub mod delta;
+pub mod events;
+pub mod folder;
+pub mod policy;
+pub mod preflight;
+pub mod preview;
+pub mod quarantine;
+pub mod session;
+pub mod snippet;
+pub mod state;
+pub mod sync;
+pub mod validate;
+
+pub use crate::cancel::{CancelToken, KeepPartial};
+pub use crate::checkpoint::{Checkpoint, CheckpointError, CheckpointStore};
+pub use crate::clipboard::{ClipChannel, Clipboard};
+pub use crate::config::TransferConfig;
+pub use crate::events::{Progress, TransferEvents};
+pub use crate::policy::{AcceptPolicy, AcceptRule, Decision};
+pub use crate::quarantine::Quarantine;
+pub use crate::session::{Direction, FileProgress, FileStatus, ReceivedChunk, TransferEvent, TransferSession, MAX_OPEN_FILES};
+pub use crate::state::{Failure, InvalidTransition, TransferState};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    /// The call is not allowed in the session's current state
+    Transition(InvalidTransition),
+    /// Sender-only call on a receiving session, or the reverse
+    WrongSide,
+    /// Message belongs to a different transfer; the session is unchanged
+    WrongTransfer(TransferId),
+    /// The peer broke the protocol; the session has failed
+    Protocol(&'static str),
+    /// Chunk is empty, too long, or short before the end of the file
+    ChunkSize,
+    /// Every offered file has been started
+    NoMoreFiles,
+    /// [`TransferConfig::streams`] files are already in flight
+    TooManyOpen,
+    /// [`TransferConfig::inflight_chunks`] chunks are waiting for acks
+    WindowFull,
+    /// No file with that index is in flight
+    NotOpen,
+    /// Resume point is past the end of the offer or of its file
+    ResumePoint,
+    /// No offered file with that index, or it already has a delta base
+    NoSuchFile,
+    /// The receiver has no older copy of the file to send a delta against
+    NoBase,
+    /// The receiver's [`HashAck`](globalsend_proto::HashAck) for file `index`
+    /// does not match what was sent; the session has failed
+    Diverged { index: u32, offset: u64 },
+    /// A per-file acceptance needs one decision per offered file and has to take at least one
+    Selection,
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Transition(e) => write!(f, "{e}"),
+            TransferError::WrongSide => write!(f, "not allowed on this side of the transfer"),
+            TransferError::WrongTransfer(_) => write!(f, "message for another transfer"),
+            TransferError::Protocol(what) => write!(f, "protocol violation: {what}"),
+            TransferError::ChunkSize => write!(f, "chunk does not fit the file's chunk size"),
+            TransferError::NoMoreFiles => write!(f, "all files already started"),
+            TransferError::TooManyOpen => write!(f, "too many files in flight"),
+            TransferError::WindowFull => write!(f, "too many chunks waiting for acks"),
+            TransferError::NotOpen => write!(f, "file is not in flight"),
+            TransferError::ResumePoint => write!(f, "resume point outside the offer"),
+            TransferError::NoSuchFile => write!(f, "no such file in the offer"),
+            TransferError::NoBase => write!(f, "receiver has no older copy of the file"),
+            TransferError::Diverged { index, offset } => write!(f, "receiver's copy of file {index} differs within its first {offset} bytes"),
+            TransferError::Selection => write!(f, "accepted files do not match the offer"),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+impl From<InvalidTransition> for TransferError {
+    fn from(e: InvalidTransition) -> Self {
+        TransferError::Transition(e)
+    }
+}
+
+/// Lowercase hex of a transfer id, for file and directory names
+pub(crate) fn hex_id(transfer: &TransferId) -> String {
+    transfer.0.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Inverse of [`hex_id`]
+pub(crate) fn parse_hex_id(s: &str) -> Option<TransferId> {
+    let mut id = [0u8; 16];
+    if s.len() != 2 * id.len() || !s.is_ascii() {
+        return None;
+    }
+    for (i, b) in id.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(TransferId(id))
+}
@@ -0,0 +1,203 @@
+//! Deciding on offers before anyone is asked
+//!
+//! An [`AcceptPolicy`] is an ordered list of [`AcceptRule`]s, checked
+//! against every incoming offer before the user is prompted. The first rule
+//! whose conditions cover every file of the offer decides; if none does,
+//! the user is asked as usual. For example, "images under 50 MB from my
+//! phone go into ~/Pictures/Inbox":
+//!
+//! ```
+//! # use globalsend_transfer::policy::{AcceptRule, Action};
+//! # let phone = globalsend_crypto::identity::Fingerprint::from_bytes([1; 32]);
+//! let rule = AcceptRule {
+//!     device: Some(phone),
+//!     file_types: vec!["image/*".into()],
+//!     max_file_size: Some(50 << 20),
+//!     destination: Some("/home/me/Pictures/Inbox".into()),
+//!     ..AcceptRule::new("phone photos", Action::Accept)
+//! };
+//! ```
+//!
+//! Only devices the trust store already knows are ever accepted
+//! automatically; a new device, or a known one with a changed key, always
+//! gets a prompt. Decline rules apply to everyone.
+
+use std::path::PathBuf;
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_crypto::trust::TrustDecision;
+use globalsend_proto::{OfferedFile, TransferOffer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Accept,
+    Decline,
+}
+
+/// One rule; every condition that is set must hold for every file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptRule {
+    /// Shown in logs and the history, so the user can tell which rule fired
+    pub name: String,
+    pub action: Action,
+    /// Sending device; `None` matches any
+    pub device: Option<Fingerprint>,
+    /// MIME types (`image/png`), MIME families (`image/*`) or extensions
+    /// (`.pdf`); empty matches any file
+    pub file_types: Vec<String>,
+    pub max_file_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub max_files: Option<usize>,
+    /// Where accepted files go instead of the default download directory
+    pub destination: Option<PathBuf>,
+}
+
+impl AcceptRule {
+    /// A rule with no conditions: it matches every offer
+    pub fn new(name: impl Into<String>, action: Action) -> Self {
+        Self {
+            name: name.into(),
+            action,
+            device: None,
+            file_types: Vec::new(),
+            max_file_size: None,
+            max_total_size: None,
+            max_files: None,
+            destination: None,
+        }
+    }
+
+    pub fn matches(&self, peer: &Fingerprint, offer: &TransferOffer) -> bool {
+        self.device.as_ref().is_none_or(|device| device == peer)
+            && self.max_files.is_none_or(|max| offer.files.len() <= max)
+            && self.max_total_size.is_none_or(|max| offer.files.iter().map(|f| f.size).sum::<u64>() <= max)
+            && offer.files.iter().all(|file| self.max_file_size.is_none_or(|max| file.size <= max) && self.type_matches(file))
+    }
+
+    fn type_matches(&self, file: &OfferedFile) -> bool {
+        if self.file_types.is_empty() {
+            return true;
+        }
+        let mime = file.mime.as_deref().or_else(|| guess_mime(&file.name));
+        let extension = file.name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+        self.file_types.iter().any(|pattern| {
+            let pattern = pattern.to_ascii_lowercase();
+            if let Some(ext) = pattern.strip_prefix('.') {
+                extension.as_deref() == Some(ext)
+            } else if let Some(family) = pattern.strip_suffix("/*") {
+                mime.is_some_and(|mime| mime.split('/').next().is_some_and(|m| m.eq_ignore_ascii_case(family)))
+            } else {
+                mime.is_some_and(|mime| mime.eq_ignore_ascii_case(&pattern))
+            }
+        })
+    }
+}
+
+/// What to do with an offer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Accept without asking, into `destination` if the rule set one
+    Accept { rule: String, destination: Option<PathBuf> },
+    Decline { rule: String },
+    /// No rule applies: prompt the user
+    Ask,
+}
+
+/// Ordered rules; the first match wins
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcceptPolicy {
+    rules: Vec<AcceptRule>,
+}
+
+impl AcceptPolicy {
+    pub fn new(rules: Vec<AcceptRule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn rules(&self) -> &[AcceptRule] {
+        &self.rules
+    }
+
+    /// Add a rule after the existing ones
+    pub fn push(&mut self, rule: AcceptRule) {
+        self.rules.push(rule);
+    }
+
+    /// Decide on `offer` from `peer`, whose key the trust store judged `trust`
+    pub fn evaluate(&self, peer: &Fingerprint, trust: TrustDecision, offer: &TransferOffer) -> Decision {
+        for rule in self.rules.iter().filter(|rule| rule.matches(peer, offer)) {
+            match rule.action {
+                Action::Decline => return Decision::Decline { rule: rule.name.clone() },
+                Action::Accept if trust == TrustDecision::Known => {
+                    return Decision::Accept { rule: rule.name.clone(), destination: rule.destination.clone() };
+                }
+                // an accept rule never vouches for a device we cannot recognise
+                Action::Accept => {}
+            }
+        }
+        Decision::Ask
+    }
+}
+
+/// MIME type for common extensions, for offers that did not say
+pub fn guess_mime(name: &str) -> Option<&'static str> {
+    let ext = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "flac" => "audio/flac",
+        "ogg" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_proto::TransferId;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let (phone, laptop) = (Fingerprint::from_bytes([1; 32]), Fingerprint::from_bytes([2; 32]));
+        let policy = AcceptPolicy::new(vec![
+            AcceptRule { device: Some(laptop), ..AcceptRule::new("blocked", Action::Decline) },
+            AcceptRule {
+                device: Some(phone),
+                file_types: vec!["image/*".into(), ".pdf".into()],
+                max_file_size: Some(50 << 20),
+                destination: Some("/home/me/Pictures/Inbox".into()),
+                ..AcceptRule::new("phone photos", Action::Accept)
+            },
+        ]);
+        let offer = |files: &[(&str, u64, Option<&str>)]| TransferOffer {
+            transfer: TransferId([0; 16]),
+            files: files.iter().map(|&(name, size, mime)| OfferedFile { name: name.into(), size, mime: mime.map(Into::into) }).collect(),
+        };
+        let photos = offer(&[("IMG_1.HEIC", 3 << 20, None), ("scan", 1 << 20, Some("image/png")), ("bill.pdf", 1000, None)]);
+
+        let accepted = policy.evaluate(&phone, TrustDecision::Known, &photos);
+        assert_eq!(accepted, Decision::Accept { rule: "phone photos".into(), destination: Some("/home/me/Pictures/Inbox".into()) });
+        // one file out of bounds, an unknown key, or another device: ask
+        assert_eq!(policy.evaluate(&phone, TrustDecision::Known, &offer(&[("IMG_2.jpg", 60 << 20, None)])), Decision::Ask);
+        assert_eq!(policy.evaluate(&phone, TrustDecision::Known, &offer(&[("a.jpg", 1, None), ("notes.txt", 1, None)])), Decision::Ask);
+        assert_eq!(policy.evaluate(&phone, TrustDecision::Changed { previous: laptop }, &photos), Decision::Ask);
+        assert_eq!(policy.evaluate(&laptop, TrustDecision::New, &photos), Decision::Decline { rule: "blocked".into() });
+    }
+}
@@ -0,0 +1,163 @@
+//! Checks a receiver runs before accepting
+//!
+//! [`check`] looks at the destination the way the transfer will: can it be
+//! written, does every offered name plan to a usable path, is anything
+//! already there, and is there room for every byte. Problems come back as
+//! [`Refusal`]s for [`TransferSession::refuse`](crate::TransferSession::refuse),
+//! so the sender learns why up front instead of the transfer dying half
+//! way through on a full disk. [`check_selected`] does the same for the
+//! files a receiver picked out of the offer.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use globalsend_proto::{Refusal, TransferOffer};
+
+use crate::folder::{FolderError, Layout};
+use crate::validate::sanitize_name;
+
+/// Space left free on the destination after the transfer
+pub const SPACE_RESERVE: u64 = 64 << 20;
+
+/// What to do about names that already exist in the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnCollision {
+    /// Give the new file or folder a ` (n)` suffix
+    #[default]
+    Rename,
+    Refuse,
+}
+
+/// Every problem with receiving `offer` into `dir`, or the layout to receive it with
+pub fn check(dir: &Path, offer: &TransferOffer, on_collision: OnCollision) -> Result<Layout, Vec<Refusal>> {
+    check_selected(dir, offer, &vec![true; offer.files.len()], on_collision)
+}
+
+/// [`check`] for only the files `wanted` takes, one decision per offered file
+pub fn check_selected(dir: &Path, offer: &TransferOffer, wanted: &[bool], on_collision: OnCollision) -> Result<Layout, Vec<Refusal>> {
+    if !writable(dir) {
+        return Err(vec![Refusal::NotWritable]);
+    }
+    let mut refusals = Vec::new();
+    let mut roots = HashSet::new();
+    for ((index, file), _) in (0u32..).zip(&offer.files).zip(wanted).filter(|(_, wanted)| **wanted) {
+        let single = TransferOffer { transfer: offer.transfer, files: vec![file.clone()] };
+        if Layout::plan(dir, &single).is_err() {
+            refusals.push(Refusal::BadName { index });
+            continue;
+        }
+        let root = sanitize_name(file.name.split('/').next().unwrap_or_default());
+        if on_collision == OnCollision::Refuse && dir.join(&root).exists() && roots.insert(root) {
+            refusals.push(Refusal::Exists { index });
+        }
+    }
+    let layout = match Layout::plan(dir, offer) {
+        Ok(layout) => Some(layout),
+        // every name is fine alone, so two of them end up the same
+        Err(FolderError::UnsafePath(name)) if refusals.is_empty() => {
+            let index = offer.files.iter().rposition(|f| f.name == name).unwrap_or_default();
+            refusals.push(Refusal::BadName { index: index as u32 });
+            None
+        }
+        Err(_) => None,
+    };
+    let needed = offer.files.iter().zip(wanted).filter(|(_, wanted)| **wanted).map(|(f, _)| f.size).fold(0u64, u64::saturating_add);
+    if let Some(free) = available_space(dir) {
+        let available = free.saturating_sub(SPACE_RESERVE);
+        if needed > available {
+            refusals.push(Refusal::DiskSpace { needed, available });
+        }
+    }
+    match layout {
+        Some(layout) if refusals.is_empty() => Ok(layout),
+        _ => Err(refusals),
+    }
+}
+
+/// Create `dir` if needed and prove a file can be made in it
+fn writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(format!(".globalsend-preflight-{}", std::process::id()));
+    let created = fs::OpenOptions::new().write(true).create_new(true).open(&probe).is_ok();
+    created && fs::remove_file(probe).is_ok()
+}
+
+/// Bytes this user may still write on the filesystem holding `dir`, if the platform says
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is large enough; it is only read on success
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs succeeded, so it filled `stat`
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion)] // the field types differ between platforms
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+/// Bytes this user may still write on the volume holding `dir`, if the platform says
+#[cfg(windows)]
+pub fn available_space(dir: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = dir.as_os_str().encode_wide().chain([0]).collect();
+    let mut free = 0u64;
+    // SAFETY: `path` is NUL-terminated; the totals we do not need may be null
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut free, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return None;
+    }
+    Some(free)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TransferConfig;
+    use crate::session::{TransferEvent, TransferSession};
+    use globalsend_proto::{Message, OfferedFile, TransferId};
+
+    #[test]
+    fn refusals_reach_the_sender() {
+        let dir = std::env::temp_dir().join(format!("gs-preflight-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("taken.txt"), b"mine").unwrap();
+        let offer = |files: &[(&str, u64)]| TransferOffer {
+            transfer: TransferId([8; 16]),
+            files: files.iter().map(|&(name, size)| OfferedFile { name: name.into(), size, mime: None }).collect(),
+        };
+
+        let fine = offer(&[("taken.txt", 4), ("new/a.txt", 4)]);
+        assert_eq!(check(&dir, &fine, OnCollision::Rename).unwrap().path(0), dir.join("taken (1).txt"));
+        assert_eq!(check(&dir, &fine, OnCollision::Refuse).unwrap_err(), [Refusal::Exists { index: 0 }]);
+        assert_eq!(check(&dir, &offer(&[("ok", 1), ("../up", 1), ("a?", 1), ("a*", 1)]), OnCollision::Rename).unwrap_err(), [Refusal::BadName { index: 1 }]);
+        assert_eq!(check(&dir, &offer(&[("a?", 1), ("a*", 1)]), OnCollision::Rename).unwrap_err(), [Refusal::BadName { index: 1 }]);
+        assert_eq!(check(&dir.join("taken.txt/sub"), &fine, OnCollision::Rename).unwrap_err(), [Refusal::NotWritable]);
+        let huge = offer(&[("huge.iso", u64::MAX / 2)]);
+        let refusals = check(&dir, &huge, OnCollision::Rename).unwrap_err();
+        assert!(matches!(refusals[..], [Refusal::DiskSpace { needed, .. }] if needed == u64::MAX / 2));
+        let mixed = offer(&[("huge.iso", u64::MAX / 2), ("taken.txt", 4)]);
+        assert_eq!(check_selected(&dir, &mixed, &[false, true], OnCollision::Rename).unwrap().path(1), dir.join("taken (1).txt"));
+
+        let (mut tx, Message::TransferOffer(sent)) = TransferSession::outgoing(huge.transfer, huge.files, TransferConfig::default()) else { panic!() };
+        let mut rx = TransferSession::incoming(&sent);
+        tx.on_message(&rx.refuse(refusals.clone()).unwrap()).unwrap();
+        let events: Vec<_> = std::iter::from_fn(|| tx.poll_event()).collect();
+        assert!(events.contains(&TransferEvent::Refused(refusals)));
+        assert!(tx.state().is_terminal());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
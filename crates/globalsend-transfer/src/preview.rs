@@ -0,0 +1,263 @@
+//! Previews for the accept prompt
+//!
+//! The sender describes each offered file in a
+//! [`TransferMetadata`] (sealed into a `MetaEnvelope` and sent as a
+//! [`Metadata`](globalsend_proto::Metadata) message) with a small preview
+//! where it can make one: a JPEG thumbnail of an image, of a PDF's first
+//! page (rendered by poppler's `pdftoppm` if it is installed), or the first
+//! lines of a text file. [`describe`] builds them.
+//!
+//! On the receiving side the previews are as hostile as the files. [`check`]
+//! refuses metadata that does not describe the offer, decodes thumbnails
+//! under tight limits and encodes them again as PNG, so a front end never
+//! hands sender bytes to a platform image decoder, and strips text of
+//! control and invisible formatting characters. A preview that fails its
+//! check is dropped; the offer still stands.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use globalsend_crypto::meta::{FileMeta, TransferMetadata, MAX_TEXT_PREVIEW_LEN, MAX_THUMBNAIL_LEN};
+use globalsend_proto::{OfferedFile, TransferOffer};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+
+use crate::policy::guess_mime;
+use crate::validate::{is_invisible, sniff};
+
+/// Longest side of a thumbnail, in pixels
+pub const THUMBNAIL_EDGE: u32 = 160;
+/// Lines of a text file a preview shows
+pub const TEXT_PREVIEW_LINES: usize = 12;
+/// Preview bytes one offer carries at most; well under a frame
+pub const PREVIEW_BUDGET: usize = 512 << 10;
+/// How long the sender spends making previews before it stops; the offer waits on them
+pub const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+const JPEG_QUALITY: u8 = 80;
+/// How long `pdftoppm` gets for a first page
+const RENDER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Memory a thumbnail may take to decode on the receiving side
+const MAX_THUMBNAIL_ALLOC: u64 = 4 << 20;
+
+/// The sender's metadata does not describe the offer it came with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewMismatch {
+    pub index: usize,
+}
+
+impl fmt::Display for PreviewMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metadata for file {} does not match the offer", self.index)
+    }
+}
+
+impl std::error::Error for PreviewMismatch {}
+
+/// What the receiver may show of one offered file, once checked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preview {
+    /// PNG encoded on this side, at most [`THUMBNAIL_EDGE`] pixels a side
+    pub thumbnail: Option<Vec<u8>>,
+    /// At most [`TEXT_PREVIEW_LINES`] lines, with nothing invisible in them
+    pub text: Option<String>,
+}
+
+/// Sender: metadata for `offer`, whose files are at `paths`; files past
+/// [`PREVIEW_BUDGET`] or [`DESCRIBE_TIMEOUT`] get none
+pub fn describe<P: AsRef<Path>>(offer: &TransferOffer, paths: &[P]) -> TransferMetadata {
+    let (mut spent, started) = (0, Instant::now());
+    let files = offer
+        .files
+        .iter()
+        .zip(paths)
+        .map(|(file, path)| {
+            let mut meta = bare(file);
+            if spent < PREVIEW_BUDGET && started.elapsed() < DESCRIBE_TIMEOUT {
+                (meta.thumbnail, meta.text) = preview(path.as_ref());
+                spent += meta.thumbnail.as_ref().map_or(0, Vec::len) + meta.text.as_ref().map_or(0, String::len);
+            }
+            meta
+        })
+        .collect();
+    TransferMetadata { files }
+}
+
+fn bare(file: &OfferedFile) -> FileMeta {
+    let mime = file.mime.as_deref().or_else(|| guess_mime(&file.name)).unwrap_or("application/octet-stream");
+    FileMeta { name: file.name.clone(), mime: mime.into(), size: file.size, thumbnail: None, text: None }
+}
+
+fn preview(path: &Path) -> (Option<Vec<u8>>, Option<String>) {
+    let mut head = Vec::with_capacity(MAX_TEXT_PREVIEW_LEN);
+    let _ = File::open(path).and_then(|f| f.take(MAX_TEXT_PREVIEW_LEN as u64).read_to_end(&mut head));
+    match sniff(&head) {
+        Some("application/pdf") => (pdf_thumbnail(path), None),
+        Some(kind) if kind.starts_with("image/") => (ImageReader::open(path).ok().and_then(|r| r.with_guessed_format().ok()).and_then(|r| r.decode().ok()).and_then(thumbnail), None),
+        Some(_) => (None, None),
+        None => (None, text_head(&head)),
+    }
+}
+
+/// Scaled-down JPEG of `image`, if it fits in [`MAX_THUMBNAIL_LEN`]
+fn thumbnail(image: DynamicImage) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let small = image.thumbnail(THUMBNAIL_EDGE, THUMBNAIL_EDGE).to_rgb8();
+    JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&small).ok()?;
+    (out.len() <= MAX_THUMBNAIL_LEN).then_some(out)
+}
+
+/// First page of the PDF at `path`, if `pdftoppm` is there to render it
+fn pdf_thumbnail(path: &Path) -> Option<Vec<u8>> {
+    static RENDERS: AtomicU64 = AtomicU64::new(0);
+    let prefix = std::env::temp_dir().join(format!("globalsend-preview-{}-{}", std::process::id(), RENDERS.fetch_add(1, Ordering::Relaxed)));
+    let rendered = prefix.with_extension("png");
+    let mut child = Command::new("pdftoppm")
+        .args(["-png", "-singlefile", "-f", "1", "-l", "1", "-scale-to"])
+        .arg(THUMBNAIL_EDGE.to_string())
+        .arg(path)
+        .arg(&prefix)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let started = Instant::now();
+    let finished = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.success(),
+            Ok(None) if started.elapsed() < RENDER_TIMEOUT => std::thread::sleep(Duration::from_millis(20)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break false;
+            }
+        }
+    };
+    let page = finished.then(|| image::open(&rendered).ok()).flatten();
+    let _ = std::fs::remove_file(&rendered);
+    thumbnail(page?)
+}
+
+/// Up to [`TEXT_PREVIEW_LINES`] lines from the start of a text file, or
+/// `None` if `head` does not look like UTF-8 text
+pub fn text_head(head: &[u8]) -> Option<String> {
+    if head.is_empty() || head.contains(&0) {
+        return None;
+    }
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // a character cut off at the end of the read is fine
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).expect("valid up to here"),
+        Err(_) => return None,
+    };
+    Some(text.lines().take(TEXT_PREVIEW_LINES).collect::<Vec<_>>().join("\n"))
+}
+
+/// Receiver: the previews in `metadata` that pass their checks, one per file of `offer`
+pub fn check(metadata: TransferMetadata, offer: &TransferOffer) -> Result<Vec<Preview>, PreviewMismatch> {
+    if metadata.files.len() != offer.files.len() {
+        return Err(PreviewMismatch { index: metadata.files.len().min(offer.files.len()) });
+    }
+    let mut previews = Vec::with_capacity(offer.files.len());
+    for (index, (meta, offered)) in metadata.files.into_iter().zip(&offer.files).enumerate() {
+        if meta.name != offered.name || meta.size != offered.size {
+            return Err(PreviewMismatch { index });
+        }
+        // only what the file's name says it is gets a preview of that kind
+        let claimed = guess_mime(&offered.name);
+        let thumbnail = meta.thumbnail.filter(|_| claimed.is_some_and(|m| m.starts_with("image/") || m == "application/pdf")).and_then(|t| reencode(&t));
+        let text = meta.text.filter(|_| claimed.is_none_or(|m| m.starts_with("text/"))).and_then(|t| clean_text(&t));
+        previews.push(Preview { thumbnail, text });
+    }
+    Ok(previews)
+}
+
+/// Decode a thumbnail under tight limits and encode it again as PNG
+fn reencode(thumbnail: &[u8]) -> Option<Vec<u8>> {
+    let format = match sniff(thumbnail)? {
+        "image/jpeg" => ImageFormat::Jpeg,
+        "image/png" => ImageFormat::Png,
+        "image/webp" => ImageFormat::WebP,
+        "image/gif" => ImageFormat::Gif,
+        _ => return None,
+    };
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(THUMBNAIL_EDGE);
+    limits.max_image_height = Some(THUMBNAIL_EDGE);
+    limits.max_alloc = Some(MAX_THUMBNAIL_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(thumbnail), format);
+    reader.limits(limits);
+    let image = reader.decode().ok()?;
+    let mut out = Vec::new();
+    image.write_to(&mut Cursor::new(&mut out), ImageFormat::Png).ok()?;
+    Some(out)
+}
+
+/// Text without control (but newlines and tabs) or invisible characters,
+/// cut to [`TEXT_PREVIEW_LINES`]; `None` if nothing visible is left
+fn clean_text(text: &str) -> Option<String> {
+    let cleaned: String = text.chars().filter(|&c| !is_invisible(c) && (!c.is_control() || c == '\n' || c == '\t')).collect();
+    let lines: Vec<&str> = cleaned.lines().take(TEXT_PREVIEW_LINES).collect();
+    let joined = lines.join("\n");
+    (!joined.trim().is_empty()).then_some(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_proto::TransferId;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn previews_are_made_and_checked_again() {
+        let dir = std::env::temp_dir().join(format!("globalsend-preview-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let photo = dir.join("photo.png");
+        RgbImage::from_fn(640, 320, |x, y| Rgb([x as u8, y as u8, 90])).save(&photo).unwrap();
+        let notes = dir.join("notes.txt");
+        std::fs::write(&notes, (1..=20).map(|i| format!("line {i}\n")).collect::<String>()).unwrap();
+        let blob = dir.join("data.bin");
+        std::fs::write(&blob, [0u8, 1, 2, 3]).unwrap();
+
+        let files = [(&photo, "photo.png"), (&notes, "notes.txt"), (&blob, "data.bin")]
+            .iter()
+            .map(|(path, name)| OfferedFile { name: (*name).into(), size: std::fs::metadata(path).unwrap().len(), mime: None })
+            .collect();
+        let offer = TransferOffer { transfer: TransferId([1; 16]), files };
+        let mut metadata = describe(&offer, &[&photo, &notes, &blob]);
+        assert_eq!(sniff(metadata.files[0].thumbnail.as_deref().unwrap()), Some("image/jpeg"));
+        assert_eq!(metadata.files[1].text.as_deref().unwrap().lines().count(), TEXT_PREVIEW_LINES);
+        assert_eq!((&metadata.files[2].thumbnail, &metadata.files[2].text), (&None, &None));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // what arrives is decoded again, and only kept where the name allows it
+        metadata.files[1].text = Some("total: 3\u{202e}txt.exe\u{7}\nok".into());
+        metadata.files[0].text = Some("MZ looks harmless".into());
+        let previews = check(metadata.clone(), &offer).unwrap();
+        let thumbnail = previews[0].thumbnail.as_deref().unwrap();
+        assert_eq!(sniff(thumbnail), Some("image/png"));
+        assert_eq!(image::load_from_memory(thumbnail).unwrap().width(), THUMBNAIL_EDGE);
+        assert_eq!(previews[0].text, None);
+        assert_eq!(previews[1].text.as_deref(), Some("total: 3txt.exe\nok"));
+        assert_eq!(previews[2], Preview::default());
+
+        let mut forged = metadata.clone();
+        forged.files[0].thumbnail = Some(b"\x89PNG\r\n\x1a\n not really".to_vec());
+        assert_eq!(check(forged, &offer).unwrap()[0].thumbnail, None);
+        let mut big = Vec::new();
+        RgbImage::new(4000, 10).write_to(&mut Cursor::new(&mut big), ImageFormat::Png).unwrap();
+        let mut oversized = metadata.clone();
+        oversized.files[0].thumbnail = Some(big);
+        assert_eq!(check(oversized, &offer).unwrap()[0].thumbnail, None);
+        let mut renamed = metadata;
+        renamed.files[1].name = "other.txt".into();
+        assert_eq!(check(renamed, &offer), Err(PreviewMismatch { index: 1 }));
+        assert_eq!(text_head(b"caf\xc3"), Some("caf".into()));
+        assert_eq!(text_head(b"\xff\xfe"), None);
+    }
+}
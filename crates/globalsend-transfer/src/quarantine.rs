@@ -0,0 +1,120 @@
+//! Holding received files until the user confirms them
+//!
+//! With a [`Quarantine`], a receiver writes a transfer into a private
+//! directory of its own instead of the download directory. Nothing there
+//! is opened, previewed or indexed; once the user has looked at what
+//! arrived, [`Quarantine::release`] moves it into place (renaming whatever
+//! would collide) or [`Quarantine::discard`] deletes it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globalsend_proto::{TransferId, TransferOffer};
+
+use crate::folder::{self, FolderError, Layout};
+
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    dir: PathBuf,
+}
+
+impl Quarantine {
+    /// Use `dir`, creating it readable by this user only
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where `transfer` is held
+    pub fn transfer_dir(&self, transfer: &TransferId) -> PathBuf {
+        self.dir.join(crate::hex_id(transfer))
+    }
+
+    /// Plan `offer` into the transfer's quarantine directory, as
+    /// [`Layout::plan`] would into a download directory
+    pub fn layout(&self, offer: &TransferOffer) -> Result<Layout, FolderError> {
+        Layout::plan(&self.transfer_dir(&offer.transfer), offer)
+    }
+
+    /// Transfers waiting for a decision
+    pub fn pending(&self) -> io::Result<Vec<TransferId>> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_str().and_then(crate::parse_hex_id) {
+                out.push(id);
+            }
+        }
+        out.sort_by_key(|id| id.0);
+        Ok(out)
+    }
+
+    /// Move everything `transfer` delivered into `destination`; returns
+    /// the top-level paths as they ended up
+    pub fn release(&self, transfer: &TransferId, destination: &Path) -> io::Result<Vec<PathBuf>> {
+        let held = self.transfer_dir(transfer);
+        fs::create_dir_all(destination)?;
+        let mut names: Vec<String> = fs::read_dir(&held)?.map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned())).collect::<Result<_, _>>()?;
+        names.sort();
+        let mut released = Vec::with_capacity(names.len());
+        for name in names {
+            let target = destination.join(folder::unique_name(destination, &name, std::iter::empty()));
+            fs::rename(held.join(&name), &target)?;
+            released.push(target);
+        }
+        fs::remove_dir(held)?;
+        Ok(released)
+    }
+
+    /// Delete what `transfer` delivered
+    pub fn discard(&self, transfer: &TransferId) -> io::Result<()> {
+        match fs::remove_dir_all(self.transfer_dir(transfer)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_proto::OfferedFile;
+
+    #[test]
+    fn holds_until_released() {
+        let base = std::env::temp_dir().join(format!("gs-quarantine-{}", std::process::id()));
+        let quarantine = Quarantine::open(base.join("held")).unwrap();
+        let downloads = base.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        fs::write(downloads.join("photo.jpg"), b"older").unwrap();
+
+        let offer = TransferOffer {
+            transfer: TransferId([4; 16]),
+            files: vec![OfferedFile { name: "photo.jpg".into(), size: 3, mime: None }, OfferedFile { name: "album/b.png".into(), size: 3, mime: None }],
+        };
+        let layout = quarantine.layout(&offer).unwrap();
+        layout.create_dirs().unwrap();
+        for i in 0..2 {
+            fs::write(layout.path(i), b"new").unwrap();
+        }
+        assert_eq!(quarantine.pending().unwrap(), vec![offer.transfer]);
+
+        let released = quarantine.release(&offer.transfer, &downloads).unwrap();
+        assert_eq!(released, vec![downloads.join("album"), downloads.join("photo (1).jpg")]);
+        assert_eq!(fs::read(downloads.join("album/b.png")).unwrap(), b"new");
+        assert!(quarantine.pending().unwrap().is_empty());
+        quarantine.discard(&offer.transfer).unwrap();
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,1180 @@
+//! One transfer, from either end
+//!
+//! [`TransferSession`] does no I/O. The caller reads files, hashes them and
+//! moves [`Message`]s over a transport; the session checks every step
+//! against the protocol, tracks per-file progress and queues
+//! [`TransferEvent`]s for whoever is displaying it. Files start in offer
+//! order; up to [`MAX_OPEN_FILES`] can be in flight at once, each on its own
+//! stream where the transport has them, and each file's chunks arrive in
+//! order. The sender paces itself by its [`TransferConfig`]:
+//! [`TransferSession::chunk`] refuses with [`TransferError::WindowFull`]
+//! until acks free up room.
+//!
+//! ```text
+//! S -> R : TransferOffer
+//! S -> R : Compression([Zstd])           optional, version 3 peers
+//! R -> S : Compression([Zstd])           optional, only to take up the offer
+//! R -> S : BlockSignatures(i)*           optional, version 4: older copies it has
+//! R -> S : Ack(0, 0)                     accept, or Cancel(Declined) / Decline (version 7)
+//!                                        or Accept(files) for only some, version 12
+//! S -> R : FileHeader(i), ChunkData(i)*  for each file, or DeltaChunk(i)*
+//! R -> S : Ack(i, offset)                as chunks are written, version 5
+//! R -> S : HashAck(i, offset, hash)      instead, every 64th chunk, version 10
+//! R -> S : Ack(i, size)                  after its hash checks out
+//! S -> R : Manifest                      folders only, right after the offer
+//! S -> R : Metadata                      previews, right after the offer, version 11
+//! ```
+//!
+//! A receiver resuming from a [`Checkpoint`](crate::checkpoint::Checkpoint)
+//! accepts with `Ack(i, offset)` instead: files before `i` are already
+//! there, and file `i`'s chunks start at `offset`. One that wants only some
+//! of the files accepts with an [`Accept`] listing them; the rest become
+//! [`FileStatus::Skipped`] on both sides and never get a header.
+//!
+//! A [`HashAck`] carries the BLAKE3 of everything the receiver wrote of the
+//! file so far. The sender hashes what it sends as it goes and fails the
+//! session on the first ack that disagrees, so a corrupted chunk early in a
+//! large file costs one window of chunks, not the whole file. Files the
+//! sender resumed or sent as deltas have no running hash on its side and
+//! are only checked at the end.
+//!
+//! Once compression is agreed the sender decides per chunk (see
+//! [`compress`](crate::compress)); the receiver gets the plain bytes back
+//! from [`TransferSession::on_message`] either way.
+//!
+//! Files the receiver sent [`BlockSignatures`] for may come as
+//! [`DeltaChunk`]s against its older copy (see [`delta`](crate::delta)).
+//! The session checks their lengths; the receiver rebuilds the bytes with
+//! [`delta::apply`](crate::delta::apply) and [`TransferSession::base`]. Delta chunks start
+//! at the beginning of the file, so resumed files go as plain chunks.
+//!
+//! The state is [`TransferState::Transferring`] while any file has bytes
+//! outstanding and [`TransferState::Verifying`] while the only open files
+//! wait for their hash check.
+//!
+//! Peer messages that break the protocol fail the session; the caller
+//! should then tell the peer with [`TransferSession::abort_message`].
+//! Messages arriving after the session has ended (chunks still in flight
+//! when a cancel crossed them) are ignored.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use globalsend_proto::{
+    Accept, Ack, BlockSignatures, Cancel, CancelReason, ChunkData, Codec, CompressedChunk, Compression, Decline, DeltaChunk, DeltaOp, FileHeader, HashAck,
+    Message, OfferedFile, Refusal, TransferId, TransferOffer,
+};
+
+use crate::compress;
+use crate::config::{TransferConfig, HASH_ACK_CHUNKS};
+use crate::delta::Signature;
+use crate::state::{Failure, InvalidTransition, TransferState};
+use crate::TransferError;
+
+/// Files a receiver lets the sender have open at once
+pub const MAX_OPEN_FILES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// What a GUI or progress bar needs to know, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferEvent {
+    State(TransferState),
+    FileStarted { index: u32 },
+    Progress { index: u32, bytes: u64, size: u64 },
+    /// Every byte of file `index` is through; its hash check is outstanding
+    Verifying { index: u32 },
+    /// File `index` arrived intact (receiver) or the receiver confirmed it (sender)
+    FileDone { index: u32 },
+    /// The peer called the transfer off
+    PeerCancelled(CancelReason),
+    /// The receiver's checks refused the offer; follows `PeerCancelled(Declined)`
+    Refused(Vec<Refusal>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// Not started
+    Pending,
+    /// Header exchanged, chunks flowing
+    Open,
+    /// Every byte through, hash check outstanding
+    Verifying,
+    Done,
+    /// Turned down by the receiver; never sent
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProgress {
+    pub name: String,
+    pub size: u64,
+    /// Sent or received so far
+    pub bytes: u64,
+    /// BLAKE3 from the file header, once it is known
+    pub hash: Option<[u8; 32]>,
+    pub chunk_size: u32,
+    pub status: FileStatus,
+    /// Sender: chunks may still go compressed; off for compressed formats
+    /// and after a chunk that did not shrink
+    pub compress: bool,
+}
+
+/// File bytes from a chunk, decompressed if they came compressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedChunk<'m> {
+    pub index: u32,
+    pub offset: u64,
+    pub data: Cow<'m, [u8]>,
+}
+
+/// Sender's record of a chunk until it is acked
+#[derive(Debug, Clone, Copy)]
+struct SentChunk {
+    /// Offset just past the chunk
+    end: u64,
+    /// BLAKE3 of the file up to `end`, where the sender has it
+    hash: Option<[u8; 32]>,
+}
+
+#[derive(Debug)]
+pub struct TransferSession {
+    id: TransferId,
+    direction: Direction,
+    state: TransferState,
+    files: Vec<FileProgress>,
+    /// Next file to start
+    next: usize,
+    /// Sender: we offered compression; receiver: the peer did
+    codec_offered: bool,
+    /// Agreed codec, if any
+    compression: Option<Codec>,
+    /// Receiver's older copies, by file index
+    bases: BTreeMap<u32, Arc<Signature>>,
+    /// Sender pacing; receivers keep the default
+    config: TransferConfig,
+    /// Sender: chunks sent and not yet acked, by file index
+    unacked: BTreeMap<u32, VecDeque<SentChunk>>,
+    /// Sender: running hash of each open file sent plain from its first byte
+    sent_hashes: BTreeMap<u32, blake3::Hasher>,
+    events: VecDeque<TransferEvent>,
+}
+
+impl TransferSession {
+    /// Sending side; send the returned offer to the receiver
+    pub fn outgoing(id: TransferId, files: Vec<OfferedFile>, config: TransferConfig) -> (Self, Message) {
+        let offer = TransferOffer { transfer: id, files: files.clone() };
+        let session = Self { config: config.clamped(), ..Self::new(id, Direction::Send, &files) };
+        (session, offer.into())
+    }
+
+    /// Receiving side of `offer`; answer with [`accept`](Self::accept) or [`decline`](Self::decline)
+    pub fn incoming(offer: &TransferOffer) -> Self {
+        Self::new(offer.transfer, Direction::Receive, &offer.files)
+    }
+
+    fn new(id: TransferId, direction: Direction, files: &[OfferedFile]) -> Self {
+        let files = files
+            .iter()
+            .map(|f| FileProgress {
+                name: f.name.clone(),
+                size: f.size,
+                bytes: 0,
+                hash: None,
+                chunk_size: 0,
+                status: FileStatus::Pending,
+                compress: compress::worth_trying(&f.name, f.mime.as_deref()),
+            })
+            .collect();
+        Self {
+            id,
+            direction,
+            state: TransferState::Offered,
+            files,
+            next: 0,
+            codec_offered: false,
+            compression: None,
+            bases: BTreeMap::new(),
+            config: TransferConfig::default(),
+            unacked: BTreeMap::new(),
+            sent_hashes: BTreeMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn id(&self) -> TransferId {
+        self.id
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn state(&self) -> TransferState {
+        self.state
+    }
+
+    pub fn files(&self) -> &[FileProgress] {
+        &self.files
+    }
+
+    pub fn config(&self) -> TransferConfig {
+        self.config
+    }
+
+    /// Sender: chunks that may go out before the next ack
+    pub fn window(&self) -> u32 {
+        let unacked: usize = self.unacked.values().map(VecDeque::len).sum();
+        self.config.inflight_chunks.saturating_sub(unacked.min(u32::MAX as usize) as u32)
+    }
+
+    /// Files started and not yet done
+    pub fn open_files(&self) -> impl Iterator<Item = u32> + '_ {
+        self.files.iter().enumerate().filter(|(_, f)| matches!(f.status, FileStatus::Open | FileStatus::Verifying)).map(|(i, _)| i as u32)
+    }
+
+    /// Files with some but not all of their data through: what a cancel leaves half-written
+    pub fn partial_files(&self) -> impl Iterator<Item = u32> + '_ {
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| !matches!(f.status, FileStatus::Done | FileStatus::Skipped) && (f.status != FileStatus::Pending || f.bytes > 0))
+            .map(|(i, _)| i as u32)
+    }
+
+    pub fn poll_event(&mut self) -> Option<TransferEvent> {
+        self.events.pop_front()
+    }
+
+    /// Codec both sides agreed on, if any
+    pub fn compression(&self) -> Option<Codec> {
+        self.compression
+    }
+
+    /// Sender: propose compression; send right after the offer, and only
+    /// to peers speaking protocol version 3 or later
+    pub fn offer_compression(&mut self) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Send)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Accepting));
+        }
+        self.codec_offered = true;
+        Ok(Compression { transfer: self.id, codecs: vec![Codec::Zstd] }.into())
+    }
+
+    /// Signature of the receiver's older copy of file `index`, if it sent one
+    ///
+    /// Shared, so the sender can run [`delta::diff`](crate::delta::diff)
+    /// while feeding its batches back in through [`delta`](Self::delta).
+    pub fn base(&self, index: u32) -> Option<Arc<Signature>> {
+        self.bases.get(&index).cloned()
+    }
+
+    /// Receiver: offer the older copy of file `index` as a delta base, before accepting
+    ///
+    /// Returns the signature messages to send; only peers speaking protocol
+    /// version 4 or later understand them.
+    pub fn delta_base(&mut self, index: u32, signature: Signature) -> Result<Vec<Message>, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Accepting));
+        }
+        if index as usize >= self.files.len() || self.bases.contains_key(&index) {
+            return Err(TransferError::NoSuchFile);
+        }
+        let messages = signature.messages(self.id, index);
+        self.bases.insert(index, Arc::new(signature));
+        Ok(messages)
+    }
+
+    /// Receiver: take up the sender's compression offer, before accepting
+    ///
+    /// Returns `None` if the sender did not offer anything usable; the
+    /// transfer then runs uncompressed. Skip the call to turn compression down.
+    pub fn accept_compression(&mut self) -> Result<Option<Message>, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Accepting));
+        }
+        if !self.codec_offered {
+            return Ok(None);
+        }
+        self.compression = Some(Codec::Zstd);
+        Ok(Some(Compression { transfer: self.id, codecs: vec![Codec::Zstd] }.into()))
+    }
+
+    /// Receiver: take the offer
+    pub fn accept(&mut self) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        self.set_state(TransferState::Accepting)?;
+        Ok(Ack { transfer: self.id, index: 0, offset: 0 }.into())
+    }
+
+    /// Receiver: take only the files `wanted` says, one decision per offered
+    /// file; only peers speaking protocol version 12 or later understand it
+    pub fn accept_files(&mut self, wanted: &[bool]) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Accepting));
+        }
+        if !self.select(wanted) {
+            return Err(TransferError::Selection);
+        }
+        self.set_state(TransferState::Accepting)?;
+        Ok(Accept { transfer: self.id, files: wanted.to_vec() }.into())
+    }
+
+    /// Receiver: take the offer, already holding every file before `index`
+    /// and file `index` up to `offset`
+    pub fn accept_from(&mut self, index: u32, offset: u64) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Accepting));
+        }
+        if !self.skip_to(index, offset) {
+            return Err(TransferError::ResumePoint);
+        }
+        self.set_state(TransferState::Accepting)?;
+        Ok(Ack { transfer: self.id, index, offset }.into())
+    }
+
+    /// Receiver: turn the offer down
+    pub fn decline(&mut self) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        if self.state != TransferState::Offered {
+            return Err(self.invalid(TransferState::Cancelled(CancelReason::Declined)));
+        }
+        self.cancel(CancelReason::Declined)
+    }
+
+    /// Receiver: turn the offer down because preflight checks failed (see
+    /// [`preflight`](crate::preflight)); peers before version 7 get [`decline`](Self::decline) instead
+    pub fn refuse(&mut self, refusals: Vec<Refusal>) -> Result<Message, TransferError> {
+        self.decline()?;
+        Ok(Decline { transfer: self.id, refusals }.into())
+    }
+
+    /// Sender: the file [`start_file`](Self::start_file) opens next, if any is left
+    pub fn next_file(&self) -> Option<u32> {
+        (self.next < self.files.len()).then_some(self.next as u32)
+    }
+
+    /// Sender: open the next file; `hash` is its BLAKE3
+    ///
+    /// Up to [`TransferConfig::streams`] files may be open at once. Returns
+    /// the header together with the index the file's chunks go under.
+    pub fn start_file(&mut self, hash: [u8; 32]) -> Result<(u32, Message), TransferError> {
+        self.expect_direction(Direction::Send)?;
+        if !self.started() {
+            return Err(self.invalid(TransferState::Transferring));
+        }
+        if self.open_files().count() >= usize::from(self.config.streams) {
+            return Err(TransferError::TooManyOpen);
+        }
+        let chunk_size = self.config.chunk_size;
+        let index = self.next;
+        if index == self.files.len() {
+            return Err(TransferError::NoMoreFiles);
+        }
+        let file = &mut self.files[index];
+        file.hash = Some(hash);
+        file.chunk_size = chunk_size;
+        let header = FileHeader { transfer: self.id, index: index as u32, size: file.size, chunk_size, hash };
+        self.open(index)?;
+        Ok((index as u32, header.into()))
+    }
+
+    /// Sender: the next chunk of open file `index`
+    ///
+    /// Every chunk but the last must be exactly the file's chunk size.
+    /// The chunk goes compressed if that was agreed and it shrinks enough.
+    pub fn chunk(&mut self, index: u32, data: Vec<u8>) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Send)?;
+        let index = index as usize;
+        if self.state.is_terminal() || self.files.get(index).map(|f| f.status) != Some(FileStatus::Open) {
+            return Err(TransferError::NotOpen);
+        }
+        if self.window() == 0 {
+            return Err(TransferError::WindowFull);
+        }
+        let file = &self.files[index];
+        if !chunk_fits(file, data.len()) {
+            return Err(TransferError::ChunkSize);
+        }
+        let (offset, len) = (file.bytes, data.len() as u64);
+        if let Some(hasher) = self.sent_hashes.get_mut(&(index as u32)) {
+            hasher.update(&data);
+        }
+        let packed = match self.compression {
+            Some(codec) if file.compress => compress::compress(&data).map(|data| (codec, data)),
+            _ => None,
+        };
+        let message = match packed {
+            Some((codec, data)) => CompressedChunk { transfer: self.id, index: index as u32, offset, codec, data }.into(),
+            None => {
+                // one chunk that did not shrink is enough to stop trying for this file
+                self.files[index].compress = false;
+                ChunkData { transfer: self.id, index: index as u32, offset, data }.into()
+            }
+        };
+        self.advance(index, len)?;
+        Ok(message)
+    }
+
+    /// Sender: the next part of open file `index` as changes against the
+    /// receiver's older copy, one batch from [`delta::diff`](crate::delta::diff)
+    pub fn delta(&mut self, index: u32, ops: Vec<DeltaOp>) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Send)?;
+        if self.state.is_terminal() || self.files.get(index as usize).map(|f| f.status) != Some(FileStatus::Open) {
+            return Err(TransferError::NotOpen);
+        }
+        if self.window() == 0 {
+            return Err(TransferError::WindowFull);
+        }
+        let base = self.bases.get(&index).ok_or(TransferError::NoBase)?;
+        let len = delta_len(&self.files[index as usize], base, &ops).ok_or(TransferError::ChunkSize)?;
+        // the rebuilt bytes never pass through here
+        self.sent_hashes.remove(&index);
+        let chunk = DeltaChunk { transfer: self.id, index, offset: self.files[index as usize].bytes, ops };
+        self.advance(index as usize, len)?;
+        Ok(chunk.into())
+    }
+
+    /// Receiver: the chunks of file `index` received so far are written out
+    ///
+    /// Returns the ack that opens the sender's window again, or `None` once
+    /// the whole file is in: the ack after [`verified`](Self::verified)
+    /// covers the last chunk.
+    pub fn written(&mut self, index: u32) -> Result<Option<Message>, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        let file = self.files.get(index as usize).filter(|f| !self.state.is_terminal() && matches!(f.status, FileStatus::Open | FileStatus::Verifying));
+        match file {
+            Some(file) if file.status == FileStatus::Open => Ok(Some(Ack { transfer: self.id, index, offset: file.bytes }.into())),
+            Some(_) => Ok(None),
+            None => Err(TransferError::NotOpen),
+        }
+    }
+
+    /// Receiver: [`written`](Self::written) for peers from [`HASH_ACK_VERSION`](crate::config::HASH_ACK_VERSION) on
+    ///
+    /// `hash` gives the BLAKE3 of file `index` as written so far, from its
+    /// first byte; it is only called when a [`HashAck`] is due.
+    pub fn written_with_hash(&mut self, index: u32, hash: impl FnOnce() -> [u8; 32]) -> Result<Option<Message>, TransferError> {
+        let Some(Message::Ack(ack)) = self.written(index)? else { return Ok(None) };
+        let chunk_size = u64::from(self.files[index as usize].chunk_size);
+        if ack.offset % (chunk_size * HASH_ACK_CHUNKS) != 0 {
+            return Ok(Some(ack.into()));
+        }
+        Ok(Some(HashAck { transfer: ack.transfer, index, offset: ack.offset, hash: hash() }.into()))
+    }
+
+    /// Receiver: result of hashing file `index` once all of it arrived
+    ///
+    /// Returns the ack for a match, or the cancel to send when the hash
+    /// is wrong (the session has then failed).
+    pub fn verified(&mut self, index: u32, hash: [u8; 32]) -> Result<Message, TransferError> {
+        self.expect_direction(Direction::Receive)?;
+        let index = index as usize;
+        if self.state.is_terminal() || self.files.get(index).map(|f| f.status) != Some(FileStatus::Verifying) {
+            return Err(TransferError::NotOpen);
+        }
+        if self.files[index].hash != Some(hash) {
+            self.set_state(TransferState::Failed(Failure::HashMismatch(index as u32)))?;
+            return Ok(self.abort_message());
+        }
+        let ack = Ack { transfer: self.id, index: index as u32, offset: self.files[index].size };
+        self.close(index)?;
+        Ok(ack.into())
+    }
+
+    /// Stop from this side; send the returned message to the peer
+    pub fn cancel(&mut self, reason: CancelReason) -> Result<Message, TransferError> {
+        let state = match reason {
+            CancelReason::User | CancelReason::Declined => TransferState::Cancelled(reason),
+            CancelReason::Failed => TransferState::Failed(Failure::Local),
+            CancelReason::Timeout => TransferState::Failed(Failure::Timeout),
+        };
+        self.set_state(state)?;
+        Ok(Cancel { transfer: self.id, reason }.into())
+    }
+
+    /// The cancel that tells the peer this session failed
+    pub fn abort_message(&self) -> Message {
+        let reason = match self.state {
+            TransferState::Cancelled(reason) => reason,
+            TransferState::Failed(Failure::Timeout) => CancelReason::Timeout,
+            _ => CancelReason::Failed,
+        };
+        Cancel { transfer: self.id, reason }.into()
+    }
+
+    /// Feed a message from the peer
+    ///
+    /// For chunks the receiver gets back the bytes to write; delta chunks
+    /// return nothing and are rebuilt by the caller with [`delta::apply`](crate::delta::apply).
+    /// [`TransferError::WrongTransfer`] leaves the session untouched, so a
+    /// caller running several transfers over one connection can route on it.
+    pub fn on_message<'m>(&mut self, message: &'m Message) -> Result<Option<ReceivedChunk<'m>>, TransferError> {
+        let transfer = match message {
+            Message::FileHeader(m) => m.transfer,
+            Message::ChunkData(m) => m.transfer,
+            Message::CompressedChunk(m) => m.transfer,
+            Message::Ack(m) => m.transfer,
+            Message::HashAck(m) => m.transfer,
+            Message::Cancel(m) => m.transfer,
+            Message::TransferOffer(m) => m.transfer,
+            Message::Manifest(m) => m.transfer,
+            Message::Compression(m) => m.transfer,
+            Message::BlockSignatures(m) => m.transfer,
+            Message::DeltaChunk(m) => m.transfer,
+            Message::Snippet(m) => m.transfer,
+            Message::Decline(m) => m.transfer,
+            Message::ManifestMinisign(m) => m.transfer,
+            Message::Metadata(m) => m.transfer,
+            Message::Accept(m) => m.transfer,
+            Message::Hello(_) | Message::HelloCapabilities(_) | Message::PairRequest(_) | Message::SyncIndex(_) | Message::IdentityProof(_) => return self.violation("not a transfer message").map(|_| None),
+        };
+        if transfer != self.id {
+            return Err(TransferError::WrongTransfer(transfer));
+        }
+        if self.state.is_terminal() {
+            return Ok(None);
+        }
+        match (self.direction, message) {
+            (Direction::Receive, Message::ChunkData(chunk)) => {
+                self.on_chunk(chunk.index, chunk.offset, chunk.data.len())?;
+                Ok(Some(ReceivedChunk { index: chunk.index, offset: chunk.offset, data: Cow::Borrowed(&chunk.data) }))
+            }
+            (Direction::Receive, Message::CompressedChunk(chunk)) => {
+                let data = self.decompress(chunk)?;
+                self.on_chunk(chunk.index, chunk.offset, data.len())?;
+                Ok(Some(ReceivedChunk { index: chunk.index, offset: chunk.offset, data: Cow::Owned(data) }))
+            }
+            _ => self.on_control(message).map(|_| None),
+        }
+    }
+
+    fn on_control(&mut self, message: &Message) -> Result<(), TransferError> {
+        match (self.direction, message) {
+            (_, Message::Cancel(cancel)) => {
+                self.events.push_back(TransferEvent::PeerCancelled(cancel.reason));
+                let state = match cancel.reason {
+                    CancelReason::User | CancelReason::Declined => TransferState::Cancelled(cancel.reason),
+                    CancelReason::Failed => TransferState::Failed(Failure::Peer),
+                    CancelReason::Timeout => TransferState::Failed(Failure::Timeout),
+                };
+                self.set_state(state)
+            }
+            (Direction::Send, Message::Decline(decline)) if self.state == TransferState::Offered => {
+                self.events.push_back(TransferEvent::PeerCancelled(CancelReason::Declined));
+                self.events.push_back(TransferEvent::Refused(decline.refusals.clone()));
+                self.set_state(TransferState::Cancelled(CancelReason::Declined))
+            }
+            (Direction::Send, Message::Ack(ack)) => self.on_ack(ack),
+            (Direction::Send, Message::Accept(accept)) if self.state == TransferState::Offered => self.on_accept(accept),
+            (Direction::Send, Message::HashAck(ack)) if self.started() => self.on_hash_ack(ack),
+            (Direction::Receive, Message::FileHeader(header)) => self.on_header(header),
+            (Direction::Receive, Message::DeltaChunk(chunk)) => self.on_delta(chunk),
+            (Direction::Send, Message::BlockSignatures(signatures)) if self.state == TransferState::Offered => self.on_signatures(signatures),
+            (_, Message::Compression(compression)) if self.state == TransferState::Offered => self.on_compression(compression),
+            // checking the manifest and previews is the caller's job; they only have to come before the answer
+            (Direction::Receive, Message::Manifest(_) | Message::ManifestMinisign(_) | Message::Metadata(_)) if self.state == TransferState::Offered => Ok(()),
+            _ => self.violation("unexpected message"),
+        }
+    }
+
+    fn on_ack(&mut self, ack: &Ack) -> Result<(), TransferError> {
+        if self.state == TransferState::Offered {
+            if !self.bases_complete() {
+                return self.violation("acceptance before the block signatures were complete");
+            }
+            if !self.skip_to(ack.index, ack.offset) {
+                return self.violation("acceptance resumes past the end of a file");
+            }
+            return self.set_state(TransferState::Accepting);
+        }
+        let index = ack.index as usize;
+        let Some(file) = self.files.get(index).filter(|f| matches!(f.status, FileStatus::Open | FileStatus::Verifying)) else {
+            return self.violation("ack for a file not in flight");
+        };
+        if ack.offset > file.bytes {
+            return self.violation("ack beyond what was sent");
+        }
+        if file.status == FileStatus::Verifying && ack.offset == file.size {
+            self.unacked.remove(&ack.index);
+            return self.close(index);
+        }
+        if let Some(sent) = self.unacked.get_mut(&ack.index) {
+            while sent.front().is_some_and(|chunk| chunk.end <= ack.offset) {
+                sent.pop_front();
+            }
+        }
+        // an intermediate ack only reports progress
+        Ok(())
+    }
+
+    /// Check the receiver's hash against ours, then take it as an ack
+    fn on_hash_ack(&mut self, ack: &HashAck) -> Result<(), TransferError> {
+        let ours = self.unacked.get(&ack.index).and_then(|sent| sent.iter().find(|chunk| chunk.end == ack.offset)).and_then(|chunk| chunk.hash);
+        if ours.is_some_and(|ours| ours != ack.hash) {
+            self.set_state(TransferState::Failed(Failure::HashMismatch(ack.index)))?;
+            return Err(TransferError::Diverged { index: ack.index, offset: ack.offset });
+        }
+        self.on_ack(&Ack { transfer: ack.transfer, index: ack.index, offset: ack.offset })
+    }
+
+    fn on_accept(&mut self, accept: &Accept) -> Result<(), TransferError> {
+        if !self.bases_complete() {
+            return self.violation("acceptance before the block signatures were complete");
+        }
+        if !self.select(&accept.files) {
+            return self.violation("acceptance does not choose among the offered files");
+        }
+        self.set_state(TransferState::Accepting)
+    }
+
+    fn bases_complete(&self) -> bool {
+        self.bases.values().all(|base| base.blocks.len() as u64 == Signature::block_count(base.size, base.block_size))
+    }
+
+    fn on_header(&mut self, header: &FileHeader) -> Result<(), TransferError> {
+        if !self.started() {
+            return self.violation("file header before the offer was accepted");
+        }
+        if self.open_files().count() == MAX_OPEN_FILES {
+            return self.violation("too many open files");
+        }
+        let index = self.next;
+        if header.index as usize != index || index == self.files.len() {
+            return self.violation("file header out of order");
+        }
+        if header.size != self.files[index].size || header.chunk_size == 0 {
+            return self.violation("file header does not match the offer");
+        }
+        let file = &mut self.files[index];
+        file.hash = Some(header.hash);
+        file.chunk_size = header.chunk_size;
+        self.open(index)
+    }
+
+    fn on_compression(&mut self, compression: &Compression) -> Result<(), TransferError> {
+        match self.direction {
+            Direction::Receive => {
+                if self.codec_offered {
+                    return self.violation("compression offered twice");
+                }
+                self.codec_offered = compression.codecs.contains(&Codec::Zstd);
+            }
+            Direction::Send => {
+                if !self.codec_offered || self.compression.is_some() || compression.codecs != [Codec::Zstd] {
+                    return self.violation("compression answer without a matching offer");
+                }
+                self.compression = Some(Codec::Zstd);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_signatures(&mut self, signatures: &BlockSignatures) -> Result<(), TransferError> {
+        let BlockSignatures { index, size, block_size, first, .. } = *signatures;
+        if index as usize >= self.files.len() || block_size == 0 {
+            return self.violation("block signatures for no offered file");
+        }
+        let base = self.bases.entry(index).or_insert_with(|| Arc::new(Signature { size, block_size, blocks: Vec::new() }));
+        // only the session holds it until the offer is accepted
+        let base = Arc::make_mut(base);
+        let expected = Signature::block_count(size, block_size);
+        if (base.size, base.block_size) != (size, block_size)
+            || base.blocks.len() != first as usize
+            || (first > 0 && signatures.blocks.is_empty())
+            || (base.blocks.len() + signatures.blocks.len()) as u64 > expected
+        {
+            return self.violation("block signatures out of order");
+        }
+        base.blocks.extend_from_slice(&signatures.blocks);
+        Ok(())
+    }
+
+    fn on_delta(&mut self, chunk: &DeltaChunk) -> Result<(), TransferError> {
+        let index = chunk.index as usize;
+        let Some(file) = self.files.get(index).filter(|f| f.status == FileStatus::Open) else {
+            return self.violation("chunk for a file not in flight");
+        };
+        let Some(base) = self.bases.get(&chunk.index) else {
+            return self.violation("delta chunk without a base");
+        };
+        if chunk.offset != file.bytes {
+            return self.violation("chunk out of order");
+        }
+        match delta_len(file, base, &chunk.ops) {
+            Some(len) => self.advance(index, len),
+            None => self.violation("delta chunk does not fit the file"),
+        }
+    }
+
+    fn on_chunk(&mut self, index: u32, offset: u64, len: usize) -> Result<(), TransferError> {
+        let index = index as usize;
+        let Some(file) = self.files.get(index).filter(|f| f.status == FileStatus::Open) else {
+            return self.violation("chunk for a file not in flight");
+        };
+        if offset != file.bytes {
+            return self.violation("chunk out of order");
+        }
+        if !chunk_fits(file, len) {
+            return self.violation("chunk size does not match the header");
+        }
+        self.advance(index, len as u64)
+    }
+
+    /// Unpack a compressed chunk, never past its file's chunk size
+    fn decompress(&mut self, chunk: &CompressedChunk) -> Result<Vec<u8>, TransferError> {
+        if self.compression != Some(chunk.codec) {
+            self.violation("compressed chunk without agreed compression")?;
+        }
+        let max = match self.files.get(chunk.index as usize).filter(|f| f.status == FileStatus::Open) {
+            Some(file) => file.chunk_size as usize,
+            None => return self.violation("chunk for a file not in flight").map(|_| Vec::new()),
+        };
+        match compress::decompress(&chunk.data, max) {
+            Ok(data) => Ok(data),
+            Err(_) => self.violation("chunk does not decompress").map(|_| Vec::new()),
+        }
+    }
+
+    /// Mark everything before file `index`, offset `offset` as already transferred
+    fn skip_to(&mut self, index: u32, offset: u64) -> bool {
+        let index = index as usize;
+        if index >= self.files.len() || offset > self.files[index].size {
+            return false;
+        }
+        for file in &mut self.files[..index] {
+            file.bytes = file.size;
+            file.status = FileStatus::Done;
+        }
+        self.files[index].bytes = offset;
+        self.next = index;
+        true
+    }
+
+    /// Skip the files `wanted` turns down; false if it does not fit the offer or takes nothing
+    fn select(&mut self, wanted: &[bool]) -> bool {
+        if wanted.len() != self.files.len() || !wanted.contains(&true) {
+            return false;
+        }
+        for (file, _) in self.files.iter_mut().zip(wanted).filter(|(_, wanted)| !**wanted) {
+            file.status = FileStatus::Skipped;
+        }
+        self.next = self.wanted_from(0);
+        true
+    }
+
+    /// First file from `index` on that was not skipped, or the end
+    fn wanted_from(&self, index: usize) -> usize {
+        (index..self.files.len()).find(|&i| self.files[i].status != FileStatus::Skipped).unwrap_or(self.files.len())
+    }
+
+    /// Accepted and not over: files may start
+    fn started(&self) -> bool {
+        matches!(self.state, TransferState::Accepting | TransferState::Transferring | TransferState::Verifying)
+    }
+
+    fn open(&mut self, index: usize) -> Result<(), TransferError> {
+        if self.state != TransferState::Transferring {
+            self.set_state(TransferState::Transferring)?;
+        }
+        self.next = self.wanted_from(index + 1);
+        self.events.push_back(TransferEvent::FileStarted { index: index as u32 });
+        let file = &mut self.files[index];
+        file.status = if file.bytes == file.size { FileStatus::Verifying } else { FileStatus::Open };
+        if self.direction == Direction::Send && file.bytes == 0 {
+            self.sent_hashes.insert(index as u32, blake3::Hasher::new());
+        }
+        if file.status == FileStatus::Verifying {
+            self.events.push_back(TransferEvent::Verifying { index: index as u32 });
+        }
+        self.settle()
+    }
+
+    fn advance(&mut self, index: usize, len: u64) -> Result<(), TransferError> {
+        let file = &mut self.files[index];
+        file.bytes += len;
+        let (bytes, size) = (file.bytes, file.size);
+        if self.direction == Direction::Send {
+            let hash = self.sent_hashes.get(&(index as u32)).map(|hasher| *hasher.finalize().as_bytes());
+            self.unacked.entry(index as u32).or_default().push_back(SentChunk { end: bytes, hash });
+        }
+        self.events.push_back(TransferEvent::Progress { index: index as u32, bytes, size });
+        if bytes == size {
+            self.files[index].status = FileStatus::Verifying;
+            self.events.push_back(TransferEvent::Verifying { index: index as u32 });
+        }
+        self.settle()
+    }
+
+    fn close(&mut self, index: usize) -> Result<(), TransferError> {
+        self.sent_hashes.remove(&(index as u32));
+        self.files[index].status = FileStatus::Done;
+        self.events.push_back(TransferEvent::FileDone { index: index as u32 });
+        self.settle()
+    }
+
+    /// Move the session state to match its files
+    fn settle(&mut self) -> Result<(), TransferError> {
+        let status = |s| self.files.iter().any(|f| f.status == s);
+        let target = if status(FileStatus::Open) {
+            TransferState::Transferring
+        } else if status(FileStatus::Verifying) {
+            TransferState::Verifying
+        } else if self.files.iter().all(|f| matches!(f.status, FileStatus::Done | FileStatus::Skipped)) {
+            TransferState::Done
+        } else {
+            // between files; the next header moves us on
+            return Ok(());
+        };
+        if target != self.state {
+            self.set_state(target)?;
+        }
+        Ok(())
+    }
+
+    fn set_state(&mut self, to: TransferState) -> Result<(), TransferError> {
+        self.state = self.state.transition(to)?;
+        self.events.push_back(TransferEvent::State(to));
+        Ok(())
+    }
+
+    fn violation(&mut self, what: &'static str) -> Result<(), TransferError> {
+        if !self.state.is_terminal() {
+            self.set_state(TransferState::Failed(Failure::Protocol))?;
+        }
+        Err(TransferError::Protocol(what))
+    }
+
+    fn invalid(&self, to: TransferState) -> TransferError {
+        TransferError::Transition(InvalidTransition { from: self.state, to })
+    }
+
+    fn expect_direction(&self, direction: Direction) -> Result<(), TransferError> {
+        if self.direction == direction {
+            Ok(())
+        } else {
+            Err(TransferError::WrongSide)
+        }
+    }
+}
+
+/// Non-empty, no larger than the chunk size, and exactly the chunk size unless it ends the file
+fn chunk_fits(file: &FileProgress, len: usize) -> bool {
+    let len = len as u64;
+    let remaining = file.size - file.bytes;
+    len > 0 && len <= remaining && (len == u64::from(file.chunk_size) || (len == remaining && len < u64::from(file.chunk_size)))
+}
+
+/// Bytes `ops` rebuild, if that is something and fits in the rest of the
+/// file, and the literals fit in a chunk
+fn delta_len(file: &FileProgress, base: &Signature, ops: &[DeltaOp]) -> Option<u64> {
+    let (len, literal) = base.output_len(ops)?;
+    (len > 0 && len <= file.size - file.bytes && literal <= u64::from(file.chunk_size)).then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer() -> Vec<OfferedFile> {
+        vec![
+            OfferedFile { name: "a.txt".into(), size: 10, mime: None },
+            OfferedFile { name: "empty".into(), size: 0, mime: None },
+        ]
+    }
+
+    fn config(chunk_size: u32) -> TransferConfig {
+        TransferConfig { chunk_size, ..TransferConfig::default() }
+    }
+
+    fn deliver(to: &mut TransferSession, message: Message) {
+        to.on_message(&message).unwrap();
+    }
+
+    #[test]
+    fn sender_and_receiver_agree_on_every_step() {
+        let id = TransferId([3; 16]);
+        let (mut tx, offer_msg) = TransferSession::outgoing(id, offer(), config(4));
+        let Message::TransferOffer(ref offer) = offer_msg else { panic!() };
+        let mut rx = TransferSession::incoming(offer);
+
+        deliver(&mut tx, rx.accept().unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Accepting, TransferState::Accepting));
+
+        deliver(&mut rx, tx.start_file([1; 32]).unwrap().1);
+        assert!(matches!(tx.chunk(0, vec![0; 3]), Err(TransferError::ChunkSize)));
+        for data in [vec![0; 4], vec![0; 4], vec![0; 2]] {
+            deliver(&mut rx, tx.chunk(0, data).unwrap());
+        }
+        assert_eq!((tx.state(), rx.state()), (TransferState::Verifying, TransferState::Verifying));
+        deliver(&mut tx, rx.verified(0, [1; 32]).unwrap());
+
+        deliver(&mut rx, tx.start_file([2; 32]).unwrap().1);
+        assert!(matches!(tx.start_file([3; 32]), Err(TransferError::NoMoreFiles)));
+        deliver(&mut tx, rx.verified(1, [2; 32]).unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Done, TransferState::Done));
+        assert!(tx.files().iter().chain(rx.files()).all(|f| f.status == FileStatus::Done));
+
+        let events: Vec<_> = std::iter::from_fn(|| rx.poll_event()).collect();
+        assert_eq!(events.first(), Some(&TransferEvent::State(TransferState::Accepting)));
+        assert!(events.contains(&TransferEvent::Progress { index: 0, bytes: 10, size: 10 }));
+        assert_eq!(events.last(), Some(&TransferEvent::State(TransferState::Done)));
+    }
+
+    #[test]
+    fn bad_peer_input_fails_the_session() {
+        let id = TransferId([4; 16]);
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, offer(), config(4)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        deliver(&mut tx, rx.accept().unwrap());
+        deliver(&mut rx, tx.start_file([1; 32]).unwrap().1);
+
+        let stray = ChunkData { transfer: TransferId([9; 16]), index: 0, offset: 0, data: vec![0; 4] };
+        assert!(matches!(rx.on_message(&stray.into()), Err(TransferError::WrongTransfer(_))));
+        assert_eq!(rx.state(), TransferState::Transferring);
+
+        let skipped = ChunkData { transfer: id, index: 0, offset: 4, data: vec![0; 4] };
+        assert!(matches!(rx.on_message(&skipped.into()), Err(TransferError::Protocol(_))));
+        assert_eq!(rx.state(), TransferState::Failed(Failure::Protocol));
+
+        deliver(&mut tx, rx.abort_message());
+        assert_eq!(tx.state(), TransferState::Failed(Failure::Peer));
+        // late chunks after the end are dropped quietly
+        assert!(rx.on_message(&tx.abort_message()).is_ok());
+    }
+
+    #[test]
+    fn accepts_only_some_files() {
+        let id = TransferId([7; 16]);
+        let mut files = offer();
+        files.push(OfferedFile { name: "b.txt".into(), size: 4, mime: None });
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files, config(4)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        assert_eq!(rx.accept_files(&[true, false]), Err(TransferError::Selection));
+        assert_eq!(rx.accept_files(&[false; 3]), Err(TransferError::Selection));
+
+        deliver(&mut tx, rx.accept_files(&[false, true, true]).unwrap());
+        assert_eq!(tx.files()[0].status, FileStatus::Skipped);
+        assert_eq!(tx.next_file(), Some(1));
+        deliver(&mut rx, tx.start_file([2; 32]).unwrap().1);
+        deliver(&mut tx, rx.verified(1, [2; 32]).unwrap());
+        assert_eq!(tx.next_file(), Some(2));
+        let (index, header) = tx.start_file([3; 32]).unwrap();
+        deliver(&mut rx, header);
+        deliver(&mut rx, tx.chunk(index, vec![0; 4]).unwrap());
+        deliver(&mut tx, rx.verified(2, [3; 32]).unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Done, TransferState::Done));
+        assert_eq!(tx.next_file(), None);
+        assert_eq!(rx.partial_files().count(), 0);
+
+        // a header for a file the receiver turned down breaks the protocol
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, offer.files, config(4)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        deliver(&mut tx, rx.accept_files(&[true, true, false]).unwrap());
+        let header = FileHeader { transfer: id, index: 2, size: 4, chunk_size: 4, hash: [0; 32] };
+        assert!(matches!(rx.on_message(&header.into()), Err(TransferError::Protocol(_))));
+        let bad = Accept { transfer: id, files: vec![true] };
+        let (mut tx, _) = TransferSession::outgoing(id, offer.files, config(4));
+        assert!(matches!(tx.on_message(&bad.into()), Err(TransferError::Protocol(_))));
+    }
+
+    #[test]
+    fn resumes_mid_file() {
+        let id = TransferId([6; 16]);
+        let mut files = offer();
+        files.reverse();
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files, config(4)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        assert_eq!(rx.accept_from(1, 11), Err(TransferError::ResumePoint));
+        deliver(&mut tx, rx.accept_from(1, 8).unwrap());
+        assert_eq!(tx.files()[0].status, FileStatus::Done);
+
+        deliver(&mut rx, tx.start_file([1; 32]).unwrap().1);
+        let Message::ChunkData(chunk) = tx.chunk(1, vec![0; 2]).unwrap() else { panic!() };
+        assert_eq!((chunk.index, chunk.offset), (1, 8));
+        deliver(&mut rx, chunk.into());
+        deliver(&mut tx, rx.verified(1, [1; 32]).unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Done, TransferState::Done));
+    }
+
+    #[test]
+    fn files_interleave() {
+        let id = TransferId([7; 16]);
+        let mut files = offer();
+        files[1].size = 4;
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files, config(8)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        deliver(&mut tx, rx.accept().unwrap());
+
+        let (a, header_a) = tx.start_file([1; 32]).unwrap();
+        let (b, header_b) = tx.start_file([2; 32]).unwrap();
+        deliver(&mut rx, header_a);
+        deliver(&mut rx, header_b);
+        deliver(&mut rx, tx.chunk(a, vec![0; 8]).unwrap());
+        deliver(&mut rx, tx.chunk(b, vec![0; 4]).unwrap());
+        deliver(&mut tx, rx.verified(b, [2; 32]).unwrap());
+        assert_eq!(rx.open_files().collect::<Vec<_>>(), vec![a]);
+        assert_eq!(rx.state(), TransferState::Transferring);
+
+        deliver(&mut rx, tx.chunk(a, vec![0; 2]).unwrap());
+        assert_eq!(rx.state(), TransferState::Verifying);
+        deliver(&mut tx, rx.verified(a, [1; 32]).unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Done, TransferState::Done));
+    }
+
+    #[test]
+    fn compresses_only_what_shrinks() {
+        let id = TransferId([8; 16]);
+        let files = vec![
+            OfferedFile { name: "build.log".into(), size: 2048, mime: None },
+            OfferedFile { name: "photo.jpg".into(), size: 2048, mime: None },
+        ];
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files, config(1024)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        deliver(&mut rx, tx.offer_compression().unwrap());
+        deliver(&mut tx, rx.accept_compression().unwrap().unwrap());
+        deliver(&mut tx, rx.accept().unwrap());
+        assert_eq!((tx.compression(), rx.compression()), (Some(Codec::Zstd), Some(Codec::Zstd)));
+
+        let log = vec![b'x'; 1024];
+        let (a, header) = tx.start_file([1; 32]).unwrap();
+        deliver(&mut rx, header);
+        let packed = tx.chunk(a, log.clone()).unwrap();
+        assert!(matches!(packed, Message::CompressedChunk(_)));
+        assert_eq!(rx.on_message(&packed).unwrap().unwrap().data, log);
+        let noise = crate::compress::tests::noise(1024);
+        assert!(matches!(tx.chunk(a, noise).unwrap(), Message::ChunkData(_)));
+        assert!(!tx.files()[0].compress);
+
+        let (b, _) = tx.start_file([2; 32]).unwrap();
+        assert!(matches!(tx.chunk(b, log.clone()).unwrap(), Message::ChunkData(_)));
+
+        // a receiver that never agreed rejects compressed chunks
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, offer.files.clone(), config(1024)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        tx.offer_compression().unwrap();
+        deliver(&mut tx, rx.accept().unwrap());
+        deliver(&mut rx, tx.start_file([1; 32]).unwrap().1);
+        let forged = CompressedChunk { transfer: id, index: 0, offset: 0, codec: Codec::Zstd, data: compress::compress(&log).unwrap() };
+        assert!(matches!(rx.on_message(&forged.into()), Err(TransferError::Protocol(_))));
+        assert_eq!(tx.compression(), None);
+    }
+
+    #[test]
+    fn delta_against_an_older_copy() {
+        let id = TransferId([9; 16]);
+        let old = crate::compress::tests::noise(20_000);
+        let mut new = old.clone();
+        new[7_000..7_004].copy_from_slice(b"edit");
+        let files = vec![OfferedFile { name: "disk.img".into(), size: new.len() as u64, mime: None }];
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files, config(4096)) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+
+        let signature = Signature::compute(&old[..], 2048).unwrap();
+        let mut parts = rx.delta_base(0, signature.clone()).unwrap();
+        assert_eq!(rx.delta_base(0, signature), Err(TransferError::NoSuchFile));
+        // an accept that overtakes the signatures is refused
+        let mut early = TransferSession::outgoing(id, offer.files.clone(), config(4096)).0;
+        let Message::BlockSignatures(mut partial) = parts[0].clone() else { panic!() };
+        partial.blocks.truncate(3);
+        deliver(&mut early, partial.into());
+        let accept = Ack { transfer: id, index: 0, offset: 0 };
+        assert!(matches!(early.on_message(&accept.into()), Err(TransferError::Protocol(_))));
+
+        deliver(&mut tx, parts.remove(0));
+        deliver(&mut tx, rx.accept().unwrap());
+        assert_eq!(tx.base(0).map(|b| b.blocks.len()), Some(10));
+
+        deliver(&mut rx, tx.start_file(*blake3::hash(&new).as_bytes()).unwrap().1);
+        let mut rebuilt = Vec::new();
+        crate::delta::diff(&tx.base(0).unwrap(), &new[..], 4096, |ops| {
+            let message = tx.delta(0, ops).unwrap();
+            assert!(rx.on_message(&message).unwrap().is_none());
+            let Message::DeltaChunk(chunk) = message else { panic!() };
+            crate::delta::apply(&rx.base(0).unwrap(), std::io::Cursor::new(&old), &chunk.ops, &mut rebuilt).map(|_| ())
+        })
+        .unwrap();
+        assert_eq!(rebuilt, new);
+        deliver(&mut tx, rx.verified(0, *blake3::hash(&rebuilt).as_bytes()).unwrap());
+        assert_eq!((tx.state(), rx.state()), (TransferState::Done, TransferState::Done));
+    }
+
+    #[test]
+    fn window_waits_for_acks() {
+        let id = TransferId([10; 16]);
+        let config = TransferConfig { chunk_size: 4, inflight_chunks: 2, streams: 1 };
+        let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, offer(), config) else { panic!() };
+        let mut rx = TransferSession::incoming(&offer);
+        deliver(&mut tx, rx.accept().unwrap());
+        deliver(&mut rx, tx.start_file([1; 32]).unwrap().1);
+        assert_eq!(tx.start_file([2; 32]), Err(TransferError::TooManyOpen));
+
+        deliver(&mut rx, tx.chunk(0, vec![0; 4]).unwrap());
+        deliver(&mut rx, tx.chunk(0, vec![0; 4]).unwrap());
+        assert_eq!(tx.chunk(0, vec![0; 2]), Err(TransferError::WindowFull));
+        let Some(ack) = rx.written(0).unwrap() else { panic!() };
+        assert_eq!(ack, Ack { transfer: id, index: 0, offset: 8 }.into());
+        deliver(&mut tx, ack);
+        assert_eq!(tx.window(), 2);
+
+        deliver(&mut rx, tx.chunk(0, vec![0; 2]).unwrap());
+        assert_eq!(rx.written(0), Ok(None));
+        assert_eq!(tx.window(), 1);
+        deliver(&mut tx, rx.verified(0, [1; 32]).unwrap());
+        assert_eq!(tx.window(), 2);
+        assert_eq!(TransferConfig::default().for_peer(4).inflight_chunks, u32::MAX);
+    }
+
+    #[test]
+    fn hash_acks_catch_a_diverging_receiver() {
+        let id = TransferId([11; 16]);
+        let data = crate::compress::tests::noise(1000);
+        let files = vec![OfferedFile { name: "a.bin".into(), size: data.len() as u64, mime: None }];
+        for corrupt in [false, true] {
+            let (mut tx, Message::TransferOffer(offer)) = TransferSession::outgoing(id, files.clone(), config(4)) else { panic!() };
+            let mut rx = TransferSession::incoming(&offer);
+            deliver(&mut tx, rx.accept().unwrap());
+            deliver(&mut rx, tx.start_file(*blake3::hash(&data).as_bytes()).unwrap().1);
+            let mut written = blake3::Hasher::new();
+            let mut hash_acks = 0;
+            for chunk in data.chunks(4) {
+                let message = tx.chunk(0, chunk.to_vec()).unwrap();
+                let received = rx.on_message(&message).unwrap().unwrap();
+                let mut bytes = received.data.into_owned();
+                if corrupt && received.offset == 8 {
+                    bytes[1] ^= 0x10;
+                }
+                written.update(&bytes);
+                let Some(ack) = rx.written_with_hash(0, || *written.finalize().as_bytes()).unwrap() else { break };
+                if let Message::HashAck(hash_ack) = &ack {
+                    assert_eq!(hash_ack.offset, 4 * HASH_ACK_CHUNKS * (hash_acks + 1));
+                    hash_acks += 1;
+                }
+                match tx.on_message(&ack) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        assert!(corrupt);
+                        assert_eq!(e, TransferError::Diverged { index: 0, offset: 4 * HASH_ACK_CHUNKS });
+                        assert_eq!(tx.state(), TransferState::Failed(Failure::HashMismatch(0)));
+                        break;
+                    }
+                }
+            }
+            if !corrupt {
+                assert_eq!(hash_acks, 3);
+                deliver(&mut tx, rx.verified(0, *written.finalize().as_bytes()).unwrap());
+                assert_eq!(tx.state(), TransferState::Done);
+            }
+        }
+    }
+}
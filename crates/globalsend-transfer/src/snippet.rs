@@ -0,0 +1,143 @@
+//! Text and links sent without files
+//!
+//! A [`Snippet`] carries a one-liner or a URL inline, so neither side
+//! materializes a temporary file for it. The sender builds one with
+//! [`offer`]; the receiver checks it with [`ReceivedSnippet::receive`],
+//! shows it with the [`actions`](ReceivedSnippet::actions) that make sense
+//! (a link can be opened in the browser or copied, text only copied) and
+//! answers once the user has picked one, or declined.
+
+use std::fmt;
+
+use globalsend_proto::{Ack, Cancel, CancelReason, Message, Payload, Snippet, TransferId, MAX_SNIPPET_LEN};
+
+/// What the receiver can do with a snippet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnippetAction {
+    /// Open the link in the default browser
+    Open,
+    /// Put it on the clipboard
+    Copy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnippetError {
+    Empty,
+    TooLong { len: usize, max: usize },
+    /// Not an absolute `http` or `https` URL, or one with spaces or control characters
+    UnsafeUrl,
+}
+
+impl fmt::Display for SnippetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnippetError::Empty => write!(f, "empty snippet"),
+            SnippetError::TooLong { len, max } => write!(f, "snippet of {len} bytes is over the {max} byte limit"),
+            SnippetError::UnsafeUrl => write!(f, "only http and https links can be sent"),
+        }
+    }
+}
+
+impl std::error::Error for SnippetError {}
+
+/// Check `payload` and wrap it for sending
+pub fn offer(transfer: TransferId, payload: Payload) -> Result<Message, SnippetError> {
+    check(&payload)?;
+    Ok(Snippet { transfer, payload }.into())
+}
+
+/// The receiver's answer to the snippet `transfer`: `None` if `reply` is
+/// about something else, otherwise whether it was taken
+pub fn outcome(transfer: TransferId, reply: &Message) -> Option<Result<(), CancelReason>> {
+    match reply {
+        Message::Ack(ack) if ack.transfer == transfer => Some(Ok(())),
+        Message::Cancel(cancel) if cancel.transfer == transfer => Some(Err(cancel.reason)),
+        _ => None,
+    }
+}
+
+fn check(payload: &Payload) -> Result<(), SnippetError> {
+    let (Payload::Text(s) | Payload::Url(s)) = payload;
+    if s.trim().is_empty() {
+        return Err(SnippetError::Empty);
+    }
+    if s.len() > MAX_SNIPPET_LEN {
+        return Err(SnippetError::TooLong { len: s.len(), max: MAX_SNIPPET_LEN });
+    }
+    if let Payload::Url(url) = payload {
+        let lower = url.to_ascii_lowercase();
+        let rest = lower.strip_prefix("https://").or_else(|| lower.strip_prefix("http://")).ok_or(SnippetError::UnsafeUrl)?;
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        if host.is_empty() || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(SnippetError::UnsafeUrl);
+        }
+    }
+    Ok(())
+}
+
+/// A snippet that passed the checks, waiting for the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedSnippet {
+    transfer: TransferId,
+    payload: Payload,
+}
+
+impl ReceivedSnippet {
+    pub fn receive(snippet: &Snippet) -> Result<Self, SnippetError> {
+        check(&snippet.payload)?;
+        Ok(Self { transfer: snippet.transfer, payload: snippet.payload.clone() })
+    }
+
+    pub fn transfer(&self) -> TransferId {
+        self.transfer
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    /// Offered to the user, the default first
+    pub fn actions(&self) -> &'static [SnippetAction] {
+        match self.payload {
+            Payload::Text(_) => &[SnippetAction::Copy],
+            Payload::Url(_) => &[SnippetAction::Open, SnippetAction::Copy],
+        }
+    }
+
+    /// Tell the sender the user took it
+    pub fn accept(&self) -> Message {
+        Ack { transfer: self.transfer, index: 0, offset: 0 }.into()
+    }
+
+    pub fn decline(&self) -> Message {
+        Cancel { transfer: self.transfer, reason: CancelReason::Declined }.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_and_text_are_checked_both_ways() {
+        let id = TransferId([5; 16]);
+        let Message::Snippet(link) = offer(id, Payload::Url("https://example.com/watch?v=1".into())).unwrap() else { panic!() };
+        let received = ReceivedSnippet::receive(&link).unwrap();
+        assert_eq!(received.actions(), [SnippetAction::Open, SnippetAction::Copy]);
+        assert_eq!(outcome(id, &received.accept()), Some(Ok(())));
+        assert_eq!(outcome(id, &received.decline()), Some(Err(CancelReason::Declined)));
+        assert_eq!(outcome(TransferId([6; 16]), &received.accept()), None);
+
+        let Message::Snippet(text) = offer(id, Payload::Text("door code 4711".into())).unwrap() else { panic!() };
+        assert_eq!(ReceivedSnippet::receive(&text).unwrap().actions(), [SnippetAction::Copy]);
+
+        for url in ["javascript:alert(1)", "file:///etc/passwd", "https://", "https://a b", "example.com"] {
+            assert_eq!(offer(id, Payload::Url(url.into())), Err(SnippetError::UnsafeUrl), "{url}");
+            // a sender that skips the check is caught on receipt
+            assert_eq!(ReceivedSnippet::receive(&Snippet { transfer: id, payload: Payload::Url(url.into()) }), Err(SnippetError::UnsafeUrl));
+        }
+        assert_eq!(offer(id, Payload::Text(" \n".into())), Err(SnippetError::Empty));
+        let long = "x".repeat(MAX_SNIPPET_LEN + 1);
+        assert_eq!(offer(id, Payload::Text(long)), Err(SnippetError::TooLong { len: MAX_SNIPPET_LEN + 1, max: MAX_SNIPPET_LEN }));
+    }
+}
@@ -0,0 +1,127 @@
+//! Transfer lifecycle
+//!
+//! ```text
+//! Offered -> Accepting -> Transferring <-> Verifying -> Done
+//!    \___________\______________\______________\______-> Cancelled | Failed
+//! ```
+//!
+//! Every state change goes through [`TransferState::transition`], so the
+//! legal edges live in one table and a session can never skip a step.
+
+use std::fmt;
+
+use globalsend_proto::CancelReason;
+
+/// Why a transfer ended without finishing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Received file `index` does not match its declared hash
+    HashMismatch(u32),
+    /// The peer sent something the protocol does not allow here
+    Protocol,
+    /// The peer gave up after a local failure of its own
+    Peer,
+    /// Disk, file or similar failure on this side
+    Local,
+    Timeout,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::HashMismatch(index) => write!(f, "file {index} failed verification"),
+            Failure::Protocol => write!(f, "peer violated the transfer protocol"),
+            Failure::Peer => write!(f, "peer failed"),
+            Failure::Local => write!(f, "local failure"),
+            Failure::Timeout => write!(f, "peer stopped responding"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferState {
+    /// Offer made, no answer yet
+    Offered,
+    /// Receiver said yes; waiting for the first file
+    Accepting,
+    /// A file's chunks are flowing
+    Transferring,
+    /// All of a file's bytes are through; waiting for its hash check
+    Verifying,
+    Done,
+    /// Declined or stopped by a user on either side
+    Cancelled(CancelReason),
+    Failed(Failure),
+}
+
+impl TransferState {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, TransferState::Done | TransferState::Cancelled(_) | TransferState::Failed(_))
+    }
+
+    /// `to` if the edge from `self` is legal
+    pub fn transition(self, to: TransferState) -> Result<TransferState, InvalidTransition> {
+        use TransferState::*;
+        let legal = match (self, to) {
+            (from, Cancelled(_) | Failed(_)) => !from.is_terminal(),
+            (Offered, Accepting) => true,
+            (Accepting | Verifying, Transferring) => true,
+            (Transferring, Verifying) => true,
+            (Verifying, Done) => true,
+            _ => false,
+        };
+        if legal {
+            Ok(to)
+        } else {
+            Err(InvalidTransition { from: self, to })
+        }
+    }
+}
+
+impl fmt::Display for TransferState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferState::Offered => write!(f, "offered"),
+            TransferState::Accepting => write!(f, "accepting"),
+            TransferState::Transferring => write!(f, "transferring"),
+            TransferState::Verifying => write!(f, "verifying"),
+            TransferState::Done => write!(f, "done"),
+            TransferState::Cancelled(CancelReason::Declined) => write!(f, "declined"),
+            TransferState::Cancelled(_) => write!(f, "cancelled"),
+            TransferState::Failed(failure) => write!(f, "failed: {failure}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: TransferState,
+    pub to: TransferState,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot go from {} to {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TransferState::*;
+
+    #[test]
+    fn only_forward_edges_are_legal() {
+        let path = [Offered, Accepting, Transferring, Verifying, Transferring, Verifying, Done];
+        let end = path[1..].iter().try_fold(Offered, |state, &next| state.transition(next)).unwrap();
+        assert_eq!(end, Done);
+
+        assert!(Offered.transition(Transferring).is_err());
+        assert!(Transferring.transition(Done).is_err());
+        assert!(Verifying.transition(Cancelled(CancelReason::User)).is_ok());
+        assert_eq!(Done.transition(Failed(Failure::Local)), Err(InvalidTransition { from: Done, to: Failed(Failure::Local) }));
+        assert!(Cancelled(CancelReason::Declined).transition(Accepting).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Deciding what a two-way folder sync moves
+//!
+//! Each side keeps, per file, the hash both sides last agreed on (its
+//! *synced* hash). A sync run goes one way: the side starting it sends its
+//! [`SyncIndex`](globalsend_proto::SyncIndex), gets the other's back and
+//! offers the files it changed since they last agreed ([`to_send`]); the
+//! other side runs its own when its files change, so both directions are
+//! covered.
+//!
+//! A file both sides changed is a conflict. The receiving side keeps its
+//! own copy under the name and puts the incoming one next to it under
+//! [`conflict_name`]; that copy is new to the sender and goes back on the
+//! next run, so both end up with both versions. Deletions are not synced.
+
+use std::collections::HashMap;
+
+use globalsend_proto::IndexedFile;
+
+use crate::validate::sanitize_name;
+
+/// What to do with a file that arrived in a sync run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Incoming {
+    /// Put it in place of the local file, if any
+    Take,
+    /// Both sides changed it: keep the local file, save this under [`conflict_name`]
+    Conflict,
+}
+
+/// Names of the files in `ours` the peer should get: the ones that differ
+/// from the peer's copy and changed here since the last agreed hash
+pub fn to_send(ours: &[IndexedFile], theirs: &[IndexedFile], synced: impl Fn(&str) -> Option<[u8; 32]>) -> Vec<String> {
+    let theirs: HashMap<&str, &[u8; 32]> = theirs.iter().map(|f| (f.name.as_str(), &f.hash)).collect();
+    ours.iter().filter(|f| theirs.get(f.name.as_str()) != Some(&&f.hash) && synced(&f.name) != Some(f.hash)).map(|f| f.name.clone()).collect()
+}
+
+/// Names both indexes hold with the same contents, and those contents
+pub fn agreed<'a>(ours: &'a [IndexedFile], theirs: &[IndexedFile]) -> Vec<(&'a str, [u8; 32])> {
+    let theirs: HashMap<&str, &[u8; 32]> = theirs.iter().map(|f| (f.name.as_str(), &f.hash)).collect();
+    ours.iter().filter(|f| theirs.get(f.name.as_str()) == Some(&&f.hash)).map(|f| (f.name.as_str(), f.hash)).collect()
+}
+
+/// How to take `incoming` given the local file's hash, if there is one,
+/// and the last hash both sides agreed on
+pub fn resolve(incoming: [u8; 32], local: Option<[u8; 32]>, synced: Option<[u8; 32]>) -> Incoming {
+    match local {
+        Some(local) if local != incoming && Some(local) != synced => Incoming::Conflict,
+        _ => Incoming::Take,
+    }
+}
+
+/// Name for a conflicting copy of `name` from `peer`: `notes/a (conflict
+/// from phone).txt`, or with ` 2`, ` 3`... after the peer while `taken`
+pub fn conflict_name(name: &str, peer: &str, taken: impl Fn(&str) -> bool) -> String {
+    let (dir, file) = match name.rsplit_once('/') {
+        Some((dir, file)) => (Some(dir), file),
+        None => (None, name),
+    };
+    let (stem, ext) = match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (file, None),
+    };
+    let peer = sanitize_name(peer);
+    (1u32..)
+        .map(|n| {
+            let suffix = if n == 1 { format!(" (conflict from {peer})") } else { format!(" (conflict from {peer} {n})") };
+            let file = match ext {
+                Some(ext) => format!("{stem}{suffix}.{ext}"),
+                None => format!("{stem}{suffix}"),
+            };
+            match dir {
+                Some(dir) => format!("{dir}/{file}"),
+                None => file,
+            }
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, hash: u8) -> IndexedFile {
+        IndexedFile { name: name.into(), size: 1, modified: 0, hash: [hash; 32] }
+    }
+
+    #[test]
+    fn sends_local_changes_and_flags_conflicts() {
+        let ours = [file("same", 1), file("new", 2), file("changed", 3), file("theirs changed", 4)];
+        let theirs = [file("same", 1), file("changed", 5), file("theirs changed", 6)];
+        let synced = |name: &str| match name {
+            "changed" => Some([9; 32]),
+            "theirs changed" => Some([4; 32]),
+            _ => None,
+        };
+        assert_eq!(to_send(&ours, &theirs, synced), ["new", "changed"]);
+        assert_eq!(agreed(&ours, &theirs), [("same", [1; 32])]);
+
+        assert_eq!(resolve([1; 32], None, None), Incoming::Take);
+        assert_eq!(resolve([1; 32], Some([2; 32]), Some([2; 32])), Incoming::Take);
+        assert_eq!(resolve([1; 32], Some([1; 32]), None), Incoming::Take);
+        assert_eq!(resolve([1; 32], Some([2; 32]), Some([3; 32])), Incoming::Conflict);
+        assert_eq!(resolve([1; 32], Some([2; 32]), None), Incoming::Conflict);
+
+        assert_eq!(conflict_name("notes/a.txt", "phone", |_| false), "notes/a (conflict from phone).txt");
+        assert_eq!(conflict_name(".bashrc", "a/b", |_| false), ".bashrc (conflict from a_b)");
+        assert_eq!(conflict_name("Makefile", "phone", |n| n == "Makefile (conflict from phone)"), "Makefile (conflict from phone 2)");
+    }
+}
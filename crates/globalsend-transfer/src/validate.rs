@@ -0,0 +1,193 @@
+//! Checks on what a sender claims
+//!
+//! Everything in an offer is attacker-controlled: names, declared types and
+//! the bytes themselves. [`sanitize_name`] turns an offered name into one
+//! that is safe to create on any platform, and [`check_content`] compares
+//! the first bytes of a received file with the type its name and MIME type
+//! claim, so `invoice.pdf` cannot turn out to be a Windows executable.
+//! Path traversal in folder offers is refused by
+//! [`Layout::plan`](crate::folder::Layout::plan), which also runs every
+//! path component through [`sanitize_name`].
+
+use std::fmt;
+
+use crate::policy::guess_mime;
+
+/// Longest file name most filesystems take, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+/// Bytes of a file [`sniff`] looks at
+pub const SNIFF_LEN: usize = 64;
+
+/// Characters Windows refuses in names; replaced everywhere so names survive a later copy
+const RESERVED_CHARS: &[char] = &['/', '\\', ':', '<', '>', '"', '|', '?', '*'];
+/// Device names Windows reserves whatever the extension
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6",
+    "lpt7", "lpt8", "lpt9",
+];
+/// Extensions whose content is expected to be a native executable
+const EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "dll", "msi", "scr", "com", "sys", "bin", "elf", "so", "dylib", "appimage", "run", "out", "o"];
+
+/// One path component that is safe to create: no separators, control or
+/// bidirectional override characters, reserved names or trailing dots,
+/// and at most [`MAX_NAME_LEN`] bytes. Never empty, `.` or `..`.
+pub fn sanitize_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .filter(|&c| !is_invisible(c))
+        .map(|c| if c.is_control() || RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    out = out.trim_start_matches(' ').trim_end_matches(['.', ' ']).to_string();
+    if out.is_empty() {
+        return "_".into();
+    }
+    let stem = out.split('.').next().unwrap_or_default().to_ascii_lowercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        out.insert(0, '_');
+    }
+    truncate(out)
+}
+
+/// Invisible formatting characters, which can make `exe.pdf` display as `fdp.exe`
+pub(crate) fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' | '\u{feff}')
+}
+
+/// Cut to [`MAX_NAME_LEN`] bytes on a character boundary, keeping a short extension
+fn truncate(name: String) -> String {
+    if name.len() <= MAX_NAME_LEN {
+        return name;
+    }
+    let ext = name.rsplit_once('.').map(|(_, ext)| ext).filter(|ext| ext.len() <= 16).unwrap_or_default();
+    let keep = if ext.is_empty() { MAX_NAME_LEN } else { MAX_NAME_LEN - ext.len() - 1 };
+    let mut end = keep;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    if ext.is_empty() {
+        name[..end].to_string()
+    } else {
+        format!("{}.{ext}", &name[..end])
+    }
+}
+
+/// MIME type of well-known formats, from their first bytes
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    Some(if starts(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if starts(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        "image/gif"
+    } else if starts(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else if starts(b"RIFF") && head.get(8..12) == Some(b"WAVE") {
+        "audio/wav"
+    } else if head.get(4..8) == Some(b"ftyp") {
+        match head.get(8..12) {
+            Some(b"heic" | b"heix" | b"mif1" | b"msf1") => "image/heic",
+            Some(b"avif") => "image/avif",
+            Some(b"M4A ") => "audio/mp4",
+            Some(b"qt  ") => "video/quicktime",
+            _ => "video/mp4",
+        }
+    } else if starts(b"\x1aE\xdf\xa3") {
+        "video/x-matroska"
+    } else if starts(b"ID3") || starts(b"\xff\xfb") {
+        "audio/mpeg"
+    } else if starts(b"fLaC") {
+        "audio/flac"
+    } else if starts(b"OggS") {
+        "audio/ogg"
+    } else if starts(b"%PDF-") {
+        "application/pdf"
+    } else if starts(b"PK\x03\x04") {
+        "application/zip"
+    } else if starts(b"\x1f\x8b") {
+        "application/gzip"
+    } else if starts(b"\x7fELF") {
+        "application/x-executable"
+    } else if starts(b"MZ") {
+        "application/x-msdownload"
+    } else if starts(b"\xcf\xfa\xed\xfe") || starts(b"\xce\xfa\xed\xfe") || starts(b"\xca\xfe\xba\xbe") {
+        "application/x-mach-binary"
+    } else {
+        return None;
+    })
+}
+
+/// Received content is not what the offer said it was
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMismatch {
+    /// Type the name or offer claimed; empty when only the executable check failed
+    pub declared: String,
+    pub actual: &'static str,
+}
+
+impl fmt::Display for ContentMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.declared.is_empty() {
+            write!(f, "file is a disguised {}", self.actual)
+        } else {
+            write!(f, "file claims to be {} but is {}", self.declared, self.actual)
+        }
+    }
+}
+
+impl std::error::Error for ContentMismatch {}
+
+/// Compare a received file's first bytes (at least [`SNIFF_LEN`] of them
+/// if it has that many) with its offered name and MIME type
+///
+/// Unknown formats pass: only contradictions are refused. Images, audio and
+/// video may be off within their family (a `.jpg` that is really a PNG),
+/// and a ZIP may carry any `application/*` type (office documents, jars).
+/// Native executables must say so in their extension.
+pub fn check_content(name: &str, declared: Option<&str>, head: &[u8]) -> Result<(), ContentMismatch> {
+    let Some(actual) = sniff(head) else {
+        return Ok(());
+    };
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    let executable = matches!(actual, "application/x-executable" | "application/x-msdownload" | "application/x-mach-binary");
+    if executable && !extension.as_deref().is_some_and(|ext| EXECUTABLE_EXTENSIONS.contains(&ext)) {
+        return Err(ContentMismatch { declared: declared.unwrap_or_default().to_string(), actual });
+    }
+    for claimed in [declared, guess_mime(name)].into_iter().flatten() {
+        let claimed = claimed.to_ascii_lowercase();
+        let family = |mime: &str| mime.split('/').next().unwrap_or_default().to_string();
+        let compatible = claimed == actual
+            || (matches!(family(actual).as_str(), "image" | "audio" | "video") && family(&claimed) == family(actual))
+            || (actual == "application/zip" && family(&claimed) == "application")
+            || claimed == "application/octet-stream";
+        if !compatible {
+            return Err(ContentMismatch { declared: claimed, actual });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_contents_are_checked() {
+        assert_eq!(sanitize_name("report\u{202e}fdp.exe"), "reportfdp.exe");
+        assert_eq!(sanitize_name("a/b\\c:d\n.txt"), "a_b_c_d_.txt");
+        assert_eq!(sanitize_name(".."), "_");
+        assert_eq!(sanitize_name("CON.txt"), "_CON.txt");
+        assert_eq!(sanitize_name("notes. . "), "notes");
+        let long = sanitize_name(&format!("{}.jpeg", "é".repeat(200)));
+        assert!(long.len() <= MAX_NAME_LEN && long.ends_with(".jpeg"));
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(check_content("photo.png", Some("image/png"), png), Ok(()));
+        assert_eq!(check_content("photo.jpg", None, png), Ok(()));
+        assert_eq!(check_content("notes.txt", None, b"hello"), Ok(()));
+        assert_eq!(check_content("report.docx", Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document"), b"PK\x03\x04"), Ok(()));
+        assert_eq!(check_content("invoice.pdf", None, png), Err(ContentMismatch { declared: "application/pdf".into(), actual: "image/png" }));
+        assert_eq!(check_content("invoice.pdf", None, b"MZ\x90\0"), Err(ContentMismatch { declared: String::new(), actual: "application/x-msdownload" }));
+        assert_eq!(check_content("setup.exe", None, b"MZ\x90\0"), Ok(()));
+    }
+}
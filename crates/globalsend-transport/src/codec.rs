@@ -0,0 +1,300 @@
+//! Sealed, length-prefixed message framing
+//!
+//! [`FrameCodec`] implements the `tokio_util` codec traits, so
+//! `Framed::new(stream, FrameCodec::new(keys))` gives a `Sink` and `Stream`
+//! of [`Message`]s over any byte stream. On the wire each message is
+//!
+//! ```text
+//! length (u32 BE) || session frame (message number || ciphertext)
+//! ```
+//!
+//! where the ciphertext seals a [`globalsend_proto`] frame. The length is
+//! checked against the codec's maximum before any buffer is grown, so a
+//! forged length costs the receiver nothing. A failed tag or a malformed
+//! message is fatal to the connection: the stream cannot resynchronise.
+//!
+//! The codec ratchets both directions itself. The stream is ordered, so the
+//! sender rekeys before the first message past an epoch's budget and the
+//! receiver does the same before opening it.
+//!
+//! With the `debug-transcript` feature a codec also logs what it seals and
+//! opens; see [`transcript`](crate::transcript).
+
+use std::fmt;
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use globalsend_crypto::aad::Aad;
+use globalsend_crypto::session::{SessionError, SessionKeys, MIN_FRAME_LEN};
+use globalsend_crypto::CryptoError;
+use globalsend_proto::{Message, ProtoError, PROTOCOL_VERSION};
+use tokio_util::codec::{Decoder, Encoder};
+
+#[cfg(feature = "debug-transcript")]
+use crate::transcript::{Direction, Transcript};
+
+pub const LENGTH_PREFIX_LEN: usize = 4;
+/// Default limit on one sealed frame, enough for a 1 MiB chunk plus headers
+pub const DEFAULT_MAX_FRAME_LEN: usize = (1 << 20) + 4096;
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    /// A length prefix above the codec's limit, read or about to be written
+    FrameTooLarge { len: usize, max: usize },
+    Session(SessionError),
+    Crypto(CryptoError),
+    Proto(ProtoError),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "i/o error: {e}"),
+            CodecError::FrameTooLarge { len, max } => write!(f, "frame of {len} bytes exceeds limit of {max}"),
+            CodecError::Session(e) => write!(f, "session frame rejected: {e}"),
+            CodecError::Crypto(e) => write!(f, "rekey failed: {e}"),
+            CodecError::Proto(e) => write!(f, "invalid message: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+impl From<SessionError> for CodecError {
+    fn from(e: SessionError) -> Self {
+        CodecError::Session(e)
+    }
+}
+
+impl From<CryptoError> for CodecError {
+    fn from(e: CryptoError) -> Self {
+        CodecError::Crypto(e)
+    }
+}
+
+impl From<ProtoError> for CodecError {
+    fn from(e: ProtoError) -> Self {
+        CodecError::Proto(e)
+    }
+}
+
+/// Seals outgoing and opens incoming [`Message`]s with one session's keys
+pub struct FrameCodec {
+    keys: SessionKeys,
+    version: u16,
+    max_frame_len: usize,
+    #[cfg(feature = "debug-transcript")]
+    transcript: Option<Transcript>,
+}
+
+impl FrameCodec {
+    /// Encode in [`PROTOCOL_VERSION`] with [`DEFAULT_MAX_FRAME_LEN`]
+    pub fn new(keys: SessionKeys) -> Self {
+        Self {
+            keys,
+            version: PROTOCOL_VERSION,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            #[cfg(feature = "debug-transcript")]
+            transcript: Transcript::start(),
+        }
+    }
+
+    /// Encode in the version agreed with [`negotiate`](globalsend_proto::negotiate)
+    pub fn with_version(mut self, version: u16) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Switch versions on an open channel: hellos go out in
+    /// [`MIN_SUPPORTED_VERSION`](globalsend_proto::MIN_SUPPORTED_VERSION),
+    /// everything after them in the negotiated one
+    pub fn set_version(&mut self, version: u16) {
+        self.version = version;
+    }
+
+    /// Refuse frames longer than `len` bytes, in both directions
+    pub fn with_max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len.min(u32::MAX as usize);
+        self
+    }
+
+    pub fn keys(&self) -> &SessionKeys {
+        &self.keys
+    }
+}
+
+impl Encoder<Message> for FrameCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let plaintext = globalsend_proto::encode(self.version, &message)?;
+        // check before sealing: a skipped message number would desync the receiver's rekey
+        let len = MIN_FRAME_LEN + plaintext.len();
+        if len > self.max_frame_len {
+            return Err(CodecError::FrameTooLarge { len, max: self.max_frame_len });
+        }
+        if self.keys.send.needs_rekey() {
+            self.keys.send.rekey()?;
+        }
+        let frame = self.keys.send.seal_frame(&Aad::default(), &plaintext)?;
+        dst.reserve(LENGTH_PREFIX_LEN + frame.len());
+        dst.put_u32(frame.len() as u32);
+        dst.put_slice(&frame);
+        #[cfg(feature = "debug-transcript")]
+        if let Some(transcript) = &mut self.transcript {
+            transcript.message(Direction::Send, self.version, &message);
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        let Some(prefix) = src.first_chunk::<LENGTH_PREFIX_LEN>() else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(*prefix) as usize;
+        if len > self.max_frame_len {
+            return Err(CodecError::FrameTooLarge { len, max: self.max_frame_len });
+        }
+        if src.len() < LENGTH_PREFIX_LEN + len {
+            src.reserve(LENGTH_PREFIX_LEN + len - src.len());
+            return Ok(None);
+        }
+        src.advance(LENGTH_PREFIX_LEN);
+        let frame = src.split_to(len);
+        if self.keys.recv.needs_rekey() {
+            self.keys.recv.rekey()?;
+        }
+        let plaintext = self.keys.recv.open_frame(&frame, &Aad::default())?;
+        let decoded = globalsend_proto::decode(&plaintext);
+        #[cfg(feature = "debug-transcript")]
+        if let Some(transcript) = &mut self.transcript {
+            match &decoded {
+                Ok((version, message)) => transcript.message(Direction::Receive, *version, message),
+                Err(e) => transcript.undecodable(&plaintext, e),
+            }
+        }
+        let (_, message) = decoded?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_crypto::{DeviceKey, EphemeralKey};
+    use globalsend_proto::{Ack, ChunkData, TransferId};
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    fn pair() -> (FrameCodec, FrameCodec) {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let (ea, eb) = (EphemeralKey::generate(), EphemeralKey::generate());
+        let (pa, pb) = (ea.public(), eb.public());
+        let ka = SessionKeys::derive(&a, ea, &b.public(), &pb).unwrap().with_rekey_threshold(3);
+        let kb = SessionKeys::derive(&b, eb, &a.public(), &pa).unwrap().with_rekey_threshold(3);
+        (FrameCodec::new(ka), FrameCodec::new(kb))
+    }
+
+    #[test]
+    fn frames_roundtrip_across_rekeys_and_enforce_limit() {
+        let (mut tx, mut rx) = pair();
+        let transfer = TransferId([1; 16]);
+        let messages: Vec<Message> =
+            (0..8).map(|i| ChunkData { transfer, index: 0, offset: i * 100, data: vec![i as u8; 100] }.into()).collect();
+
+        let mut wire = BytesMut::new();
+        for m in &messages {
+            tx.encode(m.clone(), &mut wire).unwrap();
+        }
+        assert!(tx.keys().send.epoch() > 0);
+
+        // delivered a byte at a time, as a slow socket might
+        let mut buf = BytesMut::new();
+        let mut got = Vec::new();
+        for b in wire.iter() {
+            buf.put_u8(*b);
+            if let Some(m) = rx.decode(&mut buf).unwrap() {
+                got.push(m);
+            }
+        }
+        assert_eq!(got, messages);
+        assert!(buf.is_empty());
+
+        // a forged length is refused before anything is buffered
+        let mut evil = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+        assert!(matches!(rx.decode(&mut evil), Err(CodecError::FrameTooLarge { len, .. }) if len == u32::MAX as usize));
+        let mut tx = tx.with_max_frame_len(64);
+        let big = ChunkData { transfer, index: 0, offset: 0, data: vec![0; 64] }.into();
+        assert!(matches!(tx.encode(big, &mut BytesMut::new()), Err(CodecError::FrameTooLarge { .. })));
+
+        // tampering is fatal
+        let mut wire = BytesMut::new();
+        tx.encode(Ack { transfer, index: 0, offset: 1 }.into(), &mut wire).unwrap();
+        let last = wire.len() - 1;
+        wire[last] ^= 1;
+        assert!(matches!(rx.decode(&mut wire), Err(CodecError::Session(SessionError::Decrypt))));
+    }
+
+    /// Limit for the size properties; small enough to run many cases
+    const MAX: usize = 4096;
+
+    fn chunk(len: usize) -> Message {
+        ChunkData { transfer: TransferId([7; 16]), index: 3, offset: 0, data: vec![0xa5; len] }.into()
+    }
+
+    /// Longest chunk whose frame is exactly within [`MAX`]
+    fn largest_chunk() -> usize {
+        let frame = |len| MIN_FRAME_LEN + globalsend_proto::encode(PROTOCOL_VERSION, &chunk(len)).unwrap().len();
+        (0..MAX).rev().find(|&len| frame(len) <= MAX).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn frames_of_any_size_roundtrip_up_to_the_limit(len in prop_oneof![Just(0), Just(largest_chunk()), Just(largest_chunk() + 1), 0..MAX]) {
+            let (tx, rx) = pair();
+            let (mut tx, mut rx) = (tx.with_max_frame_len(MAX), rx.with_max_frame_len(MAX));
+            let largest = largest_chunk();
+            let mut wire = BytesMut::new();
+            match tx.encode(chunk(len), &mut wire) {
+                Ok(()) => {
+                    prop_assert!(len <= largest);
+                    prop_assert!(wire.len() <= LENGTH_PREFIX_LEN + MAX);
+                    prop_assert_eq!(rx.decode(&mut wire).unwrap(), Some(chunk(len)));
+                    prop_assert!(wire.is_empty());
+                }
+                Err(CodecError::FrameTooLarge { .. }) => prop_assert!(len > largest),
+                Err(e) => prop_assert!(false, "{e}"),
+            }
+        }
+
+        #[test]
+        fn mutated_frames_never_decode(len in 0..512usize, at in any::<Index>(), flip in 1u8..) {
+            let (mut tx, mut rx) = pair();
+            let mut wire = BytesMut::new();
+            tx.encode(chunk(len), &mut wire).unwrap();
+            let i = at.index(wire.len());
+            wire[i] ^= flip;
+            // a changed length may just wait for more bytes, but never yields a message
+            prop_assert!(!matches!(rx.decode(&mut wire), Ok(Some(_))));
+        }
+    }
+}
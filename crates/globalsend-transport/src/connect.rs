@@ -0,0 +1,387 @@
+//! Picking a transport
+//!
+//! [`Dialer::connect`] tries QUIC first and falls back to [`tcp`](crate::tcp)
+//! when QUIC does not come up within [`DEFAULT_QUIC_TIMEOUT`], which is
+//! what a network that silently drops UDP looks like. [`TransportPreference`]
+//! pins one or the other. Either way the caller gets a [`Connection`] whose
+//! control channel has completed the Noise handshake.
+//!
+//! A [`Listener`] serves both on the same port number, UDP for QUIC and
+//! TCP for the fallback, so a peer only needs to advertise one address.
+//! [`Listener::accept`] only takes the connection; the handshake is
+//! [`Incoming::handshake`], bounded by [`DEFAULT_HANDSHAKE_TIMEOUT`], and
+//! belongs in a task of its own so a slow or silent peer cannot hold up
+//! the next one.
+//! When neither is reachable, [`Connection::relayed`] goes through a relay
+//! both peers agreed on, and [`Connection::wormhole`] through one found by
+//! a short code.
+//!
+//! Over QUIC, each file of a transfer can have a stream of its own next to
+//! the control channel; [`file_channel`] seals one with the file's own keys.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use globalsend_crypto::handshake::Role;
+use globalsend_crypto::keyprovider::KeyProvider;
+use globalsend_crypto::pairing::WormholeCode;
+use globalsend_crypto::session::SessionKeys;
+use globalsend_crypto::CryptoError;
+use globalsend_proto::Message;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, FramedParts};
+
+use crate::codec::FrameCodec;
+use crate::quic::{QuicConnection, QuicEndpoint, QuicError, QuicIncoming, QuicStream};
+use crate::ratelimit::{self, RateLimiter};
+use crate::relay::{self, RelayError, RelaySession};
+use crate::secure::{self, Peer, SecureError};
+use crate::tcp::{self, TcpChannel, TcpTransport};
+use crate::wormhole::{self, WormholeError};
+
+pub const DEFAULT_QUIC_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which transport [`Dialer::connect`] may use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportPreference {
+    /// QUIC, falling back to TCP
+    #[default]
+    Auto,
+    QuicOnly,
+    TcpOnly,
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    Io(io::Error),
+    Quic(QuicError),
+    Secure(SecureError),
+    Relay(RelayError),
+    Wormhole(WormholeError),
+    /// QUIC did not finish connecting in time
+    Timeout,
+    /// An accepted peer did not finish the handshake in time
+    HandshakeTimeout,
+    /// `Auto` tried both and neither worked
+    Unreachable { quic: Box<ConnectError>, tcp: Box<ConnectError> },
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Io(e) => write!(f, "i/o error: {e}"),
+            ConnectError::Quic(e) => write!(f, "quic: {e}"),
+            ConnectError::Secure(e) => write!(f, "{e}"),
+            ConnectError::Relay(e) => write!(f, "{e}"),
+            ConnectError::Wormhole(e) => write!(f, "{e}"),
+            ConnectError::Timeout => write!(f, "quic connection timed out"),
+            ConnectError::HandshakeTimeout => write!(f, "peer did not finish the handshake in time"),
+            ConnectError::Unreachable { quic, tcp } => write!(f, "peer unreachable (quic: {quic}; tcp: {tcp})"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<io::Error> for ConnectError {
+    fn from(e: io::Error) -> Self {
+        ConnectError::Io(e)
+    }
+}
+
+impl From<QuicError> for ConnectError {
+    fn from(e: QuicError) -> Self {
+        ConnectError::Quic(e)
+    }
+}
+
+impl From<SecureError> for ConnectError {
+    fn from(e: SecureError) -> Self {
+        ConnectError::Secure(e)
+    }
+}
+
+impl From<RelayError> for ConnectError {
+    fn from(e: RelayError) -> Self {
+        ConnectError::Relay(e)
+    }
+}
+
+impl From<WormholeError> for ConnectError {
+    fn from(e: WormholeError) -> Self {
+        ConnectError::Wormhole(e)
+    }
+}
+
+/// Any stream a control channel can run over
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// Sealed control messages, over a QUIC stream or a TCP connection
+pub type ControlChannel = Framed<Box<dyn Io>, FrameCodec>;
+
+/// An authenticated connection to a peer
+pub struct Connection {
+    pub control: ControlChannel,
+    pub peer: Peer,
+    /// Set for QUIC, for file streams next to `control`
+    pub quic: Option<QuicConnection>,
+    /// What [`throttle`](Self::throttle) held the connection to; give them to [`file_channel`]
+    pub limiters: Vec<RateLimiter>,
+}
+
+impl Connection {
+    /// Meet the peer at `relay` under `session`; see [`relay`](crate::relay)
+    pub async fn relayed(relay: SocketAddr, session: &RelaySession, role: Role, static_key: &dyn KeyProvider) -> Result<Self, ConnectError> {
+        Ok(Self::tcp(relay::connect(relay, session, role, static_key).await?))
+    }
+
+    /// Meet whoever holds `code` at `relay`; see [`wormhole`](crate::wormhole).
+    /// The initiator is the side that showed the code.
+    pub async fn wormhole(relay: SocketAddr, code: &WormholeCode, role: Role, static_key: &dyn KeyProvider) -> Result<Self, ConnectError> {
+        let channel = match role {
+            Role::Initiator => wormhole::send(relay, code, static_key).await?,
+            Role::Responder => wormhole::receive(relay, code, static_key).await?,
+        };
+        Ok(Self::tcp(channel))
+    }
+
+    /// Hold the control channel to `limiters`, and file streams opened
+    /// with [`limiters`](Self::limiters) from then on
+    pub fn throttle(mut self, limiters: &[RateLimiter]) -> Self {
+        self.limiters.extend_from_slice(limiters);
+        Self { control: boxed(ratelimit::throttle(self.control, limiters)), ..self }
+    }
+
+    fn tcp((channel, peer): (TcpChannel, Peer)) -> Self {
+        Self { control: boxed(channel), peer, quic: None, limiters: Vec::new() }
+    }
+}
+
+/// File `index`'s stream from [`QuicConnection::open_file`] or
+/// [`QuicConnection::accept_file`], sealed with the file's own keys (see
+/// [`SessionKeys::for_file`]), encoded in protocol `version` and held to
+/// the connection's `limiters`
+pub fn file_channel(stream: QuicStream, index: u32, keys: &SessionKeys, version: u16, limiters: &[RateLimiter]) -> Result<ControlChannel, CryptoError> {
+    let codec = FrameCodec::new(keys.for_file(u64::from(index))?).with_version(version);
+    Ok(match limiters {
+        [] => boxed(Framed::new(stream.into_io(), codec)),
+        _ => boxed(Framed::new(stream.throttled(limiters), codec)),
+    })
+}
+
+fn boxed<T: Io + 'static>(channel: Framed<T, FrameCodec>) -> ControlChannel {
+    let parts = channel.into_parts();
+    let mut boxed = FramedParts::new::<Message>(Box::new(parts.io) as Box<dyn Io>, parts.codec);
+    boxed.read_buf = parts.read_buf;
+    boxed.write_buf = parts.write_buf;
+    Framed::from_parts(boxed)
+}
+
+/// Outgoing connections with a transport preference
+pub struct Dialer<'a> {
+    static_key: &'a dyn KeyProvider,
+    quic: &'a QuicEndpoint,
+    preference: TransportPreference,
+    quic_timeout: Duration,
+}
+
+impl<'a> Dialer<'a> {
+    pub fn new(static_key: &'a dyn KeyProvider, quic: &'a QuicEndpoint) -> Self {
+        Self { static_key, quic, preference: TransportPreference::Auto, quic_timeout: DEFAULT_QUIC_TIMEOUT }
+    }
+
+    pub fn preference(mut self, preference: TransportPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    /// How long `Auto` waits for QUIC before trying TCP
+    pub fn quic_timeout(mut self, timeout: Duration) -> Self {
+        self.quic_timeout = timeout;
+        self
+    }
+
+    /// Connect to `addr` (UDP port for QUIC, same TCP port for the fallback)
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn connect(&self, addr: SocketAddr) -> Result<Connection, ConnectError> {
+        match self.preference {
+            TransportPreference::QuicOnly => self.connect_quic(addr).await,
+            TransportPreference::TcpOnly => Ok(Connection::tcp(tcp::connect(addr, self.static_key).await?)),
+            TransportPreference::Auto => match self.connect_quic(addr).await {
+                Ok(conn) => Ok(conn),
+                Err(quic) => match tcp::connect(addr, self.static_key).await {
+                    Ok(channel) => {
+                        tracing::debug!(error = %quic, "QUIC failed, connected over TCP");
+                        Ok(Connection::tcp(channel))
+                    }
+                    Err(tcp) => Err(ConnectError::Unreachable { quic: Box::new(quic), tcp: Box::new(tcp.into()) }),
+                },
+            },
+        }
+    }
+
+    async fn connect_quic(&self, addr: SocketAddr) -> Result<Connection, ConnectError> {
+        let attempt = async {
+            let (conn, stream) = self.quic.connect(addr).await?;
+            let (control, peer) = secure::initiate(Box::new(stream.into_io()) as Box<dyn Io>, self.static_key).await?;
+            Ok::<_, ConnectError>(Connection { control, peer, quic: Some(conn), limiters: Vec::new() })
+        };
+        tokio::time::timeout(self.quic_timeout, attempt).await.map_err(|_| ConnectError::Timeout)?
+    }
+}
+
+/// QUIC and TCP listeners on the same port number
+pub struct Listener {
+    quic: QuicEndpoint,
+    tcp: TcpTransport,
+}
+
+impl Listener {
+    /// Bind QUIC on `addr`, then TCP on the port QUIC got
+    pub async fn bind(addr: SocketAddr) -> Result<Self, ConnectError> {
+        let quic = QuicEndpoint::bind(addr)?;
+        let tcp = TcpTransport::bind(quic.local_addr()?).await?;
+        Ok(Self { quic, tcp })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ConnectError> {
+        Ok(self.quic.local_addr()?)
+    }
+
+    /// The QUIC endpoint, also usable for dialing out
+    pub fn quic(&self) -> &QuicEndpoint {
+        &self.quic
+    }
+
+    /// Next connection over either transport, not yet authenticated
+    pub async fn accept(&self) -> Result<Incoming, ConnectError> {
+        tokio::select! {
+            // a closed QUIC endpoint disables this branch, leaving TCP
+            Some(incoming) = self.quic.accept() => Ok(Incoming::Quic(Box::new(incoming))),
+            stream = self.tcp.accept() => Ok(Incoming::Tcp(stream?)),
+        }
+    }
+}
+
+/// A connection from [`Listener::accept`] waiting for its handshake
+pub enum Incoming {
+    Quic(Box<QuicIncoming>),
+    Tcp(TcpStream),
+}
+
+impl Incoming {
+    /// Run the handshake as responder, giving up after [`DEFAULT_HANDSHAKE_TIMEOUT`]
+    pub async fn handshake(self, static_key: &dyn KeyProvider) -> Result<Connection, ConnectError> {
+        self.handshake_within(DEFAULT_HANDSHAKE_TIMEOUT, static_key).await
+    }
+
+    /// [`handshake`](Self::handshake), giving up after `timeout`
+    pub async fn handshake_within(self, timeout: Duration, static_key: &dyn KeyProvider) -> Result<Connection, ConnectError> {
+        let attempt = async {
+            match self {
+                Incoming::Quic(incoming) => {
+                    let (conn, stream) = incoming.control().await?;
+                    let (control, peer) = secure::respond(Box::new(stream.into_io()) as Box<dyn Io>, static_key).await?;
+                    Ok(Connection { control, peer, quic: Some(conn), limiters: Vec::new() })
+                }
+                Incoming::Tcp(stream) => Ok(Connection::tcp(tcp::respond(stream, static_key).await?)),
+            }
+        };
+        tokio::time::timeout(timeout, attempt).await.map_err(|_| ConnectError::HandshakeTimeout)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use globalsend_crypto::DeviceKey;
+    use globalsend_proto::{Ack, TransferId};
+
+    use crate::CodecError;
+
+    async fn roundtrip(preference: TransportPreference, listener_quic: bool) -> bool {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let listener = Listener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        if !listener_quic {
+            // as if UDP were filtered: nothing answers QUIC
+            listener.quic.close();
+        }
+        let server = async {
+            let mut conn = listener.accept().await.unwrap().handshake(&b).await.unwrap();
+            let m = conn.control.next().await.unwrap().unwrap();
+            conn.control.send(m).await.unwrap();
+            conn
+        };
+        let endpoint = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let dialer = Dialer::new(&a, &endpoint).preference(preference).quic_timeout(Duration::from_millis(300));
+        let client = async {
+            let mut conn = dialer.connect(addr).await.unwrap();
+            let ack: Message = Ack { transfer: TransferId([3; 16]), index: 0, offset: 0 }.into();
+            conn.control.send(ack.clone()).await.unwrap();
+            assert_eq!(conn.control.next().await.unwrap().unwrap(), ack);
+            assert_eq!(conn.peer.static_key, b.public());
+            conn
+        };
+        let (server, client) = tokio::join!(server, client);
+        assert_eq!(server.peer.static_key, a.public());
+        client.quic.is_some()
+    }
+
+    #[tokio::test]
+    async fn prefers_quic_and_falls_back_to_tcp() {
+        assert!(roundtrip(TransportPreference::Auto, true).await);
+        assert!(!roundtrip(TransportPreference::TcpOnly, true).await);
+        assert!(!roundtrip(TransportPreference::Auto, false).await);
+    }
+
+    #[tokio::test]
+    async fn file_streams_seal_with_their_own_keys() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let listener = Listener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let endpoint = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let dialer = Dialer::new(&a, &endpoint).preference(TransportPreference::QuicOnly);
+        let (server, client) = tokio::join!(async { listener.accept().await.unwrap().handshake(&b).await.unwrap() }, dialer.connect(addr));
+        let client = client.unwrap();
+        let version = globalsend_proto::PROTOCOL_VERSION;
+
+        let ack: Message = Ack { transfer: TransferId([3; 16]), index: 1, offset: 0 }.into();
+        let mut sending = file_channel(client.quic.as_ref().unwrap().open_file(1).await.unwrap(), 1, client.control.codec().keys(), version, &[]).unwrap();
+        sending.send(ack.clone()).await.unwrap();
+        let (index, stream) = server.quic.as_ref().unwrap().accept_file().await.unwrap();
+        let mut receiving = file_channel(stream, index, server.control.codec().keys(), version, &[]).unwrap();
+        assert_eq!((index, receiving.next().await.unwrap().unwrap()), (1, ack.clone()));
+
+        // what is sealed for file 2 does not open as file 1
+        let mut other = file_channel(client.quic.as_ref().unwrap().open_file(2).await.unwrap(), 2, client.control.codec().keys(), version, &[]).unwrap();
+        other.send(ack).await.unwrap();
+        let (_, stream) = server.quic.as_ref().unwrap().accept_file().await.unwrap();
+        let mut misread = file_channel(stream, 1, server.control.codec().keys(), version, &[]).unwrap();
+        assert!(matches!(misread.next().await, Some(Err(CodecError::Session(_)))));
+    }
+
+    #[tokio::test]
+    async fn a_silent_peer_does_not_hold_up_the_next() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let listener = Listener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // connects over TCP and never says a word
+        let _silent = TcpStream::connect(addr).await.unwrap();
+        let stalled = listener.accept().await.unwrap();
+        let endpoint = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let dialer = Dialer::new(&a, &endpoint).preference(TransportPreference::QuicOnly);
+        let (server, client) = tokio::join!(async { listener.accept().await.unwrap().handshake(&b).await.unwrap() }, dialer.connect(addr));
+        assert_eq!(server.peer.static_key, a.public());
+        assert!(client.is_ok());
+        let timeout = stalled.handshake_within(Duration::from_millis(100), &b).await;
+        assert!(matches!(timeout, Err(ConnectError::HandshakeTimeout)));
+    }
+}
@@ -0,0 +1,46 @@
+//! Transports for globalsend sessions
+//!
+//! Carries [`globalsend_proto`] messages between two devices once
+//! [`globalsend_crypto`] has agreed on session keys. [`codec`] turns a byte
+//! stream into a stream of sealed, length-prefixed messages; the
+//! connection-level transports build on it:
+//!
+//! - [`quic`] (feature `quic`, default): one QUIC stream per file plus a
+//!   control stream
+//! - [`tcp`]: everything over one TCP connection, for networks without UDP
+//! - [`relay`]: the same over a `globalsend-relay` server, when no direct
+//!   path exists
+//! - [`webrtc`] (feature `webrtc`): a data channel to a browser, with SDP
+//!   exchanged out of band
+//! - [`wormhole`]: relayed TCP reached by a short typed code, authenticated
+//!   by SPAKE2 instead of known keys
+//!
+//! [`secure`] runs the Noise handshake that authenticates either one, and
+//! [`connect`] picks between them. [`ratelimit`] caps upload and download
+//! rates across all of them. [`nat`] punches a UDP path between peers
+//! on different networks for QUIC to run over. [`transcript`] (feature
+//! `debug-transcript`) logs the shape of every session's messages.
+
+pub mod codec;
+#[cfg(feature = "quic")]
+pub mod connect;
+pub mod nat;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod ratelimit;
+pub mod relay;
+pub mod secure;
+pub mod tcp;
+#[cfg(feature = "debug-transcript")]
+pub mod transcript;
+#[cfg(feature = "webrtc")]
+pub mod webrtc;
+pub mod wormhole;
+
+pub use crate::codec::{CodecError, FrameCodec};
+pub use crate::ratelimit::{RateLimit, RateLimiter, Throttled};
+#[cfg(feature = "quic")]
+pub use crate::connect::{Connection, Dialer, Incoming, Listener, TransportPreference};
+pub use crate::secure::Peer;
+#[cfg(feature = "webrtc")]
+pub use crate::webrtc::{RtcChannel, RtcPeer};
@@ -0,0 +1,329 @@
+//! NAT traversal for peers on different networks
+//!
+//! Each side gathers candidates for one UDP socket: its host address and
+//! the reflexive address [`stun`] servers see. The two [`CandidateOffer`]s
+//! cross over whatever [`Signaling`] channel the peers share (the rendezvous
+//! server), and then both ends punch at once: every [`PROBE_INTERVAL`] they
+//! send a probe to every candidate of the other, which opens a mapping in
+//! their own NAT for the reply. The first probe that gets through is
+//! answered with an ack, and both sides settle on the address it came from.
+//!
+//! ```text
+//! probe / ack = "GSHP" || kind u8 || receiver's nonce (16 bytes)
+//! ```
+//!
+//! The nonce only arrives through signaling, so a stray packet cannot
+//! complete the punch; the Noise handshake that follows authenticates the
+//! peer. On [`NatError::Timeout`] (symmetric NATs on both ends, UDP blocked)
+//! callers fall back to the relay.
+
+pub mod stun;
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use rand::RngCore;
+use tokio::net::UdpSocket;
+use tokio::time;
+
+/// Public STUN servers tried when the config names none
+pub const DEFAULT_STUN_SERVERS: &[&str] = &["stun.cloudflare.com:3478", "stun.l.google.com:19302"];
+pub const DEFAULT_STUN_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+pub const NONCE_LEN: usize = 16;
+
+const MAGIC: &[u8; 4] = b"GSHP";
+const KIND_PROBE: u8 = 1;
+const KIND_ACK: u8 = 2;
+const PACKET_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+/// Acks sent in reply to a probe, in case some are lost
+const ACK_REPEAT: usize = 3;
+
+#[derive(Debug)]
+pub enum NatError {
+    Io(io::Error),
+    /// No STUN server answered
+    StunTimeout,
+    /// The other side's offer did not decode
+    Malformed,
+    /// The signaling channel failed or closed
+    Signaling(String),
+    /// The other side offered no candidates
+    NoCandidates,
+    /// No probe got through in time
+    Timeout,
+}
+
+impl fmt::Display for NatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NatError::Io(e) => write!(f, "i/o error: {e}"),
+            NatError::StunTimeout => write!(f, "no stun server answered"),
+            NatError::Malformed => write!(f, "malformed candidate offer"),
+            NatError::Signaling(e) => write!(f, "signaling: {e}"),
+            NatError::NoCandidates => write!(f, "peer offered no candidates"),
+            NatError::Timeout => write!(f, "hole punching timed out"),
+        }
+    }
+}
+
+impl std::error::Error for NatError {}
+
+impl From<io::Error> for NatError {
+    fn from(e: io::Error) -> Self {
+        NatError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateKind {
+    /// Address of a local interface
+    Host = 1,
+    /// Address a STUN server saw us at
+    Reflexive = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub kind: CandidateKind,
+    pub addr: SocketAddr,
+}
+
+/// What one side sends the other before punching
+///
+/// ```text
+/// nonce (16) || count u8 || (kind u8 || family u8 (4, 6) || ip || port u16 BE)*
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateOffer {
+    pub nonce: [u8; NONCE_LEN],
+    pub candidates: Vec<Candidate>,
+}
+
+impl CandidateOffer {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.nonce.to_vec();
+        out.push(self.candidates.len().min(u8::MAX as usize) as u8);
+        for c in self.candidates.iter().take(u8::MAX as usize) {
+            out.push(c.kind as u8);
+            match c.addr.ip() {
+                IpAddr::V4(ip) => {
+                    out.push(4);
+                    out.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    out.push(6);
+                    out.extend_from_slice(&ip.octets());
+                }
+            }
+            out.extend_from_slice(&c.addr.port().to_be_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NatError> {
+        let (nonce, rest) = bytes.split_at_checked(NONCE_LEN).ok_or(NatError::Malformed)?;
+        let (&count, mut rest) = rest.split_first().ok_or(NatError::Malformed)?;
+        let mut candidates = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let [kind, family, tail @ ..] = rest else { return Err(NatError::Malformed) };
+            let kind = match kind {
+                1 => CandidateKind::Host,
+                2 => CandidateKind::Reflexive,
+                _ => return Err(NatError::Malformed),
+            };
+            let ip_len = match family {
+                4 => 4,
+                6 => 16,
+                _ => return Err(NatError::Malformed),
+            };
+            let (ip, tail) = tail.split_at_checked(ip_len).ok_or(NatError::Malformed)?;
+            let (port, tail) = tail.split_at_checked(2).ok_or(NatError::Malformed)?;
+            let ip = match <[u8; 4]>::try_from(ip) {
+                Ok(v4) => IpAddr::V4(Ipv4Addr::from(v4)),
+                Err(_) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).map_err(|_| NatError::Malformed)?)),
+            };
+            candidates.push(Candidate { kind, addr: SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])) });
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(NatError::Malformed);
+        }
+        Ok(Self { nonce: nonce.try_into().map_err(|_| NatError::Malformed)?, candidates })
+    }
+}
+
+/// Out-of-band channel to the other peer, used once in each direction
+pub trait Signaling {
+    fn send(&mut self, offer: &CandidateOffer) -> impl Future<Output = Result<(), NatError>> + Send;
+    fn recv(&mut self) -> impl Future<Output = Result<CandidateOffer, NatError>> + Send;
+}
+
+/// A UDP socket with a working path to the peer at `remote`
+#[derive(Debug)]
+pub struct Punched {
+    pub socket: UdpSocket,
+    pub remote: SocketAddr,
+}
+
+impl Punched {
+    /// The socket for [`QuicEndpoint::from_socket`](crate::quic::QuicEndpoint::from_socket)
+    pub fn into_std(self) -> io::Result<std::net::UdpSocket> {
+        self.socket.into_std()
+    }
+}
+
+/// Resolve `host:port` STUN server names, skipping those that fail
+pub async fn resolve_stun_servers(names: &[&str]) -> Vec<SocketAddr> {
+    let mut out = Vec::new();
+    for name in names {
+        if let Ok(addrs) = tokio::net::lookup_host(name).await {
+            out.extend(addrs.filter(|a| a.is_ipv4()).take(1));
+        }
+    }
+    out
+}
+
+/// Host and reflexive candidates for `socket`
+pub async fn gather(socket: &UdpSocket, stun_servers: &[SocketAddr], timeout: Duration) -> io::Result<Vec<Candidate>> {
+    let local = socket.local_addr()?;
+    let mut candidates = Vec::new();
+    if let Some(ip) = if local.ip().is_unspecified() { default_route_ip() } else { Some(local.ip()) } {
+        candidates.push(Candidate { kind: CandidateKind::Host, addr: SocketAddr::new(ip, local.port()) });
+    }
+    for server in stun_servers {
+        if let Ok(addr) = stun::reflexive_address(socket, *server, timeout).await {
+            if !candidates.iter().any(|c| c.addr == addr) {
+                candidates.push(Candidate { kind: CandidateKind::Reflexive, addr });
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Gather, swap offers over `signaling` and punch
+pub async fn traverse<S: Signaling>(
+    socket: UdpSocket,
+    signaling: &mut S,
+    stun_servers: &[SocketAddr],
+    timeout: Duration,
+) -> Result<Punched, NatError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ours = CandidateOffer { nonce, candidates: gather(&socket, stun_servers, DEFAULT_STUN_TIMEOUT).await? };
+    signaling.send(&ours).await?;
+    let theirs = signaling.recv().await?;
+    punch(socket, &nonce, &theirs, timeout).await
+}
+
+/// Probe `remote`'s candidates until one answers
+pub async fn punch(socket: UdpSocket, nonce: &[u8; NONCE_LEN], remote: &CandidateOffer, timeout: Duration) -> Result<Punched, NatError> {
+    if remote.candidates.is_empty() {
+        return Err(NatError::NoCandidates);
+    }
+    let probe = packet(KIND_PROBE, &remote.nonce);
+    let mut ticker = time::interval(PROBE_INTERVAL);
+    let deadline = time::sleep(timeout);
+    tokio::pin!(deadline);
+    let mut buf = [0u8; PACKET_LEN];
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Err(NatError::Timeout),
+            _ = ticker.tick() => {
+                for candidate in &remote.candidates {
+                    // candidates of the other address family just fail
+                    let _ = socket.send_to(&probe, candidate.addr).await;
+                }
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = received?;
+                match parse(&buf[..len]) {
+                    Some((KIND_PROBE, n)) if n == *nonce => {
+                        let ack = packet(KIND_ACK, &remote.nonce);
+                        for _ in 0..ACK_REPEAT {
+                            socket.send_to(&ack, from).await?;
+                        }
+                        return Ok(Punched { socket, remote: from });
+                    }
+                    Some((KIND_ACK, n)) if n == *nonce => return Ok(Punched { socket, remote: from }),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn packet(kind: u8, nonce: &[u8; NONCE_LEN]) -> [u8; PACKET_LEN] {
+    let mut out = [0u8; PACKET_LEN];
+    out[..4].copy_from_slice(MAGIC);
+    out[4] = kind;
+    out[5..].copy_from_slice(nonce);
+    out
+}
+
+fn parse(packet: &[u8]) -> Option<(u8, [u8; NONCE_LEN])> {
+    if packet.len() != PACKET_LEN || &packet[..4] != MAGIC {
+        return None;
+    }
+    Some((packet[4], packet[5..].try_into().ok()?))
+}
+
+/// Source address the OS would use for outside traffic; sends nothing
+fn default_route_ip() -> Option<IpAddr> {
+    let probe = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    // TEST-NET-1, never routed
+    probe.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    probe.local_addr().ok().map(|a| a.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    struct Channel(mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>);
+
+    impl Signaling for Channel {
+        async fn send(&mut self, offer: &CandidateOffer) -> Result<(), NatError> {
+            self.0.send(offer.to_bytes()).await.map_err(|e| NatError::Signaling(e.to_string()))
+        }
+
+        async fn recv(&mut self) -> Result<CandidateOffer, NatError> {
+            CandidateOffer::from_bytes(&self.1.recv().await.ok_or(NatError::Signaling("closed".into()))?)
+        }
+    }
+
+    #[tokio::test]
+    async fn punches_through_with_stun_candidates() {
+        let stun = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let stun_addr = stun.local_addr().unwrap();
+        let ((tx_a, rx_a), (tx_b, rx_b)) = (mpsc::channel(1), mpsc::channel(1));
+        let (mut sig_a, mut sig_b) = (Channel(tx_a, rx_b), Channel(tx_b, rx_a));
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (addr_a, addr_b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let servers = [stun_addr];
+
+        let (pa, pb, ()) = tokio::join!(
+            traverse(a, &mut sig_a, &servers, DEFAULT_PUNCH_TIMEOUT),
+            traverse(b, &mut sig_b, &[], DEFAULT_PUNCH_TIMEOUT),
+            stun::tests::serve_once(&stun),
+        );
+        assert_eq!(pa.unwrap().remote, addr_b);
+        assert_eq!(pb.unwrap().remote, addr_a);
+
+        let offer = CandidateOffer {
+            nonce: [7; NONCE_LEN],
+            candidates: vec![
+                Candidate { kind: CandidateKind::Host, addr: addr_a },
+                Candidate { kind: CandidateKind::Reflexive, addr: "[2001:db8::1]:4000".parse().unwrap() },
+            ],
+        };
+        assert_eq!(CandidateOffer::from_bytes(&offer.to_bytes()).unwrap(), offer);
+        assert!(CandidateOffer::from_bytes(&offer.to_bytes()[..30]).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Just enough STUN (RFC 8489) to learn our reflexive address
+//!
+//! Sends a Binding request without attributes and reads
+//! `XOR-MAPPED-ADDRESS` (or the legacy `MAPPED-ADDRESS`) from the success
+//! response. No authentication, no fingerprint attribute.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use rand::RngCore;
+use tokio::net::UdpSocket;
+use tokio::time;
+
+use super::NatError;
+
+pub const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+/// Retransmissions of a request before giving up on a server
+const ATTEMPTS: u32 = 3;
+
+pub(crate) type TransactionId = [u8; 12];
+
+pub(crate) fn binding_request(transaction: &TransactionId) -> [u8; HEADER_LEN] {
+    let mut out = [0u8; HEADER_LEN];
+    out[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    out[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    out[8..].copy_from_slice(transaction);
+    out
+}
+
+/// Mapped address from a Binding success response to `transaction`
+pub(crate) fn parse_response(packet: &[u8], transaction: &TransactionId) -> Option<SocketAddr> {
+    if packet.len() < HEADER_LEN
+        || u16::from_be_bytes([packet[0], packet[1]]) != BINDING_SUCCESS
+        || packet[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || packet[8..HEADER_LEN] != transaction[..]
+    {
+        return None;
+    }
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    let mut attrs = packet.get(HEADER_LEN..HEADER_LEN + len)?;
+    let mut mapped = None;
+    while attrs.len() >= 4 {
+        let kind = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let len = u16::from_be_bytes([attrs[2], attrs[3]]) as usize;
+        let value = attrs.get(4..4 + len)?;
+        match kind {
+            ATTR_XOR_MAPPED_ADDRESS => return address(value, Some(transaction)),
+            ATTR_MAPPED_ADDRESS => mapped = address(value, None),
+            _ => {}
+        }
+        // attributes are padded to four bytes
+        attrs = attrs.get((4 + len).next_multiple_of(4)..).unwrap_or_default();
+    }
+    mapped
+}
+
+/// Decode an address attribute, un-XORing it when `xor` gives the transaction
+fn address(value: &[u8], xor: Option<&TransactionId>) -> Option<SocketAddr> {
+    let (family, port) = (*value.get(1)?, u16::from_be_bytes([*value.get(2)?, *value.get(3)?]));
+    let mut mask = [0u8; 16];
+    if let Some(transaction) = xor {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction);
+    }
+    let port = port ^ u16::from_be_bytes([mask[0], mask[1]]);
+    let ip = match family {
+        0x01 => {
+            let raw: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            IpAddr::V4(Ipv4Addr::from(std::array::from_fn::<u8, 4, _>(|i| raw[i] ^ mask[i])))
+        }
+        0x02 => {
+            let raw: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            IpAddr::V6(Ipv6Addr::from(std::array::from_fn::<u8, 16, _>(|i| raw[i] ^ mask[i])))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Ask `server` how it sees `socket`, retrying over `timeout`
+pub async fn reflexive_address(socket: &UdpSocket, server: SocketAddr, timeout: Duration) -> Result<SocketAddr, NatError> {
+    let mut transaction = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut transaction);
+    let request = binding_request(&transaction);
+    let mut buf = [0u8; 512];
+    for _ in 0..ATTEMPTS {
+        socket.send_to(&request, server).await?;
+        let wait = async {
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                if from == server {
+                    if let Some(addr) = parse_response(&buf[..len], &transaction) {
+                        return Ok::<_, NatError>(addr);
+                    }
+                }
+            }
+        };
+        if let Ok(result) = time::timeout(timeout / ATTEMPTS, wait).await {
+            return result;
+        }
+    }
+    Err(NatError::StunTimeout)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Minimal STUN server answering with XOR-MAPPED-ADDRESS
+    pub(crate) async fn serve_once(socket: &UdpSocket) {
+        let mut buf = [0u8; 512];
+        let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(len, HEADER_LEN);
+        let transaction: TransactionId = buf[8..20].try_into().unwrap();
+        let SocketAddr::V4(from_v4) = from else { panic!("v4 only") };
+        let mut response = binding_request(&transaction).to_vec();
+        response[0..2].copy_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        response[2..4].copy_from_slice(&12u16.to_be_bytes());
+        response.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0, 0x01]);
+        response.extend_from_slice(&(from.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+        response.extend_from_slice(&(u32::from(*from_v4.ip()) ^ MAGIC_COOKIE).to_be_bytes());
+        socket.send_to(&response, from).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn learns_mapped_address() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let (mapped, ()) = tokio::join!(reflexive_address(&client, server_addr, Duration::from_secs(3)), serve_once(&server));
+        assert_eq!(mapped.unwrap(), client.local_addr().unwrap());
+    }
+}
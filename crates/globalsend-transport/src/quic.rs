@@ -0,0 +1,350 @@
+//! QUIC backend
+//!
+//! One QUIC connection per peer. The connecting side opens a control stream
+//! right away; from protocol version 15 every file of a batch then gets its
+//! own bidirectional stream for its chunks, so a stalled file does not hold
+//! up the others or the control messages, and QUIC handles congestion
+//! control and connection migration when a laptop hops between Wi-Fi
+//! networks.
+//!
+//! Each stream starts with a header naming what it carries:
+//!
+//! ```text
+//! control: 0x00
+//! file:    0x01 || file index (u32 BE)
+//! ```
+//!
+//! TLS here only provides QUIC's packet protection. Certificates are
+//! throwaway self-signed ones and are not checked: peers authenticate each
+//! other with the session handshake run over the control stream, and
+//! everything sent is sealed with the resulting session keys (wrap the
+//! streams with [`FrameCodec`](crate::FrameCodec), file streams with
+//! [`connect::file_channel`](crate::connect::file_channel)).
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig, TransportConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use tokio::io::{AsyncReadExt, Join};
+
+use crate::ratelimit::{RateLimiter, Throttled};
+
+pub const ALPN: &[u8] = b"globalsend/1";
+/// SNI sent by clients; certificates are not checked against it
+const SERVER_NAME: &str = "globalsend";
+const KIND_CONTROL: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum QuicError {
+    Io(io::Error),
+    /// Certificate generation or TLS configuration failed
+    Tls(String),
+    Connect(quinn::ConnectError),
+    Connection(quinn::ConnectionError),
+    Write(quinn::WriteError),
+    Read(quinn::ReadExactError),
+    /// The peer opened a stream with an unknown or unexpected header
+    UnexpectedStream,
+}
+
+impl fmt::Display for QuicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuicError::Io(e) => write!(f, "i/o error: {e}"),
+            QuicError::Tls(e) => write!(f, "tls setup failed: {e}"),
+            QuicError::Connect(e) => write!(f, "cannot connect: {e}"),
+            QuicError::Connection(e) => write!(f, "connection lost: {e}"),
+            QuicError::Write(e) => write!(f, "stream write failed: {e}"),
+            QuicError::Read(e) => write!(f, "stream read failed: {e}"),
+            QuicError::UnexpectedStream => write!(f, "peer opened an unexpected stream"),
+        }
+    }
+}
+
+impl std::error::Error for QuicError {}
+
+impl From<io::Error> for QuicError {
+    fn from(e: io::Error) -> Self {
+        QuicError::Io(e)
+    }
+}
+
+impl From<quinn::ConnectError> for QuicError {
+    fn from(e: quinn::ConnectError) -> Self {
+        QuicError::Connect(e)
+    }
+}
+
+impl From<quinn::ConnectionError> for QuicError {
+    fn from(e: quinn::ConnectionError) -> Self {
+        QuicError::Connection(e)
+    }
+}
+
+impl From<quinn::WriteError> for QuicError {
+    fn from(e: quinn::WriteError) -> Self {
+        QuicError::Write(e)
+    }
+}
+
+impl From<quinn::ReadExactError> for QuicError {
+    fn from(e: quinn::ReadExactError) -> Self {
+        QuicError::Read(e)
+    }
+}
+
+fn tls_error(e: impl fmt::Display) -> QuicError {
+    QuicError::Tls(e.to_string())
+}
+
+/// Both halves of a bidirectional stream
+pub struct QuicStream {
+    pub send: SendStream,
+    pub recv: RecvStream,
+}
+
+impl QuicStream {
+    /// One `AsyncRead + AsyncWrite` object, e.g. for `Framed`
+    pub fn into_io(self) -> Join<RecvStream, SendStream> {
+        tokio::io::join(self.recv, self.send)
+    }
+
+    /// [`into_io`](Self::into_io), held to `limiters`
+    pub fn throttled(self, limiters: &[RateLimiter]) -> Throttled<Join<RecvStream, SendStream>> {
+        Throttled::new(self.into_io(), limiters)
+    }
+}
+
+/// UDP socket that both accepts and initiates connections
+pub struct QuicEndpoint {
+    endpoint: Endpoint,
+}
+
+impl QuicEndpoint {
+    /// Listen on `addr` (port 0 for any) with a fresh self-signed certificate
+    pub fn bind(addr: SocketAddr) -> Result<Self, QuicError> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let mut endpoint = Endpoint::server(server_config(provider.clone())?, addr)?;
+        endpoint.set_default_client_config(client_config(provider)?);
+        Ok(Self { endpoint })
+    }
+
+    /// Run over an already bound socket, such as one from [`nat::punch`](crate::nat::punch)
+    pub fn from_socket(socket: std::net::UdpSocket) -> Result<Self, QuicError> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let runtime = Arc::new(quinn::TokioRuntime);
+        let mut endpoint = Endpoint::new(EndpointConfig::default(), Some(server_config(provider.clone())?), socket, runtime)?;
+        endpoint.set_default_client_config(client_config(provider)?);
+        Ok(Self { endpoint })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, QuicError> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Connect to `addr` and open the control stream
+    pub async fn connect(&self, addr: SocketAddr) -> Result<(QuicConnection, QuicStream), QuicError> {
+        let conn = self.endpoint.connect(addr, SERVER_NAME)?.await?;
+        let (mut send, recv) = conn.open_bi().await?;
+        send.write_all(&[KIND_CONTROL]).await?;
+        Ok((QuicConnection { conn }, QuicStream { send, recv }))
+    }
+
+    /// Next incoming connection, before its QUIC handshake; `None` once the endpoint is closed
+    pub async fn accept(&self) -> Option<QuicIncoming> {
+        self.endpoint.accept().await.map(QuicIncoming)
+    }
+
+    /// Close all connections and stop accepting
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"");
+    }
+}
+
+/// A connection a [`QuicEndpoint`] took, not yet set up
+pub struct QuicIncoming(quinn::Incoming);
+
+impl QuicIncoming {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.0.remote_address()
+    }
+
+    /// Finish the QUIC handshake and wait for the peer's control stream
+    pub async fn control(self) -> Result<(QuicConnection, QuicStream), QuicError> {
+        let conn = self.0.await?;
+        let (send, mut recv) = conn.accept_bi().await?;
+        if recv.read_u8().await? != KIND_CONTROL {
+            return Err(QuicError::UnexpectedStream);
+        }
+        Ok((QuicConnection { conn }, QuicStream { send, recv }))
+    }
+}
+
+/// An established connection; cheap to clone
+#[derive(Clone)]
+pub struct QuicConnection {
+    conn: Connection,
+}
+
+impl QuicConnection {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.conn.remote_address()
+    }
+
+    /// Open the stream for file `index` of the batch
+    pub async fn open_file(&self, index: u32) -> Result<QuicStream, QuicError> {
+        let (mut send, recv) = self.conn.open_bi().await?;
+        let mut header = [KIND_FILE, 0, 0, 0, 0];
+        header[1..].copy_from_slice(&index.to_be_bytes());
+        send.write_all(&header).await?;
+        Ok(QuicStream { send, recv })
+    }
+
+    /// Wait for the peer to open a file stream, returning its index
+    pub async fn accept_file(&self) -> Result<(u32, QuicStream), QuicError> {
+        let (send, mut recv) = self.conn.accept_bi().await?;
+        let mut header = [0; 5];
+        recv.read_exact(&mut header).await?;
+        if header[0] != KIND_FILE {
+            return Err(QuicError::UnexpectedStream);
+        }
+        let index = u32::from_be_bytes(header[1..].try_into().expect("4 bytes"));
+        Ok((index, QuicStream { send, recv }))
+    }
+
+    pub fn close(&self, reason: &[u8]) {
+        self.conn.close(0u32.into(), reason);
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().expect("idle timeout in range")));
+    Arc::new(transport)
+}
+
+fn server_config(provider: Arc<CryptoProvider>) -> Result<ServerConfig, QuicError> {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.into()]).map_err(tls_error)?;
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.cert.der().clone()], key.into())
+        .map_err(tls_error)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let mut config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls).map_err(tls_error)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn client_config(provider: Arc<CryptoProvider>) -> Result<ClientConfig, QuicError> {
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SessionAuthenticated(provider)))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let mut config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls).map_err(tls_error)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Accepts any certificate: the peer is authenticated by the session handshake, not by TLS
+#[derive(Debug)]
+struct SessionAuthenticated(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SessionAuthenticated {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn control_and_file_streams_are_independent() {
+        let server = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let client = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let receiver = tokio::spawn(async move {
+            let (conn, mut control) = server.accept().await.unwrap().control().await.unwrap();
+            let mut hello = [0; 5];
+            control.recv.read_exact(&mut hello).await.unwrap();
+            assert_eq!(&hello, b"hello");
+            let mut files = Vec::new();
+            for _ in 0..2 {
+                let (index, mut stream) = conn.accept_file().await.unwrap();
+                files.push((index, stream.recv.read_to_end(1 << 20).await.unwrap()));
+                stream.send.write_all(b"ok").await.unwrap();
+                stream.send.finish().unwrap();
+            }
+            // keep the connection up until the sender is done reading
+            control.recv.read_to_end(0).await.unwrap();
+            files.sort();
+            files
+        });
+
+        let (conn, mut control) = client.connect(addr).await.unwrap();
+        control.send.write_all(b"hello").await.unwrap();
+        let mut streams = Vec::new();
+        // opened out of order; the index travels with the stream
+        for index in [7u32, 3] {
+            let mut stream = conn.open_file(index).await.unwrap();
+            stream.send.write_all(&vec![index as u8; 10_000]).await.unwrap();
+            stream.send.finish().unwrap();
+            streams.push(stream);
+        }
+        for stream in &mut streams {
+            assert_eq!(stream.recv.read_to_end(16).await.unwrap(), b"ok");
+        }
+        control.send.finish().unwrap();
+        assert_eq!(receiver.await.unwrap(), vec![(3, vec![3; 10_000]), (7, vec![7; 10_000])]);
+        client.close();
+    }
+}
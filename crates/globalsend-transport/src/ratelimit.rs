@@ -0,0 +1,251 @@
+//! Bandwidth limiting
+//!
+//! Token buckets in bytes per second, applied where bytes meet the socket
+//! so every backend is shaped the same way. A [`RateLimiter`] holds one
+//! bucket per direction ([`RateLimit::max_up`], [`RateLimit::max_down`]) and
+//! is a cheap, shared handle: give every connection the same global limiter
+//! plus one of its own for the transfer, and each read or write waits for
+//! the tighter of the two. Limits can change while transfers run.
+//!
+//! Wrap a stream with [`Throttled`], a sealed channel with [`throttle`],
+//! or a whole [`Connection`](crate::Connection) with its `throttle` method.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+use tokio_util::codec::{Framed, FramedParts};
+
+use crate::codec::FrameCodec;
+
+/// Smallest burst a bucket allows, so slow limits still move whole frames' worth in few steps
+pub const MIN_BURST: u64 = 16 * 1024;
+
+/// Bytes per second in each direction; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max_up: Option<u64>,
+    pub max_down: Option<u64>,
+}
+
+impl RateLimit {
+    pub const UNLIMITED: RateLimit = RateLimit { max_up: None, max_down: None };
+}
+
+/// Shared handle on an upload and a download bucket
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    up: Bucket,
+    down: Bucket,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self { up: Bucket::new(limit.max_up), down: Bucket::new(limit.max_down) }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        RateLimit { max_up: self.up.rate(), max_down: self.down.rate() }
+    }
+
+    /// Change the limits for every stream sharing this limiter
+    pub fn set_limit(&self, limit: RateLimit) {
+        self.up.set_rate(limit.max_up);
+        self.down.set_rate(limit.max_down);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bucket(Arc<Mutex<BucketState>>);
+
+#[derive(Debug)]
+struct BucketState {
+    rate: Option<u64>,
+    /// May go negative when streams sharing the bucket overdraw it; later ones then wait longer
+    tokens: f64,
+    last: Instant,
+}
+
+impl Default for BucketState {
+    fn default() -> Self {
+        Self { rate: None, tokens: 0.0, last: Instant::now() }
+    }
+}
+
+impl Bucket {
+    fn new(rate: Option<u64>) -> Self {
+        let bucket = Self::default();
+        bucket.set_rate(rate);
+        bucket
+    }
+
+    fn rate(&self) -> Option<u64> {
+        self.state().rate
+    }
+
+    fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.state();
+        state.rate = rate.filter(|&r| r > 0);
+        // start full at the new rate
+        state.tokens = state.rate.map_or(0.0, burst);
+        state.last = Instant::now();
+    }
+
+    /// Bytes that may go now, or how long until a useful amount may
+    fn available(&self, now: Instant) -> Result<usize, Duration> {
+        let mut state = self.state();
+        let Some(rate) = state.rate else {
+            return Ok(usize::MAX);
+        };
+        let elapsed = now.saturating_duration_since(state.last).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * rate as f64).min(burst(rate));
+        state.last = now;
+        // wait for a quarter burst rather than trickling out a few bytes at a time
+        let quantum = burst(rate) / 4.0;
+        if state.tokens >= quantum {
+            Ok(state.tokens as usize)
+        } else {
+            Err(Duration::from_secs_f64((quantum - state.tokens) / rate as f64))
+        }
+    }
+
+    fn consume(&self, len: usize) {
+        let mut state = self.state();
+        if state.rate.is_some() {
+            state.tokens -= len as f64;
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, BucketState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A tenth of a second's worth, at least [`MIN_BURST`]
+fn burst(rate: u64) -> f64 {
+    (rate / 10).max(MIN_BURST) as f64
+}
+
+/// A stream whose reads and writes wait on every limiter it was given
+pub struct Throttled<T> {
+    inner: T,
+    limiters: Vec<RateLimiter>,
+    read_wait: Option<Pin<Box<Sleep>>>,
+    write_wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiters: &[RateLimiter]) -> Self {
+        Self { inner, limiters: limiters.to_vec(), read_wait: None, write_wait: None }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Wait until every bucket has tokens, returning how many bytes may go
+fn poll_budget<'a>(buckets: impl Iterator<Item = &'a Bucket> + Clone, wait: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<usize> {
+    loop {
+        if let Some(sleep) = wait {
+            ready!(sleep.as_mut().poll(cx));
+            *wait = None;
+        }
+        let now = Instant::now();
+        let (mut allowed, mut delay) = (usize::MAX, Duration::ZERO);
+        for bucket in buckets.clone() {
+            match bucket.available(now) {
+                Ok(n) => allowed = allowed.min(n),
+                Err(d) => delay = delay.max(d),
+            }
+        }
+        if delay.is_zero() {
+            return Poll::Ready(allowed);
+        }
+        *wait = Some(Box::pin(tokio::time::sleep_until(now + delay)));
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Throttled<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let allowed = ready!(poll_budget(this.limiters.iter().map(|l| &l.down), &mut this.read_wait, cx));
+        let len = if buf.remaining() <= allowed {
+            let before = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+            buf.filled().len() - before
+        } else {
+            let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+            let len = limited.filled().len();
+            buf.advance(len);
+            len
+        };
+        this.limiters.iter().for_each(|l| l.down.consume(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Throttled<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let allowed = ready!(poll_budget(this.limiters.iter().map(|l| &l.up), &mut this.write_wait, cx));
+        let len = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..buf.len().min(allowed)]))?;
+        this.limiters.iter().for_each(|l| l.up.consume(len));
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Rate-limit a sealed channel, keeping whatever it already buffered
+pub fn throttle<T>(channel: Framed<T, FrameCodec>, limiters: &[RateLimiter]) -> Framed<Throttled<T>, FrameCodec> {
+    let parts = channel.into_parts();
+    let mut throttled = FramedParts::new::<globalsend_proto::Message>(Throttled::new(parts.io, limiters), parts.codec);
+    throttled.read_buf = parts.read_buf;
+    throttled.write_buf = parts.write_buf;
+    Framed::from_parts(throttled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn global_and_per_transfer_limits_combine() {
+        let global = RateLimiter::new(RateLimit { max_up: Some(100_000), max_down: None });
+        let transfer = RateLimiter::new(RateLimit { max_up: Some(50_000), max_down: None });
+        let (a, mut b) = tokio::io::duplex(1 << 20);
+        let mut a = Throttled::new(a, &[global.clone(), transfer]);
+
+        let start = Instant::now();
+        a.write_all(&[0; 116_384]).await.unwrap();
+        // 16 KiB burst, then 100 KB at the tighter 50 KB/s
+        let took = start.elapsed();
+        assert!(took >= Duration::from_millis(1900) && took <= Duration::from_millis(2200), "{took:?}");
+        let mut got = vec![0; 116_384];
+        b.read_exact(&mut got).await.unwrap();
+
+        global.set_limit(RateLimit::UNLIMITED);
+        let mut unlimited = Throttled::new(b, &[global]);
+        let start = Instant::now();
+        unlimited.write_all(&[0; 1 << 19]).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,143 @@
+//! Relayed TCP, for when no direct path exists
+//!
+//! Both peers connect to the same `globalsend-relay` server and name a
+//! [`RelaySession`] they agreed on out of band (over the rendezvous server):
+//!
+//! ```text
+//! join  = "GSRL" || version u8 || session id (32 bytes)
+//! reply = status u8
+//! ```
+//!
+//! Once the second peer joins, the relay answers [`STATUS_PAIRED`] to both
+//! and from then on copies bytes between them untouched. The Noise
+//! handshake and every sealed frame pass through it end to end, so the relay
+//! sees lengths and timing but never content, and cannot impersonate either
+//! side. The session id only decides who gets paired with whom.
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use globalsend_crypto::handshake::Role;
+use globalsend_crypto::keyprovider::KeyProvider;
+use rand::RngCore;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::secure::{self, Peer, SecureError};
+use crate::tcp::TcpChannel;
+
+pub const MAGIC: &[u8; 4] = b"GSRL";
+pub const RELAY_VERSION: u8 = 1;
+pub const SESSION_ID_LEN: usize = 32;
+pub const JOIN_LEN: usize = MAGIC.len() + 1 + SESSION_ID_LEN;
+
+pub const STATUS_PAIRED: u8 = 0;
+/// Nobody else joined in time
+pub const STATUS_TIMEOUT: u8 = 1;
+/// The relay is at its session limit
+pub const STATUS_BUSY: u8 = 2;
+pub const STATUS_BAD_REQUEST: u8 = 3;
+
+/// Meeting point both peers name to the relay
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RelaySession(pub [u8; SESSION_ID_LEN]);
+
+impl RelaySession {
+    pub fn random() -> Self {
+        let mut id = [0u8; SESSION_ID_LEN];
+        rand::thread_rng().fill_bytes(&mut id);
+        Self(id)
+    }
+
+    pub fn join_request(&self) -> [u8; JOIN_LEN] {
+        let mut out = [0u8; JOIN_LEN];
+        out[..4].copy_from_slice(MAGIC);
+        out[4] = RELAY_VERSION;
+        out[5..].copy_from_slice(&self.0);
+        out
+    }
+
+    /// Session named by a join request; `None` for anything else
+    pub fn from_join_request(bytes: &[u8; JOIN_LEN]) -> Option<Self> {
+        if &bytes[..4] != MAGIC || bytes[4] != RELAY_VERSION {
+            return None;
+        }
+        Some(Self(bytes[5..].try_into().ok()?))
+    }
+}
+
+impl fmt::Debug for RelaySession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // enough to correlate logs without handing out the meeting point
+        write!(f, "RelaySession({:02x}{:02x}{:02x}{:02x}…)", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+#[derive(Debug)]
+pub enum RelayError {
+    Secure(SecureError),
+    /// The relay turned the join down with this status
+    Refused(u8),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::Secure(e) => write!(f, "{e}"),
+            RelayError::Refused(STATUS_TIMEOUT) => write!(f, "relay: peer did not join in time"),
+            RelayError::Refused(STATUS_BUSY) => write!(f, "relay is full"),
+            RelayError::Refused(status) => write!(f, "relay refused the session (status {status})"),
+        }
+    }
+}
+
+impl std::error::Error for RelayError {}
+
+impl From<SecureError> for RelayError {
+    fn from(e: SecureError) -> Self {
+        RelayError::Secure(e)
+    }
+}
+
+impl From<std::io::Error> for RelayError {
+    fn from(e: std::io::Error) -> Self {
+        RelayError::Secure(e.into())
+    }
+}
+
+/// Join `session` at `relay`, wait for the peer and run the handshake as `role`
+///
+/// The peers must pick opposite roles; the one that created the session is
+/// usually the initiator.
+pub async fn connect(relay: SocketAddr, session: &RelaySession, role: Role, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), RelayError> {
+    let stream = join(relay, session).await?;
+    Ok(match role {
+        Role::Initiator => secure::initiate(stream, static_key).await?,
+        Role::Responder => secure::respond(stream, static_key).await?,
+    })
+}
+
+/// Join `session` and wait until the relay pairs us; the stream is still raw
+pub(crate) async fn join(relay: SocketAddr, session: &RelaySession) -> Result<TcpStream, RelayError> {
+    let mut stream = TcpStream::connect(relay).await?;
+    stream.set_nodelay(true)?;
+    stream.write_all(&session.join_request()).await?;
+    match stream.read_u8().await? {
+        STATUS_PAIRED => Ok(stream),
+        status => Err(RelayError::Refused(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_request_roundtrip() {
+        let session = RelaySession::random();
+        let mut join = session.join_request();
+        assert_eq!(RelaySession::from_join_request(&join), Some(session));
+        join[4] = RELAY_VERSION + 1;
+        assert_eq!(RelaySession::from_join_request(&join), None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Noise handshake over a byte stream
+//!
+//! Runs [`Handshake`] over any ordered stream (a TCP connection, a QUIC
+//! control stream) and wraps the stream in a [`FrameCodec`] with the
+//! resulting session keys. Handshake messages are length-prefixed:
+//!
+//! ```text
+//! length (u16 BE) || Noise message
+//! ```
+//!
+//! The first two messages carry each side's offer as their payload:
+//!
+//! ```text
+//! format (1) || min version (u16 BE) || max version (u16 BE) || suite count (u8) || suite ids
+//! ```
+//!
+//! Both sides work out the same version and [`CipherSuite`] from the two
+//! offers and bind them into the transcript before deriving keys
+//! ([`Handshake::bind_parameters`]). Noise already covers the payloads, so
+//! an on-path attacker who rewrites an offer breaks the handshake; binding
+//! the outcome as well means two sides that somehow disagree on it cannot
+//! end up sharing keys either. A peer from before offers sends an empty
+//! payload, and the responder answers it in kind, with nothing bound.
+//!
+//! Each side offers [`CipherSuite::preferred`] unless [`offer_suites`] set a
+//! list, such as the one a user configured. From [`SUITE_VERSION`] the
+//! session is sealed with the negotiated suite; peers before it negotiated
+//! one too, but always sealed with XChaCha20-Poly1305, and still do.
+//!
+//! Every handshake runs in a `handshake` tracing span and is counted in the
+//! `globalsend_handshakes_total` metric, labelled by `role` and `result`.
+
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+
+use globalsend_crypto::handshake::{Handshake, HandshakeError, Role};
+use globalsend_crypto::keyprovider::KeyProvider;
+use globalsend_crypto::suite::{self, CipherSuite};
+use globalsend_proto::{ProtoError, VersionRange};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::Framed;
+use tracing::Instrument;
+use x25519_dalek::PublicKey as XPublicKey;
+
+use crate::codec::FrameCodec;
+
+/// Prologue bound into every transport handshake
+pub const PROLOGUE: &[u8] = b"globalsend transport v1";
+/// Leading byte of a handshake offer
+const OFFER_FORMAT: u8 = 1;
+/// First protocol version that seals the session with the negotiated suite
+pub const SUITE_VERSION: u16 = 14;
+
+/// Suites every handshake offers, in preference order; `None` for [`CipherSuite::preferred`]
+static SUITES: Mutex<Option<Vec<CipherSuite>>> = Mutex::new(None);
+
+/// Offer only `suites`, most preferred first, in every handshake from now
+/// on; `None` goes back to [`CipherSuite::preferred`]
+pub fn offer_suites(suites: Option<Vec<CipherSuite>>) {
+    *SUITES.lock().expect("suites lock") = suites;
+}
+
+#[derive(Debug)]
+pub enum SecureError {
+    Io(io::Error),
+    Handshake(HandshakeError),
+    /// The peer's handshake payload is not an offer
+    BadOffer,
+    /// No protocol version in common
+    Version(ProtoError),
+    /// No cipher suite in common
+    NoCommonSuite,
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureError::Io(e) => write!(f, "i/o error during handshake: {e}"),
+            SecureError::Handshake(e) => write!(f, "handshake failed: {e}"),
+            SecureError::BadOffer => write!(f, "handshake failed: malformed offer"),
+            SecureError::Version(e) => write!(f, "handshake failed: {e}"),
+            SecureError::NoCommonSuite => write!(f, "handshake failed: no cipher suite in common"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+impl From<io::Error> for SecureError {
+    fn from(e: io::Error) -> Self {
+        SecureError::Io(e)
+    }
+}
+
+impl From<HandshakeError> for SecureError {
+    fn from(e: HandshakeError) -> Self {
+        SecureError::Handshake(e)
+    }
+}
+
+/// Who is on the other end of a secured stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    /// Authenticated static key; check it against the trust store before sending anything
+    pub static_key: XPublicKey,
+    /// Transcript hash, the same on both sides; feeds SAS codes
+    pub handshake_hash: [u8; 32],
+    /// Protocol version bound into the handshake; `None` for a peer that made no offer
+    pub version: Option<u16>,
+    /// Cipher suite bound into the handshake, likewise; the session is sealed with it from [`SUITE_VERSION`]
+    pub suite: Option<CipherSuite>,
+}
+
+/// What one side of the handshake speaks
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Offer {
+    versions: VersionRange,
+    suites: Vec<CipherSuite>,
+}
+
+impl Offer {
+    /// What this process offers; see [`offer_suites`]
+    fn ours() -> Self {
+        let suites = SUITES.lock().expect("suites lock").clone().unwrap_or_else(CipherSuite::preferred);
+        Self { versions: VersionRange::CURRENT, suites }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![OFFER_FORMAT];
+        out.extend_from_slice(&self.versions.min.to_be_bytes());
+        out.extend_from_slice(&self.versions.max.to_be_bytes());
+        out.push(self.suites.len() as u8);
+        out.extend(self.suites.iter().map(|s| s.id()));
+        out
+    }
+
+    /// `None` for an empty payload; suites this build does not know are skipped
+    fn decode(payload: &[u8]) -> Result<Option<Self>, SecureError> {
+        let Some((&format, rest)) = payload.split_first() else { return Ok(None) };
+        let [min0, min1, max0, max1, count, ids @ ..] = rest else { return Err(SecureError::BadOffer) };
+        if format != OFFER_FORMAT || ids.len() != usize::from(*count) {
+            return Err(SecureError::BadOffer);
+        }
+        let versions = VersionRange { min: u16::from_be_bytes([*min0, *min1]), max: u16::from_be_bytes([*max0, *max1]) };
+        Ok(Some(Self { versions, suites: ids.iter().filter_map(|&id| CipherSuite::from_id(id)).collect() }))
+    }
+}
+
+/// Version and suite for an initiator's and a responder's offer
+fn settle(initiator: &Offer, responder: &Offer) -> Result<(u16, CipherSuite), SecureError> {
+    let version = globalsend_proto::negotiate(initiator.versions, responder.versions).map_err(SecureError::Version)?;
+    let suite = suite::negotiate(&initiator.suites, &responder.suites).ok_or(SecureError::NoCommonSuite)?;
+    Ok((version, suite))
+}
+
+/// Run the handshake as the connecting side
+pub async fn initiate<S>(stream: S, static_key: &dyn KeyProvider) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    run(stream, Handshake::initiator(static_key, PROLOGUE)).await
+}
+
+/// Run the handshake as the accepting side
+pub async fn respond<S>(stream: S, static_key: &dyn KeyProvider) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    run(stream, Handshake::responder(static_key, PROLOGUE)).await
+}
+
+/// [`initiate`] with a caller-chosen prologue; both sides must pass the same bytes
+///
+/// Binds the handshake to whatever the prologue holds, such as a key from an
+/// earlier pairing step, so a man in the middle without it cannot finish.
+pub async fn initiate_with_prologue<S>(stream: S, static_key: &dyn KeyProvider, prologue: &[u8]) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    run(stream, Handshake::initiator(static_key, prologue)).await
+}
+
+/// [`respond`] with a caller-chosen prologue
+pub async fn respond_with_prologue<S>(stream: S, static_key: &dyn KeyProvider, prologue: &[u8]) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    run(stream, Handshake::responder(static_key, prologue)).await
+}
+
+/// [`exchange`] in a `handshake` span, counted in `globalsend_handshakes_total`
+async fn run<S>(stream: S, handshake: Handshake<'_>) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let role = match handshake.role() {
+        Role::Initiator => "initiator",
+        Role::Responder => "responder",
+    };
+    let result = exchange(stream, handshake).instrument(tracing::debug_span!("handshake", role)).await;
+    let outcome = match &result {
+        Ok((_, peer)) => {
+            let key: String = peer.static_key.as_bytes()[..8].iter().map(|b| format!("{b:02x}")).collect();
+            tracing::debug!(role, peer = %key, "handshake complete");
+            "ok"
+        }
+        Err(e) => {
+            tracing::info!(role, error = %e, "handshake failed");
+            "failed"
+        }
+    };
+    metrics::counter!("globalsend_handshakes_total", "role" => role, "result" => outcome).increment(1);
+    result
+}
+
+async fn exchange<S>(mut stream: S, mut handshake: Handshake<'_>) -> Result<(Framed<S, FrameCodec>, Peer), SecureError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // XX: initiator writes messages 1 and 3, responder writes 2; offers ride in 1 and 2
+    let initiator = handshake.role() == Role::Initiator;
+    let ours = Offer::ours();
+    let mut theirs = None;
+    let mut our_turn = initiator;
+    let mut sent = 0;
+    while !handshake.is_finished() {
+        if our_turn {
+            // the responder only offers back to an initiator that offered
+            let offer = sent == 0 && (initiator || theirs.is_some());
+            let message = handshake.write_message(&if offer { ours.encode() } else { Vec::new() })?;
+            stream.write_u16(message.len() as u16).await?;
+            stream.write_all(&message).await?;
+            stream.flush().await?;
+            sent += 1;
+        } else {
+            let len = stream.read_u16().await? as usize;
+            let mut message = vec![0; len];
+            stream.read_exact(&mut message).await?;
+            let payload = handshake.read_message(&message)?;
+            if theirs.is_none() {
+                theirs = Offer::decode(&payload)?;
+            }
+        }
+        our_turn = !our_turn;
+    }
+    let settled = match &theirs {
+        Some(theirs) if initiator => Some(settle(&ours, theirs)?),
+        Some(theirs) => Some(settle(theirs, &ours)?),
+        None => None,
+    };
+    if let Some((version, suite)) = settled {
+        handshake.bind_parameters(version, suite)?;
+    }
+    let peer = Peer {
+        static_key: handshake.remote_static().expect("finished handshake"),
+        handshake_hash: handshake.handshake_hash(),
+        version: settled.map(|(v, _)| v),
+        suite: settled.map(|(_, s)| s),
+    };
+    let keys = match settled {
+        Some((version, suite)) if version >= SUITE_VERSION => handshake.into_session()?.with_suite(suite),
+        _ => handshake.into_session()?,
+    };
+    Ok((Framed::new(stream, FrameCodec::new(keys)), peer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use globalsend_crypto::DeviceKey;
+    use globalsend_proto::{Cancel, CancelReason, Message, TransferId, PROTOCOL_VERSION};
+    use tokio::io::{duplex, DuplexStream};
+
+    #[tokio::test]
+    async fn handshake_then_sealed_messages() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let (left, right) = tokio::io::duplex(1024);
+        let ((mut fa, pa), (mut fb, pb)) = tokio::try_join!(initiate(left, &a), respond(right, &b)).unwrap();
+        assert_eq!(pa.static_key, b.public());
+        assert_eq!(pb.static_key, a.public());
+        assert_eq!(pa.handshake_hash, pb.handshake_hash);
+        assert_eq!((pa.version, pa.suite), (Some(PROTOCOL_VERSION), Some(CipherSuite::preferred()[0])));
+        assert_eq!((pb.version, pb.suite), (pa.version, pa.suite));
+        assert_eq!(fa.codec().keys().session_id(), fb.codec().keys().session_id());
+        assert_eq!((fa.codec().keys().suite(), fb.codec().keys().suite()), (CipherSuite::preferred()[0], CipherSuite::preferred()[0]));
+        // a side restricted to one suite gets it
+        let (aes, chacha) = (Offer { suites: vec![CipherSuite::Aes256Gcm], ..Offer::ours() }, Offer { suites: vec![CipherSuite::XChaCha20Poly1305, CipherSuite::Aes256Gcm], ..Offer::ours() });
+        assert_eq!(settle(&chacha, &aes).unwrap().1, CipherSuite::Aes256Gcm);
+
+        let cancel: Message = Cancel { transfer: TransferId([9; 16]), reason: CancelReason::User }.into();
+        fa.send(cancel.clone()).await.unwrap();
+        assert_eq!(fb.next().await.unwrap().unwrap(), cancel);
+    }
+
+    /// Pass the handshake between `a` and `b`, handing message 1 to `rewrite` first
+    async fn relay_rewriting(mut a: DuplexStream, mut b: DuplexStream, rewrite: impl Fn(Vec<u8>) -> Vec<u8>) {
+        let len = a.read_u16().await.unwrap() as usize;
+        let mut first = vec![0; len];
+        a.read_exact(&mut first).await.unwrap();
+        let first = rewrite(first);
+        b.write_u16(first.len() as u16).await.unwrap();
+        b.write_all(&first).await.unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut a, &mut b).await;
+    }
+
+    #[tokio::test]
+    async fn stripped_offer_fails_the_handshake() {
+        let (a, b) = (DeviceKey::generate(), DeviceKey::generate());
+        let (left, proxy_left) = duplex(1024);
+        let (proxy_right, right) = duplex(1024);
+        // message 1 is the ephemeral key followed by the plaintext offer
+        tokio::spawn(relay_rewriting(proxy_left, proxy_right, |m| m[..32].to_vec()));
+        let (initiated, responded) = tokio::join!(initiate(left, &a), respond(right, &b));
+        assert!(matches!(initiated, Err(SecureError::Handshake(HandshakeError::Decrypt))));
+        assert!(responded.is_err());
+
+        // a peer from before offers still gets through, with nothing bound
+        let (left, right) = duplex(1024);
+        let legacy = async move {
+            let mut stream = left;
+            let mut handshake = Handshake::initiator(&a, PROLOGUE);
+            for turn in 0..3 {
+                if turn == 1 {
+                    let mut message = vec![0; stream.read_u16().await.unwrap() as usize];
+                    stream.read_exact(&mut message).await.unwrap();
+                    assert!(handshake.read_message(&message).unwrap().is_empty());
+                } else {
+                    let message = handshake.write_message(b"").unwrap();
+                    stream.write_u16(message.len() as u16).await.unwrap();
+                    stream.write_all(&message).await.unwrap();
+                }
+            }
+            handshake.handshake_hash()
+        };
+        let (hash, responded) = tokio::join!(legacy, respond(right, &b));
+        let (_, peer) = responded.unwrap();
+        assert_eq!((peer.version, peer.suite, peer.handshake_hash), (None, None, hash));
+    }
+}
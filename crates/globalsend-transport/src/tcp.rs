@@ -0,0 +1,53 @@
+//! Plain TCP backend
+//!
+//! For networks that drop UDP (corporate Wi-Fi, some hotel and mobile
+//! networks): one TCP connection carries the Noise handshake and then the
+//! sealed message stream, with no TLS underneath. Files share the connection
+//! and take turns, unlike the per-file streams of [`quic`](crate::quic).
+
+use std::io;
+use std::net::SocketAddr;
+
+use globalsend_crypto::keyprovider::KeyProvider;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::Framed;
+
+use crate::codec::FrameCodec;
+use crate::secure::{self, Peer, SecureError};
+
+/// Sealed message stream over one TCP connection
+pub type TcpChannel = Framed<TcpStream, FrameCodec>;
+
+/// Connect to `addr` and run the handshake as initiator
+pub async fn connect(addr: SocketAddr, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), SecureError> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    secure::initiate(stream, static_key).await
+}
+
+/// Listening socket for incoming TCP sessions
+pub struct TcpTransport {
+    listener: TcpListener,
+}
+
+impl TcpTransport {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self { listener: TcpListener::bind(addr).await? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept the next connection, without a handshake; see [`respond`]
+    pub async fn accept(&self) -> io::Result<TcpStream> {
+        let (stream, _) = self.listener.accept().await?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+}
+
+/// Run the handshake as responder on a stream from [`TcpTransport::accept`]
+pub async fn respond(stream: TcpStream, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), SecureError> {
+    secure::respond(stream, static_key).await
+}
@@ -0,0 +1,220 @@
+//! WebRTC data channel backend
+//!
+//! Lets a browser receive from a native client without installing
+//! anything: the web UI and the native side swap SDP through whatever
+//! signalling path they share (the relay, a QR code) and open one ordered
+//! data channel. DTLS underneath uses throwaway certificates and its
+//! identity is not trusted; the Noise handshake runs inside the channel and
+//! everything after it is sealed with the session keys, exactly as over
+//! [`tcp`](crate::tcp).
+//!
+//! Signalling is not trickled: [`RtcPeer::offer`] and [`RtcPeer::answer`]
+//! wait for ICE gathering and return a complete SDP blob, so one message each
+//! way is enough. The offering side runs the handshake as initiator.
+//!
+//! Data channel messages are kept to [`MAX_MESSAGE_LEN`] bytes, the size
+//! every browser accepts; the byte stream is split and rejoined transparently.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use globalsend_crypto::keyprovider::KeyProvider;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::APIBuilder;
+use webrtc::data::data_channel::{DataChannel, PollDataChannel};
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::codec::FrameCodec;
+use crate::secure::{self, Peer, SecureError};
+
+/// Largest data channel message sent, and the read buffer size
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024;
+const CHANNEL_LABEL: &str = "globalsend";
+
+#[derive(Debug)]
+pub enum WebRtcError {
+    WebRtc(webrtc::Error),
+    Secure(SecureError),
+    /// The peer connection closed before the data channel opened
+    Closed,
+}
+
+impl fmt::Display for WebRtcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebRtcError::WebRtc(e) => write!(f, "webrtc: {e}"),
+            WebRtcError::Secure(e) => write!(f, "{e}"),
+            WebRtcError::Closed => write!(f, "peer connection closed before the data channel opened"),
+        }
+    }
+}
+
+impl std::error::Error for WebRtcError {}
+
+impl From<webrtc::Error> for WebRtcError {
+    fn from(e: webrtc::Error) -> Self {
+        WebRtcError::WebRtc(e)
+    }
+}
+
+impl From<SecureError> for WebRtcError {
+    fn from(e: SecureError) -> Self {
+        WebRtcError::Secure(e)
+    }
+}
+
+/// Sealed message stream over a data channel
+pub type RtcChannel = Framed<RtcStream, FrameCodec>;
+
+/// One side of a WebRTC connection, between signalling and the open channel
+pub struct RtcPeer {
+    pc: Arc<RTCPeerConnection>,
+    opened: mpsc::Receiver<Arc<DataChannel>>,
+    initiator: bool,
+}
+
+impl RtcPeer {
+    /// Create the data channel and an offer for the browser.
+    ///
+    /// `ice_servers` are STUN/TURN URLs; none is fine on a LAN.
+    pub async fn offer(ice_servers: &[String]) -> Result<(Self, String), WebRtcError> {
+        let pc = peer_connection(ice_servers).await?;
+        let (tx, opened) = mpsc::channel(1);
+        let init = RTCDataChannelInit { ordered: Some(true), ..Default::default() };
+        let channel = pc.create_data_channel(CHANNEL_LABEL, Some(init)).await?;
+        detach_on_open(channel, tx);
+        let offer = pc.create_offer(None).await?;
+        let sdp = gather(&pc, offer).await?;
+        Ok((Self { pc, opened, initiator: true }, sdp))
+    }
+
+    /// Answer an offer from [`RtcPeer::offer`]
+    pub async fn answer(ice_servers: &[String], offer: &str) -> Result<(Self, String), WebRtcError> {
+        let pc = peer_connection(ice_servers).await?;
+        let (tx, opened) = mpsc::channel(1);
+        pc.on_data_channel(Box::new(move |channel| {
+            if channel.label() == CHANNEL_LABEL {
+                detach_on_open(channel, tx.clone());
+            }
+            Box::pin(async {})
+        }));
+        pc.set_remote_description(RTCSessionDescription::offer(offer.to_owned())?).await?;
+        let answer = pc.create_answer(None).await?;
+        let sdp = gather(&pc, answer).await?;
+        Ok((Self { pc, opened, initiator: false }, sdp))
+    }
+
+    /// Complete signalling on the offering side
+    pub async fn accept_answer(&self, answer: &str) -> Result<(), WebRtcError> {
+        Ok(self.pc.set_remote_description(RTCSessionDescription::answer(answer.to_owned())?).await?)
+    }
+
+    /// Wait for the data channel and run the Noise handshake over it
+    pub async fn connect(mut self, static_key: &dyn KeyProvider) -> Result<(RtcChannel, Peer), WebRtcError> {
+        let channel = self.opened.recv().await.ok_or(WebRtcError::Closed)?;
+        let mut poll = PollDataChannel::new(channel);
+        poll.set_read_buf_capacity(MAX_MESSAGE_LEN);
+        let stream = RtcStream { channel: poll, _pc: self.pc };
+        Ok(if self.initiator {
+            secure::initiate(stream, static_key).await?
+        } else {
+            secure::respond(stream, static_key).await?
+        })
+    }
+}
+
+async fn peer_connection(ice_servers: &[String]) -> Result<Arc<RTCPeerConnection>, WebRtcError> {
+    let mut settings = SettingEngine::default();
+    settings.detach_data_channels();
+    settings.set_include_loopback_candidate(true);
+    let api = APIBuilder::new().with_setting_engine(settings).build();
+    let ice_servers = if ice_servers.is_empty() {
+        Vec::new()
+    } else {
+        vec![RTCIceServer { urls: ice_servers.to_vec(), ..Default::default() }]
+    };
+    Ok(Arc::new(api.new_peer_connection(RTCConfiguration { ice_servers, ..Default::default() }).await?))
+}
+
+/// Set `description` and return the SDP once all candidates are in it
+async fn gather(pc: &RTCPeerConnection, description: RTCSessionDescription) -> Result<String, WebRtcError> {
+    let mut done = pc.gathering_complete_promise().await;
+    pc.set_local_description(description).await?;
+    let _ = done.recv().await;
+    pc.local_description().await.map(|d| d.sdp).ok_or(WebRtcError::Closed)
+}
+
+fn detach_on_open(channel: Arc<RTCDataChannel>, tx: mpsc::Sender<Arc<DataChannel>>) {
+    let opened = channel.clone();
+    channel.on_open(Box::new(move || {
+        Box::pin(async move {
+            if let Ok(raw) = opened.detach().await {
+                let _ = tx.send(raw).await;
+            }
+        })
+    }));
+}
+
+/// Byte stream over a data channel; keeps the peer connection alive
+pub struct RtcStream {
+    channel: PollDataChannel,
+    _pc: Arc<RTCPeerConnection>,
+}
+
+impl AsyncRead for RtcStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.channel).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RtcStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let len = buf.len().min(MAX_MESSAGE_LEN);
+        Pin::new(&mut self.channel).poll_write(cx, &buf[..len])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.channel).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.channel).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use globalsend_crypto::DeviceKey;
+    use globalsend_proto::{ChunkData, Message, TransferId};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn offer_answer_then_sealed_stream() {
+        let (native, browser) = (DeviceKey::generate(), DeviceKey::generate());
+        let (offerer, offer) = RtcPeer::offer(&[]).await.unwrap();
+        let (answerer, answer) = RtcPeer::answer(&[], &offer).await.unwrap();
+        offerer.accept_answer(&answer).await.unwrap();
+
+        let ((mut tx, to_browser), (mut rx, to_native)) =
+            tokio::try_join!(offerer.connect(&native), answerer.connect(&browser)).unwrap();
+        assert_eq!(to_browser.static_key, browser.public());
+        assert_eq!(to_native.static_key, native.public());
+
+        // bigger than one data channel message
+        let chunk: Message = ChunkData { transfer: TransferId([5; 16]), index: 0, offset: 0, data: vec![7; 100_000] }.into();
+        tx.send(chunk.clone()).await.unwrap();
+        assert_eq!(rx.next().await.unwrap().unwrap(), chunk);
+    }
+}
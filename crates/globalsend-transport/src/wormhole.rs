@@ -0,0 +1,130 @@
+//! Wormhole-style transfers: meet by a short code, pair by PAKE
+//!
+//! The sender shows a [`WormholeCode`] such as `7-guitar-sunset` and the
+//! receiver types it in. Both join the relay under a session derived from
+//! the nameplate (the `7`), which doubles as the rendezvous; once paired the
+//! stream carries:
+//!
+//! ```text
+//! both : length u16 BE || SPAKE2 message
+//! both : confirmation (32 bytes)
+//! then : Noise XX with prologue = "globalsend wormhole v1" || pairing key
+//! ```
+//!
+//! The words never leave the two devices except as SPAKE2 input, so the
+//! relay learns only the nameplate and gets one online guess per attempt. A
+//! wrong code fails at the confirmation step. Putting the pairing key in the
+//! Noise prologue ties the handshake to the PAKE: a relay that passes the
+//! PAKE through and then swaps in its own Noise keys cannot finish either
+//! handshake. Everything after that is sealed end to end as with
+//! [`relay::connect`].
+
+use std::fmt;
+use std::net::SocketAddr;
+
+use globalsend_crypto::handshake::Role;
+use globalsend_crypto::keyprovider::KeyProvider;
+use globalsend_crypto::pairing::{Pairing, PairingError, WormholeCode, CONFIRMATION_LEN, PAIRING_KEY_LEN};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use zeroize::Zeroizing;
+
+use crate::relay::{self, RelayError, RelaySession};
+use crate::secure::{self, Peer, SecureError};
+use crate::tcp::TcpChannel;
+
+pub const WORMHOLE_PROLOGUE: &[u8] = b"globalsend wormhole v1";
+/// SPAKE2 Ed25519 messages are 33 bytes; anything much larger is not a peer
+const MAX_PAKE_MESSAGE: usize = 256;
+
+#[derive(Debug)]
+pub enum WormholeError {
+    Relay(RelayError),
+    /// Wrong code, or someone else holding the nameplate
+    Pairing(PairingError),
+}
+
+impl fmt::Display for WormholeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WormholeError::Relay(e) => write!(f, "{e}"),
+            WormholeError::Pairing(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for WormholeError {}
+
+impl From<RelayError> for WormholeError {
+    fn from(e: RelayError) -> Self {
+        WormholeError::Relay(e)
+    }
+}
+
+impl From<PairingError> for WormholeError {
+    fn from(e: PairingError) -> Self {
+        WormholeError::Pairing(e)
+    }
+}
+
+impl From<SecureError> for WormholeError {
+    fn from(e: SecureError) -> Self {
+        WormholeError::Relay(e.into())
+    }
+}
+
+impl From<std::io::Error> for WormholeError {
+    fn from(e: std::io::Error) -> Self {
+        WormholeError::Relay(e.into())
+    }
+}
+
+/// Relay session both holders of `code` join
+pub fn rendezvous(code: &WormholeCode) -> RelaySession {
+    RelaySession(code.rendezvous_id())
+}
+
+/// Send side: wait at `relay` for whoever types `code`
+pub async fn send(relay: SocketAddr, code: &WormholeCode, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), WormholeError> {
+    connect(relay, code, Role::Initiator, static_key).await
+}
+
+/// Receive side: join the sender behind `code`
+pub async fn receive(relay: SocketAddr, code: &WormholeCode, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), WormholeError> {
+    connect(relay, code, Role::Responder, static_key).await
+}
+
+async fn connect(relay: SocketAddr, code: &WormholeCode, role: Role, static_key: &dyn KeyProvider) -> Result<(TcpChannel, Peer), WormholeError> {
+    let mut stream = relay::join(relay, &rendezvous(code)).await?;
+    let key = pake(&mut stream, code, role).await?;
+
+    let mut prologue = Zeroizing::new(Vec::with_capacity(WORMHOLE_PROLOGUE.len() + PAIRING_KEY_LEN));
+    prologue.extend_from_slice(WORMHOLE_PROLOGUE);
+    prologue.extend_from_slice(&key[..]);
+    Ok(match role {
+        Role::Initiator => secure::initiate_with_prologue(stream, static_key, &prologue).await?,
+        Role::Responder => secure::respond_with_prologue(stream, static_key, &prologue).await?,
+    })
+}
+
+async fn pake(stream: &mut TcpStream, code: &WormholeCode, role: Role) -> Result<Zeroizing<[u8; PAIRING_KEY_LEN]>, WormholeError> {
+    // both sides write before reading, so neither waits on the other
+    let pairing = Pairing::start_with_code(role, code);
+    stream.write_u16(pairing.message().len() as u16).await?;
+    stream.write_all(pairing.message()).await?;
+    stream.flush().await?;
+    let len = stream.read_u16().await? as usize;
+    if len > MAX_PAKE_MESSAGE {
+        return Err(PairingError::BadMessage.into());
+    }
+    let mut peer_message = vec![0; len];
+    stream.read_exact(&mut peer_message).await?;
+
+    let pending = pairing.finish(&peer_message)?;
+    stream.write_all(pending.confirmation()).await?;
+    stream.flush().await?;
+    let mut peer_confirmation = [0u8; CONFIRMATION_LEN];
+    stream.read_exact(&mut peer_confirmation).await?;
+    let key = pending.verify(&peer_confirmation)?;
+    Ok(Zeroizing::new(*key.as_bytes()))
+}
@@ -0,0 +1,533 @@
+//! `config.toml`: settings that outlive one command
+//!
+//! Layered, later wins: built-in defaults, then
+//! `$XDG_CONFIG_HOME/globalsend/config.toml` (or `--config`), then
+//! `GLOBALSEND_*` environment variables, then command-line flags, which
+//! `main` applies on top of [`Config::load`]. Unknown keys and bad values
+//! are errors that name the file or variable and the key.
+//!
+//! ```toml
+//! alias = "nas"
+//! downloads = "~/Inbox"
+//! port = 53317
+//! transport = "auto"                       # auto, quic or tcp
+//! ciphers = ["aes256gcm", "xchacha20poly1305"]
+//! relay = "relay.example.org:7000"
+//! compress = false                         # send and take chunks as they are
+//! keep_partial = "~/Partial"               # "remove", "keep", or a folder to move them to
+//! max_up = "2MB"                           # bytes a second for all transfers together
+//! max_down = "10MB"
+//! transfer_max_up = "1MB"                  # and for each one on its own
+//! transfer_max_down = "5MB"
+//!
+//! [[accept]]                               # first matching rule wins
+//! name = "phone photos"
+//! action = "accept"                        # or "decline"
+//! device = "<fingerprint>"
+//! types = ["image/*", ".heic"]
+//! max_file_size = "50MB"
+//! destination = "~/Pictures/Inbox"
+//!
+//! [[hooks]]                                # see globalsend_daemon::hooks
+//! events = ["transfer-complete"]           # offer-received, transfer-complete, verification-failed
+//! command = ["notify-send", "globalsend", "files arrived"]
+//!
+//! [[hooks]]
+//! events = ["offer-received"]
+//! url = "https://example.org/globalsend"
+//!
+//! [[sync]]                                 # see globalsend_daemon::sync
+//! folder = "notes"                         # the same id on both devices
+//! path = "~/Notes"
+//! device = "<fingerprint>"                 # a paired device
+//! addr = "192.168.1.20:53317"              # optional; found through discovery otherwise
+//! interval = 600                           # optional; seconds between runs without changes
+//! ```
+//!
+//! `accept` rules only ever take offers from paired devices; see
+//! [`AcceptPolicy`]. So do sync folders, which the daemon checks at start.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_crypto::suite::CipherSuite;
+use globalsend_daemon::hooks::{Hook, HookAction, HookEvent};
+use globalsend_daemon::sync::SyncProfile;
+use globalsend_transfer::policy::{AcceptPolicy, AcceptRule, Action};
+use globalsend_transfer::KeepPartial;
+use globalsend_transport::{RateLimit, TransportPreference};
+use toml::{Table, Value};
+
+use crate::error::CliError;
+use crate::paths;
+
+/// Environment variables read by [`Config::apply_env`], and the key each sets
+const ENV: [(&str, &str); 7] = [
+    ("GLOBALSEND_NAME", "alias"),
+    ("GLOBALSEND_DOWNLOADS", "downloads"),
+    ("GLOBALSEND_PORT", "port"),
+    ("GLOBALSEND_TRANSPORT", "transport"),
+    ("GLOBALSEND_RELAY", "relay"),
+    ("GLOBALSEND_MAX_UP", "max_up"),
+    ("GLOBALSEND_MAX_DOWN", "max_down"),
+];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: io::Error },
+    /// Not TOML at all
+    Syntax { path: PathBuf, message: String },
+    /// `key` in `source`, a file or an environment variable, has a bad value
+    Invalid { source: String, key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            ConfigError::Syntax { path, message } => write!(f, "{}: {}", path.display(), message.trim_end()),
+            ConfigError::Invalid { source, key, message } => write!(f, "{source}: {key}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings after the file and environment; `None` means the built-in default
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub alias: Option<String>,
+    pub downloads: Option<PathBuf>,
+    /// Where `receive`, `tui` and `daemon` listen; 0 picks a free port
+    pub port: u16,
+    pub transport: TransportPreference,
+    /// Ciphers this device offers in every handshake, most preferred first;
+    /// the connecting side's order wins
+    pub ciphers: Vec<CipherSuite>,
+    /// `host:port` of the relay for wormhole codes
+    pub relay: Option<String>,
+    /// Offer to compress chunks
+    pub compress: bool,
+    /// What becomes of files a receive left half-written
+    pub keep_partial: KeepPartial,
+    /// Bandwidth for all transfers together
+    pub rate: RateLimit,
+    /// Bandwidth for each transfer on its own
+    pub transfer_rate: RateLimit,
+    pub accept: AcceptPolicy,
+    pub hooks: Vec<Hook>,
+    pub sync: Vec<SyncProfile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            alias: None,
+            downloads: None,
+            port: 0,
+            transport: TransportPreference::Auto,
+            ciphers: CipherSuite::preferred(),
+            relay: None,
+            compress: true,
+            keep_partial: KeepPartial::Remove,
+            rate: RateLimit::UNLIMITED,
+            transfer_rate: RateLimit::UNLIMITED,
+            accept: AcceptPolicy::default(),
+            hooks: Vec::new(),
+            sync: Vec::new(),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/globalsend/config.toml`, `~/.config/…`, or `%APPDATA%\globalsend\config.toml`
+pub fn default_path() -> PathBuf {
+    if let Some(dir) = std::env::var_os("APPDATA").filter(|_| cfg!(windows)) {
+        return PathBuf::from(dir).join("globalsend").join("config.toml");
+    }
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("globalsend").join("config.toml"),
+        _ => paths::home().join(".config").join("globalsend").join("config.toml"),
+    }
+}
+
+impl Config {
+    /// Defaults, then the file at `path` or the default one, then the environment.
+    /// Only a missing default file is fine; a missing `path` is an error
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let file = path.map_or_else(default_path, Path::to_owned);
+        match std::fs::read_to_string(&file) {
+            Ok(text) => config.apply_toml(&file, &text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && path.is_none() => {}
+            Err(error) => return Err(ConfigError::Io { path: file, error }),
+        }
+        config.apply_env(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    fn apply_toml(&mut self, path: &Path, text: &str) -> Result<(), ConfigError> {
+        let table: Table = toml::from_str(text).map_err(|e| ConfigError::Syntax { path: path.to_owned(), message: e.to_string() })?;
+        let source = path.display().to_string();
+        for (key, value) in &table {
+            match key.as_str() {
+                "accept" => {
+                    let Value::Array(rules) = value else { return Err(invalid(&source, key, "expected [[accept]] tables")) };
+                    for (i, rule) in rules.iter().enumerate() {
+                        let at = format!("accept[{i}]");
+                        let Value::Table(rule) = rule else { return Err(invalid(&source, &at, "expected a table")) };
+                        let rule = parse_rule(rule).map_err(|(key, message)| invalid(&source, &format!("{at}.{key}"), message))?;
+                        self.accept.push(rule);
+                    }
+                }
+                "hooks" => {
+                    let Value::Array(hooks) = value else { return Err(invalid(&source, key, "expected [[hooks]] tables")) };
+                    for (i, hook) in hooks.iter().enumerate() {
+                        let at = format!("hooks[{i}]");
+                        let Value::Table(hook) = hook else { return Err(invalid(&source, &at, "expected a table")) };
+                        let hook = parse_hook(hook).map_err(|(key, message)| invalid(&source, &format!("{at}.{key}"), message))?;
+                        self.hooks.push(hook);
+                    }
+                }
+                "sync" => {
+                    let Value::Array(profiles) = value else { return Err(invalid(&source, key, "expected [[sync]] tables")) };
+                    for (i, profile) in profiles.iter().enumerate() {
+                        let at = format!("sync[{i}]");
+                        let Value::Table(profile) = profile else { return Err(invalid(&source, &at, "expected a table")) };
+                        let profile = parse_sync(profile).map_err(|(key, message)| invalid(&source, &format!("{at}.{key}"), message))?;
+                        if self.sync.iter().any(|p| p.folder == profile.folder) {
+                            return Err(invalid(&source, &format!("{at}.folder"), format!("folder {:?} is already synced", profile.folder)));
+                        }
+                        self.sync.push(profile);
+                    }
+                }
+                _ => self.set(key, value).map_err(|message| invalid(&source, key, message))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the `GLOBALSEND_*` variables `var` returns
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        for (name, key) in ENV {
+            let Some(text) = var(name) else { continue };
+            // a port is a number in the file, but everything is a string here
+            let value = match key {
+                "port" => Value::Integer(text.trim().parse().map_err(|_| invalid(name, key, "expected a port number"))?),
+                _ => Value::String(text),
+            };
+            self.set(key, &value).map_err(|message| invalid(name, key, message))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match key {
+            "alias" => self.alias = Some(non_empty(value)?.to_owned()),
+            "downloads" => self.downloads = Some(path(value)?),
+            "port" => self.port = int(value).and_then(|port| u16::try_from(port).map_err(|_| "expected a port number, 0 to 65535".to_owned()))?,
+            "transport" => {
+                self.transport = match string(value)? {
+                    "auto" => TransportPreference::Auto,
+                    "quic" => TransportPreference::QuicOnly,
+                    "tcp" => TransportPreference::TcpOnly,
+                    other => return Err(format!("unknown transport {other:?}; expected auto, quic or tcp")),
+                }
+            }
+            "ciphers" => {
+                let names = strings(value)?;
+                let mut ciphers = Vec::with_capacity(names.len());
+                for name in names {
+                    let suite = CipherSuite::ALL.into_iter().find(|s| s.name().eq_ignore_ascii_case(&name)).ok_or_else(|| format!("unknown cipher {name:?}; expected one of {}", cipher_names()))?;
+                    if !ciphers.contains(&suite) {
+                        ciphers.push(suite);
+                    }
+                }
+                if ciphers.is_empty() {
+                    return Err("allow at least one cipher".into());
+                }
+                self.ciphers = ciphers;
+            }
+            "relay" => {
+                let relay = non_empty(value)?;
+                match relay.rsplit_once(':') {
+                    Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => self.relay = Some(relay.to_owned()),
+                    _ => return Err(format!("{relay:?} is not host:port")),
+                }
+            }
+            "compress" => self.compress = value.as_bool().ok_or_else(|| format!("expected true or false, found {}", value.type_str()))?,
+            "keep_partial" => {
+                self.keep_partial = match non_empty(value)? {
+                    "remove" => KeepPartial::Remove,
+                    "keep" => KeepPartial::Keep,
+                    _ => KeepPartial::Quarantine(path(value)?),
+                }
+            }
+            "max_up" => self.rate.max_up = rate(value)?,
+            "max_down" => self.rate.max_down = rate(value)?,
+            "transfer_max_up" => self.transfer_rate.max_up = rate(value)?,
+            "transfer_max_down" => self.transfer_rate.max_down = rate(value)?,
+            _ => return Err("unknown key".into()),
+        }
+        Ok(())
+    }
+}
+
+/// Look up a `host:port` relay when it is needed
+pub async fn resolve_relay(relay: &str) -> Result<SocketAddr, CliError> {
+    let mut addrs = tokio::net::lookup_host(relay).await.map_err(|e| CliError::Failed(format!("relay {relay}: {e}")))?;
+    addrs.next().ok_or_else(|| CliError::Failed(format!("relay {relay} has no address")))
+}
+
+fn invalid(source: &str, key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { source: source.to_owned(), key: key.to_owned(), message: message.into() }
+}
+
+/// Errors carry the key inside the rule
+fn parse_rule(table: &Table) -> Result<AcceptRule, (String, String)> {
+    let field = |key: &str| table.get(key);
+    let name = match field("name") {
+        Some(value) => non_empty(value).map_err(|m| ("name".to_owned(), m))?.to_owned(),
+        None => return Err(("name".into(), "every rule needs a name".into())),
+    };
+    let action = match field("action").map(string) {
+        Some(Ok("accept")) => Action::Accept,
+        Some(Ok("decline")) => Action::Decline,
+        Some(Ok(other)) => return Err(("action".into(), format!("unknown action {other:?}; expected accept or decline"))),
+        Some(Err(message)) => return Err(("action".into(), message)),
+        None => return Err(("action".into(), "every rule needs an action".into())),
+    };
+    let mut rule = AcceptRule::new(name, action);
+    for (key, value) in table {
+        let parsed = match key.as_str() {
+            "name" | "action" => Ok(()),
+            "device" => fingerprint(value).map(|fp| rule.device = Some(fp)),
+            "types" => strings(value).map(|types| rule.file_types = types),
+            "max_file_size" => size(value).map(|size| rule.max_file_size = Some(size)),
+            "max_total_size" => size(value).map(|size| rule.max_total_size = Some(size)),
+            "max_files" => int(value).and_then(|n| usize::try_from(n).map_err(|_| "expected a count".to_owned())).map(|n| rule.max_files = Some(n)),
+            "destination" => path(value).map(|path| rule.destination = Some(path)),
+            _ => Err("unknown key".into()),
+        };
+        parsed.map_err(|message| (key.clone(), message))?;
+    }
+    Ok(rule)
+}
+
+fn parse_hook(table: &Table) -> Result<Hook, (String, String)> {
+    let mut events = Vec::new();
+    let mut action = None;
+    for (key, value) in table {
+        let parsed = match key.as_str() {
+            "events" => strings(value).and_then(|names| {
+                for name in names {
+                    let event = HookEvent::from_name(&name).ok_or_else(|| format!("unknown event {name:?}; expected offer-received, transfer-complete or verification-failed"))?;
+                    if !events.contains(&event) {
+                        events.push(event);
+                    }
+                }
+                Ok(())
+            }),
+            "command" => strings(value).and_then(|argv| {
+                if argv.is_empty() {
+                    return Err("may not be empty".into());
+                }
+                action = Some(HookAction::Command(argv));
+                Ok(())
+            }),
+            "url" => non_empty(value).and_then(|url| {
+                if !(url.starts_with("http://") || url.starts_with("https://")) {
+                    return Err(format!("{url:?} is not an http or https URL"));
+                }
+                action = Some(HookAction::Webhook(url.to_owned()));
+                Ok(())
+            }),
+            _ => Err("unknown key".into()),
+        };
+        parsed.map_err(|message| (key.clone(), message))?;
+    }
+    if table.contains_key("command") && table.contains_key("url") {
+        return Err(("url".into(), "a hook has a command or a url, not both".into()));
+    }
+    let action = action.ok_or_else(|| ("command".to_owned(), "every hook needs a command or a url".to_owned()))?;
+    if events.is_empty() {
+        return Err(("events".into(), "every hook needs at least one event".into()));
+    }
+    Ok(Hook::new(events, action))
+}
+
+fn parse_sync(table: &Table) -> Result<SyncProfile, (String, String)> {
+    let required = |key: &str| table.get(key).ok_or_else(|| (key.to_owned(), "every sync folder needs one".to_owned()));
+    let folder = non_empty(required("folder")?).map_err(|m| ("folder".to_owned(), m))?.to_owned();
+    let path = path(required("path")?).map_err(|m| ("path".to_owned(), m))?;
+    let peer = fingerprint(required("device")?).map_err(|m| ("device".to_owned(), m))?;
+    let mut profile = SyncProfile { folder, path, peer, addr: None, interval: None };
+    for (key, value) in table {
+        let parsed = match key.as_str() {
+            "folder" | "path" | "device" => Ok(()),
+            "addr" => string(value).and_then(|addr| addr.parse().map_err(|_| format!("{addr:?} is not ip:port"))).map(|addr| profile.addr = Some(addr)),
+            "interval" => int(value).and_then(|secs| if secs == 0 { Err("may not be 0".into()) } else { Ok(secs as u64) }).map(|secs| profile.interval = Some(Duration::from_secs(secs))),
+            _ => Err("unknown key".into()),
+        };
+        parsed.map_err(|message| (key.clone(), message))?;
+    }
+    Ok(profile)
+}
+
+fn fingerprint(value: &Value) -> Result<Fingerprint, String> {
+    string(value).and_then(|hex| Fingerprint::from_hex(&hex.to_ascii_lowercase()).ok_or_else(|| "expected a 64 digit fingerprint".to_owned()))
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or_else(|| format!("expected a string, found {}", value.type_str()))
+}
+
+fn non_empty(value: &Value) -> Result<&str, String> {
+    string(value).and_then(|s| if s.trim().is_empty() { Err("may not be empty".into()) } else { Ok(s) })
+}
+
+fn strings(value: &Value) -> Result<Vec<String>, String> {
+    let Value::Array(items) = value else { return Err(format!("expected a list of strings, found {}", value.type_str())) };
+    items.iter().map(|item| string(item).map(str::to_owned)).collect()
+}
+
+fn int(value: &Value) -> Result<i64, String> {
+    match value {
+        Value::Integer(n) if *n >= 0 => Ok(*n),
+        Value::Integer(_) => Err("may not be negative".into()),
+        _ => Err(format!("expected a number, found {}", value.type_str())),
+    }
+}
+
+/// A leading `~/` is the home directory
+fn path(value: &Value) -> Result<PathBuf, String> {
+    let path = non_empty(value)?;
+    Ok(match path.strip_prefix("~/") {
+        Some(rest) => paths::home().join(rest),
+        None => PathBuf::from(path),
+    })
+}
+
+/// Bytes, or a string like `50MB`, `1.5 GiB` or `800k`
+fn size(value: &Value) -> Result<u64, String> {
+    if let Value::Integer(_) = value {
+        return int(value).map(|n| n as u64);
+    }
+    let text = string(value)?.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let bad = || format!("{text:?} is not a size like \"50MB\"");
+    let number: f64 = number.parse().map_err(|_| bad())?;
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1_000_000,
+        "mib" => 1 << 20,
+        "g" | "gb" => 1_000_000_000,
+        "gib" => 1 << 30,
+        "t" | "tb" => 1_000_000_000_000,
+        "tib" => 1 << 40,
+        _ => return Err(bad()),
+    };
+    Ok((number * scale as f64) as u64)
+}
+
+/// Bytes a second as a [`size`]; 0 is unlimited
+fn rate(value: &Value) -> Result<Option<u64>, String> {
+    size(value).map(|rate| Some(rate).filter(|&r| r > 0))
+}
+
+/// `--max-up` and `--max-down`: bytes a second as in the file
+pub fn parse_rate(text: &str) -> Result<u64, String> {
+    size(&Value::String(text.into()))
+}
+
+fn cipher_names() -> String {
+    CipherSuite::ALL.iter().map(|s| s.name().to_ascii_lowercase()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        config.apply_toml(Path::new("config.toml"), text).map(|()| config)
+    }
+
+    #[test]
+    fn layers_and_errors_name_the_key() {
+        let mut config = parse(
+            r#"
+            alias = "nas"
+            port = 53317
+            transport = "tcp"
+            ciphers = ["xchacha20poly1305"]
+            relay = "relay.example.org:7000"
+            compress = false
+            keep_partial = "/srv/partial"
+            max_up = "1.5MB"
+            transfer_max_down = 300000
+
+            [[accept]]
+            name = "photos"
+            action = "accept"
+            types = ["image/*"]
+            max_file_size = "1.5MiB"
+            destination = "/srv/photos"
+
+            [[hooks]]
+            events = ["transfer-complete", "verification-failed"]
+            command = ["notify-send", "globalsend"]
+
+            [[sync]]
+            folder = "notes"
+            path = "/srv/notes"
+            device = "ABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABABAB"
+            interval = 600
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.alias.as_deref(), Some("nas"));
+        assert_eq!(config.transport, TransportPreference::TcpOnly);
+        assert_eq!(config.ciphers, [CipherSuite::XChaCha20Poly1305]);
+        assert!(!config.compress);
+        assert_eq!(config.keep_partial, KeepPartial::Quarantine("/srv/partial".into()));
+        assert_eq!((config.rate, config.transfer_rate), (RateLimit { max_up: Some(1_500_000), max_down: None }, RateLimit { max_up: None, max_down: Some(300_000) }));
+        let rule = &config.accept.rules()[0];
+        assert_eq!((rule.action, rule.max_file_size), (Action::Accept, Some(1_572_864)));
+        assert_eq!(rule.destination.as_deref(), Some(Path::new("/srv/photos")));
+        assert_eq!(config.hooks, [Hook::new(vec![HookEvent::TransferComplete, HookEvent::VerificationFailed], HookAction::Command(vec!["notify-send".into(), "globalsend".into()]))]);
+
+        let notes = &config.sync[0];
+        assert_eq!((notes.folder.as_str(), notes.path.as_path(), notes.peer, notes.interval), ("notes", Path::new("/srv/notes"), Fingerprint::from_bytes([0xab; 32]), Some(Duration::from_secs(600))));
+
+        config.apply_env(|name| (name == "GLOBALSEND_PORT").then(|| "4000".into())).unwrap();
+        assert_eq!((config.port, config.alias.as_deref()), (4000, Some("nas")));
+        let e = config.apply_env(|name| (name == "GLOBALSEND_TRANSPORT").then(|| "carrier pigeon".into())).unwrap_err();
+        assert!(e.to_string().starts_with("GLOBALSEND_TRANSPORT: transport: unknown transport"), "{e}");
+
+        for (text, at) in [
+            ("prot = 1", "config.toml: prot: unknown key"),
+            ("port = 70000", "config.toml: port: expected a port number"),
+            ("ciphers = [\"rot13\"]", "config.toml: ciphers: unknown cipher"),
+            ("relay = \"nowhere\"", "config.toml: relay: \"nowhere\" is not host:port"),
+            ("max_down = \"fast\"", "config.toml: max_down: \"fast\" is not a size"),
+            ("compress = \"no\"", "config.toml: compress: expected true or false"),
+            ("keep_partial = \"\"", "config.toml: keep_partial: may not be empty"),
+            ("[[accept]]\nname = \"x\"\naction = \"accept\"\nmax_files = \"ten\"", "config.toml: accept[0].max_files: expected a number"),
+            ("[[accept]]\nname = \"x\"", "config.toml: accept[0].action: every rule needs an action"),
+            ("[[hooks]]\nevents = [\"done\"]\nurl = \"https://x\"", "config.toml: hooks[0].events: unknown event \"done\""),
+            ("[[hooks]]\nevents = [\"offer-received\"]\nurl = \"ftp://x\"", "config.toml: hooks[0].url: \"ftp://x\" is not an http"),
+            ("[[sync]]\nfolder = \"x\"\npath = \"/x\"", "config.toml: sync[0].device: every sync folder needs one"),
+        ] {
+            let e = parse(text).unwrap_err().to_string();
+            assert!(e.starts_with(at), "{e}");
+        }
+        assert!(matches!(parse("alias = "), Err(ConfigError::Syntax { .. })));
+    }
+}
@@ -0,0 +1,118 @@
+//! Naming devices on the command line, and `globalsend devices`
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use globalsend_crypto::device_id::DeviceId;
+use globalsend_crypto::identity::Fingerprint;
+use globalsend_crypto::trust::TrustStore;
+use globalsend_daemon::rpc::DeviceInfo;
+use globalsend_discovery::MdnsDiscovery;
+use tokio::time::{sleep, Instant};
+
+use crate::error::CliError;
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// A device as the user named it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceName {
+    /// Reachable there; no discovery needed
+    Addr(SocketAddr),
+    Fingerprint(Fingerprint),
+    /// Any case; must be unambiguous
+    Alias(String),
+}
+
+impl DeviceName {
+    pub fn parse(s: &str) -> Self {
+        if let Ok(addr) = s.parse() {
+            DeviceName::Addr(addr)
+        } else if let Some(fingerprint) = parse_fingerprint(s) {
+            DeviceName::Fingerprint(fingerprint)
+        } else {
+            DeviceName::Alias(s.to_owned())
+        }
+    }
+
+    fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            DeviceName::Addr(addr) => device.addrs.contains(addr),
+            DeviceName::Fingerprint(fingerprint) => parse_fingerprint(&device.fingerprint).as_ref() == Some(fingerprint),
+            DeviceName::Alias(alias) => device.alias.eq_ignore_ascii_case(alias),
+        }
+    }
+}
+
+/// Wait up to `wait` for `seen` to list the device `name` refers to
+pub async fn find(name: &DeviceName, wait: Duration, seen: impl Fn() -> Vec<DeviceInfo>) -> Result<DeviceInfo, CliError> {
+    let deadline = Instant::now() + wait;
+    loop {
+        let mut found: Vec<_> = seen().into_iter().filter(|d| name.matches(d)).collect();
+        match found.len() {
+            0 if Instant::now() >= deadline => {
+                return Err(CliError::Failed(format!("no device {} found nearby; `globalsend devices` lists what discovery sees", label(name))));
+            }
+            0 => sleep(POLL).await,
+            1 => return Ok(found.remove(0)),
+            _ => {
+                let fingerprints: Vec<_> = found.iter().map(|d| d.fingerprint.as_str()).collect();
+                return Err(CliError::Failed(format!("several devices are called {}; pick one by fingerprint: {}", label(name), fingerprints.join(", "))));
+            }
+        }
+    }
+}
+
+/// A fingerprint in hex or as a device ID, any case
+pub fn parse_fingerprint(s: &str) -> Option<Fingerprint> {
+    Fingerprint::from_hex(&s.to_ascii_lowercase()).or_else(|| DeviceId::parse(s).ok().map(|id| *id.fingerprint()))
+}
+
+fn label(name: &DeviceName) -> String {
+    match name {
+        DeviceName::Addr(addr) => addr.to_string(),
+        DeviceName::Fingerprint(fingerprint) => fingerprint.to_hex(),
+        DeviceName::Alias(alias) => format!("{alias:?}"),
+    }
+}
+
+/// Whether `fingerprint` was pinned by `globalsend pair`, under any name
+pub fn paired(trust: &TrustStore, fingerprint: &Fingerprint) -> bool {
+    trust.iter().any(|(_, peer)| peer.fingerprint == *fingerprint)
+}
+
+/// `globalsend devices`: list what discovery sees within `wait`
+pub async fn run(ours: &Fingerprint, trust: &TrustStore, wait: Duration) -> Result<bool, CliError> {
+    let mdns = MdnsDiscovery::browse(ours)?;
+    sleep(wait).await;
+    let devices: Vec<DeviceInfo> = mdns.devices().borrow().values().map(DeviceInfo::from).collect();
+    if devices.is_empty() {
+        eprintln!("no devices found");
+    }
+    for device in devices {
+        let addrs: Vec<_> = device.addrs.iter().map(ToString::to_string).collect();
+        let paired = if parse_fingerprint(&device.fingerprint).is_some_and(|fingerprint| paired(trust, &fingerprint)) { "paired" } else { "" };
+        println!("{:<24} {}  {:<6} {}", device.alias, &device.fingerprint[..16], paired, addrs.join(" "));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_names() {
+        assert_eq!(DeviceName::parse("192.168.1.7:53317"), DeviceName::Addr("192.168.1.7:53317".parse().unwrap()));
+        let fingerprint = Fingerprint::from_bytes([0xab; 32]);
+        assert_eq!(DeviceName::parse(&fingerprint.to_hex().to_uppercase()), DeviceName::Fingerprint(fingerprint));
+        assert_eq!(DeviceName::parse(&DeviceId::from(fingerprint).to_string().to_lowercase()), DeviceName::Fingerprint(fingerprint));
+        assert_eq!(DeviceName::parse("Laptop"), DeviceName::Alias("Laptop".into()));
+
+        let device = DeviceInfo { fingerprint: fingerprint.to_hex(), alias: "laptop".into(), version: 7, addrs: vec!["10.0.0.2:1".parse().unwrap()] };
+        assert!(DeviceName::parse("LAPTOP").matches(&device));
+        assert!(DeviceName::Fingerprint(fingerprint).matches(&DeviceInfo { fingerprint: fingerprint.to_hex().to_uppercase(), ..device.clone() }));
+        assert!(DeviceName::parse("10.0.0.2:1").matches(&device));
+        assert!(!DeviceName::parse("phone").matches(&device));
+    }
+}
@@ -0,0 +1,143 @@
+//! `globalsend doctor`: what to run first when "it just doesn't work"
+//!
+//! Runs the crypto self test, then checks what this device needs to find
+//! and reach others: a writable data directory, a readable identity and
+//! list of known devices, the transfer port, mDNS and the multicast group.
+//! Each check prints one line, and each failure a hint at what to do about
+//! it. Nothing is changed; a missing identity is reported, not made.
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_crypto::selftest::{self, SelfTestError};
+use globalsend_crypto::trust::TrustStore;
+use globalsend_discovery::multicast::{self, MULTICAST_GROUP, MULTICAST_PORT};
+use globalsend_discovery::MdnsDiscovery;
+use globalsend_transport::Listener;
+
+use crate::paths::Paths;
+
+/// How long the multicast probe waits for its datagram to come back
+const PROBE_WAIT: Duration = Duration::from_secs(2);
+
+/// Run every check and print the results; true if all passed
+pub async fn run(paths: &Paths, port: u16) -> bool {
+    let mut report = Report { failed: 0 };
+
+    for check in selftest::self_test() {
+        let hint = match check.result {
+            Err(SelfTestError::Rng(_)) => "the operating system's random number generator is failing; do not pair or send from this device until it is fixed",
+            _ => "this build computes wrong results; reinstall globalsend and, if it persists, report your platform and `globalsend --version`",
+        };
+        report.check(&format!("crypto {}", check.name), check.result.map(|()| "passed".to_owned()).map_err(|e| e.to_string()), hint);
+    }
+
+    report.check("data directory", writable(&paths.data), "pass --data-dir or set GLOBALSEND_DATA_DIR to a directory this user can write");
+    let identity = identity(&paths.identity());
+    let fingerprint = identity.as_ref().ok().and_then(|(_, fingerprint)| *fingerprint);
+    report.check(
+        "identity",
+        identity.map(|(detail, _)| detail),
+        "restore the identity file from a backup, or move it away to make a new one; paired devices will then have to pair again",
+    );
+    let trust = TrustStore::open(paths.trust()).map(|trust| format!("{} paired devices", trust.iter().count()));
+    report.check("known devices", trust.map_err(|e| format!("{}: {e}", paths.trust().display())), "fix the line it names, or move the file away and pair again");
+
+    let listener = Listener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await;
+    report.check(
+        "port",
+        listener.map(|_| format!("{port} free for QUIC and TCP")).map_err(|e| format!("{port}: {e}")),
+        "something else holds the port, perhaps a running `globalsend daemon` or `receive`; stop it or set `port` in the config",
+    );
+
+    let fingerprint = fingerprint.unwrap_or_else(|| DeviceIdentity::generate().fingerprint());
+    report.check(
+        "mdns",
+        MdnsDiscovery::browse(&fingerprint).map(|_| "started".to_owned()).map_err(|e| e.to_string()),
+        "no usable network interface, or UDP port 5353 is taken or blocked; devices can still be reached by address",
+    );
+    let probe = match multicast::probe(MULTICAST_PORT, PROBE_WAIT).await {
+        Ok(true) => Ok("a probe came back".to_owned()),
+        Ok(false) => Err(format!("nothing came back from {MULTICAST_GROUP}:{MULTICAST_PORT} in {}s", PROBE_WAIT.as_secs())),
+        Err(e) => Err(e.to_string()),
+    };
+    report.check("multicast", probe, "a firewall drops multicast or the interface has none; allow UDP to that group, or rely on mDNS");
+
+    if report.failed > 0 {
+        println!("{} check(s) failed", report.failed);
+    }
+    report.failed == 0
+}
+
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, result: Result<String, String>, hint: &str) {
+        match result {
+            Ok(detail) => println!("ok    {name}: {detail}"),
+            Err(error) => {
+                self.failed += 1;
+                println!("FAIL  {name}: {error}");
+                println!("      {hint}");
+            }
+        }
+    }
+}
+
+/// Make `dir` if need be and write a file in it
+fn writable(dir: &Path) -> Result<String, String> {
+    let probe = dir.join(".doctor");
+    let result = fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"")).and_then(|()| fs::remove_file(&probe));
+    result.map(|()| dir.display().to_string()).map_err(|e| format!("{}: {e}", dir.display()))
+}
+
+/// Whether the identity at `path` loads and only this user can read it;
+/// the fingerprint if it loads
+fn identity(path: &Path) -> Result<(String, Option<Fingerprint>), String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(("none yet; one is made on first use".into(), None)),
+        Err(e) => return Err(format!("{}: {e}", path.display())),
+    };
+    let identity = DeviceIdentity::from_versioned_bytes(&bytes).map_err(|e| format!("{}: {e}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path).map_err(|e| e.to_string())?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(format!("{} is readable by other users (mode {:o}); run `chmod 600` on it", path.display(), mode & 0o777));
+        }
+    }
+    Ok((identity.fingerprint().to_hex(), Some(identity.fingerprint())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_must_parse_and_stay_private() {
+        let dir = std::env::temp_dir().join(format!("globalsend-doctor-{}", std::process::id()));
+        let path = dir.join("identity");
+        assert_eq!(identity(&path).unwrap().1, None);
+
+        let made = crate::identity::load_or_create(&path).unwrap();
+        assert_eq!(identity(&path).unwrap().1, Some(made.fingerprint()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(identity(&path).unwrap_err().contains("chmod 600"));
+        }
+
+        fs::write(&path, b"not a key").unwrap();
+        assert!(identity(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
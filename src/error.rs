@@ -0,0 +1,91 @@
+//! What can stop a command
+
+use std::fmt;
+use std::io;
+
+use globalsend_crypto::encoding::KeyFormatError;
+use globalsend_crypto::pairing::PairingError;
+use globalsend_crypto::ssh::SshKeyError;
+use globalsend_crypto::trust::TrustStoreError;
+use globalsend_crypto::CryptoError;
+use globalsend_daemon::rpc::RpcError;
+use globalsend_daemon::DaemonError;
+use globalsend_discovery::DiscoveryError;
+use globalsend_proto::ProtoError;
+use globalsend_store::StoreError;
+use globalsend_transport::connect::ConnectError;
+use globalsend_transport::CodecError;
+
+use crate::config::ConfigError;
+
+#[derive(Debug)]
+pub enum CliError {
+    Io(io::Error),
+    Daemon(DaemonError),
+    Rpc(RpcError),
+    Connect(ConnectError),
+    Discovery(DiscoveryError),
+    Codec(CodecError),
+    Proto(ProtoError),
+    Crypto(CryptoError),
+    Store(StoreError),
+    Config(ConfigError),
+    Trust(TrustStoreError),
+    /// The identity file is damaged or from a newer version
+    Key(KeyFormatError),
+    Pairing(PairingError),
+    Ssh(SshKeyError),
+    /// Anything else, already worded for the user
+    Failed(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Io(e) => write!(f, "{e}"),
+            CliError::Daemon(e) => write!(f, "{e}"),
+            CliError::Rpc(e) => write!(f, "{}", e.message),
+            CliError::Connect(e) => write!(f, "connection failed: {e}"),
+            CliError::Discovery(e) => write!(f, "discovery failed: {e}"),
+            CliError::Codec(e) => write!(f, "connection failed: {e}"),
+            CliError::Proto(e) => write!(f, "protocol error: {e}"),
+            CliError::Crypto(e) => write!(f, "{e}"),
+            CliError::Store(e) => write!(f, "history: {e}"),
+            CliError::Config(e) => write!(f, "{e}"),
+            CliError::Trust(e) => write!(f, "known devices: {e}"),
+            CliError::Key(e) => write!(f, "identity file: {e}"),
+            CliError::Pairing(e) => write!(f, "{e}"),
+            CliError::Ssh(e) => write!(f, "{e}"),
+            CliError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+macro_rules! from {
+    ($($variant:ident($error:ty)),* $(,)?) => {
+        $(impl From<$error> for CliError {
+            fn from(e: $error) -> Self {
+                CliError::$variant(e)
+            }
+        })*
+    };
+}
+
+from!(
+    Io(io::Error),
+    Daemon(DaemonError),
+    Rpc(RpcError),
+    Connect(ConnectError),
+    Discovery(DiscoveryError),
+    Codec(CodecError),
+    Proto(ProtoError),
+    Crypto(CryptoError),
+    Store(StoreError),
+    Config(ConfigError),
+    Trust(TrustStoreError),
+    Key(KeyFormatError),
+    Pairing(PairingError),
+    Ssh(SshKeyError),
+);
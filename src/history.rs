@@ -0,0 +1,69 @@
+//! `globalsend history`
+
+use std::path::Path;
+
+use globalsend_store::{HistoryQuery, HistoryStore, Outcome};
+use globalsend_transfer::Direction;
+use indicatif::HumanBytes;
+
+use crate::devices;
+use crate::error::CliError;
+
+/// Print the last `limit` transfers, newest first, optionally with one peer
+pub fn run(path: &Path, limit: usize, peer: Option<&str>) -> Result<bool, CliError> {
+    if !path.exists() {
+        eprintln!("no transfers yet");
+        return Ok(true);
+    }
+    let store = HistoryStore::open(path)?;
+    let mut query = HistoryQuery::recent(limit);
+    if let Some(peer) = peer {
+        query.peer = Some(devices::parse_fingerprint(peer).ok_or_else(|| CliError::Failed("--peer takes a fingerprint or device ID".into()))?);
+    }
+    for record in store.query(&query)? {
+        let towards = match record.direction {
+            Direction::Send => "to  ",
+            Direction::Receive => "from",
+        };
+        let outcome = match record.outcome {
+            Outcome::Completed => "completed",
+            Outcome::Declined => "declined",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Failed => "failed",
+        };
+        let names: Vec<_> = record.files.iter().map(|f| f.name.as_str()).collect();
+        println!("{}  {towards} {:<20} {outcome:<9} {:>10}  {}", date(record.finished_at), record.peer_name, HumanBytes(record.size()).to_string(), names.join(", "));
+        if let Some(error) = &record.error {
+            println!("{:16}  {error}", "");
+        }
+    }
+    Ok(true)
+}
+
+/// `2026-10-15 09:30`, UTC
+fn date(secs: u64) -> String {
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // civil-from-days, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", rem / 3600, rem % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01 00:00");
+        assert_eq!(date(951_782_400), "2000-02-29 00:00");
+        assert_eq!(date(1_792_056_600), "2026-10-15 09:30");
+    }
+}
@@ -0,0 +1,88 @@
+//! This device's long-term keys, kept in the data directory
+//!
+//! Normally made up on first use; [`import_ssh`] takes an existing OpenSSH
+//! Ed25519 key instead.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use globalsend_crypto::identity::DeviceIdentity;
+use globalsend_crypto::ssh::{self, SshKeyError};
+
+use crate::error::CliError;
+
+/// Read the identity at `path`, or make one and save it there
+pub fn load_or_create(path: &Path) -> Result<DeviceIdentity, CliError> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(DeviceIdentity::from_versioned_bytes(&bytes)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let identity = DeviceIdentity::generate();
+            save(path, &identity, false)?;
+            Ok(identity)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Make the OpenSSH key at `key` this device's identity, asking for its
+/// passphrase if it has one. Replacing an existing identity needs `force`:
+/// paired devices will not know the new one
+pub fn import_ssh(path: &Path, key: &Path, force: bool) -> Result<DeviceIdentity, CliError> {
+    if path.exists() && !force {
+        return Err(CliError::Failed(format!("{} already holds an identity; --force replaces it, and paired devices will have to pair again", path.display())));
+    }
+    let text = fs::read_to_string(key).map_err(|e| CliError::Failed(format!("{}: {e}", key.display())))?;
+    let identity = match ssh::from_openssh(&text, None) {
+        Err(SshKeyError::PassphraseRequired) => {
+            let passphrase = rpassword::prompt_password(format!("Passphrase for {}: ", key.display()))?;
+            ssh::from_openssh(&text, Some(passphrase.as_bytes()))?
+        }
+        result => result?,
+    };
+    save(path, &identity, force)?;
+    Ok(identity)
+}
+
+/// Write `identity` readable by this user only; `replace` allows an existing file
+fn save(path: &Path, identity: &DeviceIdentity, replace: bool) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if replace {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(&identity.to_versioned_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn created_once_then_loaded() {
+        let dir = std::env::temp_dir().join(format!("globalsend-identity-{}", std::process::id()));
+        let path = dir.join("nested").join("identity");
+        let created = load_or_create(&path).unwrap();
+        let loaded = load_or_create(&path).unwrap();
+        assert_eq!(created.fingerprint(), loaded.fingerprint());
+        assert_eq!(created.exchange().public(), loaded.exchange().public());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::write(&path, b"garbage").unwrap();
+        assert!(matches!(load_or_create(&path), Err(CliError::Key(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,308 @@
-fn main() {
-    println!("Hello, world!");
+//! The `globalsend` command line
+//!
+//! Every command but `daemon` runs its own short-lived [`Daemon`] in
+//! process and drives it through the same calls the control socket offers.
+//! Keys, paired devices and history live in the data directory; settings
+//! come from [`config`], with the flags here taking precedence.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use globalsend_crypto::device_id::DeviceId;
+use globalsend_crypto::pairing::WormholeCode;
+use globalsend_crypto::ssh;
+use globalsend_crypto::trust::TrustStore;
+use globalsend_daemon::{Daemon, DaemonConfig};
+use globalsend_transport::secure;
+
+mod config;
+mod devices;
+mod doctor;
+mod error;
+mod history;
+mod identity;
+mod pair;
+mod paths;
+mod progress;
+mod receive;
+mod send;
+mod telemetry;
+mod tui;
+mod watch;
+
+use crate::config::Config;
+use crate::devices::DeviceName;
+use crate::error::CliError;
+use crate::paths::Paths;
+
+#[derive(Parser)]
+#[command(name = "globalsend", version, about = "Send files between your devices")]
+struct Cli {
+    /// Settings file; defaults to config.toml in the user's config directory
+    #[arg(long, global = true, env = "GLOBALSEND_CONFIG")]
+    config: Option<PathBuf>,
+    /// Where keys, paired devices and history are kept
+    #[arg(long, global = true, env = "GLOBALSEND_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// Name other devices see; defaults to the config's alias, then the host name
+    #[arg(long, global = true)]
+    name: Option<String>,
+    /// Write a redacted transcript of each session's messages into this directory, for bug reports
+    #[arg(long, global = true)]
+    transcript: Option<PathBuf>,
+    /// Upload limit for all transfers together, in bytes a second like `2MB`; overrides the config's max_up
+    #[arg(long, global = true, value_parser = config::parse_rate)]
+    max_up: Option<u64>,
+    /// Download limit for all transfers together; overrides the config's max_down
+    #[arg(long, global = true, value_parser = config::parse_rate)]
+    max_down: Option<u64>,
+    /// Send and take chunks as they are, whatever the config says
+    #[arg(long, global = true)]
+    no_compress: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send files to a device nearby, an address, or whoever has a code
+    Send {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Device name, fingerprint or device ID, address, wormhole code, or `code` to make one up
+        #[arg(long)]
+        to: String,
+        /// Relay for wormhole codes, `host:port`
+        #[arg(long)]
+        relay: Option<String>,
+        /// Seconds to look for the device
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+    },
+    /// Send a file or folder again whenever it changes, until Ctrl-C
+    Watch {
+        path: PathBuf,
+        /// Device name, fingerprint or device ID, or address
+        #[arg(long)]
+        to: String,
+        /// Seconds to look for the device
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+    },
+    /// Wait for files, asking before taking each offer
+    Receive {
+        /// Where files go; defaults to the config's downloads, then ~/Downloads
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Meet a sender through the relay with the code it showed
+        #[arg(long)]
+        code: Option<String>,
+        #[arg(long)]
+        relay: Option<String>,
+        /// Accept offers without asking, except from a device whose key changed
+        #[arg(short, long)]
+        yes: bool,
+        /// Stop after one transfer
+        #[arg(long)]
+        once: bool,
+        #[arg(long)]
+        port: Option<u16>,
+        /// Serve Prometheus metrics on this address
+        #[arg(long)]
+        metrics: Option<SocketAddr>,
+    },
+    /// Pair with another device by comparing a code on both screens
+    Pair {
+        /// Device to reach out to; without one, wait to be reached
+        device: Option<String>,
+        #[arg(long)]
+        port: Option<u16>,
+        /// Seconds to look for the device
+        #[arg(long, default_value_t = 5)]
+        wait: u64,
+    },
+    /// List devices on the local network
+    Devices {
+        /// Seconds to listen for announcements
+        #[arg(long, default_value_t = 3)]
+        wait: u64,
+    },
+    /// Show past transfers, newest first
+    History {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Only transfers with the device with this fingerprint or device ID
+        #[arg(long)]
+        peer: Option<String>,
+    },
+    /// Full-screen view of devices, offers and progress, for terminals without a desktop
+    Tui {
+        /// Where accepted files go; defaults to the config's downloads, then ~/Downloads
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Use an OpenSSH Ed25519 key as this device's identity
+    ImportSsh {
+        /// Private key file
+        #[arg(default_value_os_t = paths::home().join(".ssh").join("id_ed25519"))]
+        key: PathBuf,
+        /// Replace an existing identity; paired devices will have to pair again
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the crypto, keys, port and network discovery, with hints for what fails
+    Doctor {
+        /// Port to check; defaults to the config's
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Keep running, take requests over the control socket and keep the `[[sync]]` folders in sync
+    Daemon {
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        port: Option<u16>,
+        /// Control socket; defaults to one in the runtime directory
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        #[arg(long)]
+        no_discovery: bool,
+        /// Serve Prometheus metrics on this address
+        #[arg(long)]
+        metrics: Option<SocketAddr>,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // log lines would tear up the full-screen view
+    if !matches!(cli.command, Command::Tui { .. }) {
+        telemetry::init_logging("warn");
+    }
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("globalsend: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(execute(cli)) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("globalsend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run the command; false when it ran but did not get what was asked for
+async fn execute(cli: Cli) -> Result<bool, CliError> {
+    if let Some(dir) = cli.transcript {
+        telemetry::record_transcripts(dir)?;
+    }
+    let mut settings = Config::load(cli.config.as_deref())?;
+    if let Some(rate) = cli.max_up {
+        settings.rate.max_up = Some(rate).filter(|&r| r > 0);
+    }
+    if let Some(rate) = cli.max_down {
+        settings.rate.max_down = Some(rate).filter(|&r| r > 0);
+    }
+    if cli.no_compress {
+        settings.compress = false;
+    }
+    secure::offer_suites(Some(settings.ciphers.clone()));
+    let paths = Paths::new(cli.data_dir);
+    let alias = cli.name.or_else(|| settings.alias.clone()).unwrap_or_else(paths::device_name);
+    let config = |dir: Option<PathBuf>, port: Option<u16>| {
+        let downloads = dir.or_else(|| settings.downloads.clone()).unwrap_or_else(paths::default_downloads);
+        let mut config = DaemonConfig::new(alias.clone(), downloads);
+        config.listen = SocketAddr::from(([0, 0, 0, 0], port.unwrap_or(settings.port)));
+        config.transport = settings.transport;
+        config.history = Some(paths.history());
+        config.checkpoints = Some(paths.checkpoints());
+        config.hooks = settings.hooks.clone();
+        config.compress = settings.compress;
+        config.keep_partial = settings.keep_partial.clone();
+        config.rate = settings.rate;
+        config.transfer_rate = settings.transfer_rate;
+        config
+    };
+    match cli.command {
+        Command::Send { files, to, relay, wait } => {
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            let options = send::SendOptions { files, to: send::To::parse(&to), relay: relay.or(settings.relay.clone()), wait: Duration::from_secs(wait) };
+            // sending never needs a fixed port
+            send::run(identity, config(None, Some(0)), options).await
+        }
+        Command::Watch { path, to, wait } => {
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            watch::run(identity, config(None, Some(0)), path, DeviceName::parse(&to), Duration::from_secs(wait)).await
+        }
+        Command::Receive { dir, code, relay, yes, once, port, metrics } => {
+            if let Some(addr) = metrics {
+                telemetry::serve_metrics(addr)?;
+            }
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            let trust = TrustStore::open(paths.trust())?;
+            let code = code.map(|c| WormholeCode::parse(&c)).transpose()?;
+            let options = receive::ReceiveOptions { code, relay: relay.or(settings.relay.clone()), policy: settings.accept.clone(), yes, once };
+            receive::run(identity, config(dir, port), &trust, options).await
+        }
+        Command::Pair { device, port, wait } => {
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            let mut trust = TrustStore::open(paths.trust())?;
+            pair::run(identity, alias.clone(), port.unwrap_or(settings.port), device.as_deref().map(DeviceName::parse), Duration::from_secs(wait), &mut trust).await
+        }
+        Command::Devices { wait } => {
+            let identity = identity::load_or_create(&paths.identity())?;
+            let trust = TrustStore::open(paths.trust())?;
+            devices::run(&identity.fingerprint(), &trust, Duration::from_secs(wait)).await
+        }
+        Command::Tui { dir, port } => {
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            let trust = TrustStore::open(paths.trust())?;
+            tui::run(identity, config(dir, port), &trust).await
+        }
+        Command::History { limit, peer } => history::run(&paths.history(), limit, peer.as_deref()),
+        Command::ImportSsh { key, force } => {
+            let identity = identity::import_ssh(&paths.identity(), &key, force)?;
+            println!("Imported {}", key.display());
+            println!("  fingerprint  {}", identity.fingerprint().to_hex());
+            println!("  device id    {}", DeviceId::of(&identity.verifying_key()));
+            println!("  ssh          {}", ssh::ssh_fingerprint(&identity.verifying_key()));
+            Ok(true)
+        }
+        Command::Doctor { port } => Ok(doctor::run(&paths, port.unwrap_or(settings.port)).await),
+        Command::Daemon { dir, port, socket, no_discovery, metrics } => {
+            if let Some(addr) = metrics {
+                telemetry::serve_metrics(addr)?;
+            }
+            let identity = Arc::new(identity::load_or_create(&paths.identity())?);
+            let mut config = config(dir, port);
+            config.discovery = !no_discovery;
+            let trust = TrustStore::open(paths.trust())?;
+            if let Some(profile) = settings.sync.iter().find(|p| !trust.iter().any(|(_, peer)| peer.fingerprint == p.peer)) {
+                return Err(CliError::Failed(format!("sync folder {:?}: device {} is not paired; run `globalsend pair` first", profile.folder, &profile.peer.to_hex()[..16])));
+            }
+            config.sync = settings.sync.clone();
+            config.index = Some(paths.sync_index());
+            // only the daemon comes back to pick up what it left
+            config.outbox = Some(paths.outbox());
+            if let Some(socket) = socket {
+                config.socket = socket;
+            }
+            let socket = config.socket.clone();
+            let daemon = Daemon::start(identity.clone(), config).await?;
+            eprintln!("globalsend daemon {} on {}, control socket {}", identity.fingerprint().to_hex(), daemon.local_addr()?, socket.display());
+            daemon.run().await?;
+            Ok(true)
+        }
+    }
 }
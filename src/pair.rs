@@ -0,0 +1,182 @@
+//! `globalsend pair`: pin another device after comparing a short code
+//!
+//! The two sides swap hellos, [`IdentityProof`]s and [`PairRequest`]s over
+//! an authenticated connection and each shows a code derived from its
+//! handshake. If both screens show the same code nobody sits in between,
+//! and each side pins the other's fingerprint under the name it asked to be
+//! paired as. The proof is what ties that fingerprint to the connection: a
+//! device can only pin an identity that signed the handshake's key and hash.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use globalsend_crypto::certificate;
+use globalsend_crypto::fingerprint::sas;
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint, Signature, VerifyingKey};
+use globalsend_crypto::trust::TrustStore;
+use globalsend_daemon::rpc::DeviceInfo;
+use globalsend_discovery::{LocalDevice, MdnsDiscovery};
+use globalsend_proto::{negotiate, Capabilities, Hello, HelloCapabilities, IdentityProof, Message, PairRequest, VersionRange, MIN_SUPPORTED_VERSION};
+use globalsend_transfer::config::IDENTITY_VERSION;
+use globalsend_transport::connect::ControlChannel;
+use globalsend_transport::{Connection, Dialer, Listener, Peer};
+
+use crate::devices::{self, DeviceName};
+use crate::error::CliError;
+use crate::progress::confirm;
+
+/// Wait for a device to pair with us, or reach out to `device`; true once paired
+pub async fn run(identity: Arc<DeviceIdentity>, alias: String, port: u16, device: Option<DeviceName>, wait: Duration, trust: &mut TrustStore) -> Result<bool, CliError> {
+    let listener = Listener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    let conn = match device {
+        None => {
+            let local = LocalDevice { fingerprint: identity.fingerprint(), alias: alias.clone(), port: listener.local_addr()?.port() };
+            let _mdns = MdnsDiscovery::start(&local)?;
+            eprintln!("Waiting for the other device; run `globalsend pair {alias}` on it");
+            listener.accept().await?.handshake(identity.exchange()).await?
+        }
+        Some(name) => {
+            let addrs = match name {
+                DeviceName::Addr(addr) => vec![addr],
+                name => {
+                    let mdns = MdnsDiscovery::browse(&identity.fingerprint())?;
+                    let devices = mdns.devices();
+                    devices::find(&name, wait, || devices.borrow().values().map(DeviceInfo::from).collect()).await?.addrs
+                }
+            };
+            dial(&identity, &listener, &addrs).await?
+        }
+    };
+    let Connection { mut control, peer, .. } = conn;
+    let ours = PairRequest { device_name: alias.clone(), fingerprint: *identity.fingerprint().as_bytes(), exchange_key: identity.exchange().public().to_bytes() };
+    let (theirs, proven) = exchange(&mut control, &alias, ours, &identity, &peer).await?;
+    if theirs.exchange_key != peer.static_key.to_bytes() {
+        return Err(CliError::Failed("the other device asked to pin a key it did not connect with".into()));
+    }
+    if theirs.fingerprint != *proven.as_bytes() {
+        return Err(CliError::Failed("the other device asked to pin a fingerprint it did not prove".into()));
+    }
+
+    let code = sas(&peer.handshake_hash, &identity.exchange().public().to_bytes(), &theirs.exchange_key)?;
+    let emoji: Vec<_> = code.emoji().iter().map(|(emoji, name)| format!("{emoji} {name}")).collect();
+    let fingerprint = proven;
+    eprintln!("Pairing with {:?} ({})\n\n  {code}\n  {}\n", theirs.device_name, &fingerprint.to_hex()[..16], emoji.join("  "));
+    // the connection stays up while the user looks, so our request is not cut off
+    let same = tokio::task::block_in_place(|| confirm("Does the other device show the same code?"))?;
+    drop(control);
+    if !same {
+        eprintln!("Not paired");
+        return Ok(false);
+    }
+    trust.pin(&theirs.device_name, &fingerprint)?;
+    trust.save()?;
+    eprintln!("Paired with {:?}", theirs.device_name);
+    Ok(true)
+}
+
+async fn dial(identity: &DeviceIdentity, listener: &Listener, addrs: &[SocketAddr]) -> Result<Connection, CliError> {
+    let dialer = Dialer::new(identity.exchange(), listener.quic());
+    let mut last = None;
+    for addr in addrs {
+        match dialer.connect(*addr).await {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.map_or_else(|| CliError::Failed("the device has no address".into()), CliError::Connect))
+}
+
+/// Swap hellos, identity proofs, then pair requests; the other side's
+/// request and the fingerprint it proved for `session`
+///
+/// Pairing uses no optional features, so the capabilities later versions
+/// swap are none and theirs are ignored. Devices from before
+/// [`IDENTITY_VERSION`] cannot prove a fingerprint and cannot pair.
+async fn exchange(control: &mut ControlChannel, alias: &str, ours: PairRequest, identity: &DeviceIdentity, session: &Peer) -> Result<(PairRequest, Fingerprint), CliError> {
+    control.codec_mut().set_version(MIN_SUPPORTED_VERSION);
+    control.send(Hello { versions: VersionRange::CURRENT, device_name: alias.into(), fingerprint: *identity.fingerprint().as_bytes() }.into()).await?;
+    let Message::Hello(hello) = next(control).await? else {
+        return Err(CliError::Failed("the other device did not start with a hello".into()));
+    };
+    let version = negotiate(VersionRange::CURRENT, hello.versions)?;
+    if version < IDENTITY_VERSION {
+        return Err(CliError::Failed("the other device is too old to prove who it is; update globalsend on it".into()));
+    }
+    control.codec_mut().set_version(version);
+    control.send(HelloCapabilities { capabilities: Capabilities::NONE }.into()).await?;
+    let Message::HelloCapabilities(_) = next(control).await? else {
+        return Err(CliError::Failed("the other device did not send its capabilities".into()));
+    };
+    let signature = identity.prove_session(&session.handshake_hash);
+    control.send(IdentityProof { identity_key: identity.verifying_key().to_bytes(), signature: signature.to_bytes().to_vec() }.into()).await?;
+    let Message::IdentityProof(proof) = next(control).await? else {
+        return Err(CliError::Failed("the other device did not prove who it is".into()));
+    };
+    let proven = VerifyingKey::from_bytes(&proof.identity_key)
+        .ok()
+        .zip(Signature::from_slice(&proof.signature).ok())
+        .and_then(|(key, signature)| certificate::verify_session_proof(&key, &session.static_key, &session.handshake_hash, &signature).ok())
+        .filter(|proven| proven.as_bytes() == &hello.fingerprint)
+        .ok_or_else(|| CliError::Failed("the other device's identity proof does not match this connection".into()))?;
+    control.send(ours.into()).await?;
+    match next(control).await? {
+        Message::PairRequest(theirs) => Ok((theirs, proven)),
+        _ => Err(CliError::Failed("the other device is not pairing; run `globalsend pair` on both".into())),
+    }
+}
+
+async fn next(control: &mut ControlChannel) -> Result<Message, CliError> {
+    match control.next().await {
+        Some(message) => Ok(message?),
+        None => Err(CliError::Failed("the other device hung up".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_transport::connect::ConnectError;
+
+    fn request(alias: &str, identity: &DeviceIdentity) -> PairRequest {
+        PairRequest { device_name: alias.into(), fingerprint: *identity.fingerprint().as_bytes(), exchange_key: identity.exchange().public().to_bytes() }
+    }
+
+    async fn accept(listener: &Listener, identity: &DeviceIdentity) -> Result<Connection, ConnectError> {
+        listener.accept().await?.handshake(identity.exchange()).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_a_proven_fingerprint_is_paired() {
+        let (laptop, phone, mallory) = (DeviceIdentity::generate(), DeviceIdentity::generate(), DeviceIdentity::generate());
+        let listener = Listener::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = [listener.local_addr().unwrap()];
+
+        let (accepted, dialed) = tokio::join!(accept(&listener, &laptop), dial(&phone, &listener, &addr));
+        let (mut a, mut b) = (accepted.unwrap(), dialed.unwrap());
+        let (on_laptop, on_phone) = tokio::join!(
+            exchange(&mut a.control, "laptop", request("laptop", &laptop), &laptop, &a.peer),
+            exchange(&mut b.control, "phone", request("phone", &phone), &phone, &b.peer),
+        );
+        assert_eq!(on_laptop.unwrap().1, phone.fingerprint());
+        assert_eq!(on_phone.unwrap().1, laptop.fingerprint());
+
+        // mallory connects with its own key but claims the phone's fingerprint
+        let (accepted, dialed) = tokio::join!(accept(&listener, &laptop), dial(&mallory, &listener, &addr));
+        let (mut a, mut m) = (accepted.unwrap(), dialed.unwrap());
+        let spoof = async {
+            let control = &mut m.control;
+            control.codec_mut().set_version(MIN_SUPPORTED_VERSION);
+            control.send(Hello { versions: VersionRange::CURRENT, device_name: "phone".into(), fingerprint: *phone.fingerprint().as_bytes() }.into()).await.unwrap();
+            let _hello = next(control).await.unwrap();
+            control.codec_mut().set_version(globalsend_proto::PROTOCOL_VERSION);
+            control.send(HelloCapabilities { capabilities: Capabilities::NONE }.into()).await.unwrap();
+            let signature = mallory.prove_session(&m.peer.handshake_hash);
+            control.send(IdentityProof { identity_key: mallory.verifying_key().to_bytes(), signature: signature.to_bytes().to_vec() }.into()).await.unwrap();
+            control.send(request("phone", &phone).into()).await.unwrap();
+        };
+        let (on_laptop, ()) = tokio::join!(exchange(&mut a.control, "laptop", request("laptop", &laptop), &laptop, &a.peer), spoof);
+        assert!(matches!(on_laptop, Err(CliError::Failed(e)) if e.contains("does not match")));
+    }
+}
@@ -0,0 +1,70 @@
+//! Where the CLI keeps its state
+
+use std::path::PathBuf;
+
+/// The data directory and the files in it
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub data: PathBuf,
+}
+
+impl Paths {
+    /// `data`, or the platform's data directory
+    pub fn new(data: Option<PathBuf>) -> Self {
+        Self { data: data.unwrap_or_else(default_data_dir) }
+    }
+
+    pub fn identity(&self) -> PathBuf {
+        self.data.join("identity")
+    }
+
+    /// Fingerprints pinned by `globalsend pair`
+    pub fn trust(&self) -> PathBuf {
+        self.data.join("known_devices")
+    }
+
+    pub fn history(&self) -> PathBuf {
+        self.data.join("history.db")
+    }
+
+    /// What sync folders held when last synced
+    pub fn sync_index(&self) -> PathBuf {
+        self.data.join("sync-index.db")
+    }
+
+    /// Progress of receives cut off part way
+    pub fn checkpoints(&self) -> PathBuf {
+        self.data.join("checkpoints")
+    }
+
+    /// Sends the daemon had going when it stopped
+    pub fn outbox(&self) -> PathBuf {
+        self.data.join("outbox")
+    }
+}
+
+pub fn home() -> PathBuf {
+    std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(PathBuf::from).unwrap_or_default()
+}
+
+/// `$XDG_DATA_HOME/globalsend`, `~/.local/share/globalsend`, or `%APPDATA%\globalsend`
+pub fn default_data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("APPDATA").filter(|_| cfg!(windows)) {
+        return PathBuf::from(dir).join("globalsend");
+    }
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("globalsend"),
+        _ => home().join(".local").join("share").join("globalsend"),
+    }
+}
+
+pub fn default_downloads() -> PathBuf {
+    home().join("Downloads")
+}
+
+/// What other devices call this one unless `--name` says otherwise
+pub fn device_name() -> String {
+    let name = std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")).ok();
+    let name = name.or_else(|| std::fs::read_to_string("/etc/hostname").ok());
+    name.map(|n| n.trim().to_owned()).filter(|n| !n.is_empty()).unwrap_or_else(|| "globalsend".into())
+}
@@ -0,0 +1,91 @@
+//! Progress bars for the transfers an in-process daemon runs
+
+use std::collections::BTreeMap;
+use std::io;
+
+use globalsend_daemon::rpc::TransferInfo;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+
+const TEMPLATE: &str = "{prefix} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} {msg}";
+
+/// One bar per transfer, keyed by transfer id
+pub struct Bars {
+    multi: MultiProgress,
+    bars: BTreeMap<String, ProgressBar>,
+}
+
+impl Bars {
+    pub fn new() -> Self {
+        Self { multi: MultiProgress::new(), bars: BTreeMap::new() }
+    }
+
+    /// Bring the bar for `info` up to date; true once the transfer has ended
+    pub fn update(&mut self, info: &TransferInfo) -> bool {
+        let bar = self.bars.entry(info.transfer.clone()).or_insert_with(|| {
+            let bar = self.multi.add(ProgressBar::new(info.total));
+            bar.set_style(ProgressStyle::with_template(TEMPLATE).expect("valid template").progress_chars("=> "));
+            bar.set_prefix(label(info));
+            bar
+        });
+        if bar.is_finished() {
+            return true;
+        }
+        bar.set_length(info.total);
+        bar.set_position(info.bytes);
+        bar.set_message(info.state.clone());
+        if info.finished() {
+            bar.finish_with_message(info.state.clone());
+        }
+        info.finished()
+    }
+
+    /// Print above the bars
+    pub fn println(&self, line: impl AsRef<str>) {
+        // hidden when stderr is not a terminal, and then println is a no-op
+        if self.multi.is_hidden() {
+            eprintln!("{}", line.as_ref());
+        } else {
+            let _ = self.multi.println(line.as_ref());
+        }
+    }
+
+    /// [`confirm`] with the bars cleared
+    pub fn confirm(&self, question: &str) -> io::Result<bool> {
+        self.multi.suspend(|| confirm(question))
+    }
+}
+
+/// Ask a yes/no question on the terminal; anything but yes is no.
+/// Blocks, so call it from [`block_in_place`](tokio::task::block_in_place)
+pub fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{question} [y/N] ");
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(matches!(line.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// `to laptop: a.txt and 2 more`
+fn label(info: &TransferInfo) -> String {
+    let towards = if info.direction == "send" { "to" } else { "from" };
+    let first = info.files.first().map_or("", |f| f.name.as_str());
+    match info.files.len() {
+        0 | 1 => format!("{towards} {}: {first}", info.peer_name),
+        n => format!("{towards} {}: {first} and {} more", info.peer_name, n - 1),
+    }
+}
+
+/// Files of an offer, one per line, with sizes
+pub fn describe(info: &TransferInfo) -> String {
+    let mut text = String::new();
+    for file in &info.files {
+        text.push_str(&format!("  {:<40} {:>10}\n", file.name, HumanBytes(file.size).to_string()));
+        if file.thumbnail.is_some() {
+            text.push_str("    (has a thumbnail)\n");
+        }
+        for line in file.text.iter().flat_map(|t| t.lines()) {
+            text.push_str(&format!("    | {line}\n"));
+        }
+    }
+    text.push_str(&format!("  {} file(s), {}", info.files.len(), HumanBytes(info.total)));
+    text
+}
@@ -0,0 +1,179 @@
+//! `globalsend receive`
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_crypto::pairing::{Role, WormholeCode};
+use globalsend_crypto::trust::{TrustDecision, TrustStore};
+use globalsend_daemon::rpc::{parse_transfer_id, TransferInfo};
+use globalsend_daemon::{Daemon, DaemonConfig};
+use globalsend_proto::{OfferedFile, TransferOffer};
+use globalsend_transfer::policy::{guess_mime, AcceptPolicy, Decision};
+use globalsend_transport::Connection;
+use tokio::time::sleep;
+
+use crate::config::resolve_relay;
+use crate::devices::paired;
+use crate::error::CliError;
+use crate::progress::{describe, Bars};
+
+const POLL: Duration = Duration::from_millis(100);
+
+pub struct ReceiveOptions {
+    /// Meet a sender through the relay instead of waiting on the network
+    pub code: Option<WormholeCode>,
+    pub relay: Option<String>,
+    /// Rules from the config, tried before asking
+    pub policy: AcceptPolicy,
+    /// Accept without asking, except from a device whose key changed
+    pub yes: bool,
+    /// Stop after the first transfer
+    pub once: bool,
+}
+
+/// Take offers until interrupted, or one with `--once` or `--code`;
+/// true unless a transfer that was accepted did not complete
+pub async fn run(identity: Arc<DeviceIdentity>, mut config: DaemonConfig, trust: &TrustStore, options: ReceiveOptions) -> Result<bool, CliError> {
+    let once = options.once || options.code.is_some();
+    if options.code.is_some() {
+        config.discovery = false;
+    }
+    let (alias, downloads) = (config.alias.clone(), config.downloads.clone());
+    let daemon = Daemon::start(identity.clone(), config).await?;
+    match &options.code {
+        Some(code) => {
+            let relay = options.relay.as_deref().ok_or_else(|| CliError::Failed("receiving with a code needs --relay or a relay in the config".into()))?;
+            let relay = resolve_relay(relay).await?;
+            eprintln!("Waiting for the sender at {relay}");
+            let conn = Connection::wormhole(relay, code, Role::Responder, identity.exchange()).await?;
+            daemon.receive_over(conn);
+        }
+        None => {
+            eprintln!("Receiving as {alias:?} ({}) on {}; files go to {}", &identity.fingerprint().to_hex()[..16], daemon.local_addr()?, downloads.display());
+            tokio::spawn({
+                let daemon = daemon.clone();
+                async move { daemon.listen().await }
+            });
+        }
+    }
+
+    let mut bars = Bars::new();
+    // whether each offer we saw was accepted
+    let mut answered = BTreeMap::new();
+    let mut all_done = true;
+    loop {
+        for info in daemon.transfers() {
+            if info.waiting {
+                if !answered.contains_key(&info.transfer) {
+                    let accepted = answer(&daemon, &bars, trust, &info, &options)?;
+                    answered.insert(info.transfer.clone(), accepted);
+                }
+                continue;
+            }
+            let finished = match answered.get(&info.transfer) {
+                Some(true) => bars.update(&info),
+                Some(false) => info.finished(),
+                // failed before it got to an offer
+                None => false,
+            };
+            if finished && once {
+                return Ok(info.state == "done");
+            }
+            if finished && answered.get(&info.transfer) == Some(&true) && info.state != "done" {
+                all_done = false;
+            }
+        }
+        tokio::select! {
+            _ = sleep(POLL) => {}
+            Ok(()) = tokio::signal::ctrl_c() => {
+                for info in daemon.transfers().iter().filter(|t| !t.finished()) {
+                    let _ = daemon.cancel(&parse_transfer_id(&info.transfer).expect("daemon reports hex ids"));
+                }
+                return Ok(all_done);
+            }
+        }
+    }
+}
+
+/// Show an offer and accept or decline it; true if accepted
+fn answer(daemon: &Daemon, bars: &Bars, trust: &TrustStore, info: &TransferInfo, options: &ReceiveOptions) -> Result<bool, CliError> {
+    let transfer = parse_transfer_id(&info.transfer).expect("daemon reports hex ids");
+    let (status, changed, ruled) = judge(trust, &options.policy, info);
+    let offer = format!("{} ({}, {status}) wants to send you:\n{}", info.peer_name, info.peer.get(..16).unwrap_or(&info.peer), describe(info));
+    let (accepted, destination) = match ruled {
+        Decision::Accept { rule, destination } => {
+            bars.println(format!("{offer}\naccepted by rule {rule:?}"));
+            (true, destination)
+        }
+        Decision::Decline { rule } => {
+            bars.println(format!("{offer}\ndeclined by rule {rule:?}"));
+            (false, None)
+        }
+        Decision::Ask if options.yes && !changed => {
+            bars.println(format!("{offer}\naccepted"));
+            (true, None)
+        }
+        Decision::Ask => (tokio::task::block_in_place(|| bars.confirm(&format!("{offer}\nAccept?")))?, None),
+    };
+    // the offer may have been withdrawn while we asked
+    let answered = if accepted { daemon.accept(&transfer, destination) } else { daemon.decline(&transfer) };
+    Ok(accepted && answered.is_ok())
+}
+
+/// How the offer's sender stands with us, whether its key changed, and what the rules make of the offer
+fn judge(trust: &TrustStore, policy: &AcceptPolicy, info: &TransferInfo) -> (String, bool, Decision) {
+    let transfer = parse_transfer_id(&info.transfer).expect("daemon reports hex ids");
+    // a fingerprint the peer only claimed could be anyone's
+    let peer = Fingerprint::from_hex(&info.peer).filter(|_| info.peer_verified);
+    let decision = peer.map(|fp| trust.check(&info.peer_name, &fp));
+    let status = match decision {
+        Some(TrustDecision::Known) => "paired".to_owned(),
+        Some(TrustDecision::Changed { previous }) => format!("WARNING: not the key paired as {:?}, which was {}", info.peer_name, &previous.to_hex()[..16]),
+        _ if peer.is_none() => "unverified".to_owned(),
+        _ if peer.is_some_and(|fp| paired(trust, &fp)) => "paired under another name".to_owned(),
+        _ => "not paired".to_owned(),
+    };
+    let changed = matches!(decision, Some(TrustDecision::Changed { .. }));
+    let files = info.files.iter().map(|f| OfferedFile { name: f.name.clone(), size: f.size, mime: guess_mime(&f.name).map(Into::into) }).collect();
+    let ruled = match (peer, decision) {
+        (Some(peer), Some(decision)) => policy.evaluate(&peer, decision, &TransferOffer { transfer, files }),
+        _ => Decision::Ask,
+    };
+    (status, changed, ruled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalsend_daemon::rpc::FileInfo;
+    use globalsend_transfer::policy::{AcceptRule, Action};
+
+    #[test]
+    fn only_a_proven_fingerprint_counts_as_paired() {
+        let laptop = DeviceIdentity::generate();
+        let mut trust = TrustStore::in_memory();
+        trust.pin("laptop", &laptop.fingerprint()).unwrap();
+        let policy = AcceptPolicy::new(vec![AcceptRule::new("anything from paired devices", Action::Accept)]);
+        let mut info = TransferInfo {
+            transfer: "00".repeat(16),
+            direction: "receive".into(),
+            peer: laptop.fingerprint().to_hex(),
+            peer_verified: true,
+            peer_name: "laptop".into(),
+            state: "offered".into(),
+            waiting: true,
+            files: vec![FileInfo { name: "notes.txt".into(), size: 10, bytes: 0, text: None, thumbnail: None }],
+            bytes: 0,
+            total: 10,
+        };
+        let (status, _, ruled) = judge(&trust, &policy, &info);
+        assert_eq!(status, "paired");
+        assert!(matches!(ruled, Decision::Accept { .. }));
+
+        // anyone can put the laptop's fingerprint and name in a hello
+        info.peer_verified = false;
+        assert_eq!(judge(&trust, &policy, &info), ("unverified".to_owned(), false, Decision::Ask));
+    }
+}
@@ -0,0 +1,116 @@
+//! `globalsend send`
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_crypto::pairing::{Role, WormholeCode};
+use globalsend_daemon::rpc::{transfer_id_hex, Target};
+use globalsend_daemon::{Daemon, DaemonConfig};
+use globalsend_proto::TransferId;
+use globalsend_transport::Connection;
+use tokio::time::sleep;
+
+use crate::config::resolve_relay;
+use crate::devices::{self, DeviceName};
+use crate::error::CliError;
+use crate::progress::Bars;
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// What `--to` names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum To {
+    Device(DeviceName),
+    /// A wormhole code the receiver typed, or none to make one up
+    Code(Option<WormholeCode>),
+}
+
+impl To {
+    /// Addresses and fingerprints first, then `code` and wormhole codes; anything else is an alias
+    pub fn parse(s: &str) -> Self {
+        match DeviceName::parse(s) {
+            DeviceName::Alias(_) if s == "code" => To::Code(None),
+            DeviceName::Alias(alias) => WormholeCode::parse(s).map_or(To::Device(DeviceName::Alias(alias)), |code| To::Code(Some(code))),
+            name => To::Device(name),
+        }
+    }
+}
+
+pub struct SendOptions {
+    pub files: Vec<PathBuf>,
+    pub to: To,
+    /// `host:port`, needed for codes
+    pub relay: Option<String>,
+    /// How long discovery gets to find the device
+    pub wait: Duration,
+}
+
+/// Send and show progress; true if the transfer completed
+pub async fn run(identity: Arc<DeviceIdentity>, mut config: DaemonConfig, options: SendOptions) -> Result<bool, CliError> {
+    // caught here rather than after the peer has been found
+    for path in &options.files {
+        let metadata = std::fs::metadata(path).map_err(|e| CliError::Failed(format!("{}: {e}", path.display())))?;
+        if !metadata.is_file() {
+            return Err(CliError::Failed(format!("{} is not a file", path.display())));
+        }
+    }
+    config.discovery = matches!(options.to, To::Device(DeviceName::Fingerprint(_) | DeviceName::Alias(_)));
+    let daemon = Daemon::start(identity.clone(), config).await?;
+    let transfer = match options.to {
+        To::Device(DeviceName::Addr(addr)) => daemon.send(Target::Addr(addr), options.files)?,
+        To::Device(name) => {
+            let device = devices::find(&name, options.wait, || daemon.devices()).await?;
+            let fingerprint = Fingerprint::from_hex(&device.fingerprint).expect("discovery reports hex fingerprints");
+            daemon.send(Target::Device(fingerprint), options.files)?
+        }
+        To::Code(code) => {
+            let relay = options.relay.ok_or_else(|| CliError::Failed("sending with a code needs --relay or a relay in the config".into()))?;
+            let relay = resolve_relay(&relay).await?;
+            let code = code.unwrap_or_else(WormholeCode::generate);
+            eprintln!("On the receiving device run:\n  globalsend receive --code {} --relay {relay}", code.as_str());
+            let conn = Connection::wormhole(relay, &code, Role::Initiator, identity.exchange()).await?;
+            daemon.send_over(conn, options.files)?
+        }
+    };
+    follow(&daemon, &transfer).await
+}
+
+/// Show `transfer` until it ends; Ctrl-C cancels it, a second one gives up waiting
+async fn follow(daemon: &Daemon, transfer: &TransferId) -> Result<bool, CliError> {
+    let id = transfer_id_hex(transfer);
+    let mut bars = Bars::new();
+    let mut interrupted = false;
+    loop {
+        if let Some(info) = daemon.transfers().into_iter().find(|t| t.transfer == id)
+            && bars.update(&info)
+        {
+            return Ok(info.state == "done");
+        }
+        tokio::select! {
+            _ = sleep(POLL) => {}
+            Ok(()) = tokio::signal::ctrl_c() => {
+                if interrupted {
+                    return Ok(false);
+                }
+                interrupted = true;
+                let _ = daemon.cancel(transfer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        assert_eq!(To::parse("code"), To::Code(None));
+        let code = WormholeCode::generate();
+        assert_eq!(To::parse(code.as_str()), To::Code(Some(code)));
+        assert_eq!(To::parse("[::1]:53317"), To::Device(DeviceName::Addr("[::1]:53317".parse().unwrap())));
+        assert_eq!(To::parse("kitchen-pc"), To::Device(DeviceName::Alias("kitchen-pc".into())));
+    }
+}
@@ -0,0 +1,43 @@
+//! Logs and metrics for people running a receiver unattended
+//!
+//! Logs go to stderr, filtered by `GLOBALSEND_LOG` (`tracing` directives
+//! such as `info` or `globalsend_transport=debug`). With the `prometheus`
+//! feature, `--metrics ADDR` serves every crate's metrics at
+//! `http://ADDR/metrics`. With the `debug-transcript` feature,
+//! `--transcript DIR` writes a redacted transcript of every session there
+//! to attach to an interop bug report.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::error::CliError;
+
+/// Log at `default` unless `GLOBALSEND_LOG` says otherwise
+pub fn init_logging(default: &str) {
+    let filter = EnvFilter::try_from_env("GLOBALSEND_LOG").unwrap_or_else(|_| default.into());
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
+}
+
+/// Serve Prometheus metrics on `addr`; needs a tokio runtime
+#[cfg(feature = "prometheus")]
+pub fn serve_metrics(addr: SocketAddr) -> Result<(), CliError> {
+    metrics_exporter_prometheus::PrometheusBuilder::new().with_http_listener(addr).install().map_err(|e| CliError::Failed(format!("cannot serve metrics on {addr}: {e}")))
+}
+
+#[cfg(not(feature = "prometheus"))]
+pub fn serve_metrics(_: SocketAddr) -> Result<(), CliError> {
+    Err(CliError::Failed("--metrics needs a build with the prometheus feature".into()))
+}
+
+/// Record a transcript of every session into `dir`
+#[cfg(feature = "debug-transcript")]
+pub fn record_transcripts(dir: PathBuf) -> Result<(), CliError> {
+    globalsend_transport::transcript::record_to(Some(dir.clone())).map_err(|e| CliError::Failed(format!("cannot write transcripts to {}: {e}", dir.display())))
+}
+
+#[cfg(not(feature = "debug-transcript"))]
+pub fn record_transcripts(_: PathBuf) -> Result<(), CliError> {
+    Err(CliError::Failed("--transcript needs a build with the debug-transcript feature".into()))
+}
@@ -0,0 +1,323 @@
+//! `globalsend tui`: devices, offers and progress in the terminal
+//!
+//! For machines with a person at an SSH session but no desktop. The screen
+//! runs its own [`Daemon`] like `receive` does and redraws from
+//! [`Daemon::devices`] and [`Daemon::transfers`] every tick; keys become
+//! [`Action`]s that go straight to the daemon.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_crypto::trust::TrustStore;
+use globalsend_daemon::rpc::{parse_transfer_id, DeviceInfo, Target, TransferInfo};
+use globalsend_daemon::{Daemon, DaemonConfig};
+use globalsend_proto::TransferId;
+use indicatif::HumanBytes;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::error::CliError;
+
+/// How often the screen redraws when no key is pressed
+const TICK: Duration = Duration::from_millis(200);
+const HELP: &str = "tab switch  ↑↓ select  s send  a accept  d decline  c cancel  q quit";
+
+/// What a key asks the daemon to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Accept(TransferId),
+    Decline(TransferId),
+    Cancel(TransferId),
+    Send { device: Fingerprint, path: PathBuf },
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Devices,
+    Transfers,
+}
+
+/// Everything on screen; knows nothing of the daemon
+struct App {
+    title: String,
+    /// Fingerprints pinned by `globalsend pair`, as hex
+    paired: BTreeSet<String>,
+    devices: Vec<DeviceInfo>,
+    /// Offers waiting for an answer first
+    transfers: Vec<TransferInfo>,
+    focus: Focus,
+    device: ListState,
+    transfer: TableState,
+    /// A path being typed, to send to the selected device
+    input: Option<String>,
+    status: String,
+}
+
+impl App {
+    fn new(title: String, trust: &TrustStore) -> Self {
+        Self {
+            title,
+            paired: trust.iter().map(|(_, peer)| peer.fingerprint.to_hex()).collect(),
+            devices: Vec::new(),
+            transfers: Vec::new(),
+            focus: Focus::Devices,
+            device: ListState::default(),
+            transfer: TableState::default(),
+            input: None,
+            status: String::new(),
+        }
+    }
+
+    fn refresh(&mut self, devices: Vec<DeviceInfo>, mut transfers: Vec<TransferInfo>) {
+        transfers.sort_by_key(|t| !t.waiting);
+        self.devices = devices;
+        self.transfers = transfers;
+        clamp(self.device.selected_mut(), self.devices.len());
+        clamp(self.transfer.selected_mut(), self.transfers.len());
+    }
+
+    fn selected_device(&self) -> Option<&DeviceInfo> {
+        self.device.selected().and_then(|i| self.devices.get(i))
+    }
+
+    fn selected_transfer(&self) -> Option<&TransferInfo> {
+        self.transfer.selected().and_then(|i| self.transfers.get(i))
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Action> {
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    let path = self.input.take().unwrap_or_default();
+                    let device = self.selected_device()?;
+                    let device = Fingerprint::from_hex(&device.fingerprint)?;
+                    return (!path.trim().is_empty()).then(|| Action::Send { device, path: path.trim().into() });
+                }
+                _ => {}
+            }
+            return None;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                self.focus = match self.focus {
+                    Focus::Devices => Focus::Transfers,
+                    Focus::Transfers => Focus::Devices,
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.step(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.step(1),
+            KeyCode::Char('s') | KeyCode::Enter if self.focus == Focus::Devices => {
+                if let Some(device) = self.selected_device() {
+                    self.status = format!("file to send to {}; enter sends, esc gives up", device.alias);
+                    self.input = Some(String::new());
+                }
+            }
+            KeyCode::Char(c @ ('a' | 'd' | 'c')) if self.focus == Focus::Transfers => {
+                let transfer = self.selected_transfer()?;
+                let id = parse_transfer_id(&transfer.transfer)?;
+                return match c {
+                    'a' if transfer.waiting => Some(Action::Accept(id)),
+                    'd' if transfer.waiting => Some(Action::Decline(id)),
+                    'c' if !transfer.finished() => Some(Action::Cancel(id)),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn step(&mut self, by: isize) {
+        let (selected, len) = match self.focus {
+            Focus::Devices => (self.device.selected_mut(), self.devices.len()),
+            Focus::Transfers => (self.transfer.selected_mut(), self.transfers.len()),
+        };
+        if len > 0 {
+            *selected = Some(selected.map_or(0, |i| i.saturating_add_signed(by).min(len - 1)));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, files, bottom] = Layout::vertical([Constraint::Min(6), Constraint::Length(8), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(top);
+        let block = |title: &str, focus: Option<Focus>| {
+            let block = Block::bordered().title(title.to_owned());
+            if focus == Some(self.focus) { block.border_style(Style::new().bold()) } else { block }
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let devices: Vec<ListItem> = self
+            .devices
+            .iter()
+            .map(|d| {
+                let paired = if self.paired.contains(&d.fingerprint) { "  paired" } else { "" };
+                ListItem::new(format!("{}{paired}", d.alias))
+            })
+            .collect();
+        let devices = List::new(devices).block(block(&format!("Devices seen by {}", self.title), Some(Focus::Devices))).highlight_style(highlight);
+        frame.render_stateful_widget(devices, left, &mut self.device);
+
+        let rows = self.transfers.iter().map(|t| {
+            let towards = if t.direction == "send" { "to" } else { "from" };
+            let state = if t.waiting { "waiting: a accepts, d declines".to_owned() } else { t.state.clone() };
+            Row::new([format!("{towards} {}", t.peer_name), format!("{:>3}%", percent(t.bytes, t.total)), HumanBytes(t.total).to_string(), state])
+        });
+        let widths = [Constraint::Percentage(30), Constraint::Length(4), Constraint::Length(10), Constraint::Fill(1)];
+        let transfers = Table::new(rows, widths).block(block("Transfers", Some(Focus::Transfers))).row_highlight_style(highlight);
+        frame.render_stateful_widget(transfers, right, &mut self.transfer);
+
+        let rows: Vec<Row> = self.selected_transfer().map_or_else(Vec::new, |t| {
+            t.files.iter().map(|f| Row::new([f.name.clone(), bar(percent(f.bytes, f.size), 20), format!("{}/{}", HumanBytes(f.bytes), HumanBytes(f.size))])).collect()
+        });
+        let widths = [Constraint::Fill(1), Constraint::Length(22), Constraint::Length(24)];
+        frame.render_widget(Table::new(rows, widths).block(block("Files", None)), files);
+
+        let line = match (&self.input, self.status.is_empty()) {
+            (Some(input), _) => Line::from(format!("{}: {input}", self.status)),
+            (None, false) => Line::from(self.status.as_str()),
+            (None, true) => Line::from(HELP).dim(),
+        };
+        frame.render_widget(Paragraph::new(line), bottom);
+    }
+}
+
+/// Keep a selection inside a list of `len`, and select the first item once there is one
+fn clamp(selected: &mut Option<usize>, len: usize) {
+    *selected = match len {
+        0 => None,
+        _ => Some(selected.unwrap_or(0).min(len - 1)),
+    };
+}
+
+fn percent(bytes: u64, total: u64) -> u64 {
+    match total {
+        0 => 100,
+        _ => bytes.min(total) * 100 / total,
+    }
+}
+
+/// `[=====     ]`
+fn bar(percent: u64, width: usize) -> String {
+    let filled = width * percent as usize / 100;
+    format!("[{}{}]", "=".repeat(filled), " ".repeat(width - filled))
+}
+
+/// Run the screen until `q`; transfers still running are cancelled on the way out
+pub async fn run(identity: Arc<DeviceIdentity>, config: DaemonConfig, trust: &TrustStore) -> Result<bool, CliError> {
+    let title = config.alias.clone();
+    let daemon = Daemon::start(identity, config).await?;
+    tokio::spawn({
+        let daemon = daemon.clone();
+        async move { daemon.listen().await }
+    });
+    let mut app = App::new(title, trust);
+    tokio::task::block_in_place(|| {
+        let mut terminal = ratatui::init();
+        let result = ui(&mut terminal, &daemon, &mut app);
+        ratatui::restore();
+        result
+    })?;
+    let running: Vec<_> = daemon.transfers().into_iter().filter(|t| !t.finished()).collect();
+    for transfer in &running {
+        let _ = daemon.cancel(&parse_transfer_id(&transfer.transfer).expect("daemon reports hex ids"));
+    }
+    if !running.is_empty() {
+        // long enough for the cancels to reach the peers
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    Ok(true)
+}
+
+fn ui(terminal: &mut DefaultTerminal, daemon: &Daemon, app: &mut App) -> io::Result<()> {
+    loop {
+        app.refresh(daemon.devices(), daemon.transfers());
+        terminal.draw(|frame| app.draw(frame))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let outcome = match app.key(key) {
+            None => continue,
+            Some(Action::Quit) => return Ok(()),
+            Some(Action::Accept(transfer)) => daemon.accept(&transfer, None),
+            Some(Action::Decline(transfer)) => daemon.decline(&transfer),
+            Some(Action::Cancel(transfer)) => daemon.cancel(&transfer),
+            Some(Action::Send { device, path }) => daemon.send(Target::Device(device), vec![path]).map(|_| ()),
+        };
+        app.status = outcome.err().map(|e| e.message).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use globalsend_daemon::rpc::FileInfo;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+
+    fn press(app: &mut App, code: KeyCode) -> Option<Action> {
+        app.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn keys_become_actions_and_draw() {
+        let mut trust = TrustStore::in_memory();
+        let fingerprint = Fingerprint::from_bytes([7; 32]);
+        trust.pin("laptop", &fingerprint).unwrap();
+        let mut app = App::new("server".into(), &trust);
+        let device = DeviceInfo { fingerprint: fingerprint.to_hex(), alias: "laptop".into(), version: 7, addrs: vec![] };
+        let offer = TransferInfo {
+            transfer: "00".repeat(16),
+            direction: "receive".into(),
+            peer: fingerprint.to_hex(),
+            peer_verified: true,
+            peer_name: "laptop".into(),
+            state: "offered".into(),
+            waiting: true,
+            files: vec![FileInfo { name: "notes.txt".into(), size: 10, bytes: 0, text: None, thumbnail: None }],
+            bytes: 0,
+            total: 10,
+        };
+        app.refresh(vec![device], vec![offer]);
+
+        press(&mut app, KeyCode::Char('s'));
+        for c in "a.txt".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        assert_eq!(press(&mut app, KeyCode::Enter), Some(Action::Send { device: fingerprint, path: "a.txt".into() }));
+        // accept only means something in the transfers pane
+        assert_eq!(press(&mut app, KeyCode::Char('a')), None);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(press(&mut app, KeyCode::Char('a')), Some(Action::Accept(TransferId([0; 16]))));
+        assert_eq!(press(&mut app, KeyCode::Char('q')), Some(Action::Quit));
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        for text in ["laptop  paired", "from laptop", "waiting", "notes.txt"] {
+            assert!(screen.contains(text), "{text} missing");
+        }
+    }
+}
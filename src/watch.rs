@@ -0,0 +1,47 @@
+//! `globalsend watch`
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use globalsend_crypto::identity::{DeviceIdentity, Fingerprint};
+use globalsend_daemon::rpc::Target;
+use globalsend_daemon::{Daemon, DaemonConfig};
+use indicatif::HumanBytes;
+use tokio::time::sleep;
+
+use crate::devices::{self, DeviceName};
+use crate::error::CliError;
+
+const POLL: Duration = Duration::from_millis(250);
+
+/// Send `path` to `to` whenever it changes, until Ctrl-C
+pub async fn run(identity: Arc<DeviceIdentity>, mut config: DaemonConfig, path: PathBuf, to: DeviceName, wait: Duration) -> Result<bool, CliError> {
+    if !path.exists() {
+        return Err(CliError::Failed(format!("{} does not exist", path.display())));
+    }
+    config.discovery = !matches!(to, DeviceName::Addr(_));
+    let daemon = Daemon::start(identity, config).await?;
+    let (target, label) = match to {
+        DeviceName::Addr(addr) => (Target::Addr(addr), addr.to_string()),
+        name => {
+            let device = devices::find(&name, wait, || daemon.devices()).await?;
+            (Target::Device(Fingerprint::from_hex(&device.fingerprint).expect("discovery reports hex fingerprints")), device.alias)
+        }
+    };
+    let _watch = daemon.watch(target, path.clone()).map_err(|e| CliError::Failed(format!("cannot watch {}: {e}", path.display())))?;
+    eprintln!("Watching {}; changes go to {label}. Ctrl-C stops.", path.display());
+    let mut reported = BTreeSet::new();
+    loop {
+        for info in daemon.transfers().into_iter().filter(|t| t.finished()) {
+            if reported.insert(info.transfer.clone()) {
+                eprintln!("{} file(s), {}: {}", info.files.len(), HumanBytes(info.total), info.state);
+            }
+        }
+        tokio::select! {
+            _ = sleep(POLL) => {}
+            Ok(()) = tokio::signal::ctrl_c() => return Ok(true),
+        }
+    }
+}